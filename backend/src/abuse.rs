@@ -0,0 +1,437 @@
+//! Scrape protection for a public deployment.
+//!
+//! Apart from `rate_limit`'s bucket, which smooths bursts from every client,
+//! anonymous requests (those presenting no key from `auth.keys`) are counted
+//! per address over a sliding `abuse.window_secs`. Past
+//! `abuse.challenge_after` of them, responses carry `x-challenge: captcha` and
+//! the `x-challenge-url` to solve one at, so a front end can put a CAPTCHA in
+//! front of a heavy anonymous user; past `abuse.max_requests` they get 429
+//! until the window slides on.
+//!
+//! Addresses and ranges (`203.0.113.0/24`) on the blocklist get 403 whatever
+//! key they present, whether or not `abuse.enabled` is set; ranges are how a
+//! hosting provider's or a region's addresses are kept out. The blocklist is
+//! kept at `abuse.blocklist_path` and managed at `/api/admin/blocklist`.
+//! Health, readiness and metrics probes are never throttled or blocked.
+
+use axum::extract::{ConnectInfo, Query, Request, State};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::auth::{presents_known_key, Caller, SCOPE_ADMIN};
+use crate::config::AbuseConfig;
+use crate::rate_limit::{client_ip, EXEMPT_PATHS};
+use crate::state::AppState;
+
+/// Windows kept before idle ones are dropped.
+const MAX_CLIENTS: usize = 10_000;
+
+/// An address, or a range of them in CIDR notation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Net {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Net {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix)).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix)).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Net {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let invalid = || format!("{:?} is not an address or CIDR range", s);
+        let (addr, prefix) = s.trim().split_once('/').map_or((s.trim(), None), |(a, p)| (a, Some(p)));
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let longest = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().ok().filter(|p| *p <= longest).ok_or_else(invalid)?,
+            None => longest,
+        };
+        Ok(Net { addr, prefix })
+    }
+}
+
+impl TryFrom<String> for Net {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        s.parse()
+    }
+}
+
+impl From<Net> for String {
+    fn from(net: Net) -> String {
+        net.to_string()
+    }
+}
+
+impl fmt::Display for Net {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let longest = if self.addr.is_ipv4() { 32 } else { 128 };
+        match self.prefix {
+            prefix if prefix == longest => write!(f, "{}", self.addr),
+            prefix => write!(f, "{}/{}", self.addr, prefix),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    pub net: Net,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub added_at: DateTime<Utc>,
+    /// Lifted after this; blocked for good without one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl Block {
+    fn active(&self, now: DateTime<Utc>) -> bool {
+        self.until.is_none_or(|until| now < until)
+    }
+}
+
+pub struct Blocklist {
+    path: Option<PathBuf>,
+    blocks: RwLock<Vec<Block>>,
+}
+
+impl Blocklist {
+    /// Loads the blocklist from `path`. `None` keeps it in memory only.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let blocks = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Blocklist { path, blocks: RwLock::new(blocks) }
+    }
+
+    fn save(&self, blocks: &[Block]) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let contents = serde_json::to_string_pretty(blocks).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| e.to_string())
+    }
+
+    /// Adds `block`, replacing any block of the same range.
+    pub fn add(&self, block: Block) -> Result<(), String> {
+        let mut blocks = self.blocks.write().unwrap();
+        blocks.retain(|existing| existing.net != block.net);
+        blocks.push(block);
+        self.save(&blocks)
+    }
+
+    /// Lifts the block of `net`. Returns whether there was one.
+    pub fn remove(&self, net: Net) -> Result<bool, String> {
+        let mut blocks = self.blocks.write().unwrap();
+        let before = blocks.len();
+        blocks.retain(|block| block.net != net);
+        if blocks.len() == before {
+            return Ok(false);
+        }
+        self.save(&blocks).map(|()| true)
+    }
+
+    /// The block `ip` falls under at `now`, if any.
+    pub fn blocked(&self, ip: IpAddr, now: DateTime<Utc>) -> Option<Block> {
+        self.blocks.read().unwrap().iter().find(|block| block.active(now) && block.net.contains(ip)).cloned()
+    }
+
+    /// Blocks in force at `now`.
+    pub fn active(&self, now: DateTime<Utc>) -> Vec<Block> {
+        self.blocks.read().unwrap().iter().filter(|block| block.active(now)).cloned().collect()
+    }
+}
+
+/// Requests counted in the current fixed window and the one before it; the
+/// sliding count weighs the previous window by how much of it still overlaps.
+struct Window {
+    started: Instant,
+    current: u32,
+    previous: u32,
+}
+
+impl Window {
+    fn slide(&mut self, now: Instant, length: Duration) {
+        let elapsed = now.saturating_duration_since(self.started);
+        if elapsed >= 2 * length {
+            (self.started, self.current, self.previous) = (now, 0, 0);
+        } else if elapsed >= length {
+            (self.started, self.previous, self.current) = (self.started + length, self.current, 0);
+        }
+    }
+
+    fn count(&self, now: Instant, length: Duration) -> f64 {
+        let into = now.saturating_duration_since(self.started).as_secs_f64() / length.as_secs_f64();
+        f64::from(self.previous) * (1.0 - into).max(0.0) + f64::from(self.current)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// Allowed, with a CAPTCHA asked for.
+    Challenge,
+    /// Refused; retry after this long.
+    Throttle(Duration),
+}
+
+pub struct AbuseGuard {
+    config: RwLock<AbuseConfig>,
+    windows: Mutex<HashMap<IpAddr, Window>>,
+    pub blocklist: Blocklist,
+}
+
+impl AbuseGuard {
+    pub fn new(config: &AbuseConfig) -> Self {
+        AbuseGuard {
+            config: RwLock::new(config.clone()),
+            windows: Mutex::default(),
+            blocklist: Blocklist::open(config.blocklist_path.clone()),
+        }
+    }
+
+    /// Applies new limits; counts so far are kept. The blocklist stays where
+    /// it was opened.
+    pub fn reconfigure(&self, config: &AbuseConfig) {
+        *self.config.write().unwrap() = config.clone();
+    }
+
+    /// Counts an anonymous request from `client` at `now`.
+    pub fn check(&self, client: IpAddr, now: Instant) -> Verdict {
+        let config = self.config.read().unwrap().clone();
+        let length = Duration::from_secs(config.window_secs.max(1));
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() >= MAX_CLIENTS && !windows.contains_key(&client) {
+            windows.retain(|_, window| {
+                window.slide(now, length);
+                window.count(now, length) > 0.0
+            });
+        }
+        let window = windows.entry(client).or_insert(Window { started: now, current: 0, previous: 0 });
+        window.slide(now, length);
+        let count = window.count(now, length) + 1.0;
+        if count > f64::from(config.max_requests) {
+            return Verdict::Throttle(length.saturating_sub(now.saturating_duration_since(window.started)));
+        }
+        window.current += 1;
+        match config.challenge_after {
+            0 => Verdict::Allow,
+            after if count > f64::from(after) => Verdict::Challenge,
+            _ => Verdict::Allow,
+        }
+    }
+}
+
+pub async fn guard(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if EXEMPT_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|info| info.0);
+    let Some(client) = client_ip(request.headers(), peer, state.config.rate_limit.trust_forwarded_for) else {
+        return next.run(request).await;
+    };
+    if state.abuse.blocklist.blocked(client, state.clock.now()).is_some() {
+        return (StatusCode::FORBIDDEN, "Blocked").into_response();
+    }
+    let config = &state.config.abuse;
+    // Keys are only checked by handlers that take a `Caller`, so an unknown one
+    // counts as anonymous here
+    if !config.enabled || presents_known_key(&state.config.auth, request.headers()) {
+        return next.run(request).await;
+    }
+    match state.abuse.check(client, Instant::now()) {
+        Verdict::Allow => next.run(request).await,
+        Verdict::Challenge => {
+            let mut response = next.run(request).await;
+            let headers = response.headers_mut();
+            headers.insert("x-challenge", HeaderValue::from_static("captcha"));
+            if let Some(value) = config.challenge_url.as_deref().and_then(|url| HeaderValue::from_str(url).ok()) {
+                headers.insert("x-challenge-url", value);
+            }
+            response
+        }
+        Verdict::Throttle(wait) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, wait.as_secs().max(1).to_string())],
+            "Too many anonymous requests; use an API key or slow down",
+        )
+            .into_response(),
+    }
+}
+
+pub async fn list(State(state): State<AppState>, caller: Caller) -> Result<Json<Vec<Block>>, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    Ok(Json(state.abuse.blocklist.active(state.clock.now())))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BlockRequest {
+    net: String,
+    reason: Option<String>,
+    until: Option<DateTime<Utc>>,
+}
+
+pub async fn add(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(request): Json<BlockRequest>,
+) -> Result<(StatusCode, Json<Block>), (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    let net: Net = request.net.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let block = Block { net, reason: request.reason, added_at: state.clock.now(), until: request.until };
+    let outcome = state.abuse.blocklist.add(block.clone());
+    state.audit.record(&caller, "blocklist.add", &block, &outcome);
+    outcome.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok((StatusCode::CREATED, Json(block)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnblockParams {
+    net: String,
+}
+
+pub async fn remove(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(params): Query<UnblockParams>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    let net: Net = params.net.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let outcome = state.abuse.blocklist.remove(net);
+    state.audit.record(&caller, "blocklist.remove", json!({ "net": net }), &outcome);
+    match outcome {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err((StatusCode::NOT_FOUND, format!("{} is not blocked", net))),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::test_utils::{stores_in, temp_dir};
+    use axum::routing::get;
+    use axum::{middleware, Router};
+
+    #[test]
+    fn test_anonymous_requests_are_challenged_then_throttled() {
+        let guard = AbuseGuard::new(&AbuseConfig {
+            enabled: true,
+            window_secs: 60,
+            max_requests: 4,
+            challenge_after: 2,
+            challenge_url: None,
+            blocklist_path: None,
+        });
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        let start = Instant::now();
+
+        assert_eq!(guard.check(client, start), Verdict::Allow);
+        assert_eq!(guard.check(client, start), Verdict::Allow);
+        assert_eq!(guard.check(client, start), Verdict::Challenge);
+        assert_eq!(guard.check(client, start), Verdict::Challenge);
+        assert_eq!(guard.check(client, start + Duration::from_secs(20)), Verdict::Throttle(Duration::from_secs(40)));
+        assert_eq!(guard.check("203.0.113.8".parse().unwrap(), start), Verdict::Allow);
+        // Halfway through the next window half of the last one still counts
+        assert_eq!(guard.check(client, start + Duration::from_secs(90)), Verdict::Challenge);
+        assert_eq!(guard.check(client, start + Duration::from_secs(200)), Verdict::Allow);
+    }
+
+    #[test]
+    fn test_blocklist_matches_ranges_and_persists() {
+        let range: Net = "198.51.100.0/24".parse().unwrap();
+        assert!(range.contains("198.51.100.200".parse().unwrap()));
+        assert!(!range.contains("198.51.101.1".parse().unwrap()));
+        assert!("2001:db8::/32".parse::<Net>().unwrap().contains("2001:db8::1".parse().unwrap()));
+        assert!("198.51.100.0/33".parse::<Net>().is_err());
+        assert_eq!("198.51.100.7".parse::<Net>().unwrap().to_string(), "198.51.100.7");
+
+        let path = temp_dir("blocklist").join("blocklist.json");
+        let now = Utc::now();
+        let blocklist = Blocklist::open(Some(path.clone()));
+        blocklist.add(Block { net: range, reason: None, added_at: now, until: None }).unwrap();
+        let single: Net = "203.0.113.7".parse().unwrap();
+        let until = Some(now + chrono::Duration::hours(1));
+        blocklist.add(Block { net: single, reason: Some("scraper".to_string()), added_at: now, until }).unwrap();
+
+        let reopened = Blocklist::open(Some(path));
+        assert!(reopened.blocked("198.51.100.9".parse().unwrap(), now).is_some());
+        assert!(reopened.blocked("203.0.113.7".parse().unwrap(), now).is_some());
+        assert!(reopened.blocked("203.0.113.7".parse().unwrap(), now + chrono::Duration::hours(2)).is_none());
+        assert!(reopened.remove(range).unwrap());
+        assert!(!reopened.remove(range).unwrap());
+        assert_eq!(reopened.active(now).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_keys_are_throttled_like_anonymous_requests() {
+        let config = Config::from_toml(
+            r#"
+            [abuse]
+            enabled = true
+            max_requests = 1
+            challenge_after = 0
+
+            [[auth.keys]]
+            name = "partner"
+            key = "s3cret"
+            "#,
+        )
+        .unwrap();
+        let state = AppState::new(stores_in(config, &temp_dir("abuse-keys")));
+        // Like the stats and area routes, this handler never looks at the key
+        let app = Router::new()
+            .route("/api/areas", get(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(state, guard));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api/areas", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+        let status = |key: &'static str| {
+            let request = client.get(&url).header("x-api-key", key);
+            async move { request.send().await.unwrap().status().as_u16() }
+        };
+        assert_eq!(status("x").await, 200);
+        assert_eq!(status("x").await, 429);
+        assert_eq!(status("s3cret").await, 200);
+    }
+}
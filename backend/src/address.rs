@@ -0,0 +1,382 @@
+//! Canonical address form.
+//!
+//! Sources write the same address many ways ("Apt 4, 12 Dame St., Dublin 02",
+//! "12 Dame Street, Apartment 4, Dublin 2, D02 X285"). `normalize` lowercases,
+//! drops punctuation, Eircodes and the country, expands common abbreviations
+//! and puts the unit before the street, giving one comma-separated form that
+//! grouping, location filters and duplicate detection can compare directly.
+//!
+//! Place names that appear in Irish ("Baile Átha Cliath 6", "Contae na
+//! Gaillimhe") are mapped to their English form, and fadas are dropped, so an
+//! address or a location filter written in either language normalizes alike.
+
+use std::sync::OnceLock;
+
+/// Abbreviation and its expansion. "St" is handled separately because it can
+/// also mean Saint.
+const ABBREVIATIONS: &[(&str, &str)] = &[
+    ("apt", "apartment"),
+    ("apts", "apartments"),
+    ("ave", "avenue"),
+    ("av", "avenue"),
+    ("blvd", "boulevard"),
+    ("cl", "close"),
+    ("co", "county"),
+    ("contae", "county"),
+    ("cres", "crescent"),
+    ("ct", "court"),
+    ("dr", "drive"),
+    ("est", "estate"),
+    ("gdns", "gardens"),
+    ("gr", "grove"),
+    ("grn", "green"),
+    ("hts", "heights"),
+    ("ln", "lane"),
+    ("lwr", "lower"),
+    ("nth", "north"),
+    ("pde", "parade"),
+    ("pk", "park"),
+    ("pl", "place"),
+    ("rd", "road"),
+    ("sq", "square"),
+    ("sth", "south"),
+    ("tce", "terrace"),
+    ("upr", "upper"),
+];
+
+/// Irish names of counties and larger towns, including the genitive forms
+/// used after "Contae", with their English names.
+const IRISH_PLACE_NAMES: &[(&str, &str)] = &[
+    ("Baile Átha Cliath", "dublin"),
+    ("Bhaile Átha Cliath", "dublin"),
+    ("Corcaigh", "cork"),
+    ("Chorcaí", "cork"),
+    ("Gaillimh", "galway"),
+    ("na Gaillimhe", "galway"),
+    ("Luimneach", "limerick"),
+    ("Luimnigh", "limerick"),
+    ("Port Láirge", "waterford"),
+    ("Phort Láirge", "waterford"),
+    ("Cill Dara", "kildare"),
+    ("Chill Dara", "kildare"),
+    ("Cill Chainnigh", "kilkenny"),
+    ("Chill Chainnigh", "kilkenny"),
+    ("Cill Mhantáin", "wicklow"),
+    ("Chill Mhantáin", "wicklow"),
+    ("Ceatharlach", "carlow"),
+    ("Cheatharlach", "carlow"),
+    ("An Cabhán", "cavan"),
+    ("an Chabháin", "cavan"),
+    ("An Clár", "clare"),
+    ("an Chláir", "clare"),
+    ("Dún na nGall", "donegal"),
+    ("Dhún na nGall", "donegal"),
+    ("Ciarraí", "kerry"),
+    ("Chiarraí", "kerry"),
+    ("Laoise", "laois"),
+    ("Liatroim", "leitrim"),
+    ("Liatroma", "leitrim"),
+    ("An Longfort", "longford"),
+    ("an Longfoirt", "longford"),
+    ("Lú", "louth"),
+    ("Maigh Eo", "mayo"),
+    ("Mhaigh Eo", "mayo"),
+    ("An Mhí", "meath"),
+    ("na Mí", "meath"),
+    ("Muineachán", "monaghan"),
+    ("Mhuineacháin", "monaghan"),
+    ("Uíbh Fhailí", "offaly"),
+    ("Ros Comáin", "roscommon"),
+    ("Sligeach", "sligo"),
+    ("Shligigh", "sligo"),
+    ("Tiobraid Árann", "tipperary"),
+    ("Thiobraid Árann", "tipperary"),
+    ("An Iarmhí", "westmeath"),
+    ("na hIarmhí", "westmeath"),
+    ("Loch Garman", "wexford"),
+    ("Baile Átha Luain", "athlone"),
+    ("Droichead Átha", "drogheda"),
+    ("Dún Dealgan", "dundalk"),
+    ("Leitir Ceanainn", "letterkenny"),
+    ("Trá Lí", "tralee"),
+    ("Cill Airne", "killarney"),
+    ("An Nás", "naas"),
+    ("Má Nuad", "maynooth"),
+    ("Sord", "swords"),
+    ("Bré", "bray"),
+    ("Port Laoise", "portlaoise"),
+    ("An Muileann gCearr", "mullingar"),
+    ("Tulach Mhór", "tullamore"),
+    ("Caisleán an Bharraigh", "castlebar"),
+    ("Cluain Meala", "clonmel"),
+];
+
+/// Irish name of a county or town, as `(nominative, genitive)`: "Corcaigh"
+/// on its own, "Contae Chorcaí" after a noun. Places with one entry use the
+/// same form for both.
+pub fn irish_names(english: &str) -> Option<(&'static str, &'static str)> {
+    let mut forms = IRISH_PLACE_NAMES.iter().filter(|(_, name)| *name == english).map(|(irish, _)| *irish);
+    let nominative = forms.next()?;
+    Some((nominative, forms.next_back().unwrap_or(nominative)))
+}
+
+/// Words that open a component naming a unit within a building.
+const UNIT_WORDS: &[&str] = &["apartment", "flat", "unit", "suite", "penthouse"];
+
+const COUNTRY: &[&str] = &["ireland", "eire", "republic of ireland", "roi"];
+
+const COUNTIES: &[&str] = &[
+    "carlow", "cavan", "clare", "cork", "donegal", "dublin", "galway", "kerry", "kildare", "kilkenny", "laois",
+    "leitrim", "limerick", "longford", "louth", "mayo", "meath", "monaghan", "offaly", "roscommon", "sligo",
+    "tipperary", "waterford", "westmeath", "wexford", "wicklow",
+];
+
+/// Eircode routing key: a letter and two digits, or D6W.
+fn is_routing_key(token: &str) -> bool {
+    let bytes = token.as_bytes();
+    token == "d6w"
+        || (bytes.len() == 3 && bytes[0].is_ascii_lowercase() && bytes[1..].iter().all(u8::is_ascii_digit))
+}
+
+/// Eircode unique identifier: four letters and digits with at least one of each.
+fn is_unique_id(token: &str) -> bool {
+    token.len() == 4
+        && token.chars().all(|c| c.is_ascii_alphanumeric())
+        && token.chars().any(|c| c.is_ascii_digit())
+        && token.chars().any(|c| c.is_ascii_alphabetic())
+}
+
+fn strip_eircodes(tokens: Vec<String>) -> Vec<String> {
+    let mut kept = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = &tokens[i];
+        if is_routing_key(token) && tokens.get(i + 1).is_some_and(|next| is_unique_id(next)) {
+            i += 2;
+            continue;
+        }
+        if token.len() == 7 && is_routing_key(&token[..3]) && is_unique_id(&token[3..]) {
+            i += 1;
+            continue;
+        }
+        kept.push(token.clone());
+        i += 1;
+    }
+    kept
+}
+
+fn expand(tokens: &mut [String]) {
+    for i in 0..tokens.len() {
+        let token = tokens[i].as_str();
+        let expanded = if token == "st" {
+            // "St Stephen's Green" but "Dame St"
+            if i + 1 < tokens.len() { "saint" } else { "street" }
+        } else if let Some((_, full)) = ABBREVIATIONS.iter().find(|(short, _)| *short == token) {
+            full
+        } else if token.chars().all(|c| c.is_ascii_digit()) && token.len() > 1 {
+            // "Dublin 02" is "Dublin 2"
+            let trimmed = token.trim_start_matches('0');
+            tokens[i] = if trimmed.is_empty() { "0".to_string() } else { trimmed.to_string() };
+            continue;
+        } else {
+            continue;
+        };
+        tokens[i] = expanded.to_string();
+    }
+}
+
+/// Drops accents, so "Dún" and "Dun" compare equal.
+fn fold(c: char) -> char {
+    match c {
+        'á' | 'à' | 'â' | 'ä' => 'a',
+        'é' | 'è' | 'ê' | 'ë' => 'e',
+        'í' | 'ì' | 'î' | 'ï' => 'i',
+        'ó' | 'ò' | 'ô' | 'ö' => 'o',
+        'ú' | 'ù' | 'û' | 'ü' => 'u',
+        _ => c,
+    }
+}
+
+fn tokens(text: &str) -> Vec<String> {
+    let cleaned: String = text
+        .to_lowercase()
+        .chars()
+        .filter(|c| *c != '\'' && *c != '’')
+        .map(|c| if c.is_alphanumeric() { fold(c) } else { ' ' })
+        .collect();
+    cleaned.split_whitespace().map(str::to_string).collect()
+}
+
+/// `IRISH_PLACE_NAMES` tokenized, longest first so "Baile Átha Luain" isn't
+/// read as something shorter.
+fn irish_place_names() -> &'static [(Vec<String>, &'static str)] {
+    static NAMES: OnceLock<Vec<(Vec<String>, &'static str)>> = OnceLock::new();
+    NAMES.get_or_init(|| {
+        let mut names: Vec<_> = IRISH_PLACE_NAMES.iter().map(|(irish, english)| (tokens(irish), *english)).collect();
+        names.sort_by_key(|(irish, _)| std::cmp::Reverse(irish.len()));
+        names
+    })
+}
+
+fn translate_place_names(tokens: Vec<String>) -> Vec<String> {
+    let mut translated = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+    'tokens: while i < tokens.len() {
+        for (irish, english) in irish_place_names() {
+            if tokens[i..].starts_with(irish) {
+                translated.push(english.to_string());
+                i += irish.len();
+                continue 'tokens;
+            }
+        }
+        translated.push(tokens[i].clone());
+        i += 1;
+    }
+    translated
+}
+
+fn normalize_component(component: &str) -> String {
+    let mut tokens = translate_place_names(strip_eircodes(tokens(component)));
+    expand(&mut tokens);
+    tokens.join(" ")
+}
+
+/// The canonical form of `raw`; empty when nothing of the address is left.
+pub fn normalize(raw: &str) -> String {
+    let mut components: Vec<String> = Vec::new();
+    for component in raw.split([',', ';', '\n']).map(normalize_component) {
+        if component.is_empty() || COUNTRY.contains(&component.as_str()) || components.contains(&component) {
+            continue;
+        }
+        // "12, Dame Street": a house number on its own belongs to the street
+        if let Some(number) = components.last().filter(|last| last.chars().all(|c| c.is_ascii_digit())) {
+            let merged = format!("{} {}", number, component);
+            *components.last_mut().unwrap() = merged;
+            continue;
+        }
+        components.push(component);
+    }
+
+    // Unit first, then the street and the rest in their original order
+    let (units, rest): (Vec<String>, Vec<String>) = components
+        .into_iter()
+        .partition(|component| component.split(' ').next().is_some_and(|word| UNIT_WORDS.contains(&word)));
+    units.into_iter().chain(rest).collect::<Vec<_>>().join(", ")
+}
+
+/// The last component of a normalized address, e.g. "dublin 6".
+pub fn area(normalized: &str) -> &str {
+    normalized.rsplit(", ").next().unwrap_or_default()
+}
+
+/// County named by a component ("dublin 6", "county cork", "galway city").
+fn component_county(component: &str) -> Option<&'static str> {
+    let name = component.strip_prefix("county ").unwrap_or(component);
+    let first = name.split(' ').next()?;
+    COUNTIES.iter().find(|county| **county == first).copied()
+}
+
+/// The county a normalized address is in, looking from the end.
+pub fn county(normalized: &str) -> Option<&'static str> {
+    normalized.rsplit(", ").find_map(component_county)
+}
+
+/// Whether a normalized address is in `location`, which may be any of its
+/// components ("ranelagh", "dublin 6") or its county ("dublin", "Co. Dublin").
+pub fn in_location(normalized: &str, location: &str) -> bool {
+    let wanted = normalize(location);
+    if wanted.is_empty() {
+        return true;
+    }
+    if normalized.split(", ").any(|component| component == wanted) {
+        return true;
+    }
+    let wanted_county = wanted.strip_prefix("county ").unwrap_or(&wanted);
+    COUNTIES.contains(&wanted_county) && county(normalized) == Some(wanted_county)
+}
+
+/// The components of a normalized address that place it within its area:
+/// no unit, no house number, and neither the area nor the county.
+fn locality(normalized: &str) -> Vec<&str> {
+    let area = area(normalized);
+    normalized
+        .split(", ")
+        .filter(|component| *component != area && component_county(component).is_none())
+        .filter(|component| !component.split(' ').next().is_some_and(|word| UNIT_WORDS.contains(&word)))
+        .map(|component| match component.split_once(' ') {
+            Some((number, rest)) if number.starts_with(|c: char| c.is_ascii_digit()) => rest,
+            _ => component,
+        })
+        .collect()
+}
+
+/// Whether two normalized addresses name different places, rather than
+/// different units of one or the same place written differently: they are
+/// in different counties, or have no street or locality in common. Addresses
+/// that give only an area can't be told apart this way.
+pub fn materially_different(a: &str, b: &str) -> bool {
+    if let (Some(a), Some(b)) = (county(a), county(b)) {
+        if a != b {
+            return true;
+        }
+    }
+    let (a, b) = (locality(a), locality(b));
+    !a.is_empty() && !b.is_empty() && !a.iter().any(|component| b.contains(component))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variants_normalize_alike() {
+        let expected = "apartment 4, 12 dame street, dublin 2";
+        assert_eq!(normalize("Apt 4, 12 Dame St., Dublin 02"), expected);
+        assert_eq!(normalize("12 Dame Street, Apartment 4, Dublin 2, D02 X285, Ireland"), expected);
+        assert_eq!(normalize("Apt. 4,  12, Dame St, Dublin 2, D02X285"), expected);
+        assert_eq!(normalize("  St Stephen's Green, Dublin 2 "), "saint stephens green, dublin 2");
+        assert_eq!(normalize("5 Oak Pk, Douglas, Co. Cork"), "5 oak park, douglas, county cork");
+        assert_eq!(normalize(" , Ireland"), "");
+    }
+
+    #[test]
+    fn test_irish_place_names() {
+        assert_eq!(normalize("Sráid an Teampaill, Baile Átha Cliath 8"), "sraid an teampaill, dublin 8");
+        assert_eq!(normalize("Bóthar na Trá, Contae na Gaillimhe"), "bothar na tra, county galway");
+        assert_eq!(county(&normalize("An Príomhshráid, Baile Átha Luain, An Iarmhí")), Some("westmeath"));
+        assert_eq!(county(&normalize("1 Sráid Mhór, Co. Chorcaí")), Some("cork"));
+
+        let english = normalize("12 Main Street, Salthill, Galway");
+        let irish = normalize("12 Main Street, Salthill, Gaillimh");
+        assert_eq!(english, irish);
+        assert!(in_location(&english, "Gaillimh"));
+        assert!(in_location(&normalize("4 Marine Road, Dún Laoghaire, Co. Dublin"), "Dun Laoghaire"));
+        assert!(in_location(&normalize("Cearnóg Mhuirfean, Baile Átha Cliath 2"), "Co. Dublin"));
+    }
+
+    #[test]
+    fn test_area_county_and_location() {
+        let address = normalize("Flat 2, 7 Castle Ave, Ranelagh, Dublin 6");
+        assert_eq!(area(&address), "dublin 6");
+        assert_eq!(county(&address), Some("dublin"));
+        assert_eq!(county(&normalize("Salthill, Galway City")), Some("galway"));
+        assert_eq!(county(&normalize("Main Street, Naas, Co Kildare")), Some("kildare"));
+        assert_eq!(county(&normalize("Main Street")), None);
+
+        assert!(in_location(&address, "Ranelagh"));
+        assert!(in_location(&address, "dublin 06"));
+        assert!(in_location(&address, "Co. Dublin"));
+        assert!(!in_location(&address, "Cork"));
+        assert!(!in_location(&address, "Dublin 4"));
+    }
+
+    #[test]
+    fn test_materially_different() {
+        let different = |a: &str, b: &str| materially_different(&normalize(a), &normalize(b));
+        assert!(!different("Apt 4, 12 Dame St, Dublin 2", "Apartment 9, Dame Street, Dublin 2"));
+        assert!(!different("The Grange, 3 Oak Road, Ranelagh, Dublin 6", "Oak Road, Dublin 6"));
+        assert!(!different("12 Dame Street, Dublin 2", "Dublin 2"));
+        assert!(different("7 Castle Ave, Ranelagh, Dublin 6", "40 Elm Park, Rathmines, Dublin 6"));
+        assert!(different("Main Street, Naas, Co Kildare", "Main Street, Dublin 2"));
+    }
+}
@@ -0,0 +1,225 @@
+//! Letting agents checked against the PSRA register.
+//!
+//! Anyone letting property on someone else's behalf needs a licence from the
+//! Property Services Regulatory Authority, and scams often pose as an agent
+//! that has none, or borrow a real agent's licence number. With
+//! `agent_register.path` pointing at a CSV export of the PSRA's public
+//! register, each listing with an agent gets an `agent_status` when parsed:
+//!
+//! - `licensed`: the licence number it quotes (the optional `agent_licence`
+//!   column) is on the register under the agent's name, or it quotes none
+//!   and the name is on the register;
+//! - `mismatched`: the licence number is on the register under another name;
+//! - `unlicensed`: the licence number is not on the register;
+//! - `unmatched`: it quotes no licence number and the name is not on the
+//!   register.
+//!
+//! The register's licence number is its first column named like "Licence
+//! No", and every column named like "Name" (licensee, trading name) gives a
+//! name the licence goes by. Names match ignoring case, punctuation and
+//! company suffixes ("Ltd", "T/A"), and licence numbers ignoring everything
+//! but their digits. Listings with no agent, or parsed without a register,
+//! have no status. `agent_flagged` filters searches on any status but
+//! `licensed`.
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::{Agent, StandardizedProperty};
+
+/// Words left out when comparing names.
+const IGNORED_WORDS: [&str; 8] = ["ltd", "limited", "dac", "plc", "teo", "t", "a", "the"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentStatus {
+    Licensed,
+    Mismatched,
+    Unlicensed,
+    Unmatched,
+}
+
+impl AgentStatus {
+    pub fn flagged(self) -> bool {
+        self != AgentStatus::Licensed
+    }
+}
+
+fn name_key(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && !IGNORED_WORDS.contains(word))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// "PSRA Licence No. 001234" -> "1234"
+fn licence_key(licence: &str) -> String {
+    let digits: String = licence.chars().filter(char::is_ascii_digit).collect();
+    digits.trim_start_matches('0').to_string()
+}
+
+/// Fields of one CSV line; quoted fields may contain commas and `""`.
+fn fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Register {
+    /// Names each licence goes by, keyed like `name_key`, by `licence_key`.
+    licences: HashMap<String, HashSet<String>>,
+    names: HashSet<String>,
+}
+
+impl Register {
+    pub fn parse(csv: &str) -> Result<Register, String> {
+        let mut lines = csv.lines().filter(|line| !line.trim().is_empty());
+        let header: Vec<String> = fields(lines.next().ok_or("The agent register is empty")?)
+            .iter()
+            .map(|column| column.trim().to_lowercase())
+            .collect();
+        let licence = header
+            .iter()
+            .position(|column| column.contains("licen"))
+            .ok_or("The agent register has no licence number column")?;
+        let names: Vec<usize> = (0..header.len()).filter(|i| header[*i].contains("name")).collect();
+        if names.is_empty() {
+            return Err("The agent register has no name column".to_string());
+        }
+
+        let mut register = Register::default();
+        for line in lines {
+            let row = fields(line);
+            let number = licence_key(row.get(licence).map_or("", String::as_str));
+            let names: HashSet<String> = names
+                .iter()
+                .filter_map(|i| row.get(*i))
+                .map(|name| name_key(name))
+                .filter(|key| !key.is_empty())
+                .collect();
+            register.names.extend(names.iter().cloned());
+            if !number.is_empty() {
+                register.licences.entry(number).or_default().extend(names);
+            }
+        }
+        Ok(register)
+    }
+
+    pub fn load(path: &Path) -> Result<Register, String> {
+        let csv = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        Register::parse(&csv)
+    }
+
+    pub fn licences(&self) -> usize {
+        self.licences.len()
+    }
+
+    /// How `agent` stands on the register; `None` when it gives neither a
+    /// name nor a licence number.
+    pub fn check(&self, agent: &Agent) -> Option<AgentStatus> {
+        let (name, licence) = (name_key(&agent.name), licence_key(&agent.licence));
+        let status = match (self.licences.get(&licence), licence.is_empty()) {
+            (Some(names), _) if name.is_empty() || names.contains(&name) => AgentStatus::Licensed,
+            (Some(_), _) => AgentStatus::Mismatched,
+            (None, false) => AgentStatus::Unlicensed,
+            (None, true) if name.is_empty() => return None,
+            (None, true) if self.names.contains(&name) => AgentStatus::Licensed,
+            (None, true) => AgentStatus::Unmatched,
+        };
+        Some(status)
+    }
+}
+
+/// The register at `path`, read when the config is loaded. A register that
+/// can't be read is logged, and listings are then parsed without a status,
+/// as they are without a path.
+pub fn open(path: Option<&Path>) -> Option<Register> {
+    let path = path?;
+    match Register::load(path) {
+        Ok(register) => {
+            info!("Loaded {} licences from the agent register {}", register.licences(), path.display());
+            Some(register)
+        }
+        Err(e) => {
+            warn!("Listings won't be checked against the agent register: {}", e);
+            None
+        }
+    }
+}
+
+/// The status of a freshly parsed listing's agent under `register`.
+pub fn status(register: Option<&Register>, property: &StandardizedProperty) -> Option<AgentStatus> {
+    register?.check(property.agent.as_ref()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::test_utils::temp_dir;
+
+    const REGISTER: &str = "\
+Licence No,Licensee Name,Trading Name,County
+001234,\"Example Lettings, Ltd\",Example Lettings,Dublin
+005678,Jane Murphy,\"Murphy \"\"Homes\"\" T/A\",Cork
+";
+
+    fn agent(name: &str, licence: &str) -> Agent {
+        Agent {
+            name: name.to_string(),
+            phone: String::new(),
+            email: String::new(),
+            address: String::new(),
+            licence: licence.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_agents_are_checked_against_the_register() {
+        let register = Register::parse(REGISTER).unwrap();
+        assert_eq!(register.licences(), 2);
+        let check = |name, licence| register.check(&agent(name, licence));
+
+        assert_eq!(check("Example Lettings", "PSRA 001234"), Some(AgentStatus::Licensed));
+        assert_eq!(check("EXAMPLE LETTINGS LIMITED", ""), Some(AgentStatus::Licensed));
+        assert_eq!(check("Murphy Homes", "5678"), Some(AgentStatus::Licensed));
+        assert_eq!(check("Quick Rentals", "001234"), Some(AgentStatus::Mismatched));
+        assert_eq!(check("Example Lettings", "999999"), Some(AgentStatus::Unlicensed));
+        assert_eq!(check("Quick Rentals", ""), Some(AgentStatus::Unmatched));
+        assert_eq!(check("", ""), None);
+
+        assert!(Register::parse("Name,County\nJane,Cork").is_err());
+    }
+
+    #[test]
+    fn test_register_is_read_with_the_config() {
+        let path = temp_dir("agent-register").join("register.csv");
+        fs::write(&path, REGISTER).unwrap();
+        let config = Config::from_toml(&format!("[agent_register]\npath = {:?}", path)).unwrap();
+        let register = config.parsing.agent_register.as_ref().unwrap();
+        assert_eq!(register.licences(), 2);
+        assert!(config.sources.iter().all(|source| source.parsing.agent_register.as_ref() == Some(register)));
+
+        let mut property = crate::test_utils::listing("daft", "1");
+        property.agent = Some(agent("Quick Rentals", ""));
+        assert_eq!(status(Some(register), &property), Some(AgentStatus::Unmatched));
+        assert_eq!(status(None, &property), None);
+    }
+}
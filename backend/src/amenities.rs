@@ -0,0 +1,146 @@
+//! Parking and outdoor space.
+//!
+//! Both move rents, but sources mostly mention them in free text ("2 parking
+//! spaces", "south-facing rear garden") if at all. Each listing gets an
+//! `amenities` object read from the optional `parking` (a count), `features`
+//! (a list of phrases) and `description` columns (see `mapping`). A field is
+//! `null` when nothing says either way; "no parking" and "no garden" give 0
+//! and `false`.
+//!
+//! Searches filter on them with `min_parking`, `garden` and `balcony`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::mapping::BatchRow;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Amenities {
+    pub parking_spaces: Option<u32>,
+    pub garden: Option<bool>,
+    pub balcony: Option<bool>,
+}
+
+/// Words that mean a garden or a balcony. "Terrace" alone is a kind of house,
+/// so only roof and private terraces count as balconies.
+const GARDEN: [&str; 5] = ["garden", "gardens", "patio", "yard", "back yard"];
+const BALCONY: [&str; 4] = ["balcony", "balconies", "roof terrace", "private terrace"];
+const NEGATIONS: [&str; 3] = ["no", "without", "not"];
+const COUNTS: [(&str, u32); 5] = [("one", 1), ("two", 2), ("three", 3), ("four", 4), ("double", 2)];
+
+/// Lowercase words of `text`, one sentence or feature per entry so a negation
+/// doesn't reach into the next one.
+fn clauses(text: &str) -> Vec<Vec<String>> {
+    text.to_lowercase()
+        .split(['.', ',', ';', '\n', '•', '|'])
+        .map(|clause| {
+            clause
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|word| !word.is_empty())
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+        .filter(|words| !words.is_empty())
+        .collect()
+}
+
+/// Position of `phrase` in `words`.
+fn find(words: &[String], phrase: &str) -> Option<usize> {
+    let phrase: Vec<&str> = phrase.split(' ').collect();
+    words.windows(phrase.len()).position(|window| window.iter().zip(&phrase).all(|(w, p)| w == p))
+}
+
+fn negated(words: &[String], at: usize) -> bool {
+    words[..at].iter().rev().take(3).any(|word| NEGATIONS.contains(&word.as_str()))
+}
+
+/// `Some(true)` when a clause mentions one of `phrases`, `Some(false)` when
+/// the only mentions are negated.
+fn mentions(clauses: &[Vec<String>], phrases: &[&str]) -> Option<bool> {
+    let mut found = None;
+    for words in clauses {
+        for phrase in phrases {
+            if let Some(at) = find(words, phrase) {
+                if !negated(words, at) {
+                    return Some(true);
+                }
+                found = Some(false);
+            }
+        }
+    }
+    found
+}
+
+fn count(word: &str) -> Option<u32> {
+    word.parse().ok().filter(|n| *n < 20).or_else(|| COUNTS.iter().find(|(w, _)| *w == word).map(|(_, n)| *n))
+}
+
+/// Parking spaces a clause gives: "2 parking spaces", "parking for 2 cars",
+/// "designated parking" (1) or "no parking" (0).
+fn parking_in(words: &[String]) -> Option<u32> {
+    let at = words.iter().position(|word| word == "parking" || word == "garage" || word == "carpark")?;
+    if negated(words, at) {
+        return Some(0);
+    }
+    // "on-street parking" isn't a space of the listing's own
+    if at >= 2 && words[at - 2] == "on" && words[at - 1] == "street" {
+        return None;
+    }
+    let before = at.checked_sub(1).and_then(|i| count(&words[i]));
+    let after = words[at + 1..].iter().take(3).find_map(|word| count(word));
+    Some(before.or(after).unwrap_or(1))
+}
+
+/// Reads the amenities out of free text such as a description or a list of
+/// features.
+pub fn from_text(text: &str) -> Amenities {
+    let clauses = clauses(text);
+    Amenities {
+        parking_spaces: clauses.iter().filter_map(|words| parking_in(words)).max(),
+        garden: mentions(&clauses, &GARDEN),
+        balcony: mentions(&clauses, &BALCONY),
+    }
+}
+
+/// A listing's feature list and description as one text, a feature per line.
+pub fn listing_text(row: &BatchRow) -> String {
+    let mut text = row.strings("features").join("\n");
+    if let Some(description) = row.string("description") {
+        text.push('\n');
+        text.push_str(&description);
+    }
+    text
+}
+
+pub fn extract(row: &BatchRow) -> Amenities {
+    let mut amenities = from_text(&listing_text(row));
+    if let Some(spaces) = row.long("parking").and_then(|n| u32::try_from(n).ok()) {
+        amenities.parking_spaces = Some(spaces);
+    }
+    amenities
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn amenities(parking_spaces: Option<u32>, garden: Option<bool>, balcony: Option<bool>) -> Amenities {
+        Amenities { parking_spaces, garden, balcony }
+    }
+
+    #[test]
+    fn test_from_text() {
+        let cases = [
+            ("2 parking spaces. South-facing rear garden.", amenities(Some(2), Some(true), None)),
+            ("Designated parking space\nBalcony", amenities(Some(1), None, Some(true))),
+            ("Parking for two cars", amenities(Some(2), None, None)),
+            ("Double garage, private terrace", amenities(Some(2), None, Some(true))),
+            ("No parking, no garden. Lovely roof terrace", amenities(Some(0), Some(false), Some(true))),
+            ("On-street parking only", Amenities::default()),
+            ("Mid-terrace house close to the park", Amenities::default()),
+            ("", Amenities::default()),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(from_text(text), expected, "{:?}", text);
+        }
+    }
+}
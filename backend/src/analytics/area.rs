@@ -0,0 +1,292 @@
+//! Neighbourhood profiles.
+//!
+//! `GET /api/areas/{area}` gathers what an area page shows into one response.
+//! It covers the area's latest month, built as the market report builds it (see
+//! `reports::market`):
+//! - the median rent overall and by bedrooms;
+//! - the monthly trend and how far it moved;
+//! - the mix of property types and sizes;
+//! - the typical BER band;
+//! - how many listings have parking, a garden or a balcony, among those whose
+//!   source says either way (see `amenities`);
+//! - the agents letting the most there.
+//!
+//! `area` is any location the search filter accepts, percent-encoded
+//! ("Dublin%206", "Co.%20Cork"). Agents whose data was suppressed (see
+//! `privacy`) are left out.
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::hedonic::{ber_band, median, Granularity};
+use super::sample;
+use crate::amenities::Amenities;
+use crate::auth::Caller;
+use crate::config::Config;
+use crate::locale::Lang;
+use crate::privacy::AgentPrivacy;
+use crate::reports::market::{self, BedroomMedian, ReportFilter, Share, Supply, TrendPoint};
+use crate::state::AppState;
+use crate::store::PropertyStore;
+use crate::StandardizedProperty;
+
+const TOP_AGENTS: usize = 5;
+
+#[derive(Debug, Serialize)]
+pub struct Trend {
+    /// Oldest first, ending with the profile's month.
+    pub points: Vec<TrendPoint>,
+    /// Relative change in median rent from the previous month.
+    pub monthly_change: Option<f64>,
+    /// Relative change from the first month of `points` with a median rent to
+    /// the last.
+    pub change: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BerProfile {
+    /// Median band of the rated listings; null below `stats.min_sample` of
+    /// them.
+    pub typical: Option<String>,
+    /// "A" to "G", "exempt" or "unknown".
+    pub by_band: Vec<Share>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Coverage {
+    /// Listings whose source says whether they have it.
+    pub known: usize,
+    /// Those of `known` that have it.
+    pub with: usize,
+    pub share: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AmenityCoverage {
+    pub parking: Coverage,
+    pub garden: Coverage,
+    pub balcony: Coverage,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AgentListings {
+    pub name: String,
+    pub listings: usize,
+    /// Share of the area's listings.
+    pub share: f64,
+    pub median_rent: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AreaProfile {
+    pub lang: Lang,
+    /// Normalized location.
+    pub area: String,
+    /// `area` for display, in the response language.
+    pub area_label: String,
+    /// The latest month with listings, "2024-11".
+    pub period: String,
+    pub listings: usize,
+    /// Null below `stats.min_sample` listings, as are the other medians here.
+    pub median_rent: Option<f64>,
+    pub by_bedrooms: Vec<BedroomMedian>,
+    pub trend: Trend,
+    pub supply: Supply,
+    pub ber: BerProfile,
+    /// Null when no listing says anything about parking or outdoor space.
+    pub amenities: Option<AmenityCoverage>,
+    /// Largest first.
+    pub top_agents: Vec<AgentListings>,
+}
+
+impl AreaProfile {
+    pub fn localize(&mut self, lang: Lang) {
+        self.lang = lang;
+        self.area_label = lang.area(&self.area);
+    }
+}
+
+fn typical_band(listings: &[&StandardizedProperty]) -> Option<String> {
+    let mut rated: Vec<String> = listings.iter().map(|p| ber_band(p)).filter(|band| band.len() == 1).collect();
+    rated.sort();
+    (!rated.is_empty() && rated.len() >= sample::min_sample()).then(|| rated.swap_remove((rated.len() - 1) / 2))
+}
+
+fn coverage(listings: &[&StandardizedProperty], has: impl Fn(&Amenities) -> Option<bool>) -> Coverage {
+    let known: Vec<bool> = listings.iter().filter_map(|p| has(&p.amenities)).collect();
+    let with = known.iter().filter(|has| **has).count();
+    Coverage { known: known.len(), with, share: (!known.is_empty()).then(|| with as f64 / known.len() as f64) }
+}
+
+fn amenity_coverage(listings: &[&StandardizedProperty]) -> Option<AmenityCoverage> {
+    let amenities = AmenityCoverage {
+        parking: coverage(listings, |a| a.parking_spaces.map(|spaces| spaces > 0)),
+        garden: coverage(listings, |a| a.garden),
+        balcony: coverage(listings, |a| a.balcony),
+    };
+    let known = amenities.parking.known + amenities.garden.known + amenities.balcony.known;
+    (known > 0).then_some(amenities)
+}
+
+fn top_agents(listings: &[&StandardizedProperty]) -> Vec<AgentListings> {
+    let mut agents: BTreeMap<&str, Vec<f64>> = BTreeMap::new();
+    for property in listings {
+        if let Some(name) = property.agent.as_ref().map(|agent| agent.name.trim()).filter(|name| !name.is_empty()) {
+            agents.entry(name).or_default().push(property.price.amount);
+        }
+    }
+    let mut top: Vec<AgentListings> = agents
+        .into_iter()
+        .map(|(name, rents)| {
+            let n = rents.len();
+            AgentListings {
+                name: name.to_string(),
+                listings: n,
+                share: n as f64 / listings.len() as f64,
+                median_rent: sample::suppress(median(rents), n),
+            }
+        })
+        .collect();
+    top.sort_by(|a, b| b.listings.cmp(&a.listings).then_with(|| a.name.cmp(&b.name)));
+    top.truncate(TOP_AGENTS);
+    top
+}
+
+/// Builds the profile of `period` from monthly `(period, listing)`
+/// observations of the area, oldest snapshot first. `None` when the period
+/// has no listings.
+pub fn build(
+    filter: &ReportFilter,
+    period: &str,
+    observations: Vec<(String, StandardizedProperty)>,
+) -> Option<AreaProfile> {
+    let latest: Vec<(String, StandardizedProperty)> =
+        observations.iter().filter(|(label, _)| label == period).cloned().collect();
+    let latest = market::by_period(latest).remove(period)?;
+    let current: Vec<&StandardizedProperty> = latest.values().collect();
+    let report = market::build(filter, period, Granularity::Month, observations)?;
+
+    let medians: Vec<f64> = report.trend.iter().filter_map(|point| point.median_rent).collect();
+    let change = match medians.as_slice() {
+        [first, .., last] => Some(last / first - 1.0),
+        _ => None,
+    };
+    let area = report.area;
+    Some(AreaProfile {
+        lang: Lang::default(),
+        area_label: Lang::default().area(&area),
+        area,
+        period: report.period,
+        listings: report.headline.listings,
+        median_rent: report.headline.median_rent,
+        by_bedrooms: report.headline.by_bedrooms,
+        trend: Trend { points: report.trend, monthly_change: report.headline.change, change },
+        supply: report.supply,
+        ber: BerProfile { typical: typical_band(&current), by_band: market::shares(&current, ber_band) },
+        amenities: amenity_coverage(&current),
+        top_agents: top_agents(&current),
+    })
+}
+
+/// The profile of the area's latest month, as `caller` may see it. Blocking.
+pub fn profile(
+    config: &Config,
+    store: &dyn PropertyStore,
+    privacy: &AgentPrivacy,
+    caller: &Caller,
+    filter: &ReportFilter,
+) -> Option<AreaProfile> {
+    let mut observations = market::load(config, store, filter, Granularity::Month);
+    let period = observations.iter().map(|(label, _)| label).max()?.clone();
+    for (_, property) in observations.iter_mut().filter(|(label, _)| *label == period) {
+        privacy.redact(property, caller);
+    }
+    build(filter, &period, observations)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AreaParams {
+    source: Option<String>,
+}
+
+pub async fn area_profile(
+    State(state): State<AppState>,
+    caller: Caller,
+    lang: Lang,
+    Path(area): Path<String>,
+    Query(params): Query<AreaParams>,
+) -> Result<Json<AreaProfile>, (StatusCode, String)> {
+    let filter = ReportFilter { area: Some(area.clone()), source: params.source, ..Default::default() };
+    if filter.normalized_area().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, format!("Invalid area {:?}", area)));
+    }
+    let profile = tokio::task::spawn_blocking(move || {
+        profile(&state.config, state.store.as_ref(), &state.privacy, &caller, &filter)
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let mut profile = profile.ok_or_else(|| (StatusCode::NOT_FOUND, format!("No listings in {}", area)))?;
+    profile.localize(lang);
+    Ok(Json(profile))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::listing;
+    use crate::{address, amenities};
+
+    fn rental(id: usize, rent: f64, ber: Option<&str>, agent: &str) -> StandardizedProperty {
+        let mut property = listing("daft", &id.to_string());
+        property.address.normalized_address = address::normalize(&format!("{} Main Street, Dublin 6", id));
+        property.property_type = "Apartment".to_string();
+        property.bedrooms = Some(2);
+        property.price.amount = rent;
+        (property.ber_rating, property.ber_status) = crate::ber::normalize(ber);
+        property.agent = Some(crate::Agent {
+            name: agent.to_string(),
+            phone: String::new(),
+            email: String::new(),
+            address: String::new(),
+            licence: String::new(),
+        });
+        property
+    }
+
+    #[test]
+    fn test_build_profile() {
+        let mut observations: Vec<(String, StandardizedProperty)> =
+            (0..4).map(|i| ("2024-10".to_string(), rental(100 + i, 2000.0, None, "Example Lettings"))).collect();
+        let latest = [
+            rental(1, 2000.0, Some("B2"), "Example Lettings"),
+            rental(2, 2100.0, Some("B3"), "Example Lettings"),
+            rental(3, 2200.0, Some("C1"), "Other Agents"),
+            rental(4, 2300.0, Some("A2"), "Example Lettings"),
+            rental(5, 2400.0, None, "Other Agents"),
+            rental(6, 2500.0, Some("Exempt"), ""),
+        ];
+        observations.extend(latest.into_iter().map(|p| ("2024-11".to_string(), p)));
+        observations[4].1.amenities = amenities::from_text("2 parking spaces, balcony");
+
+        let filter = ReportFilter { area: Some("Dublin 6".to_string()), ..Default::default() };
+        let profile = build(&filter, "2024-11", observations).unwrap();
+        assert_eq!((profile.area.as_str(), profile.area_label.as_str()), ("dublin 6", "Dublin 6"));
+        assert_eq!((profile.listings, profile.median_rent), (6, Some(2250.0)));
+        assert_eq!(profile.trend.points.len(), 2);
+        assert_eq!((profile.trend.monthly_change, profile.trend.change), (Some(0.125), Some(0.125)));
+        assert_eq!(profile.ber.typical.as_deref(), Some("B"));
+
+        let amenities = profile.amenities.unwrap();
+        assert_eq!((amenities.parking.known, amenities.parking.with, amenities.garden.known), (1, 1, 0));
+        assert_eq!(amenities.garden.share, None);
+
+        let agents: Vec<(&str, usize)> = profile.top_agents.iter().map(|a| (a.name.as_str(), a.listings)).collect();
+        assert_eq!(agents, [("Example Lettings", 3), ("Other Agents", 2)]);
+        assert_eq!(profile.top_agents[0].median_rent, Some(2100.0));
+
+        assert!(build(&filter, "2024-12", vec![]).is_none());
+    }
+}
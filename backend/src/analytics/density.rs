@@ -0,0 +1,273 @@
+//! Per-area listing density anomalies.
+//!
+//! Compares how many listings each area has in a source's latest snapshot with
+//! the median of the snapshots before it. A sharp drop usually means a scraper
+//! or parser stopped picking up part of a site rather than the market emptying
+//! overnight, so flags are surfaced in the stats endpoints and sent through the
+//! notifier for someone to look at.
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::NaiveDate;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::address;
+use crate::config::{Config, DensityConfig};
+use crate::locale::Lang;
+use crate::notifier::{self, Notification};
+use crate::state::AppState;
+use crate::store::PropertyStore;
+use crate::StandardizedProperty;
+
+/// Job kind that runs a check and notifies about new anomalies.
+pub const JOB: &str = "density.check";
+
+/// Listing counts per area for each snapshot date of one source.
+pub type AreaCounts = BTreeMap<NaiveDate, HashMap<String, usize>>;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DensityAnomaly {
+    pub source: String,
+    pub area: String,
+    /// `area` for display, in the response language.
+    pub area_label: String,
+    /// Date of the snapshot that deviates.
+    pub date: NaiveDate,
+    pub listings: usize,
+    /// Median listing count over the baseline snapshots.
+    pub baseline: f64,
+    /// Relative change from the baseline; -0.6 is a 60% drop.
+    pub change: f64,
+}
+
+impl DensityAnomaly {
+    pub fn localize(&mut self, lang: Lang) {
+        self.area_label = lang.area(&self.area);
+    }
+
+    fn key(&self) -> String {
+        format!("{}/{}/{}", self.source, self.date, self.area)
+    }
+}
+
+pub fn area_counts<'a>(properties: impl IntoIterator<Item = &'a StandardizedProperty>) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for property in properties {
+        let area = address::area(&property.address.normalized_address);
+        if !area.is_empty() {
+            *counts.entry(area.to_string()).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Flags areas of the latest snapshot in `counts` whose listing count moved at
+/// least `config.threshold` from the median of the `config.baseline_days`
+/// snapshots before it. An area missing from a snapshot counts as zero there.
+/// Largest deviations first.
+pub fn detect(source: &str, counts: &AreaCounts, config: &DensityConfig) -> Vec<DensityAnomaly> {
+    let Some((&date, latest)) = counts.last_key_value() else {
+        return vec![];
+    };
+    let baseline: Vec<&HashMap<String, usize>> =
+        counts.values().rev().skip(1).take(config.baseline_days).collect();
+    if baseline.is_empty() {
+        return vec![];
+    }
+
+    let areas: BTreeSet<&String> = baseline.iter().flat_map(|day| day.keys()).chain(latest.keys()).collect();
+    let mut anomalies: Vec<DensityAnomaly> = areas
+        .into_iter()
+        .filter_map(|area| {
+            let expected = median(baseline.iter().map(|day| *day.get(area).unwrap_or(&0) as f64).collect());
+            if expected < config.min_baseline {
+                return None;
+            }
+            let listings = *latest.get(area).unwrap_or(&0);
+            let change = listings as f64 / expected - 1.0;
+            (change.abs() >= config.threshold).then(|| DensityAnomaly {
+                source: source.to_string(),
+                area: area.clone(),
+                area_label: Lang::default().area(area),
+                date,
+                listings,
+                baseline: expected,
+                change,
+            })
+        })
+        .collect();
+    anomalies.sort_by(|a, b| b.change.abs().total_cmp(&a.change.abs()).then_with(|| a.area.cmp(&b.area)));
+    anomalies
+}
+
+/// Area counts for the latest `baseline_days + 1` snapshots of a source.
+fn load_counts(source: &str, config: &Config, store: &dyn PropertyStore) -> AreaCounts {
+    let snapshots = store.snapshots(source);
+    let skip = snapshots.len().saturating_sub(config.density.baseline_days + 1);
+    let area = |p: &StandardizedProperty| {
+        Some(address::area(&p.address.normalized_address).to_string()).filter(|area| !area.is_empty())
+    };
+    snapshots
+        .into_iter()
+        .skip(skip)
+        .map(|date| {
+            let groups = store.aggregate(source, date, &area);
+            (date, groups.into_iter().map(|(area, group)| (area, group.listings)).collect())
+        })
+        .collect()
+}
+
+/// Runs the check for the requested source, or every source. Blocking.
+pub fn check(config: &Config, store: &dyn PropertyStore, source: Option<&str>) -> Vec<DensityAnomaly> {
+    config
+        .source_names(source)
+        .into_iter()
+        .flat_map(|source| detect(source, &load_counts(source, config, store), &config.density))
+        .collect()
+}
+
+/// Anomalies already notified, so a snapshot that stays anomalous until the
+/// next drop isn't reported on every check.
+#[derive(Debug, Default)]
+pub struct DensityAlerts {
+    notified: Mutex<HashSet<String>>,
+}
+
+impl DensityAlerts {
+    fn first_seen(&self, anomaly: &DensityAnomaly) -> bool {
+        self.notified.lock().unwrap().insert(anomaly.key())
+    }
+}
+
+pub fn run_job(state: &AppState, _payload: &serde_json::Value) -> Result<(), String> {
+    let anomalies = check(&state.config, state.store.as_ref(), None);
+    info!("Density check found {} anomalies", anomalies.len());
+    for anomaly in anomalies.into_iter().filter(|a| state.density.first_seen(a)) {
+        let text = format!(
+            "{} listings in {} on {}: {} against a baseline of {:.0} ({:+.0}%)",
+            anomaly.source,
+            anomaly.area_label,
+            anomaly.date,
+            anomaly.listings,
+            anomaly.baseline,
+            anomaly.change * 100.0
+        );
+        let details = serde_json::to_value(&anomaly).map_err(|e| e.to_string())?;
+        notifier::notify(state, Notification::new("density.anomaly", text, details, state.clock.now()));
+    }
+    Ok(())
+}
+
+/// Queues a check every `density.check_interval_mins` for the life of the
+/// process.
+pub fn schedule(state: &AppState) {
+    if !state.config.density.enabled {
+        return;
+    }
+    let state = state.clone();
+    let period = Duration::from_secs(state.config.density.check_interval_mins.max(1) * 60);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            if let Err(e) = state.jobs.enqueue_once(JOB, ()) {
+                error!("Could not queue density check: {}", e);
+            }
+        }
+    });
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DensityParams {
+    source: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DensityResponse {
+    pub lang: Lang,
+    pub baseline_days: usize,
+    pub threshold: f64,
+    pub anomalies: Vec<DensityAnomaly>,
+}
+
+pub async fn anomalies(
+    State(state): State<AppState>,
+    lang: Lang,
+    Query(params): Query<DensityParams>,
+) -> Result<Json<DensityResponse>, (StatusCode, String)> {
+    let config = &state.config;
+    let mut anomalies = if config.density.enabled {
+        let reading = state.clone();
+        tokio::task::spawn_blocking(move || check(&reading.config, reading.store.as_ref(), params.source.as_deref()))
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    } else {
+        vec![]
+    };
+    for anomaly in &mut anomalies {
+        anomaly.localize(lang);
+    }
+    Ok(Json(DensityResponse {
+        lang,
+        baseline_days: config.density.baseline_days,
+        threshold: config.density.threshold,
+        anomalies,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(n: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 3, n).unwrap()
+    }
+
+    fn counts(areas: &[(&str, usize)]) -> HashMap<String, usize> {
+        areas.iter().map(|(area, n)| (area.to_string(), *n)).collect()
+    }
+
+    #[test]
+    fn test_detect_flags_sharp_moves_only() {
+        let config = DensityConfig::default();
+        let mut history = AreaCounts::new();
+        for n in 1..=7 {
+            history.insert(day(n), counts(&[("dublin 6", 100 + n as usize), ("cork", 40), ("sligo", 5)]));
+        }
+        history.insert(day(8), counts(&[("dublin 6", 80), ("sligo", 0), ("galway", 30)]));
+
+        let anomalies = detect("daft", &history, &config);
+        // Cork vanished; Dublin 6 dipped under the threshold; Sligo and the new
+        // Galway area have too small a baseline to judge
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].area, "cork");
+        assert_eq!((anomalies[0].listings, anomalies[0].baseline, anomalies[0].change), (0, 40.0, -1.0));
+        assert_eq!(anomalies[0].date, day(8));
+    }
+
+    #[test]
+    fn test_detect_needs_history() {
+        let mut history = AreaCounts::new();
+        history.insert(day(1), counts(&[("cork", 40)]));
+        assert!(detect("daft", &history, &DensityConfig::default()).is_empty());
+
+        history.insert(day(2), counts(&[("cork", 90)]));
+        let anomalies = detect("daft", &history, &DensityConfig::default());
+        assert_eq!(anomalies[0].change, 1.25);
+    }
+}
@@ -0,0 +1,189 @@
+//! Deposit-to-rent ratios per area.
+//!
+//! For the listings in each source's latest snapshot that state a deposit
+//! (see `deposit`), the deposit is divided by the monthly rent. Areas report
+//! the median and mean ratio, and listings asking more than
+//! `LEGAL_CAP_MONTHS` of rent are listed as flagged, most demanding first.
+//! With `from`/`to` every listing seen in the window's snapshots counts once,
+//! as last seen.
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::{sample, Window};
+use crate::address;
+use crate::config::Config;
+use crate::locale::Lang;
+use crate::state::AppState;
+use crate::store::PropertyStore;
+use crate::{validate_price, StandardizedProperty};
+
+/// The most a landlord may ask as a deposit, in months of rent.
+pub const LEGAL_CAP_MONTHS: f64 = 2.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AreaDeposits {
+    pub area: String,
+    /// `area` for display, in the response language.
+    pub area_label: String,
+    /// Listings with both a deposit and a valid rent.
+    pub listings: usize,
+    /// Deposits in months of rent; null below `stats.min_sample` listings.
+    pub median_ratio: Option<f64>,
+    pub mean_ratio: Option<f64>,
+    pub over_cap: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FlaggedListing {
+    pub property_id: String,
+    pub area: String,
+    pub deposit: f64,
+    pub rent: f64,
+    pub ratio: f64,
+}
+
+#[derive(Debug, Default)]
+pub struct DepositStats {
+    pub areas: Vec<AreaDeposits>,
+    pub flagged: Vec<FlaggedListing>,
+}
+
+fn median(sorted: &[f64]) -> f64 {
+    let mid = sorted.len() / 2;
+    match sorted.len() % 2 {
+        0 => (sorted[mid - 1] + sorted[mid]) / 2.0,
+        _ => sorted[mid],
+    }
+}
+
+fn tally(properties: &[StandardizedProperty]) -> DepositStats {
+    let mut ratios: HashMap<String, Vec<f64>> = HashMap::new();
+    let mut flagged = Vec::new();
+    for property in properties {
+        let (Some(deposit), rent) = (property.deposit, property.price.amount) else {
+            continue;
+        };
+        let area = address::area(&property.address.normalized_address);
+        if area.is_empty() || !validate_price(property) {
+            continue;
+        }
+        let ratio = deposit / rent;
+        ratios.entry(area.to_string()).or_default().push(ratio);
+        if ratio > LEGAL_CAP_MONTHS {
+            flagged.push(FlaggedListing {
+                property_id: property.property_id.clone(),
+                area: area.to_string(),
+                deposit,
+                rent,
+                ratio,
+            });
+        }
+    }
+
+    let mut areas: Vec<AreaDeposits> = ratios
+        .into_iter()
+        .map(|(area, mut ratios)| {
+            ratios.sort_by(f64::total_cmp);
+            let listings = ratios.len();
+            AreaDeposits {
+                area_label: Lang::default().area(&area),
+                area,
+                listings,
+                median_ratio: sample::suppress(median(&ratios), listings),
+                mean_ratio: sample::suppress(ratios.iter().sum::<f64>() / listings as f64, listings),
+                over_cap: ratios.iter().filter(|ratio| **ratio > LEGAL_CAP_MONTHS).count(),
+            }
+        })
+        .collect();
+    let ratio = |area: &AreaDeposits| area.median_ratio.unwrap_or(f64::NEG_INFINITY);
+    areas.sort_by(|a, b| ratio(b).total_cmp(&ratio(a)).then_with(|| a.area.cmp(&b.area)));
+    flagged.sort_by(|a, b| b.ratio.total_cmp(&a.ratio).then_with(|| a.property_id.cmp(&b.property_id)));
+    DepositStats { areas, flagged }
+}
+
+/// Deposit ratios over the latest snapshot of the requested source, or every
+/// source; over every listing in the snapshots of `window` when it is set.
+/// Blocking.
+pub fn compute(config: &Config, store: &dyn PropertyStore, source: Option<&str>, window: &Window) -> DepositStats {
+    let sources = config.source_names(source);
+    if window.is_set() {
+        let properties: Vec<StandardizedProperty> =
+            sources.into_iter().flat_map(|source| store.across(source, window.dates())).collect();
+        return tally(&properties);
+    }
+    tally(&store.query(&sources, &|_| true))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DepositParams {
+    source: Option<String>,
+    /// Only this normalized area, e.g. "dublin 6".
+    area: Option<String>,
+    #[serde(flatten)]
+    window: Window,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DepositsResponse {
+    pub lang: Lang,
+    pub legal_cap_months: f64,
+    pub areas: Vec<AreaDeposits>,
+    /// Listings asking more than `legal_cap_months` of rent.
+    pub flagged: Vec<FlaggedListing>,
+}
+
+pub async fn deposits(
+    State(state): State<AppState>,
+    lang: Lang,
+    Query(params): Query<DepositParams>,
+) -> Result<Json<DepositsResponse>, (StatusCode, String)> {
+    params.window.validate()?;
+    let (source, window) = (params.source.clone(), params.window);
+    let stats =
+        tokio::task::spawn_blocking(move || compute(&state.config, state.store.as_ref(), source.as_deref(), &window))
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let DepositStats { mut areas, mut flagged } = stats;
+    if let Some(area) = params.area.as_deref().map(|a| a.trim().to_lowercase()) {
+        areas.retain(|a| a.area == area);
+        flagged.retain(|f| f.area == area);
+    }
+    for area in &mut areas {
+        area.area_label = lang.area(&area.area);
+    }
+    Ok(Json(DepositsResponse { lang, legal_cap_months: LEGAL_CAP_MONTHS, areas, flagged }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::listing;
+
+    #[test]
+    fn test_ratios_and_flags() {
+        let rental = |id: &str, area: &str, rent: f64, deposit: Option<f64>| {
+            let mut property = listing("daft", id);
+            property.address.normalized_address = format!("1 main street, {}", area);
+            property.price.amount = rent;
+            property.deposit = deposit;
+            property
+        };
+        let stats = tally(&[
+            rental("1", "dublin 6", 2000.0, Some(2000.0)),
+            rental("2", "dublin 6", 2000.0, Some(6000.0)),
+            rental("3", "dublin 6", 2000.0, None),
+            rental("4", "county cork", 1500.0, Some(1500.0)),
+        ]);
+
+        let dublin = &stats.areas[0];
+        assert_eq!((dublin.area.as_str(), dublin.listings, dublin.over_cap), ("dublin 6", 2, 1));
+        assert_eq!((dublin.median_ratio, dublin.mean_ratio), (Some(2.0), Some(2.0)));
+        assert_eq!(stats.areas[1].median_ratio, Some(1.0));
+        assert_eq!(stats.flagged.len(), 1);
+        assert_eq!((stats.flagged[0].property_id.as_str(), stats.flagged[0].ratio), ("daft_2", 3.0));
+    }
+}
@@ -0,0 +1,480 @@
+//! Quality-adjusted (hedonic) rent index.
+//!
+//! Headline medians move whenever the mix of listings changes: a month with more
+//! three-bed houses looks like a rent increase even if nothing got dearer. The
+//! index below fits `ln(rent) ~ bedrooms + type + area + BER + ln(size)` separately
+//! for every period and prices a fixed basket (the average listing of the base
+//! period) with each period's coefficients, so only like-for-like changes move it.
+//! `from`/`to` limit the series to the snapshots between them, so the median
+//! asking rent of one quarter is `?period=quarter&from=2024-07-01&to=2024-09-30`.
+//! Sizes of low confidence (see `floor_area`) count as missing, both in the
+//! fit and in the median rent per m².
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{Datelike, NaiveDate};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use super::density::{self, AreaCounts, DensityAnomaly};
+use super::regression::{fit_ridge, LinearFit};
+use super::sample;
+use super::smoothing::Smoothing;
+use super::Window;
+use crate::address;
+use crate::ber::BerStatus;
+use crate::floor_area;
+use crate::locale::Lang;
+use crate::state::AppState;
+use crate::{validate_price, StandardizedProperty};
+
+/// Periods with fewer listings than this are reported without an index value.
+pub(super) const MIN_PERIOD_LISTINGS: usize = 10;
+pub(super) const RIDGE_PENALTY: f64 = 1e-3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    Day,
+    Week,
+    #[default]
+    Month,
+    Quarter,
+}
+
+impl Granularity {
+    /// Period label for a snapshot date. Labels sort chronologically as strings.
+    pub fn label(self, date: NaiveDate) -> String {
+        match self {
+            Granularity::Day => date.format("%Y-%m-%d").to_string(),
+            Granularity::Week => date.format("%G-W%V").to_string(),
+            Granularity::Month => date.format("%Y-%m").to_string(),
+            Granularity::Quarter => format!("{}-Q{}", date.year(), (date.month() - 1) / 3 + 1),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IndexParams {
+    source: Option<String>,
+    period: Option<Granularity>,
+    #[serde(flatten)]
+    window: Window,
+    smoothing: Option<Smoothing>,
+    /// With `smoothing`, also return the unsmoothed values.
+    #[serde(default)]
+    raw: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexPoint {
+    pub period: String,
+    /// `period` for display, in the response language.
+    pub label: String,
+    /// Listings behind `median_rent` and `index`.
+    pub listings: usize,
+    /// Null over fewer than `stats.min_sample` listings; see `sample`.
+    pub median_rent: Option<f64>,
+    /// Median monthly rent per m², over the listings with a usable size.
+    pub median_rent_per_sqm: Option<f64>,
+    pub index: Option<f64>,
+    pub r_squared: Option<f64>,
+    /// `median_rent` and `index` before smoothing, with `raw=true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<RawPoint>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RawPoint {
+    pub median_rent: Option<f64>,
+    pub median_rent_per_sqm: Option<f64>,
+    pub index: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexResponse {
+    pub lang: Lang,
+    pub granularity: Granularity,
+    pub granularity_label: String,
+    pub base_period: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smoothing: Option<Smoothing>,
+    pub series: Vec<IndexPoint>,
+    /// Areas whose listing count in a source's latest snapshot deviates from
+    /// its baseline; index values involving them deserve a second look.
+    pub density_anomalies: Vec<DensityAnomaly>,
+}
+
+impl IndexResponse {
+    /// Smooths `median_rent`, `median_rent_per_sqm` and `index` across the
+    /// series, keeping the raw values on each point when `keep_raw`.
+    pub fn smooth(&mut self, smoothing: Smoothing, keep_raw: bool) {
+        let rents: Vec<Option<f64>> = self.series.iter().map(|point| point.median_rent).collect();
+        let per_sqm: Vec<Option<f64>> = self.series.iter().map(|point| point.median_rent_per_sqm).collect();
+        let indices: Vec<Option<f64>> = self.series.iter().map(|point| point.index).collect();
+        let rents = smoothing.apply(self.granularity, &rents);
+        let per_sqm = smoothing.apply(self.granularity, &per_sqm);
+        let indices = smoothing.apply(self.granularity, &indices);
+        for (((point, rent), per_sqm), index) in self.series.iter_mut().zip(rents).zip(per_sqm).zip(indices) {
+            if keep_raw {
+                point.raw = Some(RawPoint {
+                    median_rent: point.median_rent,
+                    median_rent_per_sqm: point.median_rent_per_sqm,
+                    index: point.index,
+                });
+            }
+            point.median_rent = rent;
+            point.median_rent_per_sqm = per_sqm;
+            point.index = index;
+        }
+        self.smoothing = Some(smoothing);
+    }
+
+    /// Fills in the display labels for `lang`.
+    pub fn localize(&mut self, lang: Lang) {
+        self.lang = lang;
+        self.granularity_label = lang.granularity(self.granularity).to_string();
+        for point in &mut self.series {
+            point.label = lang.period(self.granularity, &point.period);
+        }
+        for anomaly in &mut self.density_anomalies {
+            anomaly.localize(lang);
+        }
+    }
+}
+
+pub async fn rent_index(
+    State(state): State<AppState>,
+    lang: Lang,
+    Query(params): Query<IndexParams>,
+) -> Result<Json<IndexResponse>, (StatusCode, String)> {
+    params.window.validate()?;
+    let granularity = params.period.unwrap_or_default();
+    let (source, window) = (params.source.clone(), params.window);
+    let (observations, density_anomalies) = tokio::task::spawn_blocking(move || {
+        let config = &state.config;
+        let mut observations = Vec::new();
+        let mut density_anomalies = Vec::new();
+        for source in config.source_names(source.as_deref()) {
+            let mut counts = AreaCounts::new();
+            for date in state.store.snapshots(source).into_iter().filter(|date| window.dates().contains(date)) {
+                let period = granularity.label(date);
+                debug!("Loading {} snapshot {} for period {}", source, date, period);
+                let properties = state.store.listings(source, date);
+                counts.insert(date, density::area_counts(&properties));
+                observations.extend(
+                    properties
+                        .into_iter()
+                        .filter(validate_price)
+                        .map(|p| (period.clone(), p)),
+                );
+            }
+            if config.density.enabled {
+                density_anomalies.extend(density::detect(source, &counts, &config.density));
+            }
+        }
+        (observations, density_anomalies)
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let mut response = build_index(granularity, observations);
+    if let Some(smoothing) = params.smoothing {
+        response.smooth(smoothing, params.raw);
+    }
+    response.density_anomalies = density_anomalies;
+    response.localize(lang);
+    Ok(Json(response))
+}
+
+/// Builds the index series from `(period, listing)` observations.
+///
+/// Observations are expected oldest snapshot first; when a listing appears in
+/// several snapshots of the same period only the latest one is kept.
+pub fn build_index(
+    granularity: Granularity,
+    observations: Vec<(String, StandardizedProperty)>,
+) -> IndexResponse {
+    let mut periods: BTreeMap<String, HashMap<String, StandardizedProperty>> = BTreeMap::new();
+    for (period, property) in observations {
+        periods
+            .entry(period)
+            .or_default()
+            .insert(property.property_id.clone(), property);
+    }
+
+    let features = FeatureSpace::build(periods.values().flat_map(|listings| listings.values()));
+
+    let fits: Vec<PeriodFit> = periods
+        .into_iter()
+        .map(|(period, listings)| {
+            let rows: Vec<Vec<f64>> = listings.values().map(|p| features.encode(p)).collect();
+            let rents: Vec<f64> = listings.values().map(|p| p.price.amount).collect();
+            let per_sqm: Vec<f64> =
+                listings.values().filter_map(|p| Some(p.price.amount / floor_area::usable_sqm(p)?)).collect();
+            let fit = if rows.len() >= MIN_PERIOD_LISTINGS {
+                let targets: Vec<f64> = rents.iter().map(|r| r.ln()).collect();
+                fit_ridge(&rows, &targets, RIDGE_PENALTY)
+            } else {
+                debug!("Period {} has only {} listings, skipping fit", period, rows.len());
+                None
+            };
+            PeriodFit { period, rows, rents, per_sqm, fit }
+        })
+        .collect();
+
+    // The basket is the average listing of the first period we could fit
+    let base = fits.iter().find_map(|period| {
+        period
+            .fit
+            .as_ref()
+            .map(|fit| (period.period.clone(), mean_row(&period.rows), fit.clone()))
+    });
+
+    let series = fits
+        .into_iter()
+        .map(|PeriodFit { period, rows, rents, per_sqm, fit }| {
+            let index = match (&base, &fit) {
+                (Some((_, basket, base_fit)), Some(fit)) => {
+                    Some(100.0 * (fit.predict(basket) - base_fit.predict(basket)).exp())
+                }
+                _ => None,
+            };
+            let (listings, sized) = (rows.len(), per_sqm.len());
+            IndexPoint {
+                label: Lang::default().period(granularity, &period),
+                period,
+                listings,
+                median_rent: sample::suppress(median(rents), listings),
+                median_rent_per_sqm: sample::suppress(median(per_sqm), sized).filter(|_| sized > 0),
+                index: index.and_then(|index| sample::suppress(index, listings)),
+                r_squared: fit.map(|f| f.r_squared),
+                raw: None,
+            }
+        })
+        .collect();
+
+    IndexResponse {
+        lang: Lang::default(),
+        granularity,
+        granularity_label: Lang::default().granularity(granularity).to_string(),
+        base_period: base.map(|(period, _, _)| period),
+        smoothing: None,
+        series,
+        density_anomalies: vec![],
+    }
+}
+
+struct PeriodFit {
+    period: String,
+    rows: Vec<Vec<f64>>,
+    rents: Vec<f64>,
+    /// Rents per m² of the listings with a usable size.
+    per_sqm: Vec<f64>,
+    fit: Option<LinearFit>,
+}
+
+/// Categorical and numeric encoding of listing characteristics.
+///
+/// Layout: intercept, bedrooms, bedrooms-missing, ln(size), size-missing, then one
+/// dummy per non-reference property type, area and BER band. The first value of
+/// each category (in sorted order) is the reference and gets no column.
+pub(super) struct FeatureSpace {
+    property_types: Vec<String>,
+    areas: Vec<String>,
+    ber_bands: Vec<String>,
+}
+
+impl FeatureSpace {
+    pub(super) fn build<'a>(properties: impl Iterator<Item = &'a StandardizedProperty>) -> Self {
+        let mut property_types = BTreeSet::new();
+        let mut areas = BTreeSet::new();
+        let mut ber_bands = BTreeSet::new();
+        for property in properties {
+            property_types.insert(property_type_key(property));
+            areas.insert(area_key(property));
+            ber_bands.insert(ber_band(property));
+        }
+
+        let drop_reference = |set: BTreeSet<String>| set.into_iter().skip(1).collect();
+        FeatureSpace {
+            property_types: drop_reference(property_types),
+            areas: drop_reference(areas),
+            ber_bands: drop_reference(ber_bands),
+        }
+    }
+
+    pub(super) fn encode(&self, property: &StandardizedProperty) -> Vec<f64> {
+        let mut row = vec![1.0];
+
+        match property.bedrooms {
+            Some(beds) => row.extend([beds as f64, 0.0]),
+            None => row.extend([0.0, 1.0]),
+        }
+        match floor_area::usable_sqm(property) {
+            Some(sqm) => row.extend([sqm.ln(), 0.0]),
+            None => row.extend([0.0, 1.0]),
+        }
+
+        let one_hot = |row: &mut Vec<f64>, levels: &[String], value: String| {
+            row.extend(levels.iter().map(|level| if *level == value { 1.0 } else { 0.0 }));
+        };
+        one_hot(&mut row, &self.property_types, property_type_key(property));
+        one_hot(&mut row, &self.areas, area_key(property));
+        one_hot(&mut row, &self.ber_bands, ber_band(property));
+        row
+    }
+}
+
+fn property_type_key(property: &StandardizedProperty) -> String {
+    let key = property.property_type.trim().to_lowercase();
+    if key.is_empty() { "unknown".to_string() } else { key }
+}
+
+/// Area is the last component of the normalized address ("..., dublin 6").
+fn area_key(property: &StandardizedProperty) -> String {
+    let area = address::area(&property.address.normalized_address);
+    if area.is_empty() {
+        "unknown".to_string()
+    } else {
+        area.to_string()
+    }
+}
+
+/// BER letter band (A-G); sub-grades such as A2/B3 collapse to their letter.
+pub fn ber_band(property: &StandardizedProperty) -> String {
+    if property.ber_status == BerStatus::Exempt {
+        return "exempt".to_string();
+    }
+    property
+        .ber_rating
+        .as_deref()
+        .and_then(|rating| rating.trim().chars().next())
+        .map(|c| c.to_ascii_uppercase())
+        .filter(|c| ('A'..='G').contains(c))
+        .map(|c| c.to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn mean_row(rows: &[Vec<f64>]) -> Vec<f64> {
+    let width = rows.first().map_or(0, |row| row.len());
+    let mut mean = vec![0.0; width];
+    for row in rows {
+        for (m, x) in mean.iter_mut().zip(row) {
+            *m += x / rows.len() as f64;
+        }
+    }
+    mean
+}
+
+pub fn median(mut values: Vec<f64>) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    fn listing(id: usize, beds: i32, property_type: &str, area: &str, rent: f64) -> StandardizedProperty {
+        let mut property = test_utils::listing("test", &id.to_string());
+        property.address.display_address = format!("{} Main Street, {}", id, area);
+        property.address.normalized_address = address::normalize(&property.address.display_address);
+        property.property_type = property_type.to_string();
+        property.bedrooms = Some(beds);
+        property.ber_rating = Some("B2".to_string());
+        property.ber_status = BerStatus::Rated;
+        property.price.amount = rent;
+        property
+    }
+
+    /// Rent is fully determined by characteristics, scaled by `level`.
+    fn period_listings(period: &str, start: usize, large_share: usize, level: f64) -> Vec<(String, StandardizedProperty)> {
+        (0..20)
+            .map(|i| {
+                let id = start + i;
+                let (beds, kind) = if i < large_share { (3, "House") } else { (1, "Apartment") };
+                let area = if i % 2 == 0 { "Dublin 6" } else { "Dublin 8" };
+                let base = 1200.0 * 1.25f64.powi(beds) * if area == "Dublin 6" { 1.1 } else { 1.0 };
+                (period.to_string(), listing(id, beds, kind, area, base * level))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_period_labels() {
+        let date = NaiveDate::from_ymd_opt(2024, 11, 5).unwrap();
+        assert_eq!(Granularity::Day.label(date), "2024-11-05");
+        assert_eq!(Granularity::Week.label(date), "2024-W45");
+        assert_eq!(Granularity::Month.label(date), "2024-11");
+        assert_eq!(Granularity::Quarter.label(date), "2024-Q4");
+    }
+
+    #[test]
+    fn test_index_ignores_mix_change() {
+        // Same prices per characteristic, but the second period has many more houses
+        let mut observations = period_listings("2024-10", 0, 4, 1.0);
+        observations.extend(period_listings("2024-11", 100, 16, 1.0));
+
+        let response = build_index(Granularity::Month, observations);
+        assert_eq!(response.base_period.as_deref(), Some("2024-10"));
+        let [first, second] = &response.series[..] else { panic!("expected two periods") };
+
+        assert!(second.median_rent.unwrap() > first.median_rent.unwrap() * 1.3);
+        assert!((first.index.unwrap() - 100.0).abs() < 0.01);
+        assert!((second.index.unwrap() - 100.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_index_tracks_quality_adjusted_growth() {
+        let mut observations = period_listings("2024-10", 0, 10, 1.0);
+        observations.extend(period_listings("2024-11", 100, 10, 1.05));
+
+        let response = build_index(Granularity::Month, observations);
+        let index = response.series[1].index.unwrap();
+        assert!((index - 105.0).abs() < 0.5, "index was {}", index);
+    }
+
+    #[test]
+    fn test_thin_periods_have_no_index() {
+        let mut observations = period_listings("2024-10", 0, 10, 1.0);
+        observations.truncate(5);
+
+        let response = build_index(Granularity::Month, observations);
+        assert_eq!(response.base_period, None);
+        assert_eq!(response.series[0].listings, 5);
+        assert!(response.series[0].index.is_none());
+    }
+
+    #[test]
+    fn test_repeated_listing_counts_once_per_period() {
+        let mut observations = period_listings("2024-10", 0, 10, 1.0);
+        observations.extend(period_listings("2024-10", 0, 10, 1.0));
+
+        let response = build_index(Granularity::Month, observations);
+        assert_eq!(response.series[0].listings, 20);
+    }
+
+    #[test]
+    fn test_window_params() {
+        let uri: axum::http::Uri = "/api/stats/index?period=quarter&from=2024-07-01&to=2024-09-30".parse().unwrap();
+        let Query(params) = Query::<IndexParams>::try_from_uri(&uri).unwrap();
+        assert_eq!(params.period, Some(Granularity::Quarter));
+        let dates = params.window.dates();
+        assert!(dates.contains(&"2024-09-30".parse().unwrap()) && !dates.contains(&"2024-10-01".parse().unwrap()));
+
+        let uri: axum::http::Uri = "/api/stats/index?from=2024-10-01&to=2024-09-30".parse().unwrap();
+        assert!(Query::<IndexParams>::try_from_uri(&uri).unwrap().0.window.validate().is_err());
+    }
+}
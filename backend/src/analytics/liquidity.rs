@@ -0,0 +1,272 @@
+//! Per-area liquidity score: how hard it is to find a place right now.
+//!
+//! Four measures are taken per area over a source's latest `window_days`
+//! snapshots, or those between `from` and `to` when given:
+//! - supply: listings in the latest snapshot
+//! - churn: the share of listings gone by the next day, averaged over the window
+//! - days on market: median age of the latest listings, from `created_date`
+//! - price dispersion: interquartile range of rents over the median rent
+//!
+//! Each is turned into a percentile rank among the areas in the response,
+//! oriented so that 1 is the hard end (little supply, fast churn, short days on
+//! market, a narrow price range), and the score is their mean scaled to 0–100.
+//! Scores are relative: 80 means harder than most other areas, not a fixed
+//! level of competition. Areas with fewer than `min_listings` listings are
+//! left out as too noisy.
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use super::Window;
+use crate::address;
+use crate::config::{Config, LiquidityConfig};
+use crate::id_index::listing_time;
+use crate::locale::Lang;
+use crate::state::AppState;
+use crate::store::PropertyStore;
+use crate::{validate_price, StandardizedProperty};
+
+/// Raw figures for one area, summed over sources.
+#[derive(Debug, Default)]
+struct Tally {
+    supply: usize,
+    /// Listings that had a next snapshot to be checked against, and how many
+    /// of those were gone from it.
+    exposed: usize,
+    removed: usize,
+    days_on_market: Vec<f64>,
+    rents: Vec<f64>,
+}
+
+fn area_of(property: &StandardizedProperty) -> Option<&str> {
+    Some(address::area(&property.address.normalized_address)).filter(|area| !area.is_empty())
+}
+
+/// Adds one source's snapshots, oldest first, to `tallies`. Only the latest
+/// snapshot and the ids of the one before it are held at a time.
+fn tally_source(
+    snapshots: impl IntoIterator<Item = (NaiveDate, Vec<StandardizedProperty>)>,
+    tallies: &mut HashMap<String, Tally>,
+) {
+    let mut previous: Option<HashMap<String, String>> = None;
+    let mut latest = None;
+    for (date, properties) in snapshots {
+        let current: HashSet<&str> = properties.iter().map(|p| p.property_id.as_str()).collect();
+        for (id, area) in previous.iter().flatten() {
+            let tally = tallies.entry(area.clone()).or_default();
+            tally.exposed += 1;
+            if !current.contains(id.as_str()) {
+                tally.removed += 1;
+            }
+        }
+        previous = Some(
+            properties
+                .iter()
+                .filter_map(|p| Some((p.property_id.clone(), area_of(p)?.to_string())))
+                .collect(),
+        );
+        latest = Some((date, properties));
+    }
+
+    let Some((date, properties)) = latest else {
+        return;
+    };
+    for property in &properties {
+        let Some(area) = area_of(property) else {
+            continue;
+        };
+        let tally = tallies.entry(area.to_string()).or_default();
+        tally.supply += 1;
+        if validate_price(property) {
+            tally.rents.push(property.price.amount);
+        }
+        if let Some(created) = listing_time(&property.created_date) {
+            let days = (date - created.date()).num_days();
+            if days >= 0 {
+                tally.days_on_market.push(days as f64);
+            }
+        }
+    }
+}
+
+/// Linear-interpolated quantile of sorted values.
+pub fn quantile(sorted: &[f64], q: f64) -> Option<f64> {
+    let last = sorted.len().checked_sub(1)?;
+    let position = q * last as f64;
+    let (low, high) = (position.floor() as usize, position.ceil() as usize);
+    Some(sorted[low] + (sorted[high] - sorted[low]) * (position - low as f64))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AreaLiquidity {
+    pub area: String,
+    /// `area` for display, in the response language.
+    pub area_label: String,
+    /// 0 (easiest) to 100 (hardest), relative to the other areas.
+    pub score: f64,
+    pub supply: usize,
+    /// Average share of listings gone the next day; `None` with one snapshot.
+    pub churn: Option<f64>,
+    pub median_days_on_market: Option<f64>,
+    pub median_rent: Option<f64>,
+    /// Interquartile range of rents over the median rent.
+    pub price_dispersion: Option<f64>,
+}
+
+/// Percentile rank of each value among the known ones, ties sharing the
+/// midpoint: 0 for the lowest, 1 for the highest, 0.5 when alone.
+fn ranks(values: &[Option<f64>]) -> Vec<Option<f64>> {
+    let known: Vec<f64> = values.iter().flatten().copied().collect();
+    values
+        .iter()
+        .map(|value| {
+            let value = (*value)?;
+            if known.len() < 2 {
+                return Some(0.5);
+            }
+            let below = known.iter().filter(|other| **other < value).count();
+            let equal = known.iter().filter(|other| **other == value).count();
+            Some((below as f64 + (equal - 1) as f64 / 2.0) / (known.len() - 1) as f64)
+        })
+        .collect()
+}
+
+fn score(tallies: HashMap<String, Tally>, config: &LiquidityConfig) -> Vec<AreaLiquidity> {
+    let mut areas: Vec<AreaLiquidity> = tallies
+        .into_iter()
+        .filter(|(_, tally)| tally.supply >= config.min_listings.max(1))
+        .map(|(area, mut tally)| {
+            tally.rents.sort_by(f64::total_cmp);
+            tally.days_on_market.sort_by(f64::total_cmp);
+            let median_rent = quantile(&tally.rents, 0.5);
+            let spread = quantile(&tally.rents, 0.75).zip(quantile(&tally.rents, 0.25)).map(|(q3, q1)| q3 - q1);
+            AreaLiquidity {
+                area_label: Lang::default().area(&area),
+                area,
+                score: 0.0,
+                supply: tally.supply,
+                churn: (tally.exposed > 0).then(|| tally.removed as f64 / tally.exposed as f64),
+                median_days_on_market: quantile(&tally.days_on_market, 0.5),
+                price_dispersion: spread.zip(median_rent).map(|(spread, median)| spread / median),
+                median_rent,
+            }
+        })
+        .collect();
+
+    // Oriented so a higher rank is harder to rent in
+    let components = [
+        ranks(&areas.iter().map(|a| Some(-(a.supply as f64))).collect::<Vec<_>>()),
+        ranks(&areas.iter().map(|a| a.churn).collect::<Vec<_>>()),
+        ranks(&areas.iter().map(|a| a.median_days_on_market.map(|d| -d)).collect::<Vec<_>>()),
+        ranks(&areas.iter().map(|a| a.price_dispersion.map(|d| -d)).collect::<Vec<_>>()),
+    ];
+    for (i, area) in areas.iter_mut().enumerate() {
+        let known: Vec<f64> = components.iter().filter_map(|ranks| ranks[i]).collect();
+        let mean = known.iter().sum::<f64>() / known.len() as f64;
+        area.score = (mean * 1000.0).round() / 10.0;
+    }
+    areas.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.area.cmp(&b.area)));
+    areas
+}
+
+/// Scores the areas of the requested source, or every source, over the
+/// snapshots of `window` or, without one, the latest `window_days`. Blocking.
+pub fn compute(config: &Config, store: &dyn PropertyStore, source: Option<&str>, window: &Window) -> Vec<AreaLiquidity> {
+    let mut tallies = HashMap::new();
+    for source in config.source_names(source) {
+        let mut snapshots = store.snapshots(source);
+        snapshots.retain(|date| window.dates().contains(date));
+        let skip = match window.is_set() {
+            true => 0,
+            false => snapshots.len().saturating_sub(config.liquidity.window_days.max(1)),
+        };
+        let loaded = snapshots.into_iter().skip(skip).map(|date| (date, store.listings(source, date)));
+        tally_source(loaded, &mut tallies);
+    }
+    score(tallies, &config.liquidity)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LiquidityParams {
+    source: Option<String>,
+    /// Only this normalized area, e.g. "dublin 6"; still scored against all.
+    area: Option<String>,
+    #[serde(flatten)]
+    window: Window,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LiquidityResponse {
+    pub lang: Lang,
+    pub window_days: usize,
+    pub areas: Vec<AreaLiquidity>,
+}
+
+pub async fn liquidity(
+    State(state): State<AppState>,
+    lang: Lang,
+    Query(params): Query<LiquidityParams>,
+) -> Result<Json<LiquidityResponse>, (StatusCode, String)> {
+    params.window.validate()?;
+    let (reading, source, window) = (state.clone(), params.source.clone(), params.window);
+    let mut areas = tokio::task::spawn_blocking(move || {
+        compute(&reading.config, reading.store.as_ref(), source.as_deref(), &window)
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let config = &state.config;
+    if let Some(area) = params.area.as_deref().map(|a| a.trim().to_lowercase()) {
+        areas.retain(|a| a.area == area);
+    }
+    for area in &mut areas {
+        area.area_label = lang.area(&area.area);
+    }
+    Ok(Json(LiquidityResponse { lang, window_days: config.liquidity.window_days, areas }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::listing;
+
+    fn rental(id: usize, area: &str, rent: f64, created: &str) -> StandardizedProperty {
+        let mut property = listing("daft", &id.to_string());
+        property.address.normalized_address = format!("{} main street, {}", id, area);
+        property.price.amount = rent;
+        property.created_date = created.to_string();
+        property
+    }
+
+    #[test]
+    fn test_tight_market_scores_higher() {
+        let day = |d| NaiveDate::from_ymd_opt(2024, 11, d).unwrap();
+        // Dublin 6: few listings, all let within a day, recent and similarly priced
+        let tight = |first: usize| -> Vec<_> {
+            (first..first + 3).map(|i| rental(i, "dublin 6", 2000.0 + i as f64, "2024-11-05")).collect()
+        };
+        // Cork: plenty that stay up for weeks across a wide range of rents
+        let slack: Vec<_> =
+            (100..110).map(|i| rental(i, "county cork", 800.0 + 100.0 * (i - 100) as f64, "2024-10-01")).collect();
+        let mut tallies = HashMap::new();
+        tally_source(
+            [(day(5), [tight(1), slack.clone()].concat()), (day(6), [tight(10), slack.clone()].concat())],
+            &mut tallies,
+        );
+
+        let areas = score(tallies, &LiquidityConfig { min_listings: 3, ..Default::default() });
+        assert_eq!(areas.iter().map(|a| (a.area.as_str(), a.score)).collect::<Vec<_>>(), [("dublin 6", 100.0), ("county cork", 0.0)]);
+        assert_eq!((areas[0].supply, areas[0].churn, areas[0].median_days_on_market), (3, Some(1.0), Some(1.0)));
+        assert_eq!((areas[1].churn, areas[1].median_rent), (Some(0.0), Some(1250.0)));
+        assert_eq!(areas[1].price_dispersion, Some(450.0 / 1250.0));
+    }
+
+    #[test]
+    fn test_ranks() {
+        assert_eq!(ranks(&[Some(3.0), None, Some(1.0), Some(3.0)]), [Some(0.75), None, Some(0.0), Some(0.75)]);
+        assert_eq!(ranks(&[Some(7.0)]), [Some(0.5)]);
+    }
+}
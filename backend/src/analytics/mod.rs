@@ -0,0 +1,46 @@
+//! Market analytics computed over parsed listings.
+
+use axum::http::StatusCode;
+use chrono::NaiveDate;
+use serde::Deserialize;
+use std::ops::RangeInclusive;
+
+pub mod area;
+pub mod density;
+pub mod deposits;
+pub mod hedonic;
+pub mod liquidity;
+pub mod regime;
+mod regression;
+pub mod sample;
+pub mod smoothing;
+pub mod stats;
+pub mod value;
+
+/// The snapshot dates a stats request covers, from its `from` and `to`
+/// parameters (both inclusive). Without either the endpoint keeps its usual
+/// snapshots, usually the latest.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct Window {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl Window {
+    pub fn is_set(&self) -> bool {
+        self.from.is_some() || self.to.is_some()
+    }
+
+    pub fn dates(&self) -> RangeInclusive<NaiveDate> {
+        self.from.unwrap_or(NaiveDate::MIN)..=self.to.unwrap_or(NaiveDate::MAX)
+    }
+
+    pub fn validate(&self) -> Result<(), (StatusCode, String)> {
+        match (self.from, self.to) {
+            (Some(from), Some(to)) if from > to => {
+                Err((StatusCode::BAD_REQUEST, format!("from ({}) is after to ({})", from, to)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
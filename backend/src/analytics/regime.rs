@@ -0,0 +1,294 @@
+//! Market regime changes.
+//!
+//! Compares each area of a source's latest snapshot with the snapshot from
+//! `regime.lookback_days` before it (a month by default) and flags the moves a
+//! market watcher would want to hear about: the median rent shifting by at
+//! least `regime.rent_threshold` either way, and supply collapsing, the
+//! listing count falling by at least `regime.supply_threshold`. Unlike
+//! `density`, which looks for scraping gaps day to day, these are market
+//! events, and unlike the digests of a user's watched areas (see
+//! `reports::watch`) they are system-wide: each shift is sent once through the
+//! notifier as a `market.rent_shift` or `market.supply_collapse` notification,
+//! and `/api/stats/regimes` lists the current ones.
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{Duration as Days, NaiveDate};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::address;
+use crate::config::{Config, RegimeConfig};
+use crate::locale::Lang;
+use crate::notifier::{self, Notification};
+use crate::state::AppState;
+use crate::store::{Group, PropertyStore};
+use crate::StandardizedProperty;
+
+/// Job kind that runs a check and notifies about new shifts.
+pub const JOB: &str = "regime.check";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShiftKind {
+    /// The median rent moved past `regime.rent_threshold`.
+    RentShift,
+    /// The listing count fell past `regime.supply_threshold`.
+    SupplyCollapse,
+}
+
+impl ShiftKind {
+    fn notification_kind(self) -> &'static str {
+        match self {
+            ShiftKind::RentShift => "market.rent_shift",
+            ShiftKind::SupplyCollapse => "market.supply_collapse",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RegimeShift {
+    pub kind: ShiftKind,
+    pub source: String,
+    pub area: String,
+    /// `area` for display, in the response language.
+    pub area_label: String,
+    /// Date of the latest snapshot.
+    pub date: NaiveDate,
+    /// Date of the snapshot compared against.
+    pub baseline_date: NaiveDate,
+    /// Median rent, or listing count for a supply collapse, on `date`.
+    pub value: f64,
+    /// The same on `baseline_date`.
+    pub baseline: f64,
+    /// Relative change from the baseline; -0.4 is a 40% fall.
+    pub change: f64,
+}
+
+impl RegimeShift {
+    pub fn localize(&mut self, lang: Lang) {
+        self.area_label = lang.area(&self.area);
+    }
+
+    fn key(&self) -> String {
+        format!("{}/{}/{}/{:?}", self.source, self.date, self.area, self.kind)
+    }
+
+    fn text(&self) -> String {
+        match self.kind {
+            ShiftKind::RentShift => format!(
+                "Median {} rent in {} moved {:+.0}% since {}: €{:.0} from €{:.0}",
+                self.source,
+                self.area_label,
+                self.change * 100.0,
+                self.baseline_date,
+                self.value,
+                self.baseline
+            ),
+            ShiftKind::SupplyCollapse => format!(
+                "{} listings in {} fell {:.0}% since {}: {} from {}",
+                self.source,
+                self.area_label,
+                -self.change * 100.0,
+                self.baseline_date,
+                self.value,
+                self.baseline
+            ),
+        }
+    }
+}
+
+/// Flags the areas whose median rent or supply moved past the thresholds
+/// going from `baseline` to `latest`. Areas with fewer than
+/// `config.min_listings` listings in the baseline are too small to judge, and
+/// a rent shift needs that many in the latest snapshot too. Largest moves
+/// first.
+pub fn detect(
+    source: &str,
+    (baseline_date, baseline): (NaiveDate, &HashMap<String, Group>),
+    (date, latest): (NaiveDate, &HashMap<String, Group>),
+    config: &RegimeConfig,
+) -> Vec<RegimeShift> {
+    let areas: BTreeSet<&String> =
+        baseline.keys().filter(|area| baseline[*area].listings >= config.min_listings).collect();
+    let shift = |kind, area: &str, value: f64, before: f64| RegimeShift {
+        kind,
+        source: source.to_string(),
+        area: area.to_string(),
+        area_label: Lang::default().area(area),
+        date,
+        baseline_date,
+        value,
+        baseline: before,
+        change: value / before - 1.0,
+    };
+    let mut shifts = Vec::new();
+    for area in areas {
+        let before = &baseline[area];
+        let now = latest.get(area).cloned().unwrap_or(Group { listings: 0, median_rent: None });
+        let supply = shift(ShiftKind::SupplyCollapse, area, now.listings as f64, before.listings as f64);
+        if supply.change <= -config.supply_threshold {
+            shifts.push(supply);
+        }
+        if let (Some(rent), Some(rent_before)) = (now.median_rent, before.median_rent) {
+            let rent = shift(ShiftKind::RentShift, area, rent, rent_before);
+            if now.listings >= config.min_listings && rent.change.abs() >= config.rent_threshold {
+                shifts.push(rent);
+            }
+        }
+    }
+    shifts.sort_by(|a, b| b.change.abs().total_cmp(&a.change.abs()).then_with(|| a.area.cmp(&b.area)));
+    shifts
+}
+
+/// The latest snapshot and the newest one at least `lookback_days` before it.
+fn compared_dates(snapshots: &[NaiveDate], lookback_days: i64) -> Option<(NaiveDate, NaiveDate)> {
+    let latest = *snapshots.last()?;
+    let cutoff = latest - Days::days(lookback_days);
+    let baseline = snapshots.iter().rev().find(|date| **date <= cutoff)?;
+    Some((*baseline, latest))
+}
+
+/// Runs the check for the requested source, or every source. Blocking.
+pub fn check(config: &Config, store: &dyn PropertyStore, source: Option<&str>) -> Vec<RegimeShift> {
+    let area = |p: &StandardizedProperty| {
+        Some(address::area(&p.address.normalized_address).to_string()).filter(|area| !area.is_empty())
+    };
+    config
+        .source_names(source)
+        .into_iter()
+        .flat_map(|source| {
+            let Some((baseline_date, date)) = compared_dates(&store.snapshots(source), config.regime.lookback_days)
+            else {
+                return vec![];
+            };
+            let baseline = store.aggregate(source, baseline_date, &area);
+            let latest = store.aggregate(source, date, &area);
+            detect(source, (baseline_date, &baseline), (date, &latest), &config.regime)
+        })
+        .collect()
+}
+
+/// Shifts already notified, so one isn't reported on every check until the
+/// next snapshot.
+#[derive(Debug, Default)]
+pub struct RegimeAlerts {
+    notified: Mutex<HashSet<String>>,
+}
+
+impl RegimeAlerts {
+    fn first_seen(&self, shift: &RegimeShift) -> bool {
+        self.notified.lock().unwrap().insert(shift.key())
+    }
+}
+
+pub fn run_job(state: &AppState, _payload: &serde_json::Value) -> Result<(), String> {
+    let shifts = check(&state.config, state.store.as_ref(), None);
+    info!("Regime check found {} market shifts", shifts.len());
+    for shift in shifts.into_iter().filter(|shift| state.regimes.first_seen(shift)) {
+        let details = serde_json::to_value(&shift).map_err(|e| e.to_string())?;
+        let notification = Notification::new(shift.kind.notification_kind(), shift.text(), details, state.clock.now());
+        notifier::notify(state, notification);
+    }
+    Ok(())
+}
+
+/// Queues a check every `regime.check_interval_mins` for the life of the
+/// process.
+pub fn schedule(state: &AppState) {
+    if !state.config.regime.enabled {
+        return;
+    }
+    let state = state.clone();
+    let period = Duration::from_secs(state.config.regime.check_interval_mins.max(1) * 60);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            if let Err(e) = state.jobs.enqueue_once(JOB, ()) {
+                error!("Could not queue regime check: {}", e);
+            }
+        }
+    });
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegimeParams {
+    source: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RegimeResponse {
+    pub lang: Lang,
+    pub lookback_days: i64,
+    pub rent_threshold: f64,
+    pub supply_threshold: f64,
+    pub shifts: Vec<RegimeShift>,
+}
+
+pub async fn shifts(
+    State(state): State<AppState>,
+    lang: Lang,
+    Query(params): Query<RegimeParams>,
+) -> Result<Json<RegimeResponse>, (StatusCode, String)> {
+    let config = &state.config;
+    let mut shifts = if config.regime.enabled {
+        let reading = state.clone();
+        tokio::task::spawn_blocking(move || check(&reading.config, reading.store.as_ref(), params.source.as_deref()))
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    } else {
+        vec![]
+    };
+    for shift in &mut shifts {
+        shift.localize(lang);
+    }
+    Ok(Json(RegimeResponse {
+        lang,
+        lookback_days: config.regime.lookback_days,
+        rent_threshold: config.regime.rent_threshold,
+        supply_threshold: config.regime.supply_threshold,
+        shifts,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(n: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 3, n).unwrap()
+    }
+
+    fn groups(areas: &[(&str, usize, f64)]) -> HashMap<String, Group> {
+        areas
+            .iter()
+            .map(|(area, listings, rent)| (area.to_string(), Group { listings: *listings, median_rent: Some(*rent) }))
+            .collect()
+    }
+
+    #[test]
+    fn test_detect_flags_rent_shifts_and_collapsing_supply() {
+        let config = RegimeConfig::default();
+        let before =
+            groups(&[("dublin 6", 100, 2000.0), ("cork", 60, 1500.0), ("galway", 50, 1400.0), ("sligo", 5, 900.0)]);
+        let after =
+            groups(&[("dublin 6", 95, 2300.0), ("cork", 20, 1520.0), ("galway", 55, 1450.0), ("sligo", 1, 1500.0)]);
+
+        let shifts = detect("daft", (day(1), &before), (day(31), &after), &config);
+        let found: Vec<(&str, ShiftKind)> = shifts.iter().map(|s| (s.area.as_str(), s.kind)).collect();
+        // Galway and Cork's rent moved too little, and Sligo is too small to
+        // judge
+        assert_eq!(found, [("cork", ShiftKind::SupplyCollapse), ("dublin 6", ShiftKind::RentShift)]);
+        assert_eq!((shifts[1].value, shifts[1].baseline), (2300.0, 2000.0));
+        assert!((shifts[1].change - 0.15).abs() < 1e-9);
+
+        let snapshots = [day(1), day(2), day(20), day(31)];
+        assert_eq!(compared_dates(&snapshots, 30), Some((day(1), day(31))));
+        assert_eq!(compared_dates(&snapshots[2..], 30), None);
+    }
+}
@@ -0,0 +1,123 @@
+/// Result of a least-squares fit.
+#[derive(Debug, Clone)]
+pub struct LinearFit {
+    pub coefficients: Vec<f64>,
+    pub r_squared: f64,
+}
+
+impl LinearFit {
+    pub fn predict(&self, features: &[f64]) -> f64 {
+        self.coefficients
+            .iter()
+            .zip(features)
+            .map(|(beta, x)| beta * x)
+            .sum()
+    }
+}
+
+/// Fits `targets ~ rows` by ordinary least squares with a small ridge penalty.
+///
+/// Column 0 of every row is treated as the intercept and is not penalized. The
+/// penalty keeps the normal equations solvable when a category has no
+/// observations in the sample (its coefficient simply shrinks to zero).
+pub fn fit_ridge(rows: &[Vec<f64>], targets: &[f64], penalty: f64) -> Option<LinearFit> {
+    let width = rows.first()?.len();
+    if rows.len() != targets.len() || rows.iter().any(|row| row.len() != width) {
+        return None;
+    }
+
+    // Build X'X + penalty * I and X'y
+    let mut xtx = vec![vec![0.0; width]; width];
+    let mut xty = vec![0.0; width];
+    for (row, y) in rows.iter().zip(targets) {
+        for i in 0..width {
+            xty[i] += row[i] * y;
+            for j in 0..width {
+                xtx[i][j] += row[i] * row[j];
+            }
+        }
+    }
+    for (i, diagonal) in xtx.iter_mut().enumerate().skip(1) {
+        diagonal[i] += penalty;
+    }
+
+    let coefficients = solve(xtx, xty)?;
+    let fit = LinearFit {
+        coefficients,
+        r_squared: 0.0,
+    };
+
+    let mean = targets.iter().sum::<f64>() / targets.len() as f64;
+    let total: f64 = targets.iter().map(|y| (y - mean).powi(2)).sum();
+    let residual: f64 = rows
+        .iter()
+        .zip(targets)
+        .map(|(row, y)| (y - fit.predict(row)).powi(2))
+        .sum();
+    let r_squared = if total > 0.0 { 1.0 - residual / total } else { 1.0 };
+
+    Some(LinearFit { r_squared, ..fit })
+}
+
+/// Gaussian elimination with partial pivoting.
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+
+        let (upper, lower) = a.split_at_mut(col + 1);
+        let pivot_row = &upper[col];
+        for (offset, row) in lower.iter_mut().enumerate() {
+            let factor = row[col] / pivot_row[col];
+            if factor == 0.0 {
+                continue;
+            }
+            for (value, pivot_value) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+                *value -= factor * pivot_value;
+            }
+            b[col + 1 + offset] -= factor * b[col];
+        }
+    }
+
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let tail: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - tail) / a[row][row];
+    }
+    Some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recovers_exact_linear_relationship() {
+        let rows: Vec<Vec<f64>> = (0..20)
+            .map(|i| vec![1.0, i as f64, (i % 3) as f64])
+            .collect();
+        let targets: Vec<f64> = rows.iter().map(|r| 2.0 + 0.5 * r[1] - 1.5 * r[2]).collect();
+
+        let fit = fit_ridge(&rows, &targets, 0.0).unwrap();
+        assert!((fit.coefficients[0] - 2.0).abs() < 1e-9);
+        assert!((fit.coefficients[1] - 0.5).abs() < 1e-9);
+        assert!((fit.coefficients[2] + 1.5).abs() < 1e-9);
+        assert!((fit.r_squared - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_ridge_handles_unused_columns() {
+        // Column 2 is always zero, which makes the unpenalized system singular
+        let rows: Vec<Vec<f64>> = (0..10).map(|i| vec![1.0, i as f64, 0.0]).collect();
+        let targets: Vec<f64> = rows.iter().map(|r| 1.0 + r[1]).collect();
+
+        assert!(fit_ridge(&rows, &targets, 0.0).is_none());
+        let fit = fit_ridge(&rows, &targets, 1e-6).unwrap();
+        assert!(fit.coefficients[2].abs() < 1e-9);
+    }
+}
@@ -0,0 +1,42 @@
+//! Sample sizes behind aggregated figures.
+//!
+//! A median of three listings says more about those three flats than about
+//! the market. Every aggregate the stats, chart and report endpoints return
+//! sits next to the number of listings it was computed from, and comes back
+//! as `null` when that is below `stats.min_sample`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static MIN_SAMPLE: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the threshold; call at startup and again when the config is
+/// reloaded. Nothing is suppressed until then.
+pub fn configure(min_sample: usize) {
+    MIN_SAMPLE.store(min_sample, Ordering::Relaxed);
+}
+
+pub fn min_sample() -> usize {
+    MIN_SAMPLE.load(Ordering::Relaxed)
+}
+
+/// `value`, computed over `n` listings, unless that is fewer than `min_sample`.
+pub fn suppress_below(value: f64, n: usize, min_sample: usize) -> Option<f64> {
+    (n >= min_sample).then_some(value)
+}
+
+/// `value`, computed over `n` listings, unless that is too few to report.
+pub fn suppress(value: f64, n: usize) -> Option<f64> {
+    suppress_below(value, n, min_sample())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_small_samples_are_suppressed() {
+        assert_eq!(suppress_below(1850.0, 4, 5), None);
+        assert_eq!(suppress_below(1850.0, 5, 5), Some(1850.0));
+        assert_eq!(suppress_below(1850.0, 0, 0), Some(1850.0));
+    }
+}
@@ -0,0 +1,78 @@
+//! Smoothing for time series.
+//!
+//! Weekly medians of a thin area jump about with whichever handful of listings
+//! happened to be up. Time-series endpoints take `smoothing=rolling_4w` for
+//! the mean of the trailing four weeks of points, or `smoothing=ewm` for an
+//! exponentially weighted mean, and `raw=true` to get the unsmoothed values
+//! alongside. Gaps stay gaps: a point without a value isn't given one, though
+//! the points around it still smooth over the values that exist.
+
+use serde::{Deserialize, Serialize};
+
+use super::hedonic::Granularity;
+
+/// Weight of the newest point in `Smoothing::Ewm`.
+const EWM_ALPHA: f64 = 0.4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Smoothing {
+    #[serde(rename = "rolling_4w")]
+    Rolling4w,
+    #[serde(rename = "ewm")]
+    Ewm,
+}
+
+impl Smoothing {
+    /// `values`, one per period of `granularity` oldest first, smoothed.
+    pub fn apply(self, granularity: Granularity, values: &[Option<f64>]) -> Vec<Option<f64>> {
+        match self {
+            Smoothing::Rolling4w => {
+                let window = match granularity {
+                    Granularity::Day => 28,
+                    Granularity::Week => 4,
+                    // Four weeks is within one period
+                    Granularity::Month | Granularity::Quarter => 1,
+                };
+                (0..values.len())
+                    .map(|i| {
+                        values[i]?;
+                        let trailing = &values[(i + 1).saturating_sub(window)..=i];
+                        let known: Vec<f64> = trailing.iter().flatten().copied().collect();
+                        Some(known.iter().sum::<f64>() / known.len() as f64)
+                    })
+                    .collect()
+            }
+            Smoothing::Ewm => {
+                let mut mean: Option<f64> = None;
+                values
+                    .iter()
+                    .map(|value| {
+                        let value = (*value)?;
+                        let smoothed = mean.map_or(value, |mean| EWM_ALPHA * value + (1.0 - EWM_ALPHA) * mean);
+                        mean = Some(smoothed);
+                        Some(smoothed)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rolling_mean_covers_four_weeks() {
+        let weekly = [Some(1000.0), Some(2000.0), None, Some(1200.0), Some(1400.0), Some(1000.0)];
+        let smoothed = Smoothing::Rolling4w.apply(Granularity::Week, &weekly);
+        assert_eq!(smoothed, [Some(1000.0), Some(1500.0), None, Some(1400.0), Some(1533.3333333333333), Some(1200.0)]);
+        assert_eq!(Smoothing::Rolling4w.apply(Granularity::Month, &weekly), weekly);
+    }
+
+    #[test]
+    fn test_ewm_skips_gaps() {
+        let smoothed = Smoothing::Ewm.apply(Granularity::Week, &[None, Some(1000.0), None, Some(2000.0)]);
+        assert_eq!(smoothed, [None, Some(1000.0), None, Some(1400.0)]);
+    }
+}
@@ -0,0 +1,206 @@
+//! Market statistics for dashboards.
+//!
+//! `GET /api/rentals/stats` summarizes the listings of each source's latest
+//! snapshot (or, with `from`/`to`, every listing seen in the window's
+//! snapshots, as last seen): how many there are, the median, mean and
+//! percentiles of their rent, and the median rent per m² of those with a
+//! trusted size (see `floor_area::usable_sqm`), over all of them and broken
+//! down by property type, bedrooms, BER band and source. Listings without a
+//! valid rent are counted but left out of the rent figures, and figures over
+//! fewer than `stats.min_sample` listings are null.
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::hedonic::ber_band;
+use super::{sample, Window};
+use crate::config::Config;
+use crate::reports::market::{bedrooms_key, property_type_key};
+use crate::state::AppState;
+use crate::store::PropertyStore;
+use crate::{address, floor_area, validate_price, StandardizedProperty};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Percentiles {
+    pub p10: Option<f64>,
+    pub p25: Option<f64>,
+    pub p75: Option<f64>,
+    pub p90: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RentStats {
+    pub listings: usize,
+    /// Listings with a valid rent, which the rent figures are over.
+    pub priced: usize,
+    pub median_rent: Option<f64>,
+    pub mean_rent: Option<f64>,
+    pub percentiles: Percentiles,
+    /// Priced listings with a trusted size.
+    pub sized: usize,
+    pub median_rent_per_sqm: Option<f64>,
+}
+
+/// One group of a breakdown.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GroupStats {
+    pub key: String,
+    #[serde(flatten)]
+    pub stats: RentStats,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MarketStats {
+    pub overall: RentStats,
+    /// Lowercased as advertised, or "unknown".
+    pub by_property_type: Vec<GroupStats>,
+    /// "studio", "1" to "3", "4+" or "unknown".
+    pub by_bedrooms: Vec<GroupStats>,
+    /// The rating's band ("A" to "G"), "exempt" or "unknown".
+    pub by_ber_rating: Vec<GroupStats>,
+    pub by_source: Vec<GroupStats>,
+}
+
+/// The value `p` (0 to 1) of the way through `sorted`, interpolating between
+/// neighbours.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let position = p * (sorted.len() - 1) as f64;
+    let (below, above) = (position.floor() as usize, position.ceil() as usize);
+    sorted[below] + (sorted[above] - sorted[below]) * (position - below as f64)
+}
+
+fn summarize(listings: &[&StandardizedProperty]) -> RentStats {
+    let priced: Vec<&StandardizedProperty> = listings.iter().copied().filter(|p| validate_price(p)).collect();
+    let mut rents: Vec<f64> = priced.iter().map(|p| p.price.amount).collect();
+    rents.sort_by(f64::total_cmp);
+    let mut per_sqm: Vec<f64> =
+        priced.iter().filter_map(|p| Some(p.price.amount / floor_area::usable_sqm(p)?)).collect();
+    per_sqm.sort_by(f64::total_cmp);
+
+    let at = |sorted: &[f64], p: f64| match sorted.is_empty() {
+        true => None,
+        false => sample::suppress(percentile(sorted, p), sorted.len()),
+    };
+    let mean = (!rents.is_empty()).then(|| rents.iter().sum::<f64>() / rents.len() as f64);
+    RentStats {
+        listings: listings.len(),
+        priced: rents.len(),
+        median_rent: at(&rents, 0.5),
+        mean_rent: mean.and_then(|mean| sample::suppress(mean, rents.len())),
+        percentiles: Percentiles {
+            p10: at(&rents, 0.1),
+            p25: at(&rents, 0.25),
+            p75: at(&rents, 0.75),
+            p90: at(&rents, 0.9),
+        },
+        sized: per_sqm.len(),
+        median_rent_per_sqm: at(&per_sqm, 0.5).map(|rent| (rent * 100.0).round() / 100.0),
+    }
+}
+
+/// Stats per `key`, the largest groups first.
+fn breakdown(listings: &[StandardizedProperty], key: impl Fn(&StandardizedProperty) -> String) -> Vec<GroupStats> {
+    let mut groups: BTreeMap<String, Vec<&StandardizedProperty>> = BTreeMap::new();
+    for property in listings {
+        groups.entry(key(property)).or_default().push(property);
+    }
+    let mut stats: Vec<GroupStats> =
+        groups.into_iter().map(|(key, listings)| GroupStats { key, stats: summarize(&listings) }).collect();
+    stats.sort_by(|a, b| b.stats.listings.cmp(&a.stats.listings).then_with(|| a.key.cmp(&b.key)));
+    stats
+}
+
+pub fn tally(listings: &[StandardizedProperty]) -> MarketStats {
+    MarketStats {
+        overall: summarize(&listings.iter().collect::<Vec<_>>()),
+        by_property_type: breakdown(listings, property_type_key),
+        by_bedrooms: breakdown(listings, |p| bedrooms_key(p).to_string()),
+        by_ber_rating: breakdown(listings, ber_band),
+        by_source: breakdown(listings, |p| p.source.clone()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatsParams {
+    source: Option<String>,
+    /// Area, locality or county the listings must be in, as the search
+    /// `location` filter takes it.
+    location: Option<String>,
+    #[serde(flatten)]
+    window: Window,
+}
+
+/// Stats over the latest snapshot of the requested source, or every source;
+/// over every listing in the snapshots of `window` when it is set. Blocking.
+pub fn compute(config: &Config, store: &dyn PropertyStore, params: &StatsParams) -> MarketStats {
+    let sources = config.source_names(params.source.as_deref());
+    let location = params.location.as_deref();
+    let within = |p: &StandardizedProperty| {
+        location.is_none_or(|location| address::in_location(&p.address.normalized_address, location))
+    };
+    let listings: Vec<StandardizedProperty> = match params.window.is_set() {
+        true => {
+            let seen = sources.into_iter().flat_map(|source| store.across(source, params.window.dates()));
+            seen.filter(within).collect()
+        }
+        false => store.query(&sources, &within),
+    };
+    tally(&listings)
+}
+
+pub async fn market_stats(
+    State(state): State<AppState>,
+    Query(params): Query<StatsParams>,
+) -> Result<Json<MarketStats>, (StatusCode, String)> {
+    params.window.validate()?;
+    let stats = tokio::task::spawn_blocking(move || compute(&state.config, state.store.as_ref(), &params))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::listing;
+    use crate::Size;
+
+    #[test]
+    fn test_stats_per_group() {
+        let rental = |id: usize, beds: i32, rent: f64, ber: Option<&str>| {
+            let mut property = listing(if id.is_multiple_of(2) { "daft" } else { "myhome" }, &id.to_string());
+            property.bedrooms = Some(beds);
+            property.property_type = "Apartment".to_string();
+            property.price.amount = rent;
+            (property.ber_rating, property.ber_status) = crate::ber::normalize(ber);
+            property.size = Some(Size { value: 50.0, unit: "square_meters".to_string() });
+            property
+        };
+        let listings = [
+            rental(1, 1, 1000.0, Some("B2")),
+            rental(2, 1, 1200.0, Some("B3")),
+            rental(3, 2, 1400.0, Some("C1")),
+            rental(4, 2, 1600.0, Some("Exempt")),
+            rental(5, 2, 1800.0, None),
+            rental(6, 2, 0.0, None),
+        ];
+        let stats = tally(&listings);
+
+        let overall = &stats.overall;
+        assert_eq!((overall.listings, overall.priced, overall.sized), (6, 5, 5));
+        assert_eq!((overall.median_rent, overall.mean_rent), (Some(1400.0), Some(1400.0)));
+        assert_eq!((overall.percentiles.p25, overall.percentiles.p90), (Some(1200.0), Some(1720.0)));
+        assert_eq!(overall.median_rent_per_sqm, Some(28.0));
+
+        let keys =
+            |groups: &[GroupStats]| groups.iter().map(|g| (g.key.clone(), g.stats.listings)).collect::<Vec<_>>();
+        assert_eq!(keys(&stats.by_bedrooms), [("2".to_string(), 4), ("1".to_string(), 2)]);
+        let ber = keys(&stats.by_ber_rating);
+        assert_eq!(ber, [("B", 2), ("unknown", 2), ("C", 1), ("exempt", 1)].map(|(k, n)| (k.to_string(), n)));
+        assert_eq!(stats.by_property_type[0].stats.median_rent, Some(1400.0));
+        assert_eq!(stats.by_source.len(), 2);
+    }
+}
@@ -0,0 +1,97 @@
+//! Value-for-money scores.
+//!
+//! Fits the rent index's hedonic model once over the latest listings of the
+//! searched sources, so every listing has a rent predicted from its bedrooms,
+//! type, area, BER and size. A listing's score is its asking rent over that
+//! prediction: 0.85 asks 15% less than comparable listings, 1.2 asks 20% more.
+//! `/api/rentals/search?sort=value` returns the lowest scores first, with the
+//! score on each listing as `value_score`.
+
+use std::cmp::Ordering;
+
+use super::hedonic::{FeatureSpace, MIN_PERIOD_LISTINGS, RIDGE_PENALTY};
+use super::regression::{fit_ridge, LinearFit};
+use crate::{validate_price, StandardizedProperty};
+
+pub struct ValueModel {
+    features: FeatureSpace,
+    fit: LinearFit,
+}
+
+impl ValueModel {
+    /// `None` with too few priced listings to fit.
+    pub fn fit(listings: &[StandardizedProperty]) -> Option<Self> {
+        let priced: Vec<&StandardizedProperty> = listings.iter().filter(|p| validate_price(p)).collect();
+        if priced.len() < MIN_PERIOD_LISTINGS {
+            return None;
+        }
+        let features = FeatureSpace::build(priced.iter().copied());
+        let rows: Vec<Vec<f64>> = priced.iter().map(|p| features.encode(p)).collect();
+        let targets: Vec<f64> = priced.iter().map(|p| p.price.amount.ln()).collect();
+        let fit = fit_ridge(&rows, &targets, RIDGE_PENALTY)?;
+        Some(ValueModel { features, fit })
+    }
+
+    /// Expected rent for a listing like `property`.
+    pub fn predict(&self, property: &StandardizedProperty) -> f64 {
+        self.fit.predict(&self.features.encode(property)).exp()
+    }
+
+    /// Asking rent over `predict`; `None` without a valid asking rent.
+    pub fn score(&self, property: &StandardizedProperty) -> Option<f64> {
+        validate_price(property).then(|| property.price.amount / self.predict(property))
+    }
+}
+
+/// Scores `properties` in place and returns their indices best value first.
+/// Listings without a score come last, as do all of them without a model.
+pub fn order(properties: &mut [StandardizedProperty], model: Option<&ValueModel>) -> Vec<usize> {
+    for property in properties.iter_mut() {
+        property.value_score = model.and_then(|model| model.score(property));
+    }
+    let mut order: Vec<usize> = (0..properties.len()).collect();
+    order.sort_by(|a, b| match (properties[*a].value_score, properties[*b].value_score) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    });
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::listing;
+
+    fn rental(id: usize, beds: i32, area: &str, rent: f64) -> StandardizedProperty {
+        let mut property = listing("daft", &id.to_string());
+        property.bedrooms = Some(beds);
+        property.property_type = "Apartment".to_string();
+        property.address.normalized_address = format!("{} main street, {}", id, area);
+        property.price.amount = rent;
+        property
+    }
+
+    #[test]
+    fn test_underpriced_listings_rank_first() {
+        let mut listings: Vec<_> = (0..20)
+            .map(|i| {
+                let (beds, area) = (1 + (i % 3) as i32, if i % 2 == 0 { "dublin 6" } else { "dublin 8" });
+                rental(i, beds, area, 1000.0 * 1.3f64.powi(beds) * if area == "dublin 6" { 1.2 } else { 1.0 })
+            })
+            .collect();
+        // A Dublin 6 three-bed at a Dublin 8 two-bed's rent
+        listings.push(rental(20, 3, "dublin 6", 1690.0));
+        let model = ValueModel::fit(&listings).unwrap();
+        assert!((model.predict(&listings[0]) - listings[0].price.amount).abs() < 50.0);
+
+        let best = order(&mut listings, Some(&model))[0];
+        assert_eq!(best, 20);
+        assert!(listings[best].value_score.unwrap() < 0.7);
+
+        assert!(ValueModel::fit(&listings[..5]).is_none());
+        assert_eq!(order(&mut listings[..3], None), [0, 1, 2]);
+        assert!(listings[..3].iter().all(|p| p.value_score.is_none()));
+    }
+}
@@ -0,0 +1,145 @@
+//! Append-only audit log of admin and data-modifying operations.
+//!
+//! Each entry records who did what, when, with which parameters and whether it
+//! succeeded. Entries are appended as JSON lines to `audit.path` and never
+//! rewritten; `/api/admin/audit` lists them newest first.
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::auth::{Caller, SCOPE_ADMIN};
+use crate::clock::Clock;
+use crate::state::AppState;
+
+const DEFAULT_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub at: DateTime<Utc>,
+    /// API key name, or "anonymous".
+    pub actor: String,
+    pub action: String,
+    pub parameters: serde_json::Value,
+    /// `None` on success, the error otherwise.
+    pub error: Option<String>,
+}
+
+pub struct AuditLog {
+    path: Option<PathBuf>,
+    entries: Mutex<Vec<AuditEntry>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl AuditLog {
+    /// Loads existing entries from `path`, and dates new ones by `clock`.
+    /// `None` keeps the log in memory only.
+    pub fn open(path: Option<PathBuf>, clock: Arc<dyn Clock>) -> Self {
+        let entries = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| contents.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+            .unwrap_or_default();
+        AuditLog { path, entries: Mutex::new(entries), clock }
+    }
+
+    /// Records an operation. Failing to persist the entry is logged loudly but
+    /// doesn't undo the operation it describes.
+    pub fn record<T, E: ToString>(
+        &self,
+        caller: &Caller,
+        action: &str,
+        parameters: impl Serialize,
+        outcome: &Result<T, E>,
+    ) {
+        let entry = AuditEntry {
+            at: self.clock.now(),
+            actor: caller.name.clone().unwrap_or_else(|| "anonymous".to_string()),
+            action: action.to_string(),
+            parameters: serde_json::to_value(parameters).unwrap_or_default(),
+            error: outcome.as_ref().err().map(|e| e.to_string()),
+        };
+        info!("Audit: {} by {} ({})", entry.action, entry.actor, if entry.error.is_none() { "ok" } else { "failed" });
+
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(path) = &self.path {
+            let appended = serde_json::to_string(&entry).map_err(|e| e.to_string()).and_then(|line| {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                }
+                let mut file = OpenOptions::new().create(true).append(true).open(path).map_err(|e| e.to_string())?;
+                writeln!(file, "{}", line).map_err(|e| e.to_string())
+            });
+            if let Err(e) = appended {
+                error!("Could not append audit entry to {:?}: {}", path, e);
+            }
+        }
+        entries.push(entry);
+    }
+
+    /// Matching entries, newest first.
+    pub fn query(&self, params: &AuditParams) -> Vec<AuditEntry> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .rev()
+            .filter(|e| params.action.as_deref().is_none_or(|action| e.action == action))
+            .filter(|e| params.actor.as_deref().is_none_or(|actor| e.actor == actor))
+            .filter(|e| params.since.is_none_or(|since| e.at >= since))
+            .take(params.limit.unwrap_or(DEFAULT_LIMIT))
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditParams {
+    action: Option<String>,
+    actor: Option<String>,
+    since: Option<DateTime<Utc>>,
+    limit: Option<usize>,
+}
+
+pub async fn list(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(params): Query<AuditParams>,
+) -> Result<Json<Vec<AuditEntry>>, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    Ok(Json(state.audit.query(&params)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::test_utils::temp_dir;
+    use serde_json::json;
+
+    #[test]
+    fn test_entries_are_appended_and_queried() {
+        let path = temp_dir("audit").join("audit.jsonl");
+        let admin = Caller::with_scopes(Some("ops".to_string()), &[SCOPE_ADMIN.to_string()]);
+        let log = AuditLog::open(Some(path.clone()), Arc::new(SystemClock));
+        log.record(&admin, "agents.suppress", json!({"email": "a@example.ie"}), &Ok::<(), String>(()));
+        log.record(&Caller::default(), "agents.suppress", json!({}), &Err::<(), _>("disk full"));
+
+        let reopened = AuditLog::open(Some(path.clone()), Arc::new(SystemClock));
+        let all = reopened.query(&AuditParams::default());
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].actor, "anonymous");
+        assert_eq!(all[0].error.as_deref(), Some("disk full"));
+
+        let by_ops = reopened.query(&AuditParams { actor: Some("ops".to_string()), ..Default::default() });
+        assert_eq!(by_ops.len(), 1);
+        assert_eq!(by_ops[0].parameters["email"], "a@example.ie");
+        assert_eq!(fs::read_to_string(path).unwrap().lines().count(), 2);
+    }
+}
@@ -0,0 +1,148 @@
+//! API keys and scopes.
+//!
+//! Callers identify themselves with `Authorization: Bearer <key>` or an
+//! `x-api-key` header. Each configured key carries a set of scopes; requests
+//! without a key get `auth.anonymous_scopes`. An unknown key is rejected rather
+//! than silently treated as anonymous.
+
+use axum::async_trait;
+use axum::extract::{FromRef, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, StatusCode};
+use serde::Deserialize;
+use std::collections::HashSet;
+
+use crate::config::AuthConfig;
+use crate::state::AppState;
+
+/// Receives agent phone numbers and email addresses.
+pub const SCOPE_AGENTS_READ: &str = "agents:read";
+/// Access to `/api/admin/*`.
+pub const SCOPE_ADMIN: &str = "admin";
+/// Receives raw source records with `include_raw`.
+pub const SCOPE_DEBUG: &str = "debug";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKey {
+    /// Shown in logs and audit records instead of the key itself.
+    pub name: String,
+    pub key: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+/// Who is making a request and what they may see.
+#[derive(Debug, Clone, Default)]
+pub struct Caller {
+    /// Name of the API key used, `None` for anonymous requests.
+    pub name: Option<String>,
+    scopes: HashSet<String>,
+}
+
+impl Caller {
+    pub fn with_scopes(name: Option<String>, scopes: &[String]) -> Self {
+        Caller { name, scopes: scopes.iter().cloned().collect() }
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.contains(scope)
+    }
+
+    /// Fails with 403 unless the caller holds `scope`.
+    pub fn require(&self, scope: &str) -> Result<(), (StatusCode, String)> {
+        if self.has_scope(scope) {
+            Ok(())
+        } else {
+            Err((StatusCode::FORBIDDEN, format!("Requires the {} scope", scope)))
+        }
+    }
+}
+
+/// Compares keys without exiting at the first differing byte.
+fn keys_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected.bytes().zip(given.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// The API key a request presents, if any.
+fn presented_key(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    bearer
+        .or_else(|| headers.get("x-api-key").and_then(|value| value.to_str().ok()))
+        .map(str::trim)
+}
+
+/// Whether a request presents a key listed in `auth.keys`.
+pub fn presents_known_key(auth: &AuthConfig, headers: &HeaderMap) -> bool {
+    presented_key(headers).is_some_and(|given| auth.keys.iter().any(|key| keys_match(&key.key, given)))
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Caller
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = AppState::from_ref(state);
+        let auth = &state.config.auth;
+        let Some(given) = presented_key(&parts.headers) else {
+            return Ok(Caller::with_scopes(None, &auth.anonymous_scopes));
+        };
+        auth.keys
+            .iter()
+            .find(|key| keys_match(&key.key, given))
+            .map(|key| Caller::with_scopes(Some(key.name.clone()), &key.scopes))
+            .ok_or((StatusCode::UNAUTHORIZED, "Unknown API key".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::test_utils::{stores_in, temp_dir};
+    use axum::http::Request;
+
+    #[tokio::test]
+    async fn test_caller_from_headers() {
+        let config = Config::from_toml(
+            r#"
+            [auth]
+            anonymous_scopes = []
+
+            [[auth.keys]]
+            name = "partner"
+            key = "s3cret"
+            scopes = ["agents:read"]
+            "#,
+        )
+        .unwrap();
+        let state = AppState::new(stores_in(config, &temp_dir("auth")));
+        let caller = |request: Request<()>| {
+            let state = state.clone();
+            async move {
+                let (mut parts, _) = request.into_parts();
+                Caller::from_request_parts(&mut parts, &state).await
+            }
+        };
+
+        let anonymous = caller(Request::new(())).await.unwrap();
+        assert!(anonymous.name.is_none());
+        assert!(anonymous.require(SCOPE_AGENTS_READ).is_err());
+
+        let request = Request::builder().header("Authorization", "Bearer s3cret").body(()).unwrap();
+        let partner = caller(request).await.unwrap();
+        assert_eq!(partner.name.as_deref(), Some("partner"));
+        assert!(partner.has_scope(SCOPE_AGENTS_READ));
+        assert!(!partner.has_scope(SCOPE_ADMIN));
+
+        let request = Request::builder().header("x-api-key", "wrong").body(()).unwrap();
+        assert_eq!(caller(request).await.unwrap_err().0, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,253 @@
+//! `bench`: load test against a running instance.
+//!
+//! Replays a corpus of requests (`bench/queries.txt` unless `--queries` names
+//! another file) at a fixed concurrency and reports p50/p95/p99 latency and
+//! error rate, overall and per query. With SLO flags set it exits non-zero
+//! when a threshold is missed, so it can gate a deploy.
+//!
+//! ```text
+//! main bench [--url http://127.0.0.1:3000] [--queries FILE] [--concurrency 8]
+//!            [--requests 500] [--slo-p95-ms N] [--slo-p99-ms N] [--max-error-rate 0.01]
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const DEFAULT_CORPUS: &str = include_str!("../bench/queries.txt");
+
+pub struct Options {
+    pub url: String,
+    pub queries: Option<String>,
+    pub concurrency: usize,
+    pub requests: usize,
+    pub slo_p95: Option<Duration>,
+    pub slo_p99: Option<Duration>,
+    pub max_error_rate: Option<f64>,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Options {
+            url: "http://127.0.0.1:3000".to_string(),
+            queries: None,
+            concurrency: 8,
+            requests: 500,
+            slo_p95: None,
+            slo_p99: None,
+            max_error_rate: None,
+        };
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or_else(|| format!("{} needs a value", flag))?;
+            let number = || value.parse::<u64>().map_err(|_| format!("Invalid {} {:?}", flag, value));
+            match flag.as_str() {
+                "--url" => options.url = value.trim_end_matches('/').to_string(),
+                "--queries" => options.queries = Some(value.clone()),
+                "--concurrency" => options.concurrency = (number()? as usize).max(1),
+                "--requests" => options.requests = number()? as usize,
+                "--slo-p95-ms" => options.slo_p95 = Some(Duration::from_millis(number()?)),
+                "--slo-p99-ms" => options.slo_p99 = Some(Duration::from_millis(number()?)),
+                "--max-error-rate" => {
+                    let rate = value.parse::<f64>().map_err(|_| format!("Invalid {} {:?}", flag, value))?;
+                    options.max_error_rate = Some(rate);
+                }
+                _ => {
+                    return Err(format!(
+                        "Unknown option {}. Options: --url, --queries, --concurrency, --requests, \
+                         --slo-p95-ms, --slo-p99-ms, --max-error-rate",
+                        flag
+                    ))
+                }
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn parse_corpus(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Latencies and failures for one query, or for the whole run.
+#[derive(Debug, Default, Clone)]
+pub struct Samples {
+    latencies: Vec<Duration>,
+    errors: usize,
+}
+
+impl Samples {
+    fn record(&mut self, latency: Duration, ok: bool) {
+        self.latencies.push(latency);
+        if !ok {
+            self.errors += 1;
+        }
+    }
+
+    fn merge(&mut self, other: &Samples) {
+        self.latencies.extend(&other.latencies);
+        self.errors += other.errors;
+    }
+
+    /// Nearest-rank percentile, `p` in `(0, 100]`.
+    fn percentile(&self, p: f64) -> Duration {
+        let mut sorted = self.latencies.clone();
+        sorted.sort_unstable();
+        if sorted.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+        sorted[rank.clamp(1, sorted.len()) - 1]
+    }
+
+    fn error_rate(&self) -> f64 {
+        if self.latencies.is_empty() {
+            0.0
+        } else {
+            self.errors as f64 / self.latencies.len() as f64
+        }
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn report_line(label: &str, samples: &Samples) -> String {
+    format!(
+        "{:>6} req  p50 {:>8.1} ms  p95 {:>8.1} ms  p99 {:>8.1} ms  errors {:>5.1}%  {}",
+        samples.latencies.len(),
+        millis(samples.percentile(50.0)),
+        millis(samples.percentile(95.0)),
+        millis(samples.percentile(99.0)),
+        samples.error_rate() * 100.0,
+        label
+    )
+}
+
+/// SLO thresholds the run missed, as human-readable reasons.
+fn slo_violations(options: &Options, overall: &Samples) -> Vec<String> {
+    let mut violations = Vec::new();
+    for (name, slo, actual) in [
+        ("p95", options.slo_p95, overall.percentile(95.0)),
+        ("p99", options.slo_p99, overall.percentile(99.0)),
+    ] {
+        if let Some(slo) = slo.filter(|slo| actual > *slo) {
+            violations.push(format!("{} {:.1} ms exceeds {} ms", name, millis(actual), slo.as_millis()));
+        }
+    }
+    if let Some(max) = options.max_error_rate.filter(|max| overall.error_rate() > *max) {
+        violations.push(format!("error rate {:.2}% exceeds {:.2}%", overall.error_rate() * 100.0, max * 100.0));
+    }
+    violations
+}
+
+/// Runs the load test. Returns whether every SLO was met.
+pub async fn run(args: &[String]) -> Result<bool, String> {
+    let options = Options::parse(args)?;
+    let corpus = match &options.queries {
+        Some(path) => fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?,
+        None => DEFAULT_CORPUS.to_string(),
+    };
+    let queries = Arc::new(parse_corpus(&corpus));
+    if queries.is_empty() {
+        return Err("Query corpus is empty".to_string());
+    }
+    println!(
+        "Sending {} requests from {} queries to {} at concurrency {}",
+        options.requests,
+        queries.len(),
+        options.url,
+        options.concurrency
+    );
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let next = Arc::new(AtomicUsize::new(0));
+    let results = Arc::new(Mutex::new(vec![Samples::default(); queries.len()]));
+    let started = Instant::now();
+    let workers: Vec<_> = (0..options.concurrency)
+        .map(|_| {
+            let (client, queries, next, results) = (client.clone(), queries.clone(), next.clone(), results.clone());
+            let (url, total) = (options.url.clone(), options.requests);
+            tokio::spawn(async move {
+                loop {
+                    let n = next.fetch_add(1, Ordering::Relaxed);
+                    if n >= total {
+                        break;
+                    }
+                    let query = n % queries.len();
+                    let sent = Instant::now();
+                    let ok = match client.get(format!("{}{}", url, queries[query])).send().await {
+                        // Read the body so the latency covers the whole response
+                        Ok(response) => response.status().is_success() && response.bytes().await.is_ok(),
+                        Err(_) => false,
+                    };
+                    results.lock().unwrap()[query].record(sent.elapsed(), ok);
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.await.map_err(|e| e.to_string())?;
+    }
+    let elapsed = started.elapsed();
+
+    let results = results.lock().unwrap();
+    let mut overall = Samples::default();
+    let mut by_query = BTreeMap::new();
+    for (query, samples) in queries.iter().zip(results.iter()) {
+        overall.merge(samples);
+        by_query.entry(query.as_str()).or_insert_with(Samples::default).merge(samples);
+    }
+    for (query, samples) in &by_query {
+        println!("{}", report_line(query, samples));
+    }
+    println!("{}", report_line("overall", &overall));
+    println!("{:.1} requests/s over {:.1} s", overall.latencies.len() as f64 / elapsed.as_secs_f64(), elapsed.as_secs_f64());
+
+    let violations = slo_violations(&options, &overall);
+    for violation in &violations {
+        println!("SLO missed: {}", violation);
+    }
+    Ok(violations.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles_and_slos() {
+        let mut samples = Samples::default();
+        for ms in 1..=100 {
+            samples.record(Duration::from_millis(ms), ms % 25 != 0);
+        }
+        assert_eq!(samples.percentile(50.0), Duration::from_millis(50));
+        assert_eq!(samples.percentile(99.0), Duration::from_millis(99));
+        assert_eq!(samples.error_rate(), 0.04);
+
+        let args: Vec<String> = ["--slo-p95-ms", "100", "--slo-p99-ms", "90", "--max-error-rate", "0.05"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let violations = slo_violations(&Options::parse(&args).unwrap(), &samples);
+        assert_eq!(violations, vec!["p99 99.0 ms exceeds 90 ms"]);
+    }
+
+    #[test]
+    fn test_default_corpus() {
+        let queries = parse_corpus(DEFAULT_CORPUS);
+        assert!(queries.iter().all(|q| q.starts_with('/')));
+        assert!(queries.iter().any(|q| q.contains("bedrooms=")));
+    }
+}
@@ -0,0 +1,89 @@
+//! Building Energy Rating states.
+//!
+//! Sources write BER as a rating ("B2", "ber b2"), as one of several ways of
+//! saying the building is exempt ("Exempt", "SI_666", "SI 666 Exempt"), or
+//! leave it blank or "N/A". Parsed listings carry the rating only when there
+//! is one, plus a `ber_status` of rated, exempt or unknown, so exempt
+//! buildings are no longer mistaken for missing data.
+//!
+//! The `ber_rating` search filter takes a comma-separated list: a band ("B")
+//! or exact rating ("B2") matches rated listings, and "exempt" and "unknown"
+//! select those states, so `ber_rating=A,B,unknown` keeps unrated listings.
+
+use serde::{Deserialize, Serialize};
+
+/// The ratings the SEAI scale has.
+pub const RATINGS: [&str; 15] = ["A1", "A2", "A3", "B1", "B2", "B3", "C1", "C2", "C3", "D1", "D2", "E1", "E2", "F", "G"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BerStatus {
+    Rated,
+    Exempt,
+    #[default]
+    Unknown,
+}
+
+/// The rating ("A1" to "G") and state of a raw BER value.
+pub fn normalize(raw: Option<&str>) -> (Option<String>, BerStatus) {
+    let compact: String = raw
+        .unwrap_or_default()
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .collect::<String>()
+        .to_uppercase();
+    if compact.contains("EXEMPT") || compact.contains("SI666") {
+        return (None, BerStatus::Exempt);
+    }
+    let rating = compact.strip_prefix("BER").unwrap_or(&compact);
+    match RATINGS.contains(&rating) {
+        true => (Some(rating.to_string()), BerStatus::Rated),
+        false => (None, BerStatus::Unknown),
+    }
+}
+
+/// Whether a listing passes a `ber_rating` filter.
+pub fn matches(filter: &str, rating: Option<&str>, status: BerStatus) -> bool {
+    filter.split(',').map(str::trim).filter(|term| !term.is_empty()).any(|term| {
+        match term.to_lowercase().as_str() {
+            "exempt" => status == BerStatus::Exempt,
+            "unknown" => status == BerStatus::Unknown,
+            _ => rating.is_some_and(|rating| rating.starts_with(&term.to_uppercase())),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let cases = [
+            (Some("B2"), Some("B2"), BerStatus::Rated),
+            (Some(" ber c1 "), Some("C1"), BerStatus::Rated),
+            (Some("G"), Some("G"), BerStatus::Rated),
+            (Some("D3"), None, BerStatus::Unknown),
+            (Some("Exempt"), None, BerStatus::Exempt),
+            (Some("SI_666"), None, BerStatus::Exempt),
+            (Some("SI 666 Exempt"), None, BerStatus::Exempt),
+            (Some(""), None, BerStatus::Unknown),
+            (Some("N/A"), None, BerStatus::Unknown),
+            (None, None, BerStatus::Unknown),
+        ];
+        for (raw, rating, status) in cases {
+            assert_eq!(normalize(raw), (rating.map(str::to_string), status), "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn test_filter_terms() {
+        assert!(matches("B", Some("B2"), BerStatus::Rated));
+        assert!(matches("b2", Some("B2"), BerStatus::Rated));
+        assert!(!matches("B1", Some("B2"), BerStatus::Rated));
+        assert!(!matches("A,B", None, BerStatus::Unknown));
+        assert!(matches("A,B,unknown", None, BerStatus::Unknown));
+        assert!(!matches("unknown", None, BerStatus::Exempt));
+        assert!(matches("exempt", None, BerStatus::Exempt));
+    }
+}
@@ -190,7 +190,7 @@ class PropertyAlertManager:
                     timeout=30
                 ) as response:
                     if response.status == 200:
-                        data = await response.json()
+                        data = (await response.json())["results"]
                         logger.info(f"Found {len(data)} properties")
                         return data
                     else:
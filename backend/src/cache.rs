@@ -0,0 +1,409 @@
+//! In-memory cache of parsed snapshots.
+//!
+//! Each source keeps at most one entry: its latest snapshot, fully parsed. The
+//! cache is bounded by a byte budget; when a new snapshot does not fit, the
+//! sources that were queried least recently are evicted first. Sizes are
+//! estimates of the parsed listings, not exact allocator figures.
+
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::config::SourceConfig;
+use crate::{parse_snapshot_parallel, scan_properties, scan_properties_from, ScanStats, StandardizedProperty};
+
+/// Parsed listings with the row offset each was read from.
+type Rows = Vec<(usize, StandardizedProperty)>;
+
+struct CachedSnapshot {
+    file: PathBuf,
+    properties: Arc<Rows>,
+    bytes: usize,
+    /// Value of the cache clock the last time the source was queried.
+    last_queried: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<String, CachedSnapshot>,
+    used_bytes: usize,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+/// Memory used by one cached source.
+#[derive(Debug, Clone)]
+pub struct SourceUsage {
+    pub source: String,
+    pub listings: usize,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheUsage {
+    pub budget_bytes: usize,
+    pub used_bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub sources: Vec<SourceUsage>,
+}
+
+pub struct SnapshotCache {
+    budget_bytes: usize,
+    state: Mutex<CacheState>,
+    /// Parses snapshots on a miss; `None` parses on the calling thread.
+    parse_pool: Option<rayon::ThreadPool>,
+}
+
+impl SnapshotCache {
+    /// A cache holding at most `budget_bytes` of parsed listings. A budget of zero
+    /// disables caching and every query streams from disk.
+    pub fn new(budget_bytes: usize) -> Self {
+        SnapshotCache {
+            budget_bytes,
+            state: Mutex::new(CacheState::default()),
+            parse_pool: None,
+        }
+    }
+
+    /// Parses snapshots on `threads` threads when filling the cache; zero
+    /// uses one per core, one keeps parsing on the calling thread.
+    pub fn with_parse_threads(self, threads: usize) -> Self {
+        if threads == 1 {
+            return self;
+        }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("snapshot-parse-{}", i))
+            .build();
+        match pool {
+            Ok(pool) => SnapshotCache { parse_pool: Some(pool), ..self },
+            Err(e) => {
+                warn!("Could not start snapshot parse threads, parsing serially: {}", e);
+                self
+            }
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.budget_bytes > 0
+    }
+
+    /// Whether `path` is the snapshot cached for `source`. Doesn't count as a query.
+    pub fn contains(&self, source: &str, path: &Path) -> bool {
+        let state = self.state.lock().unwrap();
+        state.entries.get(source).is_some_and(|entry| entry.file == path)
+    }
+
+    /// The snapshot cached for `source`, if any. Doesn't count as a query.
+    pub fn cached_file(&self, source: &str) -> Option<PathBuf> {
+        self.state.lock().unwrap().entries.get(source).map(|entry| entry.file.clone())
+    }
+
+    /// The cached listings for `path`, if that is the snapshot cached for the source.
+    fn get(&self, source: &str, path: &Path) -> Option<Arc<Rows>> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        match state.entries.get_mut(source) {
+            Some(entry) if entry.file == path => {
+                entry.last_queried = clock;
+                let properties = entry.properties.clone();
+                state.hits += 1;
+                Some(properties)
+            }
+            _ => {
+                state.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Stores a freshly parsed snapshot, evicting least recently queried sources
+    /// until it fits. Snapshots larger than the whole budget are not cached.
+    fn insert(&self, source: &str, path: &Path, properties: Arc<Rows>) {
+        let bytes = estimated_bytes(&properties);
+        if bytes > self.budget_bytes {
+            warn!(
+                "Snapshot {:?} needs ~{} bytes, over the {} byte cache budget; not caching {}",
+                path, bytes, self.budget_bytes, source
+            );
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(previous) = state.entries.remove(source) {
+            state.used_bytes -= previous.bytes;
+        }
+        while state.used_bytes + bytes > self.budget_bytes {
+            let Some(victim) = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_queried)
+                .map(|(name, _)| name.clone())
+            else {
+                break;
+            };
+            let evicted = state.entries.remove(&victim).unwrap();
+            state.used_bytes -= evicted.bytes;
+            state.evictions += 1;
+            info!("Evicted {} from the snapshot cache, freeing ~{} bytes", victim, evicted.bytes);
+        }
+
+        let clock = state.clock;
+        state.used_bytes += bytes;
+        state.entries.insert(
+            source.to_string(),
+            CachedSnapshot {
+                file: path.to_path_buf(),
+                properties,
+                bytes,
+                last_queried: clock,
+            },
+        );
+        debug!("Cached {} ({:?}), ~{} of {} bytes used", source, path, state.used_bytes, self.budget_bytes);
+    }
+
+    /// Drops `source`'s cached snapshot, so the next query parses it again.
+    pub fn evict(&self, source: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(evicted) = state.entries.remove(source) {
+            state.used_bytes -= evicted.bytes;
+            info!("Dropped {} from the snapshot cache", source);
+        }
+    }
+
+    /// Visits the listings of a snapshot from row `start` on, like
+    /// `scan_properties_from`, serving them from memory when cached. A miss parses
+    /// the whole file so it can be cached; with caching disabled the file is
+    /// streamed and the visitor can stop early.
+    pub fn scan(
+        &self,
+        source: &SourceConfig,
+        path: &Path,
+        start: usize,
+        mut visit: impl FnMut(usize, StandardizedProperty) -> ControlFlow<()>,
+    ) -> ScanStats {
+        if !self.enabled() {
+            return scan_properties_from(source, path, start, visit);
+        }
+
+        let (properties, errors) = match self.get(&source.name, path) {
+            Some(properties) => (properties, 0),
+            None => self.load(source, path),
+        };
+
+        let remaining = &properties[properties.partition_point(|(row, _)| *row < start)..];
+        let mut stats = ScanStats { total_rows: remaining.len(), errors, ..Default::default() };
+        for (row, property) in remaining {
+            stats.rows_scanned += 1;
+            if visit(*row, property.clone()).is_break() {
+                break;
+            }
+        }
+        stats
+    }
+
+    /// Parses `path` and caches it as `source`'s snapshot, replacing whatever
+    /// is cached for it once parsed, so queries in the meantime are still
+    /// served from memory. Returns the listings and the rows that failed.
+    pub fn reload(&self, source: &SourceConfig, path: &Path) -> (usize, usize) {
+        let (properties, errors) = self.load(source, path);
+        (properties.len(), errors)
+    }
+
+    fn load(&self, source: &SourceConfig, path: &Path) -> (Arc<Rows>, usize) {
+        let started = Instant::now();
+        let (rows, errors) = match &self.parse_pool {
+            Some(pool) => {
+                let (rows, stats) = parse_snapshot_parallel(source, path, pool);
+                (rows, stats.errors)
+            }
+            None => {
+                let mut rows = Vec::new();
+                let errors = scan_properties(source, path, |row, property| {
+                    rows.push((row, property));
+                    ControlFlow::Continue(())
+                })
+                .errors;
+                (rows, errors)
+            }
+        };
+        debug!("Parsed {} listings of {:?} in {:?}", rows.len(), path, started.elapsed());
+        let properties = Arc::new(rows);
+        // A file that failed to read is read again, and reported again, next time
+        if errors == 0 && self.enabled() {
+            self.insert(&source.name, path, properties.clone());
+        }
+        (properties, errors)
+    }
+
+    pub fn usage(&self) -> CacheUsage {
+        let state = self.state.lock().unwrap();
+        let mut sources: Vec<SourceUsage> = state
+            .entries
+            .iter()
+            .map(|(name, entry)| SourceUsage {
+                source: name.clone(),
+                listings: entry.properties.len(),
+                bytes: entry.bytes,
+            })
+            .collect();
+        sources.sort_by(|a, b| a.source.cmp(&b.source));
+        CacheUsage {
+            budget_bytes: self.budget_bytes,
+            used_bytes: state.used_bytes,
+            hits: state.hits,
+            misses: state.misses,
+            evictions: state.evictions,
+            sources,
+        }
+    }
+}
+
+/// Rough heap plus inline size of parsed listings.
+fn estimated_bytes(properties: &Rows) -> usize {
+    let strings = |values: &[&String]| values.iter().map(|s| s.capacity()).sum::<usize>();
+    properties
+        .iter()
+        .map(|(_, p)| {
+            let mut bytes = size_of::<(usize, StandardizedProperty)>()
+                + strings(&[
+                    &p.property_id,
+                    &p.source,
+                    &p.source_id,
+                    &p.address.display_address,
+                    &p.property_type,
+                    &p.price.currency,
+                    &p.created_date,
+                    &p.updated_date,
+                    &p.listing_type,
+                    &p.status,
+                ]);
+            bytes += p.ber_rating.as_ref().map_or(0, String::capacity);
+            bytes += p.seo_url.as_ref().map_or(0, String::capacity);
+            bytes += p.price.frequency.as_ref().map_or(0, String::capacity);
+            bytes += p.size.as_ref().map_or(0, |s| s.unit.capacity());
+            bytes += p.photos.iter().map(|photo| size_of_val(photo) + photo.url.capacity()).sum::<usize>();
+            bytes += p
+                .price
+                .price_changes
+                .iter()
+                .map(|change| size_of_val(change) + change.date.capacity() + change.direction.capacity())
+                .sum::<usize>();
+            if let Some(agent) = &p.agent {
+                bytes += strings(&[&agent.name, &agent.phone, &agent.email, &agent.address]);
+            }
+            bytes
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::load_properties;
+    use crate::test_utils::{standardized_batch, temp_dir, write_parquet};
+
+    #[test]
+    fn test_evicts_least_recently_queried_source() {
+        let dir = temp_dir("cache_eviction");
+        let config = Config::from_toml(
+            "[[sources]]\nname = \"a\"\n[[sources]]\nname = \"b\"\n[[sources]]\nname = \"c\"",
+        )
+        .unwrap();
+        let files: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .map(|name| {
+                let path = dir.join(format!("{}.parquet", name));
+                write_parquet(&path, &standardized_batch(&["1", "2", "3"]));
+                path
+            })
+            .collect();
+        let sources: Vec<_> = ["a", "b", "c"].iter().map(|name| config.resolve_source(name).unwrap()).collect();
+
+        // Room for exactly two snapshots
+        let rows = load_properties(sources[0], &files[0]).into_iter().enumerate().collect();
+        let one = estimated_bytes(&rows);
+        let cache = SnapshotCache::new(one * 2 + one / 2);
+        let count = |cache: &SnapshotCache, i: usize| {
+            let mut seen = 0;
+            cache.scan(sources[i], &files[i], 0, |_, _| {
+                seen += 1;
+                ControlFlow::Continue(())
+            });
+            seen
+        };
+
+        assert_eq!(count(&cache, 0), 3);
+        assert_eq!(count(&cache, 1), 3);
+        assert_eq!(count(&cache, 0), 3); // a is now more recent than b
+        assert_eq!(count(&cache, 2), 3);
+
+        let usage = cache.usage();
+        let cached: Vec<_> = usage.sources.iter().map(|s| s.source.as_str()).collect();
+        assert_eq!(cached, vec!["a", "c"]);
+        assert_eq!(usage.evictions, 1);
+        assert_eq!((usage.hits, usage.misses), (1, 3));
+        assert!(usage.used_bytes <= usage.budget_bytes);
+    }
+
+    #[test]
+    fn test_oversized_snapshot_is_not_cached() {
+        let path = temp_dir("cache_oversized").join("a.parquet");
+        write_parquet(&path, &standardized_batch(&["1", "2"]));
+        let config = Config::from_toml("[[sources]]\nname = \"a\"").unwrap();
+        let cache = SnapshotCache::new(16);
+
+        let stats = cache.scan(config.resolve_source("a").unwrap(), &path, 0, |_, _| ControlFlow::Continue(()));
+        assert_eq!(stats.rows_scanned, 2);
+        assert!(cache.usage().sources.is_empty());
+    }
+
+    #[test]
+    fn test_scan_resumes_from_row() {
+        let path = temp_dir("cache_resume").join("a.parquet");
+        write_parquet(&path, &standardized_batch(&["1", "2", "3", "4"]));
+        let config = Config::from_toml("[[sources]]\nname = \"a\"").unwrap();
+        let source = config.resolve_source("a").unwrap();
+
+        for cache in [SnapshotCache::new(1 << 20), SnapshotCache::new(0)] {
+            let mut rows = Vec::new();
+            cache.scan(source, &path, 2, |row, property| {
+                rows.push((row, property.source_id));
+                ControlFlow::Continue(())
+            });
+            assert_eq!(rows, vec![(2, "3".to_string()), (3, "4".to_string())]);
+        }
+    }
+
+    #[test]
+    fn test_parallel_parse_matches_serial() {
+        // Several record batches, with a listing re-crawled at the end
+        let ids: Vec<String> = (0..2500).map(|i| i.to_string()).chain(["7".to_string()]).collect();
+        let path = temp_dir("cache_parallel").join("a.parquet");
+        write_parquet(&path, &standardized_batch(&ids.iter().map(String::as_str).collect::<Vec<_>>()));
+        let config = Config::from_toml("[[sources]]\nname = \"a\"").unwrap();
+        let source = config.resolve_source("a").unwrap();
+
+        let rows = |cache: SnapshotCache| {
+            let mut rows = Vec::new();
+            cache.scan(source, &path, 0, |row, property| {
+                rows.push((row, property.source_id));
+                ControlFlow::Continue(())
+            });
+            rows
+        };
+        let serial = rows(SnapshotCache::new(1 << 24).with_parse_threads(1));
+        assert_eq!(serial.len(), 2500);
+        assert_eq!(rows(SnapshotCache::new(1 << 24).with_parse_threads(4)), serial);
+    }
+}
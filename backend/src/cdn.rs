@@ -0,0 +1,175 @@
+//! Cache hints for a CDN in front of the API.
+//!
+//! With `cdn.enabled`, search responses to anonymous callers may be kept by
+//! shared caches for `cdn.max_age_secs` (browsers still revalidate) and are
+//! tagged with the snapshots they were served from, as `Surrogate-Key`
+//! (space separated) and `Cache-Tag` (comma separated): `search`,
+//! `source:<name>` and `snapshot:<name>:<date>` for each source searched.
+//! Callers with an API key get `private` responses, since they may be
+//! redacted or annotated for them.
+//!
+//! When a newer snapshot becomes a source's latest, the key of the one it
+//! replaces is purged: every `cdn.check_interval_secs` the latest snapshots
+//! are compared with the last check, and a `cdn.purge` job POSTs
+//! `{"surrogate_keys": [...]}` to `cdn.purge_url`. Snapshots that land while
+//! the service is down are not purged; `max_age_secs` bounds how long their
+//! results stay cached. Searches answered by the CDN are not counted in
+//! search analytics or history.
+
+use axum::http::header::{CACHE_CONTROL, VARY};
+use axum::http::{HeaderMap, HeaderValue};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::auth::Caller;
+use crate::config::CdnConfig;
+use crate::state::AppState;
+use crate::{find_latest_parquet, notifier, snapshot_date};
+
+/// Job kind that purges the keys of replaced snapshots.
+pub const JOB: &str = "cdn.purge";
+
+/// The key of the snapshot of `source` at `path`: its date, or its file name
+/// when it isn't in a dated directory.
+pub fn snapshot_key(source: &str, path: &Path) -> String {
+    let name = match snapshot_date(path) {
+        Some(date) => date.to_string(),
+        None => path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default(),
+    };
+    format!("snapshot:{}:{}", source, name)
+}
+
+/// Keys of a search over the snapshots in `files`, by source.
+pub fn search_keys(files: &HashMap<String, PathBuf>) -> Vec<String> {
+    let mut sources: Vec<(&String, &PathBuf)> = files.iter().collect();
+    sources.sort();
+    let mut keys = vec!["search".to_string()];
+    for (source, path) in sources {
+        keys.push(format!("source:{}", source));
+        keys.push(snapshot_key(source, path));
+    }
+    keys
+}
+
+/// Adds the caching headers for a search by `caller` over `files`.
+pub fn tag(headers: &mut HeaderMap, config: &CdnConfig, caller: &Caller, files: &HashMap<String, PathBuf>) {
+    if !config.enabled {
+        return;
+    }
+    headers.insert(VARY, HeaderValue::from_static("authorization, x-api-key, accept-language"));
+    if caller.name.is_some() {
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("private, no-store"));
+        return;
+    }
+    let control = format!("public, max-age=0, s-maxage={}", config.max_age_secs);
+    if let Ok(value) = HeaderValue::from_str(&control) {
+        headers.insert(CACHE_CONTROL, value);
+    }
+    let keys = search_keys(files);
+    if let Ok(value) = HeaderValue::from_str(&keys.join(" ")) {
+        headers.insert("surrogate-key", value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&keys.join(",")) {
+        headers.insert("cache-tag", value);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Purge {
+    surrogate_keys: Vec<String>,
+}
+
+/// Keys of the snapshots in `previous` that `latest` has replaced.
+fn replaced(previous: &HashMap<String, PathBuf>, latest: &HashMap<String, PathBuf>) -> Vec<String> {
+    let mut keys: Vec<String> = previous
+        .iter()
+        .filter(|(source, path)| latest.get(*source).is_some_and(|newer| newer != *path))
+        .map(|(source, path)| snapshot_key(source, path))
+        .collect();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+fn latest_snapshots(state: &AppState) -> HashMap<String, PathBuf> {
+    let config = &state.config;
+    config
+        .sources
+        .iter()
+        .filter_map(|source| Some((source.name.clone(), find_latest_parquet(&source.root(&config.data_path))?)))
+        .collect()
+}
+
+pub fn run_job(state: &AppState, payload: &serde_json::Value) -> Result<(), String> {
+    let purge: Purge = serde_json::from_value(payload.clone()).map_err(|e| e.to_string())?;
+    let url = state.config.cdn.purge_url.as_deref().ok_or("No cdn.purge_url configured")?;
+    notifier::post_json(url, &purge)?;
+    info!("Purged {} from the CDN", purge.surrogate_keys.join(", "));
+    Ok(())
+}
+
+/// Checks for new snapshots every `cdn.check_interval_secs` for the life of
+/// the process, queueing a purge of the ones they replace.
+pub fn watch(state: &AppState) {
+    if !state.config.cdn.enabled || state.config.cdn.purge_url.is_none() {
+        return;
+    }
+    let state = state.clone();
+    let period = Duration::from_secs(state.config.cdn.check_interval_secs.max(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        let mut previous: Option<HashMap<String, PathBuf>> = None;
+        loop {
+            interval.tick().await;
+            let scan = state.clone();
+            let Ok(latest) = tokio::task::spawn_blocking(move || latest_snapshots(&scan)).await else {
+                continue;
+            };
+            let keys = previous.as_ref().map(|previous| replaced(previous, &latest)).unwrap_or_default();
+            if !keys.is_empty() {
+                if let Err(e) = state.jobs.enqueue(JOB, Purge { surrogate_keys: keys }) {
+                    error!("Could not queue CDN purge: {}", e);
+                }
+            }
+            previous = Some(latest);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_responses_are_tagged_by_snapshot() {
+        let files = |daft: &str| {
+            HashMap::from([
+                ("daft".to_string(), PathBuf::from(daft)),
+                ("myhome".to_string(), PathBuf::from("data/myhome/2024/11/05/listings.parquet")),
+            ])
+        };
+        let before = files("data/daft/2024/11/05/listings.parquet");
+        let config = CdnConfig { enabled: true, ..CdnConfig::default() };
+
+        let mut headers = HeaderMap::new();
+        tag(&mut headers, &config, &Caller::with_scopes(None, &[]), &before);
+        assert_eq!(
+            headers["surrogate-key"],
+            "search source:daft snapshot:daft:2024-11-05 source:myhome snapshot:myhome:2024-11-05"
+        );
+        assert_eq!(headers["cache-tag"].to_str().unwrap().split(',').count(), 5);
+        assert_eq!(headers[CACHE_CONTROL], "public, max-age=0, s-maxage=300");
+
+        let mut headers = HeaderMap::new();
+        tag(&mut headers, &config, &Caller::with_scopes(Some("partner".to_string()), &[]), &before);
+        assert_eq!(headers[CACHE_CONTROL], "private, no-store");
+        assert!(!headers.contains_key("surrogate-key"));
+
+        let after = files("data/daft/2024/11/06/listings.parquet");
+        assert_eq!(replaced(&before, &after), vec!["snapshot:daft:2024-11-05"]);
+        assert!(replaced(&after, &after).is_empty());
+    }
+}
@@ -0,0 +1,332 @@
+//! Chart-ready series for embedding.
+//!
+//! Responses have the `{ labels, datasets: [{ label, data }] }` shape Chart.js
+//! takes as `data` and ECharts maps onto `xAxis.data` and `series`, already
+//! aggregated and binned, so frontends only draw. Labels follow the request
+//! language; the machine keys behind them are returned as `keys`.
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::analytics::hedonic::{ber_band, median, Granularity};
+use crate::analytics::sample;
+use crate::analytics::smoothing::Smoothing;
+use crate::locale::Lang;
+use crate::reports::market::{self, bedrooms_key, property_type_key, ReportFilter};
+use crate::reports::template::category_label;
+use crate::state::AppState;
+use crate::{address, price_band, StandardizedProperty};
+
+const DEFAULT_TREND_PERIODS: usize = 12;
+const DEFAULT_SUPPLY_CATEGORIES: usize = 10;
+
+#[derive(Debug, Serialize)]
+pub struct Dataset {
+    pub label: String,
+    pub key: String,
+    /// One value per label; `null` where the group has fewer listings than
+    /// `stats.min_sample`.
+    pub data: Vec<Option<f64>>,
+    /// Listings behind each value of `data`, for medians.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub listings: Vec<usize>,
+    /// `data` before smoothing, with `raw=true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<Vec<Option<f64>>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChartData {
+    pub labels: Vec<String>,
+    /// Machine keys of `labels`: period keys or category values.
+    pub keys: Vec<String>,
+    pub datasets: Vec<Dataset>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dimension {
+    #[default]
+    PropertyType,
+    Bedrooms,
+    Source,
+    Area,
+    Ber,
+    PriceBand,
+}
+
+impl Dimension {
+    fn key(self, property: &StandardizedProperty) -> String {
+        match self {
+            Dimension::PropertyType => property_type_key(property),
+            Dimension::Bedrooms => bedrooms_key(property).to_string(),
+            Dimension::Source => property.source.clone(),
+            Dimension::Area => {
+                let area = address::area(&property.address.normalized_address);
+                if area.is_empty() { "unknown".to_string() } else { area.to_string() }
+            }
+            Dimension::Ber => ber_band(property),
+            Dimension::PriceBand => property.price_band.clone(),
+        }
+    }
+
+    fn label(self, lang: Lang, key: &str) -> String {
+        match self {
+            Dimension::Area if key != "unknown" => lang.area(key),
+            Dimension::Source | Dimension::Ber | Dimension::PriceBand if key != "unknown" && key != "exempt" => key.to_string(),
+            _ => category_label(lang, key),
+        }
+    }
+}
+
+fn median_rent_label(lang: Lang) -> &'static str {
+    match lang {
+        Lang::En => "Median rent",
+        Lang::Ga => "Cíos airmheánach",
+    }
+}
+
+fn listings_label(lang: Lang) -> &'static str {
+    match lang {
+        Lang::En => "Listings",
+        Lang::Ga => "Fógraí",
+    }
+}
+
+fn other_label(lang: Lang) -> &'static str {
+    match lang {
+        Lang::En => "Other",
+        Lang::Ga => "Eile",
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TrendParams {
+    area: Option<String>,
+    bedrooms: Option<i32>,
+    source: Option<String>,
+    period: Option<Granularity>,
+    /// Most recent periods to include.
+    periods: Option<usize>,
+    /// Splits the series into one dataset per group.
+    group_by: Option<Dimension>,
+    /// See `analytics::smoothing`.
+    smoothing: Option<Smoothing>,
+    /// With `smoothing`, also return each dataset's unsmoothed values.
+    #[serde(default)]
+    raw: bool,
+}
+
+/// Median rent per period, overall or per group.
+pub fn price_trend_data(
+    periods: &BTreeMap<String, Vec<StandardizedProperty>>,
+    granularity: Granularity,
+    group_by: Option<Dimension>,
+    lang: Lang,
+) -> ChartData {
+    let keys: Vec<String> = periods.keys().cloned().collect();
+    let labels = keys.iter().map(|period| lang.period(granularity, period)).collect();
+    let median_of = |listings: &[&StandardizedProperty]| {
+        let rent = (!listings.is_empty()).then(|| median(listings.iter().map(|p| p.price.amount).collect()));
+        rent.and_then(|rent| sample::suppress(rent, listings.len()))
+    };
+
+    let datasets = match group_by {
+        None => vec![Dataset {
+            label: median_rent_label(lang).to_string(),
+            key: "median_rent".to_string(),
+            data: periods.values().map(|listings| median_of(&listings.iter().collect::<Vec<_>>())).collect(),
+            listings: periods.values().map(Vec::len).collect(),
+            raw: None,
+        }],
+        Some(dimension) => {
+            let groups: BTreeSet<String> = periods.values().flatten().map(|p| dimension.key(p)).collect();
+            groups
+                .into_iter()
+                .map(|group| {
+                    let members: Vec<Vec<&StandardizedProperty>> = periods
+                        .values()
+                        .map(|listings| listings.iter().filter(|p| dimension.key(p) == group).collect())
+                        .collect();
+                    Dataset {
+                        label: dimension.label(lang, &group),
+                        data: members.iter().map(|listings| median_of(listings)).collect(),
+                        listings: members.iter().map(Vec::len).collect(),
+                        raw: None,
+                        key: group,
+                    }
+                })
+                .collect()
+        }
+    };
+    ChartData { labels, keys, datasets }
+}
+
+/// Deduplicated listings per period for `filter`, the most recent `limit`,
+/// read on the blocking pool.
+async fn load_periods(
+    state: &AppState,
+    filter: ReportFilter,
+    granularity: Granularity,
+    limit: usize,
+) -> Result<BTreeMap<String, Vec<StandardizedProperty>>, (StatusCode, String)> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let periods = market::by_period(market::load(&state.config, state.store.as_ref(), &filter, granularity));
+        let skip = periods.len().saturating_sub(limit);
+        periods
+            .into_iter()
+            .skip(skip)
+            .map(|(period, listings)| (period, listings.into_values().collect()))
+            .collect()
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+pub async fn price_trend(
+    State(state): State<AppState>,
+    lang: Lang,
+    Query(params): Query<TrendParams>,
+) -> Result<Json<ChartData>, (StatusCode, String)> {
+    let granularity = params.period.unwrap_or_default();
+    let filter = ReportFilter { area: params.area, bedrooms: params.bedrooms, source: params.source, polygon: None };
+    let periods = load_periods(&state, filter, granularity, params.periods.unwrap_or(DEFAULT_TREND_PERIODS)).await?;
+    let mut chart = price_trend_data(&periods, granularity, params.group_by, lang);
+    if let Some(smoothing) = params.smoothing {
+        for dataset in &mut chart.datasets {
+            let smoothed = smoothing.apply(granularity, &dataset.data);
+            let raw = std::mem::replace(&mut dataset.data, smoothed);
+            dataset.raw = params.raw.then_some(raw);
+        }
+    }
+    Ok(Json(chart))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SupplyParams {
+    area: Option<String>,
+    bedrooms: Option<i32>,
+    source: Option<String>,
+    /// Period key ("2024-11", "2024-Q4"); the latest month by default.
+    period: Option<String>,
+    by: Option<Dimension>,
+    /// Categories shown before the rest are folded into "Other".
+    top: Option<usize>,
+}
+
+/// Listing counts per category, largest first, the tail folded into "Other".
+pub fn supply_data(
+    listings: &[StandardizedProperty],
+    by: Dimension,
+    top: usize,
+    price_bands: &[f64],
+    lang: Lang,
+) -> ChartData {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for property in listings {
+        *counts.entry(by.key(property)).or_insert(0) += 1;
+    }
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    if counts.len() > top.max(1) {
+        let other: usize = counts.drain(top.max(1)..).map(|(_, n)| n).sum();
+        counts.push(("other".to_string(), other));
+    }
+    // Bands read cheapest first rather than by size
+    if by == Dimension::PriceBand {
+        let bands = price_band::bands(price_bands);
+        counts.sort_by_key(|(key, _)| bands.iter().position(|band| &band.key == key).unwrap_or(usize::MAX));
+    }
+
+    ChartData {
+        labels: counts
+            .iter()
+            .map(|(key, _)| if key == "other" { other_label(lang).to_string() } else { by.label(lang, key) })
+            .collect(),
+        keys: counts.iter().map(|(key, _)| key.clone()).collect(),
+        datasets: vec![Dataset {
+            label: listings_label(lang).to_string(),
+            key: "listings".to_string(),
+            data: counts.iter().map(|(_, n)| Some(*n as f64)).collect(),
+            listings: vec![],
+            raw: None,
+        }],
+    }
+}
+
+pub async fn supply(
+    State(state): State<AppState>,
+    lang: Lang,
+    Query(params): Query<SupplyParams>,
+) -> Result<Json<ChartData>, (StatusCode, String)> {
+    let granularity = match &params.period {
+        Some(period) => market::period_granularity(period)
+            .ok_or((StatusCode::BAD_REQUEST, format!("Invalid period {:?}", period)))?,
+        None => Granularity::Month,
+    };
+    let filter = ReportFilter { area: params.area, bedrooms: params.bedrooms, source: params.source, polygon: None };
+    let mut periods = load_periods(&state, filter, granularity, usize::MAX).await?;
+    let listings = match &params.period {
+        Some(period) => periods.remove(period).unwrap_or_default(),
+        None => periods.pop_last().map(|(_, listings)| listings).unwrap_or_default(),
+    };
+    let by = params.by.unwrap_or_default();
+    let top = params.top.unwrap_or(DEFAULT_SUPPLY_CATEGORIES);
+    Ok(Json(supply_data(&listings, by, top, &state.config.search.price_bands, lang)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::listing;
+
+    fn rental(id: &str, beds: i32, rent: f64) -> StandardizedProperty {
+        let mut property = listing("daft", id);
+        property.bedrooms = Some(beds);
+        property.property_type = "Apartment".to_string();
+        property.price.amount = rent;
+        property.price_band = price_band::label(&price_band::DEFAULT_BOUNDS, rent);
+        property
+    }
+
+    #[test]
+    fn test_price_trend_groups() {
+        let mut periods = BTreeMap::new();
+        periods.insert("2024-10".to_string(), vec![rental("1", 1, 1500.0), rental("2", 2, 2000.0)]);
+        periods.insert("2024-11".to_string(), vec![rental("3", 2, 2100.0), rental("4", 2, 2300.0)]);
+
+        let chart = price_trend_data(&periods, Granularity::Month, None, Lang::En);
+        assert_eq!(chart.labels, ["October 2024", "November 2024"]);
+        assert_eq!(chart.datasets[0].data, [Some(1750.0), Some(2200.0)]);
+
+        let chart = price_trend_data(&periods, Granularity::Month, Some(Dimension::Bedrooms), Lang::En);
+        assert_eq!(chart.datasets.iter().map(|d| d.key.as_str()).collect::<Vec<_>>(), ["1", "2"]);
+        assert_eq!(chart.datasets[0].data, [Some(1500.0), None]);
+        assert_eq!(chart.datasets[1].data, [Some(2000.0), Some(2200.0)]);
+        assert_eq!(chart.datasets[1].listings, [1, 2]);
+    }
+
+    #[test]
+    fn test_supply_folds_tail() {
+        let mut listings: Vec<_> = (0..6).map(|i| rental(&i.to_string(), i % 3, 1500.0)).collect();
+        listings.push(rental("9", 0, 1500.0));
+
+        let chart = supply_data(&listings, Dimension::Bedrooms, 2, &[], Lang::Ga);
+        assert_eq!(chart.keys, ["studio", "1", "other"]);
+        assert_eq!(chart.labels, ["Stiúideo", "1", "Eile"]);
+        assert_eq!(chart.datasets[0].data, [Some(3.0), Some(2.0), Some(2.0)]);
+    }
+
+    #[test]
+    fn test_supply_by_price_band_in_band_order() {
+        let listings: Vec<_> = [3100.0, 900.0, 1200.0, 1250.0, 3500.0, 3900.0].iter().map(|rent| rental("1", 1, *rent)).collect();
+
+        let chart = supply_data(&listings, Dimension::PriceBand, 10, &price_band::DEFAULT_BOUNDS, Lang::En);
+        assert_eq!(chart.keys, ["<1000", "1000–1500", "3000+"]);
+        assert_eq!(chart.datasets[0].data, [Some(1.0), Some(2.0), Some(3.0)]);
+    }
+}
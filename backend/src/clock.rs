@@ -0,0 +1,74 @@
+//! The current time, swappable for tests.
+//!
+//! Anything whose result depends on "now" (parsed listings' dates, how old a
+//! snapshot is, when a report is due) reads it from a `Clock` rather than
+//! from `Utc::now()`. Handlers use `state.clock`, and pass the time on to the
+//! stores they call; the parsers, which have no state, read the `[clock]`
+//! section handed to them with the rest of their settings (see
+//! `config::ParseSettings`), which is a clock itself.
+//!
+//! `[clock] now = "2024-11-05T12:00:00Z"` pins the time for a whole instance,
+//! so a test environment or a replay of old snapshots sees them as it would
+//! have on that day.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use std::sync::Arc;
+
+use crate::config::ClockConfig;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    fn today(&self) -> NaiveDate {
+        self.now().date_naive()
+    }
+}
+
+/// The system time.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A time that never moves.
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+/// The pinned time, else the system time.
+impl Clock for ClockConfig {
+    fn now(&self) -> DateTime<Utc> {
+        self.now.unwrap_or_else(Utc::now)
+    }
+}
+
+/// The clock `config` asks for.
+pub fn from_config(config: &ClockConfig) -> Arc<dyn Clock> {
+    match config.now {
+        Some(now) => Arc::new(FixedClock(now)),
+        None => Arc::new(SystemClock),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pinned_clocks() {
+        let at: DateTime<Utc> = "2024-11-05T12:00:00Z".parse().unwrap();
+        let clock = from_config(&ClockConfig { now: Some(at) });
+        assert_eq!(clock.today(), NaiveDate::from_ymd_opt(2024, 11, 5).unwrap());
+        assert!(from_config(&ClockConfig::default()).now() > at);
+
+        assert_eq!(ClockConfig { now: Some(at) }.now(), at);
+        assert!(ClockConfig::default().now() > at);
+    }
+}
@@ -0,0 +1,184 @@
+//! Commercial listings.
+//!
+//! Offices, retail and industrial units turn up in the same snapshots as
+//! homes, but their rents are quoted per year or per square foot ("€25 psf
+//! p.a."). With `search.commercial` on, listings classified under `commercial`
+//! (see `property_type`) get a `commercial` object with the yearly rent per sq
+//! ft and their zoning (from a mapped `zoning` column), and `price.amount` is
+//! converted to the monthly rent every filter and statistic expects. A rent
+//! quoted per sq ft of a listing without a size can't be converted and is left
+//! at 0, so searches count it as an invalid price.
+//!
+//! Searches then take `category`: `residential` (the default) leaves
+//! commercial listings out, `commercial` returns only them and `all` both.
+
+use serde::{Deserialize, Serialize};
+
+use crate::property_type;
+use crate::StandardizedProperty;
+
+const SQFT_PER_SQM: f64 = 10.7639;
+
+const PER_SQFT: [&str; 7] = ["psf", "per sq ft", "per sqft", "sq ft", "sqft", "per square foot", "ft²"];
+const PER_SQM: [&str; 6] = ["psm", "per sq m", "per sqm", "sqm", "per square metre", "m²"];
+const PER_YEAR: [&str; 5] = ["per annum", "pa", "per year", "annum", "yearly"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Residential,
+    Commercial,
+    All,
+}
+
+impl Category {
+    /// The category a search is in: as requested, else residential when
+    /// commercial handling is `enabled` and everything when it isn't.
+    pub fn effective(requested: Option<Category>, enabled: bool) -> Category {
+        requested.unwrap_or(if enabled { Category::Residential } else { Category::All })
+    }
+
+    pub fn matches(self, property_category: &str) -> bool {
+        match self {
+            Category::All => true,
+            Category::Commercial => is_commercial(property_category),
+            Category::Residential => !is_commercial(property_category),
+        }
+    }
+}
+
+pub fn is_commercial(property_category: &str) -> bool {
+    property_type::matches("commercial", property_category)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommercialDetails {
+    /// Yearly rent per square foot, as commercial rents are usually quoted.
+    pub price_per_sqft: Option<f64>,
+    pub zoning: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Per {
+    SqFt,
+    Sqm,
+    Year,
+    Month,
+}
+
+/// How a price text quotes the rent: "€25 psf" is per sq ft per year,
+/// "€60,000 p.a." per year, anything else per month.
+fn quote(price_text: &str) -> Per {
+    // "p.a." is "pa" and "sq. ft" is "sq ft"
+    let text = price_text.to_lowercase().replace('.', "");
+    let words: Vec<&str> = text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).collect();
+    let text = format!(" {} ", words.join(" "));
+    let has = |phrases: &[&str]| phrases.iter().any(|phrase| text.contains(&format!(" {} ", phrase)));
+    if has(&PER_SQFT) {
+        Per::SqFt
+    } else if has(&PER_SQM) {
+        Per::Sqm
+    } else if has(&PER_YEAR) {
+        Per::Year
+    } else {
+        Per::Month
+    }
+}
+
+/// Fills in `commercial` and converts the rent to monthly for a commercial
+/// listing parsed from `price_text`; other listings, and every listing while
+/// commercial handling isn't `enabled`, are left alone.
+pub fn apply(enabled: bool, property: &mut StandardizedProperty, price_text: &str, zoning: Option<String>) {
+    if enabled && is_commercial(&property.property_category) {
+        convert(property, price_text, zoning);
+    }
+}
+
+fn convert(property: &mut StandardizedProperty, price_text: &str, zoning: Option<String>) {
+    let sqm = property.size.as_ref().map(|size| size.value).filter(|sqm| *sqm > 0.0);
+    let amount = property.price.amount;
+    let (yearly, price_per_sqft) = match quote(price_text) {
+        Per::SqFt => (sqm.map(|sqm| amount * sqm * SQFT_PER_SQM), Some(amount)),
+        Per::Sqm => (sqm.map(|sqm| amount * sqm), Some(amount / SQFT_PER_SQM)),
+        Per::Year => (Some(amount), sqm.map(|sqm| amount / (sqm * SQFT_PER_SQM))),
+        Per::Month => (Some(amount * 12.0), sqm.map(|sqm| amount * 12.0 / (sqm * SQFT_PER_SQM))),
+    };
+    let monthly = yearly.map_or(0.0, |yearly| yearly / 12.0);
+    let factor = if amount > 0.0 { monthly / amount } else { 0.0 };
+    let price = &mut property.price;
+    price.amount = monthly;
+    price.min = price.min.map(|min| min * factor).filter(|_| monthly > 0.0);
+    price.max = price.max.map(|max| max * factor).filter(|_| monthly > 0.0);
+    price.frequency = Some("month".to_string());
+    property.commercial = Some(CommercialDetails {
+        price_per_sqft,
+        zoning: zoning.map(|z| z.trim().to_string()).filter(|z| !z.is_empty()),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, ParseSettings};
+    use crate::test_utils::listing;
+    use crate::Size;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("€25 psf"), Per::SqFt);
+        assert_eq!(quote("€22.50 per sq. ft"), Per::SqFt);
+        assert_eq!(quote("€250 per sqm p.a."), Per::Sqm);
+        assert_eq!(quote("€60,000 p.a."), Per::Year);
+        assert_eq!(quote("€60,000 per annum"), Per::Year);
+        assert_eq!(quote("€4,500 per month"), Per::Month);
+        // "pa" only as a word
+        assert_eq!(quote("€1,500 Parking incl."), Per::Month);
+    }
+
+    #[test]
+    fn test_convert_to_monthly() {
+        let office = |amount: f64, sqm: Option<f64>| {
+            let mut property = listing("daft", "1");
+            property.property_category = "office".to_string();
+            property.price.amount = amount;
+            property.size = sqm.map(|value| Size { value, unit: "square_meters".to_string() });
+            property
+        };
+
+        let mut property = office(60000.0, Some(100.0));
+        convert(&mut property, "€60,000 p.a.", Some(" Z4 ".to_string()));
+        assert_eq!(property.price.amount, 5000.0);
+        let details = property.commercial.unwrap();
+        assert!((details.price_per_sqft.unwrap() - 60000.0 / 1076.39).abs() < 1e-6);
+        assert_eq!(details.zoning.as_deref(), Some("Z4"));
+
+        let mut property = office(25.0, Some(100.0));
+        convert(&mut property, "€25 psf", None);
+        assert!((property.price.amount - 25.0 * 1076.39 / 12.0).abs() < 1e-6);
+        assert_eq!(property.commercial.unwrap().price_per_sqft, Some(25.0));
+
+        // No size: the rate can't become a rent
+        let mut property = office(25.0, None);
+        convert(&mut property, "€25 psf", None);
+        assert_eq!(property.price.amount, 0.0);
+
+        // Off by default
+        let mut property = office(60000.0, Some(100.0));
+        apply(ParseSettings::default().commercial, &mut property, "€60,000 p.a.", None);
+        assert_eq!((property.price.amount, &property.commercial), (60000.0, &None));
+        let enabled = Config::from_toml("[search]\ncommercial = true").unwrap().parsing.commercial;
+        apply(enabled, &mut property, "€60,000 p.a.", None);
+        assert_eq!(property.price.amount, 5000.0);
+    }
+
+    #[test]
+    fn test_category() {
+        assert!(Category::Residential.matches("apartment"));
+        assert!(Category::Residential.matches(property_type::UNKNOWN));
+        assert!(!Category::Residential.matches("retail"));
+        assert!(Category::Commercial.matches("industrial"));
+        assert!(Category::All.matches("office"));
+        assert_eq!(Category::effective(None, true), Category::Residential);
+        assert_eq!(Category::effective(None, false), Category::All);
+    }
+}
@@ -0,0 +1,1157 @@
+use log::{info, warn};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{env, fs};
+
+use crate::auth::ApiKey;
+use crate::mapping::SourceMapping;
+
+/// Environment variable pointing at the config file.
+const CONFIG_ENV: &str = "MARKET_ANALYSIS_CONFIG";
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+/// How rows of a source's parquet files are turned into `StandardizedProperty`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParserKind {
+    Daft,
+    MyHome,
+    Property,
+    /// Columns already follow the standardized schema and are read by name.
+    #[default]
+    Standardized,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SourceConfig {
+    pub name: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub parser: ParserKind,
+    /// Directory holding the year/month/day partitions. Defaults to
+    /// `<data_path>/processed/<name>`.
+    pub path: Option<PathBuf>,
+    /// Column mapping file for raw-schema parsers. Defaults to the built-in
+    /// mapping for the parser.
+    pub mapping: Option<PathBuf>,
+    /// Listing page URL for rows without an absolute `seo_url`, with
+    /// `{source_id}` and `{slug}` (the address as a URL slug) filled in.
+    /// Defaults to the site's own scheme for the built-in parsers.
+    pub url_template: Option<String>,
+    #[serde(skip)]
+    pub columns: Option<Arc<SourceMapping>>,
+    /// Settings the source's listings are parsed with; `Config::parsing`.
+    #[serde(skip)]
+    pub parsing: Arc<ParseSettings>,
+    /// Left out of searches and stats across all sources; set at runtime, see
+    /// `toggles`.
+    #[serde(skip)]
+    pub disabled: bool,
+}
+
+impl SourceConfig {
+    fn builtin(name: &str, parser: ParserKind, aliases: &[&str]) -> Self {
+        SourceConfig {
+            name: name.to_string(),
+            aliases: aliases.iter().map(|a| a.to_string()).collect(),
+            parser,
+            path: None,
+            mapping: None,
+            url_template: None,
+            columns: SourceMapping::builtin(parser).map(Arc::new),
+            parsing: Arc::default(),
+            disabled: false,
+        }
+    }
+
+    fn load_mapping(&mut self) -> Result<(), String> {
+        self.columns = match &self.mapping {
+            Some(path) => {
+                let contents = fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read mapping {:?} for {}: {}", path, self.name, e))?;
+                let mapping = SourceMapping::from_toml(&contents)
+                    .map_err(|e| format!("Invalid mapping {:?} for {}: {}", path, self.name, e))?;
+                Some(Arc::new(mapping))
+            }
+            None => SourceMapping::builtin(self.parser).map(Arc::new),
+        };
+        Ok(())
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let name = name.trim();
+        self.name.eq_ignore_ascii_case(name) || self.aliases.iter().any(|a| a.eq_ignore_ascii_case(name))
+    }
+
+    pub fn root(&self, data_path: &Path) -> PathBuf {
+        self.path
+            .clone()
+            .unwrap_or_else(|| data_path.join("processed").join(&self.name))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Overridden by `HOST`, or by a socket inherited from systemd.
+    pub host: String,
+    /// Overridden by `PORT`.
+    pub port: u16,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig { host: "127.0.0.1".to_string(), port: 3000 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Upper bound on memory used by parsed snapshots, in megabytes. Zero
+    /// disables the cache.
+    pub max_memory_mb: usize,
+    /// Parse every source into the cache at startup; `/ready` fails until done.
+    pub warm_up: bool,
+    /// Threads parsing a snapshot's rows when it is loaded into the cache.
+    /// Zero uses one per core; one parses on the requesting thread.
+    pub parse_threads: usize,
+    /// How often to look for snapshots newer than the cached ones and parse
+    /// them in the background; see `warmup`. Zero leaves them to be parsed
+    /// by the first query.
+    pub refresh_interval_secs: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig { max_memory_mb: 512, warm_up: false, parse_threads: 0, refresh_interval_secs: 60 }
+    }
+}
+
+impl CacheConfig {
+    pub fn budget_bytes(&self) -> usize {
+        self.max_memory_mb * 1024 * 1024
+    }
+}
+
+/// The settings applied to listings as they are parsed, rather than when
+/// they are searched, so listings cached under other settings have to be
+/// parsed again when they change.
+#[derive(Debug, PartialEq)]
+pub struct ParseSettings {
+    /// `search.price_bands`, sorted.
+    pub price_bands: Vec<f64>,
+    /// `[[price_bounds]]`.
+    pub price_bounds: Vec<crate::price_bounds::PriceRule>,
+    /// The register read from `agent_register.path`.
+    pub agent_register: Option<crate::agent_register::Register>,
+    /// `search.price_range_point`.
+    pub price_range_point: crate::price_range::RangePoint,
+    /// `search.commercial`.
+    pub commercial: bool,
+    /// `[clock]`, for the dates of listings that give none.
+    pub clock: ClockConfig,
+}
+
+impl Default for ParseSettings {
+    fn default() -> Self {
+        ParseSettings {
+            price_bands: crate::price_band::DEFAULT_BOUNDS.to_vec(),
+            price_bounds: vec![],
+            agent_register: None,
+            price_range_point: crate::price_range::RangePoint::Midpoint,
+            commercial: false,
+            clock: ClockConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SearchConfig {
+    /// How long a `snapshot_token` keeps later pages on the same snapshots.
+    pub snapshot_ttl_secs: u64,
+    /// Rents at which one `price_band` ends and the next begins; sorted when
+    /// the config is loaded.
+    pub price_bands: Vec<f64>,
+    /// A source whose newest snapshot is older than this is reported as
+    /// stale in search warnings. Zero turns the check off.
+    pub stale_after_days: u32,
+    /// How many days before an unreadable latest snapshot search may go back
+    /// for one that reads; see `fallback`.
+    pub fallback_max_age_days: u32,
+    /// A source whose scan takes longer than this is left out of the search
+    /// with a `timeout` warning. Zero waits however long it takes.
+    pub source_timeout_ms: u64,
+    /// Rent an advertised range is filtered and counted at: min, midpoint or
+    /// max.
+    pub price_range_point: crate::price_range::RangePoint,
+    /// Treat office, retail and industrial listings as commercial; see
+    /// `commercial`.
+    pub commercial: bool,
+    /// Filters every search gets unless it sets them; see `presets`.
+    pub defaults: serde_json::Map<String, serde_json::Value>,
+    /// Results per page when a search asks for a `page` without a
+    /// `page_size`; see `pagination`.
+    pub default_page_size: usize,
+    /// Largest `page_size` a search may ask for.
+    pub max_page_size: usize,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            snapshot_ttl_secs: 600,
+            price_bands: crate::price_band::DEFAULT_BOUNDS.to_vec(),
+            stale_after_days: 3,
+            fallback_max_age_days: 7,
+            source_timeout_ms: 10_000,
+            price_range_point: crate::price_range::RangePoint::Midpoint,
+            commercial: false,
+            defaults: serde_json::Map::new(),
+            default_page_size: 20,
+            max_page_size: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LinksConfig {
+    /// Public address of this service, used for share links in the sitemap.
+    pub base_url: String,
+    /// Where share link clicks are counted; in memory only when unset.
+    pub clicks_path: Option<PathBuf>,
+}
+
+impl Default for LinksConfig {
+    fn default() -> Self {
+        LinksConfig {
+            base_url: "http://localhost:3000".to_string(),
+            clicks_path: Some(PathBuf::from("links/clicks.json")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AnalyticsConfig {
+    /// Record anonymized searches and share-link clicks.
+    pub enabled: bool,
+    /// JSON-lines file the events are appended to. Without one, events are kept
+    /// in memory until restart.
+    pub path: Option<PathBuf>,
+}
+
+impl Default for AnalyticsConfig {
+    fn default() -> Self {
+        AnalyticsConfig {
+            enabled: true,
+            path: Some(PathBuf::from("analytics/events.jsonl")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// Keep the searches of callers with an API key for `/api/me/history`.
+    pub enabled: bool,
+    /// JSON-lines file the searches are appended to. Without one, history is
+    /// kept in memory until restart.
+    pub path: Option<PathBuf>,
+    /// Most recent searches kept per user.
+    pub max_per_user: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            enabled: true,
+            path: Some(PathBuf::from("history/searches.jsonl")),
+            max_per_user: 200,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub keys: Vec<ApiKey>,
+    /// Scopes granted to requests without an API key.
+    pub anonymous_scopes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PrivacyConfig {
+    /// Where agent suppression requests are recorded.
+    pub suppressions_path: Option<PathBuf>,
+    /// Secret mixed into the listing ids of the public dataset; without one
+    /// the dataset has no listing ids. See `dataset`.
+    pub dataset_salt: String,
+    /// Areas with fewer listings are published as their county.
+    pub dataset_min_area_listings: usize,
+}
+
+impl Default for PrivacyConfig {
+    fn default() -> Self {
+        PrivacyConfig {
+            suppressions_path: Some(PathBuf::from("privacy/suppressed_agents.json")),
+            dataset_salt: String::new(),
+            dataset_min_area_listings: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AgentRegisterConfig {
+    /// CSV export of the PSRA licence register; listings aren't checked
+    /// without one. See `agent_register`.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NotesConfig {
+    /// Where users' notes and tags on listings are kept.
+    pub path: Option<PathBuf>,
+    /// Where users' hidden listings and blocked agents are kept.
+    pub hidden_path: Option<PathBuf>,
+    /// Where users' planned viewings are kept.
+    pub viewings_path: Option<PathBuf>,
+    /// Where users' favourites and click-throughs are kept; see
+    /// `recommendations`.
+    pub interactions_path: Option<PathBuf>,
+}
+
+impl Default for NotesConfig {
+    fn default() -> Self {
+        NotesConfig {
+            path: Some(PathBuf::from("notes/notes.json")),
+            hidden_path: Some(PathBuf::from("notes/hidden.json")),
+            viewings_path: Some(PathBuf::from("notes/viewings.json")),
+            interactions_path: Some(PathBuf::from("notes/interactions.json")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// Append-only JSON-lines file of admin operations.
+    pub path: Option<PathBuf>,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            path: Some(PathBuf::from("audit/audit.jsonl")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SourceTogglesConfig {
+    /// Where the sources disabled at runtime are kept across restarts.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct QualityConfig {
+    pub enabled: bool,
+    /// Where the daily data-quality reports are kept; see `quality::report`.
+    pub path: Option<PathBuf>,
+    /// Hour of the day (UTC) after which the day's report is generated.
+    pub run_hour: u32,
+}
+
+impl Default for QualityConfig {
+    fn default() -> Self {
+        QualityConfig { enabled: true, path: Some(PathBuf::from("quality/reports.jsonl")), run_hour: 3 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct IdsConfig {
+    /// Where the day each listing was first seen is kept; see `ids`. Unset,
+    /// it is `listing_ids.json` under `data_path`.
+    pub path: Option<PathBuf>,
+    /// Days a source's id can go unseen before it is taken to be reused for a
+    /// new listing.
+    pub recycle_after_days: u32,
+}
+
+impl Default for IdsConfig {
+    fn default() -> Self {
+        IdsConfig { path: Some(PathBuf::from("admin/listing_ids.json")), recycle_after_days: 30 }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ClockConfig {
+    /// Pins the current time for the whole instance; see `clock`.
+    pub now: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CorrectionsConfig {
+    /// Where manual corrections to listings are kept; see `corrections`.
+    pub path: Option<PathBuf>,
+}
+
+impl Default for CorrectionsConfig {
+    fn default() -> Self {
+        CorrectionsConfig { path: Some(PathBuf::from("admin/corrections.json")) }
+    }
+}
+
+impl Default for SourceTogglesConfig {
+    fn default() -> Self {
+        SourceTogglesConfig { path: Some(PathBuf::from("admin/disabled_sources.json")) }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JobsConfig {
+    /// SQLite database holding the queue. `None` keeps jobs in memory, so
+    /// anything pending is lost on restart.
+    pub path: Option<PathBuf>,
+    pub workers: usize,
+    /// Runs per job, including the first, before it is marked failed.
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubles with every further attempt.
+    pub retry_backoff_secs: u64,
+    pub poll_interval_ms: u64,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        JobsConfig {
+            path: Some(PathBuf::from("jobs/jobs.sqlite")),
+            workers: 2,
+            max_attempts: 3,
+            retry_backoff_secs: 30,
+            poll_interval_ms: 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NotifierConfig {
+    /// URLs that receive every notification as a JSON POST. The body carries a
+    /// `text` field, so Slack and Teams incoming webhooks work as-is.
+    pub webhooks: Vec<String>,
+    /// Relay for email delivery; without it email recipients can't be served.
+    pub smtp: Option<SmtpConfig>,
+    /// Bot that sends Telegram notifications; without it Telegram users can't
+    /// be served.
+    pub telegram_bot_token: Option<String>,
+    pub telegram_api_url: String,
+    /// Where users' notification preferences and held notifications are kept.
+    pub preferences_path: Option<PathBuf>,
+    /// How often held notifications are checked for release.
+    pub check_interval_secs: u64,
+}
+
+impl Default for NotifierConfig {
+    fn default() -> Self {
+        NotifierConfig {
+            webhooks: vec![],
+            smtp: None,
+            telegram_bot_token: None,
+            telegram_api_url: "https://api.telegram.org".to_string(),
+            preferences_path: Some(PathBuf::from("notifier/preferences.json")),
+            check_interval_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub from: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port: 25,
+            from: "reports@localhost".to_string(),
+            username: None,
+            password: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReportsConfig {
+    /// Where recurring report schedules are kept. `None` keeps them in memory.
+    pub schedules_path: Option<PathBuf>,
+    /// Where users' watched areas are kept. `None` keeps them in memory.
+    pub watches_path: Option<PathBuf>,
+    /// Hour (UTC) on the first day of a period at which the report for the
+    /// period just ended goes out.
+    pub send_hour: u32,
+    pub check_interval_secs: u64,
+}
+
+impl Default for ReportsConfig {
+    fn default() -> Self {
+        ReportsConfig {
+            schedules_path: Some(PathBuf::from("reports/schedules.json")),
+            watches_path: Some(PathBuf::from("reports/watches.json")),
+            send_hour: 7,
+            check_interval_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DensityConfig {
+    pub enabled: bool,
+    /// Snapshots before the latest one that make up the baseline.
+    pub baseline_days: usize,
+    /// Relative change from the baseline that gets flagged; 0.5 flags halving
+    /// or a 50% jump.
+    pub threshold: f64,
+    /// Areas with a smaller baseline are too noisy to judge.
+    pub min_baseline: f64,
+    pub check_interval_mins: u64,
+}
+
+impl Default for DensityConfig {
+    fn default() -> Self {
+        DensityConfig {
+            enabled: true,
+            baseline_days: 7,
+            threshold: 0.5,
+            min_baseline: 20.0,
+            check_interval_mins: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RegimeConfig {
+    pub enabled: bool,
+    /// How far before the latest snapshot the one it is compared with is.
+    pub lookback_days: i64,
+    /// Relative move in an area's median rent that gets flagged; 0.1 flags a
+    /// 10% rise or fall.
+    pub rent_threshold: f64,
+    /// Relative fall in an area's listing count that gets flagged.
+    pub supply_threshold: f64,
+    /// Areas with fewer listings are too small to judge.
+    pub min_listings: usize,
+    pub check_interval_mins: u64,
+}
+
+impl Default for RegimeConfig {
+    fn default() -> Self {
+        RegimeConfig {
+            enabled: true,
+            lookback_days: 30,
+            rent_threshold: 0.1,
+            supply_threshold: 0.4,
+            min_listings: 20,
+            check_interval_mins: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LiquidityConfig {
+    /// Latest snapshots per source that churn is measured over.
+    pub window_days: usize,
+    /// Areas with fewer listings in the latest snapshots are left out.
+    pub min_listings: usize,
+}
+
+impl Default for LiquidityConfig {
+    fn default() -> Self {
+        LiquidityConfig { window_days: 7, min_listings: 10 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StatsConfig {
+    /// Aggregates over fewer listings are returned as null; see
+    /// `analytics::sample`.
+    pub min_sample: usize,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        StatsConfig { min_sample: 5 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SemanticConfig {
+    /// Accept `semantic_q` on searches; see `semantic`.
+    pub enabled: bool,
+    pub provider: crate::semantic::Provider,
+    /// Length of the `hashing` provider's vectors.
+    pub dimensions: usize,
+    /// Embeddings endpoint of the `api` provider.
+    pub api_url: Option<String>,
+    /// Sent as a bearer token, when the endpoint needs one.
+    pub api_key: Option<String>,
+    pub model: Option<String>,
+    /// Texts per request to the `api` provider.
+    pub batch_size: usize,
+    pub timeout_secs: u64,
+}
+
+impl Default for SemanticConfig {
+    fn default() -> Self {
+        SemanticConfig {
+            enabled: false,
+            provider: crate::semantic::Provider::default(),
+            dimensions: 256,
+            api_url: None,
+            api_key: None,
+            model: None,
+            batch_size: 64,
+            timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StatsSinkConfig {
+    /// Push aggregates of each new snapshot to `sinks`; see `stats_sink`.
+    pub enabled: bool,
+    pub aggregates: Vec<crate::stats_sink::Aggregate>,
+    pub sinks: Vec<crate::stats_sink::Sink>,
+    /// How often to look for new snapshots to push.
+    pub check_interval_secs: u64,
+    /// Where the snapshot last pushed for each source is kept. `None` pushes
+    /// the latest snapshots again after every restart.
+    pub state_path: Option<PathBuf>,
+}
+
+impl Default for StatsSinkConfig {
+    fn default() -> Self {
+        use crate::stats_sink::Aggregate;
+        StatsSinkConfig {
+            enabled: false,
+            aggregates: vec![Aggregate::Total, Aggregate::Area, Aggregate::Bedrooms],
+            sinks: vec![],
+            check_interval_secs: 300,
+            state_path: Some(PathBuf::from("stats_sink/pushed.json")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DeltasConfig {
+    pub enabled: bool,
+    /// How often to look for a new latest snapshot without a delta.
+    pub check_interval_mins: u64,
+    /// JSON-lines change feed; see `feed`. `None` keeps it in memory only.
+    pub feed_path: Option<PathBuf>,
+}
+
+impl Default for DeltasConfig {
+    fn default() -> Self {
+        DeltasConfig { enabled: true, check_interval_mins: 15, feed_path: Some(PathBuf::from("deltas/feed.jsonl")) }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PhotosConfig {
+    /// Download the photos of new and changed listings.
+    pub enabled: bool,
+    /// Where downloaded photos and their index are kept.
+    pub path: PathBuf,
+    /// Point every listing's photos at the stored copies where there are any,
+    /// rather than at the source site.
+    pub offline: bool,
+    pub max_per_run: usize,
+    /// Photos bigger than this are skipped.
+    pub max_bytes: u64,
+    pub check_interval_mins: u64,
+}
+
+impl Default for PhotosConfig {
+    fn default() -> Self {
+        PhotosConfig {
+            enabled: false,
+            path: PathBuf::from("photos"),
+            offline: false,
+            max_per_run: 500,
+            max_bytes: 10 * 1024 * 1024,
+            check_interval_mins: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ExportsConfig {
+    /// Where export jobs write their files; see `exports`.
+    pub path: PathBuf,
+}
+
+impl Default for ExportsConfig {
+    fn default() -> Self {
+        ExportsConfig { path: PathBuf::from("exports") }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// Sustained requests per minute allowed from one client address.
+    pub requests_per_minute: u32,
+    /// Requests a client may make in a quick burst before the limit applies.
+    pub burst: u32,
+    /// Take the client address from `X-Forwarded-For`. Only safe behind a
+    /// proxy that sets it.
+    pub trust_forwarded_for: bool,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: false,
+            requests_per_minute: 120,
+            burst: 30,
+            trust_forwarded_for: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AbuseConfig {
+    /// Count anonymous requests per address; see `abuse`. The blocklist
+    /// applies either way.
+    pub enabled: bool,
+    /// Length of the sliding window anonymous requests are counted over.
+    pub window_secs: u64,
+    /// Anonymous requests an address may make in a window.
+    pub max_requests: u32,
+    /// Anonymous requests in a window after which a CAPTCHA is asked for.
+    /// Zero never asks.
+    pub challenge_after: u32,
+    /// Where a CAPTCHA can be solved, sent as `x-challenge-url`.
+    pub challenge_url: Option<String>,
+    /// Where the blocked addresses and ranges are kept.
+    pub blocklist_path: Option<PathBuf>,
+}
+
+impl Default for AbuseConfig {
+    fn default() -> Self {
+        AbuseConfig {
+            enabled: false,
+            window_secs: 3600,
+            max_requests: 1000,
+            challenge_after: 300,
+            challenge_url: None,
+            blocklist_path: Some(PathBuf::from("admin/blocklist.json")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CdnConfig {
+    /// Mark search responses cacheable by a CDN and tag them; see `cdn`.
+    pub enabled: bool,
+    /// How long a CDN may serve a cached search response.
+    pub max_age_secs: u64,
+    /// Where the keys of replaced snapshots are POSTed to be purged.
+    pub purge_url: Option<String>,
+    /// How often to look for new snapshots to purge.
+    pub check_interval_secs: u64,
+}
+
+impl Default for CdnConfig {
+    fn default() -> Self {
+        CdnConfig { enabled: false, max_age_secs: 300, purge_url: None, check_interval_secs: 60 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DemoConfig {
+    pub enabled: bool,
+    /// Where the synthetic dataset is generated on startup; it replaces
+    /// `data_path` and the configured sources. Must be empty or hold an
+    /// earlier demo dataset; see `demo`.
+    pub data_path: PathBuf,
+    pub rows: usize,
+    pub days: usize,
+    pub seed: u64,
+    pub requests_per_minute: u32,
+    pub burst: u32,
+}
+
+impl Default for DemoConfig {
+    fn default() -> Self {
+        DemoConfig {
+            enabled: false,
+            data_path: env::temp_dir().join("market-analysis-demo"),
+            rows: 500,
+            days: 30,
+            seed: 42,
+            requests_per_minute: 30,
+            burst: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Single-line human-readable output.
+    #[default]
+    Full,
+    /// Multi-line output for local development.
+    Pretty,
+    Compact,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// `EnvFilter` directives, e.g. `info,parquet=warn,main::mapping=debug`.
+    pub level: String,
+    /// Write to this file instead of stdout. Rotated files get a date suffix.
+    pub file: Option<PathBuf>,
+    pub rotation: LogRotation,
+    /// Rotated files to keep; older ones are deleted. Unset keeps everything.
+    pub max_files: Option<usize>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            format: LogFormat::default(),
+            level: "info,parquet=warn,arrow=warn".to_string(),
+            file: None,
+            rotation: LogRotation::default(),
+            max_files: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub data_path: PathBuf,
+    pub server: ServerConfig,
+    pub cache: CacheConfig,
+    pub search: SearchConfig,
+    pub semantic: SemanticConfig,
+    pub links: LinksConfig,
+    pub analytics: AnalyticsConfig,
+    pub history: HistoryConfig,
+    pub auth: AuthConfig,
+    pub privacy: PrivacyConfig,
+    pub agent_register: AgentRegisterConfig,
+    pub notes: NotesConfig,
+    pub audit: AuditConfig,
+    pub source_toggles: SourceTogglesConfig,
+    pub corrections: CorrectionsConfig,
+    pub ids: IdsConfig,
+    pub jobs: JobsConfig,
+    pub notifier: NotifierConfig,
+    pub density: DensityConfig,
+    pub regime: RegimeConfig,
+    pub quality: QualityConfig,
+    pub liquidity: LiquidityConfig,
+    pub stats: StatsConfig,
+    pub stats_sink: StatsSinkConfig,
+    pub deltas: DeltasConfig,
+    pub photos: PhotosConfig,
+    pub exports: ExportsConfig,
+    pub reports: ReportsConfig,
+    pub rate_limit: RateLimitConfig,
+    pub abuse: AbuseConfig,
+    pub cdn: CdnConfig,
+    pub clock: ClockConfig,
+    pub demo: DemoConfig,
+    pub logging: LoggingConfig,
+    /// Named search filters; see `presets`.
+    pub presets: crate::presets::Presets,
+    /// Rent bounds per segment; see `price_bounds`.
+    pub price_bounds: Vec<crate::price_bounds::PriceRule>,
+    /// Sources declared in the config file. Entries named like a built-in source
+    /// replace it; anything else is added after the built-ins.
+    pub sources: Vec<SourceConfig>,
+    /// Built from the settings above when the config is loaded, and shared
+    /// with every source.
+    #[serde(skip)]
+    pub parsing: Arc<ParseSettings>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            data_path: PathBuf::from("housing_data"),
+            server: ServerConfig::default(),
+            cache: CacheConfig::default(),
+            search: SearchConfig::default(),
+            semantic: SemanticConfig::default(),
+            links: LinksConfig::default(),
+            analytics: AnalyticsConfig::default(),
+            history: HistoryConfig::default(),
+            auth: AuthConfig::default(),
+            privacy: PrivacyConfig::default(),
+            agent_register: AgentRegisterConfig::default(),
+            notes: NotesConfig::default(),
+            audit: AuditConfig::default(),
+            source_toggles: SourceTogglesConfig::default(),
+            corrections: CorrectionsConfig::default(),
+            ids: IdsConfig::default(),
+            jobs: JobsConfig::default(),
+            notifier: NotifierConfig::default(),
+            density: DensityConfig::default(),
+            regime: RegimeConfig::default(),
+            quality: QualityConfig::default(),
+            liquidity: LiquidityConfig::default(),
+            stats: StatsConfig::default(),
+            stats_sink: StatsSinkConfig::default(),
+            deltas: DeltasConfig::default(),
+            photos: PhotosConfig::default(),
+            exports: ExportsConfig::default(),
+            reports: ReportsConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            abuse: AbuseConfig::default(),
+            cdn: CdnConfig::default(),
+            clock: ClockConfig::default(),
+            demo: DemoConfig::default(),
+            logging: LoggingConfig::default(),
+            presets: Default::default(),
+            price_bounds: Vec::new(),
+            sources: default_sources(),
+            parsing: Arc::default(),
+        }
+    }
+}
+
+fn default_sources() -> Vec<SourceConfig> {
+    vec![
+        SourceConfig::builtin("daft", ParserKind::Daft, &["daft_ie", "daft.ie"]),
+        SourceConfig::builtin("myhome", ParserKind::MyHome, &["myhome_ie", "myhome.ie"]),
+        SourceConfig::builtin("property", ParserKind::Property, &["property_ie", "property.ie"]),
+    ]
+}
+
+impl Config {
+    /// The config file: `$MARKET_ANALYSIS_CONFIG`, or `config.toml`.
+    pub fn path() -> String {
+        env::var(CONFIG_ENV).unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string())
+    }
+
+    /// Loads the config from `$MARKET_ANALYSIS_CONFIG` or `./config.toml`, falling
+    /// back to the built-in defaults when no file exists.
+    pub fn load() -> Result<Self, String> {
+        let path = Config::path();
+        if !Path::new(&path).exists() {
+            info!("No config file at {}, using defaults", path);
+            return Ok(Config::default());
+        }
+
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read config {}: {}", path, e))?;
+        let config = Config::from_toml(&contents)
+            .map_err(|e| format!("Invalid config {}: {}", path, e))?;
+        info!("Loaded config from {} with {} sources", path, config.sources.len());
+        Ok(config)
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, String> {
+        let mut config: Config = toml::from_str(contents).map_err(|e| e.to_string())?;
+        let mut sources = default_sources();
+        for mut source in std::mem::take(&mut config.sources) {
+            source.load_mapping()?;
+            match sources.iter_mut().find(|s| s.name.eq_ignore_ascii_case(&source.name)) {
+                Some(existing) => *existing = source,
+                None => sources.push(source),
+            }
+        }
+        config.sources = sources;
+        config.search.price_bands = crate::price_band::normalize(&config.search.price_bands);
+        config.share_parse_settings();
+        config.check_aliases();
+        crate::presets::validate(&config.presets)?;
+        crate::presets::validate_defaults(&config.search.defaults)?;
+        if !(1..=config.search.max_page_size).contains(&config.search.default_page_size) {
+            return Err("search.default_page_size must be between 1 and search.max_page_size".to_string());
+        }
+        crate::price_bounds::validate(&config.price_bounds)?;
+        crate::semantic::validate(&config.semantic)?;
+        Ok(config)
+    }
+
+    /// Builds `parsing` from the rest of the config and hands it to every
+    /// source.
+    fn share_parse_settings(&mut self) {
+        self.parsing = Arc::new(ParseSettings {
+            price_bands: self.search.price_bands.clone(),
+            price_bounds: self.price_bounds.clone(),
+            agent_register: crate::agent_register::open(self.agent_register.path.as_deref()),
+            price_range_point: self.search.price_range_point,
+            commercial: self.search.commercial,
+            clock: self.clock.clone(),
+        });
+        for source in &mut self.sources {
+            source.parsing = self.parsing.clone();
+        }
+    }
+
+    fn check_aliases(&self) {
+        for (i, source) in self.sources.iter().enumerate() {
+            for other in &self.sources[i + 1..] {
+                if other.matches(&source.name) || source.aliases.iter().any(|a| other.matches(a)) {
+                    warn!("Sources {} and {} share a name or alias; {} wins", source.name, other.name, source.name);
+                }
+            }
+        }
+    }
+
+    /// Finds a source by its name or any alias, ignoring case.
+    pub fn resolve_source(&self, name: &str) -> Option<&SourceConfig> {
+        self.sources.iter().find(|s| s.matches(name))
+    }
+
+    /// The requested source, or every enabled source when none was given.
+    /// An unknown name resolves to nothing; a disabled one can still be asked
+    /// for by name.
+    pub fn select_sources(&self, requested: Option<&str>) -> Vec<&SourceConfig> {
+        match requested {
+            Some(name) => match self.resolve_source(name) {
+                Some(source) => vec![source],
+                None => {
+                    warn!("Unknown source requested: {}", name);
+                    vec![]
+                }
+            },
+            None => self.sources.iter().filter(|source| !source.disabled).collect(),
+        }
+    }
+
+    /// Names of `select_sources`, for querying the store.
+    pub fn source_names(&self, requested: Option<&str>) -> Vec<&str> {
+        self.select_sources(requested).into_iter().map(|source| source.name.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_resolve_aliases() {
+        let config = Config::default();
+        assert_eq!(config.resolve_source("property_ie").unwrap().name, "property");
+        assert_eq!(config.resolve_source("Property").unwrap().name, "property");
+        assert_eq!(config.resolve_source("DAFT").unwrap().parser, ParserKind::Daft);
+        assert!(config.resolve_source("rent_ie").is_none());
+    }
+
+    #[test]
+    fn test_new_source_from_config() {
+        let config = Config::from_toml(
+            r#"
+            data_path = "/srv/housing_data"
+
+            [[sources]]
+            name = "rent_ie"
+            path = "/srv/rent_ie"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.sources.len(), 4);
+        let source = config.resolve_source("rent_ie").unwrap();
+        assert_eq!(source.parser, ParserKind::Standardized);
+        assert_eq!(source.root(&config.data_path), PathBuf::from("/srv/rent_ie"));
+
+        let daft = config.resolve_source("daft").unwrap();
+        assert_eq!(daft.root(&config.data_path), PathBuf::from("/srv/housing_data/processed/daft"));
+    }
+
+    #[test]
+    fn test_config_overrides_builtin_source() {
+        let config = Config::from_toml(
+            r#"
+            [[sources]]
+            name = "property"
+            parser = "property"
+            aliases = ["pie"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.sources.len(), 3);
+        assert_eq!(config.resolve_source("pie").unwrap().name, "property");
+        assert!(config.resolve_source("property_ie").is_none());
+    }
+
+    #[test]
+    fn test_logging_section() {
+        let config = Config::from_toml(
+            r#"
+            [logging]
+            format = "json"
+            level = "warn,main::mapping=debug"
+            file = "logs/market-analysis.log"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.logging.format, LogFormat::Json);
+        assert_eq!(config.logging.rotation, LogRotation::Daily);
+        assert_eq!(config.logging.file, Some(PathBuf::from("logs/market-analysis.log")));
+        assert_eq!(Config::default().logging.format, LogFormat::Full);
+    }
+
+    #[test]
+    fn test_select_sources() {
+        let config = Config::default();
+        assert_eq!(config.select_sources(None).len(), 3);
+        assert_eq!(config.select_sources(Some("myhome_ie"))[0].name, "myhome");
+        assert!(config.select_sources(Some("unknown")).is_empty());
+    }
+}
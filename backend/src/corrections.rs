@@ -0,0 +1,277 @@
+//! Manual corrections to listings.
+//!
+//! A listing now and then arrives with an obviously wrong rent or address, and
+//! fixing it in one snapshot would be undone by the next. A correction is kept
+//! apart from the snapshots and applied to the listing whenever any snapshot
+//! is parsed, so it holds across refreshes until it is removed:
+//!
+//! ```json
+//! PUT /api/admin/rentals/daft_123/correction
+//! { "price": 1850, "reason": "Advertised weekly, not monthly" }
+//! ```
+//!
+//! overrides the given fields only; `"deleted": true` instead takes the
+//! listing out of search, stats and lookups altogether. `DELETE` on the same
+//! path removes the correction, and `GET /api/admin/corrections` lists them.
+//! Every change is audited. The source's cached snapshot is dropped on a
+//! change so the next query parses it with the correction.
+
+use axum::extract::{Path as UrlPath, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::auth::{Caller, SCOPE_ADMIN};
+use crate::config::ParseSettings;
+use crate::state::AppState;
+use crate::{address, price_band, price_bounds, property_type, StandardizedProperty};
+
+/// Fields a correction can override. Unset fields keep the source's value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Overrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<f64>,
+    /// The display address; the normalized one is derived from it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bedrooms: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bathrooms: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub property_type: Option<String>,
+    /// Leave the listing out everywhere.
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CorrectionRequest {
+    #[serde(flatten)]
+    pub overrides: Overrides,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Correction {
+    pub property_id: String,
+    pub source: String,
+    #[serde(flatten)]
+    pub overrides: Overrides,
+    pub reason: String,
+    pub corrected_by: String,
+    pub corrected_at: DateTime<Utc>,
+}
+
+pub struct Corrections {
+    path: Option<PathBuf>,
+    entries: RwLock<BTreeMap<String, Correction>>,
+}
+
+impl Corrections {
+    /// Loads corrections from `path`. `None` keeps them in memory only.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let entries: Vec<Correction> = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        let entries = entries.into_iter().map(|c| (c.property_id.clone(), c)).collect();
+        Corrections { path, entries: RwLock::new(entries) }
+    }
+
+    pub fn get(&self, property_id: &str) -> Option<Correction> {
+        self.entries.read().unwrap().get(property_id).cloned()
+    }
+
+    pub fn list(&self) -> Vec<Correction> {
+        self.entries.read().unwrap().values().cloned().collect()
+    }
+
+    /// Adds or replaces the correction of its listing.
+    pub fn set(&self, correction: Correction) -> Result<(), String> {
+        let mut entries = self.entries.write().unwrap();
+        entries.insert(correction.property_id.clone(), correction);
+        self.save(&entries)
+    }
+
+    /// Removes the correction of `property_id`, returning it.
+    pub fn remove(&self, property_id: &str) -> Result<Option<Correction>, String> {
+        let mut entries = self.entries.write().unwrap();
+        let removed = entries.remove(property_id);
+        if removed.is_some() {
+            self.save(&entries)?;
+        }
+        Ok(removed)
+    }
+
+    fn save(&self, entries: &BTreeMap<String, Correction>) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let list: Vec<&Correction> = entries.values().collect();
+        let contents = serde_json::to_string_pretty(&list).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| e.to_string())
+    }
+
+    /// Applies the correction of `property`, if any, deriving what a
+    /// corrected field changes under `parsing`. Returns false for a deleted
+    /// listing.
+    pub fn apply(&self, property: &mut StandardizedProperty, parsing: &ParseSettings) -> bool {
+        let entries = self.entries.read().unwrap();
+        let Some(correction) = property.ids().find_map(|id| entries.get(id)) else {
+            return true;
+        };
+        let overrides = &correction.overrides;
+        if overrides.deleted {
+            return false;
+        }
+        if let Some(price) = overrides.price {
+            property.price.amount = price;
+            property.price.min = None;
+            property.price.max = None;
+            property.price_band = price_band::label(&parsing.price_bands, price);
+        }
+        if let Some(display) = &overrides.address {
+            property.address.display_address = display.clone();
+            property.address.normalized_address = address::normalize(display);
+        }
+        if let Some(bedrooms) = overrides.bedrooms {
+            property.bedrooms = Some(bedrooms);
+        }
+        if let Some(bathrooms) = overrides.bathrooms {
+            property.bathrooms = Some(bathrooms);
+        }
+        if let Some(kind) = &overrides.property_type {
+            property.property_type = kind.clone();
+            property.property_category = property_type::classify(kind).to_string();
+        }
+        property.price_flag = price_bounds::check(&parsing.price_bounds, property);
+        true
+    }
+}
+
+static ACTIVE: OnceLock<Arc<Corrections>> = OnceLock::new();
+
+/// Makes `corrections` the ones applied while snapshots are parsed; call once
+/// at startup. Nothing is corrected until then.
+pub fn install(corrections: Arc<Corrections>) {
+    let _ = ACTIVE.set(corrections);
+}
+
+/// Applies the installed corrections to a listing freshly parsed under
+/// `parsing`. Returns false when it should be left out.
+pub fn apply(property: &mut StandardizedProperty, parsing: &ParseSettings) -> bool {
+    ACTIVE.get().is_none_or(|corrections| corrections.apply(property, parsing))
+}
+
+pub async fn list(
+    State(state): State<AppState>,
+    caller: Caller,
+) -> Result<Json<Vec<Correction>>, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    Ok(Json(state.corrections.list()))
+}
+
+pub async fn put(
+    State(state): State<AppState>,
+    caller: Caller,
+    UrlPath(property_id): UrlPath<String>,
+    Json(request): Json<CorrectionRequest>,
+) -> Result<Json<Correction>, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    if request.reason.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Give a reason for the correction".to_string()));
+    }
+    if request.overrides.price.is_some_and(|price| !price.is_finite() || price <= 0.0) {
+        return Err((StatusCode::BAD_REQUEST, "price must be positive".to_string()));
+    }
+    // A listing already corrected may be deleted, and so not found any more
+    let source = match state.corrections.get(&property_id) {
+        Some(existing) => existing.source,
+        None => {
+            let listing = state.id_index.lookup(&state.config, std::slice::from_ref(&property_id)).pop();
+            listing.ok_or((StatusCode::NOT_FOUND, format!("No listing {}", property_id)))?.source
+        }
+    };
+    let correction = Correction {
+        property_id,
+        source,
+        overrides: request.overrides,
+        reason: request.reason,
+        corrected_by: caller.name.clone().unwrap_or_else(|| "anonymous".to_string()),
+        corrected_at: state.clock.now(),
+    };
+    let outcome = state.corrections.set(correction.clone());
+    state.audit.record(&caller, "listings.correct", &correction, &outcome);
+    outcome.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    state.cache.evict(&correction.source);
+    Ok(Json(correction))
+}
+
+pub async fn delete(
+    State(state): State<AppState>,
+    caller: Caller,
+    UrlPath(property_id): UrlPath<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    let outcome = state.corrections.remove(&property_id);
+    state.audit.record(&caller, "listings.uncorrect", serde_json::json!({ "property_id": property_id }), &outcome);
+    match outcome.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))? {
+        Some(removed) => {
+            state.cache.evict(&removed.source);
+            Ok(StatusCode::NO_CONTENT)
+        }
+        None => Err((StatusCode::NOT_FOUND, format!("No correction for {}", property_id))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{listing, temp_dir};
+
+    fn correction(property_id: &str, overrides: Overrides) -> Correction {
+        Correction {
+            property_id: property_id.to_string(),
+            source: "daft".to_string(),
+            overrides,
+            reason: "test".to_string(),
+            corrected_by: "admin".to_string(),
+            corrected_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_corrections_apply_and_persist() {
+        let path = temp_dir("corrections").join("corrections.json");
+        let corrections = Corrections::open(Some(path.clone()));
+        let address = Some("1 Main St, Dublin 6".to_string());
+        let overrides = Overrides { price: Some(1850.0), address, ..Default::default() };
+        corrections.set(correction("daft_1", overrides)).unwrap();
+        corrections.set(correction("daft_2", Overrides { deleted: true, ..Default::default() })).unwrap();
+
+        let corrections = Corrections::open(Some(path));
+        let parsing = ParseSettings::default();
+        let mut property = listing("daft", "1");
+        property.price.max = Some(2000.0);
+        assert!(corrections.apply(&mut property, &parsing));
+        assert_eq!((property.price.amount, property.price.max), (1850.0, None));
+        assert_eq!(property.price_band, price_band::label(&parsing.price_bands, 1850.0));
+        assert_eq!(property.address.normalized_address, address::normalize("1 Main St, Dublin 6"));
+        assert!(!corrections.apply(&mut listing("daft", "2"), &parsing));
+        assert!(corrections.apply(&mut listing("daft", "3"), &parsing));
+
+        assert!(corrections.remove("daft_2").unwrap().is_some());
+        assert!(corrections.remove("daft_2").unwrap().is_none());
+        assert!(corrections.apply(&mut listing("daft", "2"), &parsing));
+    }
+}
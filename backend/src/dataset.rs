@@ -0,0 +1,372 @@
+//! Anonymized dataset for publication.
+//!
+//! One row per listing with what research needs and nothing that leads back
+//! to a person or a front door: no agent, address line, photos, links,
+//! coordinates or description. Locations are cut down to the area (the last
+//! address component, e.g. "dublin 6"), or to the county when fewer than
+//! `privacy.dataset_min_area_listings` listings share the area. Listing ids are
+//! replaced with a salted hash so the same listing can be followed across
+//! snapshots; without a `privacy.dataset_salt` the column is left empty, as
+//! plain hashes of public ids are easily reversed.
+//!
+//! `GET /api/dataset.csv` and `/api/dataset.parquet` export the latest
+//! snapshot of each source (or `?source=`); the command exports every
+//! snapshot:
+//!
+//! ```text
+//! main export-dataset [--format csv|parquet] [--source daft] [--latest] --out FILE
+//! ```
+//!
+//! The panel of a single area over a date range is built as a job; see
+//! `exports`.
+
+use arrow::array::{ArrayRef, BooleanArray, Float64Array, Int64Array, RecordBatch, StringArray};
+use axum::extract::{Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use chrono::NaiveDate;
+use log::error;
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::address;
+use crate::config::Config;
+use crate::state::AppState;
+use crate::{find_latest_parquet, list_snapshots, load_properties, validate_price, StandardizedProperty};
+
+enum Values {
+    Text(Vec<Option<String>>),
+    Integer(Vec<Option<i64>>),
+    Number(Vec<Option<f64>>),
+    Flag(Vec<Option<bool>>),
+}
+
+impl Values {
+    fn csv(&self, row: usize) -> String {
+        match self {
+            Values::Text(values) => values[row].as_deref().map(csv_field).unwrap_or_default(),
+            Values::Integer(values) => values[row].map(|v| v.to_string()).unwrap_or_default(),
+            Values::Number(values) => values[row].map(|v| v.to_string()).unwrap_or_default(),
+            Values::Flag(values) => values[row].map(|v| v.to_string()).unwrap_or_default(),
+        }
+    }
+
+    fn array(self) -> ArrayRef {
+        match self {
+            Values::Text(values) => Arc::new(StringArray::from(values)),
+            Values::Integer(values) => Arc::new(Int64Array::from(values)),
+            Values::Number(values) => Arc::new(Float64Array::from(values)),
+            Values::Flag(values) => Arc::new(BooleanArray::from(values)),
+        }
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// A listing as it was in one snapshot.
+pub struct Observation {
+    pub snapshot: NaiveDate,
+    pub property: StandardizedProperty,
+}
+
+/// The published columns, in order.
+pub struct Dataset {
+    rows: usize,
+    columns: Vec<(&'static str, Values)>,
+}
+
+/// Salted hash of a property id; `None` without a salt.
+fn listing_key(salt: &str, property_id: &str) -> Option<String> {
+    if salt.is_empty() {
+        return None;
+    }
+    let digest = Sha256::digest(format!("{}|{}", salt, property_id).as_bytes());
+    Some(digest.iter().take(8).map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// The published location of each observation: its area, or its county when
+/// the area is too small to hide in.
+fn locations(observations: &[Observation], min_area_listings: usize) -> Vec<Option<String>> {
+    let areas: Vec<&str> =
+        observations.iter().map(|o| address::area(&o.property.address.normalized_address)).collect();
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for area in &areas {
+        *counts.entry(area).or_default() += 1;
+    }
+    areas
+        .iter()
+        .zip(observations)
+        .map(|(area, observation)| {
+            if !area.is_empty() && counts[area] >= min_area_listings {
+                return Some(area.to_string());
+            }
+            let county = address::county(&observation.property.address.normalized_address);
+            county.map(|county| format!("county {}", county))
+        })
+        .collect()
+}
+
+impl Dataset {
+    pub fn build(config: &Config, observations: &[Observation]) -> Dataset {
+        let privacy = &config.privacy;
+        let properties = || observations.iter().map(|o| &o.property);
+        type Field<T> = fn(&StandardizedProperty) -> Option<T>;
+        let text = |value: Field<String>| Values::Text(properties().map(value).collect());
+        let integer = |value: Field<i64>| Values::Integer(properties().map(value).collect());
+        let number = |value: Field<f64>| Values::Number(properties().map(value).collect());
+        let flag = |value: Field<bool>| Values::Flag(properties().map(value).collect());
+        let listings = properties().map(|p| listing_key(&privacy.dataset_salt, &p.property_id)).collect();
+        let snapshots = observations.iter().map(|o| Some(o.snapshot.to_string())).collect();
+        let columns = vec![
+            ("listing", Values::Text(listings)),
+            ("source", text(|p| Some(p.source.clone()))),
+            ("snapshot", Values::Text(snapshots)),
+            ("listed", text(|p| p.created_date.get(..10).map(str::to_string))),
+            ("area", Values::Text(locations(observations, privacy.dataset_min_area_listings))),
+            ("property_type", text(|p| Some(p.property_type.clone()))),
+            ("property_category", text(|p| Some(p.property_category.clone()))),
+            ("bedrooms", integer(|p| p.bedrooms.map(i64::from))),
+            ("bathrooms", integer(|p| p.bathrooms.map(i64::from))),
+            ("size_sqm", number(|p| p.size.as_ref().map(|size| size.value))),
+            ("ber_rating", text(|p| p.ber_rating.clone())),
+            ("rent", number(|p| Some(p.price.amount))),
+            ("currency", text(|p| Some(p.price.currency.clone()))),
+            ("price_band", text(|p| Some(p.price_band.clone()))),
+            ("deposit", number(|p| p.deposit)),
+            ("parking_spaces", integer(|p| p.amenities.parking_spaces.map(i64::from))),
+            ("garden", flag(|p| p.amenities.garden)),
+            ("balcony", flag(|p| p.amenities.balcony)),
+            ("available_from", text(|p| p.tenancy.available_from.map(|d| d.to_string()))),
+            ("min_lease_months", integer(|p| p.tenancy.min_lease_months.map(i64::from))),
+        ];
+        Dataset { rows: observations.len(), columns }
+    }
+
+    pub fn to_csv(&self) -> String {
+        let header: Vec<&str> = self.columns.iter().map(|(name, _)| *name).collect();
+        let mut out = header.join(",");
+        out.push_str("\r\n");
+        for row in 0..self.rows {
+            let fields: Vec<String> = self.columns.iter().map(|(_, values)| values.csv(row)).collect();
+            out.push_str(&fields.join(","));
+            out.push_str("\r\n");
+        }
+        out
+    }
+
+    pub fn into_parquet(self) -> Result<Vec<u8>, String> {
+        self.into_parquet_with(WriterProperties::default())
+    }
+
+    /// `into_parquet` with the given writer settings, e.g. compression.
+    pub fn into_parquet_with(self, properties: WriterProperties) -> Result<Vec<u8>, String> {
+        let columns: Vec<(&str, ArrayRef)> =
+            self.columns.into_iter().map(|(name, values)| (name, values.array())).collect();
+        let batch = RecordBatch::try_from_iter(columns).map_err(|e| e.to_string())?;
+        let mut out = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut out, batch.schema(), Some(properties)).map_err(|e| e.to_string())?;
+        writer.write(&batch).map_err(|e| e.to_string())?;
+        writer.close().map_err(|e| e.to_string())?;
+        Ok(out)
+    }
+}
+
+/// Valid-rent listings of the selected sources: every snapshot, or only the
+/// latest of each. Blocking.
+pub fn load(config: &Config, source: Option<&str>, latest_only: bool) -> Vec<Observation> {
+    let mut observations = Vec::new();
+    for source in config.select_sources(source) {
+        let root = source.root(&config.data_path);
+        let mut snapshots = list_snapshots(&root);
+        if latest_only {
+            let latest = find_latest_parquet(&root);
+            snapshots.retain(|(_, file)| Some(file) == latest.as_ref());
+        }
+        for (snapshot, file) in snapshots {
+            observations.extend(
+                load_properties(source, &file)
+                    .into_iter()
+                    .filter(validate_price)
+                    .map(|property| Observation { snapshot, property }),
+            );
+        }
+    }
+    observations
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Csv,
+    Parquet,
+}
+
+struct Options {
+    format: Format,
+    source: Option<String>,
+    latest_only: bool,
+    out: PathBuf,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let (mut format, mut source, mut latest_only, mut out) = (Format::Csv, None, false, None);
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            if flag == "--latest" {
+                latest_only = true;
+                continue;
+            }
+            let value = args.next().ok_or_else(|| format!("{} needs a value", flag))?;
+            match flag.as_str() {
+                "--format" => {
+                    format = match value.as_str() {
+                        "csv" => Format::Csv,
+                        "parquet" => Format::Parquet,
+                        _ => return Err(format!("Unknown format {:?}; use csv or parquet", value)),
+                    }
+                }
+                "--source" => source = Some(value.clone()),
+                "--out" => out = Some(PathBuf::from(value)),
+                _ => return Err(format!("Unknown option {}. Options: --format, --source, --latest, --out", flag)),
+            }
+        }
+        let out = out.ok_or("--out is required")?;
+        Ok(Options { format, source, latest_only, out })
+    }
+}
+
+/// `main export-dataset`. Returns whether the file was written.
+pub fn run(config: &Config, args: &[String]) -> bool {
+    let options = match Options::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+    if config.privacy.dataset_salt.is_empty() {
+        eprintln!("No privacy.dataset_salt set; the listing column is left empty");
+    }
+    let observations = load(config, options.source.as_deref(), options.latest_only);
+    let dataset = Dataset::build(config, &observations);
+    let contents = match options.format {
+        Format::Csv => Ok(dataset.to_csv().into_bytes()),
+        Format::Parquet => dataset.into_parquet(),
+    };
+    match contents.and_then(|contents| std::fs::write(&options.out, contents).map_err(|e| e.to_string())) {
+        Ok(()) => {
+            println!("Wrote {} rows to {}", observations.len(), options.out.display());
+            true
+        }
+        Err(e) => {
+            eprintln!("Could not write {}: {}", options.out.display(), e);
+            false
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DatasetParams {
+    source: Option<String>,
+}
+
+fn attachment(content_type: &str, file_name: &str, body: Vec<u8>) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file_name)),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+pub async fn dataset_csv(
+    State(state): State<AppState>,
+    Query(params): Query<DatasetParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let csv = tokio::task::spawn_blocking(move || {
+        let observations = load(&state.config, params.source.as_deref(), true);
+        Dataset::build(&state.config, &observations).to_csv()
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(attachment("text/csv; charset=utf-8", "rentals.csv", csv.into_bytes()))
+}
+
+pub async fn dataset_parquet(
+    State(state): State<AppState>,
+    Query(params): Query<DatasetParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let parquet = tokio::task::spawn_blocking(move || {
+        let observations = load(&state.config, params.source.as_deref(), true);
+        Dataset::build(&state.config, &observations).into_parquet()
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .map_err(|e| {
+        error!("Could not write dataset: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Could not write dataset".to_string())
+    })?;
+    Ok(attachment("application/vnd.apache.parquet", "rentals.parquet", parquet))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::listing;
+
+    fn observation(id: &str, address: &str) -> Observation {
+        let mut property = listing("daft", id);
+        property.address.display_address = address.to_string();
+        property.address.normalized_address = address::normalize(address);
+        property.agent = Some(crate::Agent {
+            name: "Jane Murphy".to_string(),
+            phone: "087 123 4567".to_string(),
+            email: "jane@example.com".to_string(),
+            address: "1 Agent Row".to_string(),
+            licence: String::new(),
+        });
+        property.photos.push(crate::Photo { url: "https://img/1.jpg".to_string(), is_main: true, ..Default::default() });
+        Observation { snapshot: "2024-11-05".parse().unwrap(), property }
+    }
+
+    #[test]
+    fn test_strips_personal_data() {
+        let mut config = Config::default();
+        config.privacy.dataset_min_area_listings = 2;
+        let observations = [
+            observation("1", "Flat 2, 7 Castle Ave, Ranelagh, Dublin 6"),
+            observation("2", "9 Oak Road, Rathmines, Dublin 6"),
+            // Alone in its area: only the county is published
+            observation("3", "12 Main Street, Salthill, Galway City"),
+        ];
+        let csv = Dataset::build(&config, &observations).to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines[0].starts_with("listing,source,snapshot,listed,area,"));
+        assert!(lines[1].starts_with(",daft,2024-11-05,2024-11-05,dublin 6,"), "{}", lines[1]);
+        assert!(lines[3].contains(",county galway,"), "{}", lines[3]);
+        for personal in ["Castle", "castle", "Jane", "087", "example.com", "img/1.jpg", "daft_1"] {
+            assert!(!csv.contains(personal), "{:?} in {}", personal, csv);
+        }
+
+        config.privacy.dataset_salt = "pepper".to_string();
+        let dataset = Dataset::build(&config, &observations);
+        let key = listing_key("pepper", "daft_1").unwrap();
+        assert_eq!(key.len(), 16);
+        assert!(dataset.to_csv().lines().nth(1).unwrap().starts_with(&key));
+        let parquet = dataset.into_parquet().unwrap();
+        assert_eq!(&parquet[..4], b"PAR1");
+    }
+}
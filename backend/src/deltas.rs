@@ -0,0 +1,401 @@
+//! Changes between consecutive snapshots.
+//!
+//! Each day's snapshot holds every listing, so anything that cares about what
+//! changed (alerts, listing lifecycle, change feeds) would otherwise re-diff
+//! hundreds of thousands of rows. The first time a new latest snapshot is seen
+//! it is compared with the previous day's, and the new, changed and removed
+//! listings are written next to it as `<file>.delta.json`. The periodic job
+//! and `main build-deltas` (for the pipeline, right after a snapshot lands)
+//! both do this; `GET /api/rentals/changes` serves the result, and `feed`
+//! keeps every delta in a sequenced log.
+//!
+//! Listings are matched by `property_id`. A listing has changed when one of
+//! `TRACKED_FIELDS` differs; a new `updated_date` alone is not a change.
+
+use axum::extract::{Query, State};
+use axum::Json;
+use chrono::NaiveDate;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::auth::Caller;
+use crate::config::{Config, SourceConfig};
+use crate::display;
+use crate::feed;
+use crate::id_index::file_signature;
+use crate::locale::Lang;
+use crate::state::AppState;
+use crate::{find_latest_parquet, list_snapshots, load_properties, StandardizedProperty};
+
+/// Job kind that builds missing deltas for every source's latest snapshot.
+pub const JOB: &str = "snapshot.deltas";
+
+const SIDECAR_SUFFIX: &str = "delta.json";
+/// Bumped when the sidecar layout or the comparison changes.
+const SIDECAR_VERSION: u32 = 1;
+
+/// Fields compared between snapshots, as named in `ListingChange::fields`.
+pub const TRACKED_FIELDS: [&str; 9] =
+    ["price", "status", "address", "property_type", "bedrooms", "bathrooms", "size", "ber_rating", "photos"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    New,
+    Changed,
+    Removed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListingChange {
+    pub kind: ChangeKind,
+    pub property_id: String,
+    /// Tracked fields that differ; empty for new and removed listings.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<String>,
+    /// Rent in the previous snapshot, for changed and removed listings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_price: Option<f64>,
+    /// The listing as it is now, or as it last was when removed.
+    pub listing: StandardizedProperty,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotDelta {
+    #[serde(default)]
+    version: u32,
+    pub source: String,
+    pub file: PathBuf,
+    file_len: u64,
+    modified: u64,
+    pub date: Option<NaiveDate>,
+    /// The snapshot compared against; `None` for a source's first snapshot,
+    /// when every listing is new.
+    pub previous_date: Option<NaiveDate>,
+    pub changes: Vec<ListingChange>,
+}
+
+fn changed_fields(before: &StandardizedProperty, after: &StandardizedProperty) -> Vec<String> {
+    let differs = |field: &str| match field {
+        "price" => before.price.amount != after.price.amount,
+        "status" => before.status != after.status,
+        "address" => before.address.display_address != after.address.display_address,
+        "property_type" => before.property_type != after.property_type,
+        "bedrooms" => before.bedrooms != after.bedrooms,
+        "bathrooms" => before.bathrooms != after.bathrooms,
+        "size" => before.size.as_ref().map(|s| (s.value, &s.unit)) != after.size.as_ref().map(|s| (s.value, &s.unit)),
+        "ber_rating" => (&before.ber_rating, before.ber_status) != (&after.ber_rating, after.ber_status),
+        "photos" => {
+            before.photos.iter().map(|p| &p.url).collect::<Vec<_>>() != after.photos.iter().map(|p| &p.url).collect::<Vec<_>>()
+        }
+        _ => false,
+    };
+    TRACKED_FIELDS.iter().filter(|field| differs(field)).map(|field| field.to_string()).collect()
+}
+
+/// New, changed and removed listings going from `previous` to `current`, in
+/// `current`'s order with removals last.
+pub fn diff(previous: Vec<StandardizedProperty>, current: Vec<StandardizedProperty>) -> Vec<ListingChange> {
+    let mut previous: HashMap<String, StandardizedProperty> =
+        previous.into_iter().map(|p| (p.property_id.clone(), p)).collect();
+    let mut changes = Vec::new();
+    for listing in current {
+        match previous.remove(&listing.property_id) {
+            None => changes.push(ListingChange {
+                kind: ChangeKind::New,
+                property_id: listing.property_id.clone(),
+                fields: vec![],
+                previous_price: None,
+                listing,
+            }),
+            Some(before) => {
+                let fields = changed_fields(&before, &listing);
+                if !fields.is_empty() {
+                    changes.push(ListingChange {
+                        kind: ChangeKind::Changed,
+                        property_id: listing.property_id.clone(),
+                        fields,
+                        previous_price: Some(before.price.amount),
+                        listing,
+                    });
+                }
+            }
+        }
+    }
+    let mut removed: Vec<StandardizedProperty> = previous.into_values().collect();
+    removed.sort_by(|a, b| a.property_id.cmp(&b.property_id));
+    changes.extend(removed.into_iter().map(|listing| ListingChange {
+        kind: ChangeKind::Removed,
+        property_id: listing.property_id.clone(),
+        fields: vec![],
+        previous_price: Some(listing.price.amount),
+        listing,
+    }));
+    changes
+}
+
+impl SnapshotDelta {
+    pub fn sidecar_path(path: &Path) -> PathBuf {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".");
+        name.push(SIDECAR_SUFFIX);
+        path.with_file_name(name)
+    }
+
+    /// Compares `path` with the snapshot of the day before it.
+    pub fn build(config: &Config, source: &SourceConfig, path: &Path) -> Option<Self> {
+        let (file_len, modified) = file_signature(path)?;
+        let snapshots = list_snapshots(&source.root(&config.data_path));
+        let position = snapshots.iter().position(|(_, file)| file == path);
+        let date = position.map(|i| snapshots[i].0);
+        let previous = position.and_then(|i| i.checked_sub(1)).map(|i| &snapshots[i]);
+        let before = previous.map(|(_, file)| load_properties(source, file)).unwrap_or_default();
+        let changes = diff(before, load_properties(source, path));
+        Some(SnapshotDelta {
+            version: SIDECAR_VERSION,
+            source: source.name.clone(),
+            file: path.to_path_buf(),
+            file_len,
+            modified,
+            date,
+            previous_date: previous.map(|(date, _)| *date),
+            changes,
+        })
+    }
+
+    /// The sidecar if it is there and matches the snapshot, without building.
+    pub fn load(path: &Path) -> Option<Self> {
+        fs::read_to_string(Self::sidecar_path(path))
+            .ok()
+            .and_then(|contents| serde_json::from_str::<SnapshotDelta>(&contents).ok())
+            .filter(|delta| {
+                delta.version == SIDECAR_VERSION
+                    && delta.file == path
+                    && file_signature(path) == Some((delta.file_len, delta.modified))
+            })
+    }
+
+    /// Reads the sidecar when it matches the snapshot, otherwise builds it and
+    /// tries to write it back.
+    pub fn load_or_build(config: &Config, source: &SourceConfig, path: &Path) -> Option<Self> {
+        if let Some(delta) = Self::load(path) {
+            return Some(delta);
+        }
+        let delta = Self::build(config, source, path)?;
+        let sidecar = Self::sidecar_path(path);
+        match serde_json::to_string(&delta) {
+            Ok(contents) => {
+                if let Err(e) = fs::write(&sidecar, contents) {
+                    warn!("Could not write snapshot delta {:?}: {}", sidecar, e);
+                }
+            }
+            Err(e) => warn!("Could not serialize snapshot delta for {:?}: {}", path, e),
+        }
+        Some(delta)
+    }
+
+    pub fn count(&self, kind: ChangeKind) -> usize {
+        self.changes.iter().filter(|change| change.kind == kind).count()
+    }
+}
+
+/// Builds the delta of every source's latest snapshot that has none yet.
+/// Returns whether all of them could be built.
+pub fn build_missing(config: &Config) -> bool {
+    let mut ok = true;
+    for source in &config.sources {
+        let Some(latest) = find_latest_parquet(&source.root(&config.data_path)) else {
+            continue;
+        };
+        if SnapshotDelta::load(&latest).is_some() {
+            continue;
+        }
+        match SnapshotDelta::load_or_build(config, source, &latest) {
+            Some(delta) => info!(
+                "{} delta for {:?}: {} new, {} changed, {} removed",
+                source.name,
+                latest,
+                delta.count(ChangeKind::New),
+                delta.count(ChangeKind::Changed),
+                delta.count(ChangeKind::Removed)
+            ),
+            None => {
+                error!("Could not build the delta for {:?}", latest);
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+/// `main build-deltas`.
+pub fn run(config: &Config) -> bool {
+    build_missing(config)
+}
+
+pub fn run_job(state: &AppState, _payload: &serde_json::Value) -> Result<(), String> {
+    let built = build_missing(&state.config);
+    feed::sync(state);
+    match built {
+        true => Ok(()),
+        false => Err("Some snapshot deltas could not be built".to_string()),
+    }
+}
+
+/// Queues a build every `deltas.check_interval_mins` for the life of the
+/// process.
+pub fn schedule(state: &AppState) {
+    if !state.config.deltas.enabled {
+        return;
+    }
+    let state = state.clone();
+    let period = Duration::from_secs(state.config.deltas.check_interval_mins.max(1) * 60);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            if let Err(e) = state.jobs.enqueue_once(JOB, ()) {
+                error!("Could not queue snapshot deltas: {}", e);
+            }
+        }
+    });
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangesParams {
+    source: Option<String>,
+    /// Only changes of this kind: new, changed or removed.
+    kind: Option<ChangeKind>,
+    /// Add display strings to the listings; see `display`.
+    #[serde(default)]
+    format_values: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SourceChanges {
+    pub source: String,
+    pub date: Option<NaiveDate>,
+    pub previous_date: Option<NaiveDate>,
+    pub new: usize,
+    pub changed: usize,
+    pub removed: usize,
+    pub changes: Vec<ListingChange>,
+}
+
+/// Prepares a changed listing for `caller`, as search does.
+pub fn present(
+    state: &AppState,
+    caller: &Caller,
+    lang: Lang,
+    listing: &mut StandardizedProperty,
+    removed: bool,
+    format_values: bool,
+) {
+    state.privacy.redact(listing, caller);
+    // The source has usually taken a delisted listing's photos down
+    if removed {
+        state.photos.rewrite(listing);
+    } else {
+        state.photos.serve_offline(std::slice::from_mut(listing));
+    }
+    if format_values {
+        display::attach_all(std::slice::from_mut(listing), lang);
+    }
+}
+
+/// Changes in each source's latest snapshot.
+pub async fn changes(
+    State(state): State<AppState>,
+    caller: Caller,
+    lang: Lang,
+    Query(params): Query<ChangesParams>,
+) -> Json<Vec<SourceChanges>> {
+    let config = &state.config;
+    let mut response = Vec::new();
+    for source in config.select_sources(params.source.as_deref()) {
+        let Some(latest) = find_latest_parquet(&source.root(&config.data_path)) else {
+            continue;
+        };
+        let Some(delta) = SnapshotDelta::load_or_build(config, source, &latest) else {
+            continue;
+        };
+        let (new, changed, removed) =
+            (delta.count(ChangeKind::New), delta.count(ChangeKind::Changed), delta.count(ChangeKind::Removed));
+        let mut changes: Vec<ListingChange> = delta
+            .changes
+            .into_iter()
+            .filter(|change| params.kind.is_none_or(|kind| change.kind == kind))
+            .collect();
+        for change in &mut changes {
+            let removed = change.kind == ChangeKind::Removed;
+            present(&state, &caller, lang, &mut change.listing, removed, params.format_values);
+        }
+        response.push(SourceChanges {
+            source: source.name.clone(),
+            date: delta.date,
+            previous_date: delta.previous_date,
+            new,
+            changed,
+            removed,
+            changes,
+        });
+    }
+    Json(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{listing, standardized_batch, temp_dir, write_parquet};
+
+    #[test]
+    fn test_diff() {
+        let rental = |id: &str, rent: f64| {
+            let mut property = listing("daft", id);
+            property.price.amount = rent;
+            property
+        };
+        let mut refreshed = rental("2", 1500.0);
+        refreshed.updated_date = "2024-11-06".to_string();
+        let previous = vec![rental("1", 1000.0), rental("2", 1500.0), rental("3", 2000.0)];
+        let current = vec![rental("4", 900.0), refreshed, rental("3", 1900.0)];
+
+        let changes = diff(previous, current);
+        let summary: Vec<_> = changes.iter().map(|c| (c.kind, c.property_id.as_str(), c.previous_price)).collect();
+        assert_eq!(
+            summary,
+            [
+                (ChangeKind::New, "daft_4", None),
+                (ChangeKind::Changed, "daft_3", Some(2000.0)),
+                (ChangeKind::Removed, "daft_1", Some(1000.0)),
+            ]
+        );
+        assert_eq!(changes[1].fields, ["price"]);
+    }
+
+    #[test]
+    fn test_delta_against_previous_day() {
+        let data = temp_dir("deltas");
+        let config = Config::from_toml(&format!("data_path = {:?}\n[[sources]]\nname = \"rent_ie\"", data)).unwrap();
+        let source = config.resolve_source("rent_ie").unwrap();
+        let first = data.join("processed/rent_ie/2024/11/05/rent_ie_120000.parquet");
+        let second = data.join("processed/rent_ie/2024/11/06/rent_ie_120000.parquet");
+        write_parquet(&first, &standardized_batch(&["1", "2"]));
+        write_parquet(&second, &standardized_batch(&["2", "3", "4"]));
+
+        let delta = SnapshotDelta::load_or_build(&config, source, &second).unwrap();
+        assert_eq!(delta.previous_date, NaiveDate::from_ymd_opt(2024, 11, 5));
+        assert_eq!((delta.count(ChangeKind::New), delta.count(ChangeKind::Removed)), (2, 1));
+        // Row 0 moved from €1000 to the second row's €1100
+        assert_eq!(delta.count(ChangeKind::Changed), 1);
+        assert!(SnapshotDelta::sidecar_path(&second).exists());
+        assert_eq!(SnapshotDelta::load(&second).unwrap().changes.len(), 4);
+
+        let first_delta = SnapshotDelta::build(&config, source, &first).unwrap();
+        assert_eq!((first_delta.previous_date, first_delta.count(ChangeKind::New)), (None, 2));
+    }
+}
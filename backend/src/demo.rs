@@ -0,0 +1,136 @@
+//! Public read-only demo mode.
+//!
+//! A demo instance serves only a synthetic dataset generated on startup, so
+//! no scraped listings are exposed. It sends no notifications or reports,
+//! accepts no API keys (main leaves the admin and debug routes out) and rate
+//! limits every client.
+//!
+//! The dataset is generated again on every start. Only a directory this code
+//! generated (it leaves `MARKER` in it) or an empty one is cleared for it, so
+//! pointing `demo.data_path` at real data stops startup instead of deleting it.
+
+use log::info;
+use std::fs;
+use std::path::Path;
+
+use crate::clock::Clock;
+use crate::config::{Config, NotifierConfig};
+use crate::fixtures;
+
+/// Left in a generated dataset's directory to mark it safe to clear.
+const MARKER: &str = ".market-analysis-demo";
+
+/// Empties `dir` for a new dataset if it holds a previous one, refusing
+/// anything else.
+fn clear(dir: &Path) -> Result<(), String> {
+    let Ok(mut entries) = fs::read_dir(dir) else {
+        return Ok(());
+    };
+    if !dir.join(MARKER).exists() {
+        if entries.next().is_none() {
+            return Ok(());
+        }
+        return Err(format!(
+            "{} is not empty and holds no demo dataset; refusing to clear it for demo mode",
+            dir.display()
+        ));
+    }
+    fs::remove_dir_all(dir).map_err(|e| format!("Could not clear demo data at {}: {}", dir.display(), e))
+}
+
+/// Generates the demo dataset and rewrites `config` to serve only it. A no-op
+/// unless `demo.enabled`.
+pub fn prepare(config: &mut Config) -> Result<(), String> {
+    if !config.demo.enabled {
+        return Ok(());
+    }
+    let demo = config.demo.clone();
+    clear(&demo.data_path)?;
+    fs::create_dir_all(&demo.data_path)
+        .and_then(|()| fs::write(demo.data_path.join(MARKER), ""))
+        .map_err(|e| format!("Could not create demo data at {}: {}", demo.data_path.display(), e))?;
+    let written = fixtures::generate(&fixtures::Options {
+        rows: demo.rows,
+        days: demo.days,
+        seed: demo.seed,
+        out: demo.data_path.clone(),
+        today: config.clock.today(),
+    })?;
+    info!("Demo mode: serving {} synthetic snapshots from {}", written.len(), demo.data_path.display());
+
+    let defaults = Config::default();
+    config.data_path = demo.data_path;
+    config.sources = defaults.sources;
+    // The built-in sources parse with this config's settings, not the defaults
+    for source in &mut config.sources {
+        source.parsing = config.parsing.clone();
+    }
+    config.auth = defaults.auth;
+    config.notifier = NotifierConfig::default();
+    config.density.enabled = false;
+    config.reports.schedules_path = None;
+    config.rate_limit.enabled = true;
+    config.rate_limit.requests_per_minute = demo.requests_per_minute;
+    config.rate_limit.burst = demo.burst;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_dir;
+
+    #[test]
+    fn test_prepare_replaces_data_and_secrets() {
+        let dir = temp_dir("demo");
+        let mut config = Config::from_toml(
+            r#"
+            data_path = "/srv/scraped"
+            [notifier]
+            webhooks = ["https://hooks.example.com/x"]
+            [[auth.keys]]
+            name = "ops"
+            key = "secret"
+            scopes = ["admin"]
+            [[sources]]
+            name = "private_feed"
+            "#,
+        )
+        .unwrap();
+        config.demo.enabled = true;
+        config.demo.data_path = dir.join("data");
+        config.demo.rows = 5;
+        config.demo.days = 2;
+        prepare(&mut config).unwrap();
+
+        assert_eq!(config.data_path, dir.join("data"));
+        assert!(config.sources.iter().all(|s| s.name != "private_feed"));
+        assert!(config.sources.iter().all(|s| std::sync::Arc::ptr_eq(&s.parsing, &config.parsing)));
+        assert!(config.auth.keys.is_empty());
+        assert!(config.notifier.webhooks.is_empty());
+        assert!(config.rate_limit.enabled);
+        assert_eq!(config.rate_limit.requests_per_minute, config.demo.requests_per_minute);
+        assert!(config.data_path.join("processed").join("daft").exists());
+    }
+
+    #[test]
+    fn test_prepare_only_clears_its_own_data() {
+        let dir = temp_dir("demo_clear");
+        let mut config = Config::default();
+        config.demo.enabled = true;
+        config.demo.rows = 2;
+        config.demo.days = 1;
+
+        // A dataset from an earlier start is replaced
+        config.demo.data_path = dir.join("demo");
+        prepare(&mut config.clone()).unwrap();
+        prepare(&mut config.clone()).unwrap();
+        assert!(dir.join("demo").join(MARKER).exists());
+
+        let scraped = dir.join("scraped");
+        fs::create_dir_all(scraped.join("processed")).unwrap();
+        config.demo.data_path = scraped.clone();
+        assert!(prepare(&mut config).is_err());
+        assert!(scraped.join("processed").exists());
+    }
+}
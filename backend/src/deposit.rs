@@ -0,0 +1,86 @@
+//! Security deposits.
+//!
+//! Listings that say what deposit they want get a `deposit` in euro, read
+//! from the optional `deposit` column (an amount, or text like "1 month's
+//! rent") or else from a "deposit" clause of the feature list and description
+//! (see `amenities::listing_text`). Deposits given in months or weeks of rent
+//! are converted with the listing's monthly rent. `/api/stats/deposits`
+//! reports them against the rent; see `analytics::deposits`.
+
+use crate::amenities;
+use crate::mapping::BatchRow;
+use crate::price_text;
+
+const COUNTS: [(&str, f64); 6] = [("one", 1.0), ("two", 2.0), ("three", 3.0), ("four", 4.0), ("six", 6.0), ("a", 1.0)];
+const NONE: [&str; 3] = ["no deposit", "deposit free", "zero deposit"];
+
+fn count(word: &str) -> Option<f64> {
+    word.parse().ok().filter(|n: &f64| *n > 0.0 && *n <= 12.0).or_else(|| COUNTS.iter().find(|(w, _)| *w == word).map(|(_, n)| *n))
+}
+
+/// A deposit of "N months" or "N weeks" of rent, in months.
+fn months_of_rent(words: &[&str]) -> Option<f64> {
+    words.windows(2).find_map(|pair| {
+        let n = count(pair[0])?;
+        match pair[1].trim_end_matches(['\'', 's', '\u{2019}']) {
+            "month" => Some(n),
+            "week" => Some(n * 12.0 / 52.0),
+            _ => None,
+        }
+    })
+}
+
+/// The deposit a clause mentioning one asks for, given the monthly rent.
+fn from_clause(clause: &str, rent: f64) -> Option<f64> {
+    let lower = clause.to_lowercase();
+    if NONE.iter().any(|phrase| lower.contains(phrase)) {
+        return Some(0.0);
+    }
+    let words: Vec<&str> = lower.split(|c: char| c.is_whitespace() || matches!(c, ',' | ':' | '(' | ')')).collect();
+    if let Some(months) = months_of_rent(&words) {
+        return (rent > 0.0).then_some(months * rent);
+    }
+    price_text::parse(clause).map(|price| price.amount)
+}
+
+/// The deposit in `text`, from the first clause that mentions one.
+pub fn from_text(text: &str, rent: f64) -> Option<f64> {
+    // Sentences end at ". ", so "€1.850" stays whole
+    text.replace(". ", "\n")
+        .split([';', '\n', '•', '|'])
+        .filter(|clause| clause.to_lowercase().contains("deposit"))
+        .find_map(|clause| from_clause(clause, rent))
+}
+
+pub fn extract(row: &BatchRow, rent: f64) -> Option<f64> {
+    if let Some(amount) = row.double("deposit") {
+        return Some(amount).filter(|amount| *amount >= 0.0);
+    }
+    row.string("deposit")
+        .and_then(|text| from_clause(&format!("deposit {}", text), rent))
+        .or_else(|| from_text(&amenities::listing_text(row), rent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_text() {
+        let cases = [
+            ("Deposit: €1,850", Some(1850.0)),
+            ("Deposit: €1.850. Pets allowed", Some(1850.0)),
+            ("Rent €1,800 per month. Deposit €3,600 required", Some(3600.0)),
+            ("One month's rent deposit", Some(1800.0)),
+            ("Deposit of two months' rent", Some(3600.0)),
+            ("Deposit 1 month", Some(1800.0)),
+            ("Deposit: 6 weeks", Some(1800.0 * 6.0 * 12.0 / 52.0)),
+            ("No deposit required", Some(0.0)),
+            ("Deposit to be agreed", None),
+            ("Close to the Luas, 2 bed", None),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(from_text(text, 1800.0), expected, "{:?}", text);
+        }
+    }
+}
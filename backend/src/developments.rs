@@ -0,0 +1,258 @@
+//! New-home developments.
+//!
+//! Daft and MyHome advertise a new development as one listing with a "from"
+//! rent and a list of unit types, each with its own bedrooms, size and rent.
+//! Sources that map a `units` column (an optional column, see `mapping`) give
+//! such listings a `development` holding the units, and the listing's own
+//! rent is the "from" rent.
+//!
+//! `GET /api/developments` searches developments on their units, separately
+//! from the individual listings `GET /api/rentals/search` returns: a development
+//! matches when one unit has the bedrooms and rent asked for.
+//! `GET /api/developments/:id` returns one.
+
+use axum::extract::{self, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::auth::Caller;
+use crate::display;
+use crate::locale::Lang;
+use crate::mapping::BatchRow;
+use crate::state::AppState;
+use crate::{parse_price_string, StandardizedProperty};
+
+/// Keys a unit's fields go by in the sources, lowercased without separators.
+const BEDROOM_KEYS: [&str; 4] = ["numbedrooms", "bedrooms", "numberofbeds", "beds"];
+const SIZE_KEYS: [&str; 4] = ["floorarea", "size", "sizestringmeters", "sizemeters"];
+const PRICE_KEYS: [&str; 3] = ["price", "priceasstring", "pricefrom"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Unit {
+    pub bedrooms: Option<i32>,
+    /// Square metres.
+    pub size: Option<f64>,
+    /// Monthly rent.
+    pub price: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Development {
+    /// The lowest unit rent.
+    pub price_from: Option<f64>,
+    pub units: Vec<Unit>,
+}
+
+fn key(name: &str) -> String {
+    name.chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_lowercase()
+}
+
+fn field<'a>(unit: &'a serde_json::Map<String, Value>, keys: &[&str]) -> Option<&'a Value> {
+    unit.iter().find(|(name, value)| !value.is_null() && keys.contains(&key(name).as_str())).map(|(_, value)| value)
+}
+
+/// The first number in "2 Bed" or "72.5 m²".
+fn leading_number(text: &str) -> Option<f64> {
+    let start = text.find(|c: char| c.is_ascii_digit())?;
+    let number: String = text[start..].chars().take_while(|c| c.is_ascii_digit() || *c == '.').collect();
+    number.trim_end_matches('.').parse().ok()
+}
+
+fn bedrooms(value: &Value) -> Option<i32> {
+    match value {
+        Value::Number(n) => n.as_f64().map(|n| n as i32),
+        Value::String(s) if s.to_lowercase().contains("studio") => Some(0),
+        Value::String(s) => leading_number(s).map(|n| n as i32),
+        _ => None,
+    }
+}
+
+fn size(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => leading_number(s),
+        // Daft's floorArea is {"value": "72", "unit": "METRES_SQUARED"}
+        Value::Object(area) => area.get("value").and_then(size),
+        _ => None,
+    }
+}
+
+fn price(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64().filter(|n| *n > 0.0),
+        Value::String(s) => parse_price_string(s).map(|(amount, _)| amount),
+        _ => None,
+    }
+}
+
+/// Reads the units out of a list of unit structs; `None` when there are no
+/// readable units.
+pub fn from_json(units: &Value) -> Option<Development> {
+    let units: Vec<Unit> = units
+        .as_array()?
+        .iter()
+        .filter_map(Value::as_object)
+        .map(|unit| Unit {
+            bedrooms: field(unit, &BEDROOM_KEYS).and_then(bedrooms),
+            size: field(unit, &SIZE_KEYS).and_then(size),
+            price: field(unit, &PRICE_KEYS).and_then(price),
+        })
+        .filter(|unit| unit.bedrooms.is_some() || unit.size.is_some() || unit.price.is_some())
+        .collect();
+    if units.is_empty() {
+        return None;
+    }
+    let price_from = units.iter().filter_map(|unit| unit.price).min_by(f64::total_cmp);
+    Some(Development { price_from, units })
+}
+
+pub fn from_row(row: &BatchRow) -> Option<Development> {
+    from_json(&row.json("units")?)
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DevelopmentParams {
+    source: Option<String>,
+    /// Only developments with a unit of this many bedrooms.
+    bedrooms: Option<i32>,
+    /// Only developments with a unit rented within these bounds.
+    min_price: Option<f64>,
+    max_price: Option<f64>,
+    /// Text the address must contain.
+    area: Option<String>,
+    /// Add display strings; see `display`.
+    #[serde(default)]
+    format_values: bool,
+}
+
+impl DevelopmentParams {
+    fn unit_matches(&self, unit: &Unit) -> bool {
+        self.bedrooms.is_none_or(|beds| unit.bedrooms == Some(beds))
+            && self.min_price.is_none_or(|min| unit.price.is_some_and(|price| price >= min))
+            && self.max_price.is_none_or(|max| unit.price.is_some_and(|price| price <= max))
+    }
+
+    fn matches(&self, property: &StandardizedProperty) -> bool {
+        let Some(development) = &property.development else {
+            return false;
+        };
+        let area_matches = self.area.as_deref().map(|a| a.trim().to_lowercase()).is_none_or(|area| {
+            property.address.normalized_address.contains(&area)
+                || property.address.display_address.to_lowercase().contains(&area)
+        });
+        area_matches && development.units.iter().any(|unit| self.unit_matches(unit))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DevelopmentsResponse {
+    pub total: usize,
+    pub developments: Vec<StandardizedProperty>,
+}
+
+pub async fn search(
+    State(state): State<AppState>,
+    caller: Caller,
+    lang: Lang,
+    Query(params): Query<DevelopmentParams>,
+) -> Result<Json<DevelopmentsResponse>, (StatusCode, String)> {
+    let (reading, format_values) = (state.clone(), params.format_values);
+    let mut developments = tokio::task::spawn_blocking(move || {
+        let sources = reading.config.source_names(params.source.as_deref());
+        reading.store.query(&sources, &|property| params.matches(property))
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    developments.sort_by(|a, b| a.price.amount.total_cmp(&b.price.amount));
+    state.privacy.redact_all(&mut developments, &caller);
+    state.photos.serve_offline(&mut developments);
+    if format_values {
+        display::attach_all(&mut developments, lang);
+    }
+    Ok(Json(DevelopmentsResponse { total: developments.len(), developments }))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DetailParams {
+    #[serde(default)]
+    format_values: bool,
+}
+
+pub async fn get_development(
+    State(state): State<AppState>,
+    caller: Caller,
+    lang: Lang,
+    extract::Path(property_id): extract::Path<String>,
+    Query(params): Query<DetailParams>,
+) -> Result<Json<StandardizedProperty>, (StatusCode, String)> {
+    let mut property = state
+        .id_index
+        .lookup(&state.config, &[property_id])
+        .into_iter()
+        .find(|property| property.development.is_some())
+        .ok_or((StatusCode::NOT_FOUND, String::new()))?;
+    state.privacy.redact(&mut property, &caller);
+    state.photos.serve_offline(std::slice::from_mut(&mut property));
+    if params.format_values {
+        display::attach_all(std::slice::from_mut(&mut property), lang);
+    }
+    Ok(Json(property))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::listing;
+    use serde_json::json;
+
+    #[test]
+    fn test_units_from_source_shapes() {
+        // Daft subUnits
+        let daft = json!([
+            {"numBedrooms": "2 Bed", "price": "€2,150 per month", "floorArea": {"value": "74", "unit": "METRES_SQUARED"}},
+            {"numBedrooms": "Studio", "price": "€1,650 per month", "floorArea": null},
+            {"id": 7},
+        ]);
+        let development = from_json(&daft).unwrap();
+        assert_eq!(
+            development.units,
+            [
+                Unit { bedrooms: Some(2), size: Some(74.0), price: Some(2150.0) },
+                Unit { bedrooms: Some(0), size: None, price: Some(1650.0) },
+            ]
+        );
+        assert_eq!(development.price_from, Some(1650.0));
+
+        // MyHome unit types
+        let myhome = json!([{"NumberOfBeds": 3, "SizeStringMeters": "98.5 m²", "PriceAsString": "POA"}]);
+        let development = from_json(&myhome).unwrap();
+        assert_eq!(development.units, [Unit { bedrooms: Some(3), size: Some(98.5), price: None }]);
+        assert_eq!(development.price_from, None);
+
+        assert_eq!(from_json(&json!([])), None);
+        assert_eq!(from_json(&json!("not units")), None);
+    }
+
+    #[test]
+    fn test_matches_on_one_unit() {
+        let mut property = listing("daft", "1");
+        property.address.normalized_address = "grand canal dock, dublin 2".to_string();
+        property.development = from_json(&json!([
+            {"bedrooms": 1, "price": 1900},
+            {"bedrooms": 2, "price": 2400},
+        ]));
+        let params = |bedrooms, max_price| DevelopmentParams { bedrooms, max_price, ..Default::default() };
+
+        assert!(params(Some(2), Some(2500.0)).matches(&property));
+        assert!(params(None, Some(2000.0)).matches(&property));
+        // The two-bed is over budget and the one-bed that isn't has too few rooms
+        assert!(!params(Some(2), Some(2000.0)).matches(&property));
+        assert!(DevelopmentParams { area: Some("Dublin 2".to_string()), ..Default::default() }.matches(&property));
+        assert!(!DevelopmentParams { area: Some("cork".to_string()), ..Default::default() }.matches(&property));
+
+        property.development = None;
+        assert!(!DevelopmentParams::default().matches(&property));
+    }
+}
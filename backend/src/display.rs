@@ -0,0 +1,140 @@
+//! Display strings for listing values.
+//!
+//! With `format_values=true` the rental endpoints add a `display` object to
+//! each listing holding its rent, size, bedrooms and bathrooms as text
+//! ("€1,850 / month", "72 m²", "2 beds"), in the language from `?lang=` or
+//! `Accept-Language` (see `locale`). The raw numbers are unchanged, so every
+//! frontend can show the same text without its own formatting rules.
+
+use serde::{Deserialize, Serialize};
+
+use crate::locale::Lang;
+use crate::StandardizedProperty;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DisplayValues {
+    pub price: String,
+    pub size: Option<String>,
+    pub bedrooms: Option<String>,
+    pub bathrooms: Option<String>,
+}
+
+/// "1,850" for 1850.4: whole units, grouped in thousands.
+fn grouped(amount: f64) -> String {
+    let digits = format!("{:.0}", amount.abs());
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// "€1,850"; currencies without a symbol here are written as their code,
+/// "USD 1,850".
+pub fn money(amount: f64, currency: &str) -> String {
+    let sign = if amount < 0.0 && amount.round() != 0.0 { "-" } else { "" };
+    match currency.to_ascii_uppercase().as_str() {
+        "EUR" | "" => format!("{}€{}", sign, grouped(amount)),
+        "GBP" => format!("{}£{}", sign, grouped(amount)),
+        code => format!("{}{} {}", sign, code, grouped(amount)),
+    }
+}
+
+/// "€1,850 / month" or "€1,850 sa mhí"; frequencies without a translation
+/// are used as they are.
+pub fn rent(lang: Lang, amount: f64, currency: &str, frequency: Option<&str>) -> String {
+    per(lang, money(amount, currency), frequency)
+}
+
+fn per(lang: Lang, amount: String, frequency: Option<&str>) -> String {
+    match (lang, frequency.map(str::to_ascii_lowercase).as_deref()) {
+        (_, None | Some("")) => amount,
+        (Lang::En, Some(frequency)) => format!("{} / {}", amount, frequency),
+        (Lang::Ga, Some("month")) => format!("{} sa mhí", amount),
+        (Lang::Ga, Some("week")) => format!("{} sa tseachtain", amount),
+        (Lang::Ga, Some(frequency)) => format!("{} / {}", amount, frequency),
+    }
+}
+
+/// "72 m²", "72.5 m²"
+pub fn size(value: f64, unit: &str) -> String {
+    match value.fract() == 0.0 {
+        true => format!("{:.0} {}", value, unit),
+        false => format!("{:.1} {}", value, unit),
+    }
+}
+
+pub fn bedrooms(lang: Lang, count: i32) -> String {
+    match (lang, count) {
+        (Lang::En, 0) => "Studio".to_string(),
+        (Lang::En, 1) => "1 bed".to_string(),
+        (Lang::En, n) => format!("{} beds", n),
+        (Lang::Ga, 0) => "Stiúideo".to_string(),
+        // "Seomraí leapa: 2" sidesteps the mutations Irish numerals need
+        (Lang::Ga, n) => format!("Seomraí leapa: {}", n),
+    }
+}
+
+pub fn bathrooms(lang: Lang, count: i32) -> String {
+    match (lang, count) {
+        (Lang::En, 1) => "1 bath".to_string(),
+        (Lang::En, n) => format!("{} baths", n),
+        (Lang::Ga, n) => format!("Seomraí folctha: {}", n),
+    }
+}
+
+pub fn values(lang: Lang, property: &StandardizedProperty) -> DisplayValues {
+    let price = &property.price;
+    DisplayValues {
+        price: match (price.min, price.max) {
+            (Some(min), Some(max)) => per(
+                lang,
+                format!("{}–{}", money(min, &price.currency), money(max, &price.currency)),
+                price.frequency.as_deref(),
+            ),
+            _ => rent(lang, price.amount, &price.currency, price.frequency.as_deref()),
+        },
+        size: property.size.as_ref().map(|s| size(s.value, &s.unit)),
+        bedrooms: property.bedrooms.map(|count| bedrooms(lang, count)),
+        bathrooms: property.bathrooms.map(|count| bathrooms(lang, count)),
+    }
+}
+
+/// Sets `display` on every listing.
+pub fn attach_all(properties: &mut [StandardizedProperty], lang: Lang) {
+    for property in properties {
+        property.display = Some(values(lang, property));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::listing;
+
+    #[test]
+    fn test_values() {
+        let mut property = listing("daft", "1");
+        property.price.amount = 1850.0;
+        property.size = Some(crate::Size { value: 72.0, unit: "m²".to_string() });
+        property.bedrooms = Some(2);
+        property.bathrooms = Some(1);
+        let display = values(Lang::En, &property);
+        assert_eq!(display.price, "€1,850 / month");
+        assert_eq!(display.size.as_deref(), Some("72 m²"));
+        assert_eq!((display.bedrooms.unwrap(), display.bathrooms.unwrap()), ("2 beds".to_string(), "1 bath".to_string()));
+        let display = values(Lang::Ga, &property);
+        assert_eq!((display.price.as_str(), display.bedrooms.unwrap()), ("€1,850 sa mhí", "Seomraí leapa: 2".to_string()));
+
+        assert_eq!(money(1234567.0, "EUR"), "€1,234,567");
+        assert_eq!(money(-950.0, "usd"), "-USD 950");
+        assert_eq!(rent(Lang::En, 420.0, "EUR", Some("week")), "€420 / week");
+        (property.price.min, property.price.max) = (Some(1800.0), Some(2200.0));
+        assert_eq!(values(Lang::En, &property).price, "€1,800–€2,200 / month");
+        assert_eq!(size(72.5, "m²"), "72.5 m²");
+        assert_eq!(bedrooms(Lang::En, 0), "Studio");
+    }
+}
@@ -0,0 +1,245 @@
+//! Outgoing email over SMTP.
+//!
+//! Speaks plain SMTP to `notifier.smtp`, with optional AUTH PLAIN. There is no
+//! TLS, so point it at a relay on the same host or network (postfix, msmtpd,
+//! a cloud provider's relay sidecar) rather than at a public mail server.
+
+use chrono::Utc;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use crate::config::SmtpConfig;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+    pub attachments: Vec<Attachment>,
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Base64 in 76-character lines, as MIME bodies require.
+fn base64_lines(data: &[u8]) -> String {
+    let encoded = base64(data);
+    let mut out = String::with_capacity(encoded.len() + encoded.len() / 76 * 2 + 2);
+    for line in encoded.as_bytes().chunks(76) {
+        out.push_str(std::str::from_utf8(line).unwrap_or_default());
+        out.push_str("\r\n");
+    }
+    out
+}
+
+/// A header value, as an RFC 2047 encoded word when it isn't plain ASCII.
+fn header_value(value: &str) -> String {
+    if value.is_ascii() {
+        value.to_string()
+    } else {
+        format!("=?UTF-8?B?{}?=", base64(value.as_bytes()))
+    }
+}
+
+/// The full message, with CRLF line endings and dot-stuffing for DATA.
+fn message(from: &str, email: &Email) -> String {
+    let boundary = format!("=_market_analysis_{}", Utc::now().timestamp_nanos_opt().unwrap_or_default());
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n\
+         Content-Type: multipart/mixed; boundary=\"{}\"\r\n\r\n",
+        from,
+        email.to,
+        header_value(&email.subject),
+        Utc::now().to_rfc2822(),
+        boundary
+    );
+    message.push_str(&format!(
+        "--{}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n{}",
+        boundary,
+        base64_lines(email.body.as_bytes())
+    ));
+    for attachment in &email.attachments {
+        message.push_str(&format!(
+            "--{}\r\nContent-Type: {}; name=\"{}\"\r\nContent-Disposition: attachment; filename=\"{}\"\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n{}",
+            boundary,
+            attachment.content_type,
+            attachment.filename,
+            attachment.filename,
+            base64_lines(&attachment.data)
+        ));
+    }
+    message.push_str(&format!("--{}--\r\n", boundary));
+    message
+        .split("\r\n")
+        .map(|line| if line.starts_with('.') { format!(".{}", line) } else { line.to_string() })
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+struct Session {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Session {
+    /// Reads a possibly multi-line reply and checks its code is `expected`xx.
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        let mut reply = String::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+                return Err("SMTP server closed the connection".to_string());
+            }
+            reply.push_str(&line);
+            // "250-..." continues, "250 ..." ends the reply
+            if line.as_bytes().get(3) != Some(&b'-') {
+                break;
+            }
+        }
+        if reply.starts_with(expected) {
+            Ok(())
+        } else {
+            Err(format!("SMTP server replied {}", reply.trim_end()))
+        }
+    }
+
+    fn command(&mut self, command: &str, expected: char) -> Result<(), String> {
+        self.writer.write_all(format!("{}\r\n", command).as_bytes()).map_err(|e| e.to_string())?;
+        self.expect(expected)
+    }
+}
+
+/// Sends `email` through the relay. Blocking.
+pub fn send(config: &SmtpConfig, email: &Email) -> Result<(), String> {
+    let stream = TcpStream::connect((config.host.as_str(), config.port))
+        .map_err(|e| format!("Could not connect to {}:{}: {}", config.host, config.port, e))?;
+    stream.set_read_timeout(Some(TIMEOUT)).map_err(|e| e.to_string())?;
+    stream.set_write_timeout(Some(TIMEOUT)).map_err(|e| e.to_string())?;
+    let mut session = Session {
+        reader: BufReader::new(stream.try_clone().map_err(|e| e.to_string())?),
+        writer: stream,
+    };
+
+    session.expect('2')?;
+    session.command("EHLO market-analysis", '2')?;
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        let credentials = base64(format!("\0{}\0{}", username, password).as_bytes());
+        session.command(&format!("AUTH PLAIN {}", credentials), '2')?;
+    }
+    session.command(&format!("MAIL FROM:<{}>", config.from), '2')?;
+    session.command(&format!("RCPT TO:<{}>", email.to), '2')?;
+    session.command("DATA", '3')?;
+    session
+        .writer
+        .write_all(message(&config.from, email).as_bytes())
+        .map_err(|e| e.to_string())?;
+    session.command(".", '2')?;
+    // The message is accepted; a failed QUIT doesn't matter
+    let _ = session.command("QUIT", '2');
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(header_value("Tuarascáil"), "=?UTF-8?B?VHVhcmFzY8OhaWw=?=");
+    }
+
+    #[test]
+    fn test_send_talks_smtp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut transcript = Vec::new();
+            writer.write_all(b"220 test ESMTP\r\n").unwrap();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_end().to_string();
+                let reply: &[u8] = if in_data {
+                    if line == "." {
+                        in_data = false;
+                        b"250 queued\r\n"
+                    } else {
+                        transcript.push(line);
+                        continue;
+                    }
+                } else if line.starts_with("EHLO") {
+                    b"250-test\r\n250 AUTH PLAIN\r\n"
+                } else if line == "DATA" {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line == "QUIT" {
+                    writer.write_all(b"221 bye\r\n").unwrap();
+                    break;
+                } else {
+                    transcript.push(line);
+                    b"250 ok\r\n"
+                };
+                writer.write_all(reply).unwrap();
+            }
+            transcript
+        });
+
+        let config = SmtpConfig { port, ..SmtpConfig::default() };
+        let email = Email {
+            to: "reports@example.com".to_string(),
+            subject: "Weekly digest".to_string(),
+            body: ".leading dot".to_string(),
+            attachments: vec![Attachment {
+                filename: "report.csv".to_string(),
+                content_type: "text/csv".to_string(),
+                data: b"a,b\n1,2\n".to_vec(),
+            }],
+        };
+        send(&config, &email).unwrap();
+
+        let transcript = server.join().unwrap();
+        assert_eq!(transcript[0], format!("MAIL FROM:<{}>", config.from));
+        assert_eq!(transcript[1], "RCPT TO:<reports@example.com>");
+        assert!(transcript.contains(&"Subject: Weekly digest".to_string()));
+        assert!(transcript.iter().any(|line| line.contains("filename=\"report.csv\"")));
+        assert!(transcript.contains(&base64(b"a,b\n1,2\n")));
+    }
+}
@@ -0,0 +1,190 @@
+//! `?explain=true` for rental search: describes how a query would be executed
+//! instead of running it.
+
+use chrono::NaiveDate;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::config::SourceConfig;
+use crate::pagination::Cursor;
+use crate::state::AppState;
+use crate::{find_latest_parquet, snapshot_date, SearchParams};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheStatus {
+    Hit,
+    Miss,
+    Disabled,
+    /// A superseded snapshot pinned by a token; streamed without caching.
+    Bypass,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FilterPlan {
+    pub filter: String,
+    pub value: String,
+    /// Whether row groups can be skipped using parquet column statistics.
+    /// Prices and the other filtered fields are parsed out of raw columns, so
+    /// every filter is currently applied after parsing.
+    pub pushed_down: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SourcePlan {
+    pub source: String,
+    pub file: Option<PathBuf>,
+    pub snapshot_date: Option<NaiveDate>,
+    pub pinned: bool,
+    pub cache: Option<CacheStatus>,
+    pub total_rows: usize,
+    pub row_groups: usize,
+    pub start_row: usize,
+    /// Rows that would be parsed from disk; zero when served from the cache.
+    pub rows_to_decode: usize,
+    pub compressed_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchPlan {
+    pub sources: Vec<SourcePlan>,
+    pub filters: Vec<FilterPlan>,
+    /// Unless the results are sorted the scan stops once the page is full, so
+    /// the real cost is usually below the estimate.
+    pub early_termination: bool,
+    pub estimated_rows_to_decode: usize,
+    pub estimated_bytes_to_read: u64,
+}
+
+/// Row and row-group counts plus compressed size from the parquet footer.
+fn footer_stats(path: &Path) -> Option<(usize, usize, u64)> {
+    let reader = SerializedFileReader::new(File::open(path).ok()?).ok()?;
+    let metadata = reader.metadata();
+    let rows = metadata.file_metadata().num_rows().max(0) as usize;
+    let bytes = metadata
+        .row_groups()
+        .iter()
+        .map(|group| group.compressed_size().max(0) as u64)
+        .sum();
+    Some((rows, metadata.num_row_groups(), bytes))
+}
+
+fn filters(params: &SearchParams) -> Vec<FilterPlan> {
+    let mut filters = Vec::new();
+    let mut add = |filter: &str, value: Option<String>| {
+        if let Some(value) = value {
+            filters.push(FilterPlan { filter: filter.to_string(), value, pushed_down: false });
+        }
+    };
+    add("min_price", params.min_price.map(|v| v.to_string()));
+    add("max_price", params.max_price.map(|v| v.to_string()));
+    add("bedrooms", params.bedrooms.map(|v| v.to_string()));
+    add("max_per_person", params.max_per_person.map(|v| v.to_string()));
+    add("price_band", params.price_band.clone());
+    add("property_type", params.property_type.clone());
+    add("ber_rating", params.ber_rating.clone());
+    add("location", params.location.clone());
+    filters
+}
+
+pub fn plan(
+    state: &AppState,
+    params: &SearchParams,
+    sources: &[&SourceConfig],
+    pinned: Option<&HashMap<String, PathBuf>>,
+    cursor: Option<&Cursor>,
+) -> SearchPlan {
+    let config = &state.config;
+    let mut plans = Vec::new();
+
+    for source in sources {
+        let latest = find_latest_parquet(&source.root(&config.data_path));
+        let pinned_file = pinned.and_then(|files| files.get(&source.name)).cloned();
+        let is_pinned = pinned_file.is_some();
+        let file = pinned_file.or_else(|| latest.clone());
+        let start_row = match cursor {
+            Some(cursor) if cursor.source == source.name => cursor.row + 1,
+            _ => 0,
+        };
+
+        let mut plan = SourcePlan {
+            source: source.name.clone(),
+            file: file.clone(),
+            snapshot_date: file.as_deref().and_then(snapshot_date),
+            pinned: is_pinned,
+            cache: None,
+            total_rows: 0,
+            row_groups: 0,
+            start_row,
+            rows_to_decode: 0,
+            compressed_bytes: 0,
+        };
+        if let Some(file) = &file {
+            let (rows, groups, bytes) = footer_stats(file).unwrap_or_default();
+            plan.total_rows = rows;
+            plan.row_groups = groups;
+            let cache = if latest.as_ref() != Some(file) {
+                CacheStatus::Bypass
+            } else if !state.cache.enabled() {
+                CacheStatus::Disabled
+            } else if state.cache.contains(&source.name, file) {
+                CacheStatus::Hit
+            } else {
+                CacheStatus::Miss
+            };
+            (plan.rows_to_decode, plan.compressed_bytes) = match cache {
+                CacheStatus::Hit => (0, 0),
+                // A miss parses the whole file to fill the cache
+                CacheStatus::Miss => (rows, bytes),
+                CacheStatus::Disabled | CacheStatus::Bypass => (rows.saturating_sub(start_row), bytes),
+            };
+            plan.cache = Some(cache);
+        }
+        plans.push(plan);
+    }
+
+    SearchPlan {
+        estimated_rows_to_decode: plans.iter().map(|p| p.rows_to_decode).sum(),
+        estimated_bytes_to_read: plans.iter().map(|p| p.compressed_bytes).sum(),
+        early_termination: !params.ranked(),
+        filters: filters(params),
+        sources: plans,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::test_utils::{standardized_batch, stores_in, temp_dir, write_parquet};
+    use std::ops::ControlFlow;
+
+    #[test]
+    fn test_plan_reports_files_and_cache_status() {
+        let data = temp_dir("explain");
+        let file = data.join("processed/rent_ie/2024/11/05/rent_ie_120000.parquet");
+        write_parquet(&file, &standardized_batch(&["1", "2", "3"]));
+        let config = Config::from_toml(&format!("data_path = {:?}\n[[sources]]\nname = \"rent_ie\"", data)).unwrap();
+        let state = AppState::new(stores_in(config, &data));
+        let params: SearchParams = serde_json::from_str(r#"{"source": "rent_ie", "max_price": 2000, "limit": 10}"#).unwrap();
+        let config = state.config.clone();
+        let sources = config.select_sources(Some("rent_ie"));
+
+        let cold = plan(&state, &params, &sources, None, None);
+        let source = &cold.sources[0];
+        assert_eq!(source.file.as_ref(), Some(&file));
+        assert_eq!(source.snapshot_date, NaiveDate::from_ymd_opt(2024, 11, 5));
+        assert_eq!((source.total_rows, source.row_groups, source.rows_to_decode), (3, 1, 3));
+        assert!(matches!(source.cache, Some(CacheStatus::Miss)));
+        assert_eq!(cold.filters.len(), 1);
+        assert!(cold.early_termination);
+
+        state.cache.scan(sources[0], &file, 0, |_, _| ControlFlow::Continue(()));
+        let warm = plan(&state, &params, &sources, None, None);
+        assert!(matches!(warm.sources[0].cache, Some(CacheStatus::Hit)));
+        assert_eq!(warm.estimated_rows_to_decode, 0);
+    }
+}
@@ -0,0 +1,227 @@
+//! Export jobs.
+//!
+//! Exports too big to build within a request run on the job queue and leave a
+//! file under `exports.path`. `POST /api/exports/panel` with
+//!
+//! ```json
+//! { "area": "dublin 6", "from": "2024-01-01", "to": "2024-12-31" }
+//! ```
+//!
+//! queues the panel of an area: one row per listing per snapshot day between
+//! the dates, in the anonymized columns of `dataset`, as zstd-compressed
+//! parquet. The response's `id` is polled at `/api/exports/{id}` until its
+//! status is `succeeded`; the file is then at `/api/exports/{id}/download`.
+
+use axum::extract::{Path as UrlPath, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::NaiveDate;
+use log::info;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::address;
+use crate::config::Config;
+use crate::dataset::{Dataset, Observation};
+use crate::jobs::{Job, JobStatus};
+use crate::state::AppState;
+use crate::store::PropertyStore;
+use crate::validate_price;
+
+/// Job kind that writes an area's panel.
+pub const JOB: &str = "export.panel";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PanelRequest {
+    /// Any location the search filter accepts.
+    pub area: String,
+    /// Snapshot dates, both inclusive.
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub source: Option<String>,
+}
+
+/// Job payload: the request and the file it is written to.
+#[derive(Debug, Serialize, Deserialize)]
+struct PanelJob {
+    request: PanelRequest,
+    file: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportStatus {
+    pub id: i64,
+    pub status: JobStatus,
+    pub request: serde_json::Value,
+    pub error: Option<String>,
+    /// Where to fetch the file once the export has succeeded.
+    pub download: Option<String>,
+}
+
+impl ExportStatus {
+    fn of(job: &Job) -> Self {
+        ExportStatus {
+            id: job.id,
+            status: job.status,
+            request: job.payload["request"].clone(),
+            error: job.last_error.clone(),
+            download: (job.status == JobStatus::Succeeded).then(|| format!("/api/exports/{}/download", job.id)),
+        }
+    }
+}
+
+/// Listing-day observations of `request.area` in the snapshots between its
+/// dates, oldest first.
+pub fn panel(config: &Config, store: &dyn PropertyStore, request: &PanelRequest) -> Vec<Observation> {
+    let mut observations = Vec::new();
+    for source in config.source_names(request.source.as_deref()) {
+        for date in store.snapshots(source).into_iter().filter(|date| (request.from..=request.to).contains(date)) {
+            observations.extend(
+                store
+                    .listings(source, date)
+                    .into_iter()
+                    .filter(validate_price)
+                    .filter(|p| address::in_location(&p.address.normalized_address, &request.area))
+                    .map(|property| Observation { snapshot: date, property }),
+            );
+        }
+    }
+    observations.sort_by_key(|observation| observation.snapshot);
+    observations
+}
+
+/// Writes the panel of `request` to `path` through a temporary file, so a
+/// half-written export is never served. Returns the number of rows.
+pub fn write_panel(
+    config: &Config,
+    store: &dyn PropertyStore,
+    request: &PanelRequest,
+    path: &Path,
+) -> Result<usize, String> {
+    let observations = panel(config, store, request);
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .build();
+    let parquet = Dataset::build(config, &observations).into_parquet_with(properties)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+    }
+    let partial = path.with_extension("parquet.partial");
+    fs::write(&partial, parquet).map_err(|e| format!("Failed to write {:?}: {}", partial, e))?;
+    fs::rename(&partial, path).map_err(|e| format!("Failed to move {:?} into place: {}", partial, e))?;
+    Ok(observations.len())
+}
+
+pub fn run_job(state: &AppState, payload: &serde_json::Value) -> Result<(), String> {
+    let job: PanelJob = serde_json::from_value(payload.clone()).map_err(|e| e.to_string())?;
+    let path = state.config.exports.path.join(&job.file);
+    let rows = write_panel(&state.config, state.store.as_ref(), &job.request, &path)?;
+    info!("Exported {} panel rows for {:?} to {:?}", rows, job.request.area, path);
+    Ok(())
+}
+
+fn validate(config: &Config, request: &PanelRequest) -> Result<(), (StatusCode, String)> {
+    if address::normalize(&request.area).is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "area is required".to_string()));
+    }
+    if request.from > request.to {
+        return Err((StatusCode::BAD_REQUEST, format!("from ({}) is after to ({})", request.from, request.to)));
+    }
+    if let Some(source) = &request.source {
+        config.resolve_source(source).ok_or((StatusCode::BAD_REQUEST, format!("Unknown source {:?}", source)))?;
+    }
+    Ok(())
+}
+
+pub async fn create_panel(
+    State(state): State<AppState>,
+    Json(request): Json<PanelRequest>,
+) -> Result<(StatusCode, Json<ExportStatus>), (StatusCode, String)> {
+    validate(&state.config, &request)?;
+    let file = format!("panel_{}.parquet", state.clock.now().format("%Y%m%dT%H%M%S%.3f"));
+    let internal = |e: String| (StatusCode::INTERNAL_SERVER_ERROR, e);
+    let id = state.jobs.enqueue(JOB, &PanelJob { request, file }).map_err(internal)?;
+    let job = state.jobs.get(id).map_err(internal)?.ok_or((StatusCode::NOT_FOUND, String::new()))?;
+    Ok((StatusCode::ACCEPTED, Json(ExportStatus::of(&job))))
+}
+
+/// The export job `id`; other kinds of job aren't exports.
+fn export_job(state: &AppState, id: i64) -> Result<Job, (StatusCode, String)> {
+    state
+        .jobs
+        .get(id)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .filter(|job| job.kind == JOB)
+        .ok_or((StatusCode::NOT_FOUND, format!("No export {}", id)))
+}
+
+pub async fn get_export(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<i64>,
+) -> Result<Json<ExportStatus>, (StatusCode, String)> {
+    Ok(Json(ExportStatus::of(&export_job(&state, id)?)))
+}
+
+pub async fn download(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<i64>,
+) -> Result<Response, (StatusCode, String)> {
+    let job = export_job(&state, id)?;
+    if job.status != JobStatus::Succeeded {
+        return Err((StatusCode::CONFLICT, format!("Export {} hasn't succeeded; see /api/exports/{}", id, id)));
+    }
+    let file: PathBuf = job.payload["file"].as_str().map(PathBuf::from).unwrap_or_default();
+    let body = fs::read(state.config.exports.path.join(&file))
+        .map_err(|_| (StatusCode::GONE, format!("Export {} is no longer available", id)))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/vnd.apache.parquet".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file.display())),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::test_utils::{listing, temp_dir};
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    #[test]
+    fn test_panel_covers_area_and_dates() {
+        let config = Config::default();
+        let store = MemoryStore::default();
+        let rental = |id: &str, area: &str| {
+            let mut property = listing("daft", id);
+            property.address.normalized_address = format!("{} main street, {}", id, area);
+            property
+        };
+        for day in 4..=7 {
+            let date = NaiveDate::from_ymd_opt(2024, 11, day).unwrap();
+            let listings = [rental("1", "dublin 6"), rental("2", "dublin 8"), rental("3", "dublin 6")];
+            store.ingest("daft", date, &listings).unwrap();
+        }
+        let request = PanelRequest {
+            area: "Dublin 6".to_string(),
+            from: NaiveDate::from_ymd_opt(2024, 11, 5).unwrap(),
+            to: NaiveDate::from_ymd_opt(2024, 11, 6).unwrap(),
+            source: None,
+        };
+        let observations = panel(&config, &store, &request);
+        assert_eq!(observations.len(), 4);
+        assert_eq!(observations[0].snapshot, request.from);
+
+        let path = temp_dir("exports").join("panel.parquet");
+        assert_eq!(write_panel(&config, &store, &request, &path).unwrap(), 4);
+        let reader = SerializedFileReader::new(fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 4);
+        assert_eq!(reader.metadata().row_group(0).column(0).compression(), Compression::ZSTD(ZstdLevel::default()));
+    }
+}
@@ -0,0 +1,90 @@
+//! Serving an older snapshot when a source's latest one can't be read.
+//!
+//! A corrupt latest file used to leave its source with no listings until the
+//! pipeline wrote the next day. Search and the store now walk back to the most
+//! recent partition whose file opens, going at most
+//! `search.fallback_max_age_days` before the latest. Search reports the switch
+//! as a `fallback` warning, and its `x-snapshot-dates` header gives the
+//! snapshot date each source was actually served from.
+
+use chrono::Duration;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::cache::SnapshotCache;
+use crate::config::{Config, SourceConfig};
+use crate::{find_latest_parquet, list_snapshots, snapshot_date};
+
+/// The snapshot file to serve for a source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Serving {
+    pub file: PathBuf,
+    /// The unreadable latest file `file` stands in for.
+    pub replaces: Option<PathBuf>,
+}
+
+/// Whether the parquet footer of `path` parses.
+pub fn readable(path: &Path) -> bool {
+    File::open(path).ok().and_then(|file| ParquetRecordBatchReaderBuilder::try_new(file).ok()).is_some()
+}
+
+/// The newest snapshot before `latest` that passes `readable`, dated at most
+/// `max_age_days` before it.
+fn previous(root: &Path, latest: &Path, max_age_days: u32, readable: impl Fn(&Path) -> bool) -> Option<PathBuf> {
+    let oldest = snapshot_date(latest)? - Duration::days(i64::from(max_age_days));
+    list_snapshots(root)
+        .into_iter()
+        .rev()
+        .filter(|(date, file)| file != latest && *date >= oldest)
+        .map(|(_, file)| file)
+        .find(|file| readable(file))
+}
+
+/// The latest snapshot of `source` when it opens, otherwise the most recent
+/// one that does. A latest file that can't be read and has nothing to fall
+/// back on is still returned, so its reader reports the failure.
+pub fn serving(config: &Config, cache: &SnapshotCache, source: &SourceConfig) -> Option<Serving> {
+    let root = source.root(&config.data_path);
+    let latest = find_latest_parquet(&root)?;
+    // A cached snapshot has been read already
+    if cache.contains(&source.name, &latest) || readable(&latest) {
+        return Some(Serving { file: latest, replaces: None });
+    }
+    match previous(&root, &latest, config.search.fallback_max_age_days, readable) {
+        Some(file) => Some(Serving { file, replaces: Some(latest) }),
+        None => Some(Serving { file: latest, replaces: None }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{standardized_batch, temp_dir, write_parquet};
+
+    #[test]
+    fn test_walks_back_to_a_readable_snapshot() {
+        let mut config = Config { data_path: temp_dir("fallback"), ..Default::default() };
+        config.search.fallback_max_age_days = 3;
+        let source = config.resolve_source("daft").unwrap().clone();
+        let root = source.root(&config.data_path);
+        let file = |day: u32| root.join(format!("2024/11/{:02}/daft_120000.parquet", day));
+        for day in [1, 4, 5, 6] {
+            write_parquet(&file(day), &standardized_batch(&["1"]));
+        }
+        // Magic intact, footer length garbled
+        let mut bytes = std::fs::read(file(6)).unwrap();
+        let length = bytes.len() - 8;
+        bytes[length..length + 4].fill(0xff);
+        std::fs::write(file(6), &bytes).unwrap();
+        assert!(!readable(&file(6)));
+
+        let cache = SnapshotCache::new(0);
+        let served = serving(&config, &cache, &source).unwrap();
+        assert_eq!(served, Serving { file: file(5), replaces: Some(file(6)) });
+
+        // Only the oldest is readable, outside a 3-day window
+        assert_eq!(previous(&root, &file(6), 3, |f| f == file(1)), None);
+        assert_eq!(previous(&root, &file(6), 5, |f| f == file(1)), Some(file(1)));
+    }
+}
@@ -0,0 +1,255 @@
+//! Sequenced change feed.
+//!
+//! `/api/rentals/changes` only has the latest snapshot's delta, so a system
+//! syncing from it must poll before the next snapshot lands and can't tell
+//! what it missed. Each snapshot delta is also appended to a feed at
+//! `deltas.feed_path`, one event per change, numbered in the order recorded:
+//! `created`, `updated`, `price_changed` or `delisted`.
+//! `GET /api/changes?since_seq=12345` returns the events after 12345 oldest
+//! first; consumers keep the `last_seq` of each page and pass it back as
+//! `since_seq`.
+
+use axum::extract::{Query, State};
+use axum::Json;
+use chrono::NaiveDate;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::auth::Caller;
+use crate::deltas::{self, ChangeKind, ListingChange, SnapshotDelta};
+use crate::locale::Lang;
+use crate::state::AppState;
+use crate::{find_latest_parquet, snapshot_date, StandardizedProperty};
+
+const DEFAULT_LIMIT: usize = 1000;
+const MAX_LIMIT: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Created,
+    Updated,
+    /// The rent changed, possibly along with other fields.
+    PriceChanged,
+    Delisted,
+}
+
+impl EventKind {
+    fn of(change: &ListingChange) -> Self {
+        match change.kind {
+            ChangeKind::New => EventKind::Created,
+            ChangeKind::Removed => EventKind::Delisted,
+            ChangeKind::Changed if change.fields.iter().any(|field| field == "price") => EventKind::PriceChanged,
+            ChangeKind::Changed => EventKind::Updated,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedEvent {
+    pub seq: u64,
+    pub event: EventKind,
+    pub source: String,
+    /// Snapshot the change was seen in.
+    pub date: NaiveDate,
+    pub property_id: String,
+    /// Tracked fields that differ; see `deltas::TRACKED_FIELDS`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_price: Option<f64>,
+    /// The listing as it is now, or as it last was when delisted.
+    pub listing: StandardizedProperty,
+}
+
+#[derive(Default)]
+struct Events {
+    events: Vec<FeedEvent>,
+    /// Latest snapshot date recorded per source.
+    recorded: HashMap<String, NaiveDate>,
+}
+
+pub struct ChangeFeed {
+    path: Option<PathBuf>,
+    events: Mutex<Events>,
+}
+
+impl ChangeFeed {
+    /// Loads existing events from `path`. `None` keeps the feed in memory only.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let events: Vec<FeedEvent> = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| contents.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+            .unwrap_or_default();
+        let mut recorded: HashMap<String, NaiveDate> = HashMap::new();
+        for event in &events {
+            let date = recorded.entry(event.source.clone()).or_insert(event.date);
+            *date = (*date).max(event.date);
+        }
+        ChangeFeed { path, events: Mutex::new(Events { events, recorded }) }
+    }
+
+    /// Whether the feed has `source`'s snapshot of `date`, or a later one.
+    pub fn has(&self, source: &str, date: NaiveDate) -> bool {
+        self.events.lock().unwrap().recorded.get(source).is_some_and(|recorded| *recorded >= date)
+    }
+
+    /// Appends the changes of `delta` unless its snapshot is already in the
+    /// feed. Returns how many events were added.
+    pub fn record(&self, delta: &SnapshotDelta) -> usize {
+        let Some(date) = delta.date else {
+            return 0;
+        };
+        let mut events = self.events.lock().unwrap();
+        if events.recorded.get(&delta.source).is_some_and(|recorded| *recorded >= date) {
+            return 0;
+        }
+        let first = events.events.last().map_or(1, |event| event.seq + 1);
+        let added: Vec<FeedEvent> = delta
+            .changes
+            .iter()
+            .zip(first..)
+            .map(|(change, seq)| FeedEvent {
+                seq,
+                event: EventKind::of(change),
+                source: delta.source.clone(),
+                date,
+                property_id: change.property_id.clone(),
+                fields: change.fields.clone(),
+                previous_price: change.previous_price,
+                listing: change.listing.clone(),
+            })
+            .collect();
+        if let Some(path) = &self.path {
+            if let Err(e) = append(path, &added) {
+                error!("Could not append {} changes to {:?}: {}", added.len(), path, e);
+            }
+        }
+        let count = added.len();
+        events.recorded.insert(delta.source.clone(), date);
+        events.events.extend(added);
+        count
+    }
+
+    /// Up to `limit` events after `seq`, oldest first.
+    pub fn since(&self, seq: u64, limit: usize) -> Vec<FeedEvent> {
+        let events = self.events.lock().unwrap();
+        let start = events.events.partition_point(|event| event.seq <= seq);
+        events.events[start..].iter().take(limit).cloned().collect()
+    }
+
+    pub fn last_seq(&self) -> u64 {
+        self.events.lock().unwrap().events.last().map_or(0, |event| event.seq)
+    }
+}
+
+fn append(path: &Path, events: &[FeedEvent]) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let mut lines = String::new();
+    for event in events {
+        lines.push_str(&serde_json::to_string(event).map_err(|e| e.to_string())?);
+        lines.push('\n');
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path).map_err(|e| e.to_string())?;
+    file.write_all(lines.as_bytes()).map_err(|e| e.to_string())
+}
+
+/// Records the delta of every enabled source's latest snapshot not yet in
+/// the feed.
+pub fn sync(state: &AppState) {
+    let config = &state.config;
+    for source in config.select_sources(None) {
+        let Some(latest) = find_latest_parquet(&source.root(&config.data_path)) else {
+            continue;
+        };
+        if snapshot_date(&latest).is_some_and(|date| state.feed.has(&source.name, date)) {
+            continue;
+        }
+        if let Some(delta) = SnapshotDelta::load_or_build(config, source, &latest) {
+            state.feed.record(&delta);
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedParams {
+    #[serde(default)]
+    since_seq: u64,
+    limit: Option<usize>,
+    /// Add display strings to the listings; see `display`.
+    #[serde(default)]
+    format_values: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeedPage {
+    pub events: Vec<FeedEvent>,
+    /// `since_seq` for the next page; the one given when nothing is new.
+    pub last_seq: u64,
+    /// Whether there are events after `last_seq` already.
+    pub more: bool,
+}
+
+pub async fn changes(
+    State(state): State<AppState>,
+    caller: Caller,
+    lang: Lang,
+    Query(params): Query<FeedParams>,
+) -> Json<FeedPage> {
+    sync(&state);
+    let mut events = state.feed.since(params.since_seq, params.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT));
+    for event in &mut events {
+        let delisted = event.event == EventKind::Delisted;
+        deltas::present(&state, &caller, lang, &mut event.listing, delisted, params.format_values);
+    }
+    let last_seq = events.last().map_or(params.since_seq, |event| event.seq);
+    Json(FeedPage { more: state.feed.last_seq() > last_seq, last_seq, events })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::test_utils::{standardized_batch, temp_dir, write_parquet};
+
+    #[test]
+    fn test_deltas_are_sequenced_once() {
+        let data = temp_dir("feed");
+        let config = Config::from_toml(&format!("data_path = {:?}\n[[sources]]\nname = \"rent_ie\"", data)).unwrap();
+        let source = config.resolve_source("rent_ie").unwrap();
+        let first = data.join("processed/rent_ie/2024/11/05/rent_ie_120000.parquet");
+        let second = data.join("processed/rent_ie/2024/11/06/rent_ie_120000.parquet");
+        write_parquet(&first, &standardized_batch(&["1", "2"]));
+        write_parquet(&second, &standardized_batch(&["2", "3", "4"]));
+
+        let path = data.join("feed.jsonl");
+        let feed = ChangeFeed::open(Some(path.clone()));
+        assert_eq!(feed.record(&SnapshotDelta::build(&config, source, &first).unwrap()), 2);
+        let delta = SnapshotDelta::build(&config, source, &second).unwrap();
+        assert_eq!(feed.record(&delta), 4);
+        assert_eq!(feed.record(&delta), 0);
+
+        let reopened = ChangeFeed::open(Some(path));
+        assert_eq!(reopened.last_seq(), 6);
+        assert!(reopened.has("rent_ie", NaiveDate::from_ymd_opt(2024, 11, 6).unwrap()));
+        let events: Vec<_> = reopened.since(2, 10).into_iter().map(|e| (e.seq, e.event)).collect();
+        assert_eq!(
+            events,
+            [
+                (3, EventKind::PriceChanged),
+                (4, EventKind::Created),
+                (5, EventKind::Created),
+                (6, EventKind::Delisted),
+            ]
+        );
+        assert_eq!(reopened.since(6, 10).len(), 0);
+    }
+}
@@ -0,0 +1,455 @@
+//! `generate-fixtures`: synthetic snapshots for local development.
+//!
+//! Writes MyHome, Daft and property.ie snapshots in their raw collector
+//! schemas, laid out like the real data lake, so the full stack runs without
+//! scraped data. Listings get plausible Irish addresses, rents driven by area,
+//! size and BER, and a realistic BER mix; about a quarter of Daft and
+//! property.ie listings are the same homes advertised on MyHome. Output is
+//! deterministic for a given `--seed`.
+//!
+//! ```text
+//! main generate-fixtures [--rows 1000] [--days 1] [--seed 42] [--out DIR]
+//! ```
+
+use arrow::array::{
+    new_null_array, Array, ArrayRef, BooleanArray, Float64Array, Int64Array, ListArray, ListBuilder, RecordBatch,
+    StringArray, StringBuilder, StructArray,
+};
+use arrow::buffer::OffsetBuffer;
+use arrow::datatypes::{DataType, Field};
+use chrono::{Duration, NaiveDate};
+use parquet::arrow::ArrowWriter;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::ber;
+use crate::clock::{Clock, SystemClock};
+use crate::config::Config;
+
+/// Small deterministic generator (SplitMix64); fixtures don't need more.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+
+    fn weighted<'a, T>(&mut self, items: &'a [(T, u32)]) -> &'a T {
+        let total: u32 = items.iter().map(|(_, weight)| weight).sum();
+        let mut target = self.below(total as usize) as u32;
+        for (item, weight) in items {
+            if target < *weight {
+                return item;
+            }
+            target -= weight;
+        }
+        &items[items.len() - 1].0
+    }
+
+    /// Approximately standard normal.
+    fn normal(&mut self) -> f64 {
+        (0..12).map(|_| self.unit()).sum::<f64>() - 6.0
+    }
+}
+
+/// A locality with the typical rent of a two-bed home there.
+#[derive(Debug)]
+struct Area {
+    locality: &'static str,
+    town: &'static str,
+    rent: f64,
+}
+
+const AREAS: &[Area] = &[
+    Area { locality: "Ranelagh", town: "Dublin 6", rent: 2650.0 },
+    Area { locality: "Rathmines", town: "Dublin 6", rent: 2450.0 },
+    Area { locality: "Ballsbridge", town: "Dublin 4", rent: 2900.0 },
+    Area { locality: "Smithfield", town: "Dublin 7", rent: 2350.0 },
+    Area { locality: "Drumcondra", town: "Dublin 9", rent: 2200.0 },
+    Area { locality: "Clontarf", town: "Dublin 3", rent: 2400.0 },
+    Area { locality: "Tallaght", town: "Dublin 24", rent: 1950.0 },
+    Area { locality: "Blanchardstown", town: "Dublin 15", rent: 2000.0 },
+    Area { locality: "Dún Laoghaire", town: "Co. Dublin", rent: 2550.0 },
+    Area { locality: "Swords", town: "Co. Dublin", rent: 1950.0 },
+    Area { locality: "Douglas", town: "Cork", rent: 1850.0 },
+    Area { locality: "Ballincollig", town: "Cork", rent: 1700.0 },
+    Area { locality: "Salthill", town: "Galway", rent: 1800.0 },
+    Area { locality: "Knocknacarra", town: "Galway", rent: 1650.0 },
+    Area { locality: "Castletroy", town: "Limerick", rent: 1550.0 },
+    Area { locality: "Ballybricken", town: "Waterford", rent: 1300.0 },
+    Area { locality: "Naas", town: "Co. Kildare", rent: 1750.0 },
+    Area { locality: "Maynooth", town: "Co. Kildare", rent: 1800.0 },
+    Area { locality: "Drogheda", town: "Co. Louth", rent: 1500.0 },
+    Area { locality: "Athlone", town: "Co. Westmeath", rent: 1250.0 },
+    Area { locality: "Letterkenny", town: "Co. Donegal", rent: 1050.0 },
+    Area { locality: "Sligo", town: "Co. Sligo", rent: 1150.0 },
+];
+
+const STREETS: &[&str] = &[
+    "Main Street", "Church Road", "Harbour View", "Castle Avenue", "Station Road", "The Crescent", "Orchard Lane",
+    "Grange Park", "Abbey Court", "Mill Street", "Bóthar na Trá", "Meadow Vale", "Seafield Road", "Oak Drive",
+    "Willow Grove", "College Green", "Sráid an Teampaill", "Riverside Walk", "Beech Hill", "Ashfield Close",
+];
+
+/// Agent name, email domain and phone number.
+const AGENTS: &[(&str, &str, &str)] = &[
+    ("Liffey Lettings", "liffeylettings.ie", "01 555 0142"),
+    ("Corrib Property Management", "corribpm.ie", "091 555 017"),
+    ("Lee Valley Estates", "leevalley.ie", "021 555 0188"),
+    ("Shannon Residential", "shannonresidential.ie", "061 555 032"),
+    ("Brennan & Walsh Auctioneers", "brennanwalsh.ie", "045 555 071"),
+    ("Quayside Rentals", "quaysiderentals.ie", "01 555 0199"),
+];
+
+const BER_MIX: &[(&str, u32)] = &[
+    ("A1", 2), ("A2", 5), ("A3", 6), ("B1", 6), ("B2", 8), ("B3", 12), ("C1", 12), ("C2", 11), ("C3", 9),
+    ("D1", 8), ("D2", 6), ("E1", 3), ("E2", 3), ("F", 2), ("G", 2), ("", 4), ("SI_666", 1), ("Exempt", 1),
+];
+
+/// Property type, how common it is, and its bedroom range.
+const TYPES: &[((&str, i64, i64), u32)] = &[
+    (("Apartment", 1, 3), 45),
+    (("Studio", 0, 0), 8),
+    (("House", 2, 5), 25),
+    (("Terraced House", 2, 4), 10),
+    (("Semi-Detached House", 3, 4), 8),
+    (("Duplex", 2, 3), 4),
+];
+
+#[derive(Debug, Clone)]
+struct Listing {
+    house_number: usize,
+    street: &'static str,
+    area: &'static Area,
+    property_type: &'static str,
+    bedrooms: i64,
+    bathrooms: i64,
+    size: f64,
+    ber: &'static str,
+    rent: f64,
+    agent: &'static (&'static str, &'static str, &'static str),
+    listed_days_ago: i64,
+    photos: usize,
+    has_video: bool,
+}
+
+impl Listing {
+    fn random(rng: &mut Rng) -> Self {
+        let area = rng.pick(AREAS);
+        let street = *rng.pick(STREETS);
+        let &(property_type, min_beds, max_beds) = rng.weighted(TYPES);
+        let bedrooms = min_beds + rng.below((max_beds - min_beds + 1) as usize) as i64;
+        let bathrooms = (1 + bedrooms / 2 + rng.below(2) as i64 * (bedrooms / 3)).max(1);
+        let size = (28.0 + 22.0 * bedrooms as f64) * (1.0 + 0.12 * rng.normal());
+        let ber = *rng.weighted(BER_MIX);
+
+        let bed_factor = [0.7, 0.8, 1.0, 1.2, 1.38, 1.55][bedrooms as usize];
+        let type_factor = if property_type.contains("House") { 1.05 } else { 1.0 };
+        let ber_factor = match ber::normalize(Some(ber)).0.and_then(|rating| rating.chars().next()) {
+            Some('A') => 1.06,
+            Some('B') => 1.03,
+            Some('C') => 1.0,
+            Some('D') => 0.97,
+            Some(_) => 0.93,
+            None => 0.98,
+        };
+        let noise = (0.11 * rng.normal()).exp();
+        let rent = (area.rent * bed_factor * type_factor * ber_factor * noise / 25.0).round() * 25.0;
+
+        Listing {
+            house_number: 1 + rng.below(120),
+            street,
+            area,
+            property_type,
+            bedrooms,
+            bathrooms,
+            size: size.max(20.0).round(),
+            ber,
+            rent,
+            agent: rng.pick(AGENTS),
+            listed_days_ago: rng.below(60) as i64,
+            photos: 3 + rng.below(12),
+            has_video: rng.below(5) == 0,
+        }
+    }
+
+    fn address(&self) -> String {
+        format!("{} {}, {}, {}", self.house_number, self.street, self.area.locality, self.area.town)
+    }
+
+    fn slug(&self) -> String {
+        let mut slug = String::new();
+        for c in format!("{} {}", self.property_type, self.address()).to_lowercase().chars() {
+            if c.is_alphanumeric() {
+                slug.push(c);
+            } else if !slug.ends_with('-') {
+                slug.push('-');
+            }
+        }
+        slug.trim_matches('-').to_string()
+    }
+
+    /// Rent on `day` of the generated history: drifts upwards by roughly 0.2%
+    /// a day, to the nearest €5.
+    fn rent_on(&self, day: usize) -> f64 {
+        (self.rent * (1.0 + 0.002 * day as f64) / 5.0).round() * 5.0
+    }
+}
+
+fn euros(amount: f64) -> String {
+    let whole = amount.round() as i64;
+    if whole >= 1000 {
+        format!("€{},{:03}", whole / 1000, whole % 1000)
+    } else {
+        format!("€{}", whole)
+    }
+}
+
+fn strings(values: impl IntoIterator<Item = String>) -> ArrayRef {
+    Arc::new(StringArray::from_iter_values(values))
+}
+
+fn myhome_batch(listings: &[Listing], day: usize, date: NaiveDate) -> RecordBatch {
+    let n = listings.len();
+    let id = |i: usize| 4_000_000 + i as i64;
+    let listed = |l: &Listing| (date - Duration::days(l.listed_days_ago)).format("%Y-%m-%dT09:30:00").to_string();
+    let photo = |i: usize, k: usize| format!("https://photos.myhome.ie/{}/{}.jpg", id(i), k);
+
+    let mut photos = ListBuilder::new(StringBuilder::new());
+    for (i, listing) in listings.iter().enumerate() {
+        photos.append_value((0..listing.photos).map(|k| Some(photo(i, k))));
+    }
+    let columns: Vec<(&str, ArrayRef)> = vec![
+        ("PropertyId", Arc::new(Int64Array::from_iter_values((0..n).map(id)))),
+        ("RefreshedOn", strings(std::iter::repeat_n(format!("{}T07:00:00", date), n))),
+        ("GroupPhoneNumber", strings(listings.iter().map(|l| l.agent.2.to_string()))),
+        ("GroupEmail", strings(listings.iter().map(|l| format!("lettings@{}", l.agent.1)))),
+        ("GroupName", strings(listings.iter().map(|l| l.agent.0.to_string()))),
+        ("GroupAddress", strings(listings.iter().map(|l| format!("1 Main Street, {}", l.area.town)))),
+        ("CreatedOnDate", strings(listings.iter().map(listed))),
+        ("IsActive", Arc::new(BooleanArray::from(vec![true; n]))),
+        ("HasVideos", Arc::new(BooleanArray::from_iter(listings.iter().map(|l| Some(l.has_video))))),
+        ("NumberOfBeds", Arc::new(Int64Array::from_iter_values(listings.iter().map(|l| l.bedrooms)))),
+        ("PriceAsString", strings(listings.iter().map(|l| format!("{} per month", euros(l.rent_on(day)))))),
+        ("SizeStringMeters", Arc::new(Float64Array::from_iter_values(listings.iter().map(|l| l.size)))),
+        ("DisplayAddress", strings(listings.iter().map(Listing::address))),
+        ("PropertyType", strings(listings.iter().map(|l| l.property_type.to_string()))),
+        ("NumberOfBathrooms", Arc::new(Int64Array::from_iter_values(listings.iter().map(|l| l.bathrooms)))),
+        ("BerRating", strings(listings.iter().map(|l| l.ber.to_string()))),
+        ("SeoUrl", strings(listings.iter().enumerate().map(|(i, l)| format!("/rentals/{}/{}", l.slug(), id(i))))),
+        ("MainPhoto", strings((0..n).map(|i| photo(i, 0)))),
+        ("Photos", Arc::new(photos.finish())),
+    ];
+
+    // The collector's schema has 64 columns; only the mapped ones get values
+    let positions = [0, 3, 6, 7, 8, 9, 11, 28, 31, 36, 37, 40, 42, 46, 48, 49, 55, 61, 63];
+    let mut columns = columns.into_iter();
+    let mut padded: Vec<(String, ArrayRef)> = Vec::with_capacity(64);
+    for position in 0..64 {
+        match positions.contains(&position).then(|| columns.next()).flatten() {
+            Some((name, array)) => padded.push((name.to_string(), array)),
+            None => padded.push((format!("Unused{}", position), new_null_array(&DataType::Utf8, n))),
+        }
+    }
+    RecordBatch::try_from_iter(padded).expect("MyHome fixture schema is consistent")
+}
+
+fn daft_batch(listings: &[Listing], day: usize, date: NaiveDate) -> RecordBatch {
+    let n = listings.len();
+    let id = |i: usize| (5_000_000 + i).to_string();
+    let field = |name: &str, data_type: DataType| Arc::new(Field::new(name, data_type, true));
+
+    let ber = StructArray::from(vec![
+        (field("code", DataType::Utf8), strings(listings.iter().map(|l| format!("1{:08}", l.house_number * 7919)))),
+        (field("epi", DataType::Utf8), new_null_array(&DataType::Utf8, n)),
+        (field("rating", DataType::Utf8), strings(listings.iter().map(|l| l.ber.to_string()))),
+    ]);
+    let brochure_item = StructArray::from(vec![(
+        field("url", DataType::Utf8),
+        strings((0..n).map(|i| format!("https://media.daft.ie/brochures/{}.pdf", id(i)))),
+    )]);
+    let brochure = ListArray::new(
+        field("item", brochure_item.data_type().clone()),
+        OffsetBuffer::from_lengths(vec![1; n]),
+        Arc::new(brochure_item),
+        None,
+    );
+    let media = StructArray::from(vec![(field("brochure", brochure.data_type().clone()), Arc::new(brochure) as ArrayRef)]);
+    let published = date.and_hms_opt(9, 30, 0).unwrap().and_utc();
+
+    let mut fields: Vec<(Arc<Field>, ArrayRef)> = vec![
+        (field("abbreviatedPrice", DataType::Utf8), strings(listings.iter().map(|l| euros(l.rent_on(day))))),
+        (field("ber", ber.data_type().clone()), Arc::new(ber)),
+        (field("propertyType", DataType::Utf8), strings(listings.iter().map(|l| l.property_type.to_string()))),
+        (field("id", DataType::Utf8), strings((0..n).map(id))),
+        (field("numBedrooms", DataType::Utf8), strings(listings.iter().map(|l| format!("{} Bed", l.bedrooms)))),
+        (field("title", DataType::Utf8), strings(listings.iter().map(Listing::address))),
+        (field("numBathrooms", DataType::Utf8), strings(listings.iter().map(|l| format!("{} Bath", l.bathrooms)))),
+        (
+            field("publishDate", DataType::Int64),
+            Arc::new(Int64Array::from_iter_values(
+                listings.iter().map(|l| (published - Duration::days(l.listed_days_ago)).timestamp_millis()),
+            )),
+        ),
+        (field("media", media.data_type().clone()), Arc::new(media)),
+    ];
+    for position in fields.len()..23 {
+        fields.push((field(&format!("unused{}", position), DataType::Utf8), new_null_array(&DataType::Utf8, n)));
+    }
+    fields.push((
+        field("seoFriendlyPath", DataType::Utf8),
+        strings(listings.iter().enumerate().map(|(i, l)| format!("/for-rent/{}/{}", l.slug(), id(i)))),
+    ));
+    let listing = StructArray::from(fields);
+    RecordBatch::try_from_iter(vec![("listing", Arc::new(listing) as ArrayRef)]).expect("Daft fixture schema is consistent")
+}
+
+fn property_batch(listings: &[Listing], day: usize) -> RecordBatch {
+    RecordBatch::try_from_iter(vec![
+        ("address", strings(listings.iter().map(Listing::address))),
+        ("price", strings(listings.iter().map(|l| euros(l.rent_on(day))))),
+        ("id", strings((0..listings.len()).map(|i| (100_000 + i).to_string()))),
+    ])
+    .expect("property.ie fixture schema is consistent")
+}
+
+fn write(path: &Path, batch: &RecordBatch) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+    }
+    let file = File::create(path).map_err(|e| format!("Failed to create {:?}: {}", path, e))?;
+    let mut writer = ArrowWriter::try_new(file, batch.schema(), None).map_err(|e| e.to_string())?;
+    writer.write(batch).map_err(|e| e.to_string())?;
+    writer.close().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+pub struct Options {
+    pub rows: usize,
+    pub days: usize,
+    pub seed: u64,
+    pub out: PathBuf,
+    /// The day of the last snapshot.
+    pub today: NaiveDate,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Options {
+            rows: 1000,
+            days: 1,
+            seed: 42,
+            out: std::env::temp_dir().join("market-analysis-fixtures"),
+            today: SystemClock.today(),
+        };
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or_else(|| format!("{} needs a value", flag))?;
+            let number = || value.parse::<u64>().map_err(|_| format!("Invalid {} {:?}", flag, value));
+            match flag.as_str() {
+                "--rows" => options.rows = number()? as usize,
+                "--days" => options.days = (number()? as usize).max(1),
+                "--seed" => options.seed = number()?,
+                "--out" => options.out = PathBuf::from(value),
+                _ => return Err(format!("Unknown option {}. Options: --rows, --days, --seed, --out", flag)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Writes `options.days` daily snapshots, ending `options.today`, of `options.rows`
+/// listings per source under `options.out`. Returns the files written.
+pub fn generate(options: &Options) -> Result<Vec<PathBuf>, String> {
+    let mut rng = Rng(options.seed);
+    let myhome: Vec<Listing> = (0..options.rows).map(|_| Listing::random(&mut rng)).collect();
+    let cross_listed = |rng: &mut Rng| -> Vec<Listing> {
+        (0..options.rows)
+            .map(|i| if rng.below(4) == 0 { myhome[i].clone() } else { Listing::random(rng) })
+            .collect()
+    };
+    let daft = cross_listed(&mut rng);
+    let property = cross_listed(&mut rng);
+
+    let config = Config::default();
+    let today = options.today;
+    let mut written = Vec::new();
+    for day in 0..options.days {
+        let date = today - Duration::days((options.days - 1 - day) as i64);
+        for (name, batch) in [
+            ("myhome", myhome_batch(&myhome, day, date)),
+            ("daft", daft_batch(&daft, day, date)),
+            ("property", property_batch(&property, day)),
+        ] {
+            let source = config.resolve_source(name).expect("built-in source");
+            let path = source
+                .root(&options.out)
+                .join(date.format("%Y/%m/%d").to_string())
+                .join(format!("{}_080000.parquet", name));
+            write(&path, &batch)?;
+            written.push(path);
+        }
+    }
+    Ok(written)
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let options = Options::parse(args)?;
+    let written = generate(&options)?;
+    println!("Wrote {} snapshot files of {} listings each under {}", written.len(), options.rows, options.out.display());
+    println!("Serve them with data_path = {:?} in config.toml", options.out.display().to_string());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_dir;
+    use crate::validate::validate_file;
+
+    #[test]
+    fn test_fixtures_parse_in_every_schema() {
+        let options = Options::parse(&["--rows".into(), "40".into(), "--days".into(), "2".into()]).unwrap();
+        let options = Options { out: temp_dir("fixtures"), ..options };
+        let written = generate(&options).unwrap();
+        assert_eq!(written.len(), 6);
+
+        let config = Config::default();
+        for path in &written {
+            let name = path.file_name().unwrap().to_str().unwrap().split('_').next().unwrap();
+            let report = validate_file(config.resolve_source(name).unwrap(), path);
+            assert!(report.passed(), "{:?}: {:?}", path, report.failures);
+            assert_eq!(report.parsed, 40, "{:?}", path);
+        }
+        let properties = crate::load_properties(config.resolve_source("myhome").unwrap(), &written[0]);
+        assert!(properties.iter().all(|p| p.price.amount >= 500.0 && p.agent.is_some()));
+        assert!(properties.iter().any(|p| p.ber_rating.is_some()));
+    }
+
+    #[test]
+    fn test_same_seed_same_listings() {
+        let (mut a, mut b) = (Rng(7), Rng(7));
+        let first: Vec<String> = (0..20).map(|_| Listing::random(&mut a).address()).collect();
+        let second: Vec<String> = (0..20).map(|_| Listing::random(&mut b).address()).collect();
+        assert_eq!(first, second);
+        assert_eq!(euros(1850.0), "€1,850");
+    }
+}
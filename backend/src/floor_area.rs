@@ -0,0 +1,132 @@
+//! How far a listing's floor area can be trusted.
+//!
+//! Sizes are typed in by agents and go wrong in familiar ways: square feet
+//! entered as metres, the site area of a house, a stray digit. Each sized
+//! listing gets a `size_confidence`:
+//!
+//! - `low` when the size is implausible on its own (under 12 m² or over
+//!   1,000 m², under 6 m² a bedroom), when the rent per m² it implies is
+//!   outside €4 to €150 a month, or when the description states an area more
+//!   than 20% away from it
+//! - `high` when the description states an area that agrees with it, or the
+//!   listing has a floorplan to check it against
+//! - `medium` otherwise
+//!
+//! Commercial listings are only checked against a stated area; their rents
+//! per m² range too widely. Stats use `usable_sqm`, which leaves out `low`
+//! sizes, for anything per m².
+
+use serde::{Deserialize, Serialize};
+
+use crate::commercial::is_commercial;
+use crate::{rent_in_range, StandardizedProperty};
+
+const SQFT_PER_SQM: f64 = 10.7639;
+const MIN_SQM: f64 = 12.0;
+const MAX_SQM: f64 = 1000.0;
+const MIN_SQM_PER_BEDROOM: f64 = 6.0;
+/// Plausible monthly rents per m².
+const RENT_PER_SQM: (f64, f64) = (4.0, 150.0);
+/// How far a stated area may be from the size and still agree with it.
+const STATED_TOLERANCE: f64 = 0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SizeConfidence {
+    Low,
+    Medium,
+    High,
+}
+
+/// The largest area written out in `text`, in m²: "85 sq m", "85m2",
+/// "85 m²" or "915 sq ft". Smaller ones are usually rooms.
+pub fn stated_area(text: &str) -> Option<f64> {
+    let text = text.to_lowercase();
+    let units: [(&str, f64); 9] = [
+        ("sq. m", 1.0),
+        ("sq m", 1.0),
+        ("sqm", 1.0),
+        ("m2", 1.0),
+        ("m²", 1.0),
+        ("square met", 1.0),
+        ("sq. ft", 1.0 / SQFT_PER_SQM),
+        ("sq ft", 1.0 / SQFT_PER_SQM),
+        ("sqft", 1.0 / SQFT_PER_SQM),
+    ];
+    let mut largest: Option<f64> = None;
+    let mut rest = text.as_str();
+    while let Some(start) = rest.find(|c: char| c.is_ascii_digit()) {
+        let tail = &rest[start..];
+        let end = tail.find(|c: char| !(c.is_ascii_digit() || c == '.' || c == ',')).unwrap_or(tail.len());
+        let number = tail[..end].trim_end_matches(['.', ',']);
+        let after = tail[end..].trim_start();
+        if let (Ok(value), Some((_, factor))) =
+            (number.replace(',', "").parse::<f64>(), units.iter().find(|(unit, _)| after.starts_with(unit)))
+        {
+            largest = Some(largest.map_or(value * factor, |area| area.max(value * factor)));
+        }
+        rest = &tail[end..];
+    }
+    largest.filter(|area| *area > 0.0)
+}
+
+/// How far `property`'s size can be trusted, given the area its description
+/// states. `None` when it has no size.
+pub fn assess(property: &StandardizedProperty, stated: Option<f64>) -> Option<SizeConfidence> {
+    let sqm = property.size.as_ref().map(|size| size.value)?;
+    if let Some(stated) = stated {
+        let agrees = (sqm - stated).abs() <= STATED_TOLERANCE * stated;
+        return Some(if agrees { SizeConfidence::High } else { SizeConfidence::Low });
+    }
+    if !is_commercial(&property.property_category) {
+        let cramped = property.bedrooms.is_some_and(|beds| beds > 0 && sqm / f64::from(beds) < MIN_SQM_PER_BEDROOM);
+        let rent = property.price.amount / sqm;
+        let priced_oddly = rent_in_range(property.price.amount) && !(RENT_PER_SQM.0..=RENT_PER_SQM.1).contains(&rent);
+        if !(MIN_SQM..=MAX_SQM).contains(&sqm) || cramped || priced_oddly {
+            return Some(SizeConfidence::Low);
+        }
+    }
+    Some(if property.has_floorplan { SizeConfidence::High } else { SizeConfidence::Medium })
+}
+
+/// The listing's size in m², unless it is of low confidence.
+pub fn usable_sqm(property: &StandardizedProperty) -> Option<f64> {
+    let size = property.size.as_ref().filter(|size| size.value > 0.0)?;
+    (property.size_confidence != Some(SizeConfidence::Low)).then_some(size.value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::listing;
+
+    #[test]
+    fn test_sizes_are_checked_against_rent_and_stated_area() {
+        assert_eq!(stated_area("Spacious 2 bed, approx. 85 sq m, 2nd floor"), Some(85.0));
+        assert_eq!(stated_area("Floor area: 1,076 sq ft").map(f64::round), Some(100.0));
+        assert_eq!(stated_area("2 bed apartment on floor 3"), None);
+        assert_eq!(stated_area("Bedroom 12 sq m. Total 64 sq m."), Some(64.0));
+
+        let sized = |sqm: f64, rent: f64| {
+            let mut property = listing("daft", "1");
+            property.size = Some(crate::Size { value: sqm, unit: "square_meters".to_string() });
+            property.bedrooms = Some(2);
+            property.price.amount = rent;
+            property
+        };
+        assert_eq!(assess(&sized(70.0, 2000.0), None), Some(SizeConfidence::Medium));
+        assert_eq!(assess(&sized(70.0, 2000.0), Some(72.0)), Some(SizeConfidence::High));
+        assert_eq!(assess(&sized(70.0, 2000.0), Some(95.0)), Some(SizeConfidence::Low));
+        // Square feet entered as metres
+        assert_eq!(assess(&sized(750.0, 2000.0), None), Some(SizeConfidence::Low));
+        assert_eq!(assess(&sized(10.0, 2000.0), None), Some(SizeConfidence::Low));
+        assert_eq!(assess(&listing("daft", "2"), None), None);
+
+        let mut floorplan = sized(70.0, 2000.0);
+        floorplan.has_floorplan = true;
+        assert_eq!(assess(&floorplan, None), Some(SizeConfidence::High));
+        let mut bad = sized(750.0, 2000.0);
+        bad.size_confidence = assess(&bad, None);
+        assert_eq!(usable_sqm(&bad), None);
+    }
+}
@@ -0,0 +1,103 @@
+//! Listing coordinates and polygon areas.
+//!
+//! Listings carry `coordinates` when their snapshot has them: read from the
+//! optional `coordinates` column (GeoJSON order, `[lng, lat]`) or the
+//! `latitude` and `longitude` columns. Areas that don't follow address
+//! components, like a stretch of the Luas line, are given as a polygon of
+//! points; listings without coordinates are never inside one.
+
+use serde::{Deserialize, Serialize};
+
+use crate::mapping::BatchRow;
+
+/// The island of Ireland, with some margin; anything outside is a bad
+/// geocode or swapped axes.
+const LATITUDES: std::ops::RangeInclusive<f64> = 51.0..=55.6;
+const LONGITUDES: std::ops::RangeInclusive<f64> = -11.0..=-5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Point {
+    pub lat: f64,
+    pub lng: f64,
+}
+
+impl Point {
+    fn checked(lat: f64, lng: f64) -> Option<Point> {
+        (LATITUDES.contains(&lat) && LONGITUDES.contains(&lng)).then_some(Point { lat, lng })
+    }
+}
+
+/// Polygon vertices in order; the last connects back to the first.
+pub fn validate_polygon(points: &[Point]) -> Result<(), String> {
+    if points.len() < 3 {
+        return Err("A polygon needs at least 3 points".to_string());
+    }
+    match points.iter().find(|p| Point::checked(p.lat, p.lng).is_none()) {
+        Some(p) => Err(format!("Point ({}, {}) is outside Ireland", p.lat, p.lng)),
+        None => Ok(()),
+    }
+}
+
+/// Whether `point` is inside `polygon`, by ray casting along the latitude.
+pub fn contains(polygon: &[Point], point: Point) -> bool {
+    let mut inside = false;
+    let mut previous = match polygon.last() {
+        Some(last) => *last,
+        None => return false,
+    };
+    for &vertex in polygon {
+        if (vertex.lat > point.lat) != (previous.lat > point.lat) {
+            let along = (point.lat - vertex.lat) / (previous.lat - vertex.lat);
+            let crossing = vertex.lng + along * (previous.lng - vertex.lng);
+            if point.lng < crossing {
+                inside = !inside;
+            }
+        }
+        previous = vertex;
+    }
+    inside
+}
+
+pub fn from_row(row: &BatchRow) -> Option<Point> {
+    if let (Some(lat), Some(lng)) = (row.double("latitude"), row.double("longitude")) {
+        return Point::checked(lat, lng);
+    }
+    let coordinates = row.json("coordinates")?;
+    let pair = coordinates.as_array()?;
+    match pair.as_slice() {
+        [lng, lat] => Point::checked(lat.as_f64()?, lng.as_f64()?),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(lat: f64, lng: f64) -> Point {
+        Point { lat, lng }
+    }
+
+    #[test]
+    fn test_contains() {
+        // Roughly Dublin 6, and a concave notch cut out of its east side
+        let polygon = [
+            point(53.33, -6.29),
+            point(53.33, -6.24),
+            point(53.31, -6.24),
+            point(53.31, -6.26),
+            point(53.30, -6.26),
+            point(53.30, -6.29),
+        ];
+        assert!(contains(&polygon, point(53.32, -6.26)));
+        assert!(contains(&polygon, point(53.305, -6.27)));
+        assert!(!contains(&polygon, point(53.305, -6.25)));
+        assert!(!contains(&polygon, point(53.35, -6.26)));
+        assert!(!contains(&[], point(53.32, -6.26)));
+
+        assert!(validate_polygon(&polygon).is_ok());
+        assert!(validate_polygon(&polygon[..2]).is_err());
+        // Axes swapped
+        assert!(validate_polygon(&[point(-6.29, 53.33), point(-6.24, 53.33), point(-6.24, 53.31)]).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+//! Liveness and dependency checks.
+//!
+//! `/health` answers `OK` as long as the process serves requests.
+//! `/health?deep=true` also checks what the service depends on and returns
+//! each check's status, answering 503 when any of them fails:
+//!
+//! - `storage`: `data_path` can be listed
+//! - `storage.<source>`: the source has a snapshot, and its latest one opens;
+//!   `disabled` for a source an admin turned off (see `toggles`)
+//! - `jobs`: the job queue's SQLite database answers a query; `disabled` when
+//!   `jobs.path` is unset and the queue lives in memory
+//! - `notifier`: webhook URLs parse and email has an SMTP relay to go through;
+//!   `disabled` with no channel configured
+//!
+//! Snapshots are read from the local filesystem and there is no geocoder, so
+//! those are the whole of it.
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use crate::config::Config;
+use crate::jobs::JobQueue;
+use crate::state::AppState;
+use crate::{fallback, find_latest_parquet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    /// Not configured, so nothing to check.
+    Disabled,
+    Failing,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, status: Status, detail: impl Into<Option<String>>) -> Self {
+        Check { name: name.into(), status, detail: detail.into() }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub status: Status,
+    pub checks: Vec<Check>,
+}
+
+fn storage(config: &Config) -> Vec<Check> {
+    if let Err(e) = fs::read_dir(&config.data_path) {
+        return vec![Check::new("storage", Status::Failing, format!("{:?}: {}", config.data_path, e))];
+    }
+    let mut checks = vec![Check::new("storage", Status::Ok, None)];
+    for source in &config.sources {
+        let name = format!("storage.{}", source.name);
+        if source.disabled {
+            checks.push(Check::new(name, Status::Disabled, "disabled by an admin".to_string()));
+            continue;
+        }
+        checks.push(match find_latest_parquet(&source.root(&config.data_path)) {
+            None => Check::new(name, Status::Failing, "no snapshot".to_string()),
+            Some(latest) if !fallback::readable(&latest) => {
+                Check::new(name, Status::Failing, format!("{:?} can't be read", latest))
+            }
+            Some(latest) => Check::new(name, Status::Ok, latest.display().to_string()),
+        });
+    }
+    checks
+}
+
+fn jobs(config: &Config, queue: &JobQueue) -> Check {
+    match (queue.ping(), &config.jobs.path) {
+        (Err(e), _) => Check::new("jobs", Status::Failing, e),
+        (Ok(None), None) => Check::new("jobs", Status::Disabled, "in memory".to_string()),
+        (Ok(None), Some(path)) => {
+            Check::new("jobs", Status::Failing, format!("{:?} couldn't be opened; running in memory", path))
+        }
+        (Ok(Some(path)), _) => Check::new("jobs", Status::Ok, path),
+    }
+}
+
+fn notifier(config: &Config) -> Check {
+    let notifier = &config.notifier;
+    if notifier.webhooks.is_empty() && notifier.smtp.is_none() && notifier.telegram_bot_token.is_none() {
+        return Check::new("notifier", Status::Disabled, None);
+    }
+    let mut problems: Vec<String> = notifier
+        .webhooks
+        .iter()
+        .filter(|url| reqwest::Url::parse(url).is_err())
+        .map(|url| format!("invalid webhook URL {:?}", url))
+        .collect();
+    if let Some(smtp) = &notifier.smtp {
+        if smtp.host.trim().is_empty() || smtp.from.trim().is_empty() {
+            problems.push("smtp needs a host and a from address".to_string());
+        }
+    }
+    if notifier.telegram_bot_token.as_deref().is_some_and(|token| token.trim().is_empty()) {
+        problems.push("empty telegram_bot_token".to_string());
+    }
+    match problems.is_empty() {
+        true => Check::new("notifier", Status::Ok, None),
+        false => Check::new("notifier", Status::Failing, problems.join("; ")),
+    }
+}
+
+/// Runs every dependency check. Blocking.
+pub fn deep(config: &Config, queue: &JobQueue) -> HealthReport {
+    let mut checks = storage(config);
+    checks.push(jobs(config, queue));
+    checks.push(notifier(config));
+    let status = if checks.iter().any(|check| check.status == Status::Failing) { Status::Failing } else { Status::Ok };
+    HealthReport { status, checks }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HealthParams {
+    #[serde(default)]
+    deep: bool,
+}
+
+pub async fn health(State(state): State<AppState>, Query(params): Query<HealthParams>) -> Response {
+    if !params.deep {
+        return "OK".into_response();
+    }
+    let report = tokio::task::spawn_blocking(move || deep(&state.config, &state.jobs)).await;
+    match report {
+        Ok(report) if report.status == Status::Failing => {
+            (StatusCode::SERVICE_UNAVAILABLE, Json(report)).into_response()
+        }
+        Ok(report) => Json(report).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::test_utils::{standardized_batch, temp_dir, write_parquet};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_deep_checks() {
+        let data = temp_dir("health");
+        let toml = format!("data_path = {:?}\n[[sources]]\nname = \"rent_ie\"", data);
+        let mut config = Config::from_toml(&toml).unwrap();
+        config.jobs.path = None;
+        write_parquet(&data.join("processed/rent_ie/2024/11/05/rent_ie_120000.parquet"), &standardized_batch(&["1"]));
+
+        let report = deep(&config, &JobQueue::in_memory(Arc::new(SystemClock)));
+        let status = |name: &str| report.checks.iter().find(|check| check.name == name).map(|check| check.status);
+        assert_eq!(status("storage.rent_ie"), Some(Status::Ok));
+        // The built-in sources have no data here
+        assert_eq!(status("storage.daft"), Some(Status::Failing));
+        assert_eq!((status("jobs"), status("notifier")), (Some(Status::Disabled), Some(Status::Disabled)));
+        assert_eq!(report.status, Status::Failing);
+
+        config.notifier.webhooks.push("not a url".to_string());
+        assert_eq!(notifier(&config).status, Status::Failing);
+        config.data_path = data.join("missing");
+        assert_eq!(storage(&config)[0].status, Status::Failing);
+    }
+}
@@ -0,0 +1,203 @@
+//! Per-user hidden listings and blocked agents.
+//!
+//! `POST /api/rentals/{id}/hide` drops a listing from the caller's searches;
+//! with `?agent=true` everything from the same letting agent goes too.
+//! Listings are remembered by property id, which is stable across snapshots,
+//! and agents by name. `DELETE` on the same path undoes it. Direct lookups
+//! still return hidden listings.
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use crate::auth::Caller;
+use crate::state::AppState;
+use crate::StandardizedProperty;
+
+/// What one user has hidden.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HideList {
+    pub properties: BTreeSet<String>,
+    /// Agent names, lowercased.
+    pub agents: BTreeSet<String>,
+}
+
+fn agent_key(property: &StandardizedProperty) -> Option<String> {
+    let name = property.agent.as_ref()?.name.trim().to_lowercase();
+    (!name.is_empty()).then_some(name)
+}
+
+impl HideList {
+    /// Listings are matched on their canonical id alone: a later listing
+    /// that reuses a hidden one's source id isn't hidden with it.
+    pub fn hides(&self, property: &StandardizedProperty) -> bool {
+        self.properties.contains(&property.property_id)
+            || agent_key(property).is_some_and(|agent| self.agents.contains(&agent))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.properties.is_empty() && self.agents.is_empty()
+    }
+}
+
+pub struct HiddenListings {
+    path: Option<PathBuf>,
+    by_user: RwLock<BTreeMap<String, HideList>>,
+}
+
+impl HiddenListings {
+    /// Loads saved hide lists from `path`. `None` keeps them in memory only.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let by_user = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        HiddenListings { path, by_user: RwLock::new(by_user) }
+    }
+
+    /// The caller's hide list; empty for anonymous callers.
+    pub fn for_caller(&self, caller: &Caller) -> HideList {
+        caller
+            .name
+            .as_ref()
+            .and_then(|user| self.by_user.read().unwrap().get(user).cloned())
+            .unwrap_or_default()
+    }
+
+    /// Hides `property` for `user`, or shows it again when `hide` is false.
+    /// `agent` applies the same to its letting agent.
+    pub fn set(&self, user: &str, property: &StandardizedProperty, hide: bool, agent: bool) -> Result<HideList, String> {
+        let agent_key = match agent {
+            true => Some(agent_key(property).ok_or("Listing has no agent")?),
+            false => None,
+        };
+        let mut by_user = self.by_user.write().unwrap();
+        let list = by_user.entry(user.to_string()).or_default();
+        if hide {
+            list.properties.insert(property.property_id.clone());
+            list.agents.extend(agent_key);
+        } else {
+            list.properties.remove(&property.property_id);
+            if let Some(agent) = agent_key {
+                list.agents.remove(&agent);
+            }
+        }
+        let updated = list.clone();
+        if updated.is_empty() {
+            by_user.remove(user);
+        }
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+            let contents = serde_json::to_string_pretty(&*by_user).map_err(|e| e.to_string())?;
+            fs::write(path, contents).map_err(|e| e.to_string())?;
+        }
+        Ok(updated)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct HideParams {
+    /// Also hide (or unhide) everything from the listing's agent.
+    #[serde(default)]
+    agent: bool,
+}
+
+async fn set(
+    state: AppState,
+    caller: Caller,
+    property_id: String,
+    params: HideParams,
+    hide: bool,
+) -> Result<Json<HideList>, (StatusCode, String)> {
+    let user = caller
+        .name
+        .as_deref()
+        .ok_or((StatusCode::UNAUTHORIZED, "Hiding listings needs an API key".to_string()))?;
+    let property = state
+        .id_index
+        .lookup(&state.config, std::slice::from_ref(&property_id))
+        .into_iter()
+        .next()
+        .ok_or((StatusCode::NOT_FOUND, format!("No listing {}", property_id)))?;
+    if params.agent && agent_key(&property).is_none() {
+        return Err((StatusCode::BAD_REQUEST, format!("Listing {} has no agent", property_id)));
+    }
+    state.hidden.set(user, &property, hide, params.agent).map(Json).map_err(|e| {
+        warn!("Could not save hidden listings: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Could not save hidden listings".to_string())
+    })
+}
+
+pub async fn hide(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(property_id): Path<String>,
+    Query(params): Query<HideParams>,
+) -> Result<Json<HideList>, (StatusCode, String)> {
+    set(state, caller, property_id, params, true).await
+}
+
+pub async fn unhide(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(property_id): Path<String>,
+    Query(params): Query<HideParams>,
+) -> Result<Json<HideList>, (StatusCode, String)> {
+    set(state, caller, property_id, params, false).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{listing, temp_dir};
+    use crate::Agent;
+
+    fn from_agent(id: &str, agent: &str) -> StandardizedProperty {
+        let mut property = listing("daft", id);
+        property.agent = Some(Agent {
+            name: agent.to_string(),
+            phone: String::new(),
+            email: String::new(),
+            address: String::new(),
+            licence: String::new(),
+        });
+        property
+    }
+
+    #[test]
+    fn test_hide_listings_and_agents() {
+        let path = temp_dir("hidden").join("hidden.json");
+        let hidden = HiddenListings::open(Some(path.clone()));
+        let (first, second, other) =
+            (from_agent("1", "Example Lettings"), from_agent("2", "example lettings "), from_agent("3", "Other"));
+        hidden.set("alice", &first, true, false).unwrap();
+
+        let alice = Caller::with_scopes(Some("alice".to_string()), &[]);
+        let list = hidden.for_caller(&alice);
+        assert!(list.hides(&first) && !list.hides(&second));
+        assert!(!hidden.for_caller(&Caller::with_scopes(Some("bob".to_string()), &[])).hides(&first));
+        assert!(!hidden.for_caller(&Caller::default()).hides(&first));
+
+        hidden.set("alice", &first, true, true).unwrap();
+        let list = HiddenListings::open(Some(path)).for_caller(&alice);
+        assert!(list.hides(&second) && !list.hides(&other));
+
+        let list = hidden.set("alice", &second, false, true).unwrap();
+        assert!(list.hides(&first) && !list.hides(&second));
+        assert!(hidden.set("alice", &listing("daft", "4"), true, true).is_err());
+
+        // A new listing under the same source id
+        let mut recycled = from_agent("5", "Other");
+        recycled.legacy_id = Some(first.property_id.clone());
+        assert!(list.hides(&first) && !list.hides(&recycled));
+    }
+}
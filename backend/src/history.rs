@@ -0,0 +1,203 @@
+//! Per-user search history.
+//!
+//! Every first-page search by a caller with an API key is recorded with its
+//! parameters, time and result count. `GET /api/me/history` lists the caller's
+//! recent searches, newest first, and `GET /api/me/history/{id}/replay` runs
+//! one again with exactly the same parameters. Entries are appended as JSON
+//! lines to `history.path` and replayed at startup.
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::auth::Caller;
+use crate::config::HistoryConfig;
+use crate::locale::Lang;
+use crate::state::AppState;
+use crate::SearchParams;
+
+const DEFAULT_PAGE: usize = 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub at: DateTime<Utc>,
+    /// The search as run, without paging state.
+    pub params: SearchParams,
+    pub results: usize,
+}
+
+/// One line of the history file.
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    user: String,
+    #[serde(flatten)]
+    entry: HistoryEntry,
+}
+
+struct Entries {
+    next_id: u64,
+    by_user: HashMap<String, VecDeque<HistoryEntry>>,
+}
+
+pub struct SearchHistory {
+    enabled: bool,
+    path: Option<PathBuf>,
+    max_per_user: usize,
+    entries: Mutex<Entries>,
+}
+
+impl SearchHistory {
+    /// Replays entries already in `history.path`.
+    pub fn open(config: &HistoryConfig) -> Self {
+        let max_per_user = config.max_per_user.max(1);
+        let mut entries = Entries { next_id: 1, by_user: HashMap::new() };
+        let records: Vec<Record> = config
+            .path
+            .as_ref()
+            .filter(|_| config.enabled)
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| contents.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+            .unwrap_or_default();
+        for record in records {
+            entries.next_id = entries.next_id.max(record.entry.id + 1);
+            let user = entries.by_user.entry(record.user).or_default();
+            if user.len() >= max_per_user {
+                user.pop_front();
+            }
+            user.push_back(record.entry);
+        }
+        SearchHistory { enabled: config.enabled, path: config.path.clone(), max_per_user, entries: Mutex::new(entries) }
+    }
+
+    /// Records a search by `caller` made `at`; anonymous searches are not kept.
+    pub fn record(&self, caller: &Caller, params: &SearchParams, results: usize, at: DateTime<Utc>) {
+        let Some(user) = caller.name.clone().filter(|_| self.enabled) else {
+            return;
+        };
+        let params = SearchParams {
+            offset: None,
+            page: None,
+            snapshot_token: None,
+            cursor: None,
+            explain: false,
+            include_raw: false,
+            ..params.clone()
+        };
+        let mut entries = self.entries.lock().unwrap();
+        let entry = HistoryEntry { id: entries.next_id, at, params, results };
+        entries.next_id += 1;
+
+        if let Some(path) = &self.path {
+            let record = Record { user: user.clone(), entry: entry.clone() };
+            let appended = serde_json::to_string(&record).map_err(|e| e.to_string()).and_then(|line| {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                }
+                let mut file = OpenOptions::new().create(true).append(true).open(path).map_err(|e| e.to_string())?;
+                writeln!(file, "{}", line).map_err(|e| e.to_string())
+            });
+            if let Err(e) = appended {
+                warn!("Could not append search history to {:?}: {}", path, e);
+            }
+        }
+
+        let user = entries.by_user.entry(user).or_default();
+        if user.len() >= self.max_per_user {
+            user.pop_front();
+        }
+        user.push_back(entry);
+    }
+
+    /// `user`'s searches, newest first.
+    pub fn list(&self, user: &str, limit: usize) -> Vec<HistoryEntry> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .by_user
+            .get(user)
+            .map(|searches| searches.iter().rev().take(limit).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn get(&self, user: &str, id: u64) -> Option<HistoryEntry> {
+        let entries = self.entries.lock().unwrap();
+        entries.by_user.get(user)?.iter().find(|entry| entry.id == id).cloned()
+    }
+}
+
+fn user(caller: &Caller) -> Result<&str, (StatusCode, String)> {
+    caller
+        .name
+        .as_deref()
+        .ok_or((StatusCode::UNAUTHORIZED, "Search history needs an API key".to_string()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryParams {
+    limit: Option<usize>,
+}
+
+pub async fn list(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(params): Query<HistoryParams>,
+) -> Result<Json<Vec<HistoryEntry>>, (StatusCode, String)> {
+    let user = user(&caller)?;
+    Ok(Json(state.history.list(user, params.limit.unwrap_or(DEFAULT_PAGE))))
+}
+
+/// Runs a past search again; the response is that of `/api/rentals/search`.
+pub async fn replay(
+    State(state): State<AppState>,
+    caller: Caller,
+    lang: Lang,
+    Path(id): Path<u64>,
+) -> Result<Response, (StatusCode, String)> {
+    let entry = state
+        .history
+        .get(user(&caller)?, id)
+        .ok_or((StatusCode::NOT_FOUND, format!("No search {} in your history", id)))?;
+    crate::search(state, caller, lang, entry.params).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_dir;
+
+    #[test]
+    fn test_history_is_per_user_capped_and_replayed() {
+        let config = HistoryConfig {
+            enabled: true,
+            path: Some(temp_dir("history").join("searches.jsonl")),
+            max_per_user: 2,
+        };
+        let history = SearchHistory::open(&config);
+        let alice = Caller::with_scopes(Some("alice".to_string()), &[]);
+        for bedrooms in 1..=3 {
+            let params: SearchParams =
+                serde_json::from_value(serde_json::json!({"bedrooms": bedrooms, "offset": 20, "cursor": "abc"})).unwrap();
+            history.record(&alice, &params, bedrooms as usize, Utc::now());
+        }
+        history.record(&Caller::default(), &serde_json::from_str("{}").unwrap(), 0, Utc::now());
+
+        let reopened = SearchHistory::open(&config);
+        let searches = reopened.list("alice", 10);
+        assert_eq!(searches.iter().map(|e| e.params.bedrooms).collect::<Vec<_>>(), [Some(3), Some(2)]);
+        assert!(searches[0].params.offset.is_none() && searches[0].params.cursor.is_none());
+        assert_eq!(reopened.get("alice", searches[1].id).unwrap().results, 2);
+        assert!(reopened.get("bob", searches[1].id).is_none());
+
+        history.record(&alice, &serde_json::from_str("{}").unwrap(), 0, Utc::now());
+        assert_eq!(SearchHistory::open(&config).list("alice", 10)[0].id, 4);
+    }
+}
@@ -0,0 +1,303 @@
+//! Property-ID lookup index.
+//!
+//! Every snapshot file gets a sidecar `<file>.ids.json` mapping `property_id` to
+//! the row offset it was parsed from, so single-listing lookups read one row
+//! instead of parsing the whole file. Sidecars are written when a snapshot
+//! lands: on ingest, by warm-up, and by the `cache.refresh` job, which indexes
+//! any latest snapshot that has none (see `warmup`). A rewritten parquet file
+//! is indexed again the same way. Lookups only read sidecars; a snapshot not
+//! indexed yet isn't searched by id until it is.
+//!
+//! When a file holds the same `source_id` more than once (a re-crawled page),
+//! the index keeps the most recently refreshed row, falling back to the later
+//! row on a tie, and records the others as superseded so scans skip them.
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::UNIX_EPOCH;
+
+use crate::config::{Config, SourceConfig};
+use crate::ids;
+use crate::{find_latest_parquet, read_rows, scan_rows, StandardizedProperty};
+
+const SIDECAR_SUFFIX: &str = "ids.json";
+/// Bumped when the sidecar layout changes so older sidecars get rebuilt.
+const SIDECAR_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotIndex {
+    #[serde(default)]
+    version: u32,
+    pub source: String,
+    pub file: PathBuf,
+    file_len: u64,
+    modified: u64,
+    rows: HashMap<String, usize>,
+    /// Offsets of rows replaced by a fresher row with the same `source_id`.
+    #[serde(default)]
+    superseded: Vec<usize>,
+}
+
+/// A listing's `created_date` or `updated_date`, from the formats the
+/// sources use.
+pub fn listing_time(value: &str) -> Option<NaiveDateTime> {
+    let value = value.trim();
+    DateTime::parse_from_rfc3339(value)
+        .map(|at| at.naive_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f"))
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f"))
+        .ok()
+        .or_else(|| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0))
+}
+
+/// Size and modification time used to detect a rewritten snapshot.
+pub fn file_signature(path: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    Some((metadata.len(), modified))
+}
+
+impl SnapshotIndex {
+    pub fn build(source: &SourceConfig, path: &Path) -> Option<Self> {
+        let (file_len, modified) = file_signature(path)?;
+        // Freshest row per source_id, and the property_id it was parsed as
+        let mut latest: HashMap<String, (Option<NaiveDateTime>, usize, String)> = HashMap::new();
+        let mut superseded = Vec::new();
+        scan_rows(source, path, 0, &HashSet::new(), |offset, property| {
+            let candidate = (listing_time(&property.updated_date), offset, property.property_id);
+            match latest.get_mut(&property.source_id) {
+                Some(current) => {
+                    if (candidate.0, candidate.1) >= (current.0, current.1) {
+                        superseded.push(current.1);
+                        *current = candidate;
+                    } else {
+                        superseded.push(candidate.1);
+                    }
+                }
+                None => {
+                    latest.insert(property.source_id, candidate);
+                }
+            }
+            ControlFlow::Continue(())
+        });
+        superseded.sort_unstable();
+        let rows: HashMap<String, usize> = latest.into_values().map(|(_, offset, id)| (id, offset)).collect();
+        debug!("Indexed {} ids in {:?}, {} superseded rows", rows.len(), path, superseded.len());
+        Some(SnapshotIndex {
+            version: SIDECAR_VERSION,
+            source: source.name.clone(),
+            file: path.to_path_buf(),
+            file_len,
+            modified,
+            rows,
+            superseded,
+        })
+    }
+
+    pub fn sidecar_path(path: &Path) -> PathBuf {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".");
+        name.push(SIDECAR_SUFFIX);
+        path.with_file_name(name)
+    }
+
+    fn is_current(&self) -> bool {
+        file_signature(&self.file) == Some((self.file_len, self.modified))
+    }
+
+    /// The sidecar of `path`, when it matches the snapshot.
+    pub fn load(path: &Path) -> Option<Self> {
+        fs::read_to_string(Self::sidecar_path(path))
+            .ok()
+            .and_then(|contents| serde_json::from_str::<SnapshotIndex>(&contents).ok())
+            .filter(|index| index.version == SIDECAR_VERSION && index.file == path && index.is_current())
+    }
+
+    /// Reads the sidecar when it matches the snapshot, otherwise builds the
+    /// index in memory without writing it.
+    pub fn load_or_build(source: &SourceConfig, path: &Path) -> Option<Self> {
+        Self::load(path).or_else(|| Self::build(source, path))
+    }
+
+    /// Like `load_or_build`, but writes a rebuilt index back as the sidecar.
+    /// A read-only data lake only costs the rebuild.
+    fn load_or_write(source: &SourceConfig, path: &Path) -> Option<Self> {
+        if let Some(existing) = Self::load(path) {
+            return Some(existing);
+        }
+        let index = Self::build(source, path)?;
+        let sidecar = Self::sidecar_path(path);
+        match serde_json::to_string(&index) {
+            Ok(contents) => {
+                if let Err(e) = fs::write(&sidecar, contents) {
+                    warn!("Could not write id index {:?}: {}", sidecar, e);
+                }
+            }
+            Err(e) => warn!("Could not serialize id index for {:?}: {}", path, e),
+        }
+        Some(index)
+    }
+
+    pub fn offset(&self, property_id: &str) -> Option<usize> {
+        self.rows.get(property_id).copied()
+    }
+
+    pub fn superseded(&self) -> HashSet<usize> {
+        self.superseded.iter().copied().collect()
+    }
+}
+
+/// In-memory cache of the index for each source's latest snapshot.
+#[derive(Debug, Default)]
+pub struct IdIndex {
+    snapshots: RwLock<HashMap<String, Arc<SnapshotIndex>>>,
+}
+
+impl IdIndex {
+    fn cached(&self, source: &SourceConfig, path: &Path) -> Option<Arc<SnapshotIndex>> {
+        let snapshots = self.snapshots.read().unwrap();
+        snapshots.get(&source.name).filter(|index| index.file == path && index.is_current()).cloned()
+    }
+
+    fn cache(&self, source: &SourceConfig, index: SnapshotIndex) -> Arc<SnapshotIndex> {
+        let index = Arc::new(index);
+        self.snapshots.write().unwrap().insert(source.name.clone(), index.clone());
+        index
+    }
+
+    /// The index for `path`, reusing the cached one while the file is
+    /// unchanged. `None` until the snapshot has been indexed by `prepare`.
+    pub fn snapshot(&self, source: &SourceConfig, path: &Path) -> Option<Arc<SnapshotIndex>> {
+        if let Some(index) = self.cached(source, path) {
+            return Some(index);
+        }
+        Some(self.cache(source, SnapshotIndex::load(path)?))
+    }
+
+    /// Indexes `path`, writing its sidecar unless a current one exists. Run
+    /// when a snapshot lands, never on a request.
+    pub fn prepare(&self, source: &SourceConfig, path: &Path) -> Option<Arc<SnapshotIndex>> {
+        if let Some(index) = self.cached(source, path) {
+            return Some(index);
+        }
+        Some(self.cache(source, SnapshotIndex::load_or_write(source, path)?))
+    }
+
+    /// Finds listings by `property_id`, canonical or legacy, in the latest
+    /// indexed snapshot of each enabled source.
+    pub fn lookup(&self, config: &Config, ids: &[String]) -> Vec<StandardizedProperty> {
+        // Snapshot indexes know listings by their legacy id
+        let legacy: Vec<String> = ids.iter().map(|id| ids::legacy(id)).collect();
+        let mut remaining: HashSet<&str> = legacy.iter().map(|id| id.as_str()).collect();
+        let mut found = Vec::new();
+
+        for source in config.sources.iter().filter(|source| !source.disabled) {
+            if remaining.is_empty() {
+                break;
+            }
+            let Some(latest_file) = find_latest_parquet(&source.root(&config.data_path)) else {
+                continue;
+            };
+            let Some(index) = self.snapshot(source, &latest_file) else {
+                debug!("{:?} has no id index yet; not looking up ids in {}", latest_file, source.name);
+                continue;
+            };
+
+            let offsets: Vec<usize> = remaining.iter().filter_map(|id| index.offset(id)).collect();
+            if offsets.is_empty() {
+                continue;
+            }
+            for (_, property) in read_rows(source, &latest_file, &offsets) {
+                if remaining.remove(property.indexed_id()) {
+                    found.push(property);
+                }
+            }
+        }
+
+        // Keep the caller's order
+        let position: HashMap<&str, usize> = legacy.iter().enumerate().map(|(i, id)| (id.as_str(), i)).collect();
+        found.sort_by_key(|p| position.get(p.indexed_id()).copied());
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{standardized_batch, temp_dir, write_parquet};
+    use arrow::array::{ArrayRef, RecordBatch, StringArray};
+
+    #[test]
+    fn test_sidecar_path() {
+        let path = Path::new("housing_data/processed/daft/2024/11/05/daft_101500.parquet");
+        assert_eq!(
+            SnapshotIndex::sidecar_path(path),
+            PathBuf::from("housing_data/processed/daft/2024/11/05/daft_101500.parquet.ids.json")
+        );
+    }
+
+    #[test]
+    fn test_duplicates_keep_most_recently_refreshed_row() {
+        let batch = RecordBatch::try_from_iter(vec![
+            ("source_id", Arc::new(StringArray::from(vec!["7", "8", "7", "7"])) as ArrayRef),
+            ("price", Arc::new(StringArray::from(vec!["€1,000", "€1,100", "€1,200", "€1,300"])) as ArrayRef),
+            (
+                "updated_date",
+                Arc::new(StringArray::from(vec!["2024-11-05T10:00:00", "2024-11-05", "2024-11-05T12:00:00", "2024-11-04"]))
+                    as ArrayRef,
+            ),
+        ])
+        .unwrap();
+        let file = temp_dir("id_index_dupes").join("rent_ie/2024/11/05/rent_ie_120000.parquet");
+        write_parquet(&file, &batch);
+        let config = Config::from_toml("[[sources]]\nname = \"rent_ie\"").unwrap();
+        let source = config.resolve_source("rent_ie").unwrap();
+
+        let index = SnapshotIndex::build(source, &file).unwrap();
+        assert_eq!(index.offset("rent_ie_7"), Some(2));
+        assert_eq!(index.superseded, vec![0, 3]);
+
+        let mut prices = Vec::new();
+        let stats = crate::scan_properties(source, &file, |_, property| {
+            prices.push(property.price.amount);
+            ControlFlow::Continue(())
+        });
+        assert_eq!(prices, vec![1100.0, 1200.0]);
+        assert_eq!(stats.duplicates, 2);
+    }
+
+    #[test]
+    fn test_lookup_reads_indexed_rows() {
+        let data = temp_dir("id_index");
+        let file = data.join("processed/rent_ie/2024/11/05/rent_ie_120000.parquet");
+        write_parquet(&file, &standardized_batch(&["1", "2", "3", "4", "5"]));
+        let config = Config::from_toml(&format!("data_path = {:?}\n[[sources]]\nname = \"rent_ie\"", data)).unwrap();
+
+        let source = config.resolve_source("rent_ie").unwrap();
+        let index = IdIndex::default();
+        let ids = vec!["rent_ie_4".to_string(), "missing".to_string(), "rent_ie_2".to_string()];
+        // Lookups don't index snapshots themselves
+        assert!(index.lookup(&config, &ids).is_empty());
+        assert!(!SnapshotIndex::sidecar_path(&file).exists());
+
+        index.prepare(source, &file).unwrap();
+        assert!(SnapshotIndex::sidecar_path(&file).exists());
+        let found = index.lookup(&config, &ids);
+        let found_ids: Vec<_> = found.iter().map(|p| p.property_id.as_str()).collect();
+        assert_eq!(found_ids, vec!["rent_ie_4", "rent_ie_2"]);
+
+        // A fresh cache picks up the sidecar instead of rescanning
+        let fresh = IdIndex::default();
+        assert_eq!(fresh.snapshot(source, &file).unwrap().offset("rent_ie_5"), Some(4));
+
+        let mut disabled = config.clone();
+        disabled.sources.iter_mut().for_each(|source| source.disabled = true);
+        assert!(fresh.lookup(&disabled, &ids).is_empty());
+    }
+}
@@ -0,0 +1,212 @@
+//! Canonical listing ids.
+//!
+//! A source's own id (`daft_123`) isn't a safe key: sources recycle ids for
+//! new listings, and it shows how the source numbers its listings. Each
+//! listing is instead given an opaque canonical id, a hash of its source, its
+//! `source_id` and the day it was first seen, which becomes its `property_id`;
+//! the source's id is kept as `legacy_id` and still works for lookups.
+//!
+//! The day a listing was first seen is kept in a registry at `ids.path`, or
+//! `listing_ids.json` under `data_path` when that is unset, so ids survive a
+//! restart either way. A
+//! listing is taken to be the same one while it keeps turning up in
+//! snapshots; once its `source_id` has been missing for more than
+//! `ids.recycle_after_days` it is a new listing with a new id. Snapshots are
+//! dated by their directory, so parsing old snapshots again gives their
+//! listings the ids they had then.
+
+use chrono::NaiveDate;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::config::IdsConfig;
+use crate::{snapshot_date, StandardizedProperty};
+
+/// The canonical id of the listing `source_id` of `source` first seen on
+/// `first_seen`.
+pub fn canonical(source: &str, source_id: &str, first_seen: NaiveDate) -> String {
+    let digest = Sha256::digest(format!("{}\n{}\n{}", source, source_id, first_seen).as_bytes());
+    digest.iter().take(8).map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// One listing behind a source's id: the days it was seen, first to last.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Span {
+    id: String,
+    first_seen: NaiveDate,
+    last_seen: NaiveDate,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// Spans of each legacy id, oldest first.
+    spans: HashMap<String, Vec<Span>>,
+    /// Legacy id of each canonical id.
+    legacy: HashMap<String, String>,
+}
+
+impl Entries {
+    /// The span covering `seen`, or one `seen` extends by no more than
+    /// `gap` days.
+    fn find(&self, legacy: &str, seen: NaiveDate, gap: i64) -> Option<(usize, &Span)> {
+        let spans = self.spans.get(legacy)?;
+        let before = spans.iter().rposition(|span| span.first_seen <= seen);
+        match before {
+            Some(i) if (seen - spans[i].last_seen).num_days() <= gap => Some((i, &spans[i])),
+            Some(i) => spans.get(i + 1).filter(|next| (next.first_seen - seen).num_days() <= gap).map(|s| (i + 1, s)),
+            None => spans.first().filter(|first| (first.first_seen - seen).num_days() <= gap).map(|s| (0, s)),
+        }
+    }
+}
+
+/// The registry file when `ids.path` is unset, under `data_path`.
+const DEFAULT_FILE: &str = "listing_ids.json";
+
+pub struct IdRegistry {
+    path: PathBuf,
+    recycle_after_days: i64,
+    entries: RwLock<Entries>,
+    /// Whether `entries` changed since they were last saved.
+    dirty: AtomicBool,
+}
+
+impl IdRegistry {
+    /// Loads the registry from `config.path`, or from `listing_ids.json` in
+    /// `data_path` when there is none.
+    pub fn open(config: &IdsConfig, data_path: &Path) -> Self {
+        let path = config.path.clone().unwrap_or_else(|| data_path.join(DEFAULT_FILE));
+        let spans: HashMap<String, Vec<Span>> = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        let legacy = spans
+            .iter()
+            .flat_map(|(legacy, spans)| spans.iter().map(|span| (span.id.clone(), legacy.clone())))
+            .collect();
+        IdRegistry {
+            path,
+            recycle_after_days: i64::from(config.recycle_after_days),
+            entries: RwLock::new(Entries { spans, legacy }),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// The canonical id of the listing `legacy` (`source_id` of `source`) in
+    /// a snapshot of `seen`, registering it when new.
+    pub fn id(&self, source: &str, source_id: &str, legacy: &str, seen: NaiveDate) -> String {
+        let gap = self.recycle_after_days;
+        if let Some((_, span)) = self.entries.read().unwrap().find(legacy, seen, gap) {
+            if span.first_seen <= seen && seen <= span.last_seen {
+                return span.id.clone();
+            }
+        }
+        let mut entries = self.entries.write().unwrap();
+        self.dirty.store(true, Ordering::Relaxed);
+        if let Some((i, _)) = entries.find(legacy, seen, gap) {
+            let span = &mut entries.spans.get_mut(legacy).unwrap()[i];
+            span.first_seen = span.first_seen.min(seen);
+            span.last_seen = span.last_seen.max(seen);
+            return span.id.clone();
+        }
+        let id = canonical(source, source_id, seen);
+        let spans = entries.spans.entry(legacy.to_string()).or_default();
+        spans.push(Span { id: id.clone(), first_seen: seen, last_seen: seen });
+        spans.sort_by_key(|span| span.first_seen);
+        entries.legacy.insert(id.clone(), legacy.to_string());
+        id
+    }
+
+    /// The source's id of the listing with canonical id `id`.
+    pub fn legacy(&self, id: &str) -> Option<String> {
+        self.entries.read().unwrap().legacy.get(id).cloned()
+    }
+
+    /// Writes the registry back if it changed.
+    pub fn flush(&self) -> Result<(), String> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let contents = serde_json::to_string(&self.entries.read().unwrap().spans).map_err(|e| e.to_string())?;
+        fs::write(&self.path, contents).map_err(|e| e.to_string())
+    }
+}
+
+static ACTIVE: OnceLock<Arc<IdRegistry>> = OnceLock::new();
+
+/// Makes `registry` the one giving parsed listings their ids; call once at
+/// startup. Listings keep their source's id until then.
+pub fn install(registry: Arc<IdRegistry>) {
+    let _ = ACTIVE.set(registry);
+}
+
+/// Gives a listing freshly parsed from the snapshot at `path` its canonical
+/// id, counting it seen `today` when the path has no date.
+pub fn assign(property: &mut StandardizedProperty, path: &Path, today: NaiveDate) {
+    let Some(registry) = ACTIVE.get() else {
+        return;
+    };
+    let seen = snapshot_date(path).unwrap_or(today);
+    let id = registry.id(&property.source, &property.source_id, &property.property_id, seen);
+    property.legacy_id = Some(std::mem::replace(&mut property.property_id, id));
+}
+
+/// Saves ids registered while parsing.
+pub fn flush() {
+    if let Some(Err(e)) = ACTIVE.get().map(|registry| registry.flush()) {
+        warn!("Could not save listing ids: {}", e);
+    }
+}
+
+/// The id the source gave the listing `id`, which is either kind of id.
+pub fn legacy(id: &str) -> String {
+    ACTIVE.get().and_then(|registry| registry.legacy(id)).unwrap_or_else(|| id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_dir;
+
+    #[test]
+    fn test_ids_are_stable_until_a_source_id_is_recycled() {
+        let day = |d: u32| NaiveDate::from_ymd_opt(2024, 11, d).unwrap();
+        let config = IdsConfig { path: Some(temp_dir("ids").join("ids.json")), recycle_after_days: 7 };
+        let registry = IdRegistry::open(&config, Path::new("unused"));
+
+        let first = registry.id("daft", "1", "daft_1", day(5));
+        assert_eq!(first, canonical("daft", "1", day(5)));
+        assert_eq!(registry.id("daft", "1", "daft_1", day(9)), first);
+        // An older snapshot parsed later belongs to the same listing
+        assert_eq!(registry.id("daft", "1", "daft_1", day(2)), first);
+        let recycled = registry.id("daft", "1", "daft_1", day(25));
+        assert_ne!(recycled, first);
+        registry.flush().unwrap();
+
+        let reopened = IdRegistry::open(&config, Path::new("unused"));
+        assert_eq!(reopened.id("daft", "1", "daft_1", day(3)), first);
+        assert_eq!(reopened.id("daft", "1", "daft_1", day(26)), recycled);
+        assert_eq!(reopened.legacy(&recycled).as_deref(), Some("daft_1"));
+    }
+
+    #[test]
+    fn test_ids_persist_under_data_path_by_default() {
+        let data = temp_dir("ids_default");
+        let config = IdsConfig { path: None, recycle_after_days: 7 };
+        let day = NaiveDate::from_ymd_opt(2024, 11, 5).unwrap();
+        let registry = IdRegistry::open(&config, &data);
+        let id = registry.id("daft", "1", "daft_1", day);
+        registry.flush().unwrap();
+
+        assert!(data.join(DEFAULT_FILE).exists());
+        assert_eq!(IdRegistry::open(&config, &data).legacy(&id).as_deref(), Some("daft_1"));
+    }
+}
@@ -0,0 +1,461 @@
+//! Background job queue.
+//!
+//! Work that shouldn't hold up a request (cache warm-up, exports, backfills)
+//! is enqueued under a kind with a JSON payload and picked up by a fixed pool
+//! of workers. The queue lives in SQLite (`jobs.path`), so pending work
+//! survives a restart; jobs that were running when the process stopped are
+//! queued again on startup. A failing job is retried with exponential backoff
+//! until it has run `jobs.max_attempts` times, then marked failed and left for
+//! an admin to inspect or retry through `/api/admin/jobs`.
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::auth::{Caller, SCOPE_ADMIN};
+use crate::clock::Clock;
+use crate::config::JobsConfig;
+use crate::reload::Live;
+use crate::state::AppState;
+
+const DEFAULT_LIMIT: usize = 100;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS jobs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        kind TEXT NOT NULL,
+        payload TEXT NOT NULL,
+        status TEXT NOT NULL,
+        attempts INTEGER NOT NULL DEFAULT 0,
+        last_error TEXT,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL,
+        run_after INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS jobs_pending ON jobs (status, run_after);
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl JobStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Failed => "failed",
+        }
+    }
+
+    fn parse(status: &str) -> Self {
+        match status {
+            "queued" => JobStatus::Queued,
+            "running" => JobStatus::Running,
+            "succeeded" => JobStatus::Succeeded,
+            _ => JobStatus::Failed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: i64,
+    pub kind: String,
+    pub payload: serde_json::Value,
+    pub status: JobStatus,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Earliest time a queued job will be picked up.
+    pub run_after: DateTime<Utc>,
+}
+
+const COLUMNS: &str = "id, kind, payload, status, attempts, last_error, created_at, updated_at, run_after";
+
+fn timestamp(millis: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(millis).unwrap_or_default()
+}
+
+impl Job {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let payload: String = row.get(2)?;
+        let status: String = row.get(3)?;
+        Ok(Job {
+            id: row.get(0)?,
+            kind: row.get(1)?,
+            payload: serde_json::from_str(&payload).unwrap_or_default(),
+            status: JobStatus::parse(&status),
+            attempts: row.get(4)?,
+            last_error: row.get(5)?,
+            created_at: timestamp(row.get(6)?),
+            updated_at: timestamp(row.get(7)?),
+            run_after: timestamp(row.get(8)?),
+        })
+    }
+}
+
+pub struct JobQueue {
+    conn: Mutex<Connection>,
+    max_attempts: u32,
+    retry_backoff: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl JobQueue {
+    /// Opens the queue at `config.path`, or in memory when there is none,
+    /// timing jobs by `clock`. Jobs left running by a previous process are
+    /// queued again.
+    pub fn open(config: &JobsConfig, clock: Arc<dyn Clock>) -> Result<Self, String> {
+        let conn = match &config.path {
+            Some(path) => {
+                if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+                }
+                Connection::open(path).map_err(|e| format!("Failed to open job queue {:?}: {}", path, e))?
+            }
+            None => Connection::open_in_memory().map_err(|e| e.to_string())?,
+        };
+        conn.execute_batch(SCHEMA).map_err(|e| format!("Failed to create job queue schema: {}", e))?;
+        let interrupted = conn
+            .execute(
+                "UPDATE jobs SET status = 'queued', updated_at = ?1 WHERE status = 'running'",
+                params![clock.now().timestamp_millis()],
+            )
+            .map_err(|e| e.to_string())?;
+        if interrupted > 0 {
+            warn!("Re-queued {} jobs interrupted by the last shutdown", interrupted);
+        }
+        Ok(JobQueue {
+            conn: Mutex::new(conn),
+            max_attempts: config.max_attempts.max(1),
+            retry_backoff: Duration::from_secs(config.retry_backoff_secs),
+            clock,
+        })
+    }
+
+    /// In-memory queue with the default retry policy.
+    pub fn in_memory(clock: Arc<dyn Clock>) -> Self {
+        JobQueue::open(&JobsConfig { path: None, ..JobsConfig::default() }, clock)
+            .expect("in-memory SQLite is available")
+    }
+
+    /// Queries the queue's database. Returns the file it is kept in, or
+    /// `None` when it is in memory.
+    pub fn ping(&self) -> Result<Option<String>, String> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT COUNT(*) FROM jobs", [], |row| row.get::<_, i64>(0)).map_err(|e| e.to_string())?;
+        Ok(conn.path().filter(|path| !path.is_empty()).map(str::to_string))
+    }
+
+    pub fn enqueue(&self, kind: &str, payload: impl Serialize) -> Result<i64, String> {
+        let payload = serde_json::to_string(&payload).map_err(|e| e.to_string())?;
+        let now = self.clock.now().timestamp_millis();
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO jobs (kind, payload, status, created_at, updated_at, run_after) VALUES (?1, ?2, 'queued', ?3, ?3, ?3)",
+            params![kind, payload, now],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Like `enqueue`, but returns the pending job with the same kind and
+    /// payload instead of adding a duplicate.
+    pub fn enqueue_once(&self, kind: &str, payload: impl Serialize) -> Result<i64, String> {
+        let payload = serde_json::to_value(&payload).map_err(|e| e.to_string())?;
+        let existing = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT id FROM jobs WHERE kind = ?1 AND payload = ?2 AND status IN ('queued', 'running') LIMIT 1",
+                params![kind, payload.to_string()],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        match existing {
+            Some(id) => Ok(id),
+            None => self.enqueue(kind, payload),
+        }
+    }
+
+    /// Marks the oldest due job as running and returns it.
+    pub fn claim(&self) -> Result<Option<Job>, String> {
+        let now = self.clock.now().timestamp_millis();
+        let conn = self.conn.lock().unwrap();
+        let id: Option<i64> = conn
+            .query_row(
+                "SELECT id FROM jobs WHERE status = 'queued' AND run_after <= ?1 ORDER BY run_after, id LIMIT 1",
+                params![now],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        let Some(id) = id else {
+            return Ok(None);
+        };
+        conn.execute(
+            "UPDATE jobs SET status = 'running', attempts = attempts + 1, updated_at = ?2 WHERE id = ?1",
+            params![id, now],
+        )
+        .map_err(|e| e.to_string())?;
+        Self::fetch(&conn, id)
+    }
+
+    /// Records the outcome of a claimed job, scheduling a retry if it failed
+    /// and has attempts left.
+    pub fn finish(&self, job: &Job, outcome: Result<(), String>) -> Result<(), String> {
+        let now = self.clock.now();
+        let (status, run_after, error) = match outcome {
+            Ok(()) => (JobStatus::Succeeded, now, None),
+            Err(e) if job.attempts < self.max_attempts => {
+                let backoff = self.retry_backoff * 2u32.saturating_pow(job.attempts.saturating_sub(1));
+                (JobStatus::Queued, now + backoff, Some(e))
+            }
+            Err(e) => (JobStatus::Failed, now, Some(e)),
+        };
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE jobs SET status = ?2, last_error = ?3, updated_at = ?4, run_after = ?5 WHERE id = ?1",
+                params![job.id, status.as_str(), error, now.timestamp_millis(), run_after.timestamp_millis()],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Queues a failed job again with a fresh set of attempts. Returns false
+    /// if there is no failed job with that id.
+    pub fn retry(&self, id: i64) -> Result<bool, String> {
+        let now = self.clock.now().timestamp_millis();
+        let changed = self
+            .conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE jobs SET status = 'queued', attempts = 0, updated_at = ?2, run_after = ?2 WHERE id = ?1 AND status = 'failed'",
+                params![id, now],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(changed > 0)
+    }
+
+    pub fn get(&self, id: i64) -> Result<Option<Job>, String> {
+        Self::fetch(&self.conn.lock().unwrap(), id)
+    }
+
+    /// Jobs in `status` (or all), newest first.
+    pub fn list(&self, status: Option<JobStatus>, limit: usize) -> Result<Vec<Job>, String> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn
+            .prepare(&format!(
+                "SELECT {} FROM jobs WHERE ?1 IS NULL OR status = ?1 ORDER BY id DESC LIMIT ?2",
+                COLUMNS
+            ))
+            .map_err(|e| e.to_string())?;
+        let jobs = statement
+            .query_map(params![status.map(JobStatus::as_str), limit as i64], Job::from_row)
+            .map_err(|e| e.to_string())?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(|e| e.to_string())?;
+        Ok(jobs)
+    }
+
+    fn fetch(conn: &Connection, id: i64) -> Result<Option<Job>, String> {
+        conn.query_row(&format!("SELECT {} FROM jobs WHERE id = ?1", COLUMNS), params![id], Job::from_row)
+            .optional()
+            .map_err(|e| e.to_string())
+    }
+}
+
+type Handler = Box<dyn Fn(&AppState, &serde_json::Value) -> Result<(), String> + Send + Sync>;
+
+/// What to run for each job kind. Handlers run on the blocking thread pool.
+#[derive(Default)]
+pub struct Handlers {
+    handlers: HashMap<&'static str, Handler>,
+}
+
+impl Handlers {
+    pub fn register(
+        mut self,
+        kind: &'static str,
+        handler: impl Fn(&AppState, &serde_json::Value) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.handlers.insert(kind, Box::new(handler));
+        self
+    }
+
+    fn run(&self, state: &AppState, job: &Job) -> Result<(), String> {
+        match self.handlers.get(job.kind.as_str()) {
+            Some(handler) => handler(state, &job.payload),
+            None => Err(format!("No handler for job kind {:?}", job.kind)),
+        }
+    }
+}
+
+/// Starts `jobs.workers` workers that run queued jobs for the life of the
+/// process.
+pub fn spawn_workers(live: &Live, handlers: Handlers) {
+    let handlers = Arc::new(handlers);
+    let config = live.current().config;
+    let poll_interval = Duration::from_millis(config.jobs.poll_interval_ms);
+    for _ in 0..config.jobs.workers {
+        tokio::spawn(work(live.clone(), handlers.clone(), poll_interval));
+    }
+}
+
+/// Runs jobs as they come; each runs under the config current when claimed.
+async fn work(live: Live, handlers: Arc<Handlers>, poll_interval: Duration) {
+    loop {
+        let state = live.current();
+        let job = match state.jobs.claim() {
+            Ok(Some(job)) => job,
+            Ok(None) => {
+                tokio::time::sleep(poll_interval).await;
+                continue;
+            }
+            Err(e) => {
+                error!("Could not claim a job: {}", e);
+                tokio::time::sleep(poll_interval).await;
+                continue;
+            }
+        };
+        info!("Running job {} ({}), attempt {}", job.id, job.kind, job.attempts);
+
+        let outcome = {
+            let state = state.clone();
+            let handlers = handlers.clone();
+            let job = job.clone();
+            tokio::task::spawn_blocking(move || handlers.run(&state, &job))
+                .await
+                .unwrap_or_else(|e| Err(format!("Job panicked: {}", e)))
+        };
+        match &outcome {
+            Ok(()) => info!("Job {} ({}) succeeded", job.id, job.kind),
+            Err(e) => warn!("Job {} ({}) failed: {}", job.id, job.kind, e),
+        }
+        if let Err(e) = state.jobs.finish(&job, outcome) {
+            error!("Could not record the outcome of job {}: {}", job.id, e);
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JobsParams {
+    status: Option<JobStatus>,
+    limit: Option<usize>,
+}
+
+fn internal_error(e: String) -> (StatusCode, String) {
+    error!("Job queue error: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Job queue unavailable".to_string())
+}
+
+pub async fn list(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(params): Query<JobsParams>,
+) -> Result<Json<Vec<Job>>, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    let jobs = state.jobs.list(params.status, params.limit.unwrap_or(DEFAULT_LIMIT)).map_err(internal_error)?;
+    Ok(Json(jobs))
+}
+
+pub async fn get_job(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(id): Path<i64>,
+) -> Result<Json<Job>, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    match state.jobs.get(id).map_err(internal_error)? {
+        Some(job) => Ok(Json(job)),
+        None => Err((StatusCode::NOT_FOUND, format!("No job {}", id))),
+    }
+}
+
+pub async fn retry(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    let outcome = state.jobs.retry(id);
+    state.audit.record(&caller, "jobs.retry", serde_json::json!({ "id": id }), &outcome);
+    match outcome.map_err(internal_error)? {
+        true => Ok(StatusCode::ACCEPTED),
+        false => Err((StatusCode::CONFLICT, format!("Job {} does not exist or has not failed", id))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::test_utils::temp_dir;
+    use serde_json::json;
+
+    fn queue(path: Option<std::path::PathBuf>) -> JobQueue {
+        let config = JobsConfig { path, max_attempts: 2, retry_backoff_secs: 0, ..JobsConfig::default() };
+        JobQueue::open(&config, Arc::new(SystemClock)).unwrap()
+    }
+
+    #[test]
+    fn test_failed_jobs_are_retried_then_marked_failed() {
+        let jobs = queue(None);
+        let id = jobs.enqueue("export", json!({"format": "csv"})).unwrap();
+        assert_eq!(jobs.enqueue_once("export", json!({"format": "csv"})).unwrap(), id);
+
+        let job = jobs.claim().unwrap().unwrap();
+        assert_eq!((job.id, job.attempts, job.status), (id, 1, JobStatus::Running));
+        assert!(jobs.claim().unwrap().is_none());
+        jobs.finish(&job, Err("disk full".to_string())).unwrap();
+        assert_eq!(jobs.get(id).unwrap().unwrap().status, JobStatus::Queued);
+
+        let job = jobs.claim().unwrap().unwrap();
+        assert_eq!(job.attempts, 2);
+        jobs.finish(&job, Err("disk full".to_string())).unwrap();
+        let failed = jobs.get(id).unwrap().unwrap();
+        assert_eq!(failed.status, JobStatus::Failed);
+        assert_eq!(failed.last_error.as_deref(), Some("disk full"));
+        assert!(jobs.claim().unwrap().is_none());
+
+        assert!(jobs.retry(id).unwrap());
+        let job = jobs.claim().unwrap().unwrap();
+        assert_eq!(job.payload["format"], "csv");
+        jobs.finish(&job, Ok(())).unwrap();
+        assert_eq!(jobs.list(Some(JobStatus::Succeeded), 10).unwrap().len(), 1);
+        assert!(!jobs.retry(id).unwrap());
+    }
+
+    #[test]
+    fn test_running_jobs_are_requeued_on_reopen() {
+        let path = temp_dir("jobs").join("jobs.sqlite");
+        let jobs = queue(Some(path.clone()));
+        let id = jobs.enqueue("backfill", json!(null)).unwrap();
+        jobs.claim().unwrap().unwrap();
+        drop(jobs);
+
+        let reopened = queue(Some(path));
+        let job = reopened.claim().unwrap().unwrap();
+        assert_eq!((job.id, job.attempts), (id, 2));
+    }
+}
@@ -0,0 +1,351 @@
+//! Shareable short links and the sitemap.
+//!
+//! A short id is a hash of the listing's canonical id (see `ids`), so links
+//! handed out keep pointing at the same flat, and not at a different one that
+//! later reuses its source's id. `GET /l/{short_id}` redirects to
+//! the listing's page on the source site and counts the click (towards the
+//! caller's `recommendations` too, with an API key). That page's URL is built
+//! for every listing when it is parsed and returned as its `url`.
+//!
+//! Short ids resolve through a map of every listing in a snapshot loaded since
+//! startup. Each snapshot is added as the warm-up or a cache refresh loads it
+//! (see `warmup`); without a warm-up, a `links.refresh` job builds the map from
+//! the latest snapshots. Ids not in the map get 404.
+
+use axum::extract::{Path, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::ops::ControlFlow;
+use std::path::{Path as FilePath, PathBuf};
+use std::sync::{Mutex, RwLock};
+
+use crate::auth::Caller;
+use crate::cache::SnapshotCache;
+use crate::config::{ParserKind, SourceConfig};
+use crate::state::AppState;
+use crate::{find_latest_parquet, StandardizedProperty};
+
+/// Sitemaps may list at most this many URLs.
+const SITEMAP_MAX_URLS: usize = 50_000;
+
+/// 64-bit FNV-1a; unlike `DefaultHasher` it is stable across releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn base36(mut value: u64) -> String {
+    const DIGITS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+    let mut digits = Vec::new();
+    loop {
+        digits.push(DIGITS[(value % 36) as usize]);
+        value /= 36;
+        if value == 0 {
+            break;
+        }
+    }
+    digits.reverse();
+    String::from_utf8(digits).unwrap()
+}
+
+/// Short id of a listing that has been given its canonical id.
+pub fn short_id(property: &StandardizedProperty) -> String {
+    base36(fnv1a(property.property_id.as_bytes()))
+}
+
+/// An address as a URL slug: "Apt 4, Sráid an Rí" becomes "apt-4-sraid-an-ri".
+fn slug(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.to_lowercase().chars() {
+        let c = match c {
+            'á' => 'a',
+            'é' => 'e',
+            'í' => 'i',
+            'ó' => 'o',
+            'ú' => 'u',
+            c => c,
+        };
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Absolute URL of the listing on its source site: an absolute `seo_url` as
+/// given, otherwise built the way the source's site lays out listing pages,
+/// or from the source's `url_template`. `None` when the source has neither.
+pub fn listing_url(source: &SourceConfig, property: &StandardizedProperty) -> Option<String> {
+    let seo_url = property.seo_url.as_deref().map(str::trim).filter(|url| !url.is_empty());
+    let absolute = |url: &str| url.starts_with("http://") || url.starts_with("https://");
+    if let Some(url) = seo_url.filter(|url| absolute(url)) {
+        return Some(url.to_string());
+    }
+    let id = property.source_id.trim();
+    if id.is_empty() {
+        return None;
+    }
+    let slug = slug(&property.address.display_address);
+    if let Some(template) = &source.url_template {
+        return Some(template.replace("{source_id}", id).replace("{slug}", &slug));
+    }
+    match source.parser {
+        // Daft paths are "/for-rent/<slug>/<id>"; the slug is not checked
+        ParserKind::Daft => Some(match seo_url {
+            Some(path) => format!("https://www.daft.ie/{}", path.trim_start_matches('/')),
+            None => format!("https://www.daft.ie/for-rent/{}/{}", slug, id),
+        }),
+        // MyHome's seo_url is either a path or the brochure slug alone
+        ParserKind::MyHome => Some(match seo_url {
+            Some(path) if path.contains('/') => format!("https://www.myhome.ie/{}", path.trim_start_matches('/')),
+            Some(seo_slug) => format!("https://www.myhome.ie/rentals/brochure/{}/{}", seo_slug, id),
+            None => format!("https://www.myhome.ie/rentals/brochure/{}/{}", slug, id),
+        }),
+        // property.ie scrapes keep the listing URL as the id
+        ParserKind::Property if absolute(id) => Some(id.to_string()),
+        ParserKind::Property => Some(format!("https://www.property.ie/property-to-let/{}/{}/", slug, id)),
+        ParserKind::Standardized => None,
+    }
+}
+
+pub struct ShortLinks {
+    /// Short id to listing URL for every listing seen in a current snapshot.
+    /// Ids from older snapshots are kept so earlier links keep resolving.
+    targets: RwLock<HashMap<String, String>>,
+    clicks_path: Option<PathBuf>,
+    clicks: Mutex<HashMap<String, u64>>,
+}
+
+impl ShortLinks {
+    /// Loads click counts from `clicks_path`. `None` keeps them in memory
+    /// only.
+    pub fn open(clicks_path: Option<PathBuf>) -> Self {
+        let clicks = clicks_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        ShortLinks { targets: RwLock::default(), clicks_path, clicks: Mutex::new(clicks) }
+    }
+
+    /// Adds the listings of `source`'s snapshot `file` to the id map. Blocking.
+    pub fn index(&self, cache: &SnapshotCache, source: &SourceConfig, file: &FilePath) {
+        let mut found = HashMap::new();
+        cache.scan(source, file, 0, |_, property| {
+            if let Some(url) = property.url {
+                found.entry(property.short_id).or_insert(url);
+            }
+            ControlFlow::Continue(())
+        });
+        debug!("Short link map gained {} listings from {:?}", found.len(), file);
+        self.targets.write().unwrap().extend(found);
+    }
+
+    /// Adds the latest snapshot of every source to the id map. Blocking.
+    pub fn refresh(&self, state: &AppState) {
+        let config = &state.config;
+        for source in &config.sources {
+            if let Some(latest_file) = find_latest_parquet(&source.root(&config.data_path)) {
+                self.index(&state.cache, source, &latest_file);
+            }
+        }
+    }
+
+    pub fn resolve(&self, short_id: &str) -> Option<String> {
+        self.targets.read().unwrap().get(short_id).cloned()
+    }
+
+    fn record_click(&self, short_id: &str) -> Result<(), String> {
+        let mut clicks = self.clicks.lock().unwrap();
+        *clicks.entry(short_id.to_string()).or_default() += 1;
+        let Some(path) = &self.clicks_path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let contents = serde_json::to_string(&*clicks).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| e.to_string())
+    }
+
+    pub fn total_clicks(&self) -> u64 {
+        self.clicks.lock().unwrap().values().sum()
+    }
+}
+
+/// Job kind that builds the id map when no warm-up does.
+pub const JOB: &str = "links.refresh";
+
+pub fn run_job(state: &AppState, _payload: &serde_json::Value) -> Result<(), String> {
+    state.links.refresh(state);
+    Ok(())
+}
+
+pub async fn follow(State(state): State<AppState>, caller: Caller, Path(short_id): Path<String>) -> Response {
+    match state.links.resolve(&short_id) {
+        Some(url) => {
+            if let Err(e) = state.links.record_click(&short_id) {
+                warn!("Could not save share link clicks: {}", e);
+            }
+            state.analytics.record_click(&short_id, state.clock.now());
+            if let Some(user) = &caller.name {
+                state.interactions.record_click(user, &short_id);
+            }
+            (StatusCode::FOUND, [(header::LOCATION, url)]).into_response()
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Sitemap of the latest snapshot of every source. Blocking.
+fn build_sitemap(state: &AppState) -> String {
+    let config = &state.config;
+    let base_url = config.links.base_url.trim_end_matches('/');
+    let mut body = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    let mut urls = 0;
+
+    for source in &config.sources {
+        let Some(latest_file) = find_latest_parquet(&source.root(&config.data_path)) else {
+            continue;
+        };
+        state.cache.scan(source, &latest_file, 0, |_, property| {
+            if urls >= SITEMAP_MAX_URLS {
+                return ControlFlow::Break(());
+            }
+            if property.url.is_none() {
+                return ControlFlow::Continue(());
+            }
+            let loc = xml_escape(&format!("{}/l/{}", base_url, property.short_id));
+            let _ = write!(body, "  <url><loc>{}</loc>", loc);
+            if let Some(date) = property.updated_date.get(..10) {
+                let _ = write!(body, "<lastmod>{}</lastmod>", xml_escape(date));
+            }
+            body.push_str("</url>\n");
+            urls += 1;
+            ControlFlow::Continue(())
+        });
+    }
+    body.push_str("</urlset>\n");
+    body
+}
+
+pub async fn sitemap(State(state): State<AppState>) -> Response {
+    match tokio::task::spawn_blocking(move || build_sitemap(&state)).await {
+        Ok(body) => ([(header::CONTENT_TYPE, "application/xml")], body).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::test_utils::{listing, stores_in, temp_dir, write_parquet};
+    use arrow::array::{ArrayRef, StringArray};
+    use arrow::record_batch::RecordBatch;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_short_ids_follow_listings() {
+        // Two flats at the same address, with no bedroom count, as Daft gives them
+        let ids = vec!["100", "101"];
+        let urls: Vec<String> = ids.iter().map(|id| format!("https://rent.ie/{}", id)).collect();
+        let batch = RecordBatch::try_from_iter(vec![
+            ("source_id", Arc::new(StringArray::from(ids)) as ArrayRef),
+            ("price", Arc::new(StringArray::from(vec!["€1,950", "€2,050"])) as ArrayRef),
+            ("display_address", Arc::new(StringArray::from(vec!["Dame St, Dublin 2"; 2])) as ArrayRef),
+            ("seo_url", Arc::new(StringArray::from(urls)) as ArrayRef),
+        ])
+        .unwrap();
+        let data = temp_dir("links");
+        let config = Config::from_toml(&format!("data_path = {:?}\n[[sources]]\nname = \"rent_ie\"", data)).unwrap();
+        let source = config.resolve_source("rent_ie").unwrap();
+
+        let first = data.join("processed/rent_ie/2024/11/05/rent_ie_120000.parquet");
+        let second = data.join("processed/rent_ie/2024/11/06/rent_ie_120000.parquet");
+        write_parquet(&first, &batch);
+        write_parquet(&second, &batch);
+        let short_ids = |path| -> Vec<String> {
+            crate::load_properties(source, path).into_iter().map(|property| property.short_id).collect()
+        };
+        let ids = short_ids(&first);
+        assert_ne!(ids[0], ids[1]);
+        assert_eq!(short_ids(&second), ids);
+
+        let state = AppState::new(stores_in(config.clone(), &data));
+        assert_eq!(state.links.resolve(&ids[1]), None);
+        state.links.refresh(&state);
+        assert_eq!(state.links.resolve(&ids[1]).as_deref(), Some("https://rent.ie/101"));
+        assert_eq!(state.links.resolve("nope"), None);
+    }
+
+    #[test]
+    fn test_clicks_survive_a_restart() {
+        let path = temp_dir("link-clicks").join("clicks.json");
+        let links = ShortLinks::open(Some(path.clone()));
+        links.record_click("1z").unwrap();
+        links.record_click("1z").unwrap();
+        assert_eq!(ShortLinks::open(Some(path)).total_clicks(), 2);
+    }
+
+    #[test]
+    fn test_listing_url() {
+        let config = Config::default();
+        let source = |name: &str| config.sources.iter().find(|s| s.name == name).unwrap().clone();
+        let mut property = listing("daft", "1");
+        property.address.display_address = "Apt 4, 1 Sráid an Rí, Dublin 8".to_string();
+        assert_eq!(
+            listing_url(&source("daft"), &property).as_deref(),
+            Some("https://www.daft.ie/for-rent/apt-4-1-sraid-an-ri-dublin-8/1")
+        );
+        property.seo_url = Some("/for-rent/apartment-1-dame-st/1".to_string());
+        assert_eq!(
+            listing_url(&source("daft"), &property).as_deref(),
+            Some("https://www.daft.ie/for-rent/apartment-1-dame-st/1")
+        );
+        property.seo_url = Some("https://example.ie/1".to_string());
+        assert_eq!(listing_url(&source("daft"), &property).as_deref(), Some("https://example.ie/1"));
+
+        property.seo_url = Some("apartment-1-dame-st".to_string());
+        assert_eq!(
+            listing_url(&source("myhome"), &property).as_deref(),
+            Some("https://www.myhome.ie/rentals/brochure/apartment-1-dame-st/1")
+        );
+        property.seo_url = None;
+        assert_eq!(
+            listing_url(&source("property"), &property).as_deref(),
+            Some("https://www.property.ie/property-to-let/apt-4-1-sraid-an-ri-dublin-8/1/")
+        );
+        property.source_id = "https://www.property.ie/property-to-let/x/9/".to_string();
+        assert_eq!(listing_url(&source("property"), &property), Some(property.source_id.clone()));
+
+        let mut rent_ie = SourceConfig { name: "rent_ie".to_string(), ..source("property") };
+        rent_ie.parser = ParserKind::Standardized;
+        property.source_id = "77".to_string();
+        property.seo_url = Some("/77".to_string());
+        assert_eq!(listing_url(&rent_ie, &property), None);
+        rent_ie.url_template = Some("https://www.rent.ie/{slug}/{source_id}".to_string());
+        assert_eq!(
+            listing_url(&rent_ie, &property).as_deref(),
+            Some("https://www.rent.ie/apt-4-1-sraid-an-ri-dublin-8/77")
+        );
+        assert_eq!(base36(71), "1z");
+    }
+}
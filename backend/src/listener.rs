@@ -0,0 +1,117 @@
+//! Where the server listens.
+//!
+//! In order of preference:
+//! 1. a socket inherited through systemd socket activation (`LISTEN_FDS`),
+//! 2. `HOST`/`PORT` from the environment, as set by Fly.io, Railway and most
+//!    container platforms,
+//! 3. `[server]` in the config file.
+//!
+//! When only `PORT` is set the server binds all interfaces, since those
+//! platforms route traffic to the container's external interface.
+
+use log::info;
+use std::env;
+use tokio::net::TcpListener;
+
+use crate::config::ServerConfig;
+
+/// First inherited descriptor under the systemd socket activation protocol.
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// The inherited descriptor to use, if the environment passes one to this
+/// process. `LISTEN_PID` guards against variables leaked from a parent.
+#[cfg(unix)]
+fn inherited_fd(listen_fds: Option<&str>, listen_pid: Option<&str>, pid: u32) -> Option<i32> {
+    let count: u32 = listen_fds?.trim().parse().ok()?;
+    if count == 0 {
+        return None;
+    }
+    if let Some(listen_pid) = listen_pid {
+        if listen_pid.trim().parse::<u32>().ok()? != pid {
+            return None;
+        }
+    }
+    Some(SD_LISTEN_FDS_START)
+}
+
+/// `host:port` to bind when no socket is inherited.
+fn bind_address(host: Option<String>, port: Option<String>, config: &ServerConfig) -> Result<String, String> {
+    let port_from_env = port.is_some();
+    let port = match port {
+        Some(port) => port.trim().parse::<u16>().map_err(|_| format!("Invalid PORT {:?}", port))?,
+        None => config.port,
+    };
+    let host = match (host, port_from_env) {
+        (Some(host), _) => host,
+        (None, true) => "0.0.0.0".to_string(),
+        (None, false) => config.host.clone(),
+    };
+    Ok(if host.contains(':') { format!("[{}]:{}", host, port) } else { format!("{}:{}", host, port) })
+}
+
+#[cfg(unix)]
+fn take_inherited_listener() -> Result<Option<TcpListener>, String> {
+    use std::os::unix::io::FromRawFd;
+
+    let listen_fds = env::var("LISTEN_FDS").ok();
+    let listen_pid = env::var("LISTEN_PID").ok();
+    let Some(fd) = inherited_fd(listen_fds.as_deref(), listen_pid.as_deref(), std::process::id()) else {
+        return Ok(None);
+    };
+    // Don't hand the socket on to anything we spawn
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDNAMES");
+
+    // SAFETY: systemd passes an open listening socket at this descriptor and
+    // nothing else in the process owns it.
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    listener
+        .set_nonblocking(true)
+        .map_err(|e| format!("Inherited socket {} is unusable: {}", fd, e))?;
+    TcpListener::from_std(listener)
+        .map(Some)
+        .map_err(|e| format!("Inherited socket {} is unusable: {}", fd, e))
+}
+
+#[cfg(not(unix))]
+fn take_inherited_listener() -> Result<Option<TcpListener>, String> {
+    Ok(None)
+}
+
+pub async fn bind(config: &ServerConfig) -> Result<TcpListener, String> {
+    if let Some(listener) = take_inherited_listener()? {
+        info!("Using socket inherited from the service manager");
+        return Ok(listener);
+    }
+    let address = bind_address(env::var("HOST").ok(), env::var("PORT").ok(), config)?;
+    TcpListener::bind(&address)
+        .await
+        .map_err(|e| format!("Failed to bind {}: {}", address, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_inherited_fd() {
+        assert_eq!(inherited_fd(Some("1"), Some("42"), 42), Some(3));
+        assert_eq!(inherited_fd(Some("1"), None, 42), Some(3));
+        assert_eq!(inherited_fd(Some("1"), Some("7"), 42), None);
+        assert_eq!(inherited_fd(Some("0"), None, 42), None);
+        assert_eq!(inherited_fd(None, None, 42), None);
+    }
+
+    #[test]
+    fn test_bind_address() {
+        let config = ServerConfig::default();
+        assert_eq!(bind_address(Some("::".into()), Some("8080".into()), &config).unwrap(), "[::]:8080");
+        assert_eq!(bind_address(Some("10.0.0.2".into()), None, &config).unwrap(), "10.0.0.2:3000");
+        assert_eq!(bind_address(None, Some("8080".into()), &config).unwrap(), "0.0.0.0:8080");
+        assert_eq!(bind_address(None, None, &config).unwrap(), "127.0.0.1:3000");
+        assert!(bind_address(None, Some("http".into()), &config).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+//! Display labels for stats and report responses, in English or Irish.
+//!
+//! Machine-readable fields (period keys, normalized area names, enum values)
+//! never change; localized responses carry `label` fields alongside them. The
+//! language comes from `?lang=` when given, otherwise the first supported
+//! language in `Accept-Language`, otherwise English.
+
+use axum::async_trait;
+use axum::extract::{FromRequestParts, Query};
+use axum::http::request::Parts;
+use axum::http::{header, StatusCode};
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::address;
+use crate::analytics::hedonic::Granularity;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Lang {
+    #[default]
+    En,
+    Ga,
+}
+
+const MONTHS_EN: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November",
+    "December",
+];
+const MONTHS_GA: [&str; 12] = [
+    "Eanáir", "Feabhra", "Márta", "Aibreán", "Bealtaine", "Meitheamh", "Iúil", "Lúnasa", "Meán Fómhair",
+    "Deireadh Fómhair", "Samhain", "Nollaig",
+];
+
+impl Lang {
+    pub fn parse(tag: &str) -> Option<Self> {
+        let primary = tag.trim().split(['-', '_']).next()?.to_ascii_lowercase();
+        match primary.as_str() {
+            "en" => Some(Lang::En),
+            "ga" => Some(Lang::Ga),
+            _ => None,
+        }
+    }
+
+    /// First supported language in an `Accept-Language` header, by quality.
+    fn from_accept_language(header: &str) -> Option<Self> {
+        let mut ranges: Vec<(f32, Lang)> = header
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let lang = Lang::parse(parts.next()?)?;
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                Some((quality, lang))
+            })
+            .filter(|(quality, _)| *quality > 0.0)
+            .collect();
+        // Stable, so equal qualities keep the header's order
+        ranges.sort_by(|a, b| b.0.total_cmp(&a.0));
+        ranges.first().map(|(_, lang)| *lang)
+    }
+
+    fn month(self, date: NaiveDate) -> &'static str {
+        let index = date.month0() as usize;
+        match self {
+            Lang::En => MONTHS_EN[index],
+            Lang::Ga => MONTHS_GA[index],
+        }
+    }
+
+    pub fn granularity(self, granularity: Granularity) -> &'static str {
+        match (self, granularity) {
+            (Lang::En, Granularity::Day) => "Day",
+            (Lang::En, Granularity::Week) => "Week",
+            (Lang::En, Granularity::Month) => "Month",
+            (Lang::En, Granularity::Quarter) => "Quarter",
+            (Lang::Ga, Granularity::Day) => "Lá",
+            (Lang::Ga, Granularity::Week) => "Seachtain",
+            (Lang::Ga, Granularity::Month) => "Mí",
+            (Lang::Ga, Granularity::Quarter) => "Ráithe",
+        }
+    }
+
+    /// Display form of a period key from `Granularity::label`: "2024-11" is
+    /// "November 2024" or "Samhain 2024". Keys that don't parse are returned
+    /// as they are.
+    pub fn period(self, granularity: Granularity, period: &str) -> String {
+        let parsed = match granularity {
+            Granularity::Day => NaiveDate::parse_from_str(period, "%Y-%m-%d")
+                .ok()
+                .map(|date| format!("{} {} {}", date.day(), self.month(date), date.year())),
+            Granularity::Week => period.split_once("-W").map(|(year, week)| {
+                format!("{} {}, {}", self.granularity(granularity), week.trim_start_matches('0'), year)
+            }),
+            Granularity::Month => NaiveDate::parse_from_str(&format!("{}-01", period), "%Y-%m-%d")
+                .ok()
+                .map(|date| format!("{} {}", self.month(date), date.year())),
+            Granularity::Quarter => period.split_once("-Q").map(|(year, quarter)| match self {
+                Lang::En => format!("Q{} {}", quarter, year),
+                Lang::Ga => format!("Ráithe {}, {}", quarter, year),
+            }),
+        };
+        parsed.unwrap_or_else(|| period.to_string())
+    }
+
+    /// Display form of a normalized area ("county cork", "dublin 6").
+    pub fn area(self, area: &str) -> String {
+        let words: Vec<&str> = area.split(' ').filter(|w| !w.is_empty()).collect();
+        if self == Lang::Ga {
+            match words.as_slice() {
+                ["county", place] => {
+                    if let Some((_, genitive)) = address::irish_names(place) {
+                        return format!("Contae {}", genitive);
+                    }
+                }
+                [place, "city"] => {
+                    if let Some((_, genitive)) = address::irish_names(place) {
+                        return format!("Cathair {}", genitive);
+                    }
+                }
+                _ => {}
+            }
+        }
+        words
+            .iter()
+            .map(|word| match (self, address::irish_names(word)) {
+                (Lang::Ga, Some((nominative, _))) => nominative.to_string(),
+                _ => title_case(word),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+fn title_case(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LangParams {
+    lang: Option<String>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Lang {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let requested = Query::<LangParams>::try_from_uri(&parts.uri).ok().and_then(|q| q.0.lang);
+        if let Some(tag) = requested {
+            return Lang::parse(&tag)
+                .ok_or((StatusCode::BAD_REQUEST, format!("Unsupported lang {:?}; use en or ga", tag)));
+        }
+        Ok(parts
+            .headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Lang::from_accept_language)
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_language_negotiation() {
+        assert_eq!(Lang::from_accept_language("ga-IE,en;q=0.8"), Some(Lang::Ga));
+        assert_eq!(Lang::from_accept_language("fr, en-GB;q=0.5, ga;q=0.9"), Some(Lang::Ga));
+        assert_eq!(Lang::from_accept_language("ga;q=0, de"), None);
+        assert_eq!(Lang::parse("EN_ie"), Some(Lang::En));
+    }
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(Lang::Ga.period(Granularity::Month, "2024-11"), "Samhain 2024");
+        assert_eq!(Lang::En.period(Granularity::Day, "2024-03-05"), "5 March 2024");
+        assert_eq!(Lang::Ga.period(Granularity::Week, "2024-W05"), "Seachtain 5, 2024");
+        assert_eq!(Lang::En.period(Granularity::Quarter, "2024-Q4"), "Q4 2024");
+
+        assert_eq!(Lang::En.area("dublin 6"), "Dublin 6");
+        assert_eq!(Lang::Ga.area("dublin 6"), "Baile Átha Cliath 6");
+        assert_eq!(Lang::Ga.area("county cork"), "Contae Chorcaí");
+        assert_eq!(Lang::Ga.area("galway city"), "Cathair na Gaillimhe");
+        assert_eq!(Lang::Ga.area("ranelagh"), "Ranelagh");
+    }
+}
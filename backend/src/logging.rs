@@ -0,0 +1,66 @@
+//! Log output configuration.
+//!
+//! `[logging]` picks the output format (full, pretty, compact or JSON for log
+//! aggregation), per-module level directives in `EnvFilter` syntax and an
+//! optional rotating log file. `RUST_LOG`, when set, overrides the directives.
+
+use std::path::Path;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::EnvFilter;
+
+use crate::config::{LogFormat, LogRotation, LoggingConfig};
+
+fn file_writer(config: &LoggingConfig, path: &Path) -> Result<(BoxMakeWriter, WorkerGuard), String> {
+    let directory = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(".".as_ref());
+    let prefix = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("Invalid log file name {:?}", path))?;
+    let rotation = match config.rotation {
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+    let mut builder = RollingFileAppender::builder().rotation(rotation).filename_prefix(prefix);
+    if let Some(max_files) = config.max_files {
+        builder = builder.max_log_files(max_files);
+    }
+    let appender = builder
+        .build(directory)
+        .map_err(|e| format!("Failed to open log file {:?}: {}", path, e))?;
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+    Ok((BoxMakeWriter::new(writer), guard))
+}
+
+/// Installs the global subscriber, which also receives `log` records. Keep the
+/// returned guard alive for the life of the process so buffered file output
+/// gets flushed.
+pub fn init(config: &LoggingConfig) -> Result<Option<WorkerGuard>, String> {
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::try_new(&config.level)
+            .map_err(|e| format!("Invalid logging.level {:?}: {}", config.level, e))?,
+    };
+    let (writer, guard) = match &config.file {
+        Some(path) => {
+            let (writer, guard) = file_writer(config, path)?;
+            (writer, Some(guard))
+        }
+        None => (BoxMakeWriter::new(std::io::stdout), None),
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .with_ansi(config.file.is_none());
+    let installed = match config.format {
+        LogFormat::Full => builder.try_init(),
+        LogFormat::Pretty => builder.pretty().try_init(),
+        LogFormat::Compact => builder.compact().try_init(),
+        LogFormat::Json => builder.json().try_init(),
+    };
+    installed.map_err(|e| format!("Failed to initialize logging: {}", e))?;
+    Ok(guard)
+}
@@ -1,72 +1,251 @@
-use axum::{extract::Query, routing::get, Json, Router};
-use parquet::file::reader::{FileReader, SerializedFileReader};
-use parquet::record::{RowAccessor, ListAccessor};
+mod abuse;
+mod address;
+mod agent_register;
+mod amenities;
+mod analytics;
+mod audit;
+mod auth;
+mod bench;
+mod ber;
+mod cache;
+mod cdn;
+mod charts;
+mod clock;
+mod commercial;
+mod config;
+mod corrections;
+mod dataset;
+mod deltas;
+mod deposit;
+mod demo;
+mod developments;
+mod display;
+mod email;
+mod explain;
+mod exports;
+mod fallback;
+mod feed;
+mod fixtures;
+mod floor_area;
+mod geo;
+mod health;
+mod hidden;
+mod history;
+mod id_index;
+mod ids;
+mod jobs;
+mod links;
+mod listener;
+mod locale;
+mod logging;
+mod mapping;
+mod metrics;
+mod notes;
+mod notifier;
+mod pagination;
+mod partial;
+mod photos;
+mod preferences;
+mod presets;
+mod price_band;
+mod price_bounds;
+mod price_history;
+mod price_range;
+mod price_text;
+mod privacy;
+mod property_type;
+mod quality;
+mod rate_limit;
+mod raw;
+mod recommendations;
+mod reload;
+mod reports;
+mod schema;
+mod search_analytics;
+mod semantic;
+mod split;
+mod state;
+mod stats_sink;
+mod store;
+mod tenancy;
+mod toggles;
+#[cfg(test)]
+mod test_utils;
+mod validate;
+mod viewings;
+mod warmup;
+mod warnings;
+
+use axum::{extract::{self, Query, State}, http::{HeaderMap, HeaderValue, StatusCode}, middleware, response::{IntoResponse, Response}, routing::{delete, get, post, put}, Json, Router};
+use chrono::{DateTime, NaiveDate, Utc};
+use clock::Clock;
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReaderBuilder, RowSelection, RowSelector};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::{self, File};
+use std::net::SocketAddr;
+use std::ops::ControlFlow;
 use std::{env, path::{Path, PathBuf}};
-use log::{error, warn, debug};
+use tokio::sync::mpsc;
+use log::{error, warn, debug, info};
+
+use crate::auth::Caller;
+use crate::ber::BerStatus;
+use crate::config::{Config, ParserKind, SourceConfig};
+use crate::id_index::SnapshotIndex;
+use crate::locale::Lang;
+use crate::mapping::{decode_dictionaries, BatchRow, ResolvedColumns};
+use crate::pagination::{Cursor, Page, Paged};
+use crate::state::AppState;
+use crate::warnings::SourceWarning;
 
 // Type definitions for standardized properties
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Address {
     display_address: String,
+    /// `display_address` in canonical form; see `address::normalize`.
+    #[serde(default)]
+    normalized_address: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Size {
     value: f64,
     unit: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct PriceChange {
     date: String,
     amount: f64,
     direction: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Price {
     amount: f64,
     currency: String,
     frequency: Option<String>,
     price_changes: Vec<PriceChange>,
+    /// Bounds of an advertised range, for which `amount` is the point
+    /// `search.price_range_point` picks; see `price_range`.
+    #[serde(default)]
+    min: Option<f64>,
+    #[serde(default)]
+    max: Option<f64>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 struct Photo {
     url: String,
     is_main: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    caption: Option<String>,
+    /// Photo or floorplan; see `photos::from_row`.
+    #[serde(default)]
+    kind: photos::PhotoKind,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Agent {
     name: String,
     phone: String,
     email: String,
     address: String,
+    /// PSRA licence number as the listing quotes it; see `agent_register`.
+    #[serde(default)]
+    licence: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct StandardizedProperty {
+    /// Canonical id; see `ids`.
     property_id: String,
+    /// The id the listing had before canonical ids, `<source>_<source_id>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    legacy_id: Option<String>,
     source: String,
     source_id: String,
     address: Address,
     property_type: String,
+    /// Node of the property-type hierarchy; see `property_type`.
+    #[serde(default)]
+    property_category: String,
     bedrooms: Option<i32>,
     bathrooms: Option<i32>,
     size: Option<Size>,
+    /// How far `size` can be trusted; see `floor_area`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    size_confidence: Option<floor_area::SizeConfidence>,
+    /// "A1" to "G"; only set for rated listings.
     ber_rating: Option<String>,
+    #[serde(default)]
+    ber_status: BerStatus,
     price: Price,
+    /// Rent band such as "1000–1500"; see `price_band`.
+    #[serde(default)]
+    price_band: String,
+    /// The segment rent bound the rent breaks; see `price_bounds`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    price_flag: Option<price_bounds::PriceFlag>,
     created_date: String,
     updated_date: String,
     listing_type: String,
     status: String,
+    /// Exactly one is `is_main`; see `photos::mark_main`.
     photos: Vec<Photo>,
+    #[serde(default)]
+    photo_count: usize,
+    /// Whether one of `photos` is a floorplan.
+    #[serde(default)]
+    has_floorplan: bool,
     has_video: bool,
     agent: Option<Agent>,
+    /// How the agent stands on the PSRA register; see `agent_register`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    agent_status: Option<agent_register::AgentStatus>,
     seo_url: Option<String>,
+    /// The listing's page on the source site; see `links::listing_url`.
+    #[serde(default)]
+    url: Option<String>,
+    /// Stable id for `/l/{short_id}` share links; see `links::short_id`.
+    #[serde(default)]
+    short_id: String,
+    /// The caller's own notes and tags; see `notes`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    annotations: Option<notes::Annotations>,
+    /// The source record this was parsed from, with `include_raw`; see `raw`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    raw: Option<raw::RawRecord>,
+    /// Rent, size and rooms as text, with `format_values`; see `display`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    display: Option<display::DisplayValues>,
+    /// Unit types of a new-development listing; see `developments`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    development: Option<developments::Development>,
+    /// Rent per sq ft and zoning of a commercial listing; see `commercial`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    commercial: Option<commercial::CommercialDetails>,
+    /// Parking and outdoor space; see `amenities`.
+    #[serde(default)]
+    amenities: amenities::Amenities,
+    /// Move-in date and minimum lease; see `tenancy`.
+    #[serde(default)]
+    tenancy: tenancy::Tenancy,
+    /// Security deposit asked for, in the rent's currency; see `deposit`.
+    #[serde(default)]
+    deposit: Option<f64>,
+    /// Where the listing is, when the source gives it; see `geo`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    coordinates: Option<geo::Point>,
+    /// Asking rent over the rent predicted for comparable listings, with
+    /// `sort=value`; see `analytics::value`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value_score: Option<f64>,
+    /// Similarity of the listing's text to `semantic_q`; see `semantic`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    semantic_score: Option<f64>,
 }
 
 // Source-specific types
@@ -77,6 +256,7 @@ struct PropertyIEListing {
     id: String,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 struct MyHomeProperty {
     property_id: i64,
@@ -98,115 +278,222 @@ struct MyHomeProperty {
     group_address: String,
 }
 
-// Search parameters
-#[derive(Debug, Deserialize)]
+// Search parameters, from the query string or a JSON body
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct SearchParams {
+    /// Named filters to start from; see `presets`.
+    preset: Option<String>,
     source: Option<String>,
     min_price: Option<f64>,
     max_price: Option<f64>,
     bedrooms: Option<i32>,
+    /// Highest rent per sharer, one per bedroom; see `split::per_person`.
+    max_per_person: Option<f64>,
+    /// Comma-separated property-type nodes, e.g. "house" for every kind of
+    /// house; see `property_type`.
     property_type: Option<String>,
+    /// residential, commercial or all; see `commercial`.
+    category: Option<commercial::Category>,
+    /// Comma-separated price bands, e.g. "<1000,1000–1500".
+    price_band: Option<String>,
+    /// Comma-separated bands ("B"), ratings ("B2"), "exempt" or "unknown".
     ber_rating: Option<String>,
+    /// Area, locality or county the listing's address must be in.
+    location: Option<String>,
+    /// At least this many parking spaces; see `amenities`.
+    min_parking: Option<u32>,
+    /// At least this many photos.
+    min_photos: Option<usize>,
+    /// With (true) or without (false) a floorplan.
+    floorplan: Option<bool>,
+    /// With (true) or without (false) a `price_flag`; see `price_bounds`.
+    price_flagged: Option<bool>,
+    /// With (true) or without (false) an `agent_status` other than licensed;
+    /// see `agent_register`.
+    agent_flagged: Option<bool>,
+    /// With (true) or without (false) a garden.
+    garden: Option<bool>,
+    /// With (true) or without (false) a balcony.
+    balcony: Option<bool>,
+    /// Known to be available on or before this date; see `tenancy`.
+    available_from_before: Option<NaiveDate>,
+    /// Longest minimum lease acceptable, in months. Listings that don't state
+    /// one pass.
+    min_lease_months: Option<u32>,
+    /// Results per page, `search.default_page_size` by default. Without a
+    /// sort, the scan stops as soon as `offset + limit` matches have been
+    /// collected.
+    limit: Option<usize>,
+    offset: Option<usize>,
+    /// Page number, from 1, in place of `offset`; see `pagination`.
+    page: Option<usize>,
+    /// Results per page, in place of `limit`.
+    page_size: Option<usize>,
+    /// Order of the results; the order listings are stored in by default.
+    #[serde(alias = "sort_by")]
+    sort: Option<SortKey>,
+    /// Direction of `sort`; each key has its own default.
+    sort_order: Option<SortOrder>,
+    /// Rank the results by how close their text is to this; see `semantic`.
+    semantic_q: Option<String>,
+    /// Token from a previous page; pins the search to the same snapshots.
+    snapshot_token: Option<String>,
+    /// `x-next-cursor` from the previous page. Resumes the scan where that page
+    /// ended instead of re-reading and skipping `offset` matches.
+    cursor: Option<String>,
+    /// Describe how the search would run instead of running it.
+    #[serde(default)]
+    explain: bool,
+    /// Attach each listing's source record; needs the admin or debug scope.
+    #[serde(default)]
+    include_raw: bool,
+    /// Fail with 502 rather than leave out a source that can't be read.
+    #[serde(default)]
+    strict: bool,
+    /// Add display strings for rent, size and rooms; see `display`.
+    #[serde(default)]
+    format_values: bool,
+    /// Leave out `search.defaults`; see `presets`.
+    #[serde(default)]
+    no_defaults: bool,
+}
+
+impl SearchParams {
+    /// Whether results are reordered once every match is in, by `sort` or
+    /// `semantic_q`.
+    fn ranked(&self) -> bool {
+        self.sort.is_some() || self.semantic_q.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SortKey {
+    /// Best value for money first; see `analytics::value`.
+    Value,
+    /// Most recently updated first.
+    Newest,
+    /// Cheapest first.
+    Price,
+    /// Most recently listed first.
+    #[serde(rename = "created_date")]
+    CreatedDate,
+    /// Fewest bedrooms first.
+    Bedrooms,
+    /// Smallest floor area first; see `floor_area::usable_sqm`.
+    Size,
+}
+
+impl SortKey {
+    fn default_order(self) -> SortOrder {
+        match self {
+            SortKey::Newest | SortKey::CreatedDate => SortOrder::Desc,
+            SortKey::Value | SortKey::Price | SortKey::Bedrooms | SortKey::Size => SortOrder::Asc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SortOrder {
+    Asc,
+    Desc,
 }
 
 impl StandardizedProperty {
-    fn from_property_ie(raw: PropertyIEListing) -> Self {
-        let price_amount = raw
-            .price
-            .trim_start_matches('€')
-            .trim_end_matches(" monthly")
-            .split(|c: char| !c.is_ascii_digit() && c != ',' && c != '.')
-            .next()
-            .and_then(|s| s.replace(",", "").trim().parse::<f64>().ok())
-            .unwrap_or(0.0);
+    /// The listing's canonical id, then its legacy id if it has one.
+    fn ids(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.property_id.as_str()).chain(self.legacy_id.as_deref())
+    }
+
+    /// The id its snapshot's id index knows it by; see `id_index`.
+    fn indexed_id(&self) -> &str {
+        self.legacy_id.as_deref().unwrap_or(&self.property_id)
+    }
+
+    fn from_property_ie(raw: PropertyIEListing, now: DateTime<Utc>) -> Self {
+        let (price_amount, price_range) = parse_price_string(&raw.price).unwrap_or((0.0, None));
 
         StandardizedProperty {
             property_id: format!("property_{}", raw.id),
+            legacy_id: None,
             source: "property".to_string(),
             source_id: raw.id.clone(),
             address: Address {
                 display_address: raw.address.trim().to_string(),
+                normalized_address: String::new(),
             },
             property_type: String::new(),
             bedrooms: None,
             bathrooms: None,
             size: None,
             ber_rating: None,
+            ber_status: BerStatus::Unknown,
+            size_confidence: None,
+            price_band: String::new(),
+            price_flag: None,
+            property_category: String::new(),
             price: Price {
                 amount: price_amount,
                 currency: "EUR".to_string(),
                 frequency: Some("month".to_string()),
                 price_changes: vec![],
+                min: price_range.map(|(min, _)| min),
+                max: price_range.map(|(_, max)| max),
             },
-            created_date: chrono::Local::now().to_rfc3339(),
-            updated_date: chrono::Local::now().to_rfc3339(),
+            created_date: now.to_rfc3339(),
+            updated_date: now.to_rfc3339(),
             listing_type: "rent".to_string(),
             status: "active".to_string(),
             photos: vec![],
+            photo_count: 0,
+            has_floorplan: false,
             has_video: false,
             agent: None,
+            agent_status: None,
             seo_url: None,
+            url: None,
+            short_id: String::new(),
+            annotations: None,
+            raw: None,
+            display: None,
+            development: None,
+            commercial: None,
+            amenities: amenities::Amenities::default(),
+            tenancy: tenancy::Tenancy::default(),
+            deposit: None,
+            coordinates: None,
+            value_score: None,
+            semantic_score: None,
         }
     }
 
 }
 
-fn find_latest_parquet(source: &str, base_path: &str) -> Option<PathBuf> {
-    let source_path = Path::new(base_path).join("processed").join(source);
-
-    let years: Vec<_> = fs::read_dir(&source_path)
-        .ok()?
-        .filter_map(|entry| {
-            entry.ok().and_then(|e| {
-                e.path()
-                    .file_name()
-                    .and_then(|n| n.to_str())
-                    .and_then(|s| s.parse::<i32>().ok())
-                    .map(|year| (year, e.path()))
-            })
-        })
-    .collect();
-
-    let latest_year = years.iter().max_by_key(|(year, _)| year)?;
-
-    let months: Vec<_> = fs::read_dir(&latest_year.1)
-        .ok()?
-        .filter_map(|entry| {
-            entry.ok().and_then(|e| {
-                e.path()
-                    .file_name()
-                    .and_then(|n| n.to_str())
-                    .and_then(|s| s.parse::<i32>().ok())
-                    .map(|month| (month, e.path()))
-            })
-        })
-    .collect();
-
-    let latest_month = months.iter().max_by_key(|(month, _)| month)?;
-
-    let days: Vec<_> = fs::read_dir(&latest_month.1)
-        .ok()?
-        .filter_map(|entry| {
-            entry.ok().and_then(|e| {
-                e.path()
-                    .file_name()
-                    .and_then(|n| n.to_str())
-                    .and_then(|s| s.parse::<i32>().ok())
-                    .map(|day| (day, e.path()))
-            })
-        })
-    .collect();
-
-    let latest_day = days.iter().max_by_key(|(day, _)| day)?;
+/// The newest complete parquet file of the source's newest partition that has
+/// one; partitions whose files are all still being written are passed over.
+fn find_latest_parquet(source_path: &Path) -> Option<PathBuf> {
+    numeric_subdirs(source_path)
+        .into_iter()
+        .rev()
+        .flat_map(|(_, year)| numeric_subdirs(&year).into_iter().rev())
+        .flat_map(|(_, month)| numeric_subdirs(&month).into_iter().rev())
+        .find_map(|(_, day)| newest_parquet(&day, partial::is_complete))
+}
 
-    fs::read_dir(&latest_day.1)
+/// The most recently modified parquet file in `dir` that passes `keep`.
+fn newest_parquet(dir: &Path, keep: impl Fn(&Path) -> bool) -> Option<PathBuf> {
+    fs::read_dir(dir)
         .ok()?
         .filter_map(|entry| entry.ok().map(|e| e.path()))
-        .filter(|path| path.extension().map_or(false, |ext| ext == "parquet"))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "parquet"))
+        .filter(|path| keep(path))
         .max_by_key(|path| path.metadata().ok().and_then(|m| m.modified().ok()))
 }
 
-fn parse_price_string(price_str: &str) -> Option<f64> {
+/// Parses a rent such as "€1,850 / month"; see `price_text`. A range such as
+/// "€1,800 - €2,200" gives its midpoint and bounds.
+fn parse_price_string(price_str: &str) -> Option<(f64, Option<(f64, f64)>)> {
     debug!("Parsing price string: {}", price_str);
     
     // Handle empty strings
@@ -228,128 +515,67 @@ fn parse_price_string(price_str: &str) -> Option<f64> {
         .unwrap_or("")
         .trim();
 
-    // Extract only numeric characters and decimal points
-    let numeric_str: String = price_part
-        .chars()
-        .filter(|c| c.is_ascii_digit() || *c == '.')
-        .collect();
-
-    if numeric_str.is_empty() {
-        debug!("No numeric value found after cleaning: {}", price_part);
-        return None;
-    }
-
-    debug!("Cleaned price string for parsing: {}", numeric_str);
-
-    // Parse to float
-    match numeric_str.parse::<f64>() {
-        Ok(amount) if amount > 0.0 => {
-            debug!("Successfully parsed price: {}", amount);
-            Some(amount)
-        },
-        Ok(_) => {
-            debug!("Found zero or negative price");
-            None
-        },
-        Err(e) => {
-            warn!("Failed to parse price '{}' from original '{}': {}", numeric_str, price_str, e);
+    match price_text::parse(price_part) {
+        Some(parsed) => {
+            debug!("Parsed price {} (range {:?}) from {:?}", parsed.amount, parsed.range, price_str);
+            Some((parsed.amount, parsed.range))
+        }
+        None => {
+            debug!("No price found in {:?}", price_str);
             None
         }
     }
 }
 
-fn parse_myhome_row(row: &parquet::record::Row) -> Option<StandardizedProperty> {
+fn parse_myhome_row(row: &BatchRow) -> Option<StandardizedProperty> {
     debug!("Parsing MyHome row");
 
     // Basic property details
-    let property_id = row.get_long(0).unwrap_or_default();  // PropertyId
+    let property_id = row.long("property_id").unwrap_or_default();
     
-    let price_string = row.get_string(37)  // PriceAsString
-        .map(|s| s.to_string())
-        .unwrap_or_default();
+    let price_string = row.string("price").unwrap_or_default();
     
     debug!("Raw price string: {}", price_string);
-    let price_amount = parse_price_string(&price_string)?;  // Early return if price is invalid
+    let (price_amount, price_range) = parse_price_string(&price_string)?;  // Early return if price is invalid
 
-    // Display address is at index 42 (DisplayAddress)
-    let display_address = row.get_string(42)
-        .map(|s| s.to_string())
-        .unwrap_or_default();
+    let display_address = row.string("display_address").unwrap_or_default();
     
-    let bedrooms = row.get_long(36)  // NumberOfBeds
-        .ok()
-        .map(|b| b as i32);
+    let bedrooms = row.long("bedrooms").map(|b| b as i32);
     
-    let bathrooms = row.get_long(48)  // NumberOfBathrooms
-        .ok()
-        .map(|b| b as i32);
+    let bathrooms = row.long("bathrooms").map(|b| b as i32);
     
-    let property_type = row.get_string(46)  // PropertyType
-        .map(|s| s.to_string())
-        .unwrap_or_default();
+    let property_type = row.string("property_type").unwrap_or_default();
     
-    let ber_rating = row.get_string(49)  // BerRating
-        .map(|s| s.to_string())
-        .ok()
+    let ber_rating = row.string("ber_rating")
         .filter(|s| !s.is_empty());
 
-    let size_meters = row.get_double(40)  // SizeStringMeters
-        .ok();
+    let size_meters = row.double("size");
 
-    let is_active = row.get_bool(28)  // IsActive
-        .unwrap_or(false);
+    let is_active = row.bool("is_active").unwrap_or(false);
 
     // Dates
-    let created_date = row.get_string(11)  // CreatedOnDate
-        .map(|s| s.to_string())
-        .unwrap_or_default();
+    let created_date = row.string("created_date").unwrap_or_default();
     
-    let updated_date = row.get_string(3)  // RefreshedOn
-        .map(|s| s.to_string())
-        .unwrap_or_default();
-
-     let seo_url = row.get_string(55)
-        .map(|s| s.to_string())
-        .ok();
+    let updated_date = row.string("updated_date").unwrap_or_default();
 
-    // Photos - MainPhoto at index 61, Photos list at index 63
-    let mut photos = Vec::new();
-    
-    if let Ok(main_photo) = row.get_string(61) {
-        photos.push(Photo {
-            url: main_photo.to_string(),
-            is_main: true,
-        });
-    }
+    let seo_url = row.string("seo_url");
 
-    if let Ok(photo_list) = row.get_list(63) {
-        for i in 0..photo_list.len() {
-            if let Ok(url) = photo_list.get_string(i) {
-                let url_string = url.to_string();
-                if !photos.iter().any(|p| p.url == url_string) {
-                    photos.push(Photo {
-                        url: url_string,
-                        is_main: false,
-                    });
-                }
-            }
+    // Photos in the source's order, the main photo first when it isn't among them
+    let mut photos = photos::from_row(row);
+    if let Some(main_photo) = row.string("main_photo") {
+        match photos.iter_mut().find(|p| p.url == main_photo) {
+            Some(photo) => photo.is_main = true,
+            None => photos.insert(0, Photo { url: main_photo, is_main: true, ..Default::default() }),
         }
     }
 
     // Agent information
     let agent = Some(Agent {
-        name: row.get_string(8)  // GroupName
-            .map(|s| s.to_string())
-            .unwrap_or_default(),
-        phone: row.get_string(6)  // GroupPhoneNumber
-            .map(|s| s.to_string())
-            .unwrap_or_default(),
-        email: row.get_string(7)  // GroupEmail
-            .map(|s| s.to_string())
-            .unwrap_or_default(),
-        address: row.get_string(9)  // GroupAddress
-            .map(|s| s.to_string())
-            .unwrap_or_default(),
+        name: row.string("agent_name").unwrap_or_default(),
+        phone: row.string("agent_phone").unwrap_or_default(),
+        email: row.string("agent_email").unwrap_or_default(),
+        address: row.string("agent_address").unwrap_or_default(),
+        licence: row.string("agent_licence").unwrap_or_default(),
     });
 
     let size = size_meters.map(|value| Size {
@@ -359,133 +585,121 @@ fn parse_myhome_row(row: &parquet::record::Row) -> Option<StandardizedProperty>
 
     Some(StandardizedProperty {
         property_id: format!("myhome_{}", property_id),
+        legacy_id: None,
         source: "myhome".to_string(),
         source_id: property_id.to_string(),
         address: Address {
             display_address,
+            normalized_address: String::new(),
         },
         property_type,
         bedrooms,
         bathrooms,
         size,
         ber_rating,
+        ber_status: BerStatus::Unknown,
+        size_confidence: None,
+        price_band: String::new(),
+        price_flag: None,
+        property_category: String::new(),
         price: Price {
             amount: price_amount,
             currency: "EUR".to_string(),
             frequency: Some("month".to_string()),
             price_changes: vec![],
+            min: price_range.map(|(min, _)| min),
+            max: price_range.map(|(_, max)| max),
         },
         created_date,
         updated_date,
         listing_type: "rent".to_string(),
         status: if is_active { "active" } else { "inactive" }.to_string(),
         photos,
-        has_video: row.get_bool(31).unwrap_or(false),  // HasVideos
+        photo_count: 0,
+        has_floorplan: false,
+        has_video: row.bool("has_video").unwrap_or(false),
         agent,
+        agent_status: None,
         seo_url,
+        url: None,
+        short_id: String::new(),
+        annotations: None,
+        raw: None,
+        display: None,
+        development: None,
+        commercial: None,
+        amenities: amenities::Amenities::default(),
+        tenancy: tenancy::Tenancy::default(),
+        deposit: None,
+        coordinates: None,
+        value_score: None,
+        semantic_score: None,
     })
 }
 
 
-fn parse_daft_row(row: &parquet::record::Row) -> Option<StandardizedProperty> {
+fn parse_daft_row(row: &BatchRow, mapping_version: u32, now: DateTime<Utc>) -> Option<StandardizedProperty> {
     debug!("Starting to parse Daft row");
 
-    let listing = match row.get_group(0) {
-        Ok(l) => {
-            debug!("Successfully got listing group");
-            l
-        },
-        Err(e) => {
-            error!("Failed to get listing group: {}", e);
-            return None;
-        }
-    };
-
-    // First, let's debug the structure
-    debug!("Listing field count: {}", listing.len());
-    debug!("Listing fields: {:?}", listing);
-
-    // Get abbreviatedPrice (index 0)
-    let price_string = match listing.get_string(0) {
-        Ok(price) => {
+    let price_string = match row.string("price") {
+        Some(price) => {
             debug!("Found price string: {}", price);
-            price.to_string()
+            price
         },
-        Err(e) => {
-            error!("Failed to get price string: {}", e);
+        None => {
+            error!("Failed to get price string (mapping v{})", mapping_version);
             return None;
         }
     };
 
-    let price_amount = match parse_price_string(&price_string) {
-        Some(amount) => amount,
-        None => return None,
-    };
+    let (price_amount, price_range) = parse_price_string(&price_string)?;
 
-    // Get PropertyId (index 3)
-    let property_id = match listing.get_string(3) {
-        Ok(id) => {
+    let property_id = match row.string("property_id") {
+        Some(id) => {
             debug!("Found property ID: {}", id);
-            id.to_string()
+            id
         },
-        Err(e) => {
-            error!("Failed to get property ID: {}", e);
+        None => {
+            error!("Failed to get property ID (mapping v{})", mapping_version);
             return None;
         }
     };
 
-    // Get DisplayAddress (using seoTitle or title, likely index 5)
-    let display_address = match listing.get_string(5) {
-        Ok(addr) => {
+    let display_address = match row.string("display_address") {
+        Some(addr) => {
             debug!("Found display address: {}", addr);
-            addr.to_string()
+            addr
         },
-        Err(e) => {
-            debug!("Failed to get display address: {}", e);
+        None => {
+            debug!("Failed to get display address");
             "Address not available".to_string()
         }
     };
 
-    // Get PropertyType (likely index 2)
-    let property_type = match listing.get_string(2) {
-        Ok(pt) => {
+    let property_type = match row.string("property_type") {
+        Some(pt) => {
             debug!("Found property type: {}", pt);
-            pt.to_string()
+            pt
         },
-        Err(e) => {
-            debug!("Failed to get property type: {}", e);
+        None => {
+            debug!("Failed to get property type");
             "Not specified".to_string()
         }
     };
 
-    // Get seoFriendlyPath (index 23 in the listing struct)
-    let seo_url = match listing.get_string(23) {
-        Ok(path) => {
+    let seo_url = match row.string("seo_url") {
+        Some(path) => {
             debug!("Found SEO path: {}", path);
-            Some(path.to_string())
+            Some(path)
         },
-        Err(e) => {
-            debug!("Failed to get SEO friendly path: {}", e);
+        None => {
+            debug!("Failed to get SEO friendly path");
             // Fallback to brochure url from media if available
-            match listing.get_group(8)  // media
-                .and_then(|media| media.get_group(0))  // brochure
-                .and_then(|brochure_list| brochure_list.get_group(0))  // first brochure
-                .and_then(|brochure| brochure.get_string(0)) { // url
-                Ok(url) => Some(url.to_string()),
-                Err(_) => None,
-            }
+            row.string("brochure_url")
         }
     };
 
-    // Get BER rating from the nested BER struct (index 1)
-    let ber_rating = if let Ok(ber_group) = listing.get_group(1) {
-        match ber_group.get_string(2) { // rating field
-            Ok(rating) => Some(rating.to_string()),
-            Err(_) => None,
-        }
-    } else {
-        None
-    };
+    let ber_rating = row.string("ber_rating");
 
 
     // For bedrooms and bathrooms, we'll rely on the property_type string parsing for now
@@ -495,44 +709,597 @@ fn parse_daft_row(row: &parquet::record::Row) -> Option<StandardizedProperty> {
     // For now, we'll return a simplified property structure
     Some(StandardizedProperty {
         property_id: format!("daft_{}", property_id),
+        legacy_id: None,
         source: "daft".to_string(),
         source_id: property_id,
         address: Address {
             display_address,
+            normalized_address: String::new(),
         },
         property_type,
         bedrooms,
         bathrooms,
         size: None,
         ber_rating,
+        ber_status: BerStatus::Unknown,
+        size_confidence: None,
+        price_band: String::new(),
+        price_flag: None,
+        property_category: String::new(),
         price: Price {
             amount: price_amount,
             currency: "EUR".to_string(),
             frequency: Some("month".to_string()),
             price_changes: vec![],
+            min: price_range.map(|(min, _)| min),
+            max: price_range.map(|(_, max)| max),
         },
-        created_date: chrono::Utc::now().to_rfc3339(),
-        updated_date: chrono::Utc::now().to_rfc3339(),
+        created_date: now.to_rfc3339(),
+        updated_date: now.to_rfc3339(),
         listing_type: "rent".to_string(),
         status: "active".to_string(),
         photos: vec![], // We'll implement photo parsing later
+        photo_count: 0,
+        has_floorplan: false,
         has_video: false,
         agent: None,    // We'll implement agent parsing later
-        seo_url
+        agent_status: None,
+        seo_url,
+        url: None,
+        short_id: String::new(),
+        annotations: None,
+        raw: None,
+        display: None,
+        development: None,
+        commercial: None,
+        amenities: amenities::Amenities::default(),
+        tenancy: tenancy::Tenancy::default(),
+        deposit: None,
+        coordinates: None,
+        value_score: None,
+        semantic_score: None,
+    })
+}
+
+/// Parses a row from a source whose columns already follow the standardized
+/// schema. Columns are looked up by name, so their order does not matter.
+fn parse_standardized_row(source: &str, row: &BatchRow, now: DateTime<Utc>) -> Option<StandardizedProperty> {
+    let text = |name: &str| row.string(name).map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+
+    let source_id = text("source_id")?;
+    let (price_amount, price_range) = match row.double("price") {
+        Some(amount) => (amount, None),
+        None => parse_price_string(&text("price")?)?,
+    };
+    let now = now.to_rfc3339();
+
+    Some(StandardizedProperty {
+        property_id: text("property_id").unwrap_or_else(|| format!("{}_{}", source, source_id)),
+        legacy_id: None,
+        source: source.to_string(),
+        source_id,
+        address: Address {
+            display_address: text("display_address").unwrap_or_default(),
+            normalized_address: String::new(),
+        },
+        property_type: text("property_type").unwrap_or_default(),
+        bedrooms: row.long("bedrooms").map(|b| b as i32),
+        bathrooms: row.long("bathrooms").map(|b| b as i32),
+        size: row.double("size").map(|value| Size {
+            value,
+            unit: "square_meters".to_string(),
+        }),
+        ber_rating: text("ber_rating"),
+        ber_status: BerStatus::Unknown,
+        size_confidence: None,
+        price_band: String::new(),
+        price_flag: None,
+        property_category: String::new(),
+        price: Price {
+            amount: price_amount,
+            currency: text("currency").unwrap_or_else(|| "EUR".to_string()),
+            frequency: Some(text("frequency").unwrap_or_else(|| "month".to_string())),
+            price_changes: vec![],
+            min: price_range.map(|(min, _)| min),
+            max: price_range.map(|(_, max)| max),
+        },
+        created_date: text("created_date").unwrap_or_else(|| now.clone()),
+        updated_date: text("updated_date").unwrap_or(now),
+        listing_type: text("listing_type").unwrap_or_else(|| "rent".to_string()),
+        status: text("status").unwrap_or_else(|| "active".to_string()),
+        photos: vec![],
+        photo_count: 0,
+        has_floorplan: false,
+        has_video: row.bool("has_video").unwrap_or(false),
+        agent: None,
+        agent_status: None,
+        seo_url: text("seo_url"),
+        url: None,
+        short_id: String::new(),
+        annotations: None,
+        raw: None,
+        display: None,
+        development: None,
+        commercial: None,
+        amenities: amenities::Amenities::default(),
+        tenancy: tenancy::Tenancy::default(),
+        deposit: None,
+        coordinates: None,
+        value_score: None,
+        semantic_score: None,
     })
 }
 
-fn validate_price(amount: f64) -> bool {
+fn parse_source_row(
+    source: &SourceConfig,
+    columns: &ResolvedColumns,
+    row: &BatchRow,
+) -> Option<StandardizedProperty> {
+    let mut property = parse_raw_row(source, columns, row)?;
+    if let (Some(min), Some(max)) = (property.price.min, property.price.max) {
+        property.price.amount = source.parsing.price_range_point.pick(min, max);
+    }
+    property.address.normalized_address = address::normalize(&property.address.display_address);
+    (property.ber_rating, property.ber_status) = ber::normalize(property.ber_rating.as_deref());
+    property.property_category = property_type::classify(&property.property_type).to_string();
+    let price_text = row.string("price").unwrap_or_default();
+    commercial::apply(source.parsing.commercial, &mut property, &price_text, row.string("zoning"));
+    property.price_band = price_band::label(&source.parsing.price_bands, property.price.amount);
+    property.price_flag = price_bounds::check(&source.parsing.price_bounds, &property);
+    property.agent_status = agent_register::status(source.parsing.agent_register.as_ref(), &property);
+    property.deposit = deposit::extract(row, property.price.amount);
+    property.development = developments::from_row(row);
+    property.amenities = amenities::extract(row);
+    property.tenancy = tenancy::extract(row, &property.created_date, source.parsing.clock.today());
+    property.coordinates = geo::from_row(row);
+    photos::mark_main(&mut property.photos);
+    property.photo_count = property.photos.len();
+    property.has_floorplan = property.photos.iter().any(|photo| photo.kind == photos::PhotoKind::Floorplan);
+    property.size_confidence = floor_area::assess(&property, floor_area::stated_area(&amenities::listing_text(row)));
+    property.url = links::listing_url(source, &property);
+    Some(property)
+}
+
+fn parse_raw_row(
+    source: &SourceConfig,
+    columns: &ResolvedColumns,
+    row: &BatchRow,
+) -> Option<StandardizedProperty> {
+    let now = source.parsing.clock.now();
+    match source.parser {
+        ParserKind::Daft => {
+            debug!("Parsing Daft row {}", row.index());
+            match parse_daft_row(row, columns.version, now) {
+                Some(p) => {
+                    debug!("Successfully parsed Daft property: {} - {}", 
+                        p.property_id, p.price.amount);
+                    Some(p)
+                },
+                None => {
+                    debug!("Failed to parse Daft property");
+                    None
+                }
+            }
+        },
+        ParserKind::MyHome => parse_myhome_row(row),
+        ParserKind::Property => {
+            let address = row.string("address").unwrap_or_default();
+            let price_string = row.string("price").unwrap_or_default();
+            let id = row.string("id").unwrap_or_default();
+
+            Some(StandardizedProperty::from_property_ie(PropertyIEListing {
+                address,
+                price: price_string,
+                id,
+            }, now))
+        },
+        ParserKind::Standardized => parse_standardized_row(&source.name, row, now),
+    }
+}
+
+/// Partition date of a snapshot file laid out as `.../<year>/<month>/<day>/<file>`.
+fn snapshot_date(path: &Path) -> Option<NaiveDate> {
+    let mut parts = path
+        .ancestors()
+        .skip(1)
+        .take(3)
+        .map(|dir| dir.file_name().and_then(|n| n.to_str()).and_then(|s| s.parse::<u32>().ok()));
+    let (day, month, year) = (parts.next()??, parts.next()??, parts.next()??);
+    NaiveDate::from_ymd_opt(year as i32, month, day)
+}
+
+/// Row counts from a (possibly partial) scan of one snapshot file.
+#[derive(Debug, Default, Clone, Copy)]
+struct ScanStats {
+    total_rows: usize,
+    rows_scanned: usize,
+    /// Rows skipped because a more recently refreshed row in the same file has
+    /// the same `source_id`.
+    duplicates: usize,
+    /// Failures opening the file or decoding its batches; rows past a bad
+    /// batch may be missing.
+    errors: usize,
+}
+
+/// Opens a snapshot file and resolves the source's column mapping against its
+/// schema. Raw-schema sources use the mapping version in effect on the snapshot
+/// date; standardized sources read columns by name.
+fn open_snapshot(
+    source: &SourceConfig,
+    path: &Path,
+) -> Option<(ParquetRecordBatchReaderBuilder<File>, ResolvedColumns)> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            error!("Error opening file for {}: {}", source.name, e);
+            return None;
+        }
+    };
+    let builder = match ParquetRecordBatchReaderBuilder::try_new(file) {
+        Ok(builder) => builder,
+        Err(e) => {
+            error!("Error creating reader for {}: {}", source.name, e);
+            return None;
+        }
+    };
+
+    let columns = match &source.columns {
+        Some(mapping) => {
+            let version = mapping.for_date(snapshot_date(path));
+            debug!("Using {} column mapping v{} for {:?}", source.name, version.version, path);
+            let columns = version.resolve(builder.schema());
+            if !columns.missing.is_empty() {
+                warn!(
+                    "{:?} ({}) is missing columns of mapping v{}: {}",
+                    path,
+                    source.name,
+                    version.version,
+                    version.describe(&columns.missing)
+                );
+            }
+            columns
+        }
+        None => ResolvedColumns::by_name(builder.schema()),
+    };
+    Some((builder, columns))
+}
+
+/// Reads a parquet file batch by batch and parses each row with the parser for
+/// `source`, handing every parsed property and its row offset to `visit` until it
+/// breaks. Rows that fail to parse are skipped; reader errors are logged.
+fn scan_properties(
+    source: &SourceConfig,
+    path: &Path,
+    visit: impl FnMut(usize, StandardizedProperty) -> ControlFlow<()>,
+) -> ScanStats {
+    scan_properties_from(source, path, 0, visit)
+}
+
+/// Like `scan_properties`, but skips the rows before `start` without decoding
+/// them. Offsets passed to `visit` are still relative to the start of the file.
+///
+/// Re-crawled pages can put the same listing in a file more than once; only
+/// the most recently refreshed copy is visited. Which rows are superseded comes
+/// from the file's id index, built on first use.
+///
+/// Listings are visited with their canonical ids (see `ids`) and manual
+/// corrections applied, and not at all when a correction deleted them; see
+/// `corrections`.
+fn scan_properties_from(
+    source: &SourceConfig,
+    path: &Path,
+    start: usize,
+    mut visit: impl FnMut(usize, StandardizedProperty) -> ControlFlow<()>,
+) -> ScanStats {
+    let superseded = SnapshotIndex::load_or_build(source, path)
+        .map(|index| index.superseded())
+        .unwrap_or_default();
+    let stats = scan_rows(source, path, start, &superseded, |offset, mut property| {
+        identify(&mut property, source, path);
+        match corrections::apply(&mut property, &source.parsing) {
+            true => visit(offset, property),
+            false => ControlFlow::Continue(()),
+        }
+    });
+    ids::flush();
+    if stats.duplicates > 0 {
+        info!("Skipped {} duplicate listings in {:?}", stats.duplicates, path);
+    }
+    stats
+}
+
+/// Gives a listing freshly parsed from the snapshot at `path` its canonical id
+/// (see `ids`) and the short id derived from it (see `links`).
+fn identify(property: &mut StandardizedProperty, source: &SourceConfig, path: &Path) {
+    ids::assign(property, path, source.parsing.clock.today());
+    property.short_id = links::short_id(property);
+}
+
+/// Parses rows from `start`, skipping the offsets in `skip`, without
+/// deduplicating anything else.
+fn scan_rows(
+    source: &SourceConfig,
+    path: &Path,
+    start: usize,
+    skip: &HashSet<usize>,
+    mut visit: impl FnMut(usize, StandardizedProperty) -> ControlFlow<()>,
+) -> ScanStats {
+    let mut stats = ScanStats::default();
+    let Some((mut builder, columns)) = open_snapshot(source, path) else {
+        stats.errors += 1;
+        return stats;
+    };
+    let file_rows = builder.metadata().file_metadata().num_rows().max(0) as usize;
+    let start = start.min(file_rows);
+    stats.total_rows = file_rows - start;
+    if start > 0 {
+        let selectors = vec![RowSelector::skip(start), RowSelector::select(file_rows - start)];
+        builder = builder.with_row_selection(RowSelection::from(selectors));
+    }
+
+    match builder.build() {
+        Ok(reader) => {
+            for batch_result in reader {
+                match batch_result {
+                    Ok(batch) => {
+                        let batch = decode_dictionaries(batch);
+                        for index in 0..batch.num_rows() {
+                            let offset = start + stats.rows_scanned;
+                            stats.rows_scanned += 1;
+                            if skip.contains(&offset) {
+                                stats.duplicates += 1;
+                                continue;
+                            }
+                            let row = columns.row(&batch, index);
+                            let Some(property) = parse_source_row(source, &columns, &row) else {
+                                continue;
+                            };
+                            if visit(offset, property).is_break() {
+                                debug!("Stopped scanning {} after {} of {} rows",
+                                    source.name, stats.rows_scanned, stats.total_rows);
+                                return stats;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        error!("Error reading record batch: {}", e);
+                        stats.errors += 1;
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            error!("Error building batch reader for {}: {}", source.name, e);
+            stats.errors += 1;
+        }
+    }
+
+    stats
+}
+
+/// Parses every row of a snapshot like `scan_properties`, decoding the record
+/// batches one after another but parsing them in parallel on `pool`. Rows come
+/// back in file order, superseded duplicates left out.
+fn parse_snapshot_parallel(
+    source: &SourceConfig,
+    path: &Path,
+    pool: &rayon::ThreadPool,
+) -> (Vec<(usize, StandardizedProperty)>, ScanStats) {
+    let mut stats = ScanStats::default();
+    let superseded = SnapshotIndex::load_or_build(source, path)
+        .map(|index| index.superseded())
+        .unwrap_or_default();
+    let Some((builder, columns)) = open_snapshot(source, path) else {
+        stats.errors += 1;
+        return (vec![], stats);
+    };
+    stats.total_rows = builder.metadata().file_metadata().num_rows().max(0) as usize;
+
+    let mut batches = Vec::new();
+    match builder.build() {
+        Ok(reader) => {
+            for batch_result in reader {
+                match batch_result {
+                    Ok(batch) => {
+                        let rows = batch.num_rows();
+                        batches.push((stats.rows_scanned, batch));
+                        stats.rows_scanned += rows;
+                    }
+                    Err(e) => {
+                        error!("Error reading record batch: {}", e);
+                        stats.errors += 1;
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            error!("Error building batch reader for {}: {}", source.name, e);
+            stats.errors += 1;
+        }
+    }
+    stats.duplicates = superseded.iter().filter(|offset| **offset < stats.rows_scanned).count();
+    if stats.duplicates > 0 {
+        info!("Skipped {} duplicate listings in {:?}", stats.duplicates, path);
+    }
+
+    let parsed: Vec<Vec<(usize, StandardizedProperty)>> = pool.install(|| {
+        batches
+            .into_par_iter()
+            .map(|(start, batch)| {
+                let batch = decode_dictionaries(batch);
+                (0..batch.num_rows())
+                    .filter(|index| !superseded.contains(&(start + index)))
+                    .filter_map(|index| {
+                        let row = columns.row(&batch, index);
+                        let mut property = parse_source_row(source, &columns, &row)?;
+                        identify(&mut property, source, path);
+                        corrections::apply(&mut property, &source.parsing).then_some((start + index, property))
+                    })
+                    .collect()
+            })
+            .collect()
+    });
+    ids::flush();
+    (parsed.into_iter().flatten().collect(), stats)
+}
+
+/// Parses only the rows at the given offsets, skipping every other data page.
+/// Returns each parsed property with its offset.
+fn read_rows(source: &SourceConfig, path: &Path, offsets: &[usize]) -> Vec<(usize, StandardizedProperty)> {
+    let mut properties = Vec::new();
+    select_rows(source, path, offsets, |offset, columns, row| {
+        if let Some(mut property) = parse_source_row(source, columns, row) {
+            identify(&mut property, source, path);
+            if corrections::apply(&mut property, &source.parsing) {
+                properties.push((offset, property));
+            }
+        }
+    });
+    ids::flush();
+    properties
+}
+
+/// Hands the rows at the given offsets, in offset order, to `visit` without
+/// parsing them.
+fn select_rows(
+    source: &SourceConfig,
+    path: &Path,
+    offsets: &[usize],
+    mut visit: impl FnMut(usize, &ResolvedColumns, &BatchRow),
+) {
+    let mut offsets = offsets.to_vec();
+    offsets.sort_unstable();
+    offsets.dedup();
+    let Some((builder, columns)) = open_snapshot(source, path) else {
+        return;
+    };
+
+    let mut selectors = Vec::with_capacity(offsets.len() * 2);
+    let mut position = 0;
+    for &offset in &offsets {
+        selectors.push(RowSelector::skip(offset - position));
+        selectors.push(RowSelector::select(1));
+        position = offset + 1;
+    }
+
+    let mut selected = offsets.iter();
+    match builder.with_row_selection(RowSelection::from(selectors)).build() {
+        Ok(reader) => {
+            for batch_result in reader {
+                match batch_result {
+                    Ok(batch) => {
+                        let batch = decode_dictionaries(batch);
+                        for index in 0..batch.num_rows() {
+                            let Some(&offset) = selected.next() else { break };
+                            visit(offset, &columns, &columns.row(&batch, index));
+                        }
+                    }
+                    Err(e) => error!("Error reading record batch: {}", e),
+                }
+            }
+        }
+        Err(e) => error!("Error building batch reader for {}: {}", source.name, e),
+    }
+}
+
+/// Parses every row of a snapshot file.
+fn load_properties(source: &SourceConfig, path: &Path) -> Vec<StandardizedProperty> {
+    let mut properties = Vec::new();
+    scan_properties(source, path, |_, property| {
+        properties.push(property);
+        ControlFlow::Continue(())
+    });
+    properties
+}
+
+/// Row count from the parquet footer, without reading any data pages.
+fn parquet_row_count(path: &Path) -> usize {
+    File::open(path)
+        .ok()
+        .and_then(|file| ParquetRecordBatchReaderBuilder::try_new(file).ok())
+        .map(|builder| builder.metadata().file_metadata().num_rows().max(0) as usize)
+        .unwrap_or(0)
+}
+
+/// Total matches across sources: exact when every file was fully scanned,
+/// otherwise extrapolated from the match rate of the rows that were scanned.
+fn estimate_total_matches(scans: &[(usize, ScanStats)]) -> (usize, bool) {
+    let matched: usize = scans.iter().map(|(matched, _)| matched).sum();
+    let scanned: usize = scans.iter().map(|(_, stats)| stats.rows_scanned).sum();
+    if scans.iter().all(|(_, stats)| stats.rows_scanned >= stats.total_rows) {
+        return (matched, true);
+    }
+
+    let pooled_rate = if scanned > 0 { matched as f64 / scanned as f64 } else { 0.0 };
+    let estimate: f64 = scans
+        .iter()
+        .map(|(matched, stats)| {
+            if stats.rows_scanned >= stats.total_rows {
+                *matched as f64
+            } else if stats.rows_scanned > 0 {
+                *matched as f64 * stats.total_rows as f64 / stats.rows_scanned as f64
+            } else {
+                pooled_rate * stats.total_rows as f64
+            }
+        })
+        .sum();
+    (estimate.round() as usize, false)
+}
+
+/// Numeric subdirectories of `dir` (the year/month/day partitions), sorted ascending.
+fn numeric_subdirs(dir: &Path) -> Vec<(u32, PathBuf)> {
+    let mut entries: Vec<_> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| {
+                entry.ok().and_then(|e| {
+                    e.path()
+                        .file_name()
+                        .and_then(|n| n.to_str())
+                        .and_then(|s| s.parse::<u32>().ok())
+                        .map(|value| (value, e.path()))
+                })
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    entries.sort_by_key(|(value, _)| *value);
+    entries
+}
+
+/// Every daily snapshot of a source, oldest first, as the partition date and the
+/// newest complete parquet file written that day.
+fn list_snapshots(source_path: &Path) -> Vec<(NaiveDate, PathBuf)> {
+    let mut snapshots = Vec::new();
+
+    for (year, year_path) in numeric_subdirs(source_path) {
+        for (month, month_path) in numeric_subdirs(&year_path) {
+            for (day, day_path) in numeric_subdirs(&month_path) {
+                let Some(date) = NaiveDate::from_ymd_opt(year as i32, month, day) else {
+                    continue;
+                };
+                if let Some(file) = newest_parquet(&day_path, partial::is_complete) {
+                    snapshots.push((date, file));
+                }
+            }
+        }
+    }
+
+    snapshots
+}
+
+fn rent_in_range(amount: f64) -> bool {
     amount > 0.0 && amount < 100000.0 // Reasonable range for monthly rent
 }
 
-async fn health_check() -> &'static str {
-    "OK"
+/// Whether the listing's rent can go into rent statistics: in range, and
+/// within the bounds of its segment (see `price_bounds`).
+fn validate_price(property: &StandardizedProperty) -> bool {
+    rent_in_range(property.price.amount) && property.price_flag.is_none()
 }
 
-async fn debug_paths() -> String {
+async fn debug_paths(State(state): State<AppState>) -> String {
     let current_dir = env::current_dir().unwrap_or_default();
-    let data_path = current_dir.join("housing_data");
+    let data_path = current_dir.join(&state.config.data_path);
 
     format!(
         "Current directory: {:?}\nData path: {:?}\nExists: {}",
@@ -543,211 +1310,1085 @@ async fn debug_paths() -> String {
 }
 
 
-async fn search_rentals(Query(params): Query<SearchParams>) -> Json<Vec<StandardizedProperty>> {
+async fn search_rentals(
+    State(state): State<AppState>,
+    caller: Caller,
+    lang: Lang,
+    Query(params): Query<SearchParams>,
+) -> Result<Response, (StatusCode, String)> {
+    search(state, caller, lang, params).await
+}
+
+/// Same search with the parameters as a JSON body, for filters too long or
+/// awkward to encode in a query string.
+async fn search_rentals_post(
+    State(state): State<AppState>,
+    caller: Caller,
+    lang: Lang,
+    Json(params): Json<SearchParams>,
+) -> Result<Response, (StatusCode, String)> {
+    search(state, caller, lang, params).await
+}
+
+async fn search(state: AppState, caller: Caller, lang: Lang, params: SearchParams) -> Result<Response, (StatusCode, String)> {
+    let params = presets::apply(&state.config.presets, params)?;
+    let (mut params, mut defaulted) = presets::apply_defaults(&state.config.search.defaults, params)?;
+    // A default page size or sort gives way to paging by number or ranking by
+    // similarity rather than clashing with it
+    let (numbered, semantic) = (params.page.is_some() || params.page_size.is_some(), params.semantic_q.is_some());
+    let replaced = |field: &str| match field {
+        "limit" => numbered,
+        "sort" | "sort_order" => semantic,
+        _ => false,
+    };
+    for field in defaulted.iter().filter(|field| replaced(field)) {
+        match field.as_str() {
+            "limit" => params.limit = None,
+            "sort" => params.sort = None,
+            _ => params.sort_order = None,
+        }
+    }
+    defaulted.retain(|field| !replaced(field));
+    let page = Page::requested(&params, &state.config.search).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if let Some(unknown) = params.property_type.as_deref().map(property_type::unknown_terms).filter(|u| !u.is_empty()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Unknown property_type {:?}; see /api/property-types", unknown.join(", ")),
+        ));
+    }
+    if params.category == Some(commercial::Category::Commercial) && !state.config.search.commercial {
+        return Err((StatusCode::BAD_REQUEST, "Commercial listings are not enabled (search.commercial)".to_string()));
+    }
+    params.category = Some(commercial::Category::effective(params.category, state.config.search.commercial));
     let mut properties = Vec::new();
-    let sources = match &params.source {
-        Some(source) => vec![source.as_str()],
-        None => vec!["daft", "myhome", "property"]
+    let config = &state.config;
+    let sources = config.select_sources(params.source.as_deref());
+    let pinned = match &params.snapshot_token {
+        Some(token) => match state.snapshot_pins.resolve(token) {
+            Some(files) => Some(files),
+            None => return Err((StatusCode::GONE, "Snapshot token expired or unknown; restart from the first page".to_string())),
+        },
+        None => None,
+    };
+    let cursor = match &params.cursor {
+        Some(encoded) => match Cursor::decode(encoded) {
+            Some(cursor) => Some(cursor),
+            None => return Err((StatusCode::BAD_REQUEST, "Invalid cursor".to_string())),
+        },
+        None => None,
+    };
+    // A cursor resumes after the last listing of the previous page; sources
+    // that come before it in the search order are already exhausted
+    let sources = match &cursor {
+        Some(cursor) => match sources.iter().position(|s| s.name == cursor.source) {
+            Some(position) => sources[position..].to_vec(),
+            None => return Err((StatusCode::BAD_REQUEST, "Cursor does not match the requested sources".to_string())),
+        },
+        None => sources,
     };
+    if params.ranked() && cursor.is_some() {
+        return Err((StatusCode::BAD_REQUEST, "Cursors can't page sorted results; use offset".to_string()));
+    }
+    if params.semantic_q.is_some() {
+        if !config.semantic.enabled {
+            return Err((StatusCode::BAD_REQUEST, "Semantic search is not enabled (semantic.enabled)".to_string()));
+        }
+        if params.sort.is_some() {
+            return Err((StatusCode::BAD_REQUEST, "semantic_q ranks results by similarity; drop sort".to_string()));
+        }
+    }
+    if params.sort_order.is_some() && params.sort.is_none() {
+        return Err((StatusCode::BAD_REQUEST, "sort_order needs a sort".to_string()));
+    }
+    if params.include_raw {
+        raw::require_scope(&caller)?;
+    }
+    if params.explain {
+        let plan = explain::plan(&state, &params, &sources, pinned.as_ref(), cursor.as_ref());
+        return Ok(Json(plan).into_response());
+    }
+    let mut searched_files = HashMap::new();
+    let offset = match &cursor {
+        Some(_) => 0,
+        None => params.offset.unwrap_or_else(|| page.offset()),
+    };
+    let mut last_row = None;
+    let mut diagnostics = SearchDiagnostics::new(&params);
+    // A sorted page can only be cut once every match is in
+    let scan_offset = if params.ranked() { 0 } else { offset };
+    // Matches needed before the scan can stop early
+    let wanted = Some(offset + page.size).filter(|_| !params.ranked());
+    let mut matched = 0;
+    let mut scans = Vec::new();
+    let mut locations = Vec::new();
+    let mut source_warnings = Vec::new();
+    if params.source.is_none() {
+        let disabled = config.sources.iter().filter(|source| source.disabled);
+        source_warnings.extend(disabled.map(|source| SourceWarning::disabled(&source.name)));
+    }
+    let today = state.clock.today();
+    let hidden = state.hidden.for_caller(&caller);
 
     debug!("Starting search with params: {:?}", params);
-    debug!("Searching in sources: {:?}", sources);
-
-    let data_path = "housing_data";
+    debug!("Searching in sources: {:?}", sources.iter().map(|s| &s.name).collect::<Vec<_>>());
+
+    // Finding the snapshots reads directories and parquet footers, so it runs
+    // on the blocking pool like the scans
+    let locating = state.clone();
+    let wanted_sources: Vec<SourceConfig> = sources.iter().map(|source| (*source).clone()).collect();
+    let located = tokio::task::spawn_blocking(move || {
+        wanted_sources.iter().map(|source| locate_snapshot(&locating, source)).collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
-    for source in sources {
-        // Skip if source doesn't match requested source
-        if let Some(ref requested_source) = params.source {
-            if requested_source.to_lowercase() != source.to_lowercase() {
+    for (source, (serving, pending)) in sources.into_iter().zip(located) {
+        debug!("Processing source: {}", source.name);
+        
+        if let Some(fallback::Serving { file, replaces: Some(unreadable) }) = &serving {
+            warn!("Latest {} snapshot {:?} is unreadable, serving {:?}", source.name, unreadable, file);
+            source_warnings.push(SourceWarning::fallback(&source.name, unreadable, file));
+        }
+        let latest = serving.map(|serving| serving.file);
+        if let Some(pending) = pending {
+            warn!("Skipping {:?} for {} while it is being written", pending, source.name);
+            source_warnings.push(SourceWarning::partial(&source.name, &pending, latest.as_deref()));
+        }
+        if let Some(stale) = latest
+            .as_deref()
+            .and_then(|file| warnings::stale(&source.name, file, config.search.stale_after_days, today))
+        {
+            source_warnings.push(stale);
+        }
+        let pinned_file = pinned.as_ref().and_then(|files| files.get(&source.name)).cloned();
+        if let Some(latest_file) = pinned_file.or_else(|| latest.clone()) {
+            debug!("Using file for {}: {:?}", source.name, latest_file);
+            searched_files.insert(source.name.clone(), latest_file.clone());
+
+            if wanted.is_some_and(|wanted| matched >= wanted) {
+                debug!("Limit reached, skipping scan of {}", source.name);
+                let file = latest_file.clone();
+                let total_rows = tokio::task::spawn_blocking(move || parquet_row_count(&file)).await.unwrap_or(0);
+                let stats = ScanStats { total_rows, ..Default::default() };
+                scans.push((0, stats));
                 continue;
             }
-        }
 
-        debug!("Processing source: {}", source);
-        
-        if let Some(latest_file) = find_latest_parquet(source, data_path) {
-            debug!("Found latest file for {}: {:?}", source, latest_file);
-
-            match File::open(&latest_file) {
-                Ok(file) => {
-                    match SerializedFileReader::new(file) {
-                        Ok(reader) => {
-                            match reader.get_row_iter(None) {
-                                Ok(iter) => {
-                                    for row_result in iter {
-                                        match row_result {
-                                            Ok(row) => {
-                                                let property = match source {
-                                                    "daft" => {
-                                                        debug!("Parsing Daft row");
-                                                        match parse_daft_row(&row) {
-                                                            Some(p) => {
-                                                                debug!("Successfully parsed Daft property: {} - {}", 
-                                                                    p.property_id, p.price.amount);
-                                                                p
-                                                            },
-                                                            None => {
-                                                                debug!("Failed to parse Daft property");
-                                                                continue;
-                                                            }
-                                                        }
-                                                    },
-                                                    "myhome" => {
-                                                        match parse_myhome_row(&row) {
-                                                            Some(p) => p,
-                                                            None => continue,
-                                                        }
-                                                    },
-                                                    "property" => {
-                                                        let address = row
-                                                            .get_string(0)
-                                                            .map(|s| s.to_string())
-                                                            .unwrap_or_default();
-                                                        let price_string = row
-                                                            .get_string(1)
-                                                            .map(|s| s.to_string())
-                                                            .unwrap_or_default();
-                                                        let id = row
-                                                            .get_string(2)
-                                                            .map(|s| s.to_string())
-                                                            .unwrap_or_default();
-
-                                                        StandardizedProperty::from_property_ie(PropertyIEListing {
-                                                            address,
-                                                            price: price_string,
-                                                            id,
-                                                        })
-                                                    },
-                                                    _ => continue,
-                                                };
-
-                                                // Validate the price before including the property
-                                                if !validate_price(property.price.amount) {
-                                                    debug!("Invalid price {} for property {}", 
-                                                        property.price.amount, property.property_id);
-                                                    continue;
-                                                }
-
-                                                // Apply filters
-                                                if should_include_property(&property, &params) {
-                                                    debug!("Adding property {} with price {}", 
-                                                        property.property_id, property.price.amount);
-                                                    properties.push(property);
-                                                } else {
-                                                    debug!("Property {} filtered out by criteria", 
-                                                        property.property_id);
-                                                }
-                                            }
-                                            Err(e) => error!("Error reading row: {}", e),
-                                        }
-                                    }
-                                }
-                                Err(e) => error!("Error getting row iterator: {}", e),
-                            }
-                        }
-                        Err(e) => error!("Error creating reader for {}: {}", source, e),
-                    }
+            let start = match &cursor {
+                Some(cursor) if cursor.source == source.name => cursor.row + 1,
+                _ => 0,
+            };
+            let scan = SourceScan {
+                state: state.clone(),
+                source: source.clone(),
+                file: latest_file.clone(),
+                // Superseded snapshots pinned by a token are streamed so they
+                // don't displace the latest one in the cache
+                cached: latest.as_ref() == Some(&latest_file),
+                start,
+                params: params.clone(),
+                hidden: hidden.clone(),
+                before: matched,
+                offset: scan_offset,
+                wanted,
+            };
+            // Matches arrive as the scan finds them; giving up on the source
+            // drops the receiver, which stops the scan
+            let (sender, mut receiver) = mpsc::channel(SCAN_BUFFER);
+            let task = tokio::task::spawn_blocking(move || scan.run(sender));
+            let collect = async move {
+                let mut page = Vec::new();
+                while let Some(found) = receiver.recv().await {
+                    page.push(found);
+                }
+                task.await.map(|found| (page, found))
+            };
+            let outcome = match config.search.source_timeout_ms {
+                0 => Ok(collect.await),
+                ms => tokio::time::timeout(std::time::Duration::from_millis(ms), collect).await,
+            };
+            let (page, found) = match outcome {
+                Ok(Ok(found)) => found,
+                Ok(Err(e)) => {
+                    error!("Scan of {} failed: {}", source.name, e);
+                    source_warnings.push(SourceWarning::failed(&source.name, &latest_file, 1));
+                    continue;
+                }
+                Err(_) => {
+                    warn!("Scan of {:?} for {} timed out", latest_file, source.name);
+                    source_warnings.push(SourceWarning::timed_out(&source.name, config.search.source_timeout_ms));
+                    continue;
                 }
-                Err(e) => error!("Error opening file for {}: {}", source, e),
+            };
+            matched += found.matched;
+            diagnostics.merge(found.diagnostics);
+            for (row, property) in page {
+                last_row = Some((source.name.clone(), row));
+                if params.include_raw {
+                    locations.push((source.name.clone(), latest_file.clone(), row));
+                }
+                properties.push(property);
+            }
+            if found.stats.errors > 0 {
+                source_warnings.push(SourceWarning::failed(&source.name, &latest_file, found.stats.errors));
             }
+            scans.push((found.matched, found.stats));
         } else {
-            warn!("No parquet file found for source: {}", source);
+            warn!("No parquet file found for source: {}", source.name);
+            source_warnings.push(SourceWarning::missing(&source.name));
         }
     }
 
-    debug!("Found {} total properties", properties.len());
-    Json(properties)
-}
+    if params.strict && source_warnings.iter().any(SourceWarning::unserved) {
+        return Ok((StatusCode::BAD_GATEWAY, Json(source_warnings)).into_response());
+    }
 
-fn should_include_property(property: &StandardizedProperty, params: &SearchParams) -> bool {
-    debug!("Checking property {} against filters", property.property_id);
-    
-    // Price filters
-    if let Some(min_price) = params.min_price {
-        if property.price.amount < min_price {
-            debug!("Property {} filtered out by min price: {} < {}", 
-                property.property_id, property.price.amount, min_price);
-            return false;
+    let (total, exact) = estimate_total_matches(&scans);
+    let mut headers = HeaderMap::new();
+    if !defaulted.is_empty() {
+        if let Ok(value) = HeaderValue::from_str(&defaulted.join(",")) {
+            headers.insert("x-search-defaults", value);
         }
     }
-    if let Some(max_price) = params.max_price {
-        if property.price.amount > max_price {
-            debug!("Property {} filtered out by max price: {} > {}", 
-                property.property_id, property.price.amount, max_price);
-            return false;
+    if !source_warnings.is_empty() {
+        let json = serde_json::to_string(&source_warnings).unwrap_or_default();
+        if let Ok(value) = HeaderValue::from_str(&json) {
+            headers.insert(warnings::HEADER, value);
+        }
+    }
+    // Counts after a cursor only cover the rest of the result set
+    if cursor.is_none() {
+        let header = if exact { "x-total-count" } else { "x-total-count-estimate" };
+        headers.insert(header, HeaderValue::from(total));
+    }
+    if wanted.is_some_and(|wanted| matched >= wanted) {
+        if let Some((source, row)) = last_row {
+            if let Ok(value) = HeaderValue::from_str(&Cursor { source, row }.encode()) {
+                headers.insert("x-next-cursor", value);
+            }
+        }
+    }
+    let dates: BTreeMap<&String, NaiveDate> =
+        searched_files.iter().filter_map(|(source, file)| Some((source, snapshot_date(file)?))).collect();
+    if let Ok(value) = HeaderValue::from_str(&serde_json::to_string(&dates).unwrap_or_default()) {
+        headers.insert("x-snapshot-dates", value);
+    }
+    let names: Vec<String> = searched_files.keys().cloned().collect();
+    let ranked_files = params.semantic_q.as_ref().map(|_| searched_files.clone());
+    cdn::tag(&mut headers, &config.cdn, &caller, &searched_files);
+    let token = match &params.snapshot_token {
+        Some(token) => token.clone(),
+        None => state.snapshot_pins.pin(searched_files),
+    };
+    if let Ok(value) = HeaderValue::from_str(&token) {
+        headers.insert("x-snapshot-token", value);
+    }
+
+    // Explain why nothing matched; a later page running past the end is not that
+    if matched == 0 {
+        let json = serde_json::to_string(&diagnostics).unwrap_or_default();
+        if let Ok(value) = HeaderValue::from_str(&json) {
+            headers.insert("x-search-diagnostics", value);
         }
     }
 
-    // Bedrooms filter
-    if let Some(bedrooms) = params.bedrooms {
-        if let Some(prop_beds) = property.bedrooms {
-            if prop_beds != bedrooms {
-                debug!("Property {} filtered out by bedrooms: {} != {}", 
-                    property.property_id, prop_beds, bedrooms);
-                return false;
+    // Count each search once, not once per page
+    if offset == 0 && cursor.is_none() {
+        let now = state.clock.now();
+        state.analytics.record_search(&params, properties.len(), now);
+        state.history.record(&caller, &params, properties.len(), now);
+    }
+
+    let order = if let (Some(query), Some(files)) = (params.semantic_q.clone(), ranked_files) {
+        let ranking = state.clone();
+        let (ranked, order) = tokio::task::spawn_blocking(move || {
+            let order = semantic::rank(&ranking, &query, &mut properties, &files);
+            (properties, order)
+        })
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        properties = ranked;
+        Some(order.map_err(|e| (StatusCode::BAD_GATEWAY, format!("Semantic search failed: {}", e)))?)
+    } else if let Some(sort) = params.sort {
+        let direction = params.sort_order.unwrap_or(sort.default_order());
+        Some(match sort {
+            SortKey::Value => {
+                let store = state.store.clone();
+                let model = tokio::task::spawn_blocking(move || {
+                    let listings: Vec<StandardizedProperty> =
+                        names.iter().flat_map(|name| store.latest(name)).collect();
+                    analytics::value::ValueModel::fit(&listings)
+                })
+                .await
+                .ok()
+                .flatten();
+                analytics::value::order(&mut properties, model.as_ref());
+                sorted_by(&properties, direction, |p| p.value_score)
             }
-        } else {
-            debug!("Property {} filtered out: no bedroom info", property.property_id);
-            return false;
+            SortKey::Newest => sorted_by(&properties, direction, |p| Some(&p.updated_date).filter(|d| !d.is_empty())),
+            SortKey::Price => sorted_by(&properties, direction, |p| Some(p.price.amount).filter(|rent| *rent > 0.0)),
+            SortKey::CreatedDate => {
+                sorted_by(&properties, direction, |p| Some(&p.created_date).filter(|d| !d.is_empty()))
+            }
+            SortKey::Bedrooms => sorted_by(&properties, direction, |p| p.bedrooms),
+            SortKey::Size => sorted_by(&properties, direction, floor_area::usable_sqm),
+        })
+    } else {
+        None
+    };
+    if let Some(order) = order {
+        let order: Vec<usize> = order
+            .into_iter()
+            .skip(offset)
+            .take(page.size)
+            .collect();
+        properties = pick(properties, &order);
+        if params.include_raw {
+            locations = pick(locations, &order);
         }
     }
 
-    // Property type filter
-    if let Some(ref prop_type) = params.property_type {
-        if !property.property_type
-            .to_lowercase()
-            .contains(&prop_type.to_lowercase()) {
-            debug!("Property {} filtered out by type: {} doesn't contain {}", 
-                property.property_id, property.property_type, prop_type);
-            return false;
+    debug!("Found {} total properties, returning {}", total, properties.len());
+    // Raw records and price changes are read from the snapshots too
+    let indexing = state.clone();
+    let include_raw = params.include_raw;
+    let mut properties = tokio::task::spawn_blocking(move || {
+        if include_raw {
+            raw::attach(&indexing.config, &mut properties, &locations);
+        }
+        indexing.price_changes.attach_all(&indexing.config, &mut properties);
+        properties
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    state.privacy.redact_all(&mut properties, &caller);
+    state.notes.attach_all(&mut properties, &caller);
+    state.photos.serve_offline(&mut properties);
+    if params.format_values {
+        display::attach_all(&mut properties, lang);
+    }
+    let paged = Paged { total, page: page.number, page_size: page.size, results: properties };
+    Ok((headers, Json(paged)).into_response())
+}
+
+/// Positions of `properties` ordered by `key` in `direction`. Listings
+/// without a key come last; ties keep their order.
+fn sorted_by<'a, K: PartialOrd>(
+    properties: &'a [StandardizedProperty],
+    direction: SortOrder,
+    key: impl Fn(&'a StandardizedProperty) -> Option<K>,
+) -> Vec<usize> {
+    let keys: Vec<Option<K>> = properties.iter().map(key).collect();
+    let mut order: Vec<usize> = (0..properties.len()).collect();
+    order.sort_by(|a, b| match (&keys[*a], &keys[*b]) {
+        (Some(a), Some(b)) => {
+            let ordering = a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal);
+            if direction == SortOrder::Desc { ordering.reverse() } else { ordering }
+        }
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    });
+    order
+}
+
+/// The items at `order`, in that order.
+fn pick<T>(items: Vec<T>, order: &[usize]) -> Vec<T> {
+    let mut items: Vec<Option<T>> = items.into_iter().map(Some).collect();
+    order.iter().filter_map(|&index| items[index].take()).collect()
+}
+
+#[derive(Debug, Deserialize)]
+struct RentalParams {
+    /// Attach the listing's source record; see `raw`.
+    #[serde(default)]
+    include_raw: bool,
+    /// Add display strings; see `display`.
+    #[serde(default)]
+    format_values: bool,
+}
+
+async fn get_rental(
+    State(state): State<AppState>,
+    caller: Caller,
+    lang: Lang,
+    extract::Path(property_id): extract::Path<String>,
+    Query(params): Query<RentalParams>,
+) -> Result<Json<StandardizedProperty>, (StatusCode, String)> {
+    if params.include_raw {
+        raw::require_scope(&caller)?;
+    }
+    let reading = state.clone();
+    let property = tokio::task::spawn_blocking(move || {
+        let mut property = reading.id_index.lookup(&reading.config, &[property_id]).into_iter().next()?;
+        if params.include_raw {
+            if let Some(location) = raw::locate(&reading, &property) {
+                raw::attach(&reading.config, std::slice::from_mut(&mut property), &[location]);
+            }
+        }
+        reading.price_changes.attach_all(&reading.config, std::slice::from_mut(&mut property));
+        Some(property)
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let mut property = property.ok_or((StatusCode::NOT_FOUND, String::new()))?;
+    state.privacy.redact(&mut property, &caller);
+    state.notes.attach_all(std::slice::from_mut(&mut property), &caller);
+    state.photos.serve_offline(std::slice::from_mut(&mut property));
+    if params.format_values {
+        display::attach_all(std::slice::from_mut(&mut property), lang);
+    }
+    Ok(Json(property))
+}
+
+#[derive(Debug, Deserialize)]
+struct LookupRequest {
+    ids: Vec<String>,
+    /// Add display strings; see `display`.
+    #[serde(default)]
+    format_values: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct LookupResponse {
+    results: Vec<StandardizedProperty>,
+    missing: Vec<String>,
+}
+
+async fn lookup_rentals(
+    State(state): State<AppState>,
+    caller: Caller,
+    lang: Lang,
+    Json(request): Json<LookupRequest>,
+) -> Result<Json<LookupResponse>, (StatusCode, String)> {
+    let (reading, ids) = (state.clone(), request.ids.clone());
+    let mut results = tokio::task::spawn_blocking(move || {
+        let mut results = reading.store.get(&ids);
+        reading.price_changes.attach_all(&reading.config, &mut results);
+        results
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    state.privacy.redact_all(&mut results, &caller);
+    state.notes.attach_all(&mut results, &caller);
+    state.photos.serve_offline(&mut results);
+    if request.format_values {
+        display::attach_all(&mut results, lang);
+    }
+    let missing = request
+        .ids
+        .into_iter()
+        .filter(|id| !results.iter().any(|p| p.ids().any(|known| known == id)))
+        .collect();
+    Ok(Json(LookupResponse { results, missing }))
+}
+
+/// The filters a search can apply, in the order they are checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SearchFilter {
+    MinPrice,
+    MaxPrice,
+    Bedrooms,
+    MaxPerPerson,
+    PriceBand,
+    PropertyType,
+    Category,
+    BerRating,
+    Location,
+    Parking,
+    OutdoorSpace,
+    AvailableFrom,
+    LeaseTerm,
+    Photos,
+    Floorplan,
+    PriceFlagged,
+    AgentFlagged,
+}
+
+impl SearchFilter {
+    const ALL: [SearchFilter; 17] = [
+        SearchFilter::MinPrice,
+        SearchFilter::MaxPrice,
+        SearchFilter::Bedrooms,
+        SearchFilter::MaxPerPerson,
+        SearchFilter::PriceBand,
+        SearchFilter::PropertyType,
+        SearchFilter::Category,
+        SearchFilter::BerRating,
+        SearchFilter::Location,
+        SearchFilter::Parking,
+        SearchFilter::OutdoorSpace,
+        SearchFilter::AvailableFrom,
+        SearchFilter::LeaseTerm,
+        SearchFilter::Photos,
+        SearchFilter::Floorplan,
+        SearchFilter::PriceFlagged,
+        SearchFilter::AgentFlagged,
+    ];
+
+    /// Human-readable form of the filter, or `None` when the search doesn't use it.
+    fn describe(self, params: &SearchParams) -> Option<String> {
+        match self {
+            SearchFilter::MinPrice => params.min_price.map(|v| format!("price >= {}", v)),
+            SearchFilter::MaxPrice => params.max_price.map(|v| format!("price <= {}", v)),
+            SearchFilter::Bedrooms => params.bedrooms.map(|v| format!("bedrooms = {}", v)),
+            SearchFilter::MaxPerPerson => params.max_per_person.map(|v| format!("rent per person <= {}", v)),
+            SearchFilter::PriceBand => params.price_band.as_ref().map(|v| format!("price_band in {:?}", v)),
+            SearchFilter::PropertyType => params.property_type.as_ref().map(|v| format!("property_category in {:?} or below", v)),
+            SearchFilter::Category => match params.category.unwrap_or(commercial::Category::All) {
+                commercial::Category::All => None,
+                category => Some(format!("category = {:?}", category).to_lowercase()),
+            },
+            SearchFilter::BerRating => params.ber_rating.as_ref().map(|v| format!("ber_rating matches {:?}", v)),
+            SearchFilter::Location => params.location.as_ref().map(|v| format!("address in {:?}", v)),
+            SearchFilter::Parking => params.min_parking.map(|v| format!("parking_spaces >= {}", v)),
+            SearchFilter::OutdoorSpace => match (params.garden, params.balcony) {
+                (None, None) => None,
+                (garden, balcony) => Some(format!("garden = {:?}, balcony = {:?}", garden, balcony)),
+            },
+            SearchFilter::AvailableFrom => params.available_from_before.map(|v| format!("available_from <= {}", v)),
+            SearchFilter::LeaseTerm => params.min_lease_months.map(|v| format!("min_lease_months <= {}", v)),
+            SearchFilter::Photos => params.min_photos.map(|v| format!("photo_count >= {}", v)),
+            SearchFilter::Floorplan => params.floorplan.map(|v| format!("has_floorplan = {}", v)),
+            SearchFilter::PriceFlagged => params.price_flagged.map(|v| format!("price_flagged = {}", v)),
+            SearchFilter::AgentFlagged => params.agent_flagged.map(|v| format!("agent_flagged = {}", v)),
+        }
+    }
+
+    /// Whether the property passes this filter. Unused filters always pass.
+    fn matches(self, property: &StandardizedProperty, params: &SearchParams) -> bool {
+        match self {
+            SearchFilter::MinPrice => match params.min_price {
+                Some(min_price) if property.price.amount < min_price => {
+                    debug!("Property {} filtered out by min price: {} < {}",
+                        property.property_id, property.price.amount, min_price);
+                    false
+                }
+                _ => true,
+            },
+            SearchFilter::MaxPrice => match params.max_price {
+                Some(max_price) if property.price.amount > max_price => {
+                    debug!("Property {} filtered out by max price: {} > {}",
+                        property.property_id, property.price.amount, max_price);
+                    false
+                }
+                _ => true,
+            },
+            SearchFilter::Bedrooms => match (params.bedrooms, property.bedrooms) {
+                (None, _) => true,
+                (Some(bedrooms), Some(prop_beds)) if prop_beds != bedrooms => {
+                    debug!("Property {} filtered out by bedrooms: {} != {}",
+                        property.property_id, prop_beds, bedrooms);
+                    false
+                }
+                (Some(_), Some(_)) => true,
+                (Some(_), None) => {
+                    debug!("Property {} filtered out: no bedroom info", property.property_id);
+                    false
+                }
+            },
+            SearchFilter::MaxPerPerson => match params.max_per_person {
+                Some(max) if split::per_person(property).is_none_or(|share| share > max) => {
+                    debug!("Property {} filtered out by rent per person: {:?} > {}",
+                        property.property_id, split::per_person(property), max);
+                    false
+                }
+                _ => true,
+            },
+            SearchFilter::PriceBand => match &params.price_band {
+                Some(bands) if !price_band::matches(bands, &property.price_band) => {
+                    debug!("Property {} filtered out by price band: {} not in {}",
+                        property.property_id, property.price_band, bands);
+                    false
+                }
+                _ => true,
+            },
+            SearchFilter::PropertyType => match &params.property_type {
+                Some(prop_type) if !property_type::matches(prop_type, &property.property_category) => {
+                    debug!("Property {} filtered out by type: {} not under {}",
+                        property.property_id, property.property_category, prop_type);
+                    false
+                }
+                _ => true,
+            },
+            SearchFilter::Category => match params.category.unwrap_or(commercial::Category::All) {
+                category if !category.matches(&property.property_category) => {
+                    debug!("Property {} filtered out by category: {} not {:?}",
+                        property.property_id, property.property_category, category);
+                    false
+                }
+                _ => true,
+            },
+            SearchFilter::BerRating => match &params.ber_rating {
+                Some(ber) if !ber::matches(ber, property.ber_rating.as_deref(), property.ber_status) => {
+                    debug!("Property {} filtered out by BER: {:?} ({:?}) doesn't match {}",
+                        property.property_id, property.ber_rating, property.ber_status, ber);
+                    false
+                }
+                _ => true,
+            },
+            SearchFilter::Location => match &params.location {
+                Some(location) if !address::in_location(&property.address.normalized_address, location) => {
+                    debug!("Property {} filtered out by location: {:?} not in {}",
+                        property.property_id, property.address.normalized_address, location);
+                    false
+                }
+                _ => true,
+            },
+            SearchFilter::Parking => match params.min_parking {
+                Some(min) if property.amenities.parking_spaces.is_none_or(|spaces| spaces < min) => {
+                    debug!("Property {} filtered out by parking: {:?} < {}",
+                        property.property_id, property.amenities.parking_spaces, min);
+                    false
+                }
+                _ => true,
+            },
+            SearchFilter::OutdoorSpace => {
+                // Wanting none excludes listings known to have one; unknown ones pass
+                let wanted = |want: Option<bool>, has: Option<bool>| want.is_none_or(|want| (has == Some(true)) == want);
+                let amenities = &property.amenities;
+                if wanted(params.garden, amenities.garden) && wanted(params.balcony, amenities.balcony) {
+                    true
+                } else {
+                    debug!("Property {} filtered out by outdoor space: garden {:?}, balcony {:?}",
+                        property.property_id, amenities.garden, amenities.balcony);
+                    false
+                }
+            }
+            SearchFilter::AvailableFrom => match params.available_from_before {
+                Some(before) if property.tenancy.available_from.is_none_or(|from| from > before) => {
+                    debug!("Property {} filtered out by availability: {:?} after {}",
+                        property.property_id, property.tenancy.available_from, before);
+                    false
+                }
+                _ => true,
+            },
+            SearchFilter::LeaseTerm => match params.min_lease_months {
+                Some(max) if property.tenancy.min_lease_months.is_some_and(|months| months > max) => {
+                    debug!("Property {} filtered out by lease: {:?} months > {}",
+                        property.property_id, property.tenancy.min_lease_months, max);
+                    false
+                }
+                _ => true,
+            },
+            SearchFilter::Photos => match params.min_photos {
+                Some(min) if property.photo_count < min => {
+                    debug!("Property {} filtered out by photos: {} < {}", property.property_id, property.photo_count, min);
+                    false
+                }
+                _ => true,
+            },
+            SearchFilter::Floorplan => match params.floorplan {
+                Some(wanted) if property.has_floorplan != wanted => {
+                    debug!("Property {} filtered out by floorplan: {}", property.property_id, property.has_floorplan);
+                    false
+                }
+                _ => true,
+            },
+            SearchFilter::PriceFlagged => match params.price_flagged {
+                Some(wanted) if property.price_flag.is_some() != wanted => {
+                    debug!("Property {} filtered out by price flag: {:?}", property.property_id, property.price_flag);
+                    false
+                }
+                _ => true,
+            },
+            SearchFilter::AgentFlagged => match params.agent_flagged {
+                Some(wanted) if property.agent_status.is_some_and(|status| status.flagged()) != wanted => {
+                    debug!("Property {} filtered out by agent status: {:?}",
+                        property.property_id, property.agent_status);
+                    false
+                }
+                _ => true,
+            },
         }
     }
+}
+
+fn should_include_property(property: &StandardizedProperty, params: &SearchParams) -> bool {
+    debug!("Checking property {} against filters", property.property_id);
+    if SearchFilter::ALL.iter().all(|filter| filter.matches(property, params)) {
+        debug!("Property {} passed all filters", property.property_id);
+        true
+    } else {
+        false
+    }
+}
 
-    // BER rating filter
-    if let Some(ref ber) = params.ber_rating {
-        if let Some(ref property_ber) = property.ber_rating {
-            if !property_ber.to_lowercase().contains(&ber.to_lowercase()) {
-                debug!("Property {} filtered out by BER: {} doesn't match {}", 
-                    property.property_id, property_ber, ber);
-                return false;
+#[derive(Debug, Serialize)]
+struct FilterDiagnostic {
+    filter: String,
+    /// Listings that pass this filter on its own.
+    matched: usize,
+    /// Listings that passed every earlier filter but failed this one.
+    eliminated: usize,
+}
+
+/// Matches a scan may get ahead of its search by before it waits.
+const SCAN_BUFFER: usize = 64;
+
+/// The snapshot a search serves for `source`, and any newer one still being
+/// written. Blocking.
+fn locate_snapshot(state: &AppState, source: &SourceConfig) -> (Option<fallback::Serving>, Option<PathBuf>) {
+    let serving = fallback::serving(&state.config, &state.cache, source);
+    (serving, partial::pending(&source.root(&state.config.data_path)))
+}
+
+/// One source's part of a search, run on the blocking pool so neither the
+/// runtime nor other searches wait on the file, and so a slow file can be given
+/// up on; see `search.source_timeout_ms`. Matches on the requested page are
+/// sent to the search as they are found.
+struct SourceScan {
+    state: AppState,
+    source: SourceConfig,
+    file: PathBuf,
+    /// Read through the snapshot cache rather than streamed.
+    cached: bool,
+    /// Row to resume from.
+    start: usize,
+    params: SearchParams,
+    hidden: hidden::HideList,
+    /// Matches found in the sources searched before this one.
+    before: usize,
+    offset: usize,
+    wanted: Option<usize>,
+}
+
+/// What a `SourceScan` found, besides the matches it sent.
+struct SourceMatches {
+    /// Every match in the source, on the page or not.
+    matched: usize,
+    diagnostics: SearchDiagnostics,
+    stats: ScanStats,
+}
+
+impl SourceScan {
+    /// Sends the matches that fall on the requested page to `page`, with their
+    /// row offsets, and stops early once nothing receives them. Blocking.
+    fn run(self, page: mpsc::Sender<(usize, StandardizedProperty)>) -> SourceMatches {
+        let SourceScan { state, source, file, cached, start, params, hidden, before, offset, wanted } = self;
+        let mut found = SourceMatches {
+            matched: 0,
+            diagnostics: SearchDiagnostics::new(&params),
+            stats: ScanStats::default(),
+        };
+        let visit = |row: usize, property: StandardizedProperty| {
+            // Validate the price before including the property
+            if !rent_in_range(property.price.amount) {
+                debug!("Invalid price {} for property {}",
+                    property.price.amount, property.property_id);
+                found.diagnostics.listings_scanned += 1;
+                found.diagnostics.invalid_price += 1;
+                return ControlFlow::Continue(());
+            }
+            found.diagnostics.record(&property, &params);
+
+            // Apply filters
+            if should_include_property(&property, &params) && !hidden.hides(&property) {
+                found.matched += 1;
+                let matched = before + found.matched;
+                if matched > offset && wanted.is_none_or(|wanted| matched <= wanted) {
+                    debug!("Adding property {} with price {}",
+                        property.property_id, property.price.amount);
+                    if page.blocking_send((row, property)).is_err() {
+                        debug!("Search gave up on {}, stopping its scan", source.name);
+                        return ControlFlow::Break(());
+                    }
+                }
+            } else {
+                debug!("Property {} filtered out by criteria",
+                    property.property_id);
+            }
+
+            match wanted {
+                Some(wanted) if before + found.matched >= wanted => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
             }
+        };
+        let stats = if cached {
+            state.cache.scan(&source, &file, start, visit)
         } else {
-            debug!("Property {} filtered out: no BER info", property.property_id);
-            return false;
+            scan_properties_from(&source, &file, start, visit)
+        };
+        found.stats = stats;
+        found
+    }
+}
+
+/// Per-filter counts reported when a search matches nothing.
+#[derive(Debug, Default, Serialize)]
+struct SearchDiagnostics {
+    listings_scanned: usize,
+    invalid_price: usize,
+    filters: Vec<FilterDiagnostic>,
+}
+
+impl SearchDiagnostics {
+    fn new(params: &SearchParams) -> Self {
+        SearchDiagnostics {
+            filters: SearchFilter::ALL
+                .iter()
+                .filter_map(|filter| filter.describe(params))
+                .map(|filter| FilterDiagnostic { filter, matched: 0, eliminated: 0 })
+                .collect(),
+            ..Default::default()
         }
     }
 
-    debug!("Property {} passed all filters", property.property_id);
-    true
+    /// Counts one listing that had a valid price.
+    fn record(&mut self, property: &StandardizedProperty, params: &SearchParams) {
+        self.listings_scanned += 1;
+        let active = SearchFilter::ALL.iter().filter(|filter| filter.describe(params).is_some());
+        let mut eliminated = false;
+        for (filter, diagnostic) in active.zip(self.filters.iter_mut()) {
+            if filter.matches(property, params) {
+                diagnostic.matched += 1;
+            } else if !eliminated {
+                diagnostic.eliminated += 1;
+                eliminated = true;
+            }
+        }
+    }
+
+    /// Adds the counts of a search with the same parameters.
+    fn merge(&mut self, other: SearchDiagnostics) {
+        self.listings_scanned += other.listings_scanned;
+        self.invalid_price += other.invalid_price;
+        for (diagnostic, other) in self.filters.iter_mut().zip(other.filters) {
+            diagnostic.matched += other.matched;
+            diagnostic.eliminated += other.eliminated;
+        }
+    }
 }
 
 
 #[tokio::main]
 async fn main() {
-    // Initialize logging
-    tracing_subscriber::fmt::init();
+    let args: Vec<String> = env::args().collect();
+    // Commands that don't need the config
+    match args.get(1).map(String::as_str) {
+        Some("generate-fixtures") => {
+            if let Err(e) = fixtures::run(&args[2..]) {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+            return;
+        }
+        Some("bench") => match bench::run(&args[2..]).await {
+            Ok(slos_met) => std::process::exit(if slos_met { 0 } else { 1 }),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+        },
+        _ => {}
+    }
+
+    // Logging is configured from the config file, so errors loading it can only
+    // go to stderr
+    let mut config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    match args.get(1).map(String::as_str) {
+        None | Some("serve") => {}
+        Some("validate-data") => std::process::exit(if validate::run(&config) { 0 } else { 1 }),
+        Some("build-deltas") => std::process::exit(if deltas::run(&config) { 0 } else { 1 }),
+        Some("export-dataset") => std::process::exit(if dataset::run(&config, &args[2..]) { 0 } else { 1 }),
+        Some("backtest-watch") => std::process::exit(if reports::watch::run(&config, &args[2..]) { 0 } else { 1 }),
+        Some(other) => {
+            eprintln!(
+                "Unknown command {:?}. Commands: serve (default), validate-data, build-deltas, export-dataset, backtest-watch, \
+                 generate-fixtures, bench",
+                other
+            );
+            std::process::exit(2);
+        }
+    }
+    let _log_guard = match logging::init(&config.logging) {
+        Ok(guard) => guard,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    analytics::sample::configure(config.stats.min_sample);
+    if let Err(e) = demo::prepare(&mut config) {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+
+    let server_config = config.server.clone();
+    let state = AppState::new(config);
+    corrections::install(state.corrections.clone());
+    ids::install(state.ids.clone());
+    let live = reload::Live::new(state.clone());
+    jobs::spawn_workers(
+        &live,
+        jobs::Handlers::default()
+            .register(warmup::JOB, warmup::run_job)
+            .register(warmup::REFRESH_JOB, warmup::run_refresh_job)
+            .register(links::JOB, links::run_job)
+            .register(notifier::JOB, notifier::run_job)
+            .register(notifier::USER_JOB, notifier::run_user_job)
+            .register(cdn::JOB, cdn::run_job)
+            .register(analytics::density::JOB, analytics::density::run_job)
+            .register(analytics::regime::JOB, analytics::regime::run_job)
+            .register(deltas::JOB, deltas::run_job)
+            .register(exports::JOB, exports::run_job)
+            .register(photos::JOB, photos::run_job)
+            .register(quality::report::JOB, quality::report::run_job)
+            .register(reports::schedule::JOB, reports::schedule::run_job)
+            .register(reports::watch::JOB, reports::watch::run_job)
+            .register(stats_sink::JOB, stats_sink::run_job),
+    );
+    if state.config.cache.warm_up {
+        if let Err(e) = state.jobs.enqueue_once(warmup::JOB, ()) {
+            error!("Could not queue cache warm-up: {}", e);
+        }
+    } else {
+        if let Err(e) = state.jobs.enqueue_once(links::JOB, ()) {
+            error!("Could not queue the short link map: {}", e);
+        }
+        // Warm-up would index the latest snapshots; without it the refresh does
+        if let Err(e) = state.jobs.enqueue_once(warmup::REFRESH_JOB, ()) {
+            error!("Could not queue the id index: {}", e);
+        }
+    }
+    if !state.config.demo.enabled {
+        notifier::start(&state);
+        analytics::density::schedule(&state);
+        analytics::regime::schedule(&state);
+        cdn::watch(&state);
+        deltas::schedule(&state);
+        warmup::schedule(&live);
+        photos::schedule(&state);
+        quality::report::schedule(&state);
+        reports::schedule::start(&state);
+        reports::watch::start(&state);
+        stats_sink::watch(&state);
+        reload::reload_on_hangup(&live);
+    }
 
     // Setup router with all our endpoints
-    let app = Router::new()
-        .route("/health", get(health_check))
-        .route("/api/rentals/search", get(search_rentals))
-        .route("/debug/paths", get(debug_paths));
+    let mut app = Router::new()
+        .route("/health", get(health::health))
+        .route("/ready", get(warmup::ready))
+        .route("/api/schema", get(schema::describe))
+        .route("/api/property-types", get(property_type::taxonomy))
+        .route("/api/presets", get(presets::list))
+        .route("/api/developments", get(developments::search))
+        .route("/api/developments/:id", get(developments::get_development))
+        .route("/api/photos/:hash", get(photos::photo))
+        .route("/api/rentals/search", get(search_rentals).post(search_rentals_post))
+        .route("/api/rentals/lookup", post(lookup_rentals))
+        .route("/api/rentals/facets", get(price_band::facets))
+        .route("/api/rentals/stats", get(analytics::stats::market_stats))
+        .route("/api/rentals/changes", get(deltas::changes))
+        .route("/api/changes", get(feed::changes))
+        .route("/api/rentals/:id", get(get_rental))
+        .route("/api/rentals/:id/photos", get(photos::listing_photos))
+        .route("/api/rentals/:id/history", get(price_history::price_history))
+        .route("/api/rentals/:id/price_history", get(price_history::price_history))
+        .route("/api/rentals/:id/notes", get(notes::get_notes).post(notes::add_notes))
+        .route("/api/rentals/:id/hide", post(hidden::hide).delete(hidden::unhide))
+        .route("/api/rentals/:id/favourite", post(recommendations::favourite).delete(recommendations::unfavourite))
+        .route("/api/rentals/:id/viewings", post(viewings::schedule))
+        .route("/api/areas/:area", get(analytics::area::area_profile))
+        .route("/api/stats/index", get(analytics::hedonic::rent_index))
+        .route("/api/stats/density", get(analytics::density::anomalies))
+        .route("/api/stats/regimes", get(analytics::regime::shifts))
+        .route("/api/stats/liquidity", get(analytics::liquidity::liquidity))
+        .route("/api/stats/deposits", get(analytics::deposits::deposits))
+        .route("/api/stats/coverage", get(quality::coverage::coverage))
+        .route("/api/tools/split", get(split::rent_split))
+        .route("/api/charts/price_trend", get(charts::price_trend))
+        .route("/api/charts/supply", get(charts::supply))
+        .route("/api/reports/market.pdf", get(reports::market_pdf))
+        .route("/api/reports/market.csv", get(reports::market_csv))
+        .route("/api/dataset.csv", get(dataset::dataset_csv))
+        .route("/api/dataset.parquet", get(dataset::dataset_parquet))
+        .route("/api/exports/panel", post(exports::create_panel))
+        .route("/api/exports/:id", get(exports::get_export))
+        .route("/api/exports/:id/download", get(exports::download))
+        .route("/api/me/history", get(history::list))
+        .route("/api/me/history/:id/replay", get(history::replay))
+        .route("/api/me/recommendations", get(recommendations::recommendations))
+        .route(
+            "/api/me/preferences/notifications",
+            get(preferences::get).put(preferences::put).delete(preferences::delete),
+        )
+        .route("/api/me/viewings", get(viewings::list))
+        .route("/api/me/viewings.ics", get(viewings::feed))
+        .route("/api/me/viewings/:id", delete(viewings::cancel))
+        .route("/api/me/watches", get(reports::watch::list).post(reports::watch::create))
+        .route("/api/me/watches/backtest", post(reports::watch::backtest_watch))
+        .route("/api/me/watches/:id", delete(reports::watch::delete))
+        .route("/api/me/watches/:id/digest", get(reports::watch::preview))
+        .route("/l/:short_id", get(links::follow))
+        .route("/sitemap.xml", get(links::sitemap))
+        .route("/metrics", get(metrics::metrics));
+    // A demo instance has no admin, mutation or debug endpoints at all
+    if !state.config.demo.enabled {
+        app = app
+            .route("/api/admin/analytics", get(search_analytics::summary))
+            .route("/api/admin/analytics/photo-conflicts", get(quality::photo_conflicts::photo_conflicts))
+            .route("/api/admin/agents/suppress", post(privacy::suppress_agent))
+            .route("/api/admin/audit", get(audit::list))
+            .route("/api/admin/quality", get(quality::report::history))
+            .route("/api/admin/corrections", get(corrections::list))
+            .route("/api/admin/rentals/:id/correction", put(corrections::put).delete(corrections::delete))
+            .route("/api/admin/config/reload", post(reload::reload_config))
+            .route("/api/admin/refresh", post(warmup::force_refresh))
+            .route("/api/admin/blocklist", get(abuse::list).post(abuse::add).delete(abuse::remove))
+            .route("/api/admin/sources", get(toggles::list))
+            .route("/api/admin/sources/:source/snapshots", post(store::ingest))
+            .route("/api/admin/sources/:source/disable", post(toggles::disable))
+            .route("/api/admin/sources/:source/enable", post(toggles::enable))
+            .route("/api/admin/jobs", get(jobs::list))
+            .route("/api/admin/jobs/:id", get(jobs::get_job))
+            .route("/api/admin/jobs/:id/retry", post(jobs::retry))
+            .route("/api/admin/reports", get(reports::schedule::list).post(reports::schedule::create))
+            .route(
+                "/api/admin/reports/:id",
+                get(reports::schedule::get_schedule).delete(reports::schedule::delete),
+            )
+            .route("/api/admin/reports/:id/run", post(reports::schedule::run_now))
+            .route("/debug/paths", get(debug_paths))
+            .route("/debug/warmup", get(warmup::warmup_status));
+    }
+    let app = app
+        .layer(middleware::from_fn_with_state(live.clone(), rate_limit::limit))
+        .layer(middleware::from_fn_with_state(live.clone(), abuse::guard))
+        .layer(middleware::map_response(schema::version_header))
+        .with_state(live);
 
     // Start the server
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
-        .await
-        .unwrap();
+    let listener = match listener::bind(&server_config).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
     println!("Server listening on {}", listener.local_addr().unwrap());
     
     // Start serving
-    axum::serve(listener, app)
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }
 #[cfg(test)]
 mod tests {
     use super::*;
+    use arrow::array::{ArrayRef, BooleanArray, Int32Array, StringArray};
+    use arrow::record_batch::RecordBatch;
+    use crate::test_utils::{listing, standardized_batch, stores_in, temp_dir, write_parquet};
     use reqwest::{Client, Url};
+    use std::sync::Arc;
 
     const BASE_URL: &str = "http://localhost:3000";
     const BASE_PATH: &str = "housing_data";
@@ -778,10 +2419,215 @@ mod tests {
             price: "€1,500 monthly".to_string(),
             id: "12345".to_string(),
         };
-        let property = StandardizedProperty::from_property_ie(listing);
+        let property = StandardizedProperty::from_property_ie(listing, Utc::now());
         assert_eq!(property.price.amount, 1500.0);
         assert_eq!(property.source, "property");
     }
+
+    #[test]
+    fn test_price_ranges() {
+        assert_eq!(parse_price_string("€1,800 - €2,200 per month"), Some((2000.0, Some((1800.0, 2200.0)))));
+        assert_eq!(parse_price_string("From €1,800 to €2,200 / month"), Some((2000.0, Some((1800.0, 2200.0)))));
+        assert_eq!(parse_price_string("€1,850 / month"), Some((1850.0, None)));
+        // Not a range: the old reading stands
+        assert_eq!(parse_price_string("€1,800 - negotiable"), Some((1800.0, None)));
+
+        let listing = PropertyIEListing {
+            address: "Test Address".to_string(),
+            price: "€1,800–€2,400 monthly".to_string(),
+            id: "12345".to_string(),
+        };
+        let price = StandardizedProperty::from_property_ie(listing, Utc::now()).price;
+        assert_eq!((price.amount, price.min, price.max), (2100.0, Some(1800.0), Some(2400.0)));
+    }
+
+    /// Writes `batch` to a fresh parquet file under the system temp dir.
+    fn write_test_parquet(name: &str, batch: &RecordBatch) -> PathBuf {
+        let path = temp_dir(name).join(format!("{}.parquet", name));
+        write_parquet(&path, batch);
+        path
+    }
+
+    #[test]
+    fn test_total_matches_estimate() {
+        let full = |rows| ScanStats { total_rows: rows, rows_scanned: rows, ..Default::default() };
+        assert_eq!(estimate_total_matches(&[(40, full(100)), (5, full(10))]), (45, true));
+
+        // Stopped after 50 of 200 rows with 10 matches; the next source was never scanned
+        let partial = ScanStats { total_rows: 200, rows_scanned: 50, ..Default::default() };
+        let skipped = ScanStats { total_rows: 100, rows_scanned: 0, ..Default::default() };
+        assert_eq!(estimate_total_matches(&[(10, partial), (0, skipped)]), (60, false));
+    }
+
+    #[test]
+    fn test_scan_counts_unreadable_file() {
+        let path = temp_dir("corrupt").join("corrupt.parquet");
+        fs::write(&path, b"PAR1 not really parquet").unwrap();
+        let source = SourceConfig { name: "corrupt".to_string(), ..Config::default().sources[0].clone() };
+        let stats = scan_properties(&source, &path, |_, _| ControlFlow::Continue(()));
+        assert_eq!((stats.rows_scanned, stats.errors), (0, 1));
+    }
+
+    #[test]
+    fn test_scan_stops_when_visitor_breaks() {
+        let batch = RecordBatch::try_from_iter(vec![
+            ("price", Arc::new(StringArray::from(vec!["€1,000", "€1,100", "€1,200", "€1,300"])) as ArrayRef),
+            ("source_id", Arc::new(StringArray::from(vec!["1", "2", "3", "4"])) as ArrayRef),
+        ])
+        .unwrap();
+        let path = write_test_parquet("early_stop", &batch);
+        let config = Config::from_toml("[[sources]]\nname = \"rent_ie\"").unwrap();
+
+        let mut seen = Vec::new();
+        let stats = scan_properties(config.resolve_source("rent_ie").unwrap(), &path, |_, property| {
+            seen.push(property.source_id);
+            if seen.len() == 2 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+        });
+        assert_eq!(seen, vec!["1", "2"]);
+        assert_eq!(stats.rows_scanned, 2);
+        assert_eq!(stats.total_rows, 4);
+        assert_eq!(parquet_row_count(&path), 4);
+    }
+
+    #[test]
+    fn test_standardized_source_parsing() {
+        let batch = RecordBatch::try_from_iter(vec![
+            ("price", Arc::new(StringArray::from(vec!["€2,100 per month", "€1,800"])) as ArrayRef),
+            ("source_id", Arc::new(StringArray::from(vec![Some("981"), None])) as ArrayRef),
+            ("display_address", Arc::new(StringArray::from(vec!["1 Canal Road, Dublin 6", "2 Main Street, Cork"])) as ArrayRef),
+            ("bedrooms", Arc::new(Int32Array::from(vec![2, 3])) as ArrayRef),
+            ("has_video", Arc::new(BooleanArray::from(vec![true, false])) as ArrayRef),
+        ])
+        .unwrap();
+        let path = write_test_parquet("standardized", &batch);
+
+        let source = Config::from_toml("[[sources]]\nname = \"rent_ie\"").unwrap();
+        let properties = load_properties(source.resolve_source("rent_ie").unwrap(), &path);
+
+        // The second row has no source_id and is skipped
+        assert_eq!(properties.len(), 1);
+        let property = &properties[0];
+        assert_eq!(property.property_id, "rent_ie_981");
+        assert_eq!(property.source, "rent_ie");
+        assert_eq!(property.price.amount, 2100.0);
+        assert_eq!(property.bedrooms, Some(2));
+        assert!(property.has_video);
+    }
+
+    #[test]
+    fn test_zero_result_diagnostics() {
+        let params: SearchParams = serde_json::from_str(r#"{"max_price": 1800, "ber_rating": "A"}"#).unwrap();
+        let mut diagnostics = SearchDiagnostics::new(&params);
+        for (rent, ber) in [(1500.0, Some("B2")), (1700.0, None), (2400.0, Some("A3"))] {
+            let mut property = listing("daft", "1");
+            property.price.amount = rent;
+            property.ber_rating = ber.map(str::to_string);
+            diagnostics.record(&property, &params);
+        }
+
+        assert_eq!(diagnostics.listings_scanned, 3);
+        let counts: Vec<_> = diagnostics.filters.iter().map(|f| (f.filter.as_str(), f.matched, f.eliminated)).collect();
+        assert_eq!(counts, vec![("price <= 1800", 2, 1), ("ber_rating matches \"A\"", 1, 2)]);
+    }
+
+    #[test]
+    fn test_amenity_filters() {
+        let mut property = listing("daft", "1");
+        property.amenities = amenities::from_text("2 parking spaces, balcony");
+        let passes = |query: &str| {
+            let params: SearchParams = serde_json::from_str(query).unwrap();
+            should_include_property(&property, &params)
+        };
+        assert!(passes(r#"{"min_parking": 2, "balcony": true}"#));
+        assert!(!passes(r#"{"min_parking": 3}"#));
+        assert!(!passes(r#"{"balcony": false}"#));
+        // Nothing says whether it has a garden
+        assert!(!passes(r#"{"garden": true}"#));
+        assert!(passes(r#"{"garden": false}"#));
+        assert!(!passes(r#"{"min_photos": 1}"#));
+        assert!(passes(r#"{"floorplan": false}"#));
+    }
+
+    #[test]
+    fn test_tenancy_filters() {
+        let mut property = listing("daft", "1");
+        property.tenancy.available_from = "2025-02-01".parse().ok();
+        let passes = |property: &StandardizedProperty, query: &str| {
+            let params: SearchParams = serde_json::from_str(query).unwrap();
+            should_include_property(property, &params)
+        };
+        assert!(passes(&property, r#"{"available_from_before": "2025-02-01"}"#));
+        assert!(!passes(&property, r#"{"available_from_before": "2025-01-31"}"#));
+        // No stated lease passes; a year's lease is too long for six months
+        assert!(passes(&property, r#"{"min_lease_months": 6}"#));
+        property.tenancy.min_lease_months = Some(12);
+        assert!(!passes(&property, r#"{"min_lease_months": 6}"#));
+        assert!(!passes(&listing("daft", "2"), r#"{"available_from_before": "2025-02-01"}"#));
+    }
+
+    #[test]
+    fn test_sort_keys_order_missing_values_last() {
+        let properties: Vec<StandardizedProperty> = [(1800.0, Some(2)), (0.0, Some(1)), (1500.0, None)]
+            .into_iter()
+            .enumerate()
+            .map(|(i, (rent, bedrooms))| {
+                let mut property = listing("daft", &i.to_string());
+                property.price.amount = rent;
+                property.bedrooms = bedrooms;
+                property
+            })
+            .collect();
+        let rent = |p: &StandardizedProperty| Some(p.price.amount).filter(|rent| *rent > 0.0);
+        assert_eq!(sorted_by(&properties, SortOrder::Asc, rent), [2, 0, 1]);
+        assert_eq!(sorted_by(&properties, SortOrder::Desc, rent), [0, 2, 1]);
+        assert_eq!(sorted_by(&properties, SortOrder::Desc, |p| p.bedrooms), [0, 1, 2]);
+
+        let params: SearchParams = serde_json::from_str(r#"{"sort_by": "created_date"}"#).unwrap();
+        assert_eq!(params.sort.map(SortKey::default_order), Some(SortOrder::Desc));
+    }
+
+    #[test]
+    fn test_read_rows_selects_offsets() {
+        let path = write_test_parquet("read_rows", &standardized_batch(&["1", "2", "3", "4", "5", "6"]));
+        let config = Config::from_toml("[[sources]]\nname = \"rent_ie\"").unwrap();
+
+        let rows = read_rows(config.resolve_source("rent_ie").unwrap(), &path, &[4, 1, 4, 9]);
+        let ids: Vec<_> = rows.iter().map(|(offset, p)| (*offset, p.source_id.as_str())).collect();
+        assert_eq!(ids, vec![(1, "2"), (4, "5")]);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_concurrent_searches_overlap() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let data = temp_dir("concurrent-searches");
+        let ids: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+        let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+        write_parquet(&data.join("processed/rent_ie/2024/11/05/rent_ie_120000.parquet"), &standardized_batch(&ids));
+        let config = Config::from_toml(&format!("data_path = {:?}\n[[sources]]\nname = \"rent_ie\"", data)).unwrap();
+        let state = AppState::new(stores_in(config, &data));
+
+        // With a single runtime thread, searches only overlap if none of them
+        // holds it while reading the snapshot
+        let (in_flight, most) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let searches: Vec<_> = (0..4)
+            .map(|_| {
+                let (state, in_flight, most) = (state.clone(), in_flight.clone(), most.clone());
+                tokio::spawn(async move {
+                    most.fetch_max(in_flight.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    // Nothing matches, so every search reads the whole file
+                    let params: SearchParams = serde_json::from_str(r#"{"min_price": 100000}"#).unwrap();
+                    let response = search(state, Caller::with_scopes(None, &[]), Lang::En, params).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    response.map(|response| response.status())
+                })
+            })
+            .collect();
+        for search in searches {
+            assert_eq!(search.await.unwrap().unwrap(), StatusCode::OK);
+        }
+        assert_eq!(most.load(Ordering::SeqCst), 4);
+    }
 }
 
 
@@ -0,0 +1,627 @@
+//! Per-source column mappings for the raw-schema parsers.
+//!
+//! The MyHome, Daft and property.ie snapshots each have their own raw schema.
+//! Rather than hard-coding column positions in the parsers, each source has a
+//! versioned TOML mapping (see `mappings/`) from logical field names to
+//! columns. A column can be referenced by name or by index, and either form is
+//! resolved against the file's Arrow schema once per file; the built-in
+//! mappings use names, so a collector release that reorders columns doesn't
+//! shift every field. Mapped columns a file lacks are reported together, by
+//! field and column. Parsers then read values straight out of the typed column
+//! arrays of each record batch through `BatchRow`.
+
+use arrow::array::{Array, AsArray, RecordBatch};
+use arrow::compute::cast;
+use arrow::datatypes::{
+    DataType, Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, Schema, UInt16Type,
+    UInt32Type, UInt64Type, UInt8Type,
+};
+use arrow::util::display::array_value_to_string;
+use chrono::NaiveDate;
+use log::{debug, warn};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::config::ParserKind;
+
+const DAFT_MAPPING: &str = include_str!("../mappings/daft.toml");
+const MYHOME_MAPPING: &str = include_str!("../mappings/myhome.toml");
+const PROPERTY_MAPPING: &str = include_str!("../mappings/property.toml");
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Segment {
+    Index(usize),
+    Name(String),
+}
+
+/// A reference to a (possibly nested) column: `37`, `"PriceAsString"`,
+/// `"listing.price"` or `[0, "price"]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ColumnRef {
+    Index(usize),
+    Path(String),
+    Segments(Vec<Segment>),
+}
+
+impl ColumnRef {
+    fn segments(&self) -> Vec<Segment> {
+        match self {
+            ColumnRef::Index(i) => vec![Segment::Index(*i)],
+            ColumnRef::Path(path) => path
+                .split('.')
+                .map(|part| match part.parse::<usize>() {
+                    Ok(i) => Segment::Index(i),
+                    Err(_) => Segment::Name(part.to_string()),
+                })
+                .collect(),
+            ColumnRef::Segments(segments) => segments.clone(),
+        }
+    }
+}
+
+impl fmt::Display for ColumnRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self
+            .segments()
+            .into_iter()
+            .map(|segment| match segment {
+                Segment::Index(i) => i.to_string(),
+                Segment::Name(name) => name,
+            })
+            .collect();
+        f.write_str(&parts.join("."))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MappingVersion {
+    pub version: u32,
+    /// First snapshot date this layout applies to. Versions without a date apply
+    /// to every snapshot not covered by a dated version.
+    pub valid_from: Option<NaiveDate>,
+    pub columns: BTreeMap<String, ColumnRef>,
+    /// Columns only some snapshots have, such as the unit types of new
+    /// developments. They are read when the file has them and are not
+    /// reported as missing otherwise.
+    #[serde(default)]
+    pub optional: BTreeMap<String, ColumnRef>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SourceMapping {
+    pub source: String,
+    pub versions: Vec<MappingVersion>,
+}
+
+impl SourceMapping {
+    pub fn from_toml(contents: &str) -> Result<Self, String> {
+        let mapping: SourceMapping = toml::from_str(contents).map_err(|e| e.to_string())?;
+        if mapping.versions.is_empty() {
+            return Err(format!("mapping for {} declares no versions", mapping.source));
+        }
+        Ok(mapping)
+    }
+
+    /// The built-in mapping for a raw-schema parser.
+    pub fn builtin(parser: ParserKind) -> Option<Self> {
+        let contents = match parser {
+            ParserKind::Daft => DAFT_MAPPING,
+            ParserKind::MyHome => MYHOME_MAPPING,
+            ParserKind::Property => PROPERTY_MAPPING,
+            ParserKind::Standardized => return None,
+        };
+        Some(Self::from_toml(contents).expect("built-in column mapping is valid"))
+    }
+
+    /// The newest version in effect on `date`; the newest version overall when the
+    /// snapshot date is unknown.
+    pub fn for_date(&self, date: Option<NaiveDate>) -> &MappingVersion {
+        let applicable = self.versions.iter().filter(|v| match (v.valid_from, date) {
+            (Some(from), Some(date)) => from <= date,
+            _ => true,
+        });
+        applicable
+            .max_by_key(|v| (v.valid_from, v.version))
+            .or_else(|| self.versions.iter().min_by_key(|v| (v.valid_from, v.version)))
+            .expect("mapping has at least one version")
+    }
+}
+
+/// One step from a column into its nested values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    /// Child field of a struct.
+    Field(usize),
+    /// Element of a list, counted from the start of each row's list.
+    Element(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ColumnPath {
+    root: usize,
+    steps: Vec<Step>,
+}
+
+/// Column paths of one mapping version resolved against a file schema.
+#[derive(Debug, Default)]
+pub struct ResolvedColumns {
+    pub version: u32,
+    paths: HashMap<String, ColumnPath>,
+    /// Mapped fields whose column isn't in the file schema.
+    pub missing: Vec<String>,
+}
+
+impl MappingVersion {
+    pub fn resolve(&self, schema: &Schema) -> ResolvedColumns {
+        let mut paths = HashMap::new();
+        let mut missing = Vec::new();
+        for (field, column) in &self.columns {
+            match resolve_path(schema, &column.segments()) {
+                Some(path) => {
+                    paths.insert(field.clone(), path);
+                }
+                None => missing.push(field.clone()),
+            }
+        }
+        for (field, column) in &self.optional {
+            match resolve_path(schema, &column.segments()) {
+                Some(path) => {
+                    paths.insert(field.clone(), path);
+                }
+                None => debug!("Optional column {} for field {} not in file schema", column, field),
+            }
+        }
+        ResolvedColumns {
+            version: self.version,
+            paths,
+            missing,
+        }
+    }
+}
+
+impl MappingVersion {
+    /// `fields` with the columns they map to: "price (PriceAsString), ...".
+    pub fn describe(&self, fields: &[String]) -> String {
+        fields
+            .iter()
+            .map(|field| match self.columns.get(field).or_else(|| self.optional.get(field)) {
+                Some(column) => format!("{} ({})", field, column),
+                None => field.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+fn resolve_path(schema: &Schema, segments: &[Segment]) -> Option<ColumnPath> {
+    let (first, rest) = segments.split_first()?;
+    let root = match first {
+        Segment::Index(i) => Some(*i).filter(|i| *i < schema.fields().len())?,
+        Segment::Name(name) => schema.index_of(name).ok()?,
+    };
+
+    let mut data_type = schema.field(root).data_type();
+    let mut steps = Vec::with_capacity(rest.len());
+    for segment in rest {
+        match (data_type, segment) {
+            (DataType::Struct(fields), Segment::Index(i)) => {
+                data_type = fields.get(*i)?.data_type();
+                steps.push(Step::Field(*i));
+            }
+            (DataType::Struct(fields), Segment::Name(name)) => {
+                let (i, field) = fields.find(name)?;
+                data_type = field.data_type();
+                steps.push(Step::Field(i));
+            }
+            (DataType::List(item) | DataType::LargeList(item), Segment::Index(i)) => {
+                data_type = item.data_type();
+                steps.push(Step::Element(*i));
+            }
+            _ => return None,
+        }
+    }
+    Some(ColumnPath { root, steps })
+}
+
+impl ResolvedColumns {
+    /// Maps every top-level column to its own name, for sources whose columns
+    /// already use the standardized field names.
+    pub fn by_name(schema: &Schema) -> Self {
+        let paths = schema
+            .fields()
+            .iter()
+            .enumerate()
+            .map(|(root, field)| (field.name().clone(), ColumnPath { root, steps: vec![] }))
+            .collect();
+        ResolvedColumns { version: 0, paths, missing: vec![] }
+    }
+
+    pub fn row<'a>(&'a self, batch: &'a RecordBatch, index: usize) -> BatchRow<'a> {
+        BatchRow {
+            columns: self,
+            batch,
+            index,
+        }
+    }
+}
+
+/// Replaces dictionary-encoded top-level columns (pandas categoricals) with their
+/// plain value type so the accessors only have to deal with primitive arrays.
+pub fn decode_dictionaries(batch: RecordBatch) -> RecordBatch {
+    if !batch.schema().fields().iter().any(|f| matches!(f.data_type(), DataType::Dictionary(_, _))) {
+        return batch;
+    }
+
+    let mut fields = Vec::with_capacity(batch.num_columns());
+    let mut arrays = Vec::with_capacity(batch.num_columns());
+    for (field, array) in batch.schema().fields().iter().zip(batch.columns()) {
+        match field.data_type() {
+            DataType::Dictionary(_, value_type) => match cast(array, value_type) {
+                Ok(decoded) => {
+                    fields.push(field.as_ref().clone().with_data_type(value_type.as_ref().clone()));
+                    arrays.push(decoded);
+                }
+                Err(e) => {
+                    warn!("Failed to decode dictionary column {}: {}", field.name(), e);
+                    fields.push(field.as_ref().clone());
+                    arrays.push(array.clone());
+                }
+            },
+            _ => {
+                fields.push(field.as_ref().clone());
+                arrays.push(array.clone());
+            }
+        }
+    }
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays).unwrap_or(batch)
+}
+
+/// One row of a record batch, read through a resolved column mapping.
+pub struct BatchRow<'a> {
+    columns: &'a ResolvedColumns,
+    batch: &'a RecordBatch,
+    index: usize,
+}
+
+impl<'a> BatchRow<'a> {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The array holding `field` for this row and the value's position in it, or
+    /// `None` when the field is unmapped or null anywhere along its path.
+    fn value(&self, field: &str) -> Option<(&'a dyn Array, usize)> {
+        let path = self.columns.paths.get(field)?;
+        let mut array: &'a dyn Array = self.batch.column(path.root).as_ref();
+        let mut index = self.index;
+
+        for step in &path.steps {
+            if array.is_null(index) {
+                return None;
+            }
+            match *step {
+                Step::Field(i) => array = array.as_struct_opt()?.column(i).as_ref(),
+                Step::Element(i) => {
+                    let (values, start, end) = match array.data_type() {
+                        DataType::List(_) => {
+                            let list = array.as_list::<i32>();
+                            let offsets = list.value_offsets();
+                            (list.values(), offsets[index] as usize, offsets[index + 1] as usize)
+                        }
+                        _ => {
+                            let list = array.as_list_opt::<i64>()?;
+                            let offsets = list.value_offsets();
+                            (list.values(), offsets[index] as usize, offsets[index + 1] as usize)
+                        }
+                    };
+                    if start + i >= end {
+                        return None;
+                    }
+                    array = values.as_ref();
+                    index = start + i;
+                }
+            }
+        }
+
+        if array.is_null(index) {
+            None
+        } else {
+            Some((array, index))
+        }
+    }
+
+    pub fn string(&self, field: &str) -> Option<String> {
+        let (array, index) = self.value(field)?;
+        string_at(array, index)
+    }
+
+    pub fn long(&self, field: &str) -> Option<i64> {
+        let (array, index) = self.value(field)?;
+        match array.data_type() {
+            DataType::Int64 => Some(array.as_primitive::<Int64Type>().value(index)),
+            // pandas stores integer columns with missing values as floats
+            DataType::Float32 | DataType::Float64 => {
+                number_at(array, index).filter(|v| v.is_finite()).map(|v| v as i64)
+            }
+            _ => number_at(array, index).map(|v| v as i64),
+        }
+    }
+
+    pub fn double(&self, field: &str) -> Option<f64> {
+        let (array, index) = self.value(field)?;
+        number_at(array, index)
+    }
+
+    pub fn bool(&self, field: &str) -> Option<bool> {
+        let (array, index) = self.value(field)?;
+        array.as_boolean_opt().map(|values| values.value(index))
+    }
+
+    /// Every mapped field of the row as it is in the file, for `include_raw`.
+    /// Fields that are null, or not in this file's schema, are `null`.
+    pub fn raw_fields(&self) -> BTreeMap<String, serde_json::Value> {
+        let mut fields: BTreeMap<String, serde_json::Value> = self
+            .columns
+            .paths
+            .keys()
+            .map(|field| {
+                let value = self.value(field).map_or(serde_json::Value::Null, |(array, index)| json_at(array, index));
+                (field.clone(), value)
+            })
+            .collect();
+        for field in &self.columns.missing {
+            fields.insert(field.clone(), serde_json::Value::Null);
+        }
+        fields
+    }
+
+    /// The value of `field` as JSON, for nested values such as lists of structs.
+    pub fn json(&self, field: &str) -> Option<serde_json::Value> {
+        let (array, index) = self.value(field)?;
+        Some(json_at(array, index))
+    }
+
+    /// Non-null string elements of a list column, in order.
+    pub fn strings(&self, field: &str) -> Vec<String> {
+        let Some((array, index)) = self.value(field) else {
+            return vec![];
+        };
+        let values = match array.data_type() {
+            DataType::List(_) => array.as_list::<i32>().value(index),
+            DataType::LargeList(_) => array.as_list::<i64>().value(index),
+            _ => return vec![],
+        };
+        (0..values.len())
+            .filter(|&i| !values.is_null(i))
+            .filter_map(|i| string_at(values.as_ref(), i))
+            .collect()
+    }
+}
+
+fn number_at(array: &dyn Array, index: usize) -> Option<f64> {
+    let value = match array.data_type() {
+        DataType::Int8 => array.as_primitive::<Int8Type>().value(index) as f64,
+        DataType::Int16 => array.as_primitive::<Int16Type>().value(index) as f64,
+        DataType::Int32 => array.as_primitive::<Int32Type>().value(index) as f64,
+        DataType::Int64 => array.as_primitive::<Int64Type>().value(index) as f64,
+        DataType::UInt8 => array.as_primitive::<UInt8Type>().value(index) as f64,
+        DataType::UInt16 => array.as_primitive::<UInt16Type>().value(index) as f64,
+        DataType::UInt32 => array.as_primitive::<UInt32Type>().value(index) as f64,
+        DataType::UInt64 => array.as_primitive::<UInt64Type>().value(index) as f64,
+        DataType::Float32 => array.as_primitive::<Float32Type>().value(index) as f64,
+        DataType::Float64 => array.as_primitive::<Float64Type>().value(index),
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn json_at(array: &dyn Array, index: usize) -> serde_json::Value {
+    use serde_json::Value;
+    if array.is_null(index) {
+        return Value::Null;
+    }
+    match array.data_type() {
+        DataType::Boolean => Value::Bool(array.as_boolean().value(index)),
+        DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64 => {
+            number_at(array, index).map_or(Value::Null, |v| Value::from(v as i64))
+        }
+        DataType::UInt8 | DataType::UInt16 | DataType::UInt32 | DataType::UInt64 => {
+            number_at(array, index).map_or(Value::Null, |v| Value::from(v as u64))
+        }
+        DataType::Float32 | DataType::Float64 => number_at(array, index).map_or(Value::Null, Value::from),
+        DataType::List(_) | DataType::LargeList(_) => {
+            let values = match array.data_type() {
+                DataType::List(_) => array.as_list::<i32>().value(index),
+                _ => array.as_list::<i64>().value(index),
+            };
+            Value::Array((0..values.len()).map(|i| json_at(values.as_ref(), i)).collect())
+        }
+        DataType::Struct(fields) => {
+            let array = array.as_struct();
+            Value::Object(
+                fields
+                    .iter()
+                    .zip(array.columns())
+                    .map(|(field, column)| (field.name().clone(), json_at(column.as_ref(), index)))
+                    .collect(),
+            )
+        }
+        _ => string_at(array, index).map_or(Value::Null, Value::String),
+    }
+}
+
+fn string_at(array: &dyn Array, index: usize) -> Option<String> {
+    match array.data_type() {
+        DataType::Utf8 => Some(array.as_string::<i32>().value(index).to_string()),
+        DataType::LargeUtf8 => Some(array.as_string::<i64>().value(index).to_string()),
+        DataType::Utf8View => Some(array.as_string_view().value(index).to_string()),
+        DataType::Struct(_) | DataType::List(_) | DataType::LargeList(_) => None,
+        _ => array_value_to_string(array, index).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{ArrayRef, Float64Array, Int64Array, ListBuilder, StringArray, StringBuilder, StructArray};
+    use arrow::datatypes::{Field, Fields};
+
+    fn test_batch() -> RecordBatch {
+        let ber = StructArray::from(vec![(
+            Arc::new(Field::new("rating", DataType::Utf8, true)),
+            Arc::new(StringArray::from(vec![Some("B2"), None])) as ArrayRef,
+        )]);
+        let listing = StructArray::from(vec![
+            (
+                Arc::new(Field::new("title", DataType::Utf8, true)),
+                Arc::new(StringArray::from(vec!["1 Canal Road", "2 Main Street"])) as ArrayRef,
+            ),
+            (
+                Arc::new(Field::new("ber", DataType::Struct(Fields::from(vec![Field::new("rating", DataType::Utf8, true)])), true)),
+                Arc::new(ber) as ArrayRef,
+            ),
+        ]);
+        let mut photos = ListBuilder::new(StringBuilder::new());
+        photos.append_value([Some("a.jpg"), Some("b.jpg")]);
+        photos.append_value([None::<&str>]);
+
+        RecordBatch::try_from_iter(vec![
+            ("PropertyId", Arc::new(Int64Array::from(vec![10, 11])) as ArrayRef),
+            ("listing", Arc::new(listing) as ArrayRef),
+            ("PriceAsString", Arc::new(StringArray::from(vec!["€1,500", "€2,000"])) as ArrayRef),
+            ("NumberOfBeds", Arc::new(Float64Array::from(vec![Some(2.0), None])) as ArrayRef),
+            ("Photos", Arc::new(photos.finish()) as ArrayRef),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_builtin_mappings_parse() {
+        for parser in [ParserKind::Daft, ParserKind::MyHome, ParserKind::Property] {
+            let mapping = SourceMapping::builtin(parser).unwrap();
+            assert!(mapping.for_date(None).columns.contains_key("price"));
+        }
+        assert!(SourceMapping::builtin(ParserKind::Standardized).is_none());
+    }
+
+    #[test]
+    fn test_builtin_mapping_resolves_reordered_columns() {
+        let batch = RecordBatch::try_from_iter(vec![
+            ("Added", Arc::new(StringArray::from(vec!["x"])) as ArrayRef),
+            ("PriceAsString", Arc::new(StringArray::from(vec!["€1,500"])) as ArrayRef),
+            ("PropertyId", Arc::new(Int64Array::from(vec![10])) as ArrayRef),
+        ])
+        .unwrap();
+        let mapping = SourceMapping::builtin(ParserKind::MyHome).unwrap();
+        let version = mapping.for_date(None);
+        let resolved = version.resolve(&batch.schema());
+
+        let row = resolved.row(&batch, 0);
+        assert_eq!(row.long("property_id"), Some(10));
+        assert_eq!(row.string("price").as_deref(), Some("€1,500"));
+        assert!(!resolved.missing.contains(&"price".to_string()));
+        assert!(version.describe(&resolved.missing).contains("bedrooms (NumberOfBeds)"));
+    }
+
+    #[test]
+    fn test_resolves_names_indices_and_nested_paths() {
+        let mapping = SourceMapping::from_toml(
+            r#"
+            source = "test"
+            [[versions]]
+            version = 1
+            [versions.columns]
+            id = 0
+            price = "PriceAsString"
+            rating = "listing.ber.rating"
+            title = [1, "title"]
+            second_photo = ["Photos", 1]
+            missing = "NoSuchColumn"
+            [versions.optional]
+            beds = "NumberOfBeds"
+            units = "NoSuchUnits"
+            "#,
+        )
+        .unwrap();
+        let batch = test_batch();
+
+        let resolved = mapping.for_date(None).resolve(&batch.schema());
+        assert_eq!(resolved.paths["id"], ColumnPath { root: 0, steps: vec![] });
+        assert_eq!(resolved.paths["price"], ColumnPath { root: 2, steps: vec![] });
+        assert_eq!(resolved.paths["rating"], ColumnPath { root: 1, steps: vec![Step::Field(1), Step::Field(0)] });
+        assert_eq!(resolved.paths["title"], ColumnPath { root: 1, steps: vec![Step::Field(0)] });
+        assert!(!resolved.paths.contains_key("missing"));
+        assert_eq!(resolved.paths["beds"], ColumnPath { root: 3, steps: vec![] });
+        assert_eq!(resolved.missing, ["missing"]);
+
+        let first = resolved.row(&batch, 0);
+        assert_eq!(first.long("id"), Some(10));
+        assert_eq!(first.string("price").as_deref(), Some("€1,500"));
+        assert_eq!(first.string("rating").as_deref(), Some("B2"));
+        assert_eq!(first.string("second_photo").as_deref(), Some("b.jpg"));
+
+        let second = resolved.row(&batch, 1);
+        assert_eq!(second.string("title").as_deref(), Some("2 Main Street"));
+        assert_eq!(second.string("rating"), None);
+        assert_eq!(second.string("second_photo"), None);
+    }
+
+    #[test]
+    fn test_typed_accessors_coerce_numbers() {
+        let batch = test_batch();
+        let resolved = ResolvedColumns::by_name(&batch.schema());
+
+        let first = resolved.row(&batch, 0);
+        assert_eq!(first.long("NumberOfBeds"), Some(2));
+        assert_eq!(first.double("PropertyId"), Some(10.0));
+        assert_eq!(first.string("PropertyId").as_deref(), Some("10"));
+        assert_eq!(first.strings("Photos"), vec!["a.jpg", "b.jpg"]);
+        assert_eq!(first.long("PriceAsString"), None);
+
+        let second = resolved.row(&batch, 1);
+        assert_eq!(second.long("NumberOfBeds"), None);
+        assert!(second.strings("Photos").is_empty());
+    }
+
+    #[test]
+    fn test_raw_fields_keep_file_values() {
+        let batch = test_batch();
+        let resolved = ResolvedColumns::by_name(&batch.schema());
+
+        let raw = resolved.row(&batch, 1).raw_fields();
+        assert_eq!(raw["PropertyId"], serde_json::json!(11));
+        assert_eq!(raw["PriceAsString"], serde_json::json!("€2,000"));
+        assert_eq!(raw["NumberOfBeds"], serde_json::Value::Null);
+        assert_eq!(raw["Photos"], serde_json::json!([null]));
+        assert_eq!(raw["listing"], serde_json::json!({"title": "2 Main Street", "ber": {"rating": null}}));
+    }
+
+    #[test]
+    fn test_version_selection_by_snapshot_date() {
+        let mapping = SourceMapping::from_toml(
+            r#"
+            source = "test"
+            [[versions]]
+            version = 1
+            [versions.columns]
+            price = 37
+
+            [[versions]]
+            version = 2
+            valid_from = "2024-11-01"
+            [versions.columns]
+            price = 38
+            "#,
+        )
+        .unwrap();
+
+        let date = |d: &str| Some(d.parse::<NaiveDate>().unwrap());
+        assert_eq!(mapping.for_date(date("2024-10-31")).version, 1);
+        assert_eq!(mapping.for_date(date("2024-11-01")).version, 2);
+        assert_eq!(mapping.for_date(None).version, 2);
+    }
+}
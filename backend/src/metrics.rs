@@ -0,0 +1,38 @@
+//! Prometheus-style text metrics at `/metrics`.
+
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use std::fmt::Write;
+
+use crate::state::AppState;
+
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let usage = state.cache.usage();
+    let mut body = String::new();
+
+    let mut gauge = |name: &str, help: &str, kind: &str, value: String| {
+        let _ = writeln!(body, "# HELP {} {}", name, help);
+        let _ = writeln!(body, "# TYPE {} {}", name, kind);
+        let _ = writeln!(body, "{} {}", name, value);
+    };
+    gauge("market_analysis_cache_budget_bytes", "Configured snapshot cache budget.", "gauge", usage.budget_bytes.to_string());
+    gauge("market_analysis_cache_used_bytes", "Estimated memory held by cached snapshots.", "gauge", usage.used_bytes.to_string());
+    gauge("market_analysis_cache_hits_total", "Queries served from the snapshot cache.", "counter", usage.hits.to_string());
+    gauge("market_analysis_cache_misses_total", "Queries that had to read a snapshot from disk.", "counter", usage.misses.to_string());
+    gauge("market_analysis_cache_evictions_total", "Sources evicted to stay within the budget.", "counter", usage.evictions.to_string());
+    gauge("market_analysis_short_link_clicks_total", "Redirects served for share links.", "counter", state.links.total_clicks().to_string());
+
+    let _ = writeln!(body, "# HELP market_analysis_cache_source_bytes Estimated memory held per cached source.");
+    let _ = writeln!(body, "# TYPE market_analysis_cache_source_bytes gauge");
+    for source in &usage.sources {
+        let _ = writeln!(body, "market_analysis_cache_source_bytes{{source=\"{}\"}} {}", source.source, source.bytes);
+    }
+    let _ = writeln!(body, "# HELP market_analysis_cache_source_listings Listings held per cached source.");
+    let _ = writeln!(body, "# TYPE market_analysis_cache_source_listings gauge");
+    for source in &usage.sources {
+        let _ = writeln!(body, "market_analysis_cache_source_listings{{source=\"{}\"}} {}", source.source, source.listings);
+    }
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
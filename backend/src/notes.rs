@@ -0,0 +1,240 @@
+//! Per-user notes and tags on listings.
+//!
+//! Callers with an API key can keep free-text notes ("viewed 12/5, damp in
+//! bedroom") and tags against any listing with `POST /api/rentals/{id}/notes`.
+//! Annotations belong to the key that wrote them: search, lookup and detail
+//! responses carry the caller's own under `annotations`, and nobody else's.
+//! They are kept against the listing's canonical id (see `ids`), so a legacy
+//! id in the path is resolved to it first.
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use crate::auth::Caller;
+use crate::state::AppState;
+use crate::StandardizedProperty;
+
+const MAX_NOTE_CHARS: usize = 2000;
+const MAX_TAG_CHARS: usize = 50;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Note {
+    pub text: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// One user's notes and tags on one listing.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Annotations {
+    pub notes: Vec<Note>,
+    pub tags: BTreeSet<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NotesRequest {
+    /// Appended to the listing's notes.
+    pub note: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub remove_tags: Vec<String>,
+}
+
+fn tag(raw: &str) -> Result<String, String> {
+    let tag = raw.trim().to_lowercase();
+    if tag.is_empty() || tag.chars().count() > MAX_TAG_CHARS {
+        return Err(format!("Tags must be 1 to {} characters, got {:?}", MAX_TAG_CHARS, raw));
+    }
+    Ok(tag)
+}
+
+impl NotesRequest {
+    fn validate(&self) -> Result<(), String> {
+        if self.note.is_none() && self.tags.is_empty() && self.remove_tags.is_empty() {
+            return Err("Give a note, tags or remove_tags".to_string());
+        }
+        if let Some(note) = &self.note {
+            if note.trim().is_empty() || note.chars().count() > MAX_NOTE_CHARS {
+                return Err(format!("Notes must be 1 to {} characters", MAX_NOTE_CHARS));
+            }
+        }
+        self.tags.iter().chain(&self.remove_tags).try_for_each(|raw| tag(raw).map(drop))
+    }
+}
+
+/// Annotations by user name, then property id.
+type ByUser = BTreeMap<String, BTreeMap<String, Annotations>>;
+
+pub struct ListingNotes {
+    path: Option<PathBuf>,
+    by_user: RwLock<ByUser>,
+}
+
+impl ListingNotes {
+    /// Loads saved annotations from `path`. `None` keeps them in memory only.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let by_user = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        ListingNotes { path, by_user: RwLock::new(by_user) }
+    }
+
+    pub fn get(&self, user: &str, property_id: &str) -> Option<Annotations> {
+        self.by_user.read().unwrap().get(user)?.get(property_id).cloned()
+    }
+
+    /// Applies `request`, made `now`, to `user`'s annotations on `property_id`
+    /// and returns them. Listings left without notes or tags are dropped.
+    pub fn update(
+        &self,
+        user: &str,
+        property_id: &str,
+        request: &NotesRequest,
+        now: DateTime<Utc>,
+    ) -> Result<Annotations, String> {
+        request.validate()?;
+        let mut by_user = self.by_user.write().unwrap();
+        let listings = by_user.entry(user.to_string()).or_default();
+        let annotations = listings.entry(property_id.to_string()).or_default();
+        if let Some(note) = &request.note {
+            annotations.notes.push(Note { text: note.trim().to_string(), created_at: now });
+        }
+        for raw in &request.tags {
+            annotations.tags.insert(tag(raw)?);
+        }
+        for raw in &request.remove_tags {
+            annotations.tags.remove(&tag(raw)?);
+        }
+        let updated = annotations.clone();
+        if updated == Annotations::default() {
+            listings.remove(property_id);
+        }
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+            let contents = serde_json::to_string_pretty(&*by_user).map_err(|e| e.to_string())?;
+            fs::write(path, contents).map_err(|e| e.to_string())?;
+        }
+        Ok(updated)
+    }
+
+    /// Fills in the caller's own annotations, if any.
+    pub fn attach_all(&self, properties: &mut [StandardizedProperty], caller: &Caller) {
+        let Some(user) = &caller.name else {
+            return;
+        };
+        let by_user = self.by_user.read().unwrap();
+        let Some(listings) = by_user.get(user) else {
+            return;
+        };
+        for property in properties {
+            let annotations = listings.get(&property.property_id).cloned();
+            property.annotations = annotations;
+        }
+    }
+}
+
+fn user(caller: &Caller) -> Result<&str, (StatusCode, String)> {
+    caller
+        .name
+        .as_deref()
+        .ok_or((StatusCode::UNAUTHORIZED, "Notes need an API key".to_string()))
+}
+
+/// The canonical id of the listed listing `property_id` names, or `None`
+/// when nothing listed goes by it.
+async fn canonical_id(state: &AppState, property_id: &str) -> Result<Option<String>, (StatusCode, String)> {
+    let (reading, id) = (state.clone(), property_id.to_string());
+    tokio::task::spawn_blocking(move || reading.store.get(std::slice::from_ref(&id)).pop().map(|p| p.property_id))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+pub async fn get_notes(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(property_id): Path<String>,
+) -> Result<Json<Annotations>, (StatusCode, String)> {
+    let user = user(&caller)?;
+    // Notes on a listing that has since gone are still found by its canonical id
+    let property_id = canonical_id(&state, &property_id).await?.unwrap_or(property_id);
+    Ok(Json(state.notes.get(user, &property_id).unwrap_or_default()))
+}
+
+pub async fn add_notes(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(property_id): Path<String>,
+    Json(request): Json<NotesRequest>,
+) -> Result<Json<Annotations>, (StatusCode, String)> {
+    let user = user(&caller)?;
+    request.validate().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let property_id = canonical_id(&state, &property_id)
+        .await?
+        .ok_or((StatusCode::NOT_FOUND, format!("No listing {}", property_id)))?;
+    state.notes.update(user, &property_id, &request, state.clock.now()).map(Json).map_err(|e| {
+        warn!("Could not save notes: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Could not save notes".to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{listing, temp_dir};
+
+    #[test]
+    fn test_notes_are_per_user_and_persist() {
+        let path = temp_dir("notes").join("notes.json");
+        let notes = ListingNotes::open(Some(path.clone()));
+        let request = NotesRequest {
+            note: Some(" viewed 12/5, damp in bedroom ".to_string()),
+            tags: vec!["Shortlist".to_string(), "damp".to_string()],
+            remove_tags: vec![],
+        };
+        notes.update("alice", "daft_1", &request, Utc::now()).unwrap();
+        let untag = NotesRequest { remove_tags: vec!["DAMP".to_string()], ..Default::default() };
+        let annotations = notes.update("alice", "daft_1", &untag, Utc::now()).unwrap();
+        assert_eq!(annotations.notes[0].text, "viewed 12/5, damp in bedroom");
+        assert_eq!(annotations.tags.iter().collect::<Vec<_>>(), ["shortlist"]);
+
+        let reopened = ListingNotes::open(Some(path));
+        let mut properties = vec![listing("daft", "1"), listing("daft", "2")];
+        reopened.attach_all(&mut properties, &Caller::with_scopes(Some("alice".to_string()), &[]));
+        assert_eq!(properties[0].annotations.as_ref(), Some(&annotations));
+        assert!(properties[1].annotations.is_none());
+
+        // A new listing under the same source id has none of the old one's notes
+        let mut recycled = listing("daft", "3");
+        recycled.legacy_id = Some("daft_1".to_string());
+        let mut properties = vec![recycled];
+        reopened.attach_all(&mut properties, &Caller::with_scopes(Some("alice".to_string()), &[]));
+        assert!(properties[0].annotations.is_none());
+
+        let mut properties = vec![listing("daft", "1")];
+        reopened.attach_all(&mut properties, &Caller::with_scopes(Some("bob".to_string()), &[]));
+        assert!(properties[0].annotations.is_none());
+    }
+
+    #[test]
+    fn test_rejects_empty_requests() {
+        let notes = ListingNotes::open(None);
+        assert!(notes.update("alice", "daft_1", &NotesRequest::default(), Utc::now()).is_err());
+        let blank = NotesRequest { tags: vec!["  ".to_string()], ..Default::default() };
+        assert!(notes.update("alice", "daft_1", &blank, Utc::now()).is_err());
+        let cleared = NotesRequest { tags: vec!["a".to_string()], remove_tags: vec!["a".to_string()], ..Default::default() };
+        assert_eq!(notes.update("alice", "daft_1", &cleared, Utc::now()).unwrap(), Annotations::default());
+        assert!(notes.get("alice", "daft_1").is_none());
+    }
+}
@@ -0,0 +1,164 @@
+//! Outbound notifications.
+//!
+//! Anything worth a human's attention (data-quality alerts for now) goes
+//! through `notify`, which queues one delivery job per configured webhook so a
+//! slow or failing endpoint is retried by the job queue instead of holding up
+//! the caller. Users who set notification preferences get them too, over
+//! their own channel and schedule; see `preferences`. With neither,
+//! notifications are only logged.
+
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::email::{self, Email};
+use crate::preferences::{Batch, Channel};
+use crate::state::AppState;
+
+/// Job kind that delivers one notification to one webhook.
+pub const JOB: &str = "notify.webhook";
+/// Job kind that delivers notifications to one user over their channel.
+pub const USER_JOB: &str = "notify.user";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Notification {
+    /// Machine-readable category, e.g. `density.anomaly`.
+    pub kind: String,
+    /// One-line summary. Sent as `text` so chat webhooks render it.
+    pub text: String,
+    pub details: serde_json::Value,
+    pub at: DateTime<Utc>,
+}
+
+impl Notification {
+    pub fn new(kind: &str, text: String, details: serde_json::Value, at: DateTime<Utc>) -> Self {
+        Notification {
+            kind: kind.to_string(),
+            text,
+            details,
+            at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Delivery {
+    url: String,
+    notification: Notification,
+}
+
+pub fn notify(state: &AppState, notification: Notification) {
+    warn!("{}: {}", notification.kind, notification.text);
+    for url in &state.config.notifier.webhooks {
+        let delivery = Delivery {
+            url: url.clone(),
+            notification: notification.clone(),
+        };
+        if let Err(e) = state.jobs.enqueue(JOB, &delivery) {
+            error!("Could not queue {} notification for {}: {}", notification.kind, url, e);
+        }
+    }
+    for batch in state.preferences.dispatch(&notification, state.clock.now()) {
+        if let Err(e) = state.jobs.enqueue(USER_JOB, &batch) {
+            error!("Could not queue {} notification for {}: {}", notification.kind, batch.user, e);
+        }
+    }
+}
+
+/// Queues notifications held for quiet hours or digests once they are due.
+pub fn start(state: &AppState) {
+    let state = state.clone();
+    let period = Duration::from_secs(state.config.notifier.check_interval_secs.max(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let due = match state.preferences.take_due(state.clock.now()) {
+                Ok(due) => due,
+                Err(e) => {
+                    error!("Could not release held notifications: {}", e);
+                    continue;
+                }
+            };
+            for batch in due {
+                if let Err(e) = state.jobs.enqueue(USER_JOB, &batch) {
+                    error!("Could not queue notifications for {}: {}", batch.user, e);
+                }
+            }
+        }
+    });
+}
+
+/// POSTs `body` as JSON. Blocking: call it from a job handler, which runs on
+/// a blocking worker thread, so the request is driven on that thread's runtime.
+pub fn post_json(url: &str, body: &impl Serialize) -> Result<(), String> {
+    post_json_authorized(url, None, body)
+}
+
+/// Like `post_json`, with `token` as a bearer `Authorization` header.
+pub fn post_json_authorized(url: &str, token: Option<&str>, body: &impl Serialize) -> Result<(), String> {
+    let body = serde_json::to_vec(body).map_err(|e| e.to_string())?;
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut request = client.post(url).header("content-type", "application/json").body(body);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = tokio::runtime::Handle::current()
+        .block_on(request.send())
+        .map_err(|e| format!("POST {} failed: {}", url, e))?;
+    if !response.status().is_success() {
+        return Err(format!("POST {} returned {}", url, response.status()));
+    }
+    Ok(())
+}
+
+/// Subject line and plain-text body for a batch: the notification itself, or
+/// a digest listing each one.
+fn compose(notifications: &[Notification]) -> (String, String) {
+    match notifications {
+        [single] => (format!("Market analysis: {}", single.text), single.text.clone()),
+        _ => {
+            let lines: Vec<String> = notifications
+                .iter()
+                .map(|n| format!("- {} ({})", n.text, n.at.format("%Y-%m-%d %H:%M UTC")))
+                .collect();
+            (
+                format!("Market analysis digest: {} notifications", notifications.len()),
+                lines.join("\n"),
+            )
+        }
+    }
+}
+
+pub fn run_user_job(state: &AppState, payload: &serde_json::Value) -> Result<(), String> {
+    let batch: Batch = serde_json::from_value(payload.clone()).map_err(|e| e.to_string())?;
+    let (subject, body) = compose(&batch.notifications);
+    let config = &state.config.notifier;
+    match &batch.channel {
+        Channel::Email { address } => {
+            let smtp = config.smtp.as_ref().ok_or("No notifier.smtp relay configured")?;
+            email::send(smtp, &Email { to: address.clone(), subject, body, attachments: vec![] })?;
+        }
+        Channel::Telegram { chat_id } => {
+            let token = config.telegram_bot_token.as_deref().ok_or("No notifier.telegram_bot_token configured")?;
+            let url = format!("{}/bot{}/sendMessage", config.telegram_api_url.trim_end_matches('/'), token);
+            post_json(&url, &serde_json::json!({ "chat_id": chat_id, "text": format!("{}\n{}", subject, body) }))?;
+        }
+        Channel::Webhook { url } => {
+            post_json(url, &serde_json::json!({ "text": subject, "notifications": batch.notifications }))?;
+        }
+    }
+    info!("Delivered {} notifications to {}", batch.notifications.len(), batch.user);
+    Ok(())
+}
+
+pub fn run_job(_state: &AppState, payload: &serde_json::Value) -> Result<(), String> {
+    let delivery: Delivery = serde_json::from_value(payload.clone()).map_err(|e| e.to_string())?;
+    post_json(&delivery.url, &delivery.notification)?;
+    info!("Delivered {} notification to {}", delivery.notification.kind, delivery.url);
+    Ok(())
+}
@@ -0,0 +1,212 @@
+//! Snapshot pinning for consistent pagination.
+//!
+//! The first page of a search is answered from the latest snapshot of each
+//! source and comes back with an `x-snapshot-token` header. Passing that token as
+//! `snapshot_token` on later pages reads the same snapshot files, so a snapshot
+//! landing mid-pagination can't shift results between pages. Tokens expire after
+//! `search.snapshot_ttl_secs`.
+//!
+//! Deep pages use keyset cursors instead of offsets: `x-next-cursor` encodes
+//! where the previous page stopped, so the next one resumes there without
+//! re-reading everything before it. Combine a cursor with a snapshot token to
+//! keep the positions valid across snapshot drops.
+//!
+//! Every search answers one page, wrapped with the total match count:
+//! `{"total", "page", "page_size", "results"}`. Clients that show numbered
+//! pages ask for `page` (from 1) and `page_size` instead of `offset` and
+//! `limit`. Either way the size defaults to `search.default_page_size` and may
+//! not exceed `search.max_page_size`.
+
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::config::SearchConfig;
+use crate::SearchParams;
+
+/// Position just after the last listing of a page: its source and row offset
+/// within that source's snapshot file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub source: String,
+    pub row: usize,
+}
+
+impl Cursor {
+    /// Opaque hex encoding, safe to pass in a query string.
+    pub fn encode(&self) -> String {
+        format!("{}:{}", self.source, self.row)
+            .bytes()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    pub fn decode(encoded: &str) -> Option<Self> {
+        if !encoded.len().is_multiple_of(2) {
+            return None;
+        }
+        let bytes = (0..encoded.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(encoded.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        let decoded = String::from_utf8(bytes).ok()?;
+        let (source, row) = decoded.rsplit_once(':')?;
+        Some(Cursor {
+            source: source.to_string(),
+            row: row.parse().ok()?,
+        })
+    }
+}
+
+/// A page of results by number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    /// From 1.
+    pub number: usize,
+    pub size: usize,
+}
+
+impl Page {
+    /// The page `params` asks for, by `page` and `page_size` or by `offset`
+    /// and `limit`; the first page when it sets none of them.
+    pub fn requested(params: &SearchParams, config: &SearchConfig) -> Result<Page, String> {
+        let numbered = params.page.is_some() || params.page_size.is_some();
+        if numbered && (params.offset.is_some() || params.limit.is_some()) {
+            return Err("page and page_size replace offset and limit; use one or the other".to_string());
+        }
+        if numbered && params.cursor.is_some() {
+            return Err("Cursors can't be combined with page; follow x-next-cursor instead".to_string());
+        }
+        let size = params.page_size.or(params.limit).unwrap_or(config.default_page_size);
+        if size == 0 || size > config.max_page_size {
+            let field = if params.limit.is_some() { "limit" } else { "page_size" };
+            return Err(format!("{} must be between 1 and {}", field, config.max_page_size));
+        }
+        let number = match params.offset {
+            Some(offset) => offset / size + 1,
+            None => params.page.unwrap_or(1),
+        };
+        if number == 0 {
+            return Err("Pages are numbered from 1".to_string());
+        }
+        Ok(Page { number, size })
+    }
+
+    /// Matches before the page starts.
+    pub fn offset(&self) -> usize {
+        (self.number - 1).saturating_mul(self.size)
+    }
+}
+
+/// A page of results.
+#[derive(Debug, Serialize)]
+pub struct Paged<T> {
+    /// Every match, not just this page's; an estimate when the search sends
+    /// `x-total-count-estimate` rather than `x-total-count`.
+    pub total: usize,
+    /// The page `offset` falls on, or 1 for a page resumed from a cursor.
+    pub page: usize,
+    pub page_size: usize,
+    pub results: Vec<T>,
+}
+
+struct Pin {
+    files: HashMap<String, PathBuf>,
+    expires: Instant,
+}
+
+pub struct SnapshotPins {
+    ttl: RwLock<Duration>,
+    pins: Mutex<HashMap<String, Pin>>,
+}
+
+impl SnapshotPins {
+    pub fn new(ttl: Duration) -> Self {
+        SnapshotPins {
+            ttl: RwLock::new(ttl),
+            pins: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Changes how long new pins live; pins already issued keep their expiry.
+    pub fn set_ttl(&self, ttl: Duration) {
+        *self.ttl.write().unwrap() = ttl;
+    }
+
+    /// Registers the snapshot file chosen for each source and returns a token
+    /// that resolves back to them until it expires.
+    pub fn pin(&self, files: HashMap<String, PathBuf>) -> String {
+        let now = Instant::now();
+        let mut pins = self.pins.lock().unwrap();
+        pins.retain(|_, pin| pin.expires > now);
+
+        let mut hasher = DefaultHasher::new();
+        SystemTime::now().hash(&mut hasher);
+        pins.len().hash(&mut hasher);
+        let mut sorted: Vec<_> = files.iter().collect();
+        sorted.sort();
+        sorted.hash(&mut hasher);
+        let token = format!("{:016x}", hasher.finish());
+
+        pins.insert(token.clone(), Pin { files, expires: now + *self.ttl.read().unwrap() });
+        token
+    }
+
+    /// The pinned files for `token`, or `None` once it has expired or if it was
+    /// never issued.
+    pub fn resolve(&self, token: &str) -> Option<HashMap<String, PathBuf>> {
+        let pins = self.pins.lock().unwrap();
+        pins.get(token)
+            .filter(|pin| pin.expires > Instant::now())
+            .map(|pin| pin.files.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pin_resolves_until_expiry() {
+        let pins = SnapshotPins::new(Duration::from_secs(60));
+        let files = HashMap::from([("daft".to_string(), PathBuf::from("daft/2024/11/05/a.parquet"))]);
+        let token = pins.pin(files.clone());
+        assert_eq!(pins.resolve(&token), Some(files));
+        assert_eq!(pins.resolve("unknown"), None);
+
+        let expired = SnapshotPins::new(Duration::ZERO);
+        let token = expired.pin(HashMap::new());
+        assert_eq!(expired.resolve(&token), None);
+    }
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let cursor = Cursor { source: "my:home".to_string(), row: 4211 };
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+        assert_eq!(Cursor::decode("zz"), None);
+        assert_eq!(Cursor::decode("abc"), None);
+        assert_eq!(Cursor::decode(&"daft".bytes().map(|b| format!("{:02x}", b)).collect::<String>()), None);
+    }
+
+    #[test]
+    fn test_pages_resolve_to_offsets() {
+        let config = SearchConfig::default();
+        let requested = |query: &str| Page::requested(&serde_json::from_str(query).unwrap(), &config);
+        assert_eq!(requested("{}"), Ok(Page { number: 1, size: config.default_page_size }));
+        let page = requested(r#"{"page": 3, "page_size": 25}"#).unwrap();
+        assert_eq!((page.offset(), page.size), (50, 25));
+        let page = requested(r#"{"page": 2}"#).unwrap();
+        assert_eq!((page.offset(), page.size), (config.default_page_size, config.default_page_size));
+        assert_eq!(requested(r#"{"offset": 30, "limit": 10}"#), Ok(Page { number: 4, size: 10 }));
+
+        assert!(requested(r#"{"page": 0}"#).is_err());
+        assert!(requested(r#"{"page_size": 100000}"#).is_err());
+        assert!(requested(r#"{"limit": 100000}"#).is_err());
+        assert!(requested(r#"{"page": 2, "offset": 10}"#).is_err());
+        assert!(requested(r#"{"page": 2, "cursor": "abc"}"#).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+//! Snapshot files the pipeline is still writing.
+//!
+//! A parquet file ends with its footer and the `PAR1` magic, which the writer
+//! only adds when it closes the file. A file caught mid-write therefore can't
+//! be opened, and a search that picked it used to drop the source without a
+//! word. Snapshot discovery (`find_latest_parquet`, `list_snapshots`) now skips
+//! files without the closing magic, so the previous snapshot is served until
+//! the write completes, and search reports the skipped file as `partial` in
+//! its warnings.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::{newest_parquet, numeric_subdirs};
+
+const MAGIC: &[u8; 4] = b"PAR1";
+
+/// Whether `path` starts and ends with the parquet magic. A complete file also
+/// holds a 4-byte footer length, so anything shorter than 12 bytes is partial.
+pub fn is_complete(path: &Path) -> bool {
+    let read = || -> std::io::Result<bool> {
+        let mut file = File::open(path)?;
+        if file.metadata()?.len() < 12 {
+            return Ok(false);
+        }
+        let (mut head, mut tail) = ([0; 4], [0; 4]);
+        file.read_exact(&mut head)?;
+        file.seek(SeekFrom::End(-4))?;
+        file.read_exact(&mut tail)?;
+        Ok(&head == MAGIC && &tail == MAGIC)
+    };
+    read().unwrap_or(false)
+}
+
+/// The newest parquet file of the source's newest partition, when it is
+/// still being written.
+pub fn pending(source_path: &Path) -> Option<PathBuf> {
+    let newest = numeric_subdirs(source_path)
+        .into_iter()
+        .rev()
+        .flat_map(|(_, year)| numeric_subdirs(&year).into_iter().rev())
+        .flat_map(|(_, month)| numeric_subdirs(&month).into_iter().rev())
+        .find_map(|(_, day)| newest_parquet(&day, |_| true))?;
+    (!is_complete(&newest)).then_some(newest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{standardized_batch, temp_dir, write_parquet};
+    use crate::{find_latest_parquet, list_snapshots};
+
+    #[test]
+    fn test_partial_files_are_skipped() {
+        let root = temp_dir("partial");
+        let complete = root.join("2024/11/04/daft_120000.parquet");
+        write_parquet(&complete, &standardized_batch(&["1"]));
+        assert!(is_complete(&complete));
+        assert_eq!(pending(&root), None);
+
+        // The next day's file, cut off before its footer
+        let bytes = std::fs::read(&complete).unwrap();
+        let partial = root.join("2024/11/05/daft_120000.parquet");
+        std::fs::create_dir_all(partial.parent().unwrap()).unwrap();
+        std::fs::write(&partial, &bytes[..bytes.len() - 8]).unwrap();
+        assert!(!is_complete(&partial));
+
+        assert_eq!(find_latest_parquet(&root), Some(complete.clone()));
+        assert_eq!(list_snapshots(&root).len(), 1);
+        assert_eq!(pending(&root), Some(partial));
+    }
+}
@@ -0,0 +1,456 @@
+//! Local copies of listing photos.
+//!
+//! Source CDNs drop a listing's photos soon after it is delisted, which leaves
+//! the delisted listings in `/api/rentals/changes` without pictures. With
+//! `photos.enabled` a background job downloads the photos of each source's
+//! new and changed listings (from the snapshot delta, see `deltas`) into
+//! `photos.path`. Files are stored by the SHA-256 of their content, so a photo
+//! shared between listings or re-used on a relisting is kept once, and an
+//! index maps each listing to the hashes of its photos.
+//!
+//! `GET /api/photos/{hash}` serves a stored photo and
+//! `GET /api/rentals/{id}/photos` lists a listing's, delisted or not. Removed
+//! listings in `/api/rentals/changes` always point at the stored copies; with
+//! `photos.offline` every listing response does, for the photos that have one.
+//!
+//! Parsing a listing's photo list (`from_row`) and picking its main photo
+//! (`mark_main`) live here too.
+
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+use crate::config::PhotosConfig;
+use crate::deltas::{ChangeKind, SnapshotDelta};
+use crate::ids;
+use crate::state::AppState;
+use crate::mapping::BatchRow;
+use crate::{find_latest_parquet, Photo, StandardizedProperty};
+
+/// Job kind that downloads the photos of new and changed listings.
+pub const JOB: &str = "photos.fetch";
+
+const INDEX_FILE: &str = "index.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedPhoto {
+    /// Where the photo was downloaded from.
+    pub url: String,
+    pub hash: String,
+    pub bytes: u64,
+}
+
+impl CachedPhoto {
+    /// The API path serving the stored copy.
+    pub fn path(&self) -> String {
+        format!("/api/photos/{}", self.hash)
+    }
+}
+
+/// Stored photos by property id, in the order they were downloaded.
+type Index = BTreeMap<String, Vec<CachedPhoto>>;
+
+pub struct PhotoCache {
+    dir: PathBuf,
+    offline: bool,
+    index: RwLock<Index>,
+    /// URLs that failed to download since startup, skipped until a restart so
+    /// a few dead links can't use up every run.
+    failed: Mutex<HashSet<String>>,
+}
+
+fn hash(content: &[u8]) -> String {
+    Sha256::digest(content).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn is_hash(value: &str) -> bool {
+    value.len() == 64 && value.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// The image type, from the file's leading bytes.
+fn content_type(content: &[u8]) -> &'static str {
+    match content {
+        [0xff, 0xd8, 0xff, ..] => "image/jpeg",
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [b'G', b'I', b'F', b'8', ..] => "image/gif",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+impl PhotoCache {
+    /// Opens the store in `config.path`, loading its index if there is one.
+    pub fn open(config: &PhotosConfig) -> Self {
+        let index = fs::read_to_string(config.path.join(INDEX_FILE))
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        PhotoCache {
+            dir: config.path.clone(),
+            offline: config.offline,
+            index: RwLock::new(index),
+            failed: Mutex::default(),
+        }
+    }
+
+    fn blob_path(&self, hash: &str) -> PathBuf {
+        self.dir.join("blobs").join(&hash[..2]).join(hash)
+    }
+
+    pub fn has(&self, property_id: &str, url: &str) -> bool {
+        self.index.read().unwrap().get(property_id).is_some_and(|photos| photos.iter().any(|p| p.url == url))
+    }
+
+    pub fn photos(&self, property_id: &str) -> Vec<CachedPhoto> {
+        self.index.read().unwrap().get(property_id).cloned().unwrap_or_default()
+    }
+
+    /// The listings each stored photo was downloaded for, by hash.
+    pub fn listings_by_hash(&self) -> BTreeMap<String, Vec<String>> {
+        let mut listings: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (property_id, photos) in self.index.read().unwrap().iter() {
+            for photo in photos {
+                let ids = listings.entry(photo.hash.clone()).or_default();
+                if !ids.contains(property_id) {
+                    ids.push(property_id.clone());
+                }
+            }
+        }
+        listings
+    }
+
+    /// Writes `content` unless a photo with the same content is stored
+    /// already, and records it against the listing. Call `save` to persist the
+    /// index.
+    pub fn store(&self, property_id: &str, url: &str, content: &[u8]) -> Result<CachedPhoto, String> {
+        let photo = CachedPhoto { url: url.to_string(), hash: hash(content), bytes: content.len() as u64 };
+        let path = self.blob_path(&photo.hash);
+        if !path.exists() {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+            // Written under a temporary name so a crash can't leave a partial
+            // file behind a valid hash
+            let partial = path.with_extension("partial");
+            fs::write(&partial, content).map_err(|e| e.to_string())?;
+            fs::rename(&partial, &path).map_err(|e| e.to_string())?;
+        }
+        let mut index = self.index.write().unwrap();
+        let photos = index.entry(property_id.to_string()).or_default();
+        photos.retain(|p| p.url != url);
+        photos.push(photo.clone());
+        Ok(photo)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+        let contents = serde_json::to_string(&*self.index.read().unwrap()).map_err(|e| e.to_string())?;
+        fs::write(self.dir.join(INDEX_FILE), contents).map_err(|e| e.to_string())
+    }
+
+    /// A stored photo's content, by hash.
+    pub fn read(&self, hash: &str) -> Option<Vec<u8>> {
+        if !is_hash(hash) {
+            return None;
+        }
+        fs::read(self.blob_path(hash)).ok()
+    }
+
+    /// Points the listing's photos that have a stored copy at it.
+    pub fn rewrite(&self, property: &mut StandardizedProperty) {
+        let index = self.index.read().unwrap();
+        let Some(stored) = property.ids().find_map(|id| index.get(id)) else {
+            return;
+        };
+        for photo in &mut property.photos {
+            if let Some(copy) = stored.iter().find(|copy| copy.url == photo.url) {
+                photo.url = copy.path();
+            }
+        }
+    }
+
+    /// `rewrite` for every listing, in offline mode only.
+    pub fn serve_offline(&self, properties: &mut [StandardizedProperty]) {
+        if self.offline {
+            for property in properties {
+                self.rewrite(property);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PhotoKind {
+    #[default]
+    Photo,
+    Floorplan,
+}
+
+/// Keys a photo record may keep each of its fields under, lowercased.
+const URL_KEYS: [&str; 4] = ["url", "src", "image", "photo"];
+const ORDER_KEYS: [&str; 4] = ["order", "position", "sortorder", "index"];
+const CAPTION_KEYS: [&str; 3] = ["caption", "title", "description"];
+const KIND_KEYS: [&str; 4] = ["type", "kind", "phototype", "mediatype"];
+
+fn key<'a>(record: &'a serde_json::Map<String, Value>, keys: &[&str]) -> Option<&'a Value> {
+    record.iter().find(|(name, value)| keys.contains(&name.to_lowercase().as_str()) && !value.is_null()).map(|(_, v)| v)
+}
+
+fn is_floorplan(text: &str) -> bool {
+    let text = text.to_lowercase();
+    text.contains("floorplan") || text.contains("floor plan") || text.contains("floor_plan")
+}
+
+/// The listing's photo list: plain URLs, or records with a URL and any of an
+/// order, a caption and a type. Records are put in their stated order, and a
+/// record is a floorplan when its type or caption says so.
+pub fn from_json(list: &Value) -> Vec<Photo> {
+    let mut photos: Vec<(Option<i64>, Photo)> = Vec::new();
+    for item in list.as_array().into_iter().flatten() {
+        let (order, photo) = match item {
+            Value::String(url) => (None, Photo { url: url.clone(), ..Default::default() }),
+            Value::Object(record) => {
+                let Some(url) = key(record, &URL_KEYS).and_then(Value::as_str) else {
+                    continue;
+                };
+                let caption = key(record, &CAPTION_KEYS).and_then(Value::as_str).map(str::trim);
+                let caption = caption.filter(|caption| !caption.is_empty());
+                let kind = key(record, &KIND_KEYS).and_then(Value::as_str);
+                let floorplan = kind.is_some_and(is_floorplan) || caption.is_some_and(is_floorplan);
+                let order = key(record, &ORDER_KEYS).and_then(|order| {
+                    order.as_i64().or_else(|| order.as_str().and_then(|s| s.trim().parse().ok()))
+                });
+                let photo = Photo {
+                    url: url.to_string(),
+                    is_main: false,
+                    caption: caption.map(str::to_string),
+                    kind: if floorplan { PhotoKind::Floorplan } else { PhotoKind::Photo },
+                };
+                (order, photo)
+            }
+            _ => continue,
+        };
+        if !url_taken(&photos, &photo.url) {
+            photos.push((order, photo));
+        }
+    }
+    // Stated orders first; the rest keep their place in the list
+    photos.sort_by_key(|(order, _)| (order.is_none(), *order));
+    photos.into_iter().map(|(_, photo)| photo).collect()
+}
+
+fn url_taken(photos: &[(Option<i64>, Photo)], url: &str) -> bool {
+    photos.iter().any(|(_, photo)| photo.url == url)
+}
+
+pub fn from_row(row: &BatchRow) -> Vec<Photo> {
+    row.json("photos").map(|list| from_json(&list)).unwrap_or_default()
+}
+
+/// Marks the first photo the source flagged as the main one, or else the
+/// first that isn't a floorplan, and unmarks the rest.
+pub fn mark_main(photos: &mut [Photo]) {
+    let main = photos
+        .iter()
+        .position(|photo| photo.is_main)
+        .or_else(|| photos.iter().position(|photo| photo.kind == PhotoKind::Photo))
+        .unwrap_or(0);
+    for (i, photo) in photos.iter_mut().enumerate() {
+        photo.is_main = i == main;
+    }
+}
+
+fn download(client: &reqwest::Client, url: &str, max_bytes: u64) -> Result<Vec<u8>, String> {
+    let content = tokio::runtime::Handle::current().block_on(async {
+        let response = client.get(url).send().await.map_err(|e| format!("GET {} failed: {}", url, e))?;
+        if !response.status().is_success() {
+            return Err(format!("GET {} returned {}", url, response.status()));
+        }
+        if response.content_length().is_some_and(|length| length > max_bytes) {
+            return Err(format!("{} is over {} bytes", url, max_bytes));
+        }
+        response.bytes().await.map_err(|e| format!("GET {} failed: {}", url, e))
+    })?;
+    if content.len() as u64 > max_bytes {
+        return Err(format!("{} is over {} bytes", url, max_bytes));
+    }
+    Ok(content.to_vec())
+}
+
+/// Downloads up to `photos.max_per_run` photos of the new and changed
+/// listings in each source's latest snapshot that aren't stored yet. Listings
+/// left over are picked up by the next run.
+pub fn run_job(state: &AppState, _payload: &serde_json::Value) -> Result<(), String> {
+    let config = &state.config;
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let (mut fetched, mut failed) = (0, 0);
+    'sources: for source in &config.sources {
+        let Some(latest) = find_latest_parquet(&source.root(&config.data_path)) else {
+            continue;
+        };
+        let Some(delta) = SnapshotDelta::load_or_build(config, source, &latest) else {
+            continue;
+        };
+        for change in delta.changes.iter().filter(|change| change.kind != ChangeKind::Removed) {
+            for photo in &change.listing.photos {
+                if state.photos.has(&change.property_id, &photo.url)
+                    || state.photos.failed.lock().unwrap().contains(&photo.url)
+                {
+                    continue;
+                }
+                if fetched + failed >= config.photos.max_per_run {
+                    break 'sources;
+                }
+                match download(&client, &photo.url, config.photos.max_bytes)
+                    .and_then(|content| state.photos.store(&change.property_id, &photo.url, &content))
+                {
+                    Ok(_) => fetched += 1,
+                    Err(e) => {
+                        warn!("Could not store photo: {}", e);
+                        state.photos.failed.lock().unwrap().insert(photo.url.clone());
+                        failed += 1;
+                    }
+                }
+            }
+        }
+    }
+    if fetched > 0 {
+        state.photos.save()?;
+    }
+    info!("Stored {} photos, {} failed", fetched, failed);
+    Ok(())
+}
+
+/// Queues a fetch every `photos.check_interval_mins` for the life of the
+/// process.
+pub fn schedule(state: &AppState) {
+    if !state.config.photos.enabled {
+        return;
+    }
+    let state = state.clone();
+    let period = Duration::from_secs(state.config.photos.check_interval_mins.max(1) * 60);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            if let Err(e) = state.jobs.enqueue_once(JOB, ()) {
+                error!("Could not queue photo downloads: {}", e);
+            }
+        }
+    });
+}
+
+pub async fn photo(State(state): State<AppState>, Path(hash): Path<String>) -> Response {
+    match state.photos.read(&hash) {
+        Some(content) => (
+            [
+                (header::CONTENT_TYPE, content_type(&content)),
+                // Content-addressed, so a hash always means the same bytes
+                (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+            ],
+            Body::from(content),
+        )
+            .into_response(),
+        None => (StatusCode::NOT_FOUND, format!("No photo {}", hash)).into_response(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StoredPhoto {
+    #[serde(flatten)]
+    pub photo: CachedPhoto,
+    pub path: String,
+}
+
+pub async fn listing_photos(State(state): State<AppState>, Path(property_id): Path<String>) -> Json<Vec<StoredPhoto>> {
+    let mut photos = state.photos.photos(&property_id);
+    if photos.is_empty() {
+        // Photos stored before the listing had a canonical id
+        photos = state.photos.photos(&ids::legacy(&property_id));
+    }
+    Json(photos.into_iter().map(|photo| StoredPhoto { path: photo.path(), photo }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{listing, temp_dir};
+
+    #[test]
+    fn test_photos_are_stored_by_content() {
+        let config = PhotosConfig { path: temp_dir("photos"), offline: true, ..Default::default() };
+        let photos = PhotoCache::open(&config);
+        let jpeg = [0xff, 0xd8, 0xff, 0xe0, 1, 2, 3];
+        let first = photos.store("daft_1", "https://cdn/a.jpg", &jpeg).unwrap();
+        let relisted = photos.store("daft_2", "https://cdn/b.jpg", &jpeg).unwrap();
+        assert_eq!(first.hash, relisted.hash);
+        assert_eq!(fs::read_dir(config.path.join("blobs")).unwrap().count(), 1);
+        assert_eq!(content_type(&photos.read(&first.hash).unwrap()), "image/jpeg");
+        assert_eq!(photos.read("../index.json"), None);
+        photos.save().unwrap();
+
+        let reopened = PhotoCache::open(&config);
+        assert!(reopened.has("daft_1", "https://cdn/a.jpg"));
+        let mut properties = vec![listing("daft", "1")];
+        properties[0].photos = vec![
+            Photo { url: "https://cdn/a.jpg".to_string(), is_main: true, ..Default::default() },
+            Photo { url: "https://cdn/c.jpg".to_string(), is_main: false, ..Default::default() },
+        ];
+        reopened.serve_offline(&mut properties);
+        assert_eq!(properties[0].photos[0].url, format!("/api/photos/{}", first.hash));
+        assert_eq!(properties[0].photos[1].url, "https://cdn/c.jpg");
+    }
+
+    #[test]
+    fn test_exactly_one_main_photo() {
+        let photo = |url: &str, is_main: bool| Photo { url: url.to_string(), is_main, ..Default::default() };
+        let mut photos = vec![photo("a", false), photo("b", true), photo("c", true)];
+        mark_main(&mut photos);
+        assert_eq!(photos.iter().map(|p| p.is_main).collect::<Vec<_>>(), [false, true, false]);
+
+        let mut photos = vec![photo("a", false), photo("b", false)];
+        mark_main(&mut photos);
+        assert!(photos[0].is_main && !photos[1].is_main);
+        mark_main(&mut []);
+    }
+
+    #[test]
+    fn test_photo_records_keep_order_captions_and_floorplans() {
+        let list = serde_json::json!([
+            {"Url": "c.jpg", "Order": 3, "Caption": "Floor plan"},
+            {"Url": "a.jpg", "Order": 1, "Caption": " Kitchen ", "Type": "Photo"},
+            {"Url": "b.jpg", "Order": "2", "Type": "FloorPlan"},
+            {"Caption": "no url"},
+        ]);
+        let mut photos = from_json(&list);
+        let summary: Vec<_> = photos.iter().map(|p| (p.url.as_str(), p.caption.as_deref(), p.kind)).collect();
+        assert_eq!(
+            summary,
+            [
+                ("a.jpg", Some("Kitchen"), PhotoKind::Photo),
+                ("b.jpg", None, PhotoKind::Floorplan),
+                ("c.jpg", Some("Floor plan"), PhotoKind::Floorplan),
+            ]
+        );
+        assert_eq!(from_json(&serde_json::json!(["x.jpg", "y.jpg", "x.jpg"])).len(), 2);
+
+        photos.reverse();
+        mark_main(&mut photos);
+        assert!(photos[2].is_main);
+    }
+}
@@ -0,0 +1,342 @@
+//! Per-user notification preferences.
+//!
+//! Besides the operator's webhooks, every notification goes to each user who
+//! set preferences with `PUT /api/me/preferences/notifications`: over their
+//! channel (email, Telegram or webhook), either as it happens or collected
+//! into a daily or weekly digest, and never during their quiet hours.
+//! Notifications held back are kept with the preferences and released by
+//! `notifier::start`. All hours are UTC, like `reports.send_hour`.
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Timelike, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use crate::auth::Caller;
+use crate::notifier::Notification;
+use crate::state::AppState;
+
+/// Held notifications kept per user; older ones are dropped first.
+const MAX_PENDING: usize = 500;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Channel {
+    Email { address: String },
+    Telegram { chat_id: String },
+    Webhook { url: String },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Frequency {
+    #[default]
+    Instant,
+    Daily,
+    Weekly,
+}
+
+/// Hours of the day (UTC) nothing is sent, from `start` up to `end`. Wraps
+/// past midnight when `end` is before `start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuietHours {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl QuietHours {
+    fn contains(&self, hour: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&hour)
+        } else {
+            hour >= self.start || hour < self.end
+        }
+    }
+}
+
+fn default_digest_hour() -> u32 {
+    8
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Preferences {
+    pub channel: Channel,
+    #[serde(default)]
+    pub frequency: Frequency,
+    /// Hour (UTC) digests go out; weekly digests on Mondays.
+    #[serde(default = "default_digest_hour")]
+    pub digest_hour: u32,
+    #[serde(default)]
+    pub quiet_hours: Option<QuietHours>,
+    /// Notification kinds wanted, e.g. `density.anomaly`; empty for all.
+    #[serde(default)]
+    pub kinds: Vec<String>,
+}
+
+impl Preferences {
+    fn validate(&self) -> Result<(), String> {
+        match &self.channel {
+            Channel::Email { address } if !address.contains('@') => {
+                return Err(format!("Invalid email address {:?}", address))
+            }
+            Channel::Telegram { chat_id } if chat_id.trim().is_empty() => {
+                return Err("Telegram chat_id is empty".to_string())
+            }
+            Channel::Webhook { url } if reqwest::Url::parse(url).is_err() => {
+                return Err(format!("Invalid webhook URL {:?}", url))
+            }
+            _ => {}
+        }
+        let hours = [Some(self.digest_hour), self.quiet_hours.map(|q| q.start), self.quiet_hours.map(|q| q.end)];
+        if hours.into_iter().flatten().any(|hour| hour > 23) {
+            return Err("Hours must be 0 to 23".to_string());
+        }
+        Ok(())
+    }
+
+    fn wants(&self, notification: &Notification) -> bool {
+        self.kinds.is_empty() || self.kinds.contains(&notification.kind)
+    }
+
+    fn is_quiet(&self, now: DateTime<Utc>) -> bool {
+        self.quiet_hours.is_some_and(|quiet| quiet.contains(now.hour()))
+    }
+
+    /// When the digest after `now` is due; `None` for instant delivery.
+    fn next_digest(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let at = |date: NaiveDate| date.and_hms_opt(self.digest_hour.min(23), 0, 0).unwrap_or_default().and_utc();
+        let today = now.date_naive();
+        let next = match self.frequency {
+            Frequency::Instant => return None,
+            Frequency::Daily => today,
+            Frequency::Weekly => today - Duration::days(today.weekday().num_days_from_monday() as i64),
+        };
+        let step = if self.frequency == Frequency::Weekly { 7 } else { 1 };
+        let mut next = at(next);
+        while next <= now {
+            next += Duration::days(step);
+        }
+        Some(next)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Subscriber {
+    preferences: Preferences,
+    #[serde(default)]
+    pending: Vec<Notification>,
+    next_digest: Option<DateTime<Utc>>,
+}
+
+/// Notifications due for one user over their channel.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Batch {
+    pub user: String,
+    pub channel: Channel,
+    pub notifications: Vec<Notification>,
+}
+
+pub struct NotificationPreferences {
+    path: Option<PathBuf>,
+    subscribers: RwLock<BTreeMap<String, Subscriber>>,
+}
+
+impl NotificationPreferences {
+    /// Loads saved preferences from `path`. `None` keeps them in memory only.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let subscribers = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        NotificationPreferences { path, subscribers: RwLock::new(subscribers) }
+    }
+
+    pub fn get(&self, user: &str) -> Option<Preferences> {
+        self.subscribers.read().unwrap().get(user).map(|s| s.preferences.clone())
+    }
+
+    /// Sets `user`'s preferences, keeping notifications already held for them.
+    pub fn set(&self, user: &str, preferences: Preferences, now: DateTime<Utc>) -> Result<(), String> {
+        preferences.validate()?;
+        let mut subscribers = self.subscribers.write().unwrap();
+        let pending = subscribers.remove(user).map(|s| s.pending).unwrap_or_default();
+        let next_digest = preferences.next_digest(now);
+        subscribers.insert(user.to_string(), Subscriber { preferences, pending, next_digest });
+        self.save(&subscribers)
+    }
+
+    /// Returns whether `user` had preferences.
+    pub fn remove(&self, user: &str) -> Result<bool, String> {
+        let mut subscribers = self.subscribers.write().unwrap();
+        let removed = subscribers.remove(user).is_some();
+        if removed {
+            self.save(&subscribers)?;
+        }
+        Ok(removed)
+    }
+
+    /// Routes `notification` to every user who wants it: returned for those
+    /// to be sent it now, held for the rest.
+    pub fn dispatch(&self, notification: &Notification, now: DateTime<Utc>) -> Vec<Batch> {
+        let mut subscribers = self.subscribers.write().unwrap();
+        let mut now_due = Vec::new();
+        let mut held = false;
+        for (user, subscriber) in subscribers.iter_mut().filter(|(_, s)| s.preferences.wants(notification)) {
+            let preferences = &subscriber.preferences;
+            if preferences.frequency == Frequency::Instant && !preferences.is_quiet(now) {
+                now_due.push(Batch {
+                    user: user.clone(),
+                    channel: preferences.channel.clone(),
+                    notifications: vec![notification.clone()],
+                });
+            } else {
+                if subscriber.pending.len() >= MAX_PENDING {
+                    subscriber.pending.remove(0);
+                }
+                subscriber.pending.push(notification.clone());
+                held = true;
+            }
+        }
+        if held {
+            if let Err(e) = self.save(&subscribers) {
+                warn!("Could not save held notifications: {}", e);
+            }
+        }
+        now_due
+    }
+
+    /// Releases held notifications whose digest is due or whose quiet hours
+    /// have ended.
+    pub fn take_due(&self, now: DateTime<Utc>) -> Result<Vec<Batch>, String> {
+        let mut subscribers = self.subscribers.write().unwrap();
+        let mut due = Vec::new();
+        for (user, subscriber) in subscribers.iter_mut() {
+            let preferences = &subscriber.preferences;
+            let digest_due = subscriber.next_digest.is_none_or(|next| next <= now);
+            if subscriber.pending.is_empty() || !digest_due || preferences.is_quiet(now) {
+                continue;
+            }
+            subscriber.next_digest = preferences.next_digest(now);
+            due.push(Batch {
+                user: user.clone(),
+                channel: preferences.channel.clone(),
+                notifications: std::mem::take(&mut subscriber.pending),
+            });
+        }
+        if !due.is_empty() {
+            self.save(&subscribers)?;
+        }
+        Ok(due)
+    }
+
+    fn save(&self, subscribers: &BTreeMap<String, Subscriber>) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let contents = serde_json::to_string_pretty(subscribers).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| e.to_string())
+    }
+}
+
+fn user(caller: &Caller) -> Result<&str, (StatusCode, String)> {
+    caller
+        .name
+        .as_deref()
+        .ok_or((StatusCode::UNAUTHORIZED, "Notification preferences need an API key".to_string()))
+}
+
+fn storage_error(e: String) -> (StatusCode, String) {
+    warn!("Could not save notification preferences: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Could not save notification preferences".to_string())
+}
+
+pub async fn get(State(state): State<AppState>, caller: Caller) -> Result<Json<Preferences>, (StatusCode, String)> {
+    state
+        .preferences
+        .get(user(&caller)?)
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "No notification preferences set".to_string()))
+}
+
+pub async fn put(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(preferences): Json<Preferences>,
+) -> Result<Json<Preferences>, (StatusCode, String)> {
+    let user = user(&caller)?;
+    preferences.validate().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    state.preferences.set(user, preferences.clone(), state.clock.now()).map_err(storage_error)?;
+    Ok(Json(preferences))
+}
+
+pub async fn delete(State(state): State<AppState>, caller: Caller) -> Result<StatusCode, (StatusCode, String)> {
+    match state.preferences.remove(user(&caller)?).map_err(storage_error)? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err((StatusCode::NOT_FOUND, "No notification preferences set".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    fn preferences(frequency: Frequency, quiet_hours: Option<QuietHours>) -> Preferences {
+        Preferences {
+            channel: Channel::Telegram { chat_id: "42".to_string() },
+            frequency,
+            digest_hour: 8,
+            quiet_hours,
+            kinds: vec![],
+        }
+    }
+
+    #[test]
+    fn test_instant_respects_quiet_hours() {
+        let store = NotificationPreferences::open(None);
+        let quiet = Some(QuietHours { start: 22, end: 7 });
+        store.set("alice", preferences(Frequency::Instant, quiet), at("2024-12-05T12:00:00Z")).unwrap();
+        let notification =
+            Notification::new("density.anomaly", "Listings halved".to_string(), serde_json::Value::Null, Utc::now());
+
+        assert_eq!(store.dispatch(&notification, at("2024-12-05T12:00:00Z")).len(), 1);
+        assert!(store.dispatch(&notification, at("2024-12-05T23:00:00Z")).is_empty());
+        assert!(store.take_due(at("2024-12-06T03:00:00Z")).unwrap().is_empty());
+        let released = store.take_due(at("2024-12-06T07:00:00Z")).unwrap();
+        assert_eq!(released[0].notifications.len(), 1);
+        assert!(store.take_due(at("2024-12-06T07:01:00Z")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_digests_and_kinds() {
+        let store = NotificationPreferences::open(None);
+        store.set("alice", preferences(Frequency::Daily, None), at("2024-12-05T12:00:00Z")).unwrap();
+        let mut weekly = preferences(Frequency::Weekly, None);
+        weekly.kinds = vec!["reports.ready".to_string()];
+        store.set("bob", weekly.clone(), at("2024-12-05T12:00:00Z")).unwrap();
+        assert_eq!(weekly.next_digest(at("2024-12-05T12:00:00Z")), Some(at("2024-12-09T08:00:00Z")));
+
+        for _ in 0..2 {
+            let notification =
+                Notification::new("density.anomaly", "x".to_string(), serde_json::Value::Null, Utc::now());
+            assert!(store.dispatch(&notification, at("2024-12-05T13:00:00Z")).is_empty());
+        }
+        assert!(store.take_due(at("2024-12-06T07:59:00Z")).unwrap().is_empty());
+        let due = store.take_due(at("2024-12-06T08:00:00Z")).unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!((due[0].user.as_str(), due[0].notifications.len()), ("alice", 2));
+    }
+}
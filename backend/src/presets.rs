@@ -0,0 +1,181 @@
+//! Named search filters.
+//!
+//! Admins define presets in the config as tables of search filters:
+//!
+//! ```toml
+//! [presets.dublin-2bed-budget]
+//! location = "dublin"
+//! bedrooms = 2
+//! max_price = 2000
+//! ```
+//!
+//! and clients search with `/api/rentals/search?preset=dublin-2bed-budget`.
+//! Filters given alongside the preset override its own, so a dashboard can
+//! share one preset and vary a single field. `/api/presets` lists what is
+//! defined.
+//!
+//! `[search.defaults]` is a table of the same filters, plus `sort` and
+//! `sort_order`, filled in for every search after its preset's, so hygiene
+//! filters and a page size don't have to be encoded by each client. A request
+//! overrides a default by setting the field, or skips them all with
+//! `?no_defaults=true`; the fields taken from the defaults are listed in an
+//! `x-search-defaults` header. A default `limit` gives way to `page`, and a
+//! default sort to `semantic_q`.
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+use crate::state::AppState;
+use crate::SearchParams;
+
+/// Search parameters a preset may set. Paging and output options are left to
+/// the caller.
+pub const FIELDS: [&str; 20] = [
+    "source",
+    "min_price",
+    "max_price",
+    "bedrooms",
+    "max_per_person",
+    "property_type",
+    "category",
+    "price_band",
+    "ber_rating",
+    "location",
+    "min_parking",
+    "min_photos",
+    "floorplan",
+    "price_flagged",
+    "agent_flagged",
+    "garden",
+    "balcony",
+    "available_from_before",
+    "min_lease_months",
+    "limit",
+];
+
+/// Presets by name, each a table of `FIELDS`.
+pub type Presets = BTreeMap<String, Map<String, Value>>;
+
+/// Search parameters with filters filled in, and the fields that were.
+pub type Filled = (SearchParams, Vec<String>);
+
+/// Checks every preset only sets `FIELDS`, with values a search accepts.
+pub fn validate(presets: &Presets) -> Result<(), String> {
+    for (name, filters) in presets {
+        check(&format!("Preset {:?}", name), filters, &FIELDS)?;
+    }
+    Ok(())
+}
+
+/// Checks `search.defaults` only sets `FIELDS`, `sort` and `sort_order`,
+/// with values a search accepts.
+pub fn validate_defaults(defaults: &Map<String, Value>) -> Result<(), String> {
+    let fields: Vec<&str> = FIELDS.iter().copied().chain(["sort", "sort_order"]).collect();
+    check("search.defaults", defaults, &fields)
+}
+
+fn check(what: &str, filters: &Map<String, Value>, fields: &[&str]) -> Result<(), String> {
+    if let Some(field) = filters.keys().find(|field| !fields.contains(&field.as_str())) {
+        return Err(format!("{} sets {:?}, which isn't a search filter", what, field));
+    }
+    serde_json::from_value::<SearchParams>(Value::Object(filters.clone()))
+        .map_err(|e| format!("{} is invalid: {}", what, e))?;
+    Ok(())
+}
+
+/// `params` with the filters of its preset filled in where it sets none.
+pub fn apply(presets: &Presets, params: SearchParams) -> Result<SearchParams, (StatusCode, String)> {
+    let Some(name) = params.preset.as_deref() else {
+        return Ok(params);
+    };
+    let preset = presets
+        .get(name)
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("Unknown preset {:?}; see /api/presets", name)))?;
+    fill(params, preset).map(|(params, _)| params)
+}
+
+/// `params` with `search.defaults` filled in where neither it nor its preset
+/// sets a filter, and the fields that were.
+pub fn apply_defaults(defaults: &Map<String, Value>, params: SearchParams) -> Result<Filled, (StatusCode, String)> {
+    if params.no_defaults {
+        return Ok((params, vec![]));
+    }
+    fill(params, defaults)
+}
+
+fn fill(params: SearchParams, filters: &Map<String, Value>) -> Result<Filled, (StatusCode, String)> {
+    let Ok(Value::Object(mut merged)) = serde_json::to_value(&params) else {
+        return Ok((params, vec![]));
+    };
+    let mut filled = Vec::new();
+    for (field, value) in filters {
+        if merged.get(field).is_none_or(Value::is_null) {
+            merged.insert(field.clone(), value.clone());
+            filled.push(field.clone());
+        }
+    }
+    let params = serde_json::from_value(Value::Object(merged))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok((params, filled))
+}
+
+pub async fn list(State(state): State<AppState>) -> Json<Presets> {
+    Json(state.config.presets.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    const CONFIG: &str = r#"
+        [presets.dublin-2bed-budget]
+        location = "dublin"
+        bedrooms = 2
+        max_price = 2000
+    "#;
+
+    fn params(query: &str) -> SearchParams {
+        serde_json::from_str(query).unwrap()
+    }
+
+    #[test]
+    fn test_request_overrides_preset() {
+        let config = Config::from_toml(CONFIG).unwrap();
+        let applied = apply(&config.presets, params(r#"{"preset": "dublin-2bed-budget", "max_price": 2400}"#)).unwrap();
+        assert_eq!(applied.location.as_deref(), Some("dublin"));
+        assert_eq!((applied.bedrooms, applied.max_price), (Some(2), Some(2400.0)));
+
+        let unknown = apply(&config.presets, params(r#"{"preset": "galway"}"#)).unwrap_err();
+        assert_eq!(unknown.0, StatusCode::BAD_REQUEST);
+        assert!(apply(&config.presets, params("{}")).unwrap().location.is_none());
+    }
+
+    #[test]
+    fn test_defaults_fill_what_request_and_preset_leave_out() {
+        let config = Config::from_toml(&format!(
+            "[search.defaults]\nmin_photos = 1\nsort = \"newest\"\nlimit = 50\nbedrooms = 1\n{}",
+            CONFIG
+        ))
+        .unwrap();
+        let defaults = &config.search.defaults;
+        let preset = apply(&config.presets, params(r#"{"preset": "dublin-2bed-budget", "limit": 10}"#)).unwrap();
+        let (applied, filled) = apply_defaults(defaults, preset).unwrap();
+        assert_eq!((applied.bedrooms, applied.limit, applied.min_photos), (Some(2), Some(10), Some(1)));
+        assert_eq!(applied.sort, Some(crate::SortKey::Newest));
+        assert_eq!(filled, vec!["min_photos", "sort"]);
+
+        let (skipped, filled) = apply_defaults(defaults, params(r#"{"no_defaults": true}"#)).unwrap();
+        assert!(skipped.limit.is_none() && filled.is_empty());
+        assert!(Config::from_toml("[search.defaults]\nexplain = true").is_err());
+    }
+
+    #[test]
+    fn test_presets_are_validated() {
+        assert!(Config::from_toml("[presets.raw]\ninclude_raw = true").is_err());
+        assert!(Config::from_toml("[presets.beds]\nbedrooms = \"two\"").is_err());
+    }
+}
@@ -0,0 +1,144 @@
+//! Rent bands.
+//!
+//! Every listing is given a `price_band` when it is parsed, from the bounds in
+//! `search.price_bands`: with the default `[1000, 1500, 2000, 2500, 3000]` the
+//! bands are "<1000", "1000–1500", …, "3000+", each including its lower bound.
+//! The `price_band` search filter takes a comma-separated list of them, and
+//! `GET /api/rentals/facets` counts a search's matches per band, so band
+//! filters can be drawn with their counts from one request.
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::Serialize;
+use std::ops::ControlFlow;
+
+use crate::auth::Caller;
+use crate::commercial::Category;
+use crate::presets;
+use crate::state::AppState;
+use crate::{find_latest_parquet, rent_in_range, should_include_property, SearchParams};
+
+pub const DEFAULT_BOUNDS: [f64; 5] = [1000.0, 1500.0, 2000.0, 2500.0, 3000.0];
+
+/// `search.price_bands` as bounds: positive, in order and without repeats.
+pub fn normalize(bounds: &[f64]) -> Vec<f64> {
+    let mut bounds: Vec<f64> = bounds.iter().copied().filter(|b| b.is_finite() && *b > 0.0).collect();
+    bounds.sort_by(f64::total_cmp);
+    bounds.dedup();
+    bounds
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Band {
+    pub key: String,
+    /// Inclusive; `None` for the lowest band.
+    pub min: Option<f64>,
+    /// Exclusive; `None` for the highest band.
+    pub max: Option<f64>,
+}
+
+/// The bands between `bounds`, cheapest first.
+pub fn bands(bounds: &[f64]) -> Vec<Band> {
+    let edges: Vec<Option<f64>> = std::iter::once(None).chain(bounds.iter().copied().map(Some)).chain([None]).collect();
+    edges
+        .windows(2)
+        .map(|pair| {
+            let key = match (pair[0], pair[1]) {
+                (None, Some(max)) => format!("<{}", max),
+                (Some(min), Some(max)) => format!("{}–{}", min, max),
+                (Some(min), None) => format!("{}+", min),
+                (None, None) => "any".to_string(),
+            };
+            Band { key, min: pair[0], max: pair[1] }
+        })
+        .collect()
+}
+
+/// The band between `bounds` a monthly rent falls in.
+pub fn label(bounds: &[f64], amount: f64) -> String {
+    let index = bounds.iter().take_while(|bound| amount >= **bound).count();
+    bands(bounds).swap_remove(index).key
+}
+
+/// Whether a listing's band is one of the comma-separated `filter`. A plain
+/// hyphen is taken for the en dash in "1000–1500".
+pub fn matches(filter: &str, band: &str) -> bool {
+    filter.split(',').map(|term| term.trim().replace('-', "–")).any(|term| term == band)
+}
+
+#[derive(Debug, Serialize)]
+pub struct BandCount {
+    #[serde(flatten)]
+    pub band: Band,
+    pub count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Facets {
+    /// Listings matching every filter of the search.
+    pub total: usize,
+    /// Every band, including empty ones. Counts ignore the search's own
+    /// `price_band` filter so other bands still show what they would add.
+    pub price_band: Vec<BandCount>,
+}
+
+pub async fn facets(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<Facets>, (StatusCode, String)> {
+    let mut params = presets::apply(&state.config.presets, params)?;
+    params.category = Some(Category::effective(params.category, state.config.search.commercial));
+    let unbanded = SearchParams { price_band: None, ..params.clone() };
+    let hidden = state.hidden.for_caller(&caller);
+    let bands = bands(&state.config.search.price_bands);
+    let mut price_band: Vec<BandCount> = bands.into_iter().map(|band| BandCount { band, count: 0 }).collect();
+    let mut total = 0;
+
+    for source in state.config.select_sources(params.source.as_deref()) {
+        let Some(latest) = find_latest_parquet(&source.root(&state.config.data_path)) else {
+            continue;
+        };
+        state.cache.scan(source, &latest, 0, |_, property| {
+            if rent_in_range(property.price.amount)
+                && should_include_property(&property, &unbanded)
+                && !hidden.hides(&property)
+            {
+                if let Some(count) = price_band.iter_mut().find(|count| count.band.key == property.price_band) {
+                    count.count += 1;
+                }
+                if params.price_band.as_deref().is_none_or(|filter| matches(filter, &property.price_band)) {
+                    total += 1;
+                }
+            }
+            ControlFlow::Continue(())
+        });
+    }
+    Ok(Json(Facets { total, price_band }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bands() {
+        let keys: Vec<String> = bands(&DEFAULT_BOUNDS).into_iter().map(|band| band.key).collect();
+        assert_eq!(keys, ["<1000", "1000–1500", "1500–2000", "2000–2500", "2500–3000", "3000+"]);
+        assert_eq!(label(&DEFAULT_BOUNDS, 999.99), "<1000");
+        assert_eq!(label(&DEFAULT_BOUNDS, 1000.0), "1000–1500");
+        assert_eq!(label(&DEFAULT_BOUNDS, 2999.0), "2500–3000");
+        assert_eq!(label(&DEFAULT_BOUNDS, 4200.0), "3000+");
+        assert_eq!(label(&[1250.5], 1300.0), "1250.5+");
+        assert_eq!(label(&[], 1300.0), "any");
+        assert_eq!(normalize(&[1500.0, -1.0, 1000.0, 1500.0]), [1000.0, 1500.0]);
+    }
+
+    #[test]
+    fn test_filter_terms() {
+        assert!(matches("<1000, 1000-1500", "1000–1500"));
+        assert!(matches("3000+", "3000+"));
+        assert!(!matches("1500–2000", "1000–1500"));
+    }
+}
@@ -0,0 +1,147 @@
+//! Rent bounds per market segment.
+//!
+//! A rent that is plausible for one kind of listing isn't for another: €400 a
+//! month is fine for a room share, but a "3-bed in Dublin 4" at that price is
+//! almost certainly a room or a scam. Each `[[price_bounds]]` rule picks a
+//! segment by any of `bedrooms`, `property_type` (a filter term; see
+//! `property_type`) and `area` (anything the `location` filter accepts), and
+//! gives the `min` and/or `max` monthly rent expected in it:
+//!
+//! ```toml
+//! [[price_bounds]]
+//! name = "dublin-3bed"
+//! bedrooms = 3
+//! area = "dublin"
+//! min = 1200
+//! ```
+//!
+//! A listing with a rent outside the bounds of a rule whose segment it is in
+//! gets a `price_flag` naming the first such rule when parsed. Flagged
+//! listings are still served, and `price_flagged` filters on the flag, but
+//! `validate_price` leaves them out of rent statistics.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::{address, property_type, StandardizedProperty};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PriceRule {
+    /// Named in the flags the rule raises.
+    pub name: String,
+    /// Exact bedroom count; 0 for studios.
+    pub bedrooms: Option<i32>,
+    pub property_type: Option<String>,
+    pub area: Option<String>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl PriceRule {
+    fn covers(&self, property: &StandardizedProperty) -> bool {
+        let category = &property.property_category;
+        self.bedrooms.is_none_or(|bedrooms| property.bedrooms == Some(bedrooms))
+            && self.property_type.as_deref().is_none_or(|kind| property_type::matches(kind, category))
+            && self.area.as_deref().is_none_or(|area| address::in_location(&property.address.normalized_address, area))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Breach {
+    TooLow,
+    TooHigh,
+}
+
+/// The rule a listing's rent breaks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PriceFlag {
+    pub rule: String,
+    pub breach: Breach,
+    /// The rule's `min` or `max`.
+    pub bound: f64,
+}
+
+/// Checks every rule is named uniquely, bounds something and picks its
+/// segment with known property types.
+pub fn validate(rules: &[PriceRule]) -> Result<(), String> {
+    let mut names = HashSet::new();
+    for rule in rules {
+        if rule.name.trim().is_empty() || !names.insert(rule.name.as_str()) {
+            return Err(format!("Each [[price_bounds]] rule needs a unique name; {:?} isn't", rule.name));
+        }
+        match (rule.min, rule.max) {
+            (None, None) => return Err(format!("Price bound {:?} sets neither min nor max", rule.name)),
+            (Some(min), Some(max)) if min > max => {
+                return Err(format!("Price bound {:?} has min above max", rule.name));
+            }
+            _ => {}
+        }
+        let unknown = rule.property_type.as_deref().map(property_type::unknown_terms).unwrap_or_default();
+        if !unknown.is_empty() {
+            return Err(format!("Price bound {:?} has unknown property_type {}", rule.name, unknown.join(", ")));
+        }
+    }
+    Ok(())
+}
+
+/// The first of `rules` `property`'s rent breaks. Listings without a rent
+/// break none.
+pub fn check(rules: &[PriceRule], property: &StandardizedProperty) -> Option<PriceFlag> {
+    let rent = property.price.amount;
+    if rent <= 0.0 {
+        return None;
+    }
+    rules.iter().filter(|rule| rule.covers(property)).find_map(|rule| {
+        let flag = |breach, bound| PriceFlag { rule: rule.name.clone(), breach, bound };
+        match (rule.min, rule.max) {
+            (Some(min), _) if rent < min => Some(flag(Breach::TooLow, min)),
+            (_, Some(max)) if rent > max => Some(flag(Breach::TooHigh, max)),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::test_utils::listing;
+
+    const CONFIG: &str = r#"
+        [[price_bounds]]
+        name = "dublin-3bed"
+        bedrooms = 3
+        area = "dublin"
+        min = 1200
+
+        [[price_bounds]]
+        name = "rooms"
+        property_type = "share"
+        max = 1500
+    "#;
+
+    fn rental(beds: i32, property_type: &str, area: &str, rent: f64) -> StandardizedProperty {
+        let mut property = listing("daft", "1");
+        property.bedrooms = Some(beds);
+        property.property_category = property_type::classify(property_type).to_string();
+        property.address.normalized_address = address::normalize(area);
+        property.price.amount = rent;
+        property
+    }
+
+    #[test]
+    fn test_rents_are_checked_against_their_segment() {
+        let rules = Config::from_toml(CONFIG).unwrap().price_bounds;
+        let flag = check(&rules, &rental(3, "House", "Dublin 4", 400.0)).unwrap();
+        assert_eq!((flag.rule.as_str(), flag.breach, flag.bound), ("dublin-3bed", Breach::TooLow, 1200.0));
+        // A room share at the same rent is fine, and so is a 3-bed in Cork
+        assert_eq!(check(&rules, &rental(1, "Room to rent", "Dublin 4", 400.0)), None);
+        assert_eq!(check(&rules, &rental(3, "House", "Cork", 400.0)), None);
+        assert_eq!(check(&rules, &rental(1, "Shared", "Galway", 2500.0)).map(|f| f.breach), Some(Breach::TooHigh));
+        assert_eq!(check(&rules, &rental(3, "House", "Dublin 4", 0.0)), None);
+
+        assert!(Config::from_toml("[[price_bounds]]\nname = \"empty\"").is_err());
+        assert!(Config::from_toml("[[price_bounds]]\nname = \"x\"\nproperty_type = \"castle\"\nmin = 1").is_err());
+    }
+}
@@ -0,0 +1,302 @@
+//! A listing's rent over time.
+//!
+//! `GET /api/rentals/{id}/history` (also at `/price_history`) walks the
+//! listing's source snapshots oldest first and reads its entries in each one's
+//! delta (see `deltas`), building the deltas that are missing by diffing each
+//! snapshot with the one before it: the first point is the rent it appeared
+//! at, or the rent before its first change when it predates the snapshots, and
+//! every price change adds a point with the difference.
+//!
+//! The same changes fill `price.price_changes` of the listings search, lookup
+//! and detail responses return, each with the day it was seen and whether the
+//! rent went up or down. They are indexed per source when its latest snapshot
+//! is warmed or refreshed, so only the snapshots that landed since are read.
+
+use axum::extract::{self, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::config::{Config, SourceConfig};
+use crate::deltas::{ChangeKind, SnapshotDelta};
+use crate::id_index::listing_time;
+use crate::ids;
+use crate::state::AppState;
+use crate::{list_snapshots, PriceChange, StandardizedProperty};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PricePoint {
+    pub date: NaiveDate,
+    pub amount: f64,
+    /// Difference from the previous point; `None` for the first.
+    pub change: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PriceHistory {
+    pub property_id: String,
+    pub currency: String,
+    pub points: Vec<PricePoint>,
+    /// Date of the snapshot it was last removed in, while it stays delisted.
+    pub delisted: Option<NaiveDate>,
+}
+
+impl PriceHistory {
+    fn push(&mut self, date: NaiveDate, amount: f64) {
+        let change = match self.points.last() {
+            Some(last) if last.amount == amount => return,
+            Some(last) => Some(amount - last.amount),
+            None => None,
+        };
+        self.points.push(PricePoint { date, amount, change });
+    }
+
+    /// Adds the listing's entry in one day's delta, where it has one of
+    /// `ids`.
+    fn record(&mut self, delta: &SnapshotDelta, ids: &[&str]) {
+        let Some(date) = delta.date else {
+            return;
+        };
+        let Some(change) = delta.changes.iter().find(|change| ids.contains(&change.property_id.as_str())) else {
+            return;
+        };
+        self.currency.clone_from(&change.listing.price.currency);
+        match change.kind {
+            ChangeKind::New => {
+                self.delisted = None;
+                self.push(date, change.listing.price.amount);
+            }
+            ChangeKind::Changed => {
+                self.delisted = None;
+                if !change.fields.iter().any(|field| field == "price") {
+                    return;
+                }
+                if let (true, Some(previous)) = (self.points.is_empty(), change.previous_price) {
+                    self.push(delta.previous_date.unwrap_or(date), previous);
+                }
+                self.push(date, change.listing.price.amount);
+            }
+            ChangeKind::Removed => self.delisted = Some(date),
+        }
+    }
+}
+
+/// The price series of `property_id`, whose current listing is `current` when
+/// it is still listed, as of `today`. `None` when nothing is known about it.
+pub fn build(
+    config: &Config,
+    property_id: &str,
+    current: Option<&StandardizedProperty>,
+    today: NaiveDate,
+) -> Option<PriceHistory> {
+    // Deltas written before canonical ids have the legacy one
+    let legacy = ids::legacy(property_id);
+    let source = match current {
+        Some(listing) => config.resolve_source(&listing.source)?,
+        // Delisted: legacy ids are "<source>_<source_id>"
+        None => config.sources.iter().find(|source| {
+            legacy.strip_prefix(source.name.as_str()).is_some_and(|rest| rest.starts_with('_'))
+        })?,
+    };
+    let mut history = PriceHistory {
+        property_id: property_id.to_string(),
+        currency: current.map_or_else(|| "EUR".to_string(), |listing| listing.price.currency.clone()),
+        points: vec![],
+        delisted: None,
+    };
+    for (_, file) in list_snapshots(&source.root(&config.data_path)) {
+        if let Some(delta) = SnapshotDelta::load_or_build(config, source, &file) {
+            history.record(&delta, &[property_id, &legacy]);
+        }
+    }
+    // Listed since before the first delta and never changed price
+    if let (true, Some(listing)) = (history.points.is_empty(), current) {
+        let date = listing_time(&listing.created_date).map_or(today, |t| t.date());
+        history.push(date, listing.price.amount);
+    }
+    (!history.points.is_empty() || history.delisted.is_some()).then_some(history)
+}
+
+pub async fn price_history(
+    State(state): State<AppState>,
+    extract::Path(property_id): extract::Path<String>,
+) -> Result<Json<PriceHistory>, (StatusCode, String)> {
+    let history = tokio::task::spawn_blocking(move || {
+        let current = state.store.get(std::slice::from_ref(&property_id)).into_iter().next();
+        build(&state.config, &property_id, current.as_ref(), state.clock.today())
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    history.map(Json).ok_or((StatusCode::NOT_FOUND, String::new()))
+}
+
+/// The price changes in one day's delta, by the property id it has there.
+fn changes_in(delta: &SnapshotDelta) -> impl Iterator<Item = (&str, PriceChange)> {
+    let date = delta.date.map(|date| date.to_string()).unwrap_or_default();
+    delta.changes.iter().filter_map(move |change| {
+        let previous = change.previous_price.filter(|_| change.kind == ChangeKind::Changed)?;
+        let amount = change.listing.price.amount;
+        let direction = match amount.total_cmp(&previous) {
+            std::cmp::Ordering::Greater => "up",
+            std::cmp::Ordering::Less => "down",
+            std::cmp::Ordering::Equal => return None,
+        };
+        let price_change = PriceChange { date: date.clone(), amount, direction: direction.to_string() };
+        Some((change.property_id.as_str(), price_change))
+    })
+}
+
+/// One source's price changes up to the snapshot `through`.
+struct Indexed {
+    through: PathBuf,
+    changes: Arc<HashMap<String, Vec<PriceChange>>>,
+}
+
+/// Price changes of every listing, per source.
+#[derive(Default)]
+pub struct PriceChanges {
+    sources: Mutex<HashMap<String, Indexed>>,
+}
+
+impl PriceChanges {
+    /// `source`'s price changes up to its latest snapshot. Only snapshots
+    /// after the last one indexed are read.
+    fn for_source(&self, config: &Config, source: &SourceConfig) -> Arc<HashMap<String, Vec<PriceChange>>> {
+        let snapshots = list_snapshots(&source.root(&config.data_path));
+        let mut sources = self.sources.lock().unwrap();
+        let indexed = sources.get(&source.name);
+        let Some((_, latest)) = snapshots.last() else {
+            return Arc::default();
+        };
+        if let Some(indexed) = indexed.filter(|indexed| &indexed.through == latest) {
+            return indexed.changes.clone();
+        }
+        let start = indexed
+            .and_then(|indexed| snapshots.iter().position(|(_, file)| *file == indexed.through))
+            .map_or(0, |position| position + 1);
+        let mut changes = match indexed.filter(|_| start > 0) {
+            Some(indexed) => (*indexed.changes).clone(),
+            None => HashMap::new(),
+        };
+        for (_, file) in &snapshots[start..] {
+            if let Some(delta) = SnapshotDelta::load_or_build(config, source, file) {
+                for (property_id, change) in changes_in(&delta) {
+                    changes.entry(property_id.to_string()).or_insert_with(Vec::new).push(change);
+                }
+            }
+        }
+        let changes = Arc::new(changes);
+        sources.insert(source.name.clone(), Indexed { through: latest.clone(), changes: changes.clone() });
+        changes
+    }
+
+    /// Indexes `source` once its snapshot `file` is the latest.
+    pub fn prepare(&self, config: &Config, source: &SourceConfig, file: &Path) {
+        let indexed = self.sources.lock().unwrap().get(&source.name).is_some_and(|indexed| indexed.through == file);
+        if !indexed {
+            self.for_source(config, source);
+        }
+    }
+
+    /// Fills in `price.price_changes` of each of `properties`, oldest first.
+    pub fn attach_all(&self, config: &Config, properties: &mut [StandardizedProperty]) {
+        let mut by_source: HashMap<String, Arc<HashMap<String, Vec<PriceChange>>>> = HashMap::new();
+        for property in properties {
+            let Some(source) = config.resolve_source(&property.source) else {
+                continue;
+            };
+            let changes = by_source
+                .entry(source.name.clone())
+                .or_insert_with(|| self.for_source(config, source));
+            // Deltas written before canonical ids have the legacy one
+            let found = property.ids().find_map(|id| changes.get(id)).cloned();
+            property.price.price_changes = found.unwrap_or_default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deltas::diff;
+    use crate::load_properties;
+    use crate::test_utils::{listing, standardized_batch, temp_dir, write_parquet};
+
+    fn delta(date: &str, previous: Vec<StandardizedProperty>, current: Vec<StandardizedProperty>) -> SnapshotDelta {
+        let mut delta: SnapshotDelta = serde_json::from_value(serde_json::json!({
+            "source": "daft",
+            "file": "a.parquet",
+            "file_len": 0,
+            "modified": 0,
+            "date": date,
+            "previous_date": null,
+            "changes": [],
+        }))
+        .unwrap();
+        delta.changes = diff(previous, current);
+        delta
+    }
+
+    #[test]
+    fn test_series_from_deltas() {
+        let rental = |rent: f64| {
+            let mut property = listing("daft", "1");
+            property.price.amount = rent;
+            property
+        };
+        let mut history =
+            PriceHistory { property_id: "daft_1".to_string(), currency: String::new(), points: vec![], delisted: None };
+        for delta in [
+            delta("2024-11-01", vec![], vec![rental(2000.0)]),
+            delta("2024-11-02", vec![rental(2000.0)], vec![rental(2000.0)]),
+            delta("2024-11-03", vec![rental(2000.0)], vec![rental(1900.0)]),
+            delta("2024-11-04", vec![rental(1900.0)], vec![]),
+        ] {
+            history.record(&delta, &["daft_1"]);
+        }
+
+        let points: Vec<_> = history.points.iter().map(|p| (p.date.to_string(), p.amount, p.change)).collect();
+        assert_eq!(
+            points,
+            [("2024-11-01".to_string(), 2000.0, None), ("2024-11-03".to_string(), 1900.0, Some(-100.0))]
+        );
+        assert_eq!(history.delisted, "2024-11-04".parse().ok());
+        assert_eq!(history.currency, "EUR");
+    }
+
+    #[test]
+    fn test_price_changes_are_indexed_from_snapshots() {
+        let data = temp_dir("price_changes");
+        let file = |day: u32| data.join(format!("processed/rent_ie/2024/11/{:02}/rent_ie_120000.parquet", day));
+        // Rents follow row order, so swapping rows swaps the two rents
+        write_parquet(&file(1), &standardized_batch(&["1", "2"]));
+        write_parquet(&file(2), &standardized_batch(&["2", "1"]));
+        let config = Config::from_toml(&format!("data_path = {:?}\n[[sources]]\nname = \"rent_ie\"", data)).unwrap();
+        let source = config.resolve_source("rent_ie").unwrap();
+        let index = PriceChanges::default();
+        let changes = |day: u32| {
+            let mut listings = load_properties(source, &file(day));
+            index.attach_all(&config, &mut listings);
+            let changes = listings.iter().flat_map(|p| {
+                p.price.price_changes.iter().map(|c| format!("{} {} {} {}", p.source_id, c.date, c.direction, c.amount))
+            });
+            changes.collect::<Vec<_>>()
+        };
+        assert_eq!(changes(2), ["2 2024-11-02 down 1000", "1 2024-11-02 up 1100"]);
+
+        write_parquet(&file(3), &standardized_batch(&["1", "2"]));
+        assert_eq!(
+            changes(3),
+            [
+                "1 2024-11-02 up 1100",
+                "1 2024-11-03 down 1000",
+                "2 2024-11-02 down 1000",
+                "2 2024-11-03 up 1100"
+            ]
+        );
+    }
+}
@@ -0,0 +1,37 @@
+//! Advertised rent ranges.
+//!
+//! New developments and some agencies list a range ("€1,800 - €2,200")
+//! rather than one rent. Such listings keep the bounds in `price.min` and
+//! `price.max`, and `price.amount`, which every filter and statistic uses, is
+//! the point of the range picked by `search.price_range_point`: the midpoint
+//! by default.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RangePoint {
+    Min,
+    #[default]
+    Midpoint,
+    Max,
+}
+
+impl RangePoint {
+    /// The amount a listing advertised at `min` to `max` is filtered and
+    /// counted at.
+    pub fn pick(self, min: f64, max: f64) -> f64 {
+        match self {
+            RangePoint::Min => min,
+            RangePoint::Midpoint => (min + max) / 2.0,
+            RangePoint::Max => max,
+        }
+    }
+}
+
+/// Whether parsed bounds look like a real range rather than two unrelated
+/// numbers ("2-bed €1,800"): ordered, and the top no more than three times
+/// the bottom.
+pub fn plausible(min: f64, max: f64) -> bool {
+    min > 0.0 && min <= max && max <= min * 3.0
+}
@@ -0,0 +1,247 @@
+//! Reading rents out of advertised price text.
+//!
+//! Sources write prices every way people do: "€1,850", "€1.850" and
+//! "1 850 €" are all the same rent, "€1,2k" is 1200, and the text often
+//! carries other numbers ("2 bed", "available 2024-11-01"). The text is split
+//! into number tokens, each read with the grouping and decimal conventions it
+//! uses, and the rent is picked from them:
+//! - a number next to a currency sign or code, else
+//! - the first number of at least `MIN_UNMARKED`, smaller ones being counts,
+//!   else the first number.
+//!
+//! Two price-like numbers joined only by "-", "–", "—" or "to" make a range;
+//! see `price_range`.
+
+use crate::price_range::{self, RangePoint};
+
+/// Numbers without a currency marker below this are taken for counts
+/// ("2 bed", "12 months") rather than rents.
+const MIN_UNMARKED: f64 = 100.0;
+
+const CURRENCY_SIGNS: [char; 3] = ['€', '£', '$'];
+const CURRENCY_CODES: [&str; 3] = ["eur", "gbp", "usd"];
+const RANGE_WORDS: [&str; 4] = ["-", "–", "—", "to"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Token {
+    value: f64,
+    /// Byte range of the token in the text, suffix included.
+    start: usize,
+    end: usize,
+    /// Next to a currency sign or code.
+    currency: bool,
+}
+
+impl Token {
+    fn price_like(&self) -> bool {
+        self.currency || self.value >= MIN_UNMARKED
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParsedPrice {
+    /// The midpoint of a range; listings move it to the configured point
+    /// once parsed.
+    pub amount: f64,
+    /// Bounds when the text gives a range.
+    pub range: Option<(f64, f64)>,
+}
+
+fn is_space(c: char) -> bool {
+    matches!(c, ' ' | '\u{a0}' | '\u{202f}' | '\'')
+}
+
+/// Reads one number from its digit groups and the separators between them.
+/// A lone "." or "," is a thousands separator before exactly three digits
+/// ("1.200") and a decimal point otherwise ("1,2"), except with a k suffix,
+/// where it is always a decimal point ("1.250k"). When both appear, the last
+/// one is the decimal point ("1.234,56").
+fn interpret(groups: &[&str], separators: &[char], thousands: bool) -> Option<f64> {
+    let marks: Vec<(usize, char)> =
+        separators.iter().copied().enumerate().filter(|(_, c)| matches!(c, ',' | '.')).collect();
+    let decimal_at = match marks.as_slice() {
+        [] => None,
+        [.., (last, mark)] if marks.iter().any(|(_, c)| c != mark) => Some(*last),
+        [(only, _)] if thousands || groups[*only + 1].len() != 3 => Some(*only),
+        _ => None,
+    };
+    let mut number = String::new();
+    for (i, group) in groups.iter().enumerate() {
+        if i > 0 && decimal_at == Some(i - 1) {
+            number.push('.');
+        }
+        number.push_str(group);
+    }
+    let value: f64 = number.parse().ok()?;
+    Some(if thousands { value * 1000.0 } else { value })
+}
+
+fn has_currency_before(text: &str) -> bool {
+    let before = text.trim_end_matches(is_space).to_lowercase();
+    before.ends_with(CURRENCY_SIGNS) || CURRENCY_CODES.iter().any(|code| before.ends_with(code))
+}
+
+fn has_currency_after(text: &str) -> bool {
+    let after = text.trim_start_matches(is_space).to_lowercase();
+    after.starts_with(CURRENCY_SIGNS) || CURRENCY_CODES.iter().any(|code| after.starts_with(code))
+}
+
+fn tokens(text: &str) -> Vec<Token> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let at = |i: usize| chars.get(i).map(|(_, c)| *c);
+    let offset = |i: usize| chars.get(i).map_or(text.len(), |(offset, _)| *offset);
+    let digit_at = |i: usize| at(i).is_some_and(|c| c.is_ascii_digit());
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        // Digits inside a word ("D6", "B2") aren't numbers
+        if !digit_at(i) || (i > 0 && at(i - 1).is_some_and(char::is_alphabetic)) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let mut groups = Vec::new();
+        let mut separators = Vec::new();
+        loop {
+            let group_start = i;
+            while digit_at(i) {
+                i += 1;
+            }
+            groups.push(&text[offset(group_start)..offset(i)]);
+            let Some(separator) = at(i).filter(|c| matches!(c, ',' | '.') || is_space(*c)) else {
+                break;
+            };
+            // Spaces only group thousands: exactly three digits must follow
+            let grouped = (1..=3).all(|k| digit_at(i + k)) && !digit_at(i + 4);
+            if !digit_at(i + 1) || (is_space(separator) && !grouped) {
+                break;
+            }
+            separators.push(separator);
+            i += 1;
+        }
+        let suffix = if at(i) == Some(' ') { i + 1 } else { i };
+        let thousands = matches!(at(suffix), Some('k' | 'K')) && !at(suffix + 1).is_some_and(char::is_alphanumeric);
+        if thousands {
+            i = suffix + 1;
+        }
+        if let Some(value) = interpret(&groups, &separators, thousands) {
+            let (start, end) = (offset(start), offset(i));
+            let currency = has_currency_before(&text[..start]) || has_currency_after(&text[end..]);
+            tokens.push(Token { value, start, end, currency });
+        }
+    }
+    tokens
+}
+
+/// Whether only currency markers, spaces and one range word stand between two
+/// numbers.
+fn joins_range(between: &str) -> bool {
+    let mut rest = between.to_lowercase();
+    for code in CURRENCY_CODES {
+        rest = rest.replace(code, "");
+    }
+    let rest: String = rest.chars().filter(|c| !CURRENCY_SIGNS.contains(c) && !c.is_whitespace()).collect();
+    RANGE_WORDS.contains(&rest.as_str())
+}
+
+/// The rent in `text`, or `None` when it has no positive number.
+pub fn parse(text: &str) -> Option<ParsedPrice> {
+    let tokens = tokens(text);
+    for pair in tokens.windows(2) {
+        let (low, high) = (pair[0], pair[1]);
+        if low.price_like()
+            && joins_range(&text[low.end..high.start])
+            && price_range::plausible(low.value, high.value)
+        {
+            return Some(ParsedPrice {
+                amount: RangePoint::Midpoint.pick(low.value, high.value),
+                range: Some((low.value, high.value)),
+            });
+        }
+    }
+    let token = tokens
+        .iter()
+        .find(|t| t.currency)
+        .or_else(|| tokens.iter().find(|t| t.value >= MIN_UNMARKED))
+        .or(tokens.first())?;
+    (token.value > 0.0).then_some(ParsedPrice { amount: token.value, range: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_amounts() {
+        let corpus: &[(&str, Option<f64>)] = &[
+            // Grouping and decimals
+            ("€1,850", Some(1850.0)),
+            ("€1.850", Some(1850.0)),
+            ("€1 850", Some(1850.0)),
+            ("€1\u{a0}850", Some(1850.0)),
+            ("1\u{202f}850 €", Some(1850.0)),
+            ("€1'850", Some(1850.0)),
+            ("€1,234,567", Some(1234567.0)),
+            ("€1.234.567", Some(1234567.0)),
+            ("€1,850.00", Some(1850.0)),
+            ("€1.850,00", Some(1850.0)),
+            ("€1850.50", Some(1850.5)),
+            ("€1850,5", Some(1850.5)),
+            ("€950", Some(950.0)),
+            ("1850", Some(1850.0)),
+            // Thousands suffix
+            ("€1,2k", Some(1200.0)),
+            ("€1.2k", Some(1200.0)),
+            ("€1.25K per month", Some(1250.0)),
+            ("€2k", Some(2000.0)),
+            ("€1.250k", Some(1250.0)),
+            ("€2 k / month", Some(2000.0)),
+            // Currency codes and placement
+            ("EUR 1.200", Some(1200.0)),
+            ("1 200 EUR", Some(1200.0)),
+            ("£1,100 pcm", Some(1100.0)),
+            // Other numbers in the text
+            ("€1,850 per month (2 bed)", Some(1850.0)),
+            ("2 bed apartment, €1,850 pm", Some(1850.0)),
+            ("2 bed, 1850 per month", Some(1850.0)),
+            ("€2,000 (available 2024-11-01)", Some(2000.0)),
+            ("Dublin 6 - €1,900", Some(1900.0)),
+            ("D6 1,900", Some(1900.0)),
+            ("1 kitchen, €1,900", Some(1900.0)),
+            ("€1,850, 1-2 weeks", Some(1850.0)),
+            ("12 month lease 1650", Some(1650.0)),
+            // Nothing to read
+            ("Price on application", None),
+            ("€0", None),
+            ("", None),
+        ];
+        let failures: Vec<String> = corpus
+            .iter()
+            .filter_map(|(text, expected)| {
+                let amount = parse(text).map(|p| p.amount);
+                (amount != *expected).then(|| format!("{:?}: {:?}, expected {:?}", text, amount, expected))
+            })
+            .collect();
+        assert!(failures.is_empty(), "{:#?}", failures);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let corpus: &[(&str, Option<(f64, f64)>)] = &[
+            ("€1,800 - €2,200", Some((1800.0, 2200.0))),
+            ("€1,800–2,200 per month", Some((1800.0, 2200.0))),
+            ("From €1,800 to €2,200", Some((1800.0, 2200.0))),
+            ("€1.8k—€2.2k", Some((1800.0, 2200.0))),
+            ("1.800 € - 2.200 €", Some((1800.0, 2200.0))),
+            ("1800-2200", Some((1800.0, 2200.0))),
+            ("2-bed €1,800", None),
+            ("€1,850, 1-2 weeks", None),
+            ("€2,000 (available 2024-11-01)", None),
+            ("€2,200 - €1,800", None),
+        ];
+        for (text, expected) in corpus {
+            assert_eq!(parse(text).and_then(|p| p.range), *expected, "{:?}", text);
+        }
+        assert_eq!(parse("€1,800 - €2,200").unwrap().amount, 2000.0);
+    }
+}
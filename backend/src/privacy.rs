@@ -0,0 +1,187 @@
+//! Agent contact-detail redaction.
+//!
+//! Listings carry the letting agent's phone and email. Only callers with the
+//! `agents:read` scope receive them; everyone else gets the agent's name and
+//! office address. Independently of scope, an agent can ask for their personal
+//! data to be suppressed: `POST /api/admin/agents/suppress` records the request
+//! and every later response blanks the matching agent's name, phone and email.
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use crate::auth::{Caller, SCOPE_ADMIN, SCOPE_AGENTS_READ};
+use crate::state::AppState;
+use crate::{raw, Agent, StandardizedProperty};
+
+/// Identifies an agent by any of their contact details. Matching ignores case
+/// and, for phone numbers, everything but digits.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Suppression {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    #[serde(default = "Utc::now")]
+    pub requested_at: DateTime<Utc>,
+}
+
+fn digits(phone: &str) -> String {
+    phone.chars().filter(|c| c.is_ascii_digit()).collect()
+}
+
+impl Suppression {
+    fn is_empty(&self) -> bool {
+        [&self.name, &self.email, &self.phone]
+            .iter()
+            .all(|field| field.as_deref().is_none_or(|value| value.trim().is_empty()))
+    }
+
+    fn matches(&self, agent: &Agent) -> bool {
+        let same = |wanted: &Option<String>, actual: &str| {
+            wanted.as_deref().is_some_and(|wanted| {
+                !wanted.trim().is_empty() && wanted.trim().eq_ignore_ascii_case(actual.trim())
+            })
+        };
+        let same_phone = self.phone.as_deref().is_some_and(|phone| {
+            let phone = digits(phone);
+            !phone.is_empty() && phone == digits(&agent.phone)
+        });
+        same(&self.name, &agent.name) || same(&self.email, &agent.email) || same_phone
+    }
+}
+
+pub struct AgentPrivacy {
+    path: Option<PathBuf>,
+    suppressions: RwLock<Vec<Suppression>>,
+}
+
+impl AgentPrivacy {
+    /// Loads recorded suppressions from `path`. `None` keeps them in memory only.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let suppressions = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        AgentPrivacy { path, suppressions: RwLock::new(suppressions) }
+    }
+
+    pub fn suppress(&self, suppression: Suppression) -> Result<(), String> {
+        let mut suppressions = self.suppressions.write().unwrap();
+        suppressions.push(suppression);
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+            let contents = serde_json::to_string_pretty(&*suppressions).map_err(|e| e.to_string())?;
+            fs::write(path, contents).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Removes whatever contact details `caller` may not see.
+    pub fn redact(&self, property: &mut StandardizedProperty, caller: &Caller) {
+        let Some(agent) = &mut property.agent else {
+            return;
+        };
+        if self.suppressions.read().unwrap().iter().any(|s| s.matches(agent)) {
+            agent.name.clear();
+            agent.phone.clear();
+            agent.email.clear();
+            if let Some(raw) = &mut property.raw {
+                raw::redact_agent(raw, true);
+            }
+            return;
+        }
+        if !caller.has_scope(SCOPE_AGENTS_READ) {
+            agent.phone.clear();
+            agent.email.clear();
+            if let Some(raw) = &mut property.raw {
+                raw::redact_agent(raw, false);
+            }
+        }
+    }
+
+    pub fn redact_all(&self, properties: &mut [StandardizedProperty], caller: &Caller) {
+        for property in properties {
+            self.redact(property, caller);
+        }
+    }
+}
+
+pub async fn suppress_agent(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(suppression): Json<Suppression>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    if suppression.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Give at least one of name, email or phone".to_string()));
+    }
+    info!("Suppressing agent data on request of {:?}", caller.name);
+    let outcome = state.privacy.suppress(suppression.clone());
+    state.audit.record(&caller, "agents.suppress", &suppression, &outcome);
+    outcome.map_err(|e| {
+        warn!("Could not record agent suppression: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Could not record suppression".to_string())
+    })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{listing, temp_dir};
+
+    fn with_agent(name: &str, phone: &str) -> StandardizedProperty {
+        let mut property = listing("myhome", "1");
+        property.agent = Some(Agent {
+            name: name.to_string(),
+            phone: phone.to_string(),
+            email: "lettings@example.ie".to_string(),
+            address: "1 Main Street, Galway".to_string(),
+            licence: String::new(),
+        });
+        property
+    }
+
+    #[test]
+    fn test_contact_details_need_scope() {
+        let privacy = AgentPrivacy::open(None);
+        let mut anonymous = with_agent("Example Lettings", "01 234 5678");
+        privacy.redact(&mut anonymous, &Caller::default());
+        let agent = anonymous.agent.unwrap();
+        assert_eq!(agent.name, "Example Lettings");
+        assert!(agent.phone.is_empty() && agent.email.is_empty());
+
+        let mut partner = with_agent("Example Lettings", "01 234 5678");
+        privacy.redact(&mut partner, &Caller::with_scopes(None, &[SCOPE_AGENTS_READ.to_string()]));
+        assert_eq!(partner.agent.unwrap().phone, "01 234 5678");
+    }
+
+    #[test]
+    fn test_suppression_applies_to_every_caller_and_persists() {
+        let path = temp_dir("privacy").join("suppressed.json");
+        AgentPrivacy::open(Some(path.clone()))
+            .suppress(Suppression { name: None, email: None, phone: Some("+353 1 234-5678".into()), requested_at: Utc::now() })
+            .unwrap();
+
+        let privacy = AgentPrivacy::open(Some(path));
+        let reader = Caller::with_scopes(None, &[SCOPE_AGENTS_READ.to_string()]);
+        let mut property = with_agent("Jane Murphy", "3531 2345678");
+        privacy.redact(&mut property, &reader);
+        let agent = property.agent.unwrap();
+        assert!(agent.name.is_empty() && agent.phone.is_empty() && agent.email.is_empty());
+        assert_eq!(agent.address, "1 Main Street, Galway");
+
+        let mut other = with_agent("Other Agent", "01 999 0000");
+        privacy.redact(&mut other, &reader);
+        assert_eq!(other.agent.unwrap().name, "Other Agent");
+    }
+}
@@ -0,0 +1,196 @@
+//! Property-type taxonomy.
+//!
+//! Sources spell types every way ("Semi-Detached House", "semi detached",
+//! "House Share"), so each listing is also given a `property_category`: a node
+//! of a fixed hierarchy, classified from the source's text when the listing is
+//! parsed.
+//!
+//! ```text
+//! residential
+//! ├── apartment: studio, duplex, penthouse
+//! ├── house: detached, semi_detached, terraced, end_of_terrace, bungalow, townhouse, cottage
+//! └── share: room
+//! commercial: office, retail, industrial
+//! unknown
+//! ```
+//!
+//! The `property_type` search filter takes comma-separated node keys (or
+//! anything `classify` recognises, like "flat") and matches a listing whose
+//! category is one of them or below one of them, so `house` matches every
+//! kind of house. `GET /api/property-types` lists the nodes.
+
+use axum::Json;
+use serde::Serialize;
+
+pub const UNKNOWN: &str = "unknown";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Node {
+    pub key: &'static str,
+    pub parent: Option<&'static str>,
+    pub label: &'static str,
+}
+
+const fn node(key: &'static str, parent: Option<&'static str>, label: &'static str) -> Node {
+    Node { key, parent, label }
+}
+
+pub const NODES: &[Node] = &[
+    node("residential", None, "Residential"),
+    node("apartment", Some("residential"), "Apartment"),
+    node("studio", Some("apartment"), "Studio"),
+    node("duplex", Some("apartment"), "Duplex"),
+    node("penthouse", Some("apartment"), "Penthouse"),
+    node("house", Some("residential"), "House"),
+    node("detached", Some("house"), "Detached house"),
+    node("semi_detached", Some("house"), "Semi-detached house"),
+    node("terraced", Some("house"), "Terraced house"),
+    node("end_of_terrace", Some("house"), "End-of-terrace house"),
+    node("bungalow", Some("house"), "Bungalow"),
+    node("townhouse", Some("house"), "Townhouse"),
+    node("cottage", Some("house"), "Cottage"),
+    node("share", Some("residential"), "Shared accommodation"),
+    node("room", Some("share"), "Room to rent"),
+    node("commercial", None, "Commercial"),
+    node("office", Some("commercial"), "Office"),
+    node("retail", Some("commercial"), "Retail unit"),
+    node("industrial", Some("commercial"), "Industrial unit"),
+    node(UNKNOWN, None, "Unknown"),
+];
+
+/// Words in a source's type, checked in order, and the node they mean. More
+/// specific words come first: "semi-detached house" is `semi_detached`, not
+/// `detached` or `house`.
+const RULES: &[(&[&str], &str)] = &[
+    (&["office", "offices", "office space"], "office"),
+    (&["retail", "shop", "restaurant", "commercial unit"], "retail"),
+    (&["industrial", "warehouse", "factory", "workshop"], "industrial"),
+    (&["studio", "bedsit"], "studio"),
+    (&["penthouse"], "penthouse"),
+    (&["duplex"], "duplex"),
+    (&["room", "share", "shared"], "room"),
+    (&["semi"], "semi_detached"),
+    (&["end of terrace"], "end_of_terrace"),
+    (&["terrace", "terraced"], "terraced"),
+    (&["detached"], "detached"),
+    (&["bungalow"], "bungalow"),
+    (&["townhouse", "town house", "mews"], "townhouse"),
+    (&["cottage"], "cottage"),
+    (&["apartment", "flat", "maisonette"], "apartment"),
+    (&["house", "home"], "house"),
+];
+
+/// Lowercase words separated by single spaces: "Semi-Detached  House" is
+/// "semi detached house".
+fn words(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The node a source's property type falls under, `UNKNOWN` when none fits.
+pub fn classify(property_type: &str) -> &'static str {
+    let text = format!(" {} ", words(property_type));
+    RULES
+        .iter()
+        .find(|(phrases, _)| phrases.iter().any(|phrase| text.contains(&format!(" {} ", phrase))))
+        .map_or(UNKNOWN, |(_, key)| *key)
+}
+
+fn find(key: &str) -> Option<&'static Node> {
+    NODES.iter().find(|node| node.key == key)
+}
+
+/// A filter term as a node key: a key ("semi_detached"), a label
+/// ("Semi-detached house") or anything `classify` recognises ("flat").
+fn resolve(term: &str) -> Option<&'static str> {
+    let key = words(term).replace(' ', "_");
+    find(&key)
+        .or_else(|| NODES.iter().find(|node| words(node.label).replace(' ', "_") == key))
+        .map(|node| node.key)
+        .or_else(|| Some(classify(term)).filter(|key| *key != UNKNOWN))
+}
+
+/// The terms of a comma-separated filter that aren't nodes.
+pub fn unknown_terms(filter: &str) -> Vec<String> {
+    filter
+        .split(',')
+        .map(str::trim)
+        .filter(|term| !term.is_empty() && resolve(term).is_none())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Whether `category` is one of the filter's nodes or below one of them.
+pub fn matches(filter: &str, category: &str) -> bool {
+    let wanted: Vec<&str> = filter.split(',').filter_map(resolve).collect();
+    let mut current = find(category);
+    while let Some(node) = current {
+        if wanted.contains(&node.key) {
+            return true;
+        }
+        current = node.parent.and_then(find);
+    }
+    false
+}
+
+pub async fn taxonomy() -> Json<&'static [Node]> {
+    Json(NODES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let cases = [
+            ("Semi-Detached House", "semi_detached"),
+            ("semi detached", "semi_detached"),
+            ("End of Terrace House", "end_of_terrace"),
+            ("Terraced House", "terraced"),
+            ("Detached House", "detached"),
+            ("House", "house"),
+            ("Apartment", "apartment"),
+            ("Flat", "apartment"),
+            ("Studio Apartment", "studio"),
+            ("Duplex", "duplex"),
+            ("House Share", "room"),
+            ("Room to rent", "room"),
+            ("Office Space", "office"),
+            ("Retail Unit", "retail"),
+            ("Industrial / Warehouse", "industrial"),
+            ("Site", UNKNOWN),
+            ("", UNKNOWN),
+        ];
+        for (text, key) in cases {
+            assert_eq!(classify(text), key, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_filter_matches_subtypes() {
+        assert!(matches("house", "semi_detached"));
+        assert!(matches("residential", "studio"));
+        assert!(matches("Semi-Detached", "semi_detached"));
+        assert!(matches("studio, house", "terraced"));
+        assert!(matches("flat", "apartment"));
+        assert!(!matches("house", "apartment"));
+        assert!(!matches("apartment", UNKNOWN));
+        assert!(matches("commercial", "office"));
+        assert!(!matches("residential", "retail"));
+        assert_eq!(unknown_terms("house, castle"), ["castle"]);
+    }
+
+    #[test]
+    fn test_nodes_form_a_tree() {
+        for node in NODES {
+            assert!(node.parent.is_none_or(|parent| find(parent).is_some()), "{}", node.key);
+        }
+        for (_, key) in RULES {
+            assert!(find(key).is_some(), "{}", key);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! How much of each portal the snapshots capture.
+//!
+//! A portal's search reports how many listings it has, and the scrapers keep
+//! that figure next to the snapshot they write, as `<snapshot>.crawl.json`:
+//!
+//! ```json
+//! {"reported_total": 3120, "scraped": 3080, "scraped_at": "2024-11-05T12:00:00"}
+//! ```
+//!
+//! `GET /api/stats/coverage` compares it with the rows each snapshot holds, so
+//! consumers can tell how representative the stats over a source are. A
+//! coverage of 0.9 means the snapshot captured 90% of what the portal had
+//! live; well below 1 usually means pages failed or were rate limited.
+//! Snapshots without the figure (older ones, or sources whose portal doesn't
+//! report a total) have no coverage.
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::state::AppState;
+use crate::{list_snapshots, parquet_row_count};
+
+/// Days of snapshots covered without `days`.
+const DEFAULT_DAYS: i64 = 30;
+
+/// What the scraper recorded about the crawl behind a snapshot.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Crawl {
+    /// Listings the portal said its search had.
+    pub reported_total: usize,
+    /// Records the scraper fetched, before processing.
+    #[serde(default)]
+    pub scraped: Option<usize>,
+}
+
+impl Crawl {
+    fn path(snapshot: &Path) -> PathBuf {
+        snapshot.with_extension("crawl.json")
+    }
+
+    /// The crawl recorded for `snapshot`, if any.
+    pub fn of(snapshot: &Path) -> Option<Crawl> {
+        serde_json::from_str(&fs::read_to_string(Crawl::path(snapshot)).ok()?).ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SnapshotCoverage {
+    pub date: NaiveDate,
+    /// Rows in the snapshot.
+    pub captured: usize,
+    pub reported_total: Option<usize>,
+    pub scraped: Option<usize>,
+    /// `captured` over `reported_total`.
+    pub coverage: Option<f64>,
+}
+
+impl SnapshotCoverage {
+    pub fn new(date: NaiveDate, captured: usize, crawl: Option<Crawl>) -> Self {
+        let reported_total = crawl.as_ref().map(|crawl| crawl.reported_total);
+        SnapshotCoverage {
+            date,
+            captured,
+            reported_total,
+            scraped: crawl.and_then(|crawl| crawl.scraped),
+            coverage: reported_total.filter(|total| *total > 0).map(|total| captured as f64 / total as f64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SourceCoverage {
+    pub source: String,
+    /// Coverage of the latest snapshot.
+    pub coverage: Option<f64>,
+    /// Mean coverage of the snapshots that have one.
+    pub mean_coverage: Option<f64>,
+    /// Oldest first.
+    pub snapshots: Vec<SnapshotCoverage>,
+}
+
+impl SourceCoverage {
+    pub fn new(source: &str, snapshots: Vec<SnapshotCoverage>) -> Self {
+        let known: Vec<f64> = snapshots.iter().filter_map(|snapshot| snapshot.coverage).collect();
+        SourceCoverage {
+            source: source.to_string(),
+            coverage: snapshots.last().and_then(|snapshot| snapshot.coverage),
+            mean_coverage: (!known.is_empty()).then(|| known.iter().sum::<f64>() / known.len() as f64),
+            snapshots,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CoverageParams {
+    source: Option<String>,
+    /// Snapshots from this many days before each source's latest on.
+    days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CoverageResponse {
+    pub days: i64,
+    pub sources: Vec<SourceCoverage>,
+}
+
+/// Coverage of the snapshots of the requested source, or every source, from
+/// `days` before its latest on. Blocking.
+pub fn compute(config: &Config, source: Option<&str>, days: i64) -> Vec<SourceCoverage> {
+    config
+        .select_sources(source)
+        .into_iter()
+        .map(|source| {
+            let files = list_snapshots(&source.root(&config.data_path));
+            let since = files.last().map_or(NaiveDate::MIN, |(latest, _)| *latest - Duration::days(days));
+            let snapshots = files
+                .into_iter()
+                .filter(|(date, _)| *date >= since)
+                .map(|(date, file)| SnapshotCoverage::new(date, parquet_row_count(&file), Crawl::of(&file)))
+                .collect();
+            SourceCoverage::new(&source.name, snapshots)
+        })
+        .collect()
+}
+
+pub async fn coverage(
+    State(state): State<AppState>,
+    Query(params): Query<CoverageParams>,
+) -> Result<Json<CoverageResponse>, (StatusCode, String)> {
+    let days = params.days.unwrap_or(DEFAULT_DAYS);
+    if days < 0 {
+        return Err((StatusCode::BAD_REQUEST, "days must not be negative".to_string()));
+    }
+    let sources = tokio::task::spawn_blocking(move || compute(&state.config, params.source.as_deref(), days))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(CoverageResponse { days, sources }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{standardized_batch, temp_dir, write_parquet};
+
+    #[test]
+    fn test_coverage_compares_rows_with_the_reported_total() {
+        let mut config = Config { data_path: temp_dir("coverage"), ..Default::default() };
+        config.sources.retain(|source| source.name == "daft");
+        let root = config.sources[0].root(&config.data_path);
+        let snapshot = |day: u32| root.join(format!("2024/11/{:02}/daft_120000.parquet", day));
+        for day in [1, 20, 25] {
+            write_parquet(&snapshot(day), &standardized_batch(&["1", "2", "3"]));
+        }
+        fs::write(Crawl::path(&snapshot(20)), r#"{"reported_total": 4, "scraped": 4}"#).unwrap();
+        fs::write(Crawl::path(&snapshot(25)), r#"{"reported_total": 6}"#).unwrap();
+
+        let sources = compute(&config, None, 10);
+        let daft = &sources[0];
+        let dates: Vec<u32> = daft.snapshots.iter().map(|s| chrono::Datelike::day(&s.date)).collect();
+        assert_eq!(dates, [20, 25]);
+        assert_eq!((daft.snapshots[0].captured, daft.snapshots[0].scraped), (3, Some(4)));
+        assert_eq!(daft.coverage, Some(0.5));
+        assert_eq!(daft.mean_coverage, Some(0.625));
+
+        let all = compute(&config, None, 30);
+        assert_eq!((all[0].snapshots.len(), all[0].snapshots[0].coverage), (3, None));
+    }
+}
@@ -0,0 +1,5 @@
+//! Data-quality reports over the collected listings.
+
+pub mod coverage;
+pub mod photo_conflicts;
+pub mod report;
@@ -0,0 +1,117 @@
+//! Photos shared by listings at different addresses.
+//!
+//! The same flat photographed for two listings is normal when it is the same
+//! flat: a relisting, another unit in the building, or the listing on a second
+//! site. The same photo on listings in different places means a scraper put
+//! one listing's photos on another, or someone is advertising a flat they
+//! don't have. `/api/admin/analytics/photo-conflicts` lists every stored photo
+//! (see `photos`) whose current listings have materially different addresses
+//! (see `address::materially_different`), most listings first.
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::address;
+use crate::auth::{Caller, SCOPE_ADMIN};
+use crate::state::AppState;
+use crate::StandardizedProperty;
+
+#[derive(Debug, Serialize)]
+pub struct ConflictListing {
+    pub property_id: String,
+    pub source: String,
+    pub address: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PhotoConflict {
+    pub hash: String,
+    /// Where the stored photo is served.
+    pub photo: String,
+    pub listings: Vec<ConflictListing>,
+}
+
+/// Conflicts among `listings`, given the listings each photo hash was stored
+/// for. Listings not among `listings` are left out.
+pub fn find(by_hash: &BTreeMap<String, Vec<String>>, listings: &[StandardizedProperty]) -> Vec<PhotoConflict> {
+    let by_id: HashMap<&str, &StandardizedProperty> =
+        listings.iter().flat_map(|p| p.ids().map(move |id| (id, p))).collect();
+    let mut conflicts: Vec<PhotoConflict> = by_hash
+        .iter()
+        .filter_map(|(hash, ids)| {
+            let mut sharing: Vec<&StandardizedProperty> =
+                ids.iter().filter_map(|id| by_id.get(id.as_str()).copied()).collect();
+            // Photos stored under both of a listing's ids
+            sharing.sort_by(|a, b| a.property_id.cmp(&b.property_id));
+            sharing.dedup_by(|a, b| a.property_id == b.property_id);
+            let conflicting = sharing.iter().enumerate().any(|(i, a)| {
+                let a = &a.address.normalized_address;
+                sharing[i + 1..].iter().any(|b| address::materially_different(a, &b.address.normalized_address))
+            });
+            conflicting.then(|| PhotoConflict {
+                hash: hash.clone(),
+                photo: format!("/api/photos/{}", hash),
+                listings: sharing
+                    .iter()
+                    .map(|p| ConflictListing {
+                        property_id: p.property_id.clone(),
+                        source: p.source.clone(),
+                        address: p.address.display_address.clone(),
+                    })
+                    .collect(),
+            })
+        })
+        .collect();
+    conflicts.sort_by(|a, b| b.listings.len().cmp(&a.listings.len()).then_with(|| a.hash.cmp(&b.hash)));
+    conflicts
+}
+
+pub async fn photo_conflicts(
+    State(state): State<AppState>,
+    caller: Caller,
+) -> Result<Json<Vec<PhotoConflict>>, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    let conflicts = tokio::task::spawn_blocking(move || {
+        let listings: Vec<StandardizedProperty> =
+            state.config.source_names(None).into_iter().flat_map(|source| state.store.latest(source)).collect();
+        find(&state.photos.listings_by_hash(), &listings)
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(conflicts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::listing;
+
+    #[test]
+    fn test_shared_photos_at_different_addresses() {
+        let at = |source: &str, id: &str, display: &str| {
+            let mut property = listing(source, id);
+            property.address.display_address = display.to_string();
+            property.address.normalized_address = address::normalize(display);
+            property
+        };
+        let listings = [
+            at("daft", "1", "7 Castle Ave, Ranelagh, Dublin 6"),
+            at("myhome", "1", "Castle Avenue, Ranelagh, Dublin 6"),
+            at("daft", "2", "40 Elm Park, Rathmines, Dublin 6"),
+        ];
+        let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        let by_hash = BTreeMap::from([
+            ("a".repeat(64), ids(&["daft_1", "myhome_1"])),
+            ("b".repeat(64), ids(&["daft_1", "daft_2", "myhome_1"])),
+            ("c".repeat(64), ids(&["daft_2", "daft_9"])),
+        ]);
+
+        let conflicts = find(&by_hash, &listings);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].hash, "b".repeat(64));
+        assert_eq!(conflicts[0].listings.len(), 3);
+    }
+}
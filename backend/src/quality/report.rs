@@ -0,0 +1,347 @@
+//! Nightly data-quality reports.
+//!
+//! Once a day, after `quality.run_hour` (UTC), a job checks the latest
+//! snapshot of every enabled source and records, per source:
+//!
+//! - `completeness`: the share of listings with each field filled in
+//! - `parse_failure_rate`: rows that didn't parse, over all rows
+//! - `duplicate_rate`: parsed rows repeating a listing earlier in the file
+//! - `invalid_prices`, `flagged_prices` and `price_outliers`: rents out of
+//!   range, rents breaking their segment's bounds (see `price_bounds`), and
+//!   valid rents far outside the rest of their bedroom count (beyond three
+//!   interquartile ranges of the quartiles)
+//! - `drift`: mapped columns the file lacks, and columns added or removed
+//!   since the previous report
+//!
+//! Reports are appended to `quality.path` and kept, so
+//! `GET /api/admin/quality?days=30&source=daft` shows how a source's health
+//! moves over time.
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{DateTime, Duration, NaiveDate, Timelike, Utc};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::analytics::liquidity::quantile;
+use crate::auth::{Caller, SCOPE_ADMIN};
+use crate::config::{Config, SourceConfig};
+use crate::state::AppState;
+use crate::store::PropertyStore;
+use crate::validate::validate_file;
+use crate::{find_latest_parquet, rent_in_range, snapshot_date, validate_price, StandardizedProperty};
+
+/// Job kind that writes a quality report.
+pub const JOB: &str = "quality.report";
+
+/// Interquartile ranges beyond the quartiles at which a rent is an outlier.
+const OUTLIER_IQRS: f64 = 3.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceQuality {
+    pub source: String,
+    pub snapshot: Option<NaiveDate>,
+    pub rows: usize,
+    pub listings: usize,
+    /// Share of listings with each field set, 0 to 1.
+    pub completeness: BTreeMap<String, f64>,
+    pub parse_failure_rate: f64,
+    pub duplicate_rate: f64,
+    pub invalid_prices: usize,
+    #[serde(default)]
+    pub flagged_prices: usize,
+    pub price_outliers: usize,
+    /// Schema changes worth a look; empty when nothing moved.
+    pub drift: Vec<String>,
+    /// Why the snapshot failed validation, if it did; see `validate`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<String>,
+    /// The snapshot's columns, to tell drift from the next report.
+    pub columns: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QualityReport {
+    pub generated_at: DateTime<Utc>,
+    pub sources: Vec<SourceQuality>,
+}
+
+fn share(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+/// Whether a listing has a field set.
+type Has = fn(&StandardizedProperty) -> bool;
+
+/// Share of `listings` with each tracked field set.
+pub fn completeness(listings: &[StandardizedProperty]) -> BTreeMap<String, f64> {
+    let fields: [(&str, Has); 10] = [
+        ("address", |p| !p.address.normalized_address.is_empty()),
+        ("price", |p| rent_in_range(p.price.amount)),
+        ("bedrooms", |p| p.bedrooms.is_some()),
+        ("bathrooms", |p| p.bathrooms.is_some()),
+        ("size", |p| p.size.is_some()),
+        ("ber_rating", |p| p.ber_rating.is_some()),
+        ("photos", |p| !p.photos.is_empty()),
+        ("agent", |p| p.agent.is_some()),
+        ("deposit", |p| p.deposit.is_some()),
+        ("coordinates", |p| p.coordinates.is_some()),
+    ];
+    fields
+        .iter()
+        .map(|(name, has)| (name.to_string(), share(listings.iter().filter(|p| has(p)).count(), listings.len())))
+        .collect()
+}
+
+/// Valid rents far from the others of the same bedroom count.
+pub fn price_outliers(listings: &[StandardizedProperty]) -> usize {
+    let mut rents: HashMap<Option<i32>, Vec<f64>> = HashMap::new();
+    for property in listings.iter().filter(|p| validate_price(p)) {
+        rents.entry(property.bedrooms).or_default().push(property.price.amount);
+    }
+    rents
+        .into_values()
+        .map(|mut rents| {
+            rents.sort_by(f64::total_cmp);
+            let (Some(q1), Some(q3)) = (quantile(&rents, 0.25), quantile(&rents, 0.75)) else {
+                return 0;
+            };
+            let (low, high) = (q1 - OUTLIER_IQRS * (q3 - q1), q3 + OUTLIER_IQRS * (q3 - q1));
+            rents.iter().filter(|rent| **rent < low || **rent > high).count()
+        })
+        .sum()
+}
+
+/// Checks `source`'s latest snapshot; `previous` is its last report.
+fn check_source(
+    config: &Config,
+    store: &dyn PropertyStore,
+    source: &SourceConfig,
+    previous: Option<&SourceQuality>,
+) -> Option<SourceQuality> {
+    let latest = find_latest_parquet(&source.root(&config.data_path))?;
+    let file = validate_file(source, &latest);
+    let listings = store.latest(&source.name);
+
+    let mut drift: Vec<String> =
+        file.missing_columns.iter().map(|c| format!("mapped column {} is missing", c)).collect();
+    if let Some(previous) = previous.filter(|previous| !previous.columns.is_empty()) {
+        drift.extend(
+            file.columns.iter().filter(|c| !previous.columns.contains(c)).map(|c| format!("new column {}", c)),
+        );
+        drift.extend(
+            previous.columns.iter().filter(|c| !file.columns.contains(c)).map(|c| format!("column {} removed", c)),
+        );
+    }
+    Some(SourceQuality {
+        source: source.name.clone(),
+        snapshot: snapshot_date(&latest),
+        rows: file.rows,
+        listings: listings.len(),
+        completeness: completeness(&listings),
+        parse_failure_rate: share(file.rows - file.parsed, file.rows),
+        duplicate_rate: share(file.duplicates, file.parsed),
+        invalid_prices: listings.iter().filter(|p| !rent_in_range(p.price.amount)).count(),
+        flagged_prices: listings.iter().filter(|p| p.price_flag.is_some()).count(),
+        price_outliers: price_outliers(&listings),
+        drift,
+        failures: file.failures,
+        columns: file.columns,
+    })
+}
+
+/// A report on every enabled source generated `now`, comparing schemas with
+/// `previous`.
+pub fn build(
+    config: &Config,
+    store: &dyn PropertyStore,
+    previous: Option<&QualityReport>,
+    now: DateTime<Utc>,
+) -> QualityReport {
+    let sources = config
+        .select_sources(None)
+        .into_iter()
+        .filter_map(|source| {
+            let last = previous.and_then(|report| report.sources.iter().find(|s| s.source == source.name));
+            check_source(config, store, source, last)
+        })
+        .collect();
+    QualityReport { generated_at: now, sources }
+}
+
+/// Reports written so far, appended to a JSON-lines file.
+pub struct QualityHistory {
+    path: Option<PathBuf>,
+    reports: Mutex<Vec<QualityReport>>,
+}
+
+impl QualityHistory {
+    /// Loads earlier reports from `path`. `None` keeps them in memory only.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let reports = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| contents.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+            .unwrap_or_default();
+        QualityHistory { path, reports: Mutex::new(reports) }
+    }
+
+    pub fn latest(&self) -> Option<QualityReport> {
+        self.reports.lock().unwrap().last().cloned()
+    }
+
+    /// Whether a report was generated on `date` (UTC).
+    pub fn ran_on(&self, date: NaiveDate) -> bool {
+        self.reports.lock().unwrap().iter().rev().any(|report| report.generated_at.date_naive() == date)
+    }
+
+    pub fn record(&self, report: QualityReport) -> Result<(), String> {
+        let mut reports = self.reports.lock().unwrap();
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+            let line = serde_json::to_string(&report).map_err(|e| e.to_string())?;
+            let mut file = OpenOptions::new().create(true).append(true).open(path).map_err(|e| e.to_string())?;
+            writeln!(file, "{}", line).map_err(|e| e.to_string())?;
+        }
+        reports.push(report);
+        Ok(())
+    }
+
+    /// Reports generated since `since`, oldest first, each cut down to
+    /// `source` when given.
+    pub fn since(&self, since: Option<DateTime<Utc>>, source: Option<&str>) -> Vec<QualityReport> {
+        let reports = self.reports.lock().unwrap();
+        reports
+            .iter()
+            .filter(|report| since.is_none_or(|since| report.generated_at >= since))
+            .map(|report| {
+                let mut report = report.clone();
+                if let Some(source) = source {
+                    report.sources.retain(|s| s.source == source);
+                }
+                report
+            })
+            .collect()
+    }
+}
+
+pub fn run_job(state: &AppState, _payload: &serde_json::Value) -> Result<(), String> {
+    let report = build(&state.config, state.store.as_ref(), state.quality.latest().as_ref(), state.clock.now());
+    let drifting = report.sources.iter().filter(|s| !s.drift.is_empty()).count();
+    info!("Quality report covers {} sources, {} with schema drift", report.sources.len(), drifting);
+    state.quality.record(report)
+}
+
+/// Queues a report once a day, after `quality.run_hour`, for the life of the
+/// process.
+pub fn schedule(state: &AppState) {
+    if !state.config.quality.enabled {
+        return;
+    }
+    let state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(600));
+        loop {
+            interval.tick().await;
+            let now = state.clock.now();
+            if now.hour() < state.config.quality.run_hour || state.quality.ran_on(now.date_naive()) {
+                continue;
+            }
+            if let Err(e) = state.jobs.enqueue_once(JOB, ()) {
+                error!("Could not queue quality report: {}", e);
+            }
+        }
+    });
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QualityParams {
+    /// Only reports from the last `days` days.
+    days: Option<i64>,
+    source: Option<String>,
+}
+
+pub async fn history(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(params): Query<QualityParams>,
+) -> Result<Json<Vec<QualityReport>>, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    let source = match &params.source {
+        Some(name) => Some(
+            state
+                .config
+                .resolve_source(name)
+                .map(|source| source.name.clone())
+                .ok_or((StatusCode::BAD_REQUEST, format!("Unknown source {:?}", name)))?,
+        ),
+        None => None,
+    };
+    let since = params.days.map(|days| state.clock.now() - Duration::days(days));
+    Ok(Json(state.quality.since(since, source.as_deref())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{listing, standardized_batch, temp_dir, write_parquet};
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn test_completeness_and_outliers() {
+        let mut listings: Vec<StandardizedProperty> = (0..10)
+            .map(|i| {
+                let mut property = listing("daft", &i.to_string());
+                property.bedrooms = Some(2);
+                property.price.amount = 1800.0 + 10.0 * i as f64;
+                property
+            })
+            .collect();
+        listings[0].price.amount = 18_000.0;
+        listings[1].price.amount = 0.0;
+        listings[2].bathrooms = Some(1);
+
+        let completeness = completeness(&listings);
+        assert_eq!(completeness["bedrooms"], 1.0);
+        assert_eq!(completeness["bathrooms"], 0.1);
+        assert_eq!(completeness["price"], 0.9);
+        assert_eq!(price_outliers(&listings), 1);
+    }
+
+    #[test]
+    fn test_reports_flag_drift_and_persist() {
+        let data = temp_dir("quality");
+        let config = Config::from_toml(&format!("data_path = {:?}\n[[sources]]\nname = \"rent_ie\"", data)).unwrap();
+        let path = data.join("processed/rent_ie/2024/11/05/rent_ie_120000.parquet");
+        write_parquet(&path, &standardized_batch(&["1", "2", "2"]));
+        let store = MemoryStore::default();
+
+        let mut previous = build(&config, &store, None, Utc::now());
+        let first = previous.sources.iter().find(|s| s.source == "rent_ie").unwrap();
+        assert_eq!((first.rows, first.duplicate_rate), (3, 1.0 / 3.0));
+        assert!(first.drift.is_empty());
+
+        previous.sources.iter_mut().for_each(|s| s.columns.push("bedrooms".to_string()));
+        let report = build(&config, &store, Some(&previous), Utc::now());
+        let second = report.sources.iter().find(|s| s.source == "rent_ie").unwrap();
+        assert_eq!(second.drift, ["column bedrooms removed"]);
+
+        let history = QualityHistory::open(Some(data.join("quality.jsonl")));
+        history.record(report).unwrap();
+        let reopened = QualityHistory::open(Some(data.join("quality.jsonl")));
+        assert!(reopened.ran_on(Utc::now().date_naive()));
+        assert_eq!(reopened.since(None, Some("rent_ie"))[0].sources.len(), 1);
+    }
+}
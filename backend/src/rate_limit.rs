@@ -0,0 +1,153 @@
+//! Per-client rate limiting.
+//!
+//! Each client address gets a token bucket holding up to `burst` requests and
+//! refilled at `requests_per_minute`. Requests that find the bucket empty are
+//! answered with 429 and a `Retry-After` telling the client when the next
+//! token arrives. Health, readiness and metrics probes are never limited.
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimitConfig;
+use crate::state::AppState;
+
+/// Paths probes hit on a schedule; limiting them would only cause restarts.
+pub const EXEMPT_PATHS: &[&str] = &["/health", "/ready", "/metrics"];
+
+/// Buckets kept before full (idle) ones are dropped.
+const MAX_CLIENTS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Clone, Copy)]
+struct Limits {
+    capacity: f64,
+    /// Tokens added per second.
+    rate: f64,
+}
+
+impl Limits {
+    fn of(config: &RateLimitConfig) -> Self {
+        Limits { capacity: config.burst.max(1) as f64, rate: config.requests_per_minute.max(1) as f64 / 60.0 }
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.capacity)
+    }
+}
+
+pub struct RateLimiter {
+    limits: RwLock<Limits>,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        RateLimiter { limits: RwLock::new(Limits::of(config)), buckets: Mutex::default() }
+    }
+
+    /// Applies new limits; clients keep the tokens they have, up to the new
+    /// burst.
+    pub fn reconfigure(&self, config: &RateLimitConfig) {
+        *self.limits.write().unwrap() = Limits::of(config);
+    }
+
+    /// Takes a token for `client`, or returns how long until one is available.
+    pub fn check(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let limits = *self.limits.read().unwrap();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_CLIENTS && !buckets.contains_key(&client) {
+            buckets.retain(|_, bucket| limits.refilled(bucket, now) < limits.capacity);
+        }
+        let bucket = buckets.entry(client).or_insert(Bucket { tokens: limits.capacity, updated: now });
+        bucket.tokens = limits.refilled(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / limits.rate))
+        }
+    }
+}
+
+pub fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>, trust_forwarded_for: bool) -> Option<IpAddr> {
+    let forwarded = trust_forwarded_for
+        .then(|| headers.get("x-forwarded-for")?.to_str().ok()?.split(',').next()?.trim().parse().ok())
+        .flatten();
+    forwarded.or(peer.map(|addr| addr.ip()))
+}
+
+pub async fn limit(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let config = &state.config.rate_limit;
+    if !config.enabled || EXEMPT_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|info| info.0);
+    let Some(client) = client_ip(request.headers(), peer, config.trust_forwarded_for) else {
+        return next.run(request).await;
+    };
+    match state.rate_limiter.check(client, Instant::now()) {
+        Ok(()) => next.run(request).await,
+        Err(wait) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, wait.as_secs().max(1).to_string())],
+            "Too many requests; slow down",
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::new(&RateLimitConfig {
+            enabled: true,
+            requests_per_minute: 60,
+            burst: 2,
+            trust_forwarded_for: false,
+        });
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        let other: IpAddr = "203.0.113.8".parse().unwrap();
+        let start = Instant::now();
+
+        assert!(limiter.check(client, start).is_ok());
+        assert!(limiter.check(client, start).is_ok());
+        assert_eq!(limiter.check(client, start), Err(Duration::from_secs(1)));
+        assert!(limiter.check(other, start).is_ok());
+        assert!(limiter.check(client, start + Duration::from_secs(1)).is_ok());
+        assert!(limiter.check(client, start + Duration::from_secs(1)).is_err());
+
+        limiter.reconfigure(&RateLimitConfig {
+            enabled: true,
+            requests_per_minute: 120,
+            burst: 2,
+            trust_forwarded_for: false,
+        });
+        assert!(limiter.check(client, start + Duration::from_millis(1500)).is_ok());
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "198.51.100.1, 10.0.0.1".parse().unwrap());
+        let peer: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+
+        assert_eq!(client_ip(&headers, Some(peer), false), Some(peer.ip()));
+        assert_eq!(client_ip(&headers, Some(peer), true), Some("198.51.100.1".parse().unwrap()));
+        assert_eq!(client_ip(&HeaderMap::new(), Some(peer), true), Some(peer.ip()));
+    }
+}
@@ -0,0 +1,117 @@
+//! Raw source records behind parsed listings.
+//!
+//! `?include_raw=true` on `/api/rentals/search` and `/api/rentals/{id}` adds a
+//! `raw` object to each listing: the snapshot file and row it was parsed from,
+//! the column mapping version, and every mapped field as it is in the file. A
+//! listing showing 0 bathrooms can then be traced to its column without
+//! opening the parquet file. Only callers with the `admin` or `debug` scope
+//! may ask for it.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::auth::{Caller, SCOPE_ADMIN, SCOPE_DEBUG};
+use crate::config::Config;
+use crate::state::AppState;
+use crate::{find_latest_parquet, select_rows, StandardizedProperty};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RawRecord {
+    pub file: PathBuf,
+    pub row: usize,
+    pub mapping_version: u32,
+    /// Mapped field name to the value in the file; `null` when it is null
+    /// there or its column is missing from the file.
+    pub fields: BTreeMap<String, Value>,
+}
+
+/// Where a listing was read from: source name, snapshot file and row.
+pub type Location = (String, PathBuf, usize);
+
+/// Fails with 403 unless the caller may see raw records.
+pub fn require_scope(caller: &Caller) -> Result<(), (axum::http::StatusCode, String)> {
+    match caller.has_scope(SCOPE_ADMIN) {
+        true => Ok(()),
+        false => caller.require(SCOPE_DEBUG),
+    }
+}
+
+/// The raw records of the rows at `offsets` of one snapshot file.
+fn read(config: &Config, source: &str, file: &Path, offsets: &[usize]) -> HashMap<usize, RawRecord> {
+    let mut records = HashMap::new();
+    let Some(source) = config.sources.iter().find(|s| s.name == source) else {
+        return records;
+    };
+    select_rows(source, file, offsets, |row, columns, batch_row| {
+        let record = RawRecord {
+            file: file.to_path_buf(),
+            row,
+            mapping_version: columns.version,
+            fields: batch_row.raw_fields(),
+        };
+        records.insert(row, record);
+    });
+    records
+}
+
+/// Sets `raw` on each listing from its location, read with one pass per file.
+pub fn attach(config: &Config, properties: &mut [StandardizedProperty], locations: &[Location]) {
+    let mut by_file: HashMap<(&str, &Path), Vec<usize>> = HashMap::new();
+    for (source, file, row) in locations {
+        by_file.entry((source.as_str(), file.as_path())).or_default().push(*row);
+    }
+    let records: HashMap<(&str, &Path), HashMap<usize, RawRecord>> = by_file
+        .into_iter()
+        .map(|((source, file), rows)| ((source, file), read(config, source, file, &rows)))
+        .collect();
+    for (property, (source, file, row)) in properties.iter_mut().zip(locations) {
+        property.raw = records.get(&(source.as_str(), file.as_path())).and_then(|rows| rows.get(row)).cloned();
+    }
+}
+
+/// Where a listing is in its source's latest snapshot.
+pub fn locate(state: &AppState, property: &StandardizedProperty) -> Option<Location> {
+    let source = state.config.sources.iter().find(|s| s.name == property.source)?;
+    let file = find_latest_parquet(&source.root(&state.config.data_path))?;
+    let row = state.id_index.snapshot(source, &file)?.offset(property.indexed_id())?;
+    Some((source.name.clone(), file, row))
+}
+
+/// Clears agent contact fields the caller may not see; see `privacy`.
+pub fn redact_agent(record: &mut RawRecord, name_too: bool) {
+    for field in ["agent_phone", "agent_email"].into_iter().chain(name_too.then_some("agent_name")) {
+        if let Some(value) = record.fields.get_mut(field) {
+            *value = Value::Null;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{listing, standardized_batch, temp_dir, write_parquet};
+
+    #[test]
+    fn test_attach_reads_rows_of_each_file() {
+        let data = temp_dir("raw");
+        let config = Config::from_toml(&format!("data_path = {:?}\n[[sources]]\nname = \"rent_ie\"", data)).unwrap();
+        let file = data.join("processed/rent_ie/2024/11/05/rent_ie_120000.parquet");
+        write_parquet(&file, &standardized_batch(&["100", "200", "300"]));
+        let location = |row| ("rent_ie".to_string(), file.clone(), row);
+
+        let mut properties = vec![listing("rent_ie", "300"), listing("rent_ie", "100")];
+        attach(&config, &mut properties, &[location(2), location(0)]);
+        let raw = properties[0].raw.as_ref().unwrap();
+        assert_eq!((raw.row, raw.mapping_version), (2, 0));
+        assert_eq!(raw.fields["source_id"], "300");
+        assert_eq!(raw.fields["display_address"], "300 Main Street, Dublin 8");
+        assert_eq!(properties[1].raw.as_ref().unwrap().fields["price"], "€1000");
+
+        let mut record = raw.clone();
+        record.fields.insert("agent_phone".to_string(), Value::from("087 000 0000"));
+        redact_agent(&mut record, false);
+        assert_eq!(record.fields["agent_phone"], Value::Null);
+    }
+}
@@ -0,0 +1,386 @@
+//! Listing recommendations from what a user engaged with.
+//!
+//! Callers with an API key favourite listings with
+//! `POST /api/rentals/{id}/favourite` (`DELETE` undoes it), click through to
+//! them by following `/l/{short_id}` with the key, and hide them (see
+//! `hidden`). `GET /api/me/recommendations` ranks the listings of every
+//! source's latest snapshot by how closely they resemble what the caller
+//! favourited and clicked, and how little they resemble what they hid, over
+//! bedrooms, area, price band and features (property type, garden, balcony,
+//! parking, floorplan). A favourite counts twice as much as a click and a hide
+//! counts against. Listings the caller already engaged with are left out.
+//!
+//! What a favourited or clicked listing looked like is kept with it, so the
+//! caller's taste outlives the listing. Rankings are cached per user until a
+//! source lands a new snapshot or the user's interactions change.
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::NaiveDate;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+
+use crate::auth::Caller;
+use crate::hidden::HideList;
+use crate::state::AppState;
+use crate::store::PropertyStore;
+use crate::{address, StandardizedProperty};
+
+const DEFAULT_LIMIT: usize = 20;
+const MAX_LIMIT: usize = 100;
+
+const FAVOURITE_WEIGHT: f64 = 2.0;
+const CLICK_WEIGHT: f64 = 1.0;
+const HIDE_WEIGHT: f64 = -1.0;
+
+/// The parts of a listing recommendations compare.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub bedrooms: Option<i32>,
+    pub area: String,
+    pub price_band: String,
+    /// "type:<category>", "garden", "balcony", "parking" and "floorplan".
+    pub features: BTreeSet<String>,
+}
+
+impl Profile {
+    pub fn of(property: &StandardizedProperty) -> Self {
+        let amenities = &property.amenities;
+        let mut features = BTreeSet::new();
+        if !property.property_category.is_empty() {
+            features.insert(format!("type:{}", property.property_category));
+        }
+        let flags = [
+            ("garden", amenities.garden == Some(true)),
+            ("balcony", amenities.balcony == Some(true)),
+            ("parking", amenities.parking_spaces.is_some_and(|spaces| spaces > 0)),
+            ("floorplan", property.has_floorplan),
+        ];
+        features.extend(flags.into_iter().filter(|(_, set)| *set).map(|(feature, _)| feature.to_string()));
+        let rent = property.price.amount;
+        Profile {
+            bedrooms: property.bedrooms,
+            area: address::area(&property.address.normalized_address).to_string(),
+            price_band: if rent > 0.0 { property.price_band.clone() } else { String::new() },
+            features,
+        }
+    }
+
+    /// From 0 (nothing in common) to 1, each of the four parts counting
+    /// equally. A bedroom apart counts half.
+    fn similarity(&self, other: &Profile) -> f64 {
+        let bedrooms = match (self.bedrooms, other.bedrooms) {
+            (Some(a), Some(b)) if a == b => 1.0,
+            (Some(a), Some(b)) if (a - b).abs() == 1 => 0.5,
+            _ => 0.0,
+        };
+        let same = |a: &str, b: &str| if !a.is_empty() && a == b { 1.0 } else { 0.0 };
+        let union = self.features.union(&other.features).count();
+        let features = match union {
+            0 => 0.0,
+            union => self.features.intersection(&other.features).count() as f64 / union as f64,
+        };
+        (bedrooms + same(&self.area, &other.area) + same(&self.price_band, &other.price_band) + features) / 4.0
+    }
+}
+
+/// One user's favourites and click-throughs.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Engagement {
+    /// By property id.
+    pub favourites: BTreeMap<String, Profile>,
+    /// By short id; `None` until the listing is next seen in a snapshot.
+    pub clicks: BTreeMap<String, Option<Profile>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Recommendation {
+    /// Weighted similarity to the caller's engagement, up to 1.
+    pub score: f64,
+    pub listing: StandardizedProperty,
+}
+
+/// A user's last ranking and what it was ranked from.
+struct Ranking {
+    snapshots: BTreeMap<String, Option<NaiveDate>>,
+    engagement: Engagement,
+    hidden: HideList,
+    recommendations: Vec<Recommendation>,
+}
+
+pub struct Interactions {
+    path: Option<PathBuf>,
+    by_user: RwLock<BTreeMap<String, Engagement>>,
+    rankings: Mutex<HashMap<String, Ranking>>,
+}
+
+impl Interactions {
+    /// Loads saved interactions from `path`. `None` keeps them in memory only.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let by_user = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Interactions { path, by_user: RwLock::new(by_user), rankings: Mutex::default() }
+    }
+
+    pub fn for_user(&self, user: &str) -> Engagement {
+        self.by_user.read().unwrap().get(user).cloned().unwrap_or_default()
+    }
+
+    fn update(&self, user: &str, change: impl FnOnce(&mut Engagement)) -> Result<Engagement, String> {
+        let mut by_user = self.by_user.write().unwrap();
+        let engagement = by_user.entry(user.to_string()).or_default();
+        change(engagement);
+        let updated = engagement.clone();
+        if updated == Engagement::default() {
+            by_user.remove(user);
+        }
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+            let contents = serde_json::to_string_pretty(&*by_user).map_err(|e| e.to_string())?;
+            fs::write(path, contents).map_err(|e| e.to_string())?;
+        }
+        Ok(updated)
+    }
+
+    /// Favourites `property` for `user`, or drops it when `favourite` is
+    /// false.
+    pub fn set_favourite(
+        &self,
+        user: &str,
+        property: &StandardizedProperty,
+        favourite: bool,
+    ) -> Result<Engagement, String> {
+        self.update(user, |engagement| {
+            if favourite {
+                engagement.favourites.insert(property.property_id.clone(), Profile::of(property));
+            } else {
+                engagement.favourites.remove(&property.property_id);
+            }
+        })
+    }
+
+    pub fn record_click(&self, user: &str, short_id: &str) {
+        if self.for_user(user).clicks.contains_key(short_id) {
+            return;
+        }
+        if let Err(e) = self.update(user, |engagement| {
+            engagement.clicks.insert(short_id.to_string(), None);
+        }) {
+            warn!("Could not save click-through: {}", e);
+        }
+    }
+
+    /// Up to `MAX_LIMIT` recommendations for `user`, ranked again only when a
+    /// snapshot or the user's interactions changed since the last time.
+    fn recommend(
+        &self,
+        store: &dyn PropertyStore,
+        sources: &[String],
+        user: &str,
+        hidden: &HideList,
+    ) -> Vec<Recommendation> {
+        let snapshots: BTreeMap<String, Option<NaiveDate>> =
+            sources.iter().map(|source| (source.clone(), store.snapshots(source).last().copied())).collect();
+        let engagement = self.for_user(user);
+        if let Some(ranking) = self.rankings.lock().unwrap().get(user) {
+            if ranking.snapshots == snapshots && ranking.engagement == engagement && ranking.hidden == *hidden {
+                return ranking.recommendations.clone();
+            }
+        }
+
+        let listings: Vec<StandardizedProperty> = sources.iter().flat_map(|source| store.latest(source)).collect();
+        // Clicks are recorded by short id alone; keep what the listing looks
+        // like the first time it is found
+        let seen: BTreeMap<&str, Profile> = listings
+            .iter()
+            .filter(|p| matches!(engagement.clicks.get(&p.short_id), Some(None)))
+            .map(|p| (p.short_id.as_str(), Profile::of(p)))
+            .collect();
+        let engagement = match seen.is_empty() {
+            true => engagement,
+            false => self
+                .update(user, |engagement| {
+                    for (short_id, profile) in seen {
+                        engagement.clicks.insert(short_id.to_string(), Some(profile));
+                    }
+                })
+                .unwrap_or_else(|e| {
+                    warn!("Could not save click-throughs: {}", e);
+                    self.for_user(user)
+                }),
+        };
+        let recommendations = rank(&listings, &engagement, hidden);
+        let ranking = Ranking {
+            snapshots,
+            engagement,
+            hidden: hidden.clone(),
+            recommendations: recommendations.clone(),
+        };
+        self.rankings.lock().unwrap().insert(user.to_string(), ranking);
+        recommendations
+    }
+}
+
+/// `listings` the user hasn't engaged with, most like what they engaged
+/// with first. Nothing is recommended before a favourite or click.
+pub fn rank(listings: &[StandardizedProperty], engagement: &Engagement, hidden: &HideList) -> Vec<Recommendation> {
+    let mut signals: Vec<(f64, &Profile)> = engagement.favourites.values().map(|p| (FAVOURITE_WEIGHT, p)).collect();
+    signals.extend(engagement.clicks.values().flatten().map(|profile| (CLICK_WEIGHT, profile)));
+    if signals.is_empty() {
+        return vec![];
+    }
+    let dislikes: Vec<Profile> = listings
+        .iter()
+        .filter(|p| hidden.properties.contains(&p.property_id))
+        .map(Profile::of)
+        .collect();
+    signals.extend(dislikes.iter().map(|profile| (HIDE_WEIGHT, profile)));
+    let total_weight: f64 = signals.iter().map(|(weight, _)| weight.abs()).sum();
+
+    let engaged: HashSet<&str> =
+        engagement.favourites.keys().chain(engagement.clicks.keys()).map(String::as_str).collect();
+    let mut recommendations: Vec<Recommendation> = listings
+        .iter()
+        .filter(|p| !hidden.hides(p) && !engaged.contains(p.short_id.as_str()))
+        .filter(|p| !p.ids().any(|id| engaged.contains(id)))
+        .filter_map(|p| {
+            let profile = Profile::of(p);
+            let score: f64 = signals.iter().map(|(weight, signal)| weight * profile.similarity(signal)).sum();
+            let score = (score / total_weight * 10_000.0).round() / 10_000.0;
+            (score > 0.0).then(|| Recommendation { score, listing: p.clone() })
+        })
+        .collect();
+    recommendations.sort_by(|a, b| b.score.total_cmp(&a.score));
+    recommendations.truncate(MAX_LIMIT);
+    recommendations
+}
+
+fn user(caller: &Caller) -> Result<&str, (StatusCode, String)> {
+    caller
+        .name
+        .as_deref()
+        .ok_or((StatusCode::UNAUTHORIZED, "Favourites and recommendations need an API key".to_string()))
+}
+
+async fn set_favourite(
+    state: AppState,
+    caller: Caller,
+    property_id: String,
+    favourite: bool,
+) -> Result<Json<BTreeSet<String>>, (StatusCode, String)> {
+    let user = user(&caller)?;
+    let property = state
+        .id_index
+        .lookup(&state.config, std::slice::from_ref(&property_id))
+        .into_iter()
+        .next()
+        .ok_or((StatusCode::NOT_FOUND, format!("No listing {}", property_id)))?;
+    let engagement = state.interactions.set_favourite(user, &property, favourite).map_err(|e| {
+        warn!("Could not save favourites: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Could not save favourites".to_string())
+    })?;
+    Ok(Json(engagement.favourites.into_keys().collect()))
+}
+
+pub async fn favourite(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(property_id): Path<String>,
+) -> Result<Json<BTreeSet<String>>, (StatusCode, String)> {
+    set_favourite(state, caller, property_id, true).await
+}
+
+pub async fn unfavourite(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(property_id): Path<String>,
+) -> Result<Json<BTreeSet<String>>, (StatusCode, String)> {
+    set_favourite(state, caller, property_id, false).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecommendationParams {
+    limit: Option<usize>,
+}
+
+pub async fn recommendations(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(params): Query<RecommendationParams>,
+) -> Result<Json<Vec<Recommendation>>, (StatusCode, String)> {
+    let user = user(&caller)?.to_string();
+    let hidden = state.hidden.for_caller(&caller);
+    let sources: Vec<String> = state.config.source_names(None).into_iter().map(String::from).collect();
+    let ranking = state.clone();
+    let mut recommendations = tokio::task::spawn_blocking(move || {
+        ranking.interactions.recommend(ranking.store.as_ref(), &sources, &user, &hidden)
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    recommendations.truncate(params.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT));
+
+    let mut listings: Vec<StandardizedProperty> = recommendations.iter().map(|r| r.listing.clone()).collect();
+    state.privacy.redact_all(&mut listings, &caller);
+    state.notes.attach_all(&mut listings, &caller);
+    state.photos.serve_offline(&mut listings);
+    for (recommendation, listing) in recommendations.iter_mut().zip(listings) {
+        recommendation.listing = listing;
+    }
+    Ok(Json(recommendations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::test_utils::listing;
+
+    fn rental(id: &str, beds: i32, area: &str, rent: f64, garden: bool) -> StandardizedProperty {
+        let mut property = listing("daft", id);
+        property.short_id = format!("s{}", id);
+        property.bedrooms = Some(beds);
+        property.address.normalized_address = address::normalize(&format!("{} Main Street, {}", id, area));
+        property.price.amount = rent;
+        property.amenities.garden = Some(garden);
+        property
+    }
+
+    #[test]
+    fn test_listings_like_favourites_rank_first() {
+        let store = MemoryStore::default();
+        let listings = [
+            rental("1", 2, "Dublin 6", 1800.0, true),
+            rental("2", 2, "Dublin 6", 1850.0, true),
+            rental("3", 1, "Cork", 1250.0, false),
+            rental("4", 2, "Dublin 6", 1900.0, false),
+            rental("5", 1, "Galway", 1200.0, false),
+        ];
+        store.ingest("daft", "2024-11-05".parse().unwrap(), &listings).unwrap();
+        let interactions = Interactions::open(None);
+        let sources = ["daft".to_string()];
+        let hidden = HideList::default();
+        assert!(interactions.recommend(&store, &sources, "alice", &hidden).is_empty());
+
+        interactions.set_favourite("alice", &listings[0], true).unwrap();
+        interactions.record_click("alice", "s5");
+        let ids = |recommendations: Vec<Recommendation>| -> Vec<String> {
+            recommendations.into_iter().map(|r| r.listing.source_id).collect()
+        };
+        assert_eq!(ids(interactions.recommend(&store, &sources, "alice", &hidden)), ["2", "4", "3"]);
+        // The click is kept with what the listing looked like
+        assert!(interactions.for_user("alice").clicks["s5"].is_some());
+
+        let hidden = HideList { properties: BTreeSet::from(["daft_2".to_string()]), ..HideList::default() };
+        assert_eq!(ids(interactions.recommend(&store, &sources, "alice", &hidden)), ["4", "3"]);
+    }
+}
@@ -0,0 +1,201 @@
+//! Reloading the config file without a restart.
+//!
+//! `POST /api/admin/config/reload`, or a SIGHUP, reads the config file again
+//! and swaps it in for the requests that follow. Whatever is read per request
+//! or per job applies at once: sources, presets, search settings, auth keys,
+//! rate limits, the snapshot token TTL, `stats.min_sample`, notifier channels,
+//! and the cache refresh interval from the next check on.
+//! The snapshot cache and id index carry over, so a warmed cache stays warm,
+//! unless a setting applied while listings are parsed changed (price bands,
+//! `[[price_bounds]]`, the agent register, which every reload reads again,
+//! the range point, commercial handling):
+//! then the cached snapshots are dropped and parsed again under it.
+//!
+//! Some settings are only read at startup and still need a restart: the
+//! listener, logging, the cache budget, warm-up and parse threads, the job
+//! queue, the files stores are opened from and the other schedule intervals.
+//! A reload that changes any of them applies the rest and lists them under
+//! `restart_required`.
+
+use axum::extract::{FromRef, State};
+use axum::http::StatusCode;
+use axum::Json;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::fmt::Debug;
+use std::sync::{Arc, RwLock};
+
+use crate::analytics;
+use crate::auth::{Caller, SCOPE_ADMIN};
+use crate::config::Config;
+use crate::state::AppState;
+
+/// Router state: the current `AppState`, replaced as a whole on reload.
+/// Handlers extract `State<AppState>` and get whichever was current when the
+/// request arrived.
+#[derive(Clone)]
+pub struct Live(Arc<RwLock<AppState>>);
+
+impl FromRef<Live> for AppState {
+    fn from_ref(live: &Live) -> AppState {
+        live.current()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Reloaded {
+    pub sources: Vec<String>,
+    /// Changed settings that won't apply until the next restart.
+    pub restart_required: Vec<&'static str>,
+}
+
+impl Live {
+    pub fn new(state: AppState) -> Self {
+        Live(Arc::new(RwLock::new(state)))
+    }
+
+    pub fn current(&self) -> AppState {
+        self.0.read().unwrap().clone()
+    }
+
+    /// Rebuilds the current state from its own config, picking up sources
+    /// disabled or enabled since; see `toggles`.
+    pub fn refresh(&self) {
+        let mut state = self.0.write().unwrap();
+        let config = Config::clone(&state.config);
+        *state = state.reconfigured(config);
+    }
+
+    /// Re-reads the config file and applies it.
+    pub fn reload(&self) -> Result<Reloaded, String> {
+        self.apply(Config::load()?)
+    }
+
+    pub fn apply(&self, config: Config) -> Result<Reloaded, String> {
+        let mut state = self.0.write().unwrap();
+        if config.demo.enabled != state.config.demo.enabled {
+            return Err("demo.enabled can't change without a restart".to_string());
+        }
+        let restart_required = restart_required(&state.config, &config);
+        for setting in &restart_required {
+            warn!("The reloaded config changes {}, which applies after a restart", setting);
+        }
+        analytics::sample::configure(config.stats.min_sample);
+        *state = state.reconfigured(config);
+        let sources = state.config.sources.iter().map(|source| source.name.clone()).collect();
+        Ok(Reloaded { sources, restart_required })
+    }
+}
+
+/// Settings read only at startup that differ between `old` and `new`.
+fn restart_required(old: &Config, new: &Config) -> Vec<&'static str> {
+    fn differs(old: &impl Debug, new: &impl Debug) -> bool {
+        format!("{:?}", old) != format!("{:?}", new)
+    }
+    let settings = [
+        ("data_path", differs(&old.data_path, &new.data_path)),
+        ("server", differs(&old.server, &new.server)),
+        ("logging", differs(&old.logging, &new.logging)),
+        ("cache.max_memory_mb", old.cache.max_memory_mb != new.cache.max_memory_mb),
+        ("cache.warm_up", old.cache.warm_up != new.cache.warm_up),
+        ("cache.parse_threads", old.cache.parse_threads != new.cache.parse_threads),
+        ("jobs", differs(&old.jobs, &new.jobs)),
+        ("clock", differs(&old.clock, &new.clock)),
+        ("ids", differs(&old.ids, &new.ids)),
+    ];
+    settings.into_iter().filter(|(_, changed)| *changed).map(|(setting, _)| setting).collect()
+}
+
+pub async fn reload_config(
+    State(live): State<Live>,
+    caller: Caller,
+) -> Result<Json<Reloaded>, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    let reloaded = live.reload();
+    live.current().audit.record(&caller, "config.reload", serde_json::json!({ "path": Config::path() }), &reloaded);
+    reloaded.map(Json).map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))
+}
+
+/// Reloads the config on every SIGHUP for the life of the process.
+#[cfg(unix)]
+pub fn reload_on_hangup(live: &Live) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let live = live.clone();
+    tokio::spawn(async move {
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(e) => {
+                error!("Could not listen for SIGHUP; reload the config through the API instead: {}", e);
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            match live.reload() {
+                Ok(reloaded) => info!("Reloaded config on SIGHUP with {} sources", reloaded.sources.len()),
+                Err(e) => error!("Could not reload config, keeping the current one: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn reload_on_hangup(_live: &Live) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::price_range::RangePoint;
+    use crate::test_utils::{stores_in, temp_dir};
+
+    #[test]
+    fn test_reload_keeps_cache_and_applies_settings() {
+        let dir = temp_dir("reload");
+        let live = Live::new(AppState::new(stores_in(Config::default(), &dir)));
+        let before = live.current();
+
+        let config = Config::from_toml(
+            r#"
+            [search]
+            snapshot_ttl_secs = 60
+            price_bands = [900.0]
+            price_range_point = "max"
+            commercial = true
+
+            [rate_limit]
+            burst = 1
+
+            [cache]
+            refresh_interval_secs = 5
+
+            [stats]
+            min_sample = 0
+
+            [[price_bounds]]
+            name = "rooms"
+            max = 1500
+
+            [[sources]]
+            name = "rent_ie"
+            "#,
+        )
+        .unwrap();
+        let reloaded = live.apply(stores_in(config, &dir)).unwrap();
+        assert!(reloaded.sources.contains(&"rent_ie".to_string()));
+        assert!(reloaded.restart_required.is_empty());
+
+        let after = live.current();
+        assert!(after.config.resolve_source("rent_ie").is_some());
+        assert!(after.config.sources.iter().all(|source| source.parsing.price_bands == [900.0]));
+        assert_eq!(after.config.parsing.price_bounds[0].name, "rooms");
+        assert_eq!(after.config.parsing.price_range_point, RangePoint::Max);
+        assert!(after.config.parsing.commercial);
+        assert!(Arc::ptr_eq(&before.cache, &after.cache));
+        assert!(Arc::ptr_eq(&before.id_index, &after.id_index));
+
+        let mut demo = Config::default();
+        demo.demo.enabled = true;
+        assert!(live.apply(demo).is_err());
+        assert_eq!(live.current().config.sources.len(), after.config.sources.len());
+    }
+}
@@ -0,0 +1,126 @@
+//! CSV rendering of a market report, for spreadsheets.
+//!
+//! One table with a `section` column, so every figure of the report fits in a
+//! single file: `section,key,listings,median_rent,previous_median_rent,change,share`.
+//! Keys are the machine values (period keys, normalized areas), not display labels.
+
+use super::market::{MarketReport, Share};
+
+fn field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn number(value: Option<f64>, decimals: usize) -> String {
+    value.map(|v| format!("{:.*}", decimals, v)).unwrap_or_default()
+}
+
+struct Row<'a> {
+    section: &'a str,
+    key: &'a str,
+    listings: usize,
+    median_rent: Option<f64>,
+    previous_median_rent: Option<f64>,
+    change: Option<f64>,
+    share: Option<f64>,
+}
+
+impl Row<'_> {
+    fn write(&self, out: &mut String) {
+        let fields = [
+            field(self.section),
+            field(self.key),
+            self.listings.to_string(),
+            number(self.median_rent, 2),
+            number(self.previous_median_rent, 2),
+            number(self.change, 4),
+            number(self.share, 4),
+        ];
+        out.push_str(&fields.join(","));
+        out.push_str("\r\n");
+    }
+}
+
+fn share_rows(out: &mut String, section: &str, shares: &[Share]) {
+    for share in shares {
+        Row {
+            section,
+            key: &share.key,
+            listings: share.listings,
+            median_rent: None,
+            previous_median_rent: None,
+            change: None,
+            share: Some(share.share),
+        }
+        .write(out);
+    }
+}
+
+pub fn render(report: &MarketReport) -> String {
+    let mut out = String::from("section,key,listings,median_rent,previous_median_rent,change,share\r\n");
+    let headline = &report.headline;
+    Row {
+        section: "headline",
+        key: &report.period,
+        listings: headline.listings,
+        median_rent: headline.median_rent,
+        previous_median_rent: headline.previous_median_rent,
+        change: headline.change,
+        share: None,
+    }
+    .write(&mut out);
+    for bedrooms in &headline.by_bedrooms {
+        Row {
+            section: "bedrooms",
+            key: &bedrooms.bedrooms,
+            listings: bedrooms.listings,
+            median_rent: bedrooms.median_rent,
+            previous_median_rent: None,
+            change: None,
+            share: None,
+        }
+        .write(&mut out);
+    }
+    for point in &report.trend {
+        Row {
+            section: "trend",
+            key: &point.period,
+            listings: point.listings,
+            median_rent: point.median_rent,
+            previous_median_rent: None,
+            change: None,
+            share: None,
+        }
+        .write(&mut out);
+    }
+    share_rows(&mut out, "supply_type", &report.supply.by_type);
+    share_rows(&mut out, "supply_bedrooms", &report.supply.by_bedrooms);
+    for mover in &report.top_movers {
+        Row {
+            section: "mover",
+            key: &mover.area,
+            listings: mover.listings,
+            median_rent: Some(mover.median_rent),
+            previous_median_rent: Some(mover.previous_median_rent),
+            change: Some(mover.change),
+            share: None,
+        }
+        .write(&mut out);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_quoting() {
+        assert_eq!(field("dublin 6"), "dublin 6");
+        assert_eq!(field("ranelagh, dublin 6"), "\"ranelagh, dublin 6\"");
+        assert_eq!(field("the \"green\""), "\"the \"\"green\"\"\"");
+    }
+}
@@ -0,0 +1,362 @@
+//! Figures for the market report of one area and period.
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+
+use crate::address;
+use crate::analytics::hedonic::{median, Granularity};
+use crate::analytics::sample;
+use crate::config::Config;
+use crate::geo;
+use crate::store::PropertyStore;
+use crate::{validate_price, StandardizedProperty};
+
+/// Periods of history shown in the trend, including the report period.
+const TREND_PERIODS: usize = 8;
+/// Sub-areas need this many listings in both periods to count as a mover.
+const MIN_MOVER_LISTINGS: usize = 5;
+const TOP_MOVERS: usize = 5;
+
+#[derive(Debug, Serialize)]
+pub struct BedroomMedian {
+    /// "studio", "1" to "3", "4+" or "unknown".
+    pub bedrooms: String,
+    pub listings: usize,
+    /// Null below `stats.min_sample` listings, as are the other medians here.
+    pub median_rent: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Headline {
+    pub listings: usize,
+    pub median_rent: Option<f64>,
+    pub previous_median_rent: Option<f64>,
+    /// Relative change in median rent from the previous period.
+    pub change: Option<f64>,
+    pub by_bedrooms: Vec<BedroomMedian>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrendPoint {
+    pub period: String,
+    pub listings: usize,
+    pub median_rent: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Share {
+    pub key: String,
+    pub listings: usize,
+    pub share: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Supply {
+    pub by_type: Vec<Share>,
+    pub by_bedrooms: Vec<Share>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Mover {
+    pub area: String,
+    pub listings: usize,
+    pub median_rent: f64,
+    pub previous_median_rent: f64,
+    pub change: f64,
+}
+
+/// Which listings a report covers.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReportFilter {
+    /// Any location the search filter accepts; the whole country if absent.
+    pub area: Option<String>,
+    /// Exact bedroom count; 0 for studios.
+    pub bedrooms: Option<i32>,
+    pub source: Option<String>,
+    /// Only listings whose coordinates fall inside; see `geo`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub polygon: Option<Vec<geo::Point>>,
+}
+
+impl ReportFilter {
+    /// The area in normalized form; empty for the whole country.
+    pub fn normalized_area(&self) -> String {
+        address::normalize(self.area.as_deref().unwrap_or_default())
+    }
+
+    pub fn matches(&self, area: &str, property: &StandardizedProperty) -> bool {
+        self.bedrooms.is_none_or(|bedrooms| property.bedrooms == Some(bedrooms))
+            && address::in_location(&property.address.normalized_address, area)
+            && self.polygon.as_ref().is_none_or(|polygon| {
+                property.coordinates.is_some_and(|point| geo::contains(polygon, point))
+            })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MarketReport {
+    /// Normalized location the report covers; empty for the whole country.
+    pub area: String,
+    pub bedrooms: Option<i32>,
+    pub period: String,
+    pub granularity: Granularity,
+    pub previous_period: Option<String>,
+    pub headline: Headline,
+    /// Oldest first, ending with the report period.
+    pub trend: Vec<TrendPoint>,
+    pub supply: Supply,
+    pub top_movers: Vec<Mover>,
+}
+
+/// Granularity of a period key in any of the forms `Granularity::label`
+/// produces ("2024-11-05", "2024-W45", "2024-11", "2024-Q4").
+pub fn period_granularity(period: &str) -> Option<Granularity> {
+    let number_in = |text: &str, range: std::ops::RangeInclusive<u32>| {
+        text.parse::<u32>().ok().is_some_and(|n| range.contains(&n))
+    };
+    if let Some((year, quarter)) = period.split_once("-Q") {
+        return (number_in(year, 1900..=9999) && number_in(quarter, 1..=4)).then_some(Granularity::Quarter);
+    }
+    if let Some((year, week)) = period.split_once("-W") {
+        return (number_in(year, 1900..=9999) && number_in(week, 1..=53)).then_some(Granularity::Week);
+    }
+    if NaiveDate::parse_from_str(period, "%Y-%m-%d").is_ok() {
+        return Some(Granularity::Day);
+    }
+    NaiveDate::parse_from_str(&format!("{}-01", period), "%Y-%m-%d")
+        .ok()
+        .map(|_| Granularity::Month)
+}
+
+pub fn bedrooms_key(property: &StandardizedProperty) -> &'static str {
+    match property.bedrooms {
+        Some(0) => "studio",
+        Some(1) => "1",
+        Some(2) => "2",
+        Some(3) => "3",
+        Some(_) => "4+",
+        None => "unknown",
+    }
+}
+
+pub fn property_type_key(property: &StandardizedProperty) -> String {
+    let key = property.property_type.trim().to_lowercase();
+    if key.is_empty() { "unknown".to_string() } else { key }
+}
+
+fn rents<'a>(listings: impl IntoIterator<Item = &'a StandardizedProperty>) -> Vec<f64> {
+    listings.into_iter().map(|p| p.price.amount).collect()
+}
+
+/// Listing counts per key, largest first, with their share of the total.
+pub fn shares<'a>(
+    listings: &[&'a StandardizedProperty],
+    key: impl Fn(&'a StandardizedProperty) -> String,
+) -> Vec<Share> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for property in listings {
+        *counts.entry(key(property)).or_insert(0) += 1;
+    }
+    let mut shares: Vec<Share> = counts
+        .into_iter()
+        .map(|(key, n)| Share { key, listings: n, share: n as f64 / listings.len() as f64 })
+        .collect();
+    shares.sort_by(|a, b| b.listings.cmp(&a.listings).then_with(|| a.key.cmp(&b.key)));
+    shares
+}
+
+fn by_sub_area<'a>(listings: &[&'a StandardizedProperty]) -> HashMap<&'a str, Vec<&'a StandardizedProperty>> {
+    let mut areas: HashMap<&str, Vec<&StandardizedProperty>> = HashMap::new();
+    for property in listings {
+        areas.entry(address::area(&property.address.normalized_address)).or_default().push(property);
+    }
+    areas
+}
+
+fn top_movers(current: &[&StandardizedProperty], previous: &[&StandardizedProperty]) -> Vec<Mover> {
+    let min_listings = MIN_MOVER_LISTINGS.max(sample::min_sample());
+    let previous = by_sub_area(previous);
+    let mut movers: Vec<Mover> = by_sub_area(current)
+        .into_iter()
+        .filter(|(area, listings)| !area.is_empty() && listings.len() >= min_listings)
+        .filter_map(|(area, listings)| {
+            let before = previous.get(area).filter(|before| before.len() >= min_listings)?;
+            let (median_rent, previous_median_rent) =
+                (median(rents(listings.iter().copied())), median(rents(before.iter().copied())));
+            Some(Mover {
+                area: area.to_string(),
+                listings: listings.len(),
+                median_rent,
+                previous_median_rent,
+                change: median_rent / previous_median_rent - 1.0,
+            })
+        })
+        .collect();
+    movers.sort_by(|a, b| b.change.abs().total_cmp(&a.change.abs()).then_with(|| a.area.cmp(&b.area)));
+    movers.truncate(TOP_MOVERS);
+    movers
+}
+
+/// Listings of each period by property id. A listing seen in several
+/// snapshots of a period keeps its latest version, given observations oldest
+/// snapshot first.
+pub fn by_period(
+    observations: Vec<(String, StandardizedProperty)>,
+) -> BTreeMap<String, HashMap<String, StandardizedProperty>> {
+    let mut periods: BTreeMap<String, HashMap<String, StandardizedProperty>> = BTreeMap::new();
+    for (label, property) in observations {
+        periods.entry(label).or_default().insert(property.property_id.clone(), property);
+    }
+    periods
+}
+
+/// Builds the report for `period` from `(period, listing)` observations of the
+/// area, oldest snapshot first. Like the rent index, a listing seen in several
+/// snapshots of a period counts once, as its latest version. `None` when the
+/// period has no listings.
+pub fn build(
+    filter: &ReportFilter,
+    period: &str,
+    granularity: Granularity,
+    mut observations: Vec<(String, StandardizedProperty)>,
+) -> Option<MarketReport> {
+    observations.retain(|(label, _)| label.as_str() <= period);
+    let periods = by_period(observations);
+    let current: Vec<&StandardizedProperty> = periods.get(period)?.values().collect();
+    let (previous_period, previous) = match periods.range::<str, _>((Bound::Unbounded, Bound::Excluded(period))).next_back() {
+        Some((label, listings)) => (Some(label.clone()), listings.values().collect()),
+        None => (None, vec![]),
+    };
+
+    let median_rent = sample::suppress(median(rents(current.iter().copied())), current.len());
+    let previous_median_rent = (!previous.is_empty())
+        .then(|| sample::suppress(median(rents(previous.iter().copied())), previous.len()))
+        .flatten();
+    let mut bedrooms: BTreeMap<&str, Vec<f64>> = BTreeMap::new();
+    for property in &current {
+        bedrooms.entry(bedrooms_key(property)).or_default().push(property.price.amount);
+    }
+    let headline = Headline {
+        listings: current.len(),
+        median_rent,
+        previous_median_rent,
+        change: median_rent.zip(previous_median_rent).map(|(current, previous)| current / previous - 1.0),
+        by_bedrooms: bedrooms
+            .into_iter()
+            .map(|(key, rents)| {
+                let listings = rents.len();
+                BedroomMedian { bedrooms: key.to_string(), listings, median_rent: sample::suppress(median(rents), listings) }
+            })
+            .collect(),
+    };
+
+    let skip = periods.len().saturating_sub(TREND_PERIODS);
+    let trend = periods
+        .iter()
+        .skip(skip)
+        .map(|(label, listings)| TrendPoint {
+            period: label.clone(),
+            listings: listings.len(),
+            median_rent: sample::suppress(median(rents(listings.values())), listings.len()),
+        })
+        .collect();
+
+    Some(MarketReport {
+        area: filter.normalized_area(),
+        bedrooms: filter.bedrooms,
+        period: period.to_string(),
+        granularity,
+        previous_period,
+        supply: Supply {
+            by_type: shares(&current, property_type_key),
+            by_bedrooms: shares(&current, |p| bedrooms_key(p).to_string()),
+        },
+        top_movers: top_movers(&current, &previous),
+        headline,
+        trend,
+    })
+}
+
+/// `(period, listing)` observations matching `filter` from every snapshot of
+/// the selected sources, oldest first. Blocking.
+pub fn load(
+    config: &Config,
+    store: &dyn PropertyStore,
+    filter: &ReportFilter,
+    granularity: Granularity,
+) -> Vec<(String, StandardizedProperty)> {
+    let area = filter.normalized_area();
+    let mut observations = Vec::new();
+    for source in config.source_names(filter.source.as_deref()) {
+        for date in store.snapshots(source) {
+            let period = granularity.label(date);
+            observations.extend(
+                store
+                    .listings(source, date)
+                    .into_iter()
+                    .filter(validate_price)
+                    .filter(|p| filter.matches(&area, p))
+                    .map(|p| (period.clone(), p)),
+            );
+        }
+    }
+    observations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::listing;
+
+    fn rental(id: usize, area: &str, beds: i32, rent: f64) -> StandardizedProperty {
+        let mut property = listing("daft", &id.to_string());
+        property.address.normalized_address = address::normalize(&format!("{} Main Street, {}", id, area));
+        property.property_type = if beds > 2 { "House" } else { "Apartment" }.to_string();
+        property.bedrooms = Some(beds);
+        property.price.amount = rent;
+        property
+    }
+
+    #[test]
+    fn test_period_granularity() {
+        assert_eq!(period_granularity("2024-Q4"), Some(Granularity::Quarter));
+        assert_eq!(period_granularity("2024-W45"), Some(Granularity::Week));
+        assert_eq!(period_granularity("2024-11"), Some(Granularity::Month));
+        assert_eq!(period_granularity("2024-11-05"), Some(Granularity::Day));
+        assert_eq!(period_granularity("2024-Q5"), None);
+        assert_eq!(period_granularity("2024-13"), None);
+    }
+
+    #[test]
+    fn test_build_report() {
+        let mut observations = Vec::new();
+        for i in 0..6 {
+            observations.push(("2024-Q3".to_string(), rental(i, "Dublin 6", 2, 2000.0)));
+            observations.push(("2024-Q3".to_string(), rental(100 + i, "Dublin 8", 1, 1500.0)));
+            observations.push(("2024-Q4".to_string(), rental(200 + i, "Dublin 6", 2, 2200.0)));
+            observations.push(("2024-Q4".to_string(), rental(300 + i, "Dublin 8", 3, 1500.0)));
+            // Later periods are ignored
+            observations.push(("2025-Q1".to_string(), rental(400 + i, "Dublin 8", 1, 9000.0)));
+        }
+        // Seen again in a later snapshot of the quarter: counts once
+        observations.push(("2024-Q4".to_string(), rental(200, "Dublin 6", 2, 2200.0)));
+
+        let filter = ReportFilter { area: Some("Co. Dublin".to_string()), ..Default::default() };
+        let report = build(&filter, "2024-Q4", Granularity::Quarter, observations).unwrap();
+        assert_eq!(report.area, "county dublin");
+        assert_eq!(report.previous_period.as_deref(), Some("2024-Q3"));
+        assert_eq!(report.headline.listings, 12);
+        assert_eq!(report.headline.median_rent, Some(1850.0));
+        assert_eq!(report.headline.previous_median_rent, Some(1750.0));
+        assert_eq!(report.trend.iter().map(|p| p.period.as_str()).collect::<Vec<_>>(), ["2024-Q3", "2024-Q4"]);
+        assert_eq!(report.supply.by_type[0].share, 0.5);
+
+        assert_eq!(report.top_movers.len(), 2);
+        assert_eq!(report.top_movers[0].area, "dublin 6");
+        assert!((report.top_movers[0].change - 0.1).abs() < 1e-9);
+
+        assert!(build(&filter, "2023-Q1", Granularity::Quarter, vec![]).is_none());
+    }
+}
@@ -0,0 +1,111 @@
+//! Generated market reports.
+//!
+//! A report is built in three steps: `market` computes the figures for an area
+//! and period, `template` lays them out as a document in the requested
+//! language, and a renderer (`pdf`) turns the document into a file. `csv`
+//! renders the figures directly. Recurring deliveries live in `schedule`, and
+//! users' weekly digests of the areas they watch in `watch`.
+
+pub mod csv;
+pub mod market;
+pub mod pdf;
+pub mod schedule;
+pub mod template;
+pub mod watch;
+
+use axum::extract::{Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+
+use self::market::{MarketReport, ReportFilter};
+use crate::config::Config;
+use crate::locale::Lang;
+use crate::state::AppState;
+use crate::store::PropertyStore;
+
+#[derive(Debug, Deserialize)]
+pub struct MarketParams {
+    area: Option<String>,
+    bedrooms: Option<i32>,
+    source: Option<String>,
+    /// "2024-Q4", "2024-11", "2024-W45" or "2024-11-05".
+    period: String,
+}
+
+/// "market-dublin-6-2bed-2024-Q4"
+pub fn file_stem(report: &MarketReport) -> String {
+    let mut parts: Vec<String> = report
+        .area
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(str::to_string)
+        .collect();
+    if parts.is_empty() {
+        parts.push("ireland".to_string());
+    }
+    if let Some(bedrooms) = report.bedrooms {
+        parts.push(format!("{}bed", bedrooms));
+    }
+    format!("market-{}-{}", parts.join("-"), report.period)
+}
+
+/// Builds the report for `period`. Blocking.
+pub fn generate(
+    config: &Config,
+    store: &dyn PropertyStore,
+    filter: &ReportFilter,
+    period: &str,
+) -> Result<MarketReport, (StatusCode, String)> {
+    let granularity = market::period_granularity(period).ok_or((
+        StatusCode::BAD_REQUEST,
+        format!("Invalid period {:?}; use e.g. 2024-Q4, 2024-11, 2024-W45 or 2024-11-05", period),
+    ))?;
+    let observations = market::load(config, store, filter, granularity);
+    market::build(filter, period, granularity, observations).ok_or_else(|| {
+        let area = filter.normalized_area();
+        (
+            StatusCode::NOT_FOUND,
+            format!("No listings for {} in {}", if area.is_empty() { "any area" } else { &area }, period),
+        )
+    })
+}
+
+/// The report `params` ask for, built on the blocking pool.
+async fn generate_from(state: &AppState, params: MarketParams) -> Result<MarketReport, (StatusCode, String)> {
+    let filter = ReportFilter { area: params.area, bedrooms: params.bedrooms, source: params.source, polygon: None };
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || generate(&state.config, state.store.as_ref(), &filter, &params.period))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+}
+
+fn attachment(content_type: &str, file_name: String, body: Vec<u8>) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, format!("inline; filename=\"{}\"", file_name)),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+pub async fn market_pdf(
+    State(state): State<AppState>,
+    lang: Lang,
+    Query(params): Query<MarketParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let report = generate_from(&state, params).await?;
+    let pdf = pdf::render(&template::market(&report, lang, state.clock.today()));
+    Ok(attachment("application/pdf", format!("{}.pdf", file_stem(&report)), pdf))
+}
+
+pub async fn market_csv(
+    State(state): State<AppState>,
+    Query(params): Query<MarketParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let report = generate_from(&state, params).await?;
+    let csv = csv::render(&report).into_bytes();
+    Ok(attachment("text/csv; charset=utf-8", format!("{}.csv", file_stem(&report)), csv))
+}
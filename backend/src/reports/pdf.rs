@@ -0,0 +1,322 @@
+//! Minimal PDF renderer for report documents.
+//!
+//! Writes A4 pages using the standard Helvetica fonts, which every viewer
+//! has, so nothing needs embedding. Text is WinAnsi-encoded: Latin-1 covers
+//! Irish fadas, and the euro sign has its own code. Widths are estimated
+//! rather than measured, which is good enough for tables of short values.
+
+use std::fmt::Write;
+
+use super::template::{Block, Document};
+
+const PAGE_WIDTH: f64 = 595.0;
+const PAGE_HEIGHT: f64 = 842.0;
+const MARGIN: f64 = 50.0;
+const CONTENT_WIDTH: f64 = PAGE_WIDTH - 2.0 * MARGIN;
+const BODY_SIZE: f64 = 10.0;
+const CHART_HEIGHT: f64 = 170.0;
+/// Average Helvetica glyph width as a fraction of the font size.
+const GLYPH_WIDTH: f64 = 0.52;
+
+#[derive(Clone, Copy)]
+enum Font {
+    Regular,
+    Bold,
+}
+
+impl Font {
+    fn resource(self) -> &'static str {
+        match self {
+            Font::Regular => "F1",
+            Font::Bold => "F2",
+        }
+    }
+}
+
+/// A PDF string literal in WinAnsi encoding.
+fn literal(text: &str) -> String {
+    let mut out = String::from("(");
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            ' '..='~' => out.push(c),
+            '€' => out.push_str("\\200"),
+            '…' => out.push_str("\\205"),
+            '\u{a0}'..='\u{ff}' => {
+                let _ = write!(out, "\\{:03o}", c as u32);
+            }
+            _ => out.push('?'),
+        }
+    }
+    out.push(')');
+    out
+}
+
+fn text_width(text: &str, size: f64) -> f64 {
+    text.chars().count() as f64 * size * GLYPH_WIDTH
+}
+
+/// Cuts `text` to fit `width`, marking the cut.
+fn fit(text: &str, width: f64, size: f64) -> String {
+    if text_width(text, size) <= width {
+        return text.to_string();
+    }
+    let keep = ((width / (size * GLYPH_WIDTH)) as usize).saturating_sub(1);
+    format!("{}…", text.chars().take(keep).collect::<String>())
+}
+
+fn wrap(text: &str, width: f64, size: f64) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let candidate = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+        if !line.is_empty() && text_width(&candidate, size) > width {
+            lines.push(std::mem::replace(&mut line, word.to_string()));
+        } else {
+            line = candidate;
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Content streams of the pages laid out so far, and the cursor on the
+/// current one.
+struct Layout {
+    pages: Vec<String>,
+    ops: String,
+    y: f64,
+}
+
+impl Layout {
+    fn new() -> Self {
+        Layout { pages: Vec::new(), ops: String::new(), y: PAGE_HEIGHT - MARGIN }
+    }
+
+    /// Starts a new page unless `height` still fits on this one.
+    fn reserve(&mut self, height: f64) {
+        if self.y - height < MARGIN && !self.ops.is_empty() {
+            self.pages.push(std::mem::take(&mut self.ops));
+            self.y = PAGE_HEIGHT - MARGIN;
+        }
+    }
+
+    fn text(&mut self, font: Font, size: f64, x: f64, y: f64, text: &str) {
+        let _ = writeln!(self.ops, "BT /{} {} Tf {:.1} {:.1} Td {} Tj ET", font.resource(), size, x, y, literal(text));
+    }
+
+    fn polyline(&mut self, width: f64, gray: f64, points: &[(f64, f64)]) {
+        let Some(((x, y), rest)) = points.split_first() else {
+            return;
+        };
+        let _ = write!(self.ops, "{:.2} w {:.2} G {:.1} {:.1} m", width, gray, x, y);
+        for (x, y) in rest {
+            let _ = write!(self.ops, " {:.1} {:.1} l", x, y);
+        }
+        self.ops.push_str(" S\n");
+    }
+
+    fn title(&mut self, text: &str) {
+        let size = 16.0;
+        for line in wrap(text, CONTENT_WIDTH, size) {
+            self.reserve(size * 1.4);
+            self.y -= size * 1.4;
+            self.text(Font::Bold, size, MARGIN, self.y, &line);
+        }
+        self.y -= 6.0;
+    }
+
+    fn heading(&mut self, text: &str) {
+        let size = 12.0;
+        // Keep a heading with at least a few lines of what follows
+        self.reserve(size * 2.0 + BODY_SIZE * 6.0);
+        self.y -= size * 2.0;
+        self.text(Font::Bold, size, MARGIN, self.y, text);
+        self.y -= 4.0;
+    }
+
+    fn paragraph(&mut self, text: &str) {
+        for line in wrap(text, CONTENT_WIDTH, BODY_SIZE) {
+            self.reserve(BODY_SIZE * 1.5);
+            self.y -= BODY_SIZE * 1.5;
+            self.text(Font::Regular, BODY_SIZE, MARGIN, self.y, &line);
+        }
+    }
+
+    fn table_row(&mut self, font: Font, cells: &[String], column_width: f64) {
+        self.y -= BODY_SIZE * 1.6;
+        for (i, cell) in cells.iter().enumerate() {
+            let cell = fit(cell, column_width - 6.0, BODY_SIZE);
+            self.text(font, BODY_SIZE, MARGIN + i as f64 * column_width, self.y, &cell);
+        }
+    }
+
+    fn table_header(&mut self, columns: &[String], column_width: f64) {
+        self.table_row(Font::Bold, columns, column_width);
+        let rule = self.y - 4.0;
+        self.polyline(0.5, 0.5, &[(MARGIN, rule), (MARGIN + CONTENT_WIDTH, rule)]);
+        self.y -= 2.0;
+    }
+
+    /// Repeats the header row on every page the table spans.
+    fn table(&mut self, columns: &[String], rows: &[Vec<String>]) {
+        let row_height = BODY_SIZE * 1.6;
+        let column_width = CONTENT_WIDTH / columns.len().max(1) as f64;
+        self.reserve(row_height * 3.0);
+        self.table_header(columns, column_width);
+        for row in rows {
+            if self.y - row_height < MARGIN {
+                self.reserve(f64::INFINITY);
+                self.table_header(columns, column_width);
+            }
+            self.table_row(Font::Regular, row, column_width);
+        }
+        self.y -= 4.0;
+    }
+
+    fn line_chart(&mut self, labels: &[String], values: &[f64]) {
+        if values.is_empty() {
+            return;
+        }
+        let label_size = 8.0;
+        self.reserve(CHART_HEIGHT + label_size * 3.0);
+        let left = MARGIN + 45.0;
+        let (bottom, top) = (self.y - CHART_HEIGHT, self.y - 8.0);
+        let right = MARGIN + CONTENT_WIDTH;
+
+        let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let pad = ((max - min) * 0.1).max(max.abs() * 0.02).max(1.0);
+        let (low, high) = (min - pad, max + pad);
+        let step = if values.len() > 1 { (right - left) / (values.len() - 1) as f64 } else { 0.0 };
+        let x_at = |i: usize| if values.len() > 1 { left + i as f64 * step } else { (left + right) / 2.0 };
+        let y_at = |value: f64| bottom + (value - low) / (high - low) * (top - bottom);
+
+        self.polyline(0.5, 0.5, &[(left, top), (left, bottom), (right, bottom)]);
+        for value in [low, high] {
+            let label = super::template::euros(value);
+            let y = y_at(value);
+            self.text(Font::Regular, label_size, MARGIN, y - label_size / 3.0, &label);
+        }
+        let points: Vec<(f64, f64)> = values.iter().enumerate().map(|(i, v)| (x_at(i), y_at(*v))).collect();
+        self.polyline(1.5, 0.0, &points);
+
+        // Label as many points as fit without overlapping
+        let widest = labels.iter().map(|l| text_width(l, label_size)).fold(0.0, f64::max) + 6.0;
+        let every = if step > 0.0 { (widest / step).ceil().max(1.0) as usize } else { 1 };
+        for (i, label) in labels.iter().enumerate().filter(|(i, _)| i % every == 0) {
+            let x = x_at(i) - text_width(label, label_size) / 2.0;
+            self.text(Font::Regular, label_size, x.max(MARGIN), bottom - label_size * 1.6, label);
+        }
+        self.y = bottom - label_size * 2.5;
+    }
+
+    fn finish(mut self) -> Vec<String> {
+        if !self.ops.is_empty() || self.pages.is_empty() {
+            self.pages.push(self.ops);
+        }
+        self.pages
+    }
+}
+
+/// Renders `document` as a complete PDF file.
+pub fn render(document: &Document) -> Vec<u8> {
+    let mut layout = Layout::new();
+    layout.title(&document.title);
+    for block in &document.blocks {
+        match block {
+            Block::Heading(text) => layout.heading(text),
+            Block::Text(text) => layout.paragraph(text),
+            Block::Table { columns, rows } => layout.table(columns, rows),
+            Block::LineChart { labels, values } => layout.line_chart(labels, values),
+        }
+    }
+    let pages = layout.finish();
+
+    // 1 catalog, 2 page tree, 3-4 fonts, 5 info, then a page and its content
+    // stream per page
+    let page_ids: Vec<usize> = (0..pages.len()).map(|i| 6 + 2 * i).collect();
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            page_ids.iter().map(|id| format!("{} 0 R", id)).collect::<Vec<_>>().join(" "),
+            pages.len()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_string(),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>".to_string(),
+        format!("<< /Title {} /Producer (market-analysis) >>", literal(&document.title)),
+    ];
+    for (page, id) in pages.iter().zip(&page_ids) {
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+             /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+            PAGE_WIDTH,
+            PAGE_HEIGHT,
+            id + 1
+        ));
+        objects.push(format!("<< /Length {} >>\nstream\n{}endstream", page.len(), page));
+    }
+
+    let mut out: Vec<u8> = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(out.len());
+        out.extend(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).as_bytes());
+    }
+    let xref = out.len();
+    let mut trailer = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        let _ = writeln!(trailer, "{:010} 00000 n ", offset);
+    }
+    let _ = write!(
+        trailer,
+        "trailer\n<< /Size {} /Root 1 0 R /Info 5 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    );
+    out.extend(trailer.as_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literal_encoding() {
+        assert_eq!(literal("€1,850 (2 beds)"), "(\\2001,850 \\(2 beds\\))");
+        assert_eq!(literal("Márta"), "(M\\341rta)");
+        assert_eq!(literal("→"), "(?)");
+    }
+
+    #[test]
+    fn test_render_paginates_and_indexes_objects() {
+        let rows: Vec<Vec<String>> = (0..120).map(|i| vec![i.to_string(), "x".to_string()]).collect();
+        let document = Document {
+            title: "Report".to_string(),
+            blocks: vec![
+                Block::Heading("Trend".to_string()),
+                Block::LineChart { labels: vec!["a".into(), "b".into()], values: vec![1000.0, 1100.0] },
+                Block::Table { columns: vec!["n".into(), "x".into()], rows },
+            ],
+        };
+        let pdf = render(&document);
+        assert!(pdf.starts_with(b"%PDF-1.4"));
+        // Everything after the binary marker comment is ASCII
+        let text = std::str::from_utf8(&pdf[15..]).unwrap();
+        assert!(text.contains("/Count 4"), "expected four pages");
+
+        // startxref points at the xref table, whose entries point at the objects
+        let startxref: usize = text.rsplit("startxref\n").next().unwrap().lines().next().unwrap().parse().unwrap();
+        assert!(pdf[startxref..].starts_with(b"xref"));
+        let first = text[startxref - 15..].lines().nth(3).unwrap();
+        let offset: usize = first[..10].parse().unwrap();
+        assert!(pdf[offset..].starts_with(b"1 0 obj"));
+    }
+}
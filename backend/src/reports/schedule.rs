@@ -0,0 +1,524 @@
+//! Recurring report delivery.
+//!
+//! A schedule names the listings to report on, how often (every week, month or
+//! quarter) and who gets it. At `reports.send_hour` on the first day of each
+//! period the report for the period just ended is queued once per recipient:
+//! email recipients get the PDF and/or CSV attached, webhook recipients a
+//! summary with download links. Schedules are managed through
+//! `/api/admin/reports` and kept in `reports.schedules_path`.
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use super::market::{MarketReport, ReportFilter};
+use super::{csv, file_stem, generate, pdf, template};
+use crate::analytics::hedonic::Granularity;
+use crate::auth::{Caller, SCOPE_ADMIN};
+use crate::config::Config;
+use crate::email::{self, Attachment, Email};
+use crate::locale::Lang;
+use crate::notifier;
+use crate::state::AppState;
+
+/// Job kind that delivers one report to one recipient.
+pub const JOB: &str = "reports.deliver";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Pdf,
+    Csv,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Pdf => "pdf",
+            Format::Csv => "csv",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Pdf => "application/pdf",
+            Format::Csv => "text/csv",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Recipient {
+    Email { address: String },
+    /// Slack-compatible incoming webhook.
+    Webhook { url: String },
+}
+
+fn default_formats() -> Vec<Format> {
+    vec![Format::Pdf]
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScheduleRequest {
+    pub name: String,
+    #[serde(flatten)]
+    pub filter: ReportFilter,
+    pub every: Granularity,
+    #[serde(default = "default_formats")]
+    pub formats: Vec<Format>,
+    #[serde(default)]
+    pub lang: Lang,
+    pub recipients: Vec<Recipient>,
+}
+
+impl ScheduleRequest {
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("name is required".to_string());
+        }
+        if self.formats.is_empty() {
+            return Err("Give at least one format".to_string());
+        }
+        if self.recipients.is_empty() {
+            return Err("Give at least one recipient".to_string());
+        }
+        for recipient in &self.recipients {
+            match recipient {
+                Recipient::Email { address } if !address.contains('@') => {
+                    return Err(format!("Invalid email address {:?}", address))
+                }
+                Recipient::Webhook { url } if reqwest::Url::parse(url).is_err() => {
+                    return Err(format!("Invalid webhook URL {:?}", url))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: u64,
+    pub name: String,
+    #[serde(flatten)]
+    pub filter: ReportFilter,
+    pub every: Granularity,
+    pub formats: Vec<Format>,
+    pub lang: Lang,
+    pub recipients: Vec<Recipient>,
+    pub created_at: DateTime<Utc>,
+    pub next_run: DateTime<Utc>,
+    pub last_run: Option<DateTime<Utc>>,
+    /// Period covered by the last run.
+    pub last_period: Option<String>,
+}
+
+/// First day of the period containing `date`.
+fn period_start(every: Granularity, date: NaiveDate) -> NaiveDate {
+    match every {
+        Granularity::Day => date,
+        Granularity::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+        Granularity::Month => date.with_day(1).unwrap_or(date),
+        Granularity::Quarter => {
+            NaiveDate::from_ymd_opt(date.year(), (date.month() - 1) / 3 * 3 + 1, 1).unwrap_or(date)
+        }
+    }
+}
+
+/// First day of the period after the one containing `date`.
+pub(super) fn next_period_start(every: Granularity, date: NaiveDate) -> NaiveDate {
+    let start = period_start(every, date);
+    match every {
+        Granularity::Day => start + Duration::days(1),
+        Granularity::Week => start + Duration::days(7),
+        Granularity::Month => start + Months::new(1),
+        Granularity::Quarter => start + Months::new(3),
+    }
+}
+
+pub(super) fn send_time(date: NaiveDate, send_hour: u32) -> DateTime<Utc> {
+    date.and_hms_opt(send_hour.min(23), 0, 0).unwrap_or_default().and_utc()
+}
+
+/// The first send time after `now`.
+pub(super) fn first_run(every: Granularity, now: DateTime<Utc>, send_hour: u32) -> DateTime<Utc> {
+    let this_period = send_time(period_start(every, now.date_naive()), send_hour);
+    if this_period > now {
+        this_period
+    } else {
+        send_time(next_period_start(every, now.date_naive()), send_hour)
+    }
+}
+
+/// The last period that had ended by `at`.
+pub(super) fn completed_period(every: Granularity, at: DateTime<Utc>) -> String {
+    let date = at.date_naive();
+    every.label(period_start(every, date).pred_opt().unwrap_or(date))
+}
+
+pub struct ReportSchedules {
+    path: Option<PathBuf>,
+    schedules: RwLock<Vec<Schedule>>,
+}
+
+impl ReportSchedules {
+    /// Loads schedules from `path`. `None` keeps them in memory only.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let schedules = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        ReportSchedules { path, schedules: RwLock::new(schedules) }
+    }
+
+    fn save(&self, schedules: &[Schedule]) -> Result<(), String> {
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+            let contents = serde_json::to_string_pretty(schedules).map_err(|e| e.to_string())?;
+            fs::write(path, contents).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<Schedule> {
+        self.schedules.read().unwrap().clone()
+    }
+
+    pub fn get(&self, id: u64) -> Option<Schedule> {
+        self.schedules.read().unwrap().iter().find(|s| s.id == id).cloned()
+    }
+
+    pub fn create(&self, request: ScheduleRequest, now: DateTime<Utc>, send_hour: u32) -> Result<Schedule, String> {
+        request.validate()?;
+        let mut schedules = self.schedules.write().unwrap();
+        let schedule = Schedule {
+            id: schedules.iter().map(|s| s.id).max().unwrap_or(0) + 1,
+            name: request.name,
+            filter: request.filter,
+            every: request.every,
+            formats: request.formats,
+            lang: request.lang,
+            recipients: request.recipients,
+            created_at: now,
+            next_run: first_run(request.every, now, send_hour),
+            last_run: None,
+            last_period: None,
+        };
+        schedules.push(schedule.clone());
+        self.save(&schedules)?;
+        Ok(schedule)
+    }
+
+    pub fn delete(&self, id: u64) -> Result<bool, String> {
+        let mut schedules = self.schedules.write().unwrap();
+        let before = schedules.len();
+        schedules.retain(|s| s.id != id);
+        if schedules.len() == before {
+            return Ok(false);
+        }
+        self.save(&schedules)?;
+        Ok(true)
+    }
+
+    /// Schedules due at `now` with the period each should report on. Their
+    /// next run moves to the start of the following period, so a schedule
+    /// that was due several times while the service was down runs once.
+    pub fn take_due(&self, now: DateTime<Utc>, send_hour: u32) -> Result<Vec<(Schedule, String)>, String> {
+        let mut schedules = self.schedules.write().unwrap();
+        let mut due = Vec::new();
+        for schedule in schedules.iter_mut().filter(|s| s.next_run <= now) {
+            let period = completed_period(schedule.every, now);
+            schedule.last_run = Some(now);
+            schedule.last_period = Some(period.clone());
+            schedule.next_run = send_time(next_period_start(schedule.every, now.date_naive()), send_hour);
+            due.push((schedule.clone(), period));
+        }
+        if !due.is_empty() {
+            self.save(&schedules)?;
+        }
+        Ok(due)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Delivery {
+    /// The schedule as it was when the run was queued, so deleting or editing
+    /// it doesn't break a pending delivery.
+    schedule: Schedule,
+    period: String,
+    recipient: Recipient,
+}
+
+/// Queues one delivery per recipient. Returns the job ids.
+fn queue(state: &AppState, schedule: &Schedule, period: &str) -> Result<Vec<i64>, String> {
+    schedule
+        .recipients
+        .iter()
+        .map(|recipient| {
+            let delivery = Delivery { schedule: schedule.clone(), period: period.to_string(), recipient: recipient.clone() };
+            state.jobs.enqueue(JOB, &delivery)
+        })
+        .collect()
+}
+
+/// Queues the deliveries of every due schedule each `reports.check_interval_secs`
+/// for the life of the process.
+pub fn start(state: &AppState) {
+    let state = state.clone();
+    let period = std::time::Duration::from_secs(state.config.reports.check_interval_secs.max(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let due = match state.report_schedules.take_due(state.clock.now(), state.config.reports.send_hour) {
+                Ok(due) => due,
+                Err(e) => {
+                    error!("Could not update report schedules: {}", e);
+                    continue;
+                }
+            };
+            for (schedule, period) in due {
+                info!("Queueing report {:?} ({}) for {}", schedule.name, schedule.id, period);
+                if let Err(e) = queue(&state, &schedule, &period) {
+                    error!("Could not queue report {}: {}", schedule.id, e);
+                }
+            }
+        }
+    });
+}
+
+/// Link to the report endpoint for `format`, on the public address.
+fn download_url(config: &Config, schedule: &Schedule, period: &str, format: Format) -> String {
+    let base = format!("{}/api/reports/market.{}", config.links.base_url.trim_end_matches('/'), format.extension());
+    let filter = &schedule.filter;
+    let bedrooms = filter.bedrooms.map(|b| b.to_string());
+    let lang = serde_json::to_value(schedule.lang).ok().and_then(|v| v.as_str().map(str::to_string));
+    let params = [
+        ("period", Some(period.to_string())),
+        ("area", filter.area.clone()),
+        ("bedrooms", bedrooms),
+        ("source", filter.source.clone()),
+        ("lang", lang),
+    ];
+    let params = params.iter().filter_map(|(name, value)| value.as_ref().map(|v| (*name, v.as_str())));
+    reqwest::Url::parse_with_params(&base, params).map(String::from).unwrap_or(base)
+}
+
+/// Summary lines for the message body.
+fn summary(report: &MarketReport, lang: Lang) -> String {
+    let headline = &report.headline;
+    let (listings, median) = match lang {
+        Lang::En => ("Listings", "Median rent"),
+        Lang::Ga => ("Fógraí", "Cíos airmheánach"),
+    };
+    let change = headline.change.map(|c| format!(" ({})", template::percent_change(c))).unwrap_or_default();
+    format!(
+        "{}: {}\n{}: {}{}",
+        listings,
+        headline.listings,
+        median,
+        template::median_euros(headline.median_rent),
+        change
+    )
+}
+
+pub fn run_job(state: &AppState, payload: &serde_json::Value) -> Result<(), String> {
+    let delivery: Delivery = serde_json::from_value(payload.clone()).map_err(|e| e.to_string())?;
+    let (schedule, period) = (&delivery.schedule, delivery.period.as_str());
+
+    let (subject, body, attachments) = match generate(&state.config, state.store.as_ref(), &schedule.filter, period) {
+        Ok(report) => {
+            let document = template::market(&report, schedule.lang, state.clock.today());
+            let attachments = schedule
+                .formats
+                .iter()
+                .map(|format| Attachment {
+                    filename: format!("{}.{}", file_stem(&report), format.extension()),
+                    content_type: format.content_type().to_string(),
+                    data: match format {
+                        Format::Pdf => pdf::render(&document),
+                        Format::Csv => csv::render(&report).into_bytes(),
+                    },
+                })
+                .collect();
+            (document.title, summary(&report, schedule.lang), attachments)
+        }
+        // Nothing to report is still worth telling the recipient about
+        Err((StatusCode::NOT_FOUND, message)) => (format!("{}: {}", schedule.name, period), message, vec![]),
+        Err((_, message)) => return Err(message),
+    };
+
+    match &delivery.recipient {
+        Recipient::Email { address } => {
+            let smtp = state.config.notifier.smtp.as_ref().ok_or("No notifier.smtp relay configured")?;
+            email::send(smtp, &Email { to: address.clone(), subject, body, attachments })?;
+            info!("Emailed report {} for {} to {}", schedule.id, period, address);
+        }
+        Recipient::Webhook { url } => {
+            let links: Vec<String> = if attachments.is_empty() {
+                vec![]
+            } else {
+                schedule.formats.iter().map(|f| download_url(&state.config, schedule, period, *f)).collect()
+            };
+            let text = std::iter::once(format!("*{}*", subject))
+                .chain(std::iter::once(body))
+                .chain(links.iter().cloned())
+                .collect::<Vec<_>>()
+                .join("\n");
+            let message = serde_json::json!({
+                "text": text,
+                "schedule": schedule.name,
+                "period": period,
+                "links": links,
+            });
+            notifier::post_json(url, &message)?;
+            info!("Posted report {} for {} to {}", schedule.id, period, url);
+        }
+    }
+    Ok(())
+}
+
+fn storage_error(e: String) -> (StatusCode, String) {
+    warn!("Could not save report schedules: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Could not save report schedules".to_string())
+}
+
+pub async fn list(State(state): State<AppState>, caller: Caller) -> Result<Json<Vec<Schedule>>, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    Ok(Json(state.report_schedules.list()))
+}
+
+pub async fn get_schedule(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(id): Path<u64>,
+) -> Result<Json<Schedule>, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    state
+        .report_schedules
+        .get(id)
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, format!("No report schedule {}", id)))
+}
+
+pub async fn create(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(request): Json<ScheduleRequest>,
+) -> Result<(StatusCode, Json<Schedule>), (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    if let Err(e) = request.validate() {
+        return Err((StatusCode::BAD_REQUEST, e));
+    }
+    let parameters = serde_json::json!({ "name": request.name, "every": request.every });
+    let outcome = state.report_schedules.create(request, state.clock.now(), state.config.reports.send_hour);
+    state.audit.record(&caller, "reports.create", parameters, &outcome);
+    Ok((StatusCode::CREATED, Json(outcome.map_err(storage_error)?)))
+}
+
+pub async fn delete(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(id): Path<u64>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    let outcome = state.report_schedules.delete(id);
+    state.audit.record(&caller, "reports.delete", serde_json::json!({ "id": id }), &outcome);
+    match outcome.map_err(storage_error)? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err((StatusCode::NOT_FOUND, format!("No report schedule {}", id))),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RunParams {
+    /// Defaults to the last completed period.
+    period: Option<String>,
+}
+
+/// Queues a schedule's deliveries now, outside its cadence.
+pub async fn run_now(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(id): Path<u64>,
+    Query(params): Query<RunParams>,
+) -> Result<(StatusCode, Json<Vec<i64>>), (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    let schedule = state
+        .report_schedules
+        .get(id)
+        .ok_or((StatusCode::NOT_FOUND, format!("No report schedule {}", id)))?;
+    let period = params.period.unwrap_or_else(|| completed_period(schedule.every, state.clock.now()));
+    let outcome = queue(&state, &schedule, &period);
+    state.audit.record(&caller, "reports.run", serde_json::json!({ "id": id, "period": period }), &outcome);
+    let jobs = outcome.map_err(|e| {
+        error!("Could not queue report {}: {}", id, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Job queue unavailable".to_string())
+    })?;
+    Ok((StatusCode::ACCEPTED, Json(jobs)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(y: i32, m: u32, d: u32, h: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_periods() {
+        // Wednesday 16 October 2024
+        let date = NaiveDate::from_ymd_opt(2024, 10, 16).unwrap();
+        assert_eq!(period_start(Granularity::Week, date), NaiveDate::from_ymd_opt(2024, 10, 14).unwrap());
+        assert_eq!(next_period_start(Granularity::Month, date), NaiveDate::from_ymd_opt(2024, 11, 1).unwrap());
+        assert_eq!(next_period_start(Granularity::Quarter, date), NaiveDate::from_ymd_opt(2025, 1, 1).unwrap());
+
+        assert_eq!(first_run(Granularity::Week, at(2024, 10, 16, 12), 7), at(2024, 10, 21, 7));
+        assert_eq!(first_run(Granularity::Month, at(2024, 11, 1, 6), 7), at(2024, 11, 1, 7));
+        assert_eq!(completed_period(Granularity::Quarter, at(2025, 1, 1, 7)), "2024-Q4");
+        assert_eq!(completed_period(Granularity::Week, at(2024, 10, 21, 7)), "2024-W42");
+    }
+
+    #[test]
+    fn test_due_schedules_advance() {
+        let schedules = ReportSchedules::open(None);
+        let request: ScheduleRequest = serde_json::from_value(serde_json::json!({
+            "name": "Weekly Dublin 2-bed digest",
+            "area": "Dublin",
+            "bedrooms": 2,
+            "every": "week",
+            "formats": ["pdf", "csv"],
+            "recipients": [{ "type": "email", "address": "team@example.com" }],
+        }))
+        .unwrap();
+        let created = schedules.create(request, at(2024, 10, 16, 12), 7).unwrap();
+        assert_eq!(created.filter.bedrooms, Some(2));
+
+        assert!(schedules.take_due(at(2024, 10, 21, 6), 7).unwrap().is_empty());
+        // Missed a few runs while down: one delivery, for the last full week
+        let due = schedules.take_due(at(2024, 11, 6, 9), 7).unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].1, "2024-W44");
+        assert_eq!(schedules.get(created.id).unwrap().next_run, at(2024, 11, 11, 7));
+        assert!(schedules.take_due(at(2024, 11, 6, 10), 7).unwrap().is_empty());
+
+        let invalid: ScheduleRequest = serde_json::from_value(serde_json::json!({
+            "name": "No one", "every": "month", "recipients": [],
+        }))
+        .unwrap();
+        assert!(schedules.create(invalid, at(2024, 10, 16, 12), 7).is_err());
+    }
+}
@@ -0,0 +1,251 @@
+//! Lays report figures out as a document of headings, text, tables and
+//! charts, with every label in the report language. Renderers (PDF for now)
+//! only deal with `Document`.
+
+use chrono::NaiveDate;
+
+use super::market::{MarketReport, Share};
+use crate::display;
+use crate::locale::Lang;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    Heading(String),
+    Text(String),
+    Table { columns: Vec<String>, rows: Vec<Vec<String>> },
+    /// One series over categorical x labels.
+    LineChart { labels: Vec<String>, values: Vec<f64> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    pub title: String,
+    pub blocks: Vec<Block>,
+}
+
+/// Fixed wording of the market report.
+struct Strings {
+    title: &'static str,
+    all_areas: &'static str,
+    headline: &'static str,
+    listings: &'static str,
+    median_rent: &'static str,
+    change_on: &'static str,
+    bedrooms: &'static str,
+    /// Follows the bedroom count in a title: "2-bed", "2 seomra codlata".
+    bed_suffix: &'static str,
+    by_bedrooms: &'static str,
+    trend: &'static str,
+    supply: &'static str,
+    property_type: &'static str,
+    share: &'static str,
+    movers: &'static str,
+    no_movers: &'static str,
+    area: &'static str,
+    previous: &'static str,
+    change: &'static str,
+    studio: &'static str,
+    unknown: &'static str,
+    exempt: &'static str,
+    generated: &'static str,
+}
+
+const EN: Strings = Strings {
+    title: "Rental market report",
+    all_areas: "All areas",
+    headline: "Headline figures",
+    listings: "Listings",
+    median_rent: "Median rent",
+    change_on: "Change on",
+    bedrooms: "Bedrooms",
+    bed_suffix: "-bed",
+    by_bedrooms: "Median rent by bedrooms",
+    trend: "Median rent trend",
+    supply: "Supply",
+    property_type: "Property type",
+    share: "Share",
+    movers: "Top movers",
+    no_movers: "Not enough listings to compare areas with the previous period.",
+    area: "Area",
+    previous: "Previous",
+    change: "Change",
+    studio: "Studio",
+    unknown: "Unknown",
+    exempt: "Exempt",
+    generated: "Generated",
+};
+
+const GA: Strings = Strings {
+    title: "Tuarascáil ar an margadh cíosa",
+    all_areas: "Gach ceantar",
+    headline: "Príomhfhigiúirí",
+    listings: "Fógraí",
+    median_rent: "Cíos airmheánach",
+    change_on: "Athrú ó",
+    bedrooms: "Seomraí codlata",
+    bed_suffix: " seomra codlata",
+    by_bedrooms: "Cíos airmheánach de réir seomraí codlata",
+    trend: "Treocht an chíosa airmheánaigh",
+    supply: "Soláthar",
+    property_type: "Cineál maoine",
+    share: "Sciar",
+    movers: "Na hathruithe is mó",
+    no_movers: "Níl go leor fógraí ann chun ceantair a chur i gcomparáid leis an tréimhse roimhe.",
+    area: "Ceantar",
+    previous: "Roimhe",
+    change: "Athrú",
+    studio: "Stiúideo",
+    unknown: "Anaithnid",
+    exempt: "Díolmhaithe",
+    generated: "Gineadh",
+};
+
+fn strings(lang: Lang) -> &'static Strings {
+    match lang {
+        Lang::En => &EN,
+        Lang::Ga => &GA,
+    }
+}
+
+/// "€1,850"
+pub fn euros(amount: f64) -> String {
+    display::money(amount, "EUR")
+}
+
+/// `euros`, or a dash for a median suppressed for too few listings.
+pub fn median_euros(median: Option<f64>) -> String {
+    median.map_or_else(|| "–".to_string(), euros)
+}
+
+/// "+3.2%"
+pub fn percent_change(change: f64) -> String {
+    format!("{:+.1}%", change * 100.0)
+}
+
+fn percent(share: f64) -> String {
+    format!("{:.1}%", share * 100.0)
+}
+
+fn key_label(text: &Strings, key: &str) -> String {
+    match key {
+        "studio" => text.studio.to_string(),
+        "unknown" => text.unknown.to_string(),
+        "exempt" => text.exempt.to_string(),
+        _ => {
+            let mut chars = key.chars();
+            chars.next().map(|c| c.to_uppercase().chain(chars).collect()).unwrap_or_default()
+        }
+    }
+}
+
+/// Display name of a category key ("studio", "apartment", "4+").
+pub fn category_label(lang: Lang, key: &str) -> String {
+    key_label(strings(lang), key)
+}
+
+fn share_table(text: &Strings, column: &str, shares: &[Share]) -> Block {
+    Block::Table {
+        columns: vec![column.to_string(), text.listings.to_string(), text.share.to_string()],
+        rows: shares
+            .iter()
+            .map(|share| vec![key_label(text, &share.key), share.listings.to_string(), percent(share.share)])
+            .collect(),
+    }
+}
+
+/// The market report as a document, dated `generated`.
+pub fn market(report: &MarketReport, lang: Lang, generated: NaiveDate) -> Document {
+    let text = strings(lang);
+    let area = if report.area.is_empty() { text.all_areas.to_string() } else { lang.area(&report.area) };
+    let period = lang.period(report.granularity, &report.period);
+    let scope = match report.bedrooms {
+        Some(0) => format!("{}, {}", area, text.studio),
+        Some(bedrooms) => format!("{}, {}{}", area, bedrooms, text.bed_suffix),
+        None => area,
+    };
+    let headline = &report.headline;
+
+    let mut summary = vec![
+        vec![text.listings.to_string(), headline.listings.to_string()],
+        vec![text.median_rent.to_string(), median_euros(headline.median_rent)],
+    ];
+    if let (Some(previous), Some(change)) = (&report.previous_period, headline.change) {
+        summary.push(vec![
+            format!("{} {}", text.change_on, lang.period(report.granularity, previous)),
+            percent_change(change),
+        ]);
+    }
+
+    // Periods too thin for a median are left off the chart
+    let trend: Vec<(&str, f64)> =
+        report.trend.iter().filter_map(|p| Some((p.period.as_str(), p.median_rent?))).collect();
+    let mut blocks = vec![
+        Block::Text(format!("{} {}", text.generated, generated.format("%Y-%m-%d"))),
+        Block::Heading(text.headline.to_string()),
+        Block::Table { columns: vec![String::new(), period.clone()], rows: summary },
+        Block::Heading(text.by_bedrooms.to_string()),
+        Block::Table {
+            columns: vec![text.bedrooms.to_string(), text.listings.to_string(), text.median_rent.to_string()],
+            rows: headline
+                .by_bedrooms
+                .iter()
+                .map(|b| vec![key_label(text, &b.bedrooms), b.listings.to_string(), median_euros(b.median_rent)])
+                .collect(),
+        },
+        Block::Heading(text.trend.to_string()),
+        Block::LineChart {
+            labels: trend.iter().map(|(period, _)| lang.period(report.granularity, period)).collect(),
+            values: trend.iter().map(|(_, rent)| *rent).collect(),
+        },
+        Block::Heading(text.supply.to_string()),
+        share_table(text, text.property_type, &report.supply.by_type),
+        share_table(text, text.bedrooms, &report.supply.by_bedrooms),
+        Block::Heading(text.movers.to_string()),
+    ];
+    if report.top_movers.is_empty() {
+        blocks.push(Block::Text(text.no_movers.to_string()));
+    } else {
+        blocks.push(Block::Table {
+            columns: vec![
+                text.area.to_string(),
+                text.listings.to_string(),
+                text.median_rent.to_string(),
+                text.previous.to_string(),
+                text.change.to_string(),
+            ],
+            rows: report
+                .top_movers
+                .iter()
+                .map(|m| {
+                    vec![
+                        lang.area(&m.area),
+                        m.listings.to_string(),
+                        euros(m.median_rent),
+                        euros(m.previous_median_rent),
+                        percent_change(m.change),
+                    ]
+                })
+                .collect(),
+        });
+    }
+
+    Document {
+        title: format!("{}: {}, {}", text.title, scope, period),
+        blocks,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_number_formats() {
+        assert_eq!(euros(1850.4), "€1,850");
+        assert_eq!(euros(1234567.0), "€1,234,567");
+        assert_eq!(euros(950.0), "€950");
+        assert_eq!(median_euros(None), "–");
+        assert_eq!(percent_change(0.032), "+3.2%");
+        assert_eq!(percent_change(-0.1), "-10.0%");
+    }
+}
@@ -0,0 +1,709 @@
+//! Watched areas and their weekly digests.
+//!
+//! Rather than a full saved search, a user can watch an area: a county or
+//! district (anything the search `location` filter accepts) or a polygon of
+//! points (see `geo`), optionally narrowed to a bedroom count and source. At
+//! `reports.send_hour` each Monday, every watch gets a digest of the week just
+//! ended over the user's notification channel (see `preferences`): the median
+//! rent and its change from the week before, from `market`, and the new
+//! listings and notable price drops from the week's snapshot deltas. Watches
+//! are managed through `/api/me/watches` and kept in `reports.watches_path`.
+//!
+//! A watch can be backtested before it is saved: `POST
+//! /api/me/watches/backtest` (or `main backtest-watch <watch.json>`) replays
+//! the last `weeks` weeks of snapshots through the digest, building any
+//! snapshot deltas missing on the way, and returns the digest of each week.
+//! Users tune `min_drop` with it, and the command's output can be diffed
+//! before and after a change to the digest logic.
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use super::market::{self, ReportFilter};
+use super::schedule::{completed_period, first_run, next_period_start, send_time};
+use super::template;
+use crate::analytics::hedonic::Granularity;
+use crate::auth::Caller;
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::deltas::{ChangeKind, SnapshotDelta};
+use crate::geo;
+use crate::locale::Lang;
+use crate::notifier::{self, Notification};
+use crate::preferences::Batch;
+use crate::state::AppState;
+use crate::store::PropertyStore;
+use crate::{list_snapshots, validate_price};
+
+/// Job kind that builds and sends one watch's digest.
+pub const JOB: &str = "reports.watch";
+/// Notification kind digests are sent as.
+pub const KIND: &str = "reports.watch";
+/// A price cut of at least this fraction of the rent is notable.
+const NOTABLE_DROP: f64 = 0.05;
+const MAX_DROPS: usize = 10;
+const MAX_WATCHES: usize = 20;
+/// Weeks a backtest replays by default, and at most.
+const BACKTEST_WEEKS: (usize, usize) = (13, 52);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WatchRequest {
+    pub name: String,
+    #[serde(flatten)]
+    pub filter: ReportFilter,
+    /// Smallest price cut listed, as a fraction of the rent; 5% by default.
+    #[serde(default)]
+    pub min_drop: Option<f64>,
+    #[serde(default)]
+    pub lang: Lang,
+}
+
+impl WatchRequest {
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("name is required".to_string());
+        }
+        if self.min_drop.is_some_and(|drop| !(drop > 0.0 && drop < 1.0)) {
+            return Err("min_drop must be between 0 and 1".to_string());
+        }
+        let area = self.filter.area.as_deref().unwrap_or_default();
+        match &self.filter.polygon {
+            Some(polygon) => geo::validate_polygon(polygon),
+            None if area.trim().is_empty() => Err("Give an area or a polygon".to_string()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchedArea {
+    pub id: u64,
+    pub name: String,
+    #[serde(flatten)]
+    pub filter: ReportFilter,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_drop: Option<f64>,
+    pub lang: Lang,
+    pub created_at: DateTime<Utc>,
+    pub next_run: DateTime<Utc>,
+    /// Week covered by the last digest.
+    pub last_period: Option<String>,
+}
+
+/// Watches by user name.
+type ByUser = BTreeMap<String, Vec<WatchedArea>>;
+
+pub struct WatchedAreas {
+    path: Option<PathBuf>,
+    by_user: RwLock<ByUser>,
+}
+
+impl WatchedAreas {
+    /// Loads saved watches from `path`. `None` keeps them in memory only.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let by_user = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        WatchedAreas { path, by_user: RwLock::new(by_user) }
+    }
+
+    fn save(&self, by_user: &ByUser) -> Result<(), String> {
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+            let contents = serde_json::to_string_pretty(by_user).map_err(|e| e.to_string())?;
+            fs::write(path, contents).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    pub fn list(&self, user: &str) -> Vec<WatchedArea> {
+        self.by_user.read().unwrap().get(user).cloned().unwrap_or_default()
+    }
+
+    pub fn get(&self, user: &str, id: u64) -> Option<WatchedArea> {
+        self.by_user.read().unwrap().get(user)?.iter().find(|w| w.id == id).cloned()
+    }
+
+    pub fn create(
+        &self,
+        user: &str,
+        request: WatchRequest,
+        now: DateTime<Utc>,
+        send_hour: u32,
+    ) -> Result<WatchedArea, String> {
+        request.validate()?;
+        let mut by_user = self.by_user.write().unwrap();
+        let id = by_user.values().flatten().map(|w| w.id).max().unwrap_or(0) + 1;
+        let watches = by_user.entry(user.to_string()).or_default();
+        if watches.len() >= MAX_WATCHES {
+            return Err(format!("At most {} watched areas per user", MAX_WATCHES));
+        }
+        let watch = WatchedArea {
+            id,
+            name: request.name,
+            filter: request.filter,
+            min_drop: request.min_drop,
+            lang: request.lang,
+            created_at: now,
+            next_run: first_run(Granularity::Week, now, send_hour),
+            last_period: None,
+        };
+        watches.push(watch.clone());
+        self.save(&by_user)?;
+        Ok(watch)
+    }
+
+    pub fn delete(&self, user: &str, id: u64) -> Result<bool, String> {
+        let mut by_user = self.by_user.write().unwrap();
+        let Some(watches) = by_user.get_mut(user) else {
+            return Ok(false);
+        };
+        let before = watches.len();
+        watches.retain(|w| w.id != id);
+        if watches.len() == before {
+            return Ok(false);
+        }
+        if watches.is_empty() {
+            by_user.remove(user);
+        }
+        self.save(&by_user)?;
+        Ok(true)
+    }
+
+    /// `(user, watch, week)` for each watch due at `now`. Like report
+    /// schedules, a watch due several times while the service was down gets
+    /// one digest, for the last full week.
+    pub fn take_due(&self, now: DateTime<Utc>, send_hour: u32) -> Result<Vec<(String, WatchedArea, String)>, String> {
+        let mut by_user = self.by_user.write().unwrap();
+        let mut due = Vec::new();
+        for (user, watches) in by_user.iter_mut() {
+            for watch in watches.iter_mut().filter(|w| w.next_run <= now) {
+                let period = completed_period(Granularity::Week, now);
+                watch.last_period = Some(period.clone());
+                watch.next_run = send_time(next_period_start(Granularity::Week, now.date_naive()), send_hour);
+                due.push((user.clone(), watch.clone(), period));
+            }
+        }
+        if !due.is_empty() {
+            self.save(&by_user)?;
+        }
+        Ok(due)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PriceDrop {
+    pub property_id: String,
+    pub address: String,
+    pub previous_rent: f64,
+    pub rent: f64,
+    /// Relative change, negative.
+    pub change: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Digest {
+    pub name: String,
+    pub period: String,
+    /// Listings seen during the week.
+    pub listings: usize,
+    pub median_rent: Option<f64>,
+    pub previous_median_rent: Option<f64>,
+    /// Relative change in median rent from the previous week.
+    pub change: Option<f64>,
+    /// Listings that first appeared during the week.
+    pub new_listings: usize,
+    /// Largest cuts first.
+    pub notable_drops: Vec<PriceDrop>,
+}
+
+/// New listings and drops of at least `min_drop` matching `filter` in a
+/// week's deltas, oldest first. A listing cut several times drops from its
+/// rent before the first cut to its latest.
+fn changes(deltas: &[SnapshotDelta], filter: &ReportFilter, min_drop: f64) -> (usize, Vec<PriceDrop>) {
+    let area = filter.normalized_area();
+    let mut new = HashSet::new();
+    let mut cuts: HashMap<&str, PriceDrop> = HashMap::new();
+    let relevant = deltas
+        .iter()
+        .flat_map(|delta| &delta.changes)
+        .filter(|change| validate_price(&change.listing) && filter.matches(&area, &change.listing));
+    for change in relevant {
+        let rent = change.listing.price.amount;
+        match (change.kind, change.previous_price) {
+            (ChangeKind::New, _) => {
+                new.insert(change.property_id.as_str());
+            }
+            (ChangeKind::Changed, Some(previous_rent)) if change.fields.iter().any(|f| f == "price") => {
+                let cut = cuts.entry(&change.property_id).or_insert_with(|| PriceDrop {
+                    property_id: change.property_id.clone(),
+                    address: change.listing.address.display_address.clone(),
+                    previous_rent,
+                    rent,
+                    change: 0.0,
+                });
+                cut.rent = rent;
+                cut.change = rent / cut.previous_rent - 1.0;
+            }
+            _ => {}
+        }
+    }
+    let mut drops: Vec<PriceDrop> = cuts.into_values().filter(|cut| cut.change <= -min_drop).collect();
+    drops.sort_by(|a, b| a.change.total_cmp(&b.change).then_with(|| a.property_id.cmp(&b.property_id)));
+    drops.truncate(MAX_DROPS);
+    (new.len(), drops)
+}
+
+/// Builds the digest of `watch` for the week `period`, e.g. "2024-W45".
+/// Blocking.
+pub fn digest(
+    config: &Config,
+    store: &dyn PropertyStore,
+    watch: &WatchedArea,
+    period: &str,
+) -> Result<Digest, (StatusCode, String)> {
+    if market::period_granularity(period) != Some(Granularity::Week) {
+        return Err((StatusCode::BAD_REQUEST, format!("Invalid week {:?}; use e.g. 2024-W45", period)));
+    }
+    let observations = market::load(config, store, &watch.filter, Granularity::Week);
+    Ok(digest_from(config, watch, period, observations, false))
+}
+
+/// The digest of `watch` for `period` from its `market::load` observations.
+/// Snapshot deltas that are missing are built when `build_deltas` is set and
+/// left out otherwise.
+fn digest_from(
+    config: &Config,
+    watch: &WatchedArea,
+    period: &str,
+    observations: Vec<(String, crate::StandardizedProperty)>,
+    build_deltas: bool,
+) -> Digest {
+    let headline = market::build(&watch.filter, period, Granularity::Week, observations).map(|report| report.headline);
+    let deltas: Vec<SnapshotDelta> = config
+        .select_sources(watch.filter.source.as_deref())
+        .into_iter()
+        .flat_map(|source| list_snapshots(&source.root(&config.data_path)).into_iter().map(move |s| (source, s)))
+        .filter(|(_, (date, _))| Granularity::Week.label(*date) == period)
+        .filter_map(|(source, (_, file))| match build_deltas {
+            true => SnapshotDelta::load_or_build(config, source, &file),
+            false => SnapshotDelta::load(&file),
+        })
+        .collect();
+    let (new_listings, notable_drops) = changes(&deltas, &watch.filter, watch.min_drop.unwrap_or(NOTABLE_DROP));
+    Digest {
+        name: watch.name.clone(),
+        period: period.to_string(),
+        listings: headline.as_ref().map_or(0, |h| h.listings),
+        median_rent: headline.as_ref().and_then(|h| h.median_rent),
+        previous_median_rent: headline.as_ref().and_then(|h| h.previous_median_rent),
+        change: headline.as_ref().and_then(|h| h.change),
+        new_listings,
+        notable_drops,
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Backtest {
+    pub name: String,
+    /// The digest of each week replayed, oldest first.
+    pub digests: Vec<Digest>,
+    /// Weeks whose digest had new listings or notable drops.
+    pub weeks_alerted: usize,
+    pub new_listings: usize,
+    pub notable_drops: usize,
+}
+
+/// The `weeks` full weeks before the one containing `today`, oldest first.
+fn past_weeks(today: NaiveDate, weeks: usize) -> Vec<String> {
+    let monday = today - Duration::days(i64::from(today.weekday().num_days_from_monday()));
+    (1..=weeks as i64).rev().map(|back| Granularity::Week.label(monday - Duration::weeks(back))).collect()
+}
+
+/// The digests `request` would have got over the `weeks` full weeks before
+/// `today`. Blocking.
+pub fn backtest(
+    config: &Config,
+    store: &dyn PropertyStore,
+    request: WatchRequest,
+    weeks: Option<usize>,
+    today: NaiveDate,
+) -> Result<Backtest, String> {
+    request.validate()?;
+    let weeks = weeks.unwrap_or(BACKTEST_WEEKS.0);
+    if !(1..=BACKTEST_WEEKS.1).contains(&weeks) {
+        return Err(format!("weeks must be between 1 and {}", BACKTEST_WEEKS.1));
+    }
+    let now = DateTime::<Utc>::from_naive_utc_and_offset(today.and_time(chrono::NaiveTime::MIN), Utc);
+    let watch = WatchedArea {
+        id: 0,
+        name: request.name,
+        filter: request.filter,
+        min_drop: request.min_drop,
+        lang: request.lang,
+        created_at: now,
+        next_run: now,
+        last_period: None,
+    };
+    let observations = market::load(config, store, &watch.filter, Granularity::Week);
+    let digests: Vec<Digest> = past_weeks(today, weeks)
+        .iter()
+        .map(|period| digest_from(config, &watch, period, observations.clone(), true))
+        .collect();
+    Ok(Backtest {
+        name: watch.name,
+        weeks_alerted: digests.iter().filter(|d| d.new_listings > 0 || !d.notable_drops.is_empty()).count(),
+        new_listings: digests.iter().map(|d| d.new_listings).sum(),
+        notable_drops: digests.iter().map(|d| d.notable_drops.len()).sum(),
+        digests,
+    })
+}
+
+/// `main backtest-watch <watch.json> [--weeks N]`: prints the backtest of the
+/// watch in the file as JSON. Returns whether it ran.
+pub fn run(config: &Config, args: &[String]) -> bool {
+    let parsed = match args {
+        [path] => Ok((path, None)),
+        [path, flag, weeks] if flag == "--weeks" => {
+            weeks.parse().map(|weeks| (path, Some(weeks))).map_err(|_| format!("Invalid --weeks {:?}", weeks))
+        }
+        _ => Err("Usage: main backtest-watch <watch.json> [--weeks N]".to_string()),
+    };
+    let outcome = parsed.and_then(|(path, weeks)| {
+        let contents = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        let request: WatchRequest = serde_json::from_str(&contents).map_err(|e| format!("Invalid {}: {}", path, e))?;
+        let config = std::sync::Arc::new(config.clone());
+        let cache = std::sync::Arc::new(crate::cache::SnapshotCache::new(0));
+        let store = crate::store::ParquetStore::new(config.clone(), cache, std::sync::Arc::default());
+        let backtest = backtest(&config, &store, request, weeks, config.clock.today())?;
+        serde_json::to_string_pretty(&backtest).map_err(|e| e.to_string())
+    });
+    match outcome {
+        Ok(json) => {
+            println!("{}", json);
+            true
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            false
+        }
+    }
+}
+
+/// The digest as a message: a title line, then one line per figure.
+fn compose(digest: &Digest, lang: Lang) -> String {
+    let (week, median, listings, new, drops, quiet) = match lang {
+        Lang::En => ("week", "Median rent", "listings", "New listings", "Notable price drops", "No listings this week"),
+        Lang::Ga => (
+            "seachtain",
+            "Cíos airmheánach",
+            "fógraí",
+            "Fógraí nua",
+            "Titim shuntasacha praghais",
+            "Gan fógraí an tseachtain seo",
+        ),
+    };
+    let mut lines = vec![format!("{}: {} {}", digest.name, week, digest.period)];
+    match digest.median_rent {
+        Some(rent) => {
+            let change = digest.change.map(|c| format!(" ({})", template::percent_change(c))).unwrap_or_default();
+            lines.push(format!("{}: {}{}, {} {}", median, template::euros(rent), change, digest.listings, listings));
+        }
+        None => lines.push(quiet.to_string()),
+    }
+    lines.push(format!("{}: {}", new, digest.new_listings));
+    if !digest.notable_drops.is_empty() {
+        lines.push(format!("{}:", drops));
+        lines.extend(digest.notable_drops.iter().map(|drop| {
+            format!(
+                "- {}: {} → {} ({})",
+                drop.address,
+                template::euros(drop.previous_rent),
+                template::euros(drop.rent),
+                template::percent_change(drop.change)
+            )
+        }));
+    }
+    lines.join("\n")
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Delivery {
+    user: String,
+    /// The watch as it was when queued, so deleting it doesn't break a
+    /// pending digest.
+    watch: WatchedArea,
+    period: String,
+}
+
+/// Queues the digests of every due watch each `reports.check_interval_secs`
+/// for the life of the process.
+pub fn start(state: &AppState) {
+    let state = state.clone();
+    let period = std::time::Duration::from_secs(state.config.reports.check_interval_secs.max(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let due = match state.watches.take_due(state.clock.now(), state.config.reports.send_hour) {
+                Ok(due) => due,
+                Err(e) => {
+                    error!("Could not update watched areas: {}", e);
+                    continue;
+                }
+            };
+            for (user, watch, period) in due {
+                info!("Queueing digest {:?} ({}) of {} for {}", watch.name, watch.id, user, period);
+                if let Err(e) = state.jobs.enqueue(JOB, &Delivery { user, watch, period }) {
+                    error!("Could not queue digest: {}", e);
+                }
+            }
+        }
+    });
+}
+
+/// Builds the digest and hands it to the notifier for the user's channel.
+pub fn run_job(state: &AppState, payload: &serde_json::Value) -> Result<(), String> {
+    let delivery: Delivery = serde_json::from_value(payload.clone()).map_err(|e| e.to_string())?;
+    let Some(preferences) = state.preferences.get(&delivery.user) else {
+        warn!("Dropping digest {} for {}: no notification preferences", delivery.watch.id, delivery.user);
+        return Ok(());
+    };
+    let digest = digest(&state.config, state.store.as_ref(), &delivery.watch, &delivery.period).map_err(|(_, message)| message)?;
+    let details = serde_json::to_value(&digest).map_err(|e| e.to_string())?;
+    let batch = Batch {
+        user: delivery.user,
+        channel: preferences.channel,
+        notifications: vec![Notification::new(
+            KIND,
+            compose(&digest, delivery.watch.lang),
+            details,
+            state.clock.now(),
+        )],
+    };
+    state.jobs.enqueue(notifier::USER_JOB, &batch)?;
+    Ok(())
+}
+
+fn user(caller: &Caller) -> Result<&str, (StatusCode, String)> {
+    caller
+        .name
+        .as_deref()
+        .ok_or((StatusCode::UNAUTHORIZED, "Watched areas need an API key".to_string()))
+}
+
+fn storage_error(e: String) -> (StatusCode, String) {
+    warn!("Could not save watched areas: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Could not save watched areas".to_string())
+}
+
+fn not_found(id: u64) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("No watched area {}", id))
+}
+
+pub async fn list(
+    State(state): State<AppState>,
+    caller: Caller,
+) -> Result<Json<Vec<WatchedArea>>, (StatusCode, String)> {
+    Ok(Json(state.watches.list(user(&caller)?)))
+}
+
+pub async fn create(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(request): Json<WatchRequest>,
+) -> Result<(StatusCode, Json<WatchedArea>), (StatusCode, String)> {
+    let user = user(&caller)?;
+    request.validate().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if state.preferences.get(user).is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Set a channel with PUT /api/me/preferences/notifications first".to_string(),
+        ));
+    }
+    if state.watches.list(user).len() >= MAX_WATCHES {
+        return Err((StatusCode::BAD_REQUEST, format!("At most {} watched areas per user", MAX_WATCHES)));
+    }
+    let now = state.clock.now();
+    let watch = state.watches.create(user, request, now, state.config.reports.send_hour).map_err(storage_error)?;
+    Ok((StatusCode::CREATED, Json(watch)))
+}
+
+pub async fn delete(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(id): Path<u64>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match state.watches.delete(user(&caller)?, id).map_err(storage_error)? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(not_found(id)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BacktestRequest {
+    #[serde(flatten)]
+    watch: WatchRequest,
+    /// Weeks to replay; 13 by default.
+    weeks: Option<usize>,
+}
+
+/// What a watch would have sent over past weeks, for tuning it before saving.
+pub async fn backtest_watch(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(request): Json<BacktestRequest>,
+) -> Result<Json<Backtest>, (StatusCode, String)> {
+    user(&caller)?;
+    let today = state.clock.today();
+    let task = tokio::task::spawn_blocking(move || {
+        backtest(&state.config, state.store.as_ref(), request.watch, request.weeks, today)
+    });
+    match task.await {
+        Ok(outcome) => outcome.map(Json).map_err(|e| (StatusCode::BAD_REQUEST, e)),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DigestParams {
+    /// Defaults to the last full week.
+    period: Option<String>,
+}
+
+/// The digest a watch would get, for checking it before Monday.
+pub async fn preview(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(id): Path<u64>,
+    Query(params): Query<DigestParams>,
+) -> Result<Json<Digest>, (StatusCode, String)> {
+    let watch = state.watches.get(user(&caller)?, id).ok_or_else(|| not_found(id))?;
+    let period = params.period.unwrap_or_else(|| completed_period(Granularity::Week, state.clock.now()));
+    tokio::task::spawn_blocking(move || digest(&state.config, state.store.as_ref(), &watch, &period))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map(Json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deltas::diff;
+    use crate::test_utils::listing;
+    use chrono::TimeZone;
+
+    fn rental(id: &str, area: &str, rent: f64) -> crate::StandardizedProperty {
+        let mut property = listing("daft", id);
+        property.address.display_address = format!("{} Main Street, {}", id, area);
+        property.address.normalized_address = crate::address::normalize(&property.address.display_address);
+        property.price.amount = rent;
+        property
+    }
+
+    fn request(value: serde_json::Value) -> WatchRequest {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_watches_come_due_weekly() {
+        let watches = WatchedAreas::open(None);
+        let at = |d: u32, h: u32| Utc.with_ymd_and_hms(2024, 11, d, h, 0, 0).unwrap();
+        let d6 = request(serde_json::json!({ "name": "D6", "area": "Dublin 6" }));
+        let created = watches.create("alice", d6, at(6, 12), 7).unwrap();
+        assert_eq!(created.next_run, at(11, 7));
+        assert!(watches.create("alice", request(serde_json::json!({ "name": "Everywhere" })), at(6, 12), 7).is_err());
+        let triangle = serde_json::json!([
+            { "lat": 53.33, "lng": -6.29 }, { "lat": 53.33, "lng": -6.24 }, { "lat": 53.30, "lng": -6.26 },
+        ]);
+        let luas = request(serde_json::json!({ "name": "Luas", "polygon": triangle }));
+        watches.create("bob", luas, at(6, 12), 7).unwrap();
+
+        assert_eq!(watches.list("alice").len(), 1);
+        let due = watches.take_due(at(11, 7), 7).unwrap();
+        assert_eq!(due.iter().map(|(user, _, period)| (user.as_str(), period.as_str())).collect::<Vec<_>>(), [
+            ("alice", "2024-W45"),
+            ("bob", "2024-W45")
+        ]);
+        assert!(watches.take_due(at(11, 8), 7).unwrap().is_empty());
+        assert!(!watches.delete("bob", created.id).unwrap());
+        assert!(watches.delete("alice", created.id).unwrap());
+    }
+
+    #[test]
+    fn test_backtest_replays_full_weeks() {
+        let wednesday = NaiveDate::from_ymd_opt(2024, 11, 13).unwrap();
+        assert_eq!(past_weeks(wednesday, 3), ["2024-W43", "2024-W44", "2024-W45"]);
+        let config = Config::default();
+        let store = crate::store::MemoryStore::default();
+        let bad = request(serde_json::json!({ "name": "D6", "area": "Dublin 6", "min_drop": 1.5 }));
+        assert!(backtest(&config, &store, bad, None, wednesday).is_err());
+        let d6 = request(serde_json::json!({ "name": "D6", "area": "Dublin 6" }));
+        assert!(backtest(&config, &store, d6.clone(), Some(53), wednesday).is_err());
+        let quiet = backtest(&config, &store, d6, Some(2), wednesday).unwrap();
+        assert_eq!(quiet.digests.iter().map(|d| d.period.as_str()).collect::<Vec<_>>(), ["2024-W44", "2024-W45"]);
+        assert_eq!(quiet.weeks_alerted, 0);
+    }
+
+    #[test]
+    fn test_new_supply_and_drops() {
+        let delta = |previous, current| {
+            let mut delta: SnapshotDelta = serde_json::from_value(serde_json::json!({
+                "source": "daft", "file": "a.parquet", "file_len": 0, "modified": 0,
+                "date": "2024-11-05", "previous_date": "2024-11-04", "changes": [],
+            }))
+            .unwrap();
+            delta.changes = diff(previous, current);
+            delta
+        };
+        let deltas = [
+            delta(
+                vec![rental("1", "Dublin 6", 2000.0), rental("2", "Dublin 6", 2000.0), rental("3", "Dublin 8", 2000.0)],
+                vec![
+                    rental("1", "Dublin 6", 1800.0),
+                    rental("2", "Dublin 6", 1950.0),
+                    rental("3", "Dublin 8", 1500.0),
+                    rental("4", "Dublin 6", 2100.0),
+                    rental("5", "Dublin 8", 2100.0),
+                ],
+            ),
+            delta(vec![rental("1", "Dublin 6", 1800.0)], vec![rental("1", "Dublin 6", 1700.0)]),
+        ];
+        let filter = ReportFilter { area: Some("Dublin 6".to_string()), ..Default::default() };
+        let (new, drops) = changes(&deltas, &filter, NOTABLE_DROP);
+        assert_eq!(new, 1);
+        // 2.5% on daft_2 isn't notable; daft_1 was cut twice, 15% in all
+        assert_eq!(drops.len(), 1);
+        assert_eq!(changes(&deltas, &filter, 0.02).1.len(), 2);
+        assert_eq!(changes(&deltas, &filter, 0.2).1.len(), 0);
+        assert_eq!((drops[0].property_id.as_str(), drops[0].previous_rent, drops[0].rent), ("daft_1", 2000.0, 1700.0));
+        assert!((drops[0].change + 0.15).abs() < 1e-9);
+
+        let digest = Digest {
+            name: "D6".to_string(),
+            period: "2024-W45".to_string(),
+            listings: 4,
+            median_rent: Some(2000.0),
+            previous_median_rent: Some(1900.0),
+            change: Some(2000.0 / 1900.0 - 1.0),
+            new_listings: new,
+            notable_drops: drops,
+        };
+        let text = compose(&digest, Lang::En);
+        assert!(text.starts_with("D6: week 2024-W45\nMedian rent"), "{}", text);
+        let expected = "(+5.3%), 4 listings\nNew listings: 1\nNotable price drops:\n- 1 Main Street";
+        assert!(text.contains(expected), "{}", text);
+    }
+}
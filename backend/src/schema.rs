@@ -0,0 +1,333 @@
+//! Listing schema version.
+//!
+//! Every response carries `X-Schema-Version`, the version of the listing
+//! (`StandardizedProperty`) shape returned by the rental endpoints.
+//! `GET /api/schema` describes that shape field by field, with the values of
+//! enumerated fields and a changelog, so ETL jobs reading the API can notice a
+//! new version and adapt. A change to the serialized listing updates
+//! `listing_fields` and adds a `CHANGELOG` entry; `test_schema_matches_listing`
+//! fails while the description and the listing disagree.
+
+use axum::extract::State;
+use axum::http::HeaderValue;
+use axum::response::Response;
+use axum::Json;
+use serde::Serialize;
+
+use crate::ber::{BerStatus, RATINGS};
+use crate::price_band;
+use crate::property_type;
+use crate::state::AppState;
+
+pub const HEADER: &str = "x-schema-version";
+
+/// Versions of the listing shape, oldest first; the last is current.
+const CHANGELOG: &[(u32, &str)] = &[
+    (1, "Listings with address, price, photos, agent, seo_url and short_id"),
+    (2, "annotations: the caller's notes and tags"),
+    (3, "ber_status: rated, exempt or unknown; ber_rating only set when rated"),
+    (4, "price_band: the listing's rent band"),
+    (5, "url: the listing's page on the source site, for every source"),
+    (6, "raw: the source record, with include_raw"),
+    (7, "display: rent, size and rooms as text, with format_values"),
+    (8, "price.min and price.max: bounds of an advertised rent range"),
+    (9, "property_category: node of the property-type hierarchy"),
+    (10, "development: unit types of a new-development listing"),
+    (11, "commercial: rent per sq ft and zoning of a commercial listing"),
+    (12, "amenities: parking spaces, garden and balcony"),
+    (13, "tenancy: available_from date and min_lease_months"),
+    (14, "deposit: security deposit asked for"),
+    (15, "coordinates: latitude and longitude, when the source gives them"),
+    (16, "value_score: asking rent over the predicted rent, with sort=value"),
+    (17, "property_id: an opaque canonical id; legacy_id: the former <source>_<source_id> id"),
+    (18, "photo_count: number of photos; exactly one photo is_main when there are any"),
+    (19, "photos[].caption and photos[].kind (photo or floorplan); has_floorplan"),
+    (20, "size_confidence: low, medium or high; omitted without a size"),
+    (21, "price_flag: the [[price_bounds]] rule the rent breaks; omitted when none"),
+    (22, "semantic_score: similarity of the listing's text to semantic_q"),
+    (23, "price.price_changes: filled with the rent changes seen between snapshots"),
+    (24, "agent.licence and agent_status: the agent checked against the PSRA register"),
+];
+
+pub fn version() -> u32 {
+    CHANGELOG.last().map_or(0, |(version, _)| *version)
+}
+
+#[derive(Debug, Serialize)]
+pub struct Field {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub nullable: bool,
+    pub description: &'static str,
+    /// Every value the field can take, for enumerated fields.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<String>,
+    /// Members of an object, or of the objects in an array.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<Field>,
+}
+
+fn field(name: &'static str, kind: &'static str, description: &'static str) -> Field {
+    Field { name, kind, nullable: false, description, values: vec![], fields: vec![] }
+}
+
+impl Field {
+    fn nullable(self) -> Self {
+        Field { nullable: true, ..self }
+    }
+
+    fn values(self, values: Vec<String>) -> Self {
+        Field { values, ..self }
+    }
+
+    fn fields(self, fields: Vec<Field>) -> Self {
+        Field { fields, ..self }
+    }
+}
+
+/// The listing fields, with the bands between `price_bands`.
+fn listing_fields(price_bands: &[f64]) -> Vec<Field> {
+    let ber_statuses = [BerStatus::Rated, BerStatus::Exempt, BerStatus::Unknown]
+        .iter()
+        .filter_map(|status| serde_json::to_value(status).ok()?.as_str().map(str::to_string))
+        .collect();
+    vec![
+        field("property_id", "string", "Canonical id, stable while the listing is up"),
+        field("legacy_id", "string", "The former <source>_<source_id> id, still accepted by lookups").nullable(),
+        field("source", "string", "Source name, e.g. daft"),
+        field("source_id", "string", "The listing's id on the source site"),
+        field("address", "object", "").fields(vec![
+            field("display_address", "string", "As the source wrote it"),
+            field("normalized_address", "string", "Canonical form used for matching"),
+        ]),
+        field("property_type", "string", "As the source wrote it, e.g. Apartment"),
+        field("property_category", "string", "Node of /api/property-types, e.g. semi_detached")
+            .values(property_type::NODES.iter().map(|node| node.key.to_string()).collect()),
+        field("bedrooms", "integer", "").nullable(),
+        field("bathrooms", "integer", "").nullable(),
+        field("size", "object", "Floor area").nullable().fields(vec![
+            field("value", "number", ""),
+            field("unit", "string", "e.g. m²"),
+        ]),
+        field("size_confidence", "string", "How far size can be trusted; omitted without a size")
+            .nullable()
+            .values(vec!["low".to_string(), "medium".to_string(), "high".to_string()]),
+        field("ber_rating", "string", "Set for rated listings only")
+            .nullable()
+            .values(RATINGS.iter().map(|r| r.to_string()).collect()),
+        field("ber_status", "string", "").values(ber_statuses),
+        field("price", "object", "").fields(vec![
+            field("amount", "number", "Rent in currency units; for a range, the point search.price_range_point picks"),
+            field("currency", "string", "e.g. EUR"),
+            field("frequency", "string", "e.g. month").nullable(),
+            field("min", "number", "Lowest rent of an advertised range").nullable(),
+            field("max", "number", "Highest rent of an advertised range").nullable(),
+            field("price_changes", "array", "Rent changes seen between snapshots, oldest first").fields(vec![
+                field("date", "string", "Date of the snapshot the new rent was first seen in"),
+                field("amount", "number", "The new rent"),
+                field("direction", "string", "up or down"),
+            ]),
+        ]),
+        field("price_band", "string", "Rent band from search.price_bands")
+            .values(price_band::bands(price_bands).into_iter().map(|band| band.key).collect()),
+        field("price_flag", "object", "The [[price_bounds]] rule the rent breaks; omitted when none")
+            .nullable()
+            .fields(vec![
+                field("rule", "string", ""),
+                field("breach", "string", "").values(vec!["too_low".to_string(), "too_high".to_string()]),
+                field("bound", "number", "The rule's min or max"),
+            ]),
+        field("created_date", "string", "RFC 3339 or as the source wrote it"),
+        field("updated_date", "string", "RFC 3339 or as the source wrote it"),
+        field("listing_type", "string", "e.g. rent"),
+        field("status", "string", "e.g. active"),
+        field("photos", "array", "In the source's order").fields(vec![
+            field("url", "string", ""),
+            field("is_main", "boolean", ""),
+            field("caption", "string", "As the source wrote it; omitted when none").nullable(),
+            field("kind", "string", "").values(vec!["photo".to_string(), "floorplan".to_string()]),
+        ]),
+        field("photo_count", "integer", ""),
+        field("has_floorplan", "boolean", "Whether one of the photos is a floorplan"),
+        field("has_video", "boolean", ""),
+        field("agent", "object", "Contact details need the agents:read scope").nullable().fields(vec![
+            field("name", "string", ""),
+            field("phone", "string", ""),
+            field("email", "string", ""),
+            field("address", "string", ""),
+            field("licence", "string", "PSRA licence number as the listing quotes it; empty when none"),
+        ]),
+        field("agent_status", "string", "The agent checked against the PSRA register; omitted when not checked")
+            .nullable()
+            .values(["licensed", "mismatched", "unlicensed", "unmatched"].map(str::to_string).to_vec()),
+        field("seo_url", "string", "Path or URL as the source gave it").nullable(),
+        field("url", "string", "The listing's page on the source site").nullable(),
+        field("short_id", "string", "Id for /l/{short_id} share links"),
+        field("annotations", "object", "The caller's notes and tags; omitted when none").nullable().fields(vec![
+            field("notes", "array", "").fields(vec![field("text", "string", ""), field("created_at", "string", "")]),
+            field("tags", "array", "Strings, sorted"),
+        ]),
+        field("raw", "object", "Source record; only with include_raw").nullable().fields(vec![
+            field("file", "string", ""),
+            field("row", "integer", ""),
+            field("mapping_version", "integer", ""),
+            field("fields", "object", "Mapped field name to the value in the file"),
+        ]),
+        field("display", "object", "Values as text in the request language; only with format_values")
+            .nullable()
+            .fields(vec![
+                field("price", "string", "e.g. €1,850 / month"),
+                field("size", "string", "e.g. 72 m²").nullable(),
+                field("bedrooms", "string", "e.g. 2 beds").nullable(),
+                field("bathrooms", "string", "e.g. 1 bath").nullable(),
+            ]),
+        field("development", "object", "Only on new-development listings").nullable().fields(vec![
+            field("price_from", "number", "Lowest unit rent").nullable(),
+            field("units", "array", "").fields(vec![
+                field("bedrooms", "integer", "").nullable(),
+                field("size", "number", "Square metres").nullable(),
+                field("price", "number", "Monthly rent").nullable(),
+            ]),
+        ]),
+        field("commercial", "object", "Only on commercial listings, with search.commercial").nullable().fields(vec![
+            field("price_per_sqft", "number", "Yearly rent per square foot").nullable(),
+            field("zoning", "string", "").nullable(),
+        ]),
+        field("amenities", "object", "From the source's fields and description; null when not mentioned").fields(vec![
+            field("parking_spaces", "integer", "").nullable(),
+            field("garden", "boolean", "").nullable(),
+            field("balcony", "boolean", "").nullable(),
+        ]),
+        field("tenancy", "object", "From the source's fields and description; null when not stated").fields(vec![
+            field("available_from", "string", "YYYY-MM-DD").nullable(),
+            field("min_lease_months", "integer", "").nullable(),
+        ]),
+        field("deposit", "number", "Security deposit, in the rent's currency").nullable(),
+        field("coordinates", "object", "Only when the source gives them").nullable().fields(vec![
+            field("lat", "number", ""),
+            field("lng", "number", ""),
+        ]),
+        field("value_score", "number", "Asking rent over the rent predicted for comparable listings, with sort=value")
+            .nullable(),
+        field("semantic_score", "number", "Cosine similarity of the listing's text to semantic_q, -1 to 1").nullable(),
+    ]
+}
+
+#[derive(Debug, Serialize)]
+pub struct Change {
+    pub version: u32,
+    pub change: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Schema {
+    pub version: u32,
+    pub listing: Vec<Field>,
+    pub changelog: Vec<Change>,
+}
+
+pub async fn describe(State(state): State<AppState>) -> Json<Schema> {
+    Json(Schema {
+        version: version(),
+        listing: listing_fields(&state.config.search.price_bands),
+        changelog: CHANGELOG.iter().map(|(version, change)| Change { version: *version, change }).collect(),
+    })
+}
+
+/// Adds `X-Schema-Version` to every response.
+pub async fn version_header(mut response: Response) -> Response {
+    response.headers_mut().insert(HEADER, HeaderValue::from(version()));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::listing;
+    use serde_json::Value;
+
+    /// Field names of `value` that `fields` doesn't describe, and the reverse.
+    fn differences(path: &str, fields: &[Field], value: &Value, found: &mut Vec<String>) {
+        let Some(object) = value.as_object() else {
+            return;
+        };
+        for key in object.keys() {
+            if !fields.iter().any(|field| field.name == key) {
+                found.push(format!("{}{} is not described", path, key));
+            }
+        }
+        for field in fields {
+            let member = match object.get(field.name) {
+                Some(Value::Array(items)) => items.first(),
+                Some(member) => Some(member),
+                None => {
+                    found.push(format!("{}{} is not serialized", path, field.name));
+                    None
+                }
+            };
+            if let Some(member) = member.filter(|_| !field.fields.is_empty()) {
+                differences(&format!("{}{}.", path, field.name), &field.fields, member, found);
+            }
+        }
+    }
+
+    #[test]
+    fn test_schema_matches_listing() {
+        let mut property = listing("daft", "1");
+        property.size = Some(crate::Size { value: 60.0, unit: "m²".to_string() });
+        property.size_confidence = Some(crate::floor_area::SizeConfidence::Medium);
+        property.price_flag = Some(crate::price_bounds::PriceFlag {
+            rule: "dublin-3bed".to_string(),
+            breach: crate::price_bounds::Breach::TooLow,
+            bound: 1200.0,
+        });
+        property.price.price_changes.push(crate::PriceChange {
+            date: "2024-11-01".to_string(),
+            amount: 1900.0,
+            direction: "down".to_string(),
+        });
+        property.photos.push(crate::Photo {
+            url: "a.jpg".to_string(),
+            is_main: true,
+            caption: Some("Kitchen".to_string()),
+            kind: crate::photos::PhotoKind::Photo,
+        });
+        property.agent = Some(crate::Agent {
+            name: String::new(),
+            phone: String::new(),
+            email: String::new(),
+            address: String::new(),
+            licence: String::new(),
+        });
+        let mut annotations = crate::notes::Annotations::default();
+        annotations.notes.push(crate::notes::Note { text: "damp".to_string(), created_at: chrono::Utc::now() });
+        property.annotations = Some(annotations);
+        property.raw = Some(crate::raw::RawRecord {
+            file: "a.parquet".into(),
+            row: 0,
+            mapping_version: 1,
+            fields: Default::default(),
+        });
+        property.display = Some(crate::display::values(crate::locale::Lang::En, &property));
+        property.commercial =
+            Some(crate::commercial::CommercialDetails { price_per_sqft: Some(25.0), zoning: Some("Z4".to_string()) });
+        property.development = crate::developments::from_json(&serde_json::json!([{"bedrooms": 2, "price": 2150}]));
+        property.coordinates = Some(crate::geo::Point { lat: 53.32, lng: -6.26 });
+        property.value_score = Some(0.9);
+        property.semantic_score = Some(0.42);
+        property.legacy_id = Some("daft_1".to_string());
+        property.agent_status = Some(crate::agent_register::AgentStatus::Unmatched);
+
+        let mut found = Vec::new();
+        let fields = listing_fields(&price_band::DEFAULT_BOUNDS);
+        differences("", &fields, &serde_json::to_value(&property).unwrap(), &mut found);
+        assert!(found.is_empty(), "Update the schema description and add a CHANGELOG version: {:?}", found);
+    }
+
+    #[test]
+    fn test_changelog_versions_increase() {
+        assert!(CHANGELOG.windows(2).all(|pair| pair[1].0 == pair[0].0 + 1));
+        assert_eq!(version(), CHANGELOG.len() as u32);
+    }
+}
@@ -0,0 +1,278 @@
+//! Anonymized search and click-through analytics.
+//!
+//! Every search records which filters were used and how many results came
+//! back; every share-link redirect records the short id. Nothing about the
+//! caller is stored, and price filters are rounded to the nearest €100 so rare
+//! exact values can't single anyone out. Events are appended as JSON lines to
+//! `analytics.path` and replayed at startup; `/api/admin/analytics` summarizes
+//! them, with zero-result searches called out to show where coverage is thin.
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::address;
+use crate::auth::{Caller, SCOPE_ADMIN};
+use crate::state::AppState;
+use crate::SearchParams;
+
+/// Events kept in memory for summaries; older ones stay on disk only.
+const MAX_EVENTS: usize = 100_000;
+const TOP_N: usize = 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    Search {
+        at: DateTime<Utc>,
+        filters: BTreeMap<String, String>,
+        results: usize,
+    },
+    Click {
+        at: DateTime<Utc>,
+        short_id: String,
+    },
+}
+
+impl Event {
+    fn at(&self) -> DateTime<Utc> {
+        match self {
+            Event::Search { at, .. } | Event::Click { at, .. } => *at,
+        }
+    }
+}
+
+/// Filters of a search with identifying detail removed.
+pub fn anonymized_filters(params: &SearchParams) -> BTreeMap<String, String> {
+    let round = |price: f64| format!("{}", (price / 100.0).round() * 100.0);
+    let mut filters = BTreeMap::new();
+    if let Some(source) = &params.source {
+        filters.insert("source".to_string(), source.to_lowercase());
+    }
+    if let Some(price) = params.min_price {
+        filters.insert("min_price".to_string(), round(price));
+    }
+    if let Some(price) = params.max_price {
+        filters.insert("max_price".to_string(), round(price));
+    }
+    if let Some(price) = params.max_per_person {
+        filters.insert("max_per_person".to_string(), round(price));
+    }
+    if let Some(bands) = &params.price_band {
+        filters.insert("price_band".to_string(), bands.replace('-', "–"));
+    }
+    if let Some(bedrooms) = params.bedrooms {
+        filters.insert("bedrooms".to_string(), bedrooms.to_string());
+    }
+    if let Some(property_type) = &params.property_type {
+        filters.insert("property_type".to_string(), property_type.to_lowercase());
+    }
+    if let Some(ber) = &params.ber_rating {
+        filters.insert("ber_rating".to_string(), ber.to_uppercase());
+    }
+    if let Some(location) = params.location.as_deref().map(address::normalize).filter(|l| !l.is_empty()) {
+        filters.insert("location".to_string(), location);
+    }
+    filters
+}
+
+fn query_key(filters: &BTreeMap<String, String>) -> String {
+    if filters.is_empty() {
+        return "(no filters)".to_string();
+    }
+    filters.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("&")
+}
+
+pub struct SearchAnalytics {
+    enabled: bool,
+    path: Option<PathBuf>,
+    events: Mutex<Vec<Event>>,
+}
+
+impl SearchAnalytics {
+    /// Replays events already in `path`. `None` keeps events in memory only.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let mut events: Vec<Event> = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| contents.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+            .unwrap_or_default();
+        if events.len() > MAX_EVENTS {
+            events.drain(..events.len() - MAX_EVENTS);
+        }
+        SearchAnalytics { enabled: true, path, events: Mutex::new(events) }
+    }
+
+    /// Records nothing; summaries stay empty.
+    pub fn disabled() -> Self {
+        SearchAnalytics { enabled: false, path: None, events: Mutex::new(Vec::new()) }
+    }
+
+    fn record(&self, event: Event) {
+        if !self.enabled {
+            return;
+        }
+        if let Some(path) = &self.path {
+            let appended = serde_json::to_string(&event).map_err(|e| e.to_string()).and_then(|line| {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                }
+                let mut file = OpenOptions::new().create(true).append(true).open(path).map_err(|e| e.to_string())?;
+                writeln!(file, "{}", line).map_err(|e| e.to_string())
+            });
+            if let Err(e) = appended {
+                warn!("Could not append analytics event to {:?}: {}", path, e);
+            }
+        }
+
+        let mut events = self.events.lock().unwrap();
+        if events.len() >= MAX_EVENTS {
+            events.remove(0);
+        }
+        events.push(event);
+    }
+
+    pub fn record_search(&self, params: &SearchParams, results: usize, at: DateTime<Utc>) {
+        self.record(Event::Search { at, filters: anonymized_filters(params), results });
+    }
+
+    pub fn record_click(&self, short_id: &str, at: DateTime<Utc>) {
+        self.record(Event::Click { at, short_id: short_id.to_string() });
+    }
+
+    pub fn summary(&self, since: Option<DateTime<Utc>>) -> Summary {
+        let events = self.events.lock().unwrap();
+        let mut summary = Summary::default();
+        let mut searches: HashMap<String, (usize, usize)> = HashMap::new();
+        let mut clicks: HashMap<&str, usize> = HashMap::new();
+
+        for event in events.iter().filter(|e| since.is_none_or(|since| e.at() >= since)) {
+            match event {
+                Event::Search { filters, results, .. } => {
+                    summary.searches += 1;
+                    for name in filters.keys() {
+                        *summary.filter_usage.entry(name.clone()).or_default() += 1;
+                    }
+                    let entry = searches.entry(query_key(filters)).or_default();
+                    entry.0 += 1;
+                    if *results == 0 {
+                        summary.zero_result_searches += 1;
+                        entry.1 += 1;
+                    }
+                }
+                Event::Click { short_id, .. } => {
+                    summary.clicks += 1;
+                    *clicks.entry(short_id).or_default() += 1;
+                }
+            }
+        }
+
+        let mut ranked: Vec<QueryCount> = searches
+            .into_iter()
+            .map(|(query, (count, zero_results))| QueryCount { query, count, zero_results })
+            .collect();
+        ranked.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.query.cmp(&b.query)));
+        summary.top_zero_result_searches = ranked.iter().filter(|q| q.zero_results > 0).cloned().collect();
+        summary
+            .top_zero_result_searches
+            .sort_by(|a, b| b.zero_results.cmp(&a.zero_results).then_with(|| a.query.cmp(&b.query)));
+        summary.top_zero_result_searches.truncate(TOP_N);
+        ranked.truncate(TOP_N);
+        summary.top_searches = ranked;
+
+        let mut clicked: Vec<ClickCount> = clicks
+            .into_iter()
+            .map(|(short_id, clicks)| ClickCount { short_id: short_id.to_string(), clicks })
+            .collect();
+        clicked.sort_by(|a, b| b.clicks.cmp(&a.clicks).then_with(|| a.short_id.cmp(&b.short_id)));
+        clicked.truncate(TOP_N);
+        summary.top_clicked = clicked;
+        summary
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryCount {
+    pub query: String,
+    pub count: usize,
+    pub zero_results: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClickCount {
+    pub short_id: String,
+    pub clicks: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    pub searches: usize,
+    pub zero_result_searches: usize,
+    pub clicks: usize,
+    /// How many searches used each filter.
+    pub filter_usage: BTreeMap<String, usize>,
+    pub top_searches: Vec<QueryCount>,
+    pub top_zero_result_searches: Vec<QueryCount>,
+    pub top_clicked: Vec<ClickCount>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SummaryParams {
+    /// Only count events from the last `days` days.
+    days: Option<i64>,
+}
+
+pub async fn summary(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(params): Query<SummaryParams>,
+) -> Result<Json<Summary>, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    let since = params.days.map(|days| state.clock.now() - Duration::days(days));
+    Ok(Json(state.analytics.summary(since)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_dir;
+
+    fn params(query: &str) -> SearchParams {
+        serde_json::from_str(query).unwrap()
+    }
+
+    #[test]
+    fn test_summary_counts_zero_result_searches() {
+        let analytics = SearchAnalytics::open(None);
+        analytics.record_search(&params(r#"{"bedrooms": 2, "max_price": 1849}"#), 12, Utc::now());
+        analytics.record_search(&params(r#"{"bedrooms": 2, "max_price": 1820}"#), 0, Utc::now());
+        analytics.record_search(&params(r#"{"ber_rating": "a1"}"#), 0, Utc::now());
+        analytics.record_click("abc", Utc::now());
+
+        let summary = analytics.summary(None);
+        assert_eq!((summary.searches, summary.zero_result_searches, summary.clicks), (3, 2, 1));
+        assert_eq!(summary.filter_usage["bedrooms"], 2);
+        // Both price filters round to 1800 and count as the same search
+        assert_eq!(summary.top_searches[0].query, "bedrooms=2&max_price=1800");
+        assert_eq!(summary.top_searches[0].count, 2);
+        assert_eq!(summary.top_zero_result_searches.len(), 2);
+        assert_eq!(summary.top_clicked[0].short_id, "abc");
+    }
+
+    #[test]
+    fn test_events_are_replayed_from_disk() {
+        let path = temp_dir("search_analytics").join("events.jsonl");
+        SearchAnalytics::open(Some(path.clone())).record_search(&params("{}"), 0, Utc::now());
+        let reopened = SearchAnalytics::open(Some(path));
+        assert_eq!(reopened.summary(None).zero_result_searches, 1);
+        assert_eq!(reopened.summary(Some(Utc::now() + Duration::days(1))).searches, 0);
+    }
+}
@@ -0,0 +1,334 @@
+//! Semantic search over listing text.
+//!
+//! With `semantic.enabled`, `semantic_q="bright top-floor flat near the canal"`
+//! on `/api/rentals/search` ranks the listings that pass the other filters by
+//! how close their text is to the query, most similar first, and sets each
+//! one's `semantic_score` (cosine similarity, -1 to 1). A listing's text is its
+//! address, property type, bedrooms, feature list and description; the last
+//! two are read from the snapshot file, as listings don't keep them.
+//!
+//! Texts are embedded by `semantic.provider`:
+//!
+//! - `hashing` (the default) runs in process: words and word pairs hashed
+//!   into `dimensions` buckets. It needs nothing else, but only matches words
+//!   the query and listing share.
+//! - `api` POSTs `{"model", "input": [...]}` to `api_url`, any endpoint
+//!   answering like OpenAI's `/v1/embeddings` (a local Ollama or llama.cpp
+//!   server, or a hosted one), `batch_size` texts at a time.
+//!
+//! A source's vectors are built from its latest snapshot when the cache
+//! loads it (see `warmup`), or by the first semantic search after, and kept
+//! in memory next to the snapshot cache. Listings whose text didn't change
+//! keep their vector when a new snapshot is indexed, so an API is only asked
+//! for new and edited listings.
+
+use log::{info, warn};
+use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::{SemanticConfig, SourceConfig};
+use crate::state::AppState;
+use crate::{amenities, select_rows, StandardizedProperty};
+
+/// Words too common in queries and listings to tell them apart.
+const STOP_WORDS: &[&str] =
+    &["a", "an", "and", "at", "by", "for", "in", "is", "of", "on", "or", "the", "to", "with"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    #[default]
+    Hashing,
+    Api,
+}
+
+/// Checks an `api` provider has somewhere to send texts.
+pub fn validate(config: &SemanticConfig) -> Result<(), String> {
+    if config.provider == Provider::Api && (config.api_url.is_none() || config.model.is_none()) {
+        return Err("semantic.provider = \"api\" needs semantic.api_url and semantic.model".to_string());
+    }
+    if config.provider == Provider::Hashing && config.dimensions == 0 {
+        return Err("semantic.dimensions must be above 0".to_string());
+    }
+    Ok(())
+}
+
+/// The text a listing is embedded from.
+pub fn listing_text(property: &StandardizedProperty, description: &str) -> String {
+    let mut parts = vec![property.address.display_address.clone(), property.property_type.clone()];
+    if let Some(bedrooms) = property.bedrooms {
+        parts.push(format!("{} bed", bedrooms));
+    }
+    parts.push(description.trim().to_string());
+    parts.retain(|part| !part.is_empty());
+    parts.join(". ")
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && !STOP_WORDS.contains(word))
+        .map(str::to_string)
+        .collect()
+}
+
+fn hash(value: &impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Scales `vector` to unit length, so a dot product is the cosine.
+fn normalized(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+    vector
+}
+
+/// Words and adjacent word pairs hashed into `dimensions` signed buckets.
+fn hashed(text: &str, dimensions: usize) -> Vec<f32> {
+    let words = words(text);
+    let pairs = words.windows(2).map(|pair| pair.join(" "));
+    let mut vector = vec![0.0; dimensions];
+    for feature in words.iter().cloned().chain(pairs) {
+        let h = hash(&feature);
+        let sign = if h & 1 == 0 { 1.0 } else { -1.0 };
+        vector[(h >> 1) as usize % dimensions] += sign;
+    }
+    vector
+}
+
+#[derive(Debug, Deserialize)]
+struct Embedding {
+    embedding: Vec<f32>,
+    #[serde(default)]
+    index: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<Embedding>,
+}
+
+/// POSTs `texts` to the embeddings API. Blocking, like `notifier::post_json`.
+fn requested(config: &SemanticConfig, texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+    let url = config.api_url.as_deref().ok_or("No semantic.api_url configured")?;
+    let body = serde_json::json!({ "model": config.model, "input": texts });
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut request = client.post(url).header("content-type", "application/json").body(body.to_string());
+    if let Some(key) = &config.api_key {
+        request = request.bearer_auth(key);
+    }
+    let bytes = tokio::runtime::Handle::current()
+        .block_on(async { request.send().await?.error_for_status()?.bytes().await })
+        .map_err(|e| format!("Embedding request to {} failed: {}", url, e))?;
+    let response: EmbeddingResponse =
+        serde_json::from_slice(&bytes).map_err(|e| format!("Unexpected embeddings from {}: {}", url, e))?;
+    if response.data.len() != texts.len() {
+        return Err(format!("{} returned {} embeddings for {} texts", url, response.data.len(), texts.len()));
+    }
+    let mut data = response.data;
+    data.sort_by_key(|embedding| embedding.index);
+    Ok(data.into_iter().map(|embedding| embedding.embedding).collect())
+}
+
+/// Unit vectors of `texts`, in order.
+pub fn embed(config: &SemanticConfig, texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+    let vectors = match config.provider {
+        Provider::Hashing => texts.iter().map(|text| hashed(text, config.dimensions)).collect(),
+        Provider::Api => {
+            let mut vectors = Vec::with_capacity(texts.len());
+            for batch in texts.chunks(config.batch_size.max(1)) {
+                vectors.extend(requested(config, batch)?);
+            }
+            vectors
+        }
+    };
+    Ok(vectors.into_iter().map(normalized).collect())
+}
+
+fn similarity(a: &[f32], b: &[f32]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| f64::from(x * y)).sum()
+}
+
+struct Entry {
+    /// Of the text the vector was embedded from.
+    text_hash: u64,
+    vector: Vec<f32>,
+}
+
+/// Vectors of one snapshot's listings, by `property_id`.
+struct Vectors {
+    file: PathBuf,
+    entries: HashMap<String, Entry>,
+}
+
+/// Each source's vectors, for its most recently indexed snapshot.
+#[derive(Default)]
+pub struct SemanticIndex {
+    sources: Mutex<HashMap<String, Arc<Vectors>>>,
+    /// Held while a snapshot is indexed, so concurrent searches wait for it
+    /// rather than embed it again.
+    building: Mutex<()>,
+}
+
+impl SemanticIndex {
+    fn current(&self, source: &str, file: &Path) -> Option<Arc<Vectors>> {
+        self.sources.lock().unwrap().get(source).filter(|vectors| vectors.file == file).cloned()
+    }
+}
+
+/// The vectors of `source`'s snapshot at `file`, embedding it if it isn't
+/// indexed yet. Blocking.
+fn vectors(state: &AppState, source: &SourceConfig, file: &Path) -> Result<Arc<Vectors>, String> {
+    let index = &state.semantic;
+    if let Some(vectors) = index.current(&source.name, file) {
+        return Ok(vectors);
+    }
+    let _building = index.building.lock().unwrap();
+    if let Some(vectors) = index.current(&source.name, file) {
+        return Ok(vectors);
+    }
+
+    let started = Instant::now();
+    let mut listings = Vec::new();
+    state.cache.scan(source, file, 0, |row, property| {
+        listings.push((row, property));
+        ControlFlow::Continue(())
+    });
+    let rows: Vec<usize> = listings.iter().map(|(row, _)| *row).collect();
+    let mut descriptions = HashMap::new();
+    select_rows(source, file, &rows, |row, _, batch_row| {
+        descriptions.insert(row, amenities::listing_text(batch_row));
+    });
+
+    let previous = index.sources.lock().unwrap().get(&source.name).cloned();
+    let mut entries = HashMap::with_capacity(listings.len());
+    let mut pending = Vec::new();
+    for (row, property) in &listings {
+        let text = listing_text(property, descriptions.get(row).map_or("", String::as_str));
+        let text_hash = hash(&text);
+        let kept = previous.as_ref().and_then(|previous| previous.entries.get(&property.property_id));
+        match kept.filter(|entry| entry.text_hash == text_hash) {
+            Some(entry) => {
+                entries.insert(property.property_id.clone(), Entry { text_hash, vector: entry.vector.clone() });
+            }
+            None => pending.push((property.property_id.clone(), text_hash, text)),
+        }
+    }
+    let texts: Vec<String> = pending.iter().map(|(_, _, text)| text.clone()).collect();
+    let embedded = embed(&state.config.semantic, &texts)?;
+    for ((id, text_hash, _), vector) in pending.into_iter().zip(embedded) {
+        entries.insert(id, Entry { text_hash, vector });
+    }
+    info!(
+        "Indexed {} listings of {:?} for semantic search ({} embedded) in {:?}",
+        entries.len(),
+        file,
+        texts.len(),
+        started.elapsed()
+    );
+
+    let vectors = Arc::new(Vectors { file: file.to_path_buf(), entries });
+    index.sources.lock().unwrap().insert(source.name.clone(), vectors.clone());
+    Ok(vectors)
+}
+
+/// Indexes `source`'s snapshot at `file` ahead of the first semantic search,
+/// when semantic search is on. Blocking; failures are logged.
+pub fn prepare(state: &AppState, source: &SourceConfig, file: &Path) {
+    if !state.config.semantic.enabled {
+        return;
+    }
+    if let Err(e) = vectors(state, source, file) {
+        warn!("Could not index {} for semantic search: {}", source.name, e);
+    }
+}
+
+/// Positions of `properties`, most similar to `query` first, setting each
+/// one's `semantic_score`; `files` are the snapshots they came from, by
+/// source. Listings without a vector come last. Blocking.
+pub fn rank(
+    state: &AppState,
+    query: &str,
+    properties: &mut [StandardizedProperty],
+    files: &HashMap<String, PathBuf>,
+) -> Result<Vec<usize>, String> {
+    let config = &state.config.semantic;
+    let query = embed(config, &[query.to_string()])?.pop().unwrap_or_default();
+    let mut indexed = HashMap::new();
+    let present: HashSet<&str> = properties.iter().map(|property| property.source.as_str()).collect();
+    for (name, file) in files.iter().filter(|(name, _)| present.contains(name.as_str())) {
+        if let Some(source) = state.config.sources.iter().find(|source| source.name == *name) {
+            indexed.insert(name.as_str(), vectors(state, source, file)?);
+        }
+    }
+    for property in properties.iter_mut() {
+        let entry = indexed.get(property.source.as_str()).and_then(|v| v.entries.get(&property.property_id));
+        property.semantic_score = entry.map(|entry| (similarity(&query, &entry.vector) * 10_000.0).round() / 10_000.0);
+    }
+    let mut order: Vec<usize> = (0..properties.len()).collect();
+    let score = |index: &usize| properties[*index].semantic_score.unwrap_or(f64::NEG_INFINITY);
+    order.sort_by(|a, b| score(b).total_cmp(&score(a)));
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::test_utils::{listing, standardized_batch, stores_in, temp_dir, write_parquet};
+
+    #[test]
+    fn test_listings_rank_by_shared_words() {
+        let config = SemanticConfig { enabled: true, ..SemanticConfig::default() };
+        let texts = [
+            "Bright top floor apartment overlooking the Grand Canal".to_string(),
+            "Three bed semi-detached house with garden in Lucan".to_string(),
+        ];
+        let vectors = embed(&config, &texts).unwrap();
+        let query = embed(&config, &["bright top-floor flat near the canal".to_string()]).unwrap().remove(0);
+        assert!(similarity(&query, &vectors[0]) > similarity(&query, &vectors[1]));
+        assert!((similarity(&vectors[0], &vectors[0]) - 1.0).abs() < 1e-6);
+
+        let mut property = listing("daft", "1");
+        property.address.display_address = "Grand Canal Dock".to_string();
+        property.property_type = "Apartment".to_string();
+        property.bedrooms = Some(2);
+        assert_eq!(listing_text(&property, " Bright. "), "Grand Canal Dock. Apartment. 2 bed. Bright.");
+
+        let api = Config::from_toml("[semantic]\nenabled = true\nprovider = \"api\"");
+        assert!(api.is_err());
+    }
+
+    #[test]
+    fn test_search_results_are_ranked() {
+        let data = temp_dir("semantic");
+        let file = data.join("processed/rent_ie/2024/11/05/rent_ie_120000.parquet");
+        write_parquet(&file, &standardized_batch(&["1", "2", "3"]));
+        let config = Config::from_toml(&format!(
+            "data_path = {:?}\n[semantic]\nenabled = true\n[[sources]]\nname = \"rent_ie\"",
+            data
+        ))
+        .unwrap();
+        let state = AppState::new(stores_in(config, &data));
+        let mut properties = state.store.latest("rent_ie");
+        let query = properties[2].address.display_address.clone();
+        let files = HashMap::from([("rent_ie".to_string(), file)]);
+
+        let order = rank(&state, &query, &mut properties, &files).unwrap();
+        assert_eq!(order[0], 2);
+        assert!(properties.iter().all(|property| property.semantic_score.is_some()));
+    }
+}
@@ -0,0 +1,152 @@
+//! Rent split between sharers.
+//!
+//! Most sharers search by what they would pay each, not by the whole rent.
+//! A listing's rent is divided over its bedrooms, one person per bedroom and
+//! one for a studio; room-share listings are already priced per room. The
+//! `max_per_person` search filter uses the equal split, and
+//! `GET /api/tools/split` splits any rent, optionally weighting rooms (a
+//! double room with an ensuite paying more than a box room).
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::state::AppState;
+use crate::StandardizedProperty;
+
+/// People paying the rent: a room share is one room; otherwise one per
+/// bedroom, with studios housing one. `None` when bedrooms are unknown.
+pub fn sharers(property: &StandardizedProperty) -> Option<u32> {
+    let property_type = property.property_type.to_lowercase();
+    if property_type.contains("share") || property_type.contains("room") {
+        return Some(1);
+    }
+    property.bedrooms.map(|bedrooms| bedrooms.max(1) as u32)
+}
+
+/// Each sharer's equal part of the rent.
+pub fn per_person(property: &StandardizedProperty) -> Option<f64> {
+    sharers(property).map(|people| property.price.amount / people as f64)
+}
+
+/// Divides `rent` in proportion to `weights`, to the cent. Rounding is
+/// settled on the first room so the shares add up to the rent exactly.
+pub fn split(rent: f64, weights: &[f64]) -> Vec<f64> {
+    let total: f64 = weights.iter().sum();
+    let cents = (rent * 100.0).round();
+    let mut shares: Vec<f64> = weights.iter().map(|w| (cents * w / total).round()).collect();
+    let rounding = cents - shares.iter().sum::<f64>();
+    if let Some(first) = shares.first_mut() {
+        *first += rounding;
+    }
+    shares.into_iter().map(|share| share / 100.0).collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SplitParams {
+    /// Split this listing's rent over its rooms.
+    property_id: Option<String>,
+    /// Or split a given rent over `bedrooms` rooms.
+    rent: Option<f64>,
+    bedrooms: Option<u32>,
+    /// Comma-separated relative room sizes, e.g. "1.5,1,1"; equal by default.
+    weights: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoomShare {
+    pub room: usize,
+    pub weight: f64,
+    pub share: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SplitResponse {
+    pub property_id: Option<String>,
+    pub rent: f64,
+    pub per_person: f64,
+    pub rooms: Vec<RoomShare>,
+}
+
+fn bad_request(message: impl Into<String>) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, message.into())
+}
+
+pub async fn rent_split(
+    State(state): State<AppState>,
+    Query(params): Query<SplitParams>,
+) -> Result<Json<SplitResponse>, (StatusCode, String)> {
+    let (rent, rooms) = match &params.property_id {
+        Some(property_id) => {
+            let property = state
+                .id_index
+                .lookup(&state.config, std::slice::from_ref(property_id))
+                .into_iter()
+                .next()
+                .ok_or((StatusCode::NOT_FOUND, format!("No listing {}", property_id)))?;
+            let rooms = sharers(&property).ok_or_else(|| bad_request(format!("Listing {} has no bedroom count", property_id)))?;
+            (property.price.amount, rooms)
+        }
+        None => match (params.rent, params.bedrooms) {
+            (Some(rent), Some(bedrooms)) => (rent, bedrooms.max(1)),
+            _ => return Err(bad_request("Give property_id, or rent and bedrooms")),
+        },
+    };
+    if !(rent.is_finite() && rent > 0.0) || rooms > 20 {
+        return Err(bad_request("rent must be positive and bedrooms at most 20"));
+    }
+    let weights: Vec<f64> = match &params.weights {
+        Some(weights) => weights
+            .split(',')
+            .map(|w| w.trim().parse::<f64>().ok().filter(|w| w.is_finite() && *w > 0.0))
+            .collect::<Option<_>>()
+            .ok_or_else(|| bad_request("weights must be positive numbers"))?,
+        None => vec![1.0; rooms as usize],
+    };
+    if weights.len() != rooms as usize {
+        return Err(bad_request(format!("Give one weight per room ({})", rooms)));
+    }
+
+    let shares = split(rent, &weights);
+    Ok(Json(SplitResponse {
+        property_id: params.property_id,
+        rent,
+        per_person: (rent * 100.0 / rooms as f64).round() / 100.0,
+        rooms: weights
+            .into_iter()
+            .zip(shares)
+            .enumerate()
+            .map(|(i, (weight, share))| RoomShare { room: i + 1, weight, share })
+            .collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::listing;
+
+    #[test]
+    fn test_split_adds_up() {
+        assert_eq!(split(2400.0, &[1.0, 1.0, 1.0]), [800.0, 800.0, 800.0]);
+        assert_eq!(split(1000.0, &[1.0, 1.0, 1.0]), [333.34, 333.33, 333.33]);
+        assert_eq!(split(2500.0, &[1.5, 1.0, 1.0]), [1071.42, 714.29, 714.29]);
+    }
+
+    #[test]
+    fn test_per_person() {
+        let mut property = listing("daft", "1");
+        property.price.amount = 2400.0;
+        property.bedrooms = Some(3);
+        assert_eq!(per_person(&property), Some(800.0));
+        property.bedrooms = Some(0);
+        assert_eq!(per_person(&property), Some(2400.0));
+        property.property_type = "House Share".to_string();
+        property.bedrooms = Some(4);
+        assert_eq!(per_person(&property), Some(2400.0));
+        property.property_type = "Apartment".to_string();
+        property.bedrooms = None;
+        assert_eq!(per_person(&property), None);
+    }
+}
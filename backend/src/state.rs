@@ -0,0 +1,175 @@
+use log::{error, info};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::abuse::AbuseGuard;
+use crate::analytics::density::DensityAlerts;
+use crate::analytics::regime::RegimeAlerts;
+use crate::audit::AuditLog;
+use crate::cache::SnapshotCache;
+use crate::clock::{self, Clock};
+use crate::config::Config;
+use crate::corrections::Corrections;
+use crate::feed::ChangeFeed;
+use crate::hidden::HiddenListings;
+use crate::history::SearchHistory;
+use crate::id_index::IdIndex;
+use crate::ids::IdRegistry;
+use crate::jobs::JobQueue;
+use crate::links::ShortLinks;
+use crate::notes::ListingNotes;
+use crate::pagination::SnapshotPins;
+use crate::photos::PhotoCache;
+use crate::preferences::NotificationPreferences;
+use crate::price_history::PriceChanges;
+use crate::privacy::AgentPrivacy;
+use crate::quality::report::QualityHistory;
+use crate::rate_limit::RateLimiter;
+use crate::recommendations::Interactions;
+use crate::reports::schedule::ReportSchedules;
+use crate::reports::watch::WatchedAreas;
+use crate::search_analytics::SearchAnalytics;
+use crate::semantic::SemanticIndex;
+use crate::store::{ParquetStore, PropertyStore};
+use crate::toggles::SourceToggles;
+use crate::viewings::Viewings;
+use crate::warmup::WarmupProgress;
+
+/// Shared state handed to every handler.
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    /// What handlers take as now; see `clock`.
+    pub clock: Arc<dyn Clock>,
+    /// Listings; see `store`.
+    pub store: Arc<dyn PropertyStore>,
+    pub id_index: Arc<IdIndex>,
+    pub ids: Arc<IdRegistry>,
+    pub cache: Arc<SnapshotCache>,
+    pub warmup: Arc<WarmupProgress>,
+    pub snapshot_pins: Arc<SnapshotPins>,
+    pub links: Arc<ShortLinks>,
+    pub analytics: Arc<SearchAnalytics>,
+    pub privacy: Arc<AgentPrivacy>,
+    pub notes: Arc<ListingNotes>,
+    pub photos: Arc<PhotoCache>,
+    pub hidden: Arc<HiddenListings>,
+    /// Favourites and click-throughs; see `recommendations`.
+    pub interactions: Arc<Interactions>,
+    pub history: Arc<SearchHistory>,
+    pub viewings: Arc<Viewings>,
+    pub preferences: Arc<NotificationPreferences>,
+    pub audit: Arc<AuditLog>,
+    pub feed: Arc<ChangeFeed>,
+    pub jobs: Arc<JobQueue>,
+    pub density: Arc<DensityAlerts>,
+    pub regimes: Arc<RegimeAlerts>,
+    pub quality: Arc<QualityHistory>,
+    pub report_schedules: Arc<ReportSchedules>,
+    pub watches: Arc<WatchedAreas>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub abuse: Arc<AbuseGuard>,
+    pub source_toggles: Arc<SourceToggles>,
+    pub corrections: Arc<Corrections>,
+    pub semantic: Arc<SemanticIndex>,
+    pub price_changes: Arc<PriceChanges>,
+}
+
+impl AppState {
+    pub fn new(mut config: Config) -> Self {
+        let source_toggles = SourceToggles::open(config.source_toggles.path.clone());
+        source_toggles.apply(&mut config);
+        let cache = SnapshotCache::new(config.cache.budget_bytes()).with_parse_threads(config.cache.parse_threads);
+        let sources: Vec<String> = config.sources.iter().map(|s| s.name.clone()).collect();
+        let warmup = WarmupProgress::new(config.cache.warm_up, &sources);
+        let snapshot_pins = SnapshotPins::new(Duration::from_secs(config.search.snapshot_ttl_secs));
+        let analytics = if config.analytics.enabled {
+            SearchAnalytics::open(config.analytics.path.clone())
+        } else {
+            SearchAnalytics::disabled()
+        };
+        let privacy = AgentPrivacy::open(config.privacy.suppressions_path.clone());
+        let notes = ListingNotes::open(config.notes.path.clone());
+        let photos = PhotoCache::open(&config.photos);
+        let links = ShortLinks::open(config.links.clicks_path.clone());
+        let hidden = HiddenListings::open(config.notes.hidden_path.clone());
+        let interactions = Interactions::open(config.notes.interactions_path.clone());
+        let history = SearchHistory::open(&config.history);
+        let viewings = Viewings::open(config.notes.viewings_path.clone());
+        let preferences = NotificationPreferences::open(config.notifier.preferences_path.clone());
+        let report_schedules = ReportSchedules::open(config.reports.schedules_path.clone());
+        let watches = WatchedAreas::open(config.reports.watches_path.clone());
+        let clock = clock::from_config(&config.clock);
+        let audit = AuditLog::open(config.audit.path.clone(), clock.clone());
+        let feed = ChangeFeed::open(config.deltas.feed_path.clone());
+        let quality = QualityHistory::open(config.quality.path.clone());
+        let jobs = JobQueue::open(&config.jobs, clock.clone()).unwrap_or_else(|e| {
+            error!("{}; queued jobs will not survive a restart", e);
+            JobQueue::in_memory(clock.clone())
+        });
+        let rate_limiter = RateLimiter::new(&config.rate_limit);
+        let abuse = AbuseGuard::new(&config.abuse);
+        let corrections = Corrections::open(config.corrections.path.clone());
+        let ids = IdRegistry::open(&config.ids, &config.data_path);
+        let config = Arc::new(config);
+        let (cache, id_index) = (Arc::new(cache), Arc::new(IdIndex::default()));
+        AppState {
+            store: Arc::new(ParquetStore::new(config.clone(), cache.clone(), id_index.clone())),
+            clock,
+            config,
+            id_index,
+            ids: Arc::new(ids),
+            cache,
+            warmup: Arc::new(warmup),
+            snapshot_pins: Arc::new(snapshot_pins),
+            links: Arc::new(links),
+            analytics: Arc::new(analytics),
+            privacy: Arc::new(privacy),
+            notes: Arc::new(notes),
+            photos: Arc::new(photos),
+            hidden: Arc::new(hidden),
+            interactions: Arc::new(interactions),
+            history: Arc::new(history),
+            viewings: Arc::new(viewings),
+            preferences: Arc::new(preferences),
+            audit: Arc::new(audit),
+            feed: Arc::new(feed),
+            jobs: Arc::new(jobs),
+            density: Arc::default(),
+            regimes: Arc::default(),
+            quality: Arc::new(quality),
+            report_schedules: Arc::new(report_schedules),
+            watches: Arc::new(watches),
+            rate_limiter: Arc::new(rate_limiter),
+            abuse: Arc::new(abuse),
+            source_toggles: Arc::new(source_toggles),
+            corrections: Arc::new(corrections),
+            semantic: Arc::default(),
+            price_changes: Arc::default(),
+        }
+    }
+
+    /// This state under a reloaded `config`. The store reads the new source
+    /// list through the same cache and id index, so nothing has to be warmed
+    /// again unless the parse settings changed; rate limits and the snapshot
+    /// pin TTL change in place. Stores opened from a path at startup are kept
+    /// as they are.
+    pub fn reconfigured(&self, mut config: Config) -> Self {
+        self.source_toggles.apply(&mut config);
+        if config.parsing != self.config.parsing {
+            info!("Parse settings changed; cached snapshots will be parsed again");
+            for source in &self.config.sources {
+                self.cache.evict(&source.name);
+            }
+        }
+        self.snapshot_pins.set_ttl(Duration::from_secs(config.search.snapshot_ttl_secs));
+        self.rate_limiter.reconfigure(&config.rate_limit);
+        self.abuse.reconfigure(&config.abuse);
+        let config = Arc::new(config);
+        AppState {
+            store: Arc::new(ParquetStore::new(config.clone(), self.cache.clone(), self.id_index.clone())),
+            config,
+            ..self.clone()
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Pushes aggregates to lightweight dashboards.
+//!
+//! With `stats_sink.enabled`, each source's latest snapshot is checked every
+//! `stats_sink.check_interval_secs`. When one lands (from its collector or
+//! through `POST /api/admin/sources/{source}/snapshots`), the listing count
+//! and median rent of each of `stats_sink.aggregates` over it are sent to
+//! every `[[stats_sink.sinks]]` entry by a `stats.push` job:
+//!
+//! - `type = "webhook"` POSTs `{"source", "date", "rows": [...]}` to `url`;
+//! - `type = "sheets"` appends one row per group (`date`, `source`,
+//!   `aggregate`, `key`, `listings`, `median_rent`) to `range` of the Google
+//!   Sheet `spreadsheet_id`, authorized with the OAuth `access_token`.
+//!
+//! The snapshot each source was last pushed for is kept at
+//! `stats_sink.state_path`, so a restart doesn't push it again and snapshots
+//! that landed while the service was down are pushed on the first check.
+//! Medians over fewer than `stats.min_sample` listings are sent as null.
+
+use chrono::NaiveDate;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::analytics::sample;
+use crate::reports::market::{bedrooms_key, property_type_key};
+use crate::state::AppState;
+use crate::store::PropertyStore;
+use crate::{address, notifier, StandardizedProperty};
+
+/// Job kind that sends one snapshot's aggregates to one sink.
+pub const JOB: &str = "stats.push";
+
+/// What listings are grouped by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregate {
+    /// Every listing, under the key "all".
+    Total,
+    Area,
+    Bedrooms,
+    PropertyType,
+}
+
+impl Aggregate {
+    fn key(self, property: &StandardizedProperty) -> Option<String> {
+        let key = match self {
+            Aggregate::Total => "all".to_string(),
+            Aggregate::Area => address::area(&property.address.normalized_address).to_string(),
+            Aggregate::Bedrooms => bedrooms_key(property).to_string(),
+            Aggregate::PropertyType => property_type_key(property),
+        };
+        Some(key).filter(|key| !key.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Sink {
+    Webhook {
+        url: String,
+    },
+    Sheets {
+        spreadsheet_id: String,
+        /// A1 range the rows are appended after, e.g. "Rents!A:F".
+        range: String,
+        access_token: String,
+        #[serde(default = "default_sheets_api_url")]
+        api_url: String,
+    },
+}
+
+fn default_sheets_api_url() -> String {
+    "https://sheets.googleapis.com".to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Row {
+    pub aggregate: Aggregate,
+    pub key: String,
+    pub listings: usize,
+    pub median_rent: Option<f64>,
+}
+
+/// One snapshot's aggregates.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Push {
+    pub source: String,
+    pub date: NaiveDate,
+    pub rows: Vec<Row>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Delivery {
+    /// Index into `stats_sink.sinks`, so access tokens stay out of the job
+    /// queue.
+    sink: usize,
+    push: Push,
+}
+
+/// `aggregates` over `source`'s snapshot on `date`, each sorted by key.
+pub fn aggregate(store: &dyn PropertyStore, source: &str, date: NaiveDate, aggregates: &[Aggregate]) -> Push {
+    let mut rows = Vec::new();
+    for &aggregate in aggregates {
+        let groups: BTreeMap<String, _> = store.aggregate(source, date, &|p| aggregate.key(p)).into_iter().collect();
+        rows.extend(groups.into_iter().map(|(key, group)| Row {
+            aggregate,
+            key,
+            listings: group.listings,
+            median_rent: group.median_rent.and_then(|median| sample::suppress(median, group.listings)),
+        }));
+    }
+    Push { source: source.to_string(), date, rows }
+}
+
+/// The cells `push` appends to a sheet, one row per group.
+fn sheet_values(push: &Push) -> Vec<Vec<serde_json::Value>> {
+    push.rows
+        .iter()
+        .map(|row| {
+            let aggregate = serde_json::to_value(row.aggregate).unwrap_or_default();
+            vec![
+                push.date.to_string().into(),
+                push.source.clone().into(),
+                aggregate,
+                row.key.clone().into(),
+                row.listings.into(),
+                row.median_rent.into(),
+            ]
+        })
+        .collect()
+}
+
+fn send(sink: &Sink, push: &Push) -> Result<(), String> {
+    match sink {
+        Sink::Webhook { url } => notifier::post_json(url, push),
+        Sink::Sheets { spreadsheet_id, range, access_token, api_url } => {
+            let mut url = reqwest::Url::parse(api_url).map_err(|e| format!("Invalid Sheets API URL: {}", e))?;
+            url.path_segments_mut()
+                .map_err(|_| "Invalid Sheets API URL".to_string())?
+                .extend(["v4", "spreadsheets", spreadsheet_id, "values", &format!("{}:append", range)]);
+            url.query_pairs_mut()
+                .append_pair("valueInputOption", "RAW")
+                .append_pair("insertDataOption", "INSERT_ROWS");
+            let body = serde_json::json!({ "values": sheet_values(push) });
+            notifier::post_json_authorized(url.as_str(), Some(access_token), &body)
+        }
+    }
+}
+
+pub fn run_job(state: &AppState, payload: &serde_json::Value) -> Result<(), String> {
+    let delivery: Delivery = serde_json::from_value(payload.clone()).map_err(|e| e.to_string())?;
+    let sink = state.config.stats_sink.sinks.get(delivery.sink).ok_or("Stats sink is no longer configured")?;
+    send(sink, &delivery.push)?;
+    info!("Pushed {} stats for {} to sink {}", delivery.push.source, delivery.push.date, delivery.sink);
+    Ok(())
+}
+
+/// The snapshot date each source was last pushed for.
+struct Pushed {
+    path: Option<PathBuf>,
+    dates: BTreeMap<String, NaiveDate>,
+}
+
+impl Pushed {
+    fn open(path: Option<PathBuf>) -> Self {
+        let dates = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Pushed { path, dates }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let contents = serde_json::to_string_pretty(&self.dates).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| e.to_string())
+    }
+
+    /// Sources whose latest snapshot hasn't been pushed, with its date.
+    fn pending(&self, store: &dyn PropertyStore, sources: &[String]) -> Vec<(String, NaiveDate)> {
+        sources
+            .iter()
+            .filter_map(|source| Some((source.clone(), *store.snapshots(source).last()?)))
+            .filter(|(source, date)| self.dates.get(source) != Some(date))
+            .collect()
+    }
+}
+
+/// Queues a push of every new latest snapshot to each sink. Returns how many
+/// snapshots were queued.
+fn check(state: &AppState, pushed: &mut Pushed) -> usize {
+    let config = &state.config.stats_sink;
+    let sources: Vec<String> = state.config.sources.iter().map(|source| source.name.clone()).collect();
+    let pending = pushed.pending(state.store.as_ref(), &sources);
+    for (source, date) in &pending {
+        let push = aggregate(state.store.as_ref(), source, *date, &config.aggregates);
+        for sink in 0..config.sinks.len() {
+            if let Err(e) = state.jobs.enqueue(JOB, Delivery { sink, push: push.clone() }) {
+                error!("Could not queue stats push for {} {}: {}", source, date, e);
+            }
+        }
+        pushed.dates.insert(source.clone(), *date);
+    }
+    if !pending.is_empty() {
+        if let Err(e) = pushed.save() {
+            error!("Could not save pushed stats snapshots: {}", e);
+        }
+    }
+    pending.len()
+}
+
+/// Checks for new snapshots every `stats_sink.check_interval_secs` for the
+/// life of the process.
+pub fn watch(state: &AppState) {
+    if !state.config.stats_sink.enabled || state.config.stats_sink.sinks.is_empty() {
+        return;
+    }
+    let state = state.clone();
+    let period = Duration::from_secs(state.config.stats_sink.check_interval_secs.max(1));
+    let pushed = Arc::new(Mutex::new(Pushed::open(state.config.stats_sink.state_path.clone())));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let (scan, pushed) = (state.clone(), pushed.clone());
+            match tokio::task::spawn_blocking(move || check(&scan, &mut pushed.lock().unwrap())).await {
+                Ok(0) => {}
+                Ok(queued) => info!("Queued stats pushes for {} new snapshots", queued),
+                Err(e) => error!("Stats sink check failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::test_utils::listing;
+
+    fn rental(id: &str, beds: i32, rent: f64) -> StandardizedProperty {
+        let mut property = listing("feed", id);
+        property.bedrooms = Some(beds);
+        property.address.normalized_address = address::normalize("Main Street, Dublin 6");
+        property.price.amount = rent;
+        property
+    }
+
+    #[test]
+    fn test_new_snapshots_are_aggregated_once() {
+        let store = MemoryStore::default();
+        let date: NaiveDate = "2024-11-05".parse().unwrap();
+        let listings = [rental("1", 2, 1800.0), rental("2", 2, 2000.0), rental("3", 3, 2600.0)];
+        store.ingest("feed", date, &listings).unwrap();
+
+        let push = aggregate(&store, "feed", date, &[Aggregate::Total, Aggregate::Bedrooms]);
+        let rows: Vec<(&str, usize, Option<f64>)> =
+            push.rows.iter().map(|row| (row.key.as_str(), row.listings, row.median_rent)).collect();
+        assert_eq!(rows, [("all", 3, Some(2000.0)), ("2", 2, Some(1900.0)), ("3", 1, Some(2600.0))]);
+        assert_eq!(
+            sheet_values(&push)[1],
+            serde_json::json!(["2024-11-05", "feed", "bedrooms", "2", 2, 1900.0]).as_array().unwrap().clone()
+        );
+
+        let mut pushed = Pushed::open(None);
+        let sources = ["feed".to_string(), "empty".to_string()];
+        assert_eq!(pushed.pending(&store, &sources), [("feed".to_string(), date)]);
+        pushed.dates.insert("feed".to_string(), date);
+        assert!(pushed.pending(&store, &sources).is_empty());
+
+        let sink: Sink =
+            toml::from_str("type = 'sheets'\nspreadsheet_id = 'abc'\nrange = 'A:F'\naccess_token = 't'").unwrap();
+        assert!(matches!(sink, Sink::Sheets { api_url, .. } if api_url == "https://sheets.googleapis.com"));
+    }
+}
@@ -0,0 +1,387 @@
+//! Where listings are read from.
+//!
+//! `PropertyStore` is the data access handlers go through: a source's
+//! snapshot dates and the listings of any one of them, lookups by id, and
+//! `ingest` to add a snapshot. `query` and `aggregate` are built on those, so a
+//! backend only has to provide the five primitives, though one with its own
+//! query engine (SQLite, Postgres, DuckDB) can override them.
+//!
+//! `ParquetStore` reads the partitioned parquet files under `data_path`,
+//! serving latest snapshots (or the fallback for an unreadable one, see
+//! `fallback`) from the snapshot cache; `MemoryStore` keeps
+//! everything in memory, for tests. Paginated search still scans the parquet
+//! files itself: its cursors, snapshot tokens and raw records point at rows of
+//! particular files.
+//!
+//! Admins can push a snapshot for a standardized source with
+//! `POST /api/admin/sources/{source}/snapshots`.
+
+use arrow::array::{ArrayRef, BooleanArray, Float64Array, Int64Array, RecordBatch, StringArray};
+use axum::extract::{Path as UrlPath, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::NaiveDate;
+use log::info;
+use parquet::arrow::ArrowWriter;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+#[cfg(test)]
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::ops::{ControlFlow, RangeInclusive};
+use std::path::Path;
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::RwLock;
+
+use crate::auth::{Caller, SCOPE_ADMIN};
+use crate::cache::SnapshotCache;
+use crate::clock::Clock;
+use crate::config::{Config, ParserKind, SourceConfig};
+use crate::fallback;
+use crate::id_index::IdIndex;
+use crate::state::AppState;
+use crate::{find_latest_parquet, list_snapshots, load_properties, validate_price, StandardizedProperty};
+
+/// Listings sharing an `aggregate` key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    pub listings: usize,
+    /// Over the listings with a valid rent; `None` when none has one.
+    pub median_rent: Option<f64>,
+}
+
+pub trait PropertyStore: Send + Sync {
+    /// Dates of `source`'s snapshots, oldest first.
+    fn snapshots(&self, source: &str) -> Vec<NaiveDate>;
+
+    /// Every listing of `source`'s snapshot on `date`; empty without one.
+    fn listings(&self, source: &str, date: NaiveDate) -> Vec<StandardizedProperty>;
+
+    /// Listings of `source`'s latest snapshot.
+    fn latest(&self, source: &str) -> Vec<StandardizedProperty> {
+        match self.snapshots(source).last() {
+            Some(date) => self.listings(source, *date),
+            None => vec![],
+        }
+    }
+
+    /// Listings in the latest snapshot of any source with one of `ids`, in
+    /// the order of `ids`.
+    fn get(&self, ids: &[String]) -> Vec<StandardizedProperty>;
+
+    /// Stores `listings` as `source`'s snapshot for `date`, replacing any.
+    fn ingest(&self, source: &str, date: NaiveDate, listings: &[StandardizedProperty]) -> Result<(), String>;
+
+    /// Every listing seen in `source`'s snapshots dated within `dates`, each
+    /// as of the last snapshot it was in.
+    fn across(&self, source: &str, dates: RangeInclusive<NaiveDate>) -> Vec<StandardizedProperty> {
+        let mut latest: HashMap<String, StandardizedProperty> = HashMap::new();
+        for date in self.snapshots(source).into_iter().filter(|date| dates.contains(date)) {
+            for property in self.listings(source, date) {
+                latest.insert(property.property_id.clone(), property);
+            }
+        }
+        let mut listings: Vec<StandardizedProperty> = latest.into_values().collect();
+        listings.sort_by(|a, b| a.property_id.cmp(&b.property_id));
+        listings
+    }
+
+    /// Listings of the latest snapshot of each of `sources` that pass `filter`.
+    fn query(&self, sources: &[&str], filter: &dyn Fn(&StandardizedProperty) -> bool) -> Vec<StandardizedProperty> {
+        sources.iter().flat_map(|source| self.latest(source)).filter(|p| filter(p)).collect()
+    }
+
+    /// Listings of `source`'s snapshot on `date` grouped by `key`; listings
+    /// without a key are left out.
+    fn aggregate(
+        &self,
+        source: &str,
+        date: NaiveDate,
+        key: &dyn Fn(&StandardizedProperty) -> Option<String>,
+    ) -> HashMap<String, Group> {
+        let mut rents: HashMap<String, (usize, Vec<f64>)> = HashMap::new();
+        for property in self.listings(source, date) {
+            let Some(key) = key(&property) else {
+                continue;
+            };
+            let (listings, valid) = rents.entry(key).or_default();
+            *listings += 1;
+            if validate_price(&property) {
+                valid.push(property.price.amount);
+            }
+        }
+        rents
+            .into_iter()
+            .map(|(key, (listings, mut valid))| {
+                valid.sort_by(f64::total_cmp);
+                let mid = valid.len() / 2;
+                let median_rent = match valid.len() {
+                    0 => None,
+                    n if n % 2 == 0 => Some((valid[mid - 1] + valid[mid]) / 2.0),
+                    _ => Some(valid[mid]),
+                };
+                (key, Group { listings, median_rent })
+            })
+            .collect()
+    }
+}
+
+/// The partitioned parquet files of each configured source.
+pub struct ParquetStore {
+    config: Arc<Config>,
+    cache: Arc<SnapshotCache>,
+    id_index: Arc<IdIndex>,
+}
+
+impl ParquetStore {
+    pub fn new(config: Arc<Config>, cache: Arc<SnapshotCache>, id_index: Arc<IdIndex>) -> Self {
+        ParquetStore { config, cache, id_index }
+    }
+
+    fn cached(&self, source: &SourceConfig, file: &Path) -> Vec<StandardizedProperty> {
+        let mut properties = Vec::new();
+        self.cache.scan(source, file, 0, |_, property| {
+            properties.push(property);
+            ControlFlow::Continue(())
+        });
+        properties
+    }
+}
+
+impl PropertyStore for ParquetStore {
+    fn snapshots(&self, source: &str) -> Vec<NaiveDate> {
+        let Some(source) = self.config.resolve_source(source) else {
+            return vec![];
+        };
+        list_snapshots(&source.root(&self.config.data_path)).into_iter().map(|(date, _)| date).collect()
+    }
+
+    fn listings(&self, source: &str, date: NaiveDate) -> Vec<StandardizedProperty> {
+        let Some(source) = self.config.resolve_source(source) else {
+            return vec![];
+        };
+        let root = source.root(&self.config.data_path);
+        match list_snapshots(&root).into_iter().find(|(d, _)| *d == date) {
+            // Only the latest snapshot is worth its place in the cache
+            Some((_, file)) if find_latest_parquet(&root).as_ref() != Some(&file) => load_properties(source, &file),
+            Some((_, file)) => self.cached(source, &file),
+            None => vec![],
+        }
+    }
+
+    fn latest(&self, source: &str) -> Vec<StandardizedProperty> {
+        let Some(source) = self.config.resolve_source(source) else {
+            return vec![];
+        };
+        match fallback::serving(&self.config, &self.cache, source) {
+            Some(serving) => self.cached(source, &serving.file),
+            None => vec![],
+        }
+    }
+
+    fn get(&self, ids: &[String]) -> Vec<StandardizedProperty> {
+        self.id_index.lookup(&self.config, ids)
+    }
+
+    /// Writes a standardized-schema file into the day's partition. Only for
+    /// sources read with the standardized parser; the others' files follow
+    /// their collector's schema.
+    fn ingest(&self, source: &str, date: NaiveDate, listings: &[StandardizedProperty]) -> Result<(), String> {
+        let source = self.config.resolve_source(source).ok_or_else(|| format!("Unknown source {:?}", source))?;
+        if source.parser != ParserKind::Standardized {
+            return Err(format!("{} snapshots come from its collector and can't be ingested", source.name));
+        }
+        let dir = source.root(&self.config.data_path).join(date.format("%Y/%m/%d").to_string());
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+        // The newest file of a day is its snapshot
+        let path = dir.join(format!("ingest_{}.parquet", self.config.clock.now().format("%Y%m%dT%H%M%S%.3f")));
+        let batch = standardized_batch(listings)?;
+        let file = File::create(&path).map_err(|e| format!("Failed to create {:?}: {}", path, e))?;
+        let mut writer = ArrowWriter::try_new(file, batch.schema(), None).map_err(|e| e.to_string())?;
+        writer.write(&batch).map_err(|e| e.to_string())?;
+        writer.close().map_err(|e| e.to_string())?;
+        self.id_index.prepare(source, &path);
+        Ok(())
+    }
+}
+
+/// `listings` in the columns `parse_standardized_row` reads.
+fn standardized_batch(listings: &[StandardizedProperty]) -> Result<RecordBatch, String> {
+    let text = |value: fn(&StandardizedProperty) -> Option<String>| {
+        Arc::new(StringArray::from(listings.iter().map(value).collect::<Vec<_>>())) as ArrayRef
+    };
+    let integer = |value: fn(&StandardizedProperty) -> Option<i32>| {
+        Arc::new(Int64Array::from(listings.iter().map(|p| value(p).map(i64::from)).collect::<Vec<_>>())) as ArrayRef
+    };
+    let columns = vec![
+        ("source_id", text(|p| Some(p.source_id.clone()))),
+        // Parsing the file gives the listing its canonical id again
+        ("property_id", text(|p| Some(p.indexed_id().to_string()))),
+        ("display_address", text(|p| Some(p.address.display_address.clone()))),
+        ("property_type", text(|p| Some(p.property_type.clone()))),
+        ("bedrooms", integer(|p| p.bedrooms)),
+        ("bathrooms", integer(|p| p.bathrooms)),
+        ("size", Arc::new(Float64Array::from(listings.iter().map(|p| p.size.as_ref().map(|s| s.value)).collect::<Vec<_>>())) as ArrayRef),
+        ("ber_rating", text(|p| p.ber_rating.clone())),
+        ("price", Arc::new(Float64Array::from(listings.iter().map(|p| p.price.amount).collect::<Vec<_>>())) as ArrayRef),
+        ("currency", text(|p| Some(p.price.currency.clone()))),
+        ("frequency", text(|p| p.price.frequency.clone())),
+        ("created_date", text(|p| Some(p.created_date.clone()))),
+        ("updated_date", text(|p| Some(p.updated_date.clone()))),
+        ("listing_type", text(|p| Some(p.listing_type.clone()))),
+        ("status", text(|p| Some(p.status.clone()))),
+        ("has_video", Arc::new(BooleanArray::from(listings.iter().map(|p| p.has_video).collect::<Vec<_>>())) as ArrayRef),
+        ("seo_url", text(|p| p.seo_url.clone())),
+    ];
+    RecordBatch::try_from_iter(columns).map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IngestRequest {
+    date: NaiveDate,
+    listings: Vec<StandardizedProperty>,
+}
+
+/// Stores the listings in the body as a snapshot of the source. Admin only.
+pub async fn ingest(
+    State(state): State<AppState>,
+    caller: Caller,
+    UrlPath(source): UrlPath<String>,
+    Json(request): Json<IngestRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    info!("Ingesting {} {} listings for {}", request.listings.len(), source, request.date);
+    let parameters = json!({ "source": source, "date": request.date, "listings": request.listings.len() });
+    let (store, date) = (state.store.clone(), request.date);
+    let outcome = tokio::task::spawn_blocking(move || store.ingest(&source, date, &request.listings))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    state.audit.record(&caller, "snapshots.ingest", parameters, &outcome);
+    outcome.map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    Ok(StatusCode::CREATED)
+}
+
+/// Snapshots held in memory, for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryStore {
+    sources: RwLock<BTreeMap<String, BTreeMap<NaiveDate, Vec<StandardizedProperty>>>>,
+}
+
+#[cfg(test)]
+impl PropertyStore for MemoryStore {
+    fn snapshots(&self, source: &str) -> Vec<NaiveDate> {
+        self.sources.read().unwrap().get(source).map(|s| s.keys().copied().collect()).unwrap_or_default()
+    }
+
+    fn listings(&self, source: &str, date: NaiveDate) -> Vec<StandardizedProperty> {
+        self.sources.read().unwrap().get(source).and_then(|s| s.get(&date)).cloned().unwrap_or_default()
+    }
+
+    fn get(&self, ids: &[String]) -> Vec<StandardizedProperty> {
+        let wanted: HashSet<&str> = ids.iter().map(String::as_str).collect();
+        let sources = self.sources.read().unwrap();
+        let mut found: Vec<StandardizedProperty> = sources
+            .values()
+            .filter_map(|snapshots| snapshots.values().next_back())
+            .flatten()
+            .filter(|p| p.ids().any(|id| wanted.contains(id)))
+            .cloned()
+            .collect();
+        let position: HashMap<&str, usize> = ids.iter().enumerate().map(|(i, id)| (id.as_str(), i)).collect();
+        found.sort_by_key(|p| p.ids().find_map(|id| position.get(id).copied()));
+        found
+    }
+
+    fn ingest(&self, source: &str, date: NaiveDate, listings: &[StandardizedProperty]) -> Result<(), String> {
+        self.sources.write().unwrap().entry(source.to_string()).or_default().insert(date, listings.to_vec());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{listing, temp_dir};
+
+    fn rental(id: &str, area: &str, rent: f64) -> StandardizedProperty {
+        let mut property = listing("daft", id);
+        property.address.display_address = format!("{} Main Street, {}", id, area);
+        property.address.normalized_address = crate::address::normalize(&property.address.display_address);
+        property.price.amount = rent;
+        property
+    }
+
+    fn date(text: &str) -> NaiveDate {
+        text.parse().unwrap()
+    }
+
+    fn area(property: &StandardizedProperty) -> Option<String> {
+        Some(crate::address::area(&property.address.normalized_address).to_string())
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let store = MemoryStore::default();
+        store.ingest("daft", date("2024-11-04"), &[rental("1", "Dublin 6", 2000.0)]).unwrap();
+        store
+            .ingest(
+                "daft",
+                date("2024-11-05"),
+                &[rental("1", "Dublin 6", 1900.0), rental("2", "Dublin 6", 2100.0), rental("3", "Dublin 8", 0.0)],
+            )
+            .unwrap();
+
+        assert_eq!(store.snapshots("daft"), [date("2024-11-04"), date("2024-11-05")]);
+        assert!(store.snapshots("myhome").is_empty());
+        let found = store.get(&["daft_3".to_string(), "daft_1".to_string(), "daft_9".to_string()]);
+        assert_eq!(found.iter().map(|p| p.property_id.as_str()).collect::<Vec<_>>(), ["daft_3", "daft_1"]);
+        assert_eq!(found[1].price.amount, 1900.0);
+        assert_eq!(store.query(&["daft", "myhome"], &|p| p.price.amount > 2000.0).len(), 1);
+        let across = store.across("daft", date("2024-11-01")..=date("2024-11-30"));
+        assert_eq!((across.len(), across[0].price.amount), (3, 1900.0));
+        assert_eq!(store.across("daft", date("2024-11-01")..=date("2024-11-04")).len(), 1);
+
+        let groups = store.aggregate("daft", date("2024-11-05"), &area);
+        assert_eq!(groups["dublin 6"], Group { listings: 2, median_rent: Some(2000.0) });
+        assert_eq!(groups["dublin 8"], Group { listings: 1, median_rent: None });
+    }
+
+    #[test]
+    fn test_parquet_store_round_trip() {
+        let mut config = Config { data_path: temp_dir("store"), ..Default::default() };
+        config.sources.push(SourceConfig {
+            name: "feed".to_string(),
+            aliases: vec![],
+            parser: ParserKind::Standardized,
+            path: None,
+            mapping: None,
+            url_template: None,
+            columns: None,
+            parsing: Arc::default(),
+            disabled: false,
+        });
+        let config = Arc::new(config);
+        let store = ParquetStore::new(config.clone(), Arc::new(SnapshotCache::new(0)), Arc::default());
+
+        let mut listings = vec![rental("1", "Dublin 6", 2000.0), rental("2", "Dublin 8", 1500.0)];
+        for property in &mut listings {
+            property.source = "feed".to_string();
+            property.property_id = format!("feed_{}", property.source_id);
+            property.bedrooms = Some(2);
+        }
+        store.ingest("feed", date("2024-11-05"), &listings).unwrap();
+        assert!(store.ingest("daft", date("2024-11-05"), &listings).is_err());
+
+        assert_eq!(store.snapshots("feed"), [date("2024-11-05")]);
+        let read = store.latest("feed");
+        assert_eq!(read.len(), 2);
+        assert_eq!((read[0].property_id.as_str(), read[0].price.amount, read[0].bedrooms), ("feed_1", 2000.0, Some(2)));
+        // Parsed like any other snapshot
+        assert_eq!(read[1].address.normalized_address, "2 main street, dublin 8");
+        assert_eq!(store.get(&["feed_2".to_string()]).len(), 1);
+        assert_eq!(store.listings("feed", date("2024-11-05")).len(), 2);
+    }
+}
@@ -0,0 +1,226 @@
+//! Move-in dates and lease terms.
+//!
+//! Someone with a fixed move-in date can't use a listing that frees up a month
+//! later, and a student can't sign a year's lease for one term. Sources give
+//! both in free text if at all ("Available from 1st February", "minimum 6
+//! month lease"), so each listing gets a `tenancy` object read from the
+//! optional `available_from` and `lease` columns and, failing those, the
+//! feature list and description (see `amenities::listing_text`).
+//!
+//! Dates without a year are taken to be the next such date after the listing
+//! was created, unless that is more than `PAST_DAYS` in the past: "available 1st
+//! February" on a listing from 5th February means it is available now.
+//!
+//! Searches filter with `available_from_before=2025-02-01` (listings known to
+//! be available by then) and `min_lease_months=6` (listings whose minimum
+//! lease is at most six months, or not stated).
+
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::amenities;
+use crate::mapping::BatchRow;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Tenancy {
+    pub available_from: Option<NaiveDate>,
+    pub min_lease_months: Option<u32>,
+}
+
+const MONTHS: [&str; 12] = [
+    "january", "february", "march", "april", "may", "june", "july", "august", "september", "october", "november",
+    "december",
+];
+const NOW: [&str; 3] = ["now", "immediately", "immediate"];
+/// Words that make a duration in the same clause a lease term.
+const LEASE_WORDS: [&str; 6] = ["lease", "tenancy", "term", "let", "minimum", "min"];
+const COUNTS: [(&str, u32); 6] = [("one", 1), ("two", 2), ("three", 3), ("six", 6), ("nine", 9), ("twelve", 12)];
+/// How far before the listing's creation a yearless date can fall and still
+/// be this year's.
+const PAST_DAYS: i64 = 60;
+
+fn month(word: &str) -> Option<u32> {
+    let word = word.trim_end_matches('.');
+    if word.len() < 3 {
+        return None;
+    }
+    MONTHS.iter().position(|name| name.starts_with(word)).map(|i| i as u32 + 1)
+}
+
+/// "1st", "21", "3rd"
+fn day(word: &str) -> Option<u32> {
+    let digits = word.trim_end_matches(|c: char| c.is_alphabetic());
+    let suffix = &word[digits.len()..];
+    if !["", "st", "nd", "rd", "th"].contains(&suffix) {
+        return None;
+    }
+    digits.parse().ok().filter(|d| (1..=31).contains(d))
+}
+
+fn year(word: &str) -> Option<i32> {
+    word.parse().ok().filter(|y| (2000..2100).contains(y))
+}
+
+/// "2025-02-01", "01/02/2025", "1/2/25" or "01.02.2025", days first.
+fn numeric_date(word: &str) -> Option<NaiveDate> {
+    ["%Y-%m-%d", "%d/%m/%Y", "%d/%m/%y", "%d.%m.%Y", "%d-%m-%Y"]
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(word, format).ok())
+}
+
+/// The date a yearless day and month most likely means, given when the
+/// listing was created.
+fn with_year(day: u32, month: u32, year: Option<i32>, reference: NaiveDate) -> Option<NaiveDate> {
+    if let Some(year) = year {
+        return NaiveDate::from_ymd_opt(year, month, day);
+    }
+    let date = NaiveDate::from_ymd_opt(reference.year(), month, day)?;
+    match (reference - date).num_days() > PAST_DAYS {
+        true => NaiveDate::from_ymd_opt(reference.year() + 1, month, day),
+        false => Some(date),
+    }
+}
+
+/// Reads a date from the words right after "available".
+fn date_from(words: &[&str], reference: NaiveDate) -> Option<NaiveDate> {
+    let words: Vec<&str> =
+        words.iter().copied().skip_while(|w| ["from", "on", "to", "let", "date"].contains(w)).take(4).collect();
+    let first = *words.first()?;
+    let at = |i: usize| words.get(i).copied().unwrap_or("");
+    if NOW.contains(&first) {
+        return Some(reference);
+    }
+    if let Some(date) = numeric_date(first) {
+        return Some(date);
+    }
+    let (of, next) = if at(1) == "of" { (2, 3) } else { (1, 2) };
+    match (day(first), month(first)) {
+        // "1st February 2025", "1st of February"
+        (Some(d), _) => with_year(d, month(at(of))?, year(at(next)), reference),
+        // "February 1st, 2025" or "February 2025"
+        (None, Some(m)) => match day(at(1)) {
+            Some(d) => with_year(d, m, year(at(2)), reference),
+            None => with_year(1, m, year(at(1)), reference),
+        },
+        _ => None,
+    }
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| c.is_whitespace() || matches!(c, ',' | ':' | '(' | ')'))
+        .map(|w| w.trim_end_matches('.'))
+        .filter(|w| !w.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The first "available ..." date in `text`.
+pub fn available_from(text: &str, reference: NaiveDate) -> Option<NaiveDate> {
+    let words = words(text);
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    (0..words.len())
+        .filter(|&i| words[i].starts_with("available"))
+        .find_map(|i| date_from(&words[i + 1..], reference))
+}
+
+fn count(word: &str) -> Option<u32> {
+    word.parse().ok().filter(|n| (1..=60).contains(n)).or_else(|| COUNTS.iter().find(|(w, _)| *w == word).map(|(_, n)| *n))
+}
+
+/// Months in "6 months", "12-month", "1 year" or "two years".
+fn duration(words: &[&str]) -> Option<u32> {
+    words.iter().enumerate().find_map(|(i, word)| {
+        // "12-month" is one word
+        let (number, unit) = match word.split_once('-') {
+            Some((number, unit)) if count(number).is_some() => (number, unit),
+            _ => (*word, *words.get(i + 1)?),
+        };
+        let n = count(number)?;
+        match unit.trim_end_matches('s') {
+            "month" | "mth" | "mo" => Some(n),
+            "year" | "yr" => Some(n * 12),
+            _ => None,
+        }
+    })
+}
+
+/// The shortest lease a clause of `text` asks for.
+pub fn min_lease_months(text: &str) -> Option<u32> {
+    text.split(['.', ';', '\n', '•', '|'])
+        .map(words)
+        .filter(|words| words.iter().any(|w| LEASE_WORDS.iter().any(|l| w.trim_end_matches('s') == *l)))
+        .find_map(|words| duration(&words.iter().map(String::as_str).collect::<Vec<_>>()))
+}
+
+/// The day the listing was created, falling back to `today`.
+fn reference_date(created_date: &str, today: NaiveDate) -> NaiveDate {
+    created_date.get(..10).and_then(|d| d.parse().ok()).unwrap_or(today)
+}
+
+pub fn extract(row: &BatchRow, created_date: &str, today: NaiveDate) -> Tenancy {
+    let reference = reference_date(created_date, today);
+    let text = amenities::listing_text(row);
+    let available = row.string("available_from").map(|s| format!("available {}", s));
+    let lease = row.string("lease").map(|s| format!("lease {}", s));
+    Tenancy {
+        available_from: available
+            .and_then(|text| available_from(&text, reference))
+            .or_else(|| available_from(&text, reference)),
+        // A bare number is in months
+        min_lease_months: row
+            .long("lease")
+            .and_then(|months| u32::try_from(months).ok())
+            .or_else(|| lease.and_then(|text| min_lease_months(&text)))
+            .or_else(|| min_lease_months(&text)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(text: &str) -> NaiveDate {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_available_from() {
+        let reference = date("2024-11-05");
+        let cases = [
+            ("Available from 1st February 2025", Some("2025-02-01")),
+            ("Available 1st of Feb", Some("2025-02-01")),
+            ("available: February 14th, 2025", Some("2025-02-14")),
+            ("Available from December", Some("2024-12-01")),
+            ("Available from 01/12/2024. Minimum lease 12 months", Some("2024-12-01")),
+            ("Available 2025-01-15", Some("2025-01-15")),
+            ("Available immediately", Some("2024-11-05")),
+            // Earlier this year: available now, not next October
+            ("Available from 20th October", Some("2024-10-20")),
+            // A year ago is too far back to be this year's
+            ("Available 1st of August", Some("2025-08-01")),
+            ("Parking available", None),
+            ("", None),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(available_from(text, reference), expected.map(date), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_min_lease_months() {
+        let cases = [
+            ("Minimum 12 month lease", Some(12)),
+            ("Lease: 6 months", Some(6)),
+            ("1 year lease required", Some(12)),
+            ("Short term let (3 months). Pets allowed", Some(3)),
+            ("12-month tenancy", Some(12)),
+            ("Available for six months minimum", Some(6)),
+            ("Built 2 years ago. Close to shops", None),
+            ("", None),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(min_lease_months(text), expected, "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Fixtures shared by the unit tests.
+
+use arrow::array::{ArrayRef, StringArray};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::ber::BerStatus;
+use crate::config::Config;
+use crate::{Address, Price, StandardizedProperty};
+
+/// A fresh, empty directory under the system temp dir.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("market-analysis-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// `config` with every file it persists to (analytics, jobs, notes, audit and
+/// the rest) moved under `dir`, so an `AppState` built from it leaves the
+/// working directory alone.
+pub fn stores_in(mut config: Config, dir: &Path) -> Config {
+    let root = |path: &mut PathBuf| *path = dir.join(&*path);
+    for path in [
+        &mut config.links.clicks_path,
+        &mut config.analytics.path,
+        &mut config.history.path,
+        &mut config.privacy.suppressions_path,
+        &mut config.agent_register.path,
+        &mut config.notes.path,
+        &mut config.notes.hidden_path,
+        &mut config.notes.viewings_path,
+        &mut config.notes.interactions_path,
+        &mut config.audit.path,
+        &mut config.source_toggles.path,
+        &mut config.quality.path,
+        &mut config.ids.path,
+        &mut config.corrections.path,
+        &mut config.jobs.path,
+        &mut config.notifier.preferences_path,
+        &mut config.reports.schedules_path,
+        &mut config.reports.watches_path,
+        &mut config.stats_sink.state_path,
+        &mut config.deltas.feed_path,
+        &mut config.abuse.blocklist_path,
+        &mut config.logging.file,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        root(path);
+    }
+    root(&mut config.photos.path);
+    root(&mut config.exports.path);
+    config
+}
+
+/// Writes `batch` as a parquet file at `path`, creating parent directories.
+pub fn write_parquet(path: &Path, batch: &RecordBatch) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let mut writer = ArrowWriter::try_new(File::create(path).unwrap(), batch.schema(), None).unwrap();
+    writer.write(batch).unwrap();
+    writer.close().unwrap();
+}
+
+/// A standardized-schema batch with one €1,000+ listing per source id.
+pub fn standardized_batch(ids: &[&str]) -> RecordBatch {
+    let prices: Vec<String> = (0..ids.len()).map(|i| format!("€{}", 1000 + 100 * i)).collect();
+    let addresses: Vec<String> = ids.iter().map(|id| format!("{} Main Street, Dublin 8", id)).collect();
+    RecordBatch::try_from_iter(vec![
+        ("source_id", Arc::new(StringArray::from(ids.to_vec())) as ArrayRef),
+        ("price", Arc::new(StringArray::from(prices)) as ArrayRef),
+        ("display_address", Arc::new(StringArray::from(addresses)) as ArrayRef),
+    ])
+    .unwrap()
+}
+
+/// A minimal active rental listing; tests fill in the fields they care about.
+pub fn listing(source: &str, source_id: &str) -> StandardizedProperty {
+    StandardizedProperty {
+        property_id: format!("{}_{}", source, source_id),
+        legacy_id: None,
+        source: source.to_string(),
+        source_id: source_id.to_string(),
+        address: Address {
+            display_address: String::new(),
+            normalized_address: String::new(),
+        },
+        property_type: String::new(),
+        bedrooms: None,
+        bathrooms: None,
+        size: None,
+        ber_rating: None,
+        ber_status: BerStatus::Unknown,
+        size_confidence: None,
+        price_band: String::new(),
+        price_flag: None,
+        property_category: String::new(),
+        price: Price {
+            amount: 1500.0,
+            currency: "EUR".to_string(),
+            frequency: Some("month".to_string()),
+            price_changes: vec![],
+            min: None,
+            max: None,
+        },
+        created_date: "2024-11-05T12:00:00+00:00".to_string(),
+        updated_date: "2024-11-05T12:00:00+00:00".to_string(),
+        listing_type: "rent".to_string(),
+        status: "active".to_string(),
+        photos: vec![],
+        photo_count: 0,
+        has_floorplan: false,
+        has_video: false,
+        agent: None,
+        agent_status: None,
+        seo_url: None,
+        url: None,
+        short_id: String::new(),
+        annotations: None,
+        raw: None,
+        display: None,
+        development: None,
+        commercial: None,
+        amenities: Default::default(),
+        tenancy: Default::default(),
+        deposit: None,
+        coordinates: None,
+        value_score: None,
+        semantic_score: None,
+    }
+}
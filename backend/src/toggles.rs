@@ -0,0 +1,143 @@
+//! Turning sources off at runtime.
+//!
+//! When a collector starts writing a broken schema, its rows shouldn't leak
+//! into everything that combines sources while a fix is prepared.
+//! `POST /api/admin/sources/daft/disable` takes daft out of every search and
+//! stat not asked for it by name, which also get a `disabled` search warning;
+//! `POST /api/admin/sources/daft/enable` puts it back. Asking for
+//! `source=daft` still reads it, so the broken rows can be looked at. The
+//! disabled sources are kept at `source_toggles.path` and stay disabled after
+//! a restart or a config reload.
+
+use axum::extract::{Path as UrlPath, State};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use crate::auth::{Caller, SCOPE_ADMIN};
+use crate::config::Config;
+use crate::reload::Live;
+use crate::state::AppState;
+
+pub struct SourceToggles {
+    path: Option<PathBuf>,
+    disabled: RwLock<BTreeSet<String>>,
+}
+
+impl SourceToggles {
+    /// Loads the disabled sources from `path`. `None` keeps them in memory
+    /// only.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let disabled = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        SourceToggles { path, disabled: RwLock::new(disabled) }
+    }
+
+    /// Disables or re-enables `source`. Returns whether that changed anything.
+    pub fn set(&self, source: &str, disabled: bool) -> Result<bool, String> {
+        let mut sources = self.disabled.write().unwrap();
+        let changed = if disabled { sources.insert(source.to_string()) } else { sources.remove(source) };
+        if let (true, Some(path)) = (changed, &self.path) {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+            let contents = serde_json::to_string_pretty(&*sources).map_err(|e| e.to_string())?;
+            fs::write(path, contents).map_err(|e| e.to_string())?;
+        }
+        Ok(changed)
+    }
+
+    /// Marks the disabled sources in `config`.
+    pub fn apply(&self, config: &mut Config) {
+        let disabled = self.disabled.read().unwrap();
+        for source in &mut config.sources {
+            source.disabled = disabled.contains(&source.name);
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SourceState {
+    pub name: String,
+    pub enabled: bool,
+}
+
+fn states(config: &Config) -> Vec<SourceState> {
+    config.sources.iter().map(|s| SourceState { name: s.name.clone(), enabled: !s.disabled }).collect()
+}
+
+pub async fn list(
+    State(state): State<AppState>,
+    caller: Caller,
+) -> Result<Json<Vec<SourceState>>, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    Ok(Json(states(&state.config)))
+}
+
+fn toggle(
+    live: &Live,
+    caller: &Caller,
+    source: &str,
+    disabled: bool,
+) -> Result<Json<Vec<SourceState>>, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    let state = live.current();
+    let name = state
+        .config
+        .resolve_source(source)
+        .map(|source| source.name.clone())
+        .ok_or((StatusCode::NOT_FOUND, format!("Unknown source {:?}", source)))?;
+    let outcome = state.source_toggles.set(&name, disabled);
+    let action = if disabled { "sources.disable" } else { "sources.enable" };
+    state.audit.record(caller, action, json!({ "source": name }), &outcome);
+    outcome.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    live.refresh();
+    Ok(Json(states(&live.current().config)))
+}
+
+pub async fn disable(
+    State(live): State<Live>,
+    caller: Caller,
+    UrlPath(source): UrlPath<String>,
+) -> Result<Json<Vec<SourceState>>, (StatusCode, String)> {
+    toggle(&live, &caller, &source, true)
+}
+
+pub async fn enable(
+    State(live): State<Live>,
+    caller: Caller,
+    UrlPath(source): UrlPath<String>,
+) -> Result<Json<Vec<SourceState>>, (StatusCode, String)> {
+    toggle(&live, &caller, &source, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_dir;
+
+    #[test]
+    fn test_disabled_sources_persist_and_leave_combined_queries() {
+        let path = temp_dir("toggles").join("disabled.json");
+        let toggles = SourceToggles::open(Some(path.clone()));
+        assert!(toggles.set("daft", true).unwrap());
+        assert!(!toggles.set("daft", true).unwrap());
+
+        let mut config = Config::default();
+        SourceToggles::open(Some(path.clone())).apply(&mut config);
+        assert!(!config.source_names(None).contains(&"daft"));
+        assert_eq!(config.source_names(Some("daft")), ["daft"]);
+
+        assert!(toggles.set("daft", false).unwrap());
+        SourceToggles::open(Some(path)).apply(&mut config);
+        assert!(config.source_names(None).contains(&"daft"));
+    }
+}
@@ -117,7 +117,9 @@ class DaftAsyncScraper:
                 if data:  # Only save if we have data
                     # Store raw and processed data
                     raw_path = await self.data_lake.store_raw(data, source="daft")
-                    processed_path = await self.data_lake.process_and_store(raw_path, source="daft")
+                    processed_path = await self.data_lake.process_and_store(
+                        raw_path, source="daft", reported_total=total_results
+                    )
 
                     logger.info(f"Raw data stored at: {raw_path}")
                     logger.info(f"Processed data stored at: {processed_path}")
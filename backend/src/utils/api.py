@@ -10,11 +10,14 @@ class AsyncApi:
         self.payload_api_url = kwargs.get('payload_api_url')
         self.api_key = kwargs.get('api_key')
         self.correlation_id = kwargs.get('correlation_id')
+        # Listings the API says the search has, from the last get_data
+        self.reported_total: Optional[int] = None
 
     async def get_data(self, page_size: int = 20) -> List[Dict[str, Any]]:
         """Get data asynchronously"""
         all_results = []
-        
+        self.reported_total = None
+
         tasks = []
         for i in range(page_size):
             payload = {
@@ -40,6 +43,8 @@ class AsyncApi:
                 continue
             if result:
                 all_results.extend(result.get("SearchResults", []))
+                if isinstance(result.get("ResultCount"), int):
+                    self.reported_total = max(self.reported_total or 0, result["ResultCount"])
                 
         return all_results
 
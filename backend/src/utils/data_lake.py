@@ -49,8 +49,15 @@ class AsyncDataLakeManager:
         logger.info(f"Stored raw data at: {filepath}")
         return filepath
 
-    async def process_and_store(self, raw_filepath: Path, source: str) -> Optional[Path]:
-        """Process and store data asynchronously"""
+    async def process_and_store(
+        self, raw_filepath: Path, source: str, reported_total: Optional[int] = None
+    ) -> Optional[Path]:
+        """Process and store data asynchronously.
+
+        reported_total is the number of listings the portal said its search
+        had; it is kept next to the snapshot so the API can tell how much of
+        the portal the snapshot captured.
+        """
         logger.debug(f"Processing data from: {raw_filepath}")
         try:
             async with aiofiles.open(raw_filepath) as f:
@@ -82,12 +89,29 @@ class AsyncDataLakeManager:
                     )
 
             logger.info(f"Stored processed data at: {proc_filepath}")
+            if reported_total is not None:
+                await self.store_crawl(proc_filepath, reported_total, len(raw_data), timestamp)
             return proc_filepath
 
         except Exception as e:
             logger.error(f"Error processing data for {source}: {str(e)}", exc_info=True)
             return None
 
+    async def store_crawl(
+        self, proc_filepath: Path, reported_total: int, scraped: int, timestamp: datetime
+    ) -> Path:
+        """Write the crawl's counts next to its snapshot, as <snapshot>.crawl.json"""
+        filepath = proc_filepath.with_suffix(".crawl.json")
+        crawl = {
+            "reported_total": reported_total,
+            "scraped": scraped,
+            "scraped_at": timestamp.isoformat(),
+        }
+        async with aiofiles.open(filepath, 'w') as f:
+            await f.write(json.dumps(crawl, indent=2))
+        logger.info(f"Stored crawl counts at: {filepath}")
+        return filepath
+
 class AsyncHousingCollector:
     def __init__(self, data_lake: AsyncDataLakeManager):
         self.data_lake = data_lake
@@ -167,7 +191,8 @@ class AsyncHousingCollector:
 
             logger.debug(f"Retrieved {len(data)} records from {source}")
             raw_path = await self.data_lake.store_raw(data, source)
-            return await self.data_lake.process_and_store(raw_path, source)
+            reported_total = getattr(scraper, "reported_total", None)
+            return await self.data_lake.process_and_store(raw_path, source, reported_total)
 
         except Exception as e:
             logger.error(f"Error collecting from {source}: {str(e)}", exc_info=True)
@@ -0,0 +1,196 @@
+//! `validate-data`: checks every snapshot in the data lake.
+//!
+//! Walks each configured source's `YYYY/MM/DD` partitions, opens every parquet
+//! file, resolves the column mapping in effect for its date and parses every
+//! row. Prints one line per file with its row counts or the reasons it failed,
+//! and exits non-zero if any file is unreadable, is missing mapped columns or
+//! has rows of which none parse. Meant to run after every pipeline drop.
+
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, SourceConfig};
+use crate::mapping::{decode_dictionaries, ResolvedColumns};
+use crate::{numeric_subdirs, parse_source_row, snapshot_date};
+
+#[derive(Debug)]
+pub struct FileReport {
+    /// Mapping version used; 0 for sources read by column name.
+    pub mapping_version: u32,
+    pub rows: usize,
+    pub parsed: usize,
+    /// Parsed rows repeating a `source_id` seen earlier in the file.
+    pub duplicates: usize,
+    /// Columns of the file's schema.
+    pub columns: Vec<String>,
+    /// Mapped columns the file doesn't have.
+    pub missing_columns: Vec<String>,
+    /// Why the file failed validation. Empty when it passed.
+    pub failures: Vec<String>,
+}
+
+impl FileReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Every parquet file under a source's partitions, oldest partition first.
+fn partition_files(source_path: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for (_, year_path) in numeric_subdirs(source_path) {
+        for (_, month_path) in numeric_subdirs(&year_path) {
+            for (_, day_path) in numeric_subdirs(&month_path) {
+                let mut day_files: Vec<PathBuf> = fs::read_dir(&day_path)
+                    .ok()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|entry| entry.ok().map(|e| e.path()))
+                    .filter(|path| path.extension().is_some_and(|ext| ext == "parquet"))
+                    .collect();
+                day_files.sort();
+                files.extend(day_files);
+            }
+        }
+    }
+    files
+}
+
+pub fn validate_file(source: &SourceConfig, path: &Path) -> FileReport {
+    let mut report = FileReport {
+        mapping_version: 0,
+        rows: 0,
+        parsed: 0,
+        duplicates: 0,
+        columns: vec![],
+        missing_columns: vec![],
+        failures: vec![],
+    };
+    let builder = match File::open(path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| ParquetRecordBatchReaderBuilder::try_new(file).map_err(|e| e.to_string()))
+    {
+        Ok(builder) => builder,
+        Err(e) => {
+            report.failures.push(format!("unreadable: {}", e));
+            return report;
+        }
+    };
+
+    let version = source.columns.as_ref().map(|mapping| mapping.for_date(snapshot_date(path)));
+    let columns = match version {
+        Some(version) => version.resolve(builder.schema()),
+        None => ResolvedColumns::by_name(builder.schema()),
+    };
+    report.mapping_version = columns.version;
+    report.columns = builder.schema().fields().iter().map(|field| field.name().clone()).collect();
+    report.missing_columns = columns.missing.clone();
+    if let Some(version) = version.filter(|_| !columns.missing.is_empty()) {
+        report.failures.push(format!(
+            "mapping v{} columns not in schema: {}",
+            columns.version,
+            version.describe(&columns.missing)
+        ));
+    }
+
+    let reader = match builder.build() {
+        Ok(reader) => reader,
+        Err(e) => {
+            report.failures.push(format!("unreadable: {}", e));
+            return report;
+        }
+    };
+    let mut seen = HashSet::new();
+    for batch in reader {
+        let batch = match batch {
+            Ok(batch) => decode_dictionaries(batch),
+            Err(e) => {
+                report.failures.push(format!("corrupt record batch after row {}: {}", report.rows, e));
+                return report;
+            }
+        };
+        for index in 0..batch.num_rows() {
+            report.rows += 1;
+            if let Some(property) = parse_source_row(source, &columns, &columns.row(&batch, index)) {
+                report.parsed += 1;
+                if !seen.insert(property.source_id) {
+                    report.duplicates += 1;
+                }
+            }
+        }
+    }
+    if report.rows > 0 && report.parsed == 0 {
+        report.failures.push(format!("none of {} rows parse", report.rows));
+    }
+    report
+}
+
+/// Validates every file of every configured source, printing a line per file
+/// and a summary. Returns whether everything passed.
+pub fn run(config: &Config) -> bool {
+    let (mut checked, mut failed) = (0, 0);
+    for source in &config.sources {
+        let root = source.root(&config.data_path);
+        if !root.is_dir() {
+            println!("SKIP  {:<12} no data at {}", source.name, root.display());
+            continue;
+        }
+        for path in partition_files(&root) {
+            let report = validate_file(source, &path);
+            let relative = path.strip_prefix(&root).unwrap_or(&path).display();
+            checked += 1;
+            if report.passed() {
+                println!(
+                    "OK    {:<12} {}  rows={} parsed={} duplicates={} mapping=v{}",
+                    source.name, relative, report.rows, report.parsed, report.duplicates, report.mapping_version
+                );
+            } else {
+                failed += 1;
+                println!("FAIL  {:<12} {}  {}", source.name, relative, report.failures.join("; "));
+            }
+        }
+    }
+    println!("{} files checked, {} failed", checked, failed);
+    failed == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{standardized_batch, temp_dir, write_parquet};
+
+    #[test]
+    fn test_validate_file() {
+        let config = Config::from_toml("[[sources]]\nname = \"rent_ie\"").unwrap();
+        let source = config.resolve_source("rent_ie").unwrap();
+        let day = temp_dir("validate").join("rent_ie/2024/03/01");
+
+        let good = day.join("rent_ie_090000.parquet");
+        write_parquet(&good, &standardized_batch(&["1", "2"]));
+        let report = validate_file(source, &good);
+        assert!(report.passed(), "{:?}", report.failures);
+        assert_eq!((report.rows, report.parsed), (2, 2));
+        assert!(report.columns.contains(&"source_id".to_string()));
+
+        let corrupt = day.join("rent_ie_100000.parquet");
+        fs::write(&corrupt, b"PAR1 not really parquet").unwrap();
+        let report = validate_file(source, &corrupt);
+        assert!(!report.passed());
+        assert!(report.failures[0].starts_with("unreadable"));
+
+        assert_eq!(partition_files(&day.join("../../..")).len(), 2);
+    }
+
+    #[test]
+    fn test_missing_mapped_columns_fail() {
+        let config = Config::default();
+        let daft = config.resolve_source("daft").unwrap();
+        let path = temp_dir("validate-daft").join("daft/2024/03/01/daft_090000.parquet");
+        write_parquet(&path, &standardized_batch(&["1"]));
+        let report = validate_file(daft, &path);
+        assert!(report.failures.iter().any(|f| f.contains("columns not in schema")));
+        assert!(report.failures.iter().any(|f| f.contains("price (listing.abbreviatedPrice)")));
+    }
+}
@@ -0,0 +1,304 @@
+//! Planned viewings and their iCal feed.
+//!
+//! Callers with an API key schedule a viewing of a listing with
+//! `POST /api/rentals/{id}/viewings`; `GET /api/me/viewings` lists theirs and
+//! `GET /api/me/viewings.ics` serves the same as an iCalendar feed calendar
+//! apps can subscribe to. Address and share link are copied from the listing
+//! when the viewing is made, so the feed still reads right after it is
+//! delisted.
+
+use axum::extract::{Path, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use crate::auth::Caller;
+use crate::state::AppState;
+use crate::StandardizedProperty;
+
+const DEFAULT_DURATION_MINS: i64 = 30;
+const MAX_DURATION_MINS: i64 = 24 * 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Viewing {
+    pub id: u64,
+    pub property_id: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub address: String,
+    pub rent: f64,
+    /// For the `/l/{short_id}` share link.
+    pub short_id: String,
+    pub note: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ViewingRequest {
+    /// RFC 3339, e.g. "2024-12-05T18:30:00+00:00".
+    pub start: DateTime<Utc>,
+    pub duration_mins: Option<i64>,
+    pub note: Option<String>,
+}
+
+pub struct Viewings {
+    path: Option<PathBuf>,
+    by_user: RwLock<BTreeMap<String, Vec<Viewing>>>,
+}
+
+impl Viewings {
+    /// Loads saved viewings from `path`. `None` keeps them in memory only.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let by_user = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Viewings { path, by_user: RwLock::new(by_user) }
+    }
+
+    /// `user`'s viewings, soonest first.
+    pub fn list(&self, user: &str) -> Vec<Viewing> {
+        let mut viewings = self.by_user.read().unwrap().get(user).cloned().unwrap_or_default();
+        viewings.sort_by_key(|viewing| (viewing.start, viewing.id));
+        viewings
+    }
+
+    pub fn add(
+        &self,
+        user: &str,
+        property: &StandardizedProperty,
+        request: ViewingRequest,
+        now: DateTime<Utc>,
+    ) -> Result<Viewing, String> {
+        let duration = request.duration_mins.unwrap_or(DEFAULT_DURATION_MINS);
+        if !(1..=MAX_DURATION_MINS).contains(&duration) {
+            return Err(format!("duration_mins must be 1 to {}", MAX_DURATION_MINS));
+        }
+        let mut by_user = self.by_user.write().unwrap();
+        let id = by_user.values().flatten().map(|v| v.id).max().unwrap_or(0) + 1;
+        let viewing = Viewing {
+            id,
+            property_id: property.property_id.clone(),
+            start: request.start,
+            end: request.start + Duration::minutes(duration),
+            address: property.address.display_address.clone(),
+            rent: property.price.amount,
+            short_id: property.short_id.clone(),
+            note: request.note.map(|note| note.trim().to_string()).filter(|note| !note.is_empty()),
+            created_at: now,
+        };
+        by_user.entry(user.to_string()).or_default().push(viewing.clone());
+        self.save(&by_user)?;
+        Ok(viewing)
+    }
+
+    /// Returns whether `user` had a viewing `id`.
+    pub fn cancel(&self, user: &str, id: u64) -> Result<bool, String> {
+        let mut by_user = self.by_user.write().unwrap();
+        let Some(viewings) = by_user.get_mut(user) else {
+            return Ok(false);
+        };
+        let before = viewings.len();
+        viewings.retain(|viewing| viewing.id != id);
+        if viewings.len() == before {
+            return Ok(false);
+        }
+        if viewings.is_empty() {
+            by_user.remove(user);
+        }
+        self.save(&by_user)?;
+        Ok(true)
+    }
+
+    fn save(&self, by_user: &BTreeMap<String, Vec<Viewing>>) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let contents = serde_json::to_string_pretty(by_user).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| e.to_string())
+    }
+}
+
+/// Escapes a TEXT value (RFC 5545 3.3.11).
+fn ical_text(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// Folds a content line at 75 octets without splitting a character.
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+fn ical_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// The viewings as an iCalendar (RFC 5545) document, linking listings under
+/// `base_url`.
+pub fn ical(viewings: &[Viewing], base_url: &str) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//Market Analysis//Viewings//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "X-WR-CALNAME:Viewings".to_string(),
+    ];
+    for viewing in viewings {
+        let mut description = format!("Rent: €{:.0}/month", viewing.rent);
+        if let Some(note) = &viewing.note {
+            description = format!("{}\n{}", note, description);
+        }
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:viewing-{}@market-analysis", viewing.id),
+            format!("DTSTAMP:{}", ical_time(viewing.created_at)),
+            format!("DTSTART:{}", ical_time(viewing.start)),
+            format!("DTEND:{}", ical_time(viewing.end)),
+            format!("SUMMARY:{}", ical_text(&format!("Viewing: {}", viewing.address))),
+            format!("LOCATION:{}", ical_text(&viewing.address)),
+            format!("DESCRIPTION:{}", ical_text(&description)),
+        ]);
+        if !viewing.short_id.is_empty() {
+            lines.push(format!("URL:{}/l/{}", base_url.trim_end_matches('/'), viewing.short_id));
+        }
+        lines.push("END:VEVENT".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+fn user(caller: &Caller) -> Result<&str, (StatusCode, String)> {
+    caller
+        .name
+        .as_deref()
+        .ok_or((StatusCode::UNAUTHORIZED, "Viewings need an API key".to_string()))
+}
+
+fn storage_error(e: String) -> (StatusCode, String) {
+    warn!("Could not save viewings: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Could not save viewings".to_string())
+}
+
+pub async fn schedule(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(property_id): Path<String>,
+    Json(request): Json<ViewingRequest>,
+) -> Result<(StatusCode, Json<Viewing>), (StatusCode, String)> {
+    let user = user(&caller)?;
+    let property = state
+        .id_index
+        .lookup(&state.config, std::slice::from_ref(&property_id))
+        .into_iter()
+        .next()
+        .ok_or((StatusCode::NOT_FOUND, format!("No listing {}", property_id)))?;
+    if request.duration_mins.is_some_and(|mins| !(1..=MAX_DURATION_MINS).contains(&mins)) {
+        return Err((StatusCode::BAD_REQUEST, format!("duration_mins must be 1 to {}", MAX_DURATION_MINS)));
+    }
+    let viewing = state.viewings.add(user, &property, request, state.clock.now()).map_err(storage_error)?;
+    Ok((StatusCode::CREATED, Json(viewing)))
+}
+
+pub async fn list(State(state): State<AppState>, caller: Caller) -> Result<Json<Vec<Viewing>>, (StatusCode, String)> {
+    Ok(Json(state.viewings.list(user(&caller)?)))
+}
+
+pub async fn cancel(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(id): Path<u64>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match state.viewings.cancel(user(&caller)?, id).map_err(storage_error)? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err((StatusCode::NOT_FOUND, format!("No viewing {}", id))),
+    }
+}
+
+pub async fn feed(State(state): State<AppState>, caller: Caller) -> Result<Response, (StatusCode, String)> {
+    let body = ical(&state.viewings.list(user(&caller)?), &state.config.links.base_url);
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/calendar; charset=utf-8"),
+            (header::CONTENT_DISPOSITION, "inline; filename=\"viewings.ics\""),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{listing, temp_dir};
+
+    #[test]
+    fn test_viewings_per_user() {
+        let path = temp_dir("viewings").join("viewings.json");
+        let viewings = Viewings::open(Some(path.clone()));
+        let mut property = listing("daft", "1");
+        property.address.display_address = "1 Main Street, Galway".to_string();
+        let at = |time: &str| ViewingRequest { start: time.parse().unwrap(), duration_mins: None, note: None };
+
+        let late = viewings.add("alice", &property, at("2024-12-06T18:00:00Z"), Utc::now()).unwrap();
+        let early = viewings.add("alice", &property, at("2024-12-05T18:00:00+01:00"), Utc::now()).unwrap();
+        viewings.add("bob", &property, at("2024-12-05T09:00:00Z"), Utc::now()).unwrap();
+        assert_eq!(early.end.to_rfc3339(), "2024-12-05T17:30:00+00:00");
+
+        let reopened = Viewings::open(Some(path));
+        assert_eq!(reopened.list("alice"), [early.clone(), late]);
+        assert!(!reopened.cancel("bob", early.id).unwrap());
+        assert!(reopened.cancel("alice", early.id).unwrap());
+        assert_eq!(reopened.list("alice").len(), 1);
+    }
+
+    #[test]
+    fn test_ical_feed() {
+        let viewing = Viewing {
+            id: 7,
+            property_id: "daft_1".to_string(),
+            start: "2024-12-05T18:30:00Z".parse().unwrap(),
+            end: "2024-12-05T19:00:00Z".parse().unwrap(),
+            address: "Apt 4, 12 Long Road; Dublin 8".to_string(),
+            rent: 2100.0,
+            short_id: "k3x9".to_string(),
+            note: Some("Ask about the damp in the bedroom, which the last tenants reported twice".to_string()),
+            created_at: "2024-12-01T09:00:00Z".parse().unwrap(),
+        };
+        let ics = ical(&[viewing], "https://rent.example.ie/");
+
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+        assert!(ics.contains("DTSTART:20241205T183000Z\r\nDTEND:20241205T190000Z\r\n"));
+        assert!(ics.contains("SUMMARY:Viewing: Apt 4\\, 12 Long Road\\; Dublin 8\r\n"));
+        assert!(ics.contains("URL:https://rent.example.ie/l/k3x9\r\n"));
+        assert!(ics.contains("\r\n "), "long lines are folded");
+        assert!(ics.lines().all(|line| line.trim_end_matches('\r').len() <= 75));
+        assert!(ics.ends_with("END:VEVENT\r\nEND:VCALENDAR\r\n"));
+    }
+}
@@ -0,0 +1,381 @@
+//! Startup warm-up.
+//!
+//! With `cache.warm_up` enabled, the latest snapshot of every configured source
+//! is parsed into the snapshot cache, the id index and the short link map (see
+//! `links`) before `/ready` reports the service ready, so the first request
+//! after a deploy doesn't pay for a cold scan. It runs as a job on the
+//! background queue.
+//!
+//! Newer snapshots are picked up the same way: every
+//! `cache.refresh_interval_secs` (which a config reload changes), a source
+//! whose cached snapshot is no longer
+//! its latest gets a `cache.refresh` job that parses the new one in the
+//! background, so no search waits for it. `POST /api/admin/refresh` reparses
+//! every source's latest snapshot right away. Either way, the snapshot it
+//! replaces keeps being served until the new one is parsed. The same job
+//! writes the id index of any source's latest snapshot that has none (see
+//! `id_index`), cached or not.
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use crate::auth::{Caller, SCOPE_ADMIN};
+use crate::find_latest_parquet;
+use crate::reload::Live;
+use crate::semantic;
+use crate::state::AppState;
+
+/// How often the refresh schedule looks for an interval while
+/// `cache.refresh_interval_secs` is zero.
+const IDLE_POLL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Warm-up is disabled; the service is ready immediately.
+    Skipped,
+    Pending,
+    Running,
+    Done,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceStatus {
+    Pending,
+    Loading,
+    Loaded,
+    /// No snapshot was found for the source.
+    Missing,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceProgress {
+    pub source: String,
+    pub status: SourceStatus,
+    pub listings: usize,
+    pub elapsed_ms: Option<u128>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WarmupReport {
+    pub phase: Phase,
+    pub sources_done: usize,
+    pub sources_total: usize,
+    pub elapsed_ms: Option<u128>,
+    pub sources: Vec<SourceProgress>,
+}
+
+#[derive(Debug)]
+pub struct WarmupProgress {
+    report: RwLock<WarmupReport>,
+}
+
+impl WarmupProgress {
+    pub fn new(enabled: bool, sources: &[String]) -> Self {
+        let phase = if enabled { Phase::Pending } else { Phase::Skipped };
+        let sources = sources
+            .iter()
+            .map(|source| SourceProgress {
+                source: source.clone(),
+                status: SourceStatus::Pending,
+                listings: 0,
+                elapsed_ms: None,
+            })
+            .collect::<Vec<_>>();
+        WarmupProgress {
+            report: RwLock::new(WarmupReport {
+                phase,
+                sources_done: 0,
+                sources_total: sources.len(),
+                elapsed_ms: None,
+                sources,
+            }),
+        }
+    }
+
+    pub fn report(&self) -> WarmupReport {
+        self.report.read().unwrap().clone()
+    }
+
+    pub fn is_ready(&self) -> bool {
+        matches!(self.report.read().unwrap().phase, Phase::Skipped | Phase::Done)
+    }
+
+    fn update(&self, change: impl FnOnce(&mut WarmupReport)) {
+        change(&mut self.report.write().unwrap());
+    }
+
+    fn update_source(&self, index: usize, change: impl FnOnce(&mut SourceProgress)) {
+        self.update(|report| change(&mut report.sources[index]));
+    }
+}
+
+/// Job kind that runs the warm-up on the background queue.
+pub const JOB: &str = "cache.warm_up";
+
+pub fn run_job(state: &AppState, _payload: &serde_json::Value) -> Result<(), String> {
+    run(state);
+    Ok(())
+}
+
+/// Parses every source's latest snapshot into the cache and the id index,
+/// recording progress as it goes. Blocking; run it off the async runtime.
+pub fn run(state: &AppState) {
+    let config = &state.config;
+    let started = Instant::now();
+    state.warmup.update(|report| report.phase = Phase::Running);
+    info!("Warming up {} sources", config.sources.len());
+
+    for (index, source) in config.sources.iter().enumerate() {
+        let Some(latest_file) = find_latest_parquet(&source.root(&config.data_path)) else {
+            warn!("Warm-up: no snapshot for {}", source.name);
+            state.warmup.update_source(index, |progress| progress.status = SourceStatus::Missing);
+            state.warmup.update(|report| report.sources_done += 1);
+            continue;
+        };
+
+        state.warmup.update_source(index, |progress| progress.status = SourceStatus::Loading);
+        let source_started = Instant::now();
+        let mut listings = 0;
+        state.cache.scan(source, &latest_file, 0, |_, _| {
+            listings += 1;
+            ControlFlow::Continue(())
+        });
+        state.id_index.prepare(source, &latest_file);
+        state.links.index(&state.cache, source, &latest_file);
+        semantic::prepare(state, source, &latest_file);
+        state.price_changes.prepare(&state.config, source, &latest_file);
+
+        let elapsed = source_started.elapsed();
+        state.warmup.update_source(index, |progress| {
+            progress.status = SourceStatus::Loaded;
+            progress.listings = listings;
+            progress.elapsed_ms = Some(elapsed.as_millis());
+        });
+        state.warmup.update(|report| report.sources_done += 1);
+        info!(
+            "Warm-up: loaded {} listings for {} in {:?} ({}/{})",
+            listings, source.name, elapsed, index + 1, config.sources.len()
+        );
+    }
+
+    let elapsed = started.elapsed();
+    state.warmup.update(|report| {
+        report.phase = Phase::Done;
+        report.elapsed_ms = Some(elapsed.as_millis());
+    });
+    info!("Warm-up finished in {:?}", elapsed);
+}
+
+/// Job kind that loads newer snapshots into the cache.
+pub const REFRESH_JOB: &str = "cache.refresh";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Refreshed {
+    pub source: String,
+    pub file: PathBuf,
+    pub listings: usize,
+    /// Rows that failed to parse; a snapshot with any isn't cached.
+    pub errors: usize,
+}
+
+/// Latest snapshots of the sources to refresh: every source's with `force`,
+/// otherwise those replacing a snapshot in the cache. Sources never loaded
+/// (or evicted) are left to be parsed on first query.
+fn stale(state: &AppState, force: bool) -> Vec<(usize, PathBuf)> {
+    let config = &state.config;
+    config
+        .sources
+        .iter()
+        .enumerate()
+        .filter_map(|(index, source)| Some((index, find_latest_parquet(&source.root(&config.data_path))?)))
+        .filter(|(index, latest)| {
+            force || state.cache.cached_file(&config.sources[*index].name).is_some_and(|cached| cached != *latest)
+        })
+        .collect()
+}
+
+/// Latest snapshots of enabled sources with no id index yet, whether cached
+/// or not.
+fn unindexed(state: &AppState) -> Vec<(usize, PathBuf)> {
+    let config = &state.config;
+    config
+        .sources
+        .iter()
+        .enumerate()
+        .filter(|(_, source)| !source.disabled)
+        .filter_map(|(index, source)| Some((index, find_latest_parquet(&source.root(&config.data_path))?)))
+        .filter(|(index, latest)| state.id_index.snapshot(&config.sources[*index], latest).is_none())
+        .collect()
+}
+
+/// Parses newer snapshots into the cache and the id index; every source's
+/// latest snapshot with `force`. Latest snapshots not in the cache are only
+/// indexed. Blocking; run it off the async runtime.
+pub fn refresh(state: &AppState, force: bool) -> Vec<Refreshed> {
+    let mut refreshed = Vec::new();
+    for (index, file) in stale(state, force) {
+        let source = &state.config.sources[index];
+        let started = Instant::now();
+        let (listings, errors) = state.cache.reload(source, &file);
+        state.id_index.prepare(source, &file);
+        state.links.index(&state.cache, source, &file);
+        semantic::prepare(state, source, &file);
+        state.price_changes.prepare(&state.config, source, &file);
+        info!("Refreshed {} from {:?}: {} listings in {:?}", source.name, file, listings, started.elapsed());
+        refreshed.push(Refreshed { source: source.name.clone(), file, listings, errors });
+    }
+    for (index, file) in unindexed(state) {
+        let source = &state.config.sources[index];
+        if state.id_index.prepare(source, &file).is_some() {
+            info!("Indexed the ids of {} in {:?}", source.name, file);
+        }
+    }
+    refreshed
+}
+
+pub fn run_refresh_job(state: &AppState, _payload: &serde_json::Value) -> Result<(), String> {
+    refresh(state, false);
+    Ok(())
+}
+
+/// Looks for newer snapshots every `cache.refresh_interval_secs` for the life
+/// of the process, queueing a refresh when there are any. The interval is read
+/// from the current config before every wait, so a reload changes it from the
+/// next check on; while it is zero the config is looked at every `IDLE_POLL`.
+pub fn schedule(live: &Live) {
+    if !live.current().cache.enabled() {
+        return;
+    }
+    let live = live.clone();
+    tokio::spawn(async move {
+        loop {
+            let interval_secs = live.current().config.cache.refresh_interval_secs;
+            if interval_secs == 0 {
+                tokio::time::sleep(IDLE_POLL).await;
+                continue;
+            }
+            tokio::time::sleep(Duration::from_secs(interval_secs)).await;
+            let state = live.current();
+            let scan = state.clone();
+            let check = move || !stale(&scan, false).is_empty() || !unindexed(&scan).is_empty();
+            let Ok(stale) = tokio::task::spawn_blocking(check).await else {
+                continue;
+            };
+            if stale {
+                if let Err(e) = state.jobs.enqueue_once(REFRESH_JOB, ()) {
+                    error!("Could not queue cache refresh: {}", e);
+                }
+            }
+        }
+    });
+}
+
+pub async fn force_refresh(
+    State(state): State<AppState>,
+    caller: Caller,
+) -> Result<Json<Vec<Refreshed>>, (StatusCode, String)> {
+    caller.require(SCOPE_ADMIN)?;
+    let worker = state.clone();
+    let refreshed = tokio::task::spawn_blocking(move || refresh(&worker, true))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+    let outcome = refreshed.as_ref().map(|_| ()).map_err(|(_, e)| e.clone());
+    state.audit.record(&caller, "cache.refresh", serde_json::json!({}), &outcome);
+    refreshed.map(Json)
+}
+
+pub async fn ready(State(state): State<AppState>) -> (StatusCode, &'static str) {
+    if state.warmup.is_ready() {
+        (StatusCode::OK, "READY")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "WARMING UP")
+    }
+}
+
+pub async fn warmup_status(State(state): State<AppState>) -> Json<WarmupReport> {
+    Json(state.warmup.report())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::test_utils::{standardized_batch, stores_in, temp_dir, write_parquet};
+
+    #[test]
+    fn test_warmup_loads_sources_and_marks_ready() {
+        let data = temp_dir("warmup");
+        write_parquet(
+            &data.join("processed/rent_ie/2024/11/05/rent_ie_120000.parquet"),
+            &standardized_batch(&["1", "2", "3"]),
+        );
+        let config = Config::from_toml(&format!(
+            "data_path = {:?}\n[cache]\nwarm_up = true\n[[sources]]\nname = \"rent_ie\"",
+            data
+        ))
+        .unwrap();
+        let state = AppState::new(stores_in(config, &data));
+        assert!(!state.warmup.is_ready());
+
+        run(&state);
+
+        let report = state.warmup.report();
+        assert!(state.warmup.is_ready());
+        assert_eq!(report.sources_done, report.sources_total);
+        let rent = report.sources.iter().find(|s| s.source == "rent_ie").unwrap();
+        assert_eq!(rent.status, SourceStatus::Loaded);
+        assert_eq!(rent.listings, 3);
+        let daft = report.sources.iter().find(|s| s.source == "daft").unwrap();
+        assert_eq!(daft.status, SourceStatus::Missing);
+        assert_eq!(state.cache.usage().sources.len(), 1);
+    }
+
+    #[test]
+    fn test_refresh_loads_newer_snapshots() {
+        let data = temp_dir("refresh");
+        let day = |d: &str| data.join(format!("processed/rent_ie/2024/11/{}/rent_ie_120000.parquet", d));
+        write_parquet(&day("05"), &standardized_batch(&["1", "2"]));
+        let config = Config::from_toml(&format!("data_path = {:?}\n[[sources]]\nname = \"rent_ie\"", data)).unwrap();
+        let state = AppState::new(stores_in(config, &data));
+        run(&state);
+        assert!(refresh(&state, false).is_empty());
+
+        write_parquet(&day("06"), &standardized_batch(&["1", "2", "3"]));
+        let refreshed = refresh(&state, false);
+        assert_eq!(refreshed.len(), 1);
+        assert_eq!((refreshed[0].source.as_str(), refreshed[0].listings), ("rent_ie", 3));
+        assert_eq!(state.cache.cached_file("rent_ie"), Some(day("06")));
+        assert!(refresh(&state, false).is_empty());
+        assert_eq!(refresh(&state, true).len(), 1);
+    }
+
+    #[test]
+    fn test_refresh_indexes_uncached_snapshots() {
+        let data = temp_dir("refresh_index");
+        write_parquet(
+            &data.join("processed/rent_ie/2024/11/05/rent_ie_120000.parquet"),
+            &standardized_batch(&["1", "2"]),
+        );
+        let config = Config::from_toml(&format!("data_path = {:?}
+[[sources]]
+name = \"rent_ie\"", data)).unwrap();
+        let state = AppState::new(stores_in(config, &data));
+        let ids = ["rent_ie_2".to_string()];
+        assert!(state.store.get(&ids).is_empty());
+        assert_eq!(unindexed(&state).len(), 1);
+
+        assert!(refresh(&state, false).is_empty());
+        assert!(unindexed(&state).is_empty());
+        assert_eq!(state.store.get(&ids).len(), 1);
+        assert!(state.cache.cached_file("rent_ie").is_none());
+    }
+}
@@ -0,0 +1,146 @@
+//! Sources a search could not fully serve.
+//!
+//! A corrupt snapshot or an empty source directory used to leave a search
+//! returning only the other sources' listings with nothing to say so. Search
+//! now lists each source it had trouble with in an `x-search-warnings` header
+//! (a JSON array): `failed` when its snapshot could not be read, `missing`
+//! when it has none, `stale` when the newest one is older than
+//! `search.stale_after_days`, and `partial` when a newer snapshot is still
+//! being written (see `partial`) and an older one was served instead, or
+//! `fallback` when the latest could not be read and an older one was served
+//! (see `fallback`), and `timeout` when its scan ran past
+//! `search.source_timeout_ms` and the search went on without it, and
+//! `disabled` when an admin has taken it out of combined searches (see
+//! `toggles`). With
+//! `strict=true` a failed, missing or timed-out source makes the search fail
+//! with 502 and the warnings as its body; the others are still served.
+
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::snapshot_date;
+
+pub const HEADER: &str = "x-search-warnings";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Problem {
+    Failed,
+    Missing,
+    Stale,
+    Partial,
+    Fallback,
+    Timeout,
+    Disabled,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SourceWarning {
+    pub source: String,
+    pub problem: Problem,
+    pub detail: String,
+}
+
+impl SourceWarning {
+    pub fn failed(source: &str, file: &Path, errors: usize) -> Self {
+        SourceWarning {
+            source: source.to_string(),
+            problem: Problem::Failed,
+            detail: format!("{} read error(s) in {}", errors, file.display()),
+        }
+    }
+
+    pub fn missing(source: &str) -> Self {
+        SourceWarning { source: source.to_string(), problem: Problem::Missing, detail: "No snapshot found".to_string() }
+    }
+
+    /// `pending` is being written; `served` is the snapshot read instead.
+    pub fn partial(source: &str, pending: &Path, served: Option<&Path>) -> Self {
+        let instead = match served.and_then(snapshot_date) {
+            Some(date) => format!("serving the snapshot from {}", date),
+            None => "no earlier snapshot to serve".to_string(),
+        };
+        SourceWarning {
+            source: source.to_string(),
+            problem: Problem::Partial,
+            detail: format!("{} is still being written; {}", pending.display(), instead),
+        }
+    }
+
+    /// `unreadable` is the latest snapshot; `served` the one read instead.
+    pub fn fallback(source: &str, unreadable: &Path, served: &Path) -> Self {
+        let date = snapshot_date(served).map_or_else(|| served.display().to_string(), |date| date.to_string());
+        SourceWarning {
+            source: source.to_string(),
+            problem: Problem::Fallback,
+            detail: format!("{} could not be read; serving the snapshot from {}", unreadable.display(), date),
+        }
+    }
+
+    pub fn timed_out(source: &str, timeout_ms: u64) -> Self {
+        SourceWarning {
+            source: source.to_string(),
+            problem: Problem::Timeout,
+            detail: format!("Scan took longer than {} ms", timeout_ms),
+        }
+    }
+
+    pub fn disabled(source: &str) -> Self {
+        SourceWarning {
+            source: source.to_string(),
+            problem: Problem::Disabled,
+            detail: "Disabled by an admin; ask for it with source= to search it alone".to_string(),
+        }
+    }
+
+    /// Whether the source can't be served at all, as opposed to served late.
+    pub fn unserved(&self) -> bool {
+        matches!(self.problem, Problem::Failed | Problem::Missing | Problem::Timeout)
+    }
+}
+
+/// A warning when `file`, the newest snapshot, is dated more than
+/// `stale_after_days` before `today`. Zero days turns the check off.
+pub fn stale(source: &str, file: &Path, stale_after_days: u32, today: NaiveDate) -> Option<SourceWarning> {
+    let date = snapshot_date(file)?;
+    let age = (today - date).num_days();
+    (stale_after_days > 0 && age > i64::from(stale_after_days)).then(|| SourceWarning {
+        source: source.to_string(),
+        problem: Problem::Stale,
+        detail: format!("Newest snapshot is from {} ({} days old)", date, age),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stale_snapshots() {
+        let file = Path::new("/data/processed/daft/2024/11/05/daft_120000.parquet");
+        let day = |d| NaiveDate::from_ymd_opt(2024, 11, d).unwrap();
+        assert_eq!(stale("daft", file, 3, day(8)), None);
+        let warning = stale("daft", file, 3, day(9)).unwrap();
+        assert_eq!((warning.problem, warning.detail.as_str()), (Problem::Stale, "Newest snapshot is from 2024-11-05 (4 days old)"));
+        assert!(!warning.unserved());
+        assert_eq!(stale("daft", file, 0, day(30)), None);
+        assert_eq!(stale("daft", Path::new("daft.parquet"), 3, day(30)), None);
+        assert!(SourceWarning::missing("daft").unserved());
+        assert!(SourceWarning::timed_out("daft", 10_000).unserved());
+    }
+
+    #[test]
+    fn test_partial_snapshots() {
+        let pending = Path::new("/data/processed/daft/2024/11/06/daft_120000.parquet");
+        let served = Path::new("/data/processed/daft/2024/11/05/daft_120000.parquet");
+        let warning = SourceWarning::partial("daft", pending, Some(served));
+        assert_eq!(warning.problem, Problem::Partial);
+        assert!(warning.detail.ends_with("is still being written; serving the snapshot from 2024-11-05"));
+        assert!(!warning.unserved());
+
+        let warning = SourceWarning::fallback("daft", pending, served);
+        assert!(warning.detail.ends_with("could not be read; serving the snapshot from 2024-11-05"));
+        assert!(!warning.unserved());
+    }
+}